@@ -1,3 +1,30 @@
+use std::path::Path;
+use std::process::Command;
+
 fn main() {
     embuild::espidf::sysenv::output();
+
+    // 固件版本中带上git提交，设备信息服务中可以看到
+    let hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=GIT_HASH={}", hash);
+
+    // 提交或切换分支后重新生成
+    let head = Path::new(".git/HEAD");
+    if head.exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        if let Some(reference) = std::fs::read_to_string(head)
+            .ok()
+            .and_then(|head| head.strip_prefix("ref: ").map(|reference| reference.trim().to_string()))
+        {
+            println!("cargo:rerun-if-changed=.git/{}", reference);
+        }
+    }
 }
@@ -0,0 +1,185 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::bluetooth::Message;
+use crate::button::{Button, ButtonEvent, POLL_INTERVAL};
+use crate::console::ConsoleEvent;
+use crate::ir::queue::TransmitEvent;
+use crate::ir::receiver::IrEvent;
+use crate::http::HttpEvent;
+use crate::macros::MacroEvent;
+use crate::mqtt::MqttEvent;
+
+/// 没有LED效果运行时的Tick间隔（毫秒）
+pub const IDLE_TICK_MS: u32 = 100;
+
+/// 省电模式中的Tick间隔（毫秒），两次Tick之间可以进入light sleep
+pub const SLEEP_TICK_MS: u32 = 1000;
+
+/// 省电模式中轮询按键的间隔，仍然比去抖时间长不了多少
+const LOW_POWER_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// 定时器线程的栈大小
+const TICKER_STACK_SIZE: usize = 4096;
+
+/// 主循环处理的事件，各个线程都发到同一个通道
+#[derive(Debug)]
+pub enum AppEvent {
+    /// 客户端写入recv特征的一条消息
+    BleMessage(Message),
+    /// 串口控制台输入的一行命令
+    Console(ConsoleEvent),
+    /// 接收线程上报的红外捕获
+    Ir(IrEvent),
+    /// 发射线程上报的进度
+    Transmit(TransmitEvent),
+    /// 宏执行线程上报的进度
+    Macro(MacroEvent),
+    /// 去抖后的BOOT按键手势
+    Button(ButtonEvent),
+    /// MQTT线程转来的发射请求
+    Mqtt(MqttEvent),
+    /// HTTP任务转来的请求，处理结果交回HTTP任务
+    Http(HttpEvent),
+    /// 定时推进超时判定和LED效果
+    Tick,
+}
+
+impl From<Message> for AppEvent {
+    fn from(message: Message) -> Self {
+        Self::BleMessage(message)
+    }
+}
+
+impl From<ConsoleEvent> for AppEvent {
+    fn from(event: ConsoleEvent) -> Self {
+        Self::Console(event)
+    }
+}
+
+impl From<IrEvent> for AppEvent {
+    fn from(event: IrEvent) -> Self {
+        Self::Ir(event)
+    }
+}
+
+impl From<TransmitEvent> for AppEvent {
+    fn from(event: TransmitEvent) -> Self {
+        Self::Transmit(event)
+    }
+}
+
+impl From<MacroEvent> for AppEvent {
+    fn from(event: MacroEvent) -> Self {
+        Self::Macro(event)
+    }
+}
+
+impl From<MqttEvent> for AppEvent {
+    fn from(event: MqttEvent) -> Self {
+        Self::Mqtt(event)
+    }
+}
+
+impl From<HttpEvent> for AppEvent {
+    fn from(event: HttpEvent) -> Self {
+        Self::Http(event)
+    }
+}
+
+/// 定时器线程的句柄，按间隔发送Tick，并更频繁地轮询按键
+///
+/// 上一个Tick还没有处理完时不再发送，主循环忙时Tick不会在通道中堆积。
+pub struct Ticker {
+    period_ms: Arc<AtomicU32>,
+    pending: Arc<AtomicBool>,
+    low_power: Arc<AtomicBool>,
+    wake: SyncSender<()>,
+}
+
+impl Ticker {
+    /// 按键初始化失败时`button`为None，只发送Tick
+    pub fn spawn(mut button: Option<Button>, events: Sender<AppEvent>) -> std::io::Result<Self> {
+        let period_ms = Arc::new(AtomicU32::new(IDLE_TICK_MS));
+        let pending = Arc::new(AtomicBool::new(false));
+        let low_power = Arc::new(AtomicBool::new(false));
+        let (wake, woken) = mpsc::sync_channel(1);
+
+        let period = period_ms.clone();
+        let tick_pending = pending.clone();
+        let slow_poll = low_power.clone();
+        thread::Builder::new()
+            .name("ticker".into())
+            .stack_size(TICKER_STACK_SIZE)
+            .spawn(move || {
+                let mut next_tick = Instant::now();
+                loop {
+                    // 按键按固定间隔轮询去抖，Tick按当前间隔发送
+                    let poll_interval = if slow_poll.load(Ordering::Relaxed) {
+                        LOW_POWER_POLL_INTERVAL
+                    } else {
+                        POLL_INTERVAL
+                    };
+                    let timeout = next_tick.saturating_duration_since(Instant::now()).min(poll_interval);
+                    match woken.recv_timeout(timeout) {
+                        Ok(()) => next_tick = Instant::now(),
+                        Err(RecvTimeoutError::Timeout) => (),
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+
+                    let now = Instant::now();
+                    if let Some(event) = button.as_mut().and_then(|button| button.poll(now)) {
+                        if events.send(AppEvent::Button(event)).is_err() {
+                            break;
+                        }
+                    }
+                    if now < next_tick {
+                        continue;
+                    }
+                    next_tick = now + Duration::from_millis(period.load(Ordering::Relaxed) as u64);
+                    if !tick_pending.swap(true, Ordering::AcqRel) && events.send(AppEvent::Tick).is_err() {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            period_ms,
+            pending,
+            low_power,
+            wake,
+        })
+    }
+
+    /// 定时器线程启动失败时使用，没有Tick和按键事件
+    pub fn unavailable() -> Self {
+        let (wake, _) = mpsc::sync_channel(1);
+        Self {
+            period_ms: Arc::new(AtomicU32::new(IDLE_TICK_MS)),
+            pending: Arc::new(AtomicBool::new(false)),
+            low_power: Arc::new(AtomicBool::new(false)),
+            wake,
+        }
+    }
+
+    /// 主循环开始处理Tick时调用，之后才会发送下一个
+    pub fn ticked(&self) {
+        self.pending.store(false, Ordering::Release);
+    }
+
+    /// 省电模式中降低按键轮询频率
+    pub fn set_low_power(&self, low_power: bool) {
+        self.low_power.store(low_power, Ordering::Relaxed);
+    }
+
+    /// 修改Tick间隔，LED效果运行时按帧间隔推进
+    pub fn set_period(&self, period_ms: u32) {
+        if self.period_ms.swap(period_ms, Ordering::Relaxed) != period_ms {
+            // 缩短间隔时立即生效，不必等完上一个较长的间隔
+            let _ = self.wake.try_send(());
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use esp_idf_svc::sys::{self, EspError};
+
+use crate::bluetooth::BluetoothManager;
+
+/// 默认的电池分压输入引脚
+pub const DEFAULT_ADC_PIN: u8 = 1;
+
+/// ESP32-S3上ADC1的引脚，ADC2与蓝牙共用时不可靠，不使用
+pub const ADC_PINS: RangeInclusive<u8> = 1..=10;
+
+/// 默认分压比（千分比），两个相同电阻分压时为2
+pub const DEFAULT_DIVIDER_PERMILLE: u32 = 2000;
+
+/// 允许设置的分压比范围（千分比）
+pub const DIVIDER_RANGE_PERMILLE: RangeInclusive<u32> = 1000..=10_000;
+
+/// 两次采样的间隔
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 每次采样取平均的读数数量
+const READS_PER_SAMPLE: u32 = 8;
+
+/// 没有校准数据时12dB衰减的满量程（毫伏）
+const UNCALIBRATED_FULL_SCALE_MV: u32 = 3100;
+
+/// 12位ADC的最大读数
+const ADC_MAX_RAW: u32 = 4095;
+
+const MONITOR_STACK_SIZE: usize = 4096;
+
+/// 单节锂聚合物电池的放电曲线：电压（毫伏）和剩余电量（百分比），按电压从高到低排列
+const LIPO_CURVE: [(u32, u8); 21] = [
+    (4200, 100),
+    (4150, 95),
+    (4110, 90),
+    (4080, 85),
+    (4020, 80),
+    (3980, 75),
+    (3950, 70),
+    (3910, 65),
+    (3870, 60),
+    (3850, 55),
+    (3840, 50),
+    (3820, 45),
+    (3800, 40),
+    (3790, 35),
+    (3770, 30),
+    (3750, 25),
+    (3730, 20),
+    (3710, 15),
+    (3690, 10),
+    (3610, 5),
+    (3270, 0),
+];
+
+/// 一次电池电压采样
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reading {
+    pub millivolts: u32,
+    pub percent: u8,
+}
+
+/// 按放电曲线线性插值换算剩余电量
+pub fn lipo_percent(millivolts: u32) -> u8 {
+    let (full_mv, _) = LIPO_CURVE[0];
+    if millivolts >= full_mv {
+        return 100;
+    }
+    for pair in LIPO_CURVE.windows(2) {
+        let ((high_mv, high), (low_mv, low)) = (pair[0], pair[1]);
+        if millivolts >= low_mv {
+            let span = (high - low) as u32 * (millivolts - low_mv) / (high_mv - low_mv);
+            return low + span as u8;
+        }
+    }
+    0
+}
+
+/// 电池监测的设置和最近一次采样，可以在运行时修改
+struct Shared {
+    enabled: AtomicBool,
+    pin: AtomicU8,
+    divider_permille: AtomicU32,
+    reading: Mutex<Option<Reading>>,
+}
+
+/// 电池监测线程的句柄，修改设置后立即重新采样
+pub struct BatteryMonitor {
+    shared: Arc<Shared>,
+    wake: SyncSender<()>,
+}
+
+impl BatteryMonitor {
+    /// 在独立线程中定期采样电池电压，并更新蓝牙电池服务
+    pub fn spawn(
+        enabled: bool,
+        pin: u8,
+        divider_permille: u32,
+        bluetooth_manager: BluetoothManager,
+    ) -> std::io::Result<Self> {
+        let shared = Arc::new(Shared {
+            enabled: AtomicBool::new(enabled),
+            pin: AtomicU8::new(pin),
+            divider_permille: AtomicU32::new(divider_permille),
+            reading: Mutex::new(None),
+        });
+        let (wake, woken) = mpsc::sync_channel(1);
+
+        let monitor = shared.clone();
+        thread::Builder::new()
+            .name("battery".into())
+            .stack_size(MONITOR_STACK_SIZE)
+            .spawn(move || {
+                let mut adc: Option<Adc> = None;
+                loop {
+                    let reading = if monitor.enabled.load(Ordering::Relaxed) {
+                        monitor.sample(&mut adc)
+                    } else {
+                        // 关闭时释放ADC，引脚可以另作他用
+                        adc = None;
+                        None
+                    };
+                    *monitor.reading.lock().unwrap() = reading;
+                    bluetooth_manager.set_battery_level(reading.map(|reading| reading.percent));
+
+                    match woken.recv_timeout(SAMPLE_INTERVAL) {
+                        Ok(()) | Err(RecvTimeoutError::Timeout) => (),
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+            })?;
+
+        Ok(Self { shared, wake })
+    }
+
+    /// 采样线程启动失败时使用，设置照常修改但没有采样
+    pub fn unavailable(enabled: bool, pin: u8, divider_permille: u32) -> Self {
+        let shared = Arc::new(Shared {
+            enabled: AtomicBool::new(enabled),
+            pin: AtomicU8::new(pin),
+            divider_permille: AtomicU32::new(divider_permille),
+            reading: Mutex::new(None),
+        });
+        // 没有线程接收唤醒，wake中的发送失败被忽略
+        let (wake, _) = mpsc::sync_channel(1);
+        Self { shared, wake }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.shared.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.shared.enabled.store(enabled, Ordering::Relaxed);
+        self.wake();
+    }
+
+    pub fn pin(&self) -> u8 {
+        self.shared.pin.load(Ordering::Relaxed)
+    }
+
+    pub fn set_pin(&self, pin: u8) {
+        self.shared.pin.store(pin, Ordering::Relaxed);
+        self.wake();
+    }
+
+    pub fn divider_permille(&self) -> u32 {
+        self.shared.divider_permille.load(Ordering::Relaxed)
+    }
+
+    pub fn set_divider_permille(&self, divider_permille: u32) {
+        self.shared.divider_permille.store(divider_permille, Ordering::Relaxed);
+        self.wake();
+    }
+
+    /// 最近一次采样，关闭或采样失败时为None
+    pub fn reading(&self) -> Option<Reading> {
+        *self.shared.reading.lock().unwrap()
+    }
+
+    fn wake(&self) {
+        // 已经有一个唤醒在等待时不需要再发
+        let _ = self.wake.try_send(());
+    }
+}
+
+impl Shared {
+    /// 读取一次电池电压，引脚改变时重新创建ADC
+    fn sample(&self, adc: &mut Option<Adc>) -> Option<Reading> {
+        let pin = self.pin.load(Ordering::Relaxed);
+        if adc.as_ref().map_or(true, |adc| adc.pin != pin) {
+            *adc = None;
+            match Adc::new(pin) {
+                Ok(new_adc) => *adc = Some(new_adc),
+                Err(e) => {
+                    log::error!("初始化电池ADC失败: GPIO{}, {:?}", pin, e);
+                    return None;
+                }
+            }
+        }
+
+        match adc.as_ref()?.read_millivolts() {
+            Ok(pin_mv) => {
+                let millivolts = pin_mv * self.divider_permille.load(Ordering::Relaxed) / 1000;
+                let reading = Reading {
+                    millivolts,
+                    percent: lipo_percent(millivolts),
+                };
+                log::debug!("电池电压: {}mV, {}%", reading.millivolts, reading.percent);
+                Some(reading)
+            }
+            Err(e) => {
+                log::error!("读取电池电压失败: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+/// ADC单次转换驱动，引脚在运行时指定
+struct Adc {
+    pin: u8,
+    unit: sys::adc_oneshot_unit_handle_t,
+    channel: sys::adc_channel_t,
+    /// 芯片没有校准数据时为None，按满量程线性换算
+    cali: Option<sys::adc_cali_handle_t>,
+}
+
+impl Adc {
+    fn new(pin: u8) -> Result<Self, EspError> {
+        let mut unit_id: sys::adc_unit_t = 0;
+        let mut channel: sys::adc_channel_t = 0;
+        EspError::convert(unsafe { sys::adc_oneshot_io_to_channel(pin as _, &mut unit_id, &mut channel) })?;
+        if unit_id != sys::adc_unit_t_ADC_UNIT_1 {
+            return Err(EspError::from_infallible::<{ sys::ESP_ERR_NOT_SUPPORTED }>());
+        }
+
+        let mut unit = std::ptr::null_mut();
+        let unit_config = sys::adc_oneshot_unit_init_cfg_t {
+            unit_id,
+            ..Default::default()
+        };
+        EspError::convert(unsafe { sys::adc_oneshot_new_unit(&unit_config, &mut unit) })?;
+
+        // 先放进结构体，之后出错时由Drop释放
+        let mut adc = Self {
+            pin,
+            unit,
+            channel,
+            cali: None,
+        };
+        let channel_config = sys::adc_oneshot_chan_cfg_t {
+            atten: sys::adc_atten_t_ADC_ATTEN_DB_12,
+            bitwidth: sys::adc_bitwidth_t_ADC_BITWIDTH_DEFAULT,
+        };
+        EspError::convert(unsafe { sys::adc_oneshot_config_channel(unit, channel, &channel_config) })?;
+
+        let cali_config = sys::adc_cali_curve_fitting_config_t {
+            unit_id,
+            chan: channel,
+            atten: channel_config.atten,
+            bitwidth: channel_config.bitwidth,
+        };
+        let mut cali = std::ptr::null_mut();
+        match EspError::convert(unsafe { sys::adc_cali_create_scheme_curve_fitting(&cali_config, &mut cali) }) {
+            Ok(()) => adc.cali = Some(cali),
+            Err(e) => log::warn!("电池ADC没有校准数据，按满量程换算: {:?}", e),
+        }
+
+        log::info!("电池ADC初始化完成: GPIO{}", pin);
+        Ok(adc)
+    }
+
+    /// 多次读取取平均，返回引脚上的电压（毫伏）
+    fn read_millivolts(&self) -> Result<u32, EspError> {
+        let mut total = 0;
+        for _ in 0..READS_PER_SAMPLE {
+            let mut raw = 0;
+            EspError::convert(unsafe { sys::adc_oneshot_read(self.unit, self.channel, &mut raw) })?;
+            total += raw.max(0) as u32;
+        }
+        let raw = total / READS_PER_SAMPLE;
+
+        match self.cali {
+            Some(cali) => {
+                let mut millivolts = 0;
+                EspError::convert(unsafe { sys::adc_cali_raw_to_voltage(cali, raw as _, &mut millivolts) })?;
+                Ok(millivolts.max(0) as u32)
+            }
+            None => Ok(raw * UNCALIBRATED_FULL_SCALE_MV / ADC_MAX_RAW),
+        }
+    }
+}
+
+impl Drop for Adc {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(cali) = self.cali {
+                sys::adc_cali_delete_scheme_curve_fitting(cali);
+            }
+            sys::adc_oneshot_del_unit(self.unit);
+        }
+    }
+}
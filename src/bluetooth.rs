@@ -1,17 +1,26 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
 use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 use enumset::enum_set;
 
-use esp_idf_svc::bt::ble::gap::{AdvConfiguration, BleGapEvent, EspBleGap};
+use esp_idf_svc::bt::ble::gap::{BleGapEvent, EspBleGap};
 use esp_idf_svc::bt::ble::gatt::server::{ConnectionId, EspGatts, GattsEvent, TransferId};
 use esp_idf_svc::bt::ble::gatt::{
     AutoResponse, GattCharacteristic, GattDescriptor, GattId, GattInterface, GattResponse,
     GattServiceId, GattStatus, Handle, Permission, Property,
 };
 use esp_idf_svc::bt::{BdAddr, Ble, BtDriver, BtStatus, BtUuid};
-use esp_idf_svc::sys::{EspError, ESP_FAIL};
+use esp_idf_svc::sys::{self, EspError, ESP_ERR_INVALID_STATE, ESP_FAIL};
 
 use log::{info, warn};
 
+use crate::chunk::{Chunker, CHUNK_HEADER_LEN};
+use crate::console;
+use crate::error::Error;
+use crate::events::{EventLog, SequenceGap};
+use crate::write_policy::{AllowAll, WritePolicy, WriteRequest};
+
 // 我们的服务UUID
 pub const SERVICE_UUID: u128 = 0xad91b201734740479e173bed82d75f9d;
 
@@ -19,86 +28,709 @@ pub const SERVICE_UUID: u128 = 0xad91b201734740479e173bed82d75f9d;
 pub const RECV_CHARACTERISTIC_UUID: u128 = 0xb6fccb5087be44f3ae22f85485ea42c4;
 /// 我们的"indicate"特征 - 客户端可以接收数据的地方
 pub const IND_CHARACTERISTIC_UUID: u128 = 0x503de214868246c4828fd59144da41be;
+/// 我们的"telemetry"特征 - 客户端订阅后接收设备日志的地方
+pub const TELEMETRY_CHARACTERISTIC_UUID: u128 = 0x7c2e4f1a93d84b6e8a05c1d2e3f40516;
+/// 标准电池服务
+const BATTERY_SERVICE_UUID: u16 = 0x180F;
+/// 电池服务中的电量特征，一个字节的百分比
+const BATTERY_LEVEL_UUID: u16 = 0x2A19;
+/// 标准设备信息服务
+const DEVICE_INFO_SERVICE_UUID: u16 = 0x180A;
+const MODEL_NUMBER_UUID: u16 = 0x2A24;
+const FIRMWARE_REVISION_UUID: u16 = 0x2A26;
+const HARDWARE_REVISION_UUID: u16 = 0x2A27;
+const MANUFACTURER_NAME_UUID: u16 = 0x2A29;
+const MANUFACTURER_NAME: &str = "Chronostasys";
+const MODEL_NUMBER: &str = "ESP32-S3 IR Recorder";
+/// 固件版本和构建时的git提交，客户端据此判断支持的功能
+pub const FIRMWARE_REVISION: &str = concat!(env!("CARGO_PKG_VERSION"), "+", env!("GIT_HASH"));
+/// 没有设置过时的硬件版本
+pub const DEFAULT_HARDWARE_REVISION: &str = "unknown";
+/// 硬件版本的最大字节数
+pub const MAX_HARDWARE_REVISION_LEN: usize = 16;
 
 const APP_ID: u16 = 0;
-const MAX_CONNECTIONS: usize = 2;
+/// 重启时关闭和重新初始化之间的等待时间
+const RESTART_DELAY: Duration = Duration::from_millis(500);
+pub const MAX_CONNECTIONS: usize = 2;
+/// 连接白名单最多保存的地址数量
+pub const MAX_WHITELIST: usize = 8;
+/// 广播间隔范围（0.625ms为单位），与协议栈默认的广播参数相同
+const ADV_INTERVAL_MIN: u16 = 0x20;
+const ADV_INTERVAL_MAX: u16 = 0x40;
+/// 省电模式中的广播间隔范围，约1秒
+const ADV_INTERVAL_SLOW_MIN: u16 = 0x640;
+const ADV_INTERVAL_SLOW_MAX: u16 = 0x800;
+/// 没有协商MTU时的默认值
+const DEFAULT_MTU: u16 = 23;
+/// ATT指示的头部长度，MTU减去它才是有效载荷
+const ATT_HEADER_LEN: usize = 3;
+/// 一次长写入拼接后的最大长度
+const MAX_PREPARED_LEN: usize = 2048;
+/// 主循环还没有处理完的消息最多保留的数量
+const RECEIVE_QUEUE_LEN: usize = 32;
+/// 配对码为6位数字
+pub const MAX_PASSKEY: u32 = 999_999;
+/// 配对密钥的最大长度（字节）
+const MAX_KEY_SIZE: u8 = 16;
+/// 读取状态时最多附带的最近一次捕获的时长数量，整个状态不超过ATT属性的512字节上限
+const STATUS_CAPTURE_DURATIONS: usize = 64;
+/// 状态中时长之前的固定部分
+const STATUS_HEADER_LEN: usize = 9;
+/// 状态末尾表示没有RSSI读数的值，与HCI的约定相同
+const RSSI_UNKNOWN: i8 = 127;
+/// 等待协议栈返回RSSI读数的时间
+const RSSI_TIMEOUT: Duration = Duration::from_secs(1);
+/// 连接空闲这么久之后切换为低功耗的连接参数
+const LINK_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// 放宽后的连接参数使用的监督超时（毫秒）
+const RELAXED_TIMEOUT_MS: u32 = 6000;
+/// 没有设置过时的设备名称
+pub const DEFAULT_DEVICE_NAME: &str = "ESP32-IR-Recorder";
+/// 设备名称的最大字节数，扫描响应中还要放下名称和厂商数据的头部
+pub const MAX_DEVICE_NAME_LEN: usize = ADV_MAX_LEN - AD_HEADER_LEN * 2 - MANUFACTURER_DATA_LEN;
+/// 广播包和扫描响应的最大长度
+const ADV_MAX_LEN: usize = 31;
+/// 每个AD结构的长度和类型字节
+const AD_HEADER_LEN: usize = 2;
+const AD_TYPE_FLAGS: u8 = 0x01;
+const AD_TYPE_UUID128_COMPLETE: u8 = 0x07;
+const AD_TYPE_NAME_SHORT: u8 = 0x08;
+const AD_TYPE_NAME_COMPLETE: u8 = 0x09;
+const AD_TYPE_MANUFACTURER: u8 = 0xFF;
+/// LE通用可发现、不支持BR/EDR
+const ADV_FLAGS: u8 = 0x06;
+/// 厂商数据中的公司编号，0xFFFF保留给测试使用
+const MANUFACTURER_ID: u16 = 0xFFFF;
+/// 厂商数据：公司编号和用户编号（各2字节，小端序）
+const MANUFACTURER_DATA_LEN: usize = 4;
+/// CCCD中表示订阅通知的位
+const CCCD_NOTIFY: u16 = 0x0001;
+/// CCCD中表示订阅指示的位
+const CCCD_INDICATE: u16 = 0x0002;
+/// 电量变化达到这个百分比时才通知订阅的客户端
+const BATTERY_NOTIFY_STEP: u8 = 2;
+/// 默认等待客户端确认指示的时间（毫秒）
+pub const DEFAULT_INDICATION_TIMEOUT_MS: u32 = 5000;
+/// 允许设置的确认超时范围（毫秒）
+pub const INDICATION_TIMEOUT_RANGE_MS: std::ops::RangeInclusive<u32> = 100..=30_000;
 
 #[derive(Debug, Clone)]
 struct Connection {
     peer: BdAddr,
     conn_id: Handle,
-    subscribed: bool,
+    /// 客户端写入CCCD的值
+    cccd: u16,
+    /// 客户端写入日志特征CCCD的值，只支持通知
+    telemetry_cccd: u16,
+    /// 客户端写入电量特征CCCD的值，只支持通知
+    battery_cccd: u16,
+    /// 协议栈报告发送缓冲区拥塞，暂停通知
+    congested: bool,
     mtu: Option<u16>,
+    /// 等待执行写入的长写入，每个特征一个
+    prepared: Vec<PreparedWrite>,
+    /// 偏移为0的读取生成的状态快照，之后的长读取使用同一份
+    read_snapshot: Option<Vec<u8>>,
+    /// 最近一次读取的信号强度（dBm）
+    rssi: Option<i8>,
+    /// 最近一次请求的连接参数预设
+    link_mode: LinkMode,
+    /// 客户端拒绝后已经用放宽的参数重试过
+    link_retried: bool,
+    /// 最近一次收发数据的时间，用于判断空闲
+    last_activity: Instant,
+}
+
+impl Connection {
+    fn new(conn_id: ConnectionId, peer: BdAddr, now: Instant) -> Self {
+        Self {
+            peer,
+            conn_id,
+            cccd: 0,
+            telemetry_cccd: 0,
+            battery_cccd: 0,
+            congested: false,
+            mtu: None,
+            prepared: Vec::new(),
+            read_snapshot: None,
+            rssi: None,
+            link_mode: LinkMode::Balanced,
+            link_retried: false,
+            last_activity: now,
+        }
+    }
+}
+
+/// 当前连接的客户端，最多`MAX_CONNECTIONS`个
+///
+/// 连接状态只由列表是否为空决定，一个客户端断开不影响其他客户端。
+#[derive(Debug, Default)]
+struct Connections {
+    list: heapless::Vec<Connection, MAX_CONNECTIONS>,
+}
+
+impl Connections {
+    /// 是否还有客户端连接
+    fn is_connected(&self) -> bool {
+        !self.list.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    /// 还能接受新的连接
+    fn has_room(&self) -> bool {
+        self.list.len() < MAX_CONNECTIONS
+    }
+
+    /// 添加连接，已满时返回false
+    fn add(&mut self, conn_id: ConnectionId, peer: BdAddr, now: Instant) -> bool {
+        self.list.push(Connection::new(conn_id, peer, now)).is_ok()
+    }
+
+    /// 删除该客户端的连接，不存在时返回None
+    fn remove(&mut self, peer: BdAddr) -> Option<Connection> {
+        let index = self.list.iter().position(|conn| conn.peer == peer)?;
+        Some(self.list.swap_remove(index))
+    }
+
+    fn get(&self, index: usize) -> Option<&Connection> {
+        self.list.get(index)
+    }
+
+    fn iter(&self) -> std::slice::Iter<'_, Connection> {
+        self.list.iter()
+    }
+
+    fn iter_mut(&mut self) -> std::slice::IterMut<'_, Connection> {
+        self.list.iter_mut()
+    }
+
+    fn position(&self, conn_id: ConnectionId) -> Option<usize> {
+        self.list.iter().position(|conn| conn.conn_id == conn_id)
+    }
+
+    fn find(&self, conn_id: ConnectionId) -> Option<&Connection> {
+        self.list.iter().find(|conn| conn.conn_id == conn_id)
+    }
+
+    fn find_mut(&mut self, conn_id: ConnectionId) -> Option<&mut Connection> {
+        self.list.iter_mut().find(|conn| conn.conn_id == conn_id)
+    }
+
+    fn find_peer_mut(&mut self, peer: BdAddr) -> Option<&mut Connection> {
+        self.list.iter_mut().find(|conn| conn.peer == peer)
+    }
+}
+
+impl std::ops::Index<usize> for Connections {
+    type Output = Connection;
+
+    fn index(&self, index: usize) -> &Connection {
+        &self.list[index]
+    }
+}
+
+impl std::ops::IndexMut<usize> for Connections {
+    fn index_mut(&mut self, index: usize) -> &mut Connection {
+        &mut self.list[index]
+    }
+}
+
+/// 连接参数预设
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkMode {
+    /// 短连接间隔，用于导出、导入等大量传输
+    LowLatency,
+    /// 连接后的默认参数
+    Balanced,
+    /// 长连接间隔加从机延迟，空闲时省电
+    LowPower,
+}
+
+impl LinkMode {
+    fn params(self) -> ConnParams {
+        match self {
+            Self::LowLatency => ConnParams {
+                min_int_ms: 8,
+                max_int_ms: 15,
+                latency_ms: 0,
+                timeout_ms: 2000,
+            },
+            Self::Balanced => ConnParams {
+                min_int_ms: 30,
+                max_int_ms: 50,
+                latency_ms: 0,
+                timeout_ms: 4000,
+            },
+            Self::LowPower => ConnParams {
+                min_int_ms: 100,
+                max_int_ms: 200,
+                latency_ms: 4,
+                timeout_ms: RELAXED_TIMEOUT_MS,
+            },
+        }
+    }
+}
+
+/// 请求客户端使用的连接参数，与`set_conn_params_conf`的参数相同
+#[derive(Debug, Clone, Copy)]
+struct ConnParams {
+    min_int_ms: u32,
+    max_int_ms: u32,
+    latency_ms: u32,
+    timeout_ms: u32,
+}
+
+impl ConnParams {
+    /// 客户端拒绝后重试用的参数：间隔上限加倍，监督超时放长，让客户端有更多选择
+    fn relaxed(self) -> Self {
+        Self {
+            max_int_ms: self.max_int_ms * 2,
+            timeout_ms: self.timeout_ms.max(RELAXED_TIMEOUT_MS),
+            ..self
+        }
+    }
+}
+
+/// 发送数据的接收方
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recipient {
+    /// 所有订阅的客户端，用于新捕获等主动上报的事件
+    All,
+    /// 只发给一个客户端，用于命令的回复
+    Peer(ConnectionId),
+    /// 串口控制台发出的命令，回复写到串口，不经过蓝牙
+    Console,
+}
+
+impl Recipient {
+    fn includes(self, conn_id: ConnectionId) -> bool {
+        match self {
+            Self::All => true,
+            Self::Peer(peer) => peer == conn_id,
+            Self::Console => false,
+        }
+    }
+}
+
+/// 只接受名单中的客户端连接，其他设备仍然可以扫描到广播
+#[derive(Debug, Clone, Default)]
+struct Whitelist {
+    enabled: bool,
+    peers: Vec<BdAddr>,
+}
+
+impl Whitelist {
+    /// 名单为空时即使打开也不过滤，避免所有设备都无法连接
+    fn active(&self) -> bool {
+        self.enabled && !self.peers.is_empty()
+    }
+}
+
+/// 读写请求的处理结果，失败时带上回复给客户端的ATT错误码
+type AttResult<T = ()> = Result<T, GattStatus>;
+
+type Gap = EspBleGap<'static, Ble, Arc<BtDriver<'static, Ble>>>;
+type Gatts = EspGatts<'static, Ble, Arc<BtDriver<'static, Ble>>>;
+
+/// 一次写入的目标属性
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteTarget {
+    Recv,
+    IndicationCccd,
+    TelemetryCccd,
+    BatteryCccd,
+}
+
+/// 按偏移拼接中的长写入
+#[derive(Debug, Clone)]
+struct PreparedWrite {
+    handle: Handle,
+    value: Vec<u8>,
+}
+
+impl Connection {
+    /// 订阅了通知，同时订阅两者时优先使用通知
+    fn uses_notify(&self) -> bool {
+        self.cccd & CCCD_NOTIFY != 0
+    }
+
+    fn uses_indicate(&self) -> bool {
+        self.cccd & CCCD_INDICATE != 0 && !self.uses_notify()
+    }
+
+    /// 把长写入的片段放到偏移处，重叠的部分以后到的片段为准
+    fn prepare(&mut self, handle: Handle, offset: u16, value: &[u8]) -> AttResult {
+        let index = self.prepared.iter().position(|prepared| prepared.handle == handle);
+        let len = index.map_or(0, |index| self.prepared[index].value.len());
+        let (offset, end) = (offset as usize, offset as usize + value.len());
+        // 片段必须与已有内容相接或重叠
+        if offset > len {
+            return Err(GattStatus::InvalidOffset);
+        }
+        if end > MAX_PREPARED_LEN {
+            return Err(GattStatus::InvalidAttrLen);
+        }
+
+        let index = index.unwrap_or_else(|| {
+            self.prepared.push(PreparedWrite {
+                handle,
+                value: Vec::new(),
+            });
+            self.prepared.len() - 1
+        });
+        let prepared = &mut self.prepared[index].value;
+        if end > prepared.len() {
+            prepared.resize(end, 0);
+        }
+        prepared[offset..end].copy_from_slice(value);
+        Ok(())
+    }
+}
+
+/// 读取IND特征时返回的设备状态，由主循环更新
+#[derive(Debug, Clone, Default)]
+struct DeviceStatus {
+    slot_count: u16,
+    recording_state: u8,
+    /// 最近一次捕获的时长总数
+    pulse_count: u16,
+    /// 最近一次捕获的前一部分时长
+    durations: Vec<u32>,
+}
+
+/// 客户端的一次写入，长写入拼接后算一次
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub conn_id: ConnectionId,
+    pub data: Vec<u8>,
+}
+
+/// 收到的消息交给`start_data_receiver`设置的回调，主循环没有处理完的消息有上限，超过时拒绝新的消息
+#[derive(Default)]
+struct ReceiveQueue {
+    sink: Option<Box<dyn Fn(Message) + Send>>,
+    /// 已经交给主循环、还没有处理完的消息数量
+    pending: usize,
+    dropped: u32,
+}
+
+impl ReceiveQueue {
+    /// 积压已满时返回Insufficient Resources，客户端可以稍后重试
+    fn push(&mut self, message: Message) -> AttResult {
+        let sink = match self.sink.as_ref() {
+            Some(sink) if self.pending < RECEIVE_QUEUE_LEN => sink,
+            _ => {
+                self.dropped = self.dropped.wrapping_add(1);
+                warn!("接收队列已满，拒绝新的消息（共拒绝{}条）", self.dropped);
+                return Err(GattStatus::InsufficientResources);
+            }
+        };
+        sink(message);
+        self.pending += 1;
+        Ok(())
+    }
+}
+
+/// 我们提供的GATT服务，前一个服务的特征和描述符都添加完成后再创建下一个
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Service {
+    Main,
+    Battery,
+    DeviceInfo,
+}
+
+impl Service {
+    const ALL: [Self; 3] = [Self::Main, Self::Battery, Self::DeviceInfo];
+
+    fn uuid(self) -> BtUuid {
+        match self {
+            Self::Main => BtUuid::uuid128(SERVICE_UUID),
+            Self::Battery => BtUuid::uuid16(BATTERY_SERVICE_UUID),
+            Self::DeviceInfo => BtUuid::uuid16(DEVICE_INFO_SERVICE_UUID),
+        }
+    }
+
+    fn from_uuid(uuid: &BtUuid) -> Option<Self> {
+        Self::ALL.into_iter().find(|service| service.uuid() == *uuid)
+    }
+
+    /// 服务声明、每个特征的声明和值、以及描述符占用的句柄数量
+    fn num_handles(self) -> u16 {
+        match self {
+            Self::Main => 12,
+            Self::Battery => 4,
+            Self::DeviceInfo => 9,
+        }
+    }
+
+    fn next(self) -> Option<Self> {
+        match self {
+            Self::Main => Some(Self::Battery),
+            Self::Battery => Some(Self::DeviceInfo),
+            Self::DeviceInfo => None,
+        }
+    }
+}
+
+/// 电池服务的句柄和电量
+#[derive(Default)]
+struct BatteryService {
+    /// 没有电池分压电路的设备关闭服务，客户端不会看到错误的0%
+    enabled: bool,
+    level_handle: Option<Handle>,
+    cccd_handle: Option<Handle>,
+    level: Option<u8>,
+    /// 上一次通知的电量
+    notified: Option<u8>,
 }
 
 #[derive(Default)]
 struct State {
+    /// 从初始化到关闭之间为true
+    running: bool,
     gatt_if: Option<GattInterface>,
-    service_handle: Option<Handle>,
+    /// 按`Service`的顺序保存每个服务的句柄
+    services: [Option<Handle>; Service::ALL.len()],
     recv_handle: Option<Handle>,
     ind_handle: Option<Handle>,
     ind_cccd_handle: Option<Handle>,
-    connections: heapless::Vec<Connection, MAX_CONNECTIONS>,
+    tel_handle: Option<Handle>,
+    tel_cccd_handle: Option<Handle>,
+    battery: BatteryService,
+    whitelist: Whitelist,
+    /// 省电模式中使用较长的广播间隔
+    slow_advertising: bool,
+    hardware_revision: String,
+    hw_rev_handle: Option<Handle>,
+    connections: Connections,
     response: GattResponse,
     ind_confirmed: Option<BdAddr>,
+    /// 等待GAP事件返回的RSSI读取，收到结果后值为Some
+    rssi_pending: HashMap<BdAddr, Option<i8>>,
+    /// 连接数已满时被拒绝的连接数量
+    rejected_connections: u32,
+    /// 发送通知时因为协议栈拥塞而等待的次数
+    congestion_stalls: u32,
+    /// 发出的指示，以及等待确认超时的次数
+    indications_sent: u32,
+    indication_timeouts: u32,
+    /// 客户端断开的次数
+    disconnects: u32,
+    status: DeviceStatus,
+    device_name: String,
+    /// 放在扫描响应厂商数据中的用户编号，用于区分多台设备
+    user_id: Option<u16>,
+}
+
+impl State {
+    fn service_handle(&self, service: Service) -> Option<Handle> {
+        self.services[service as usize]
+    }
+
+    /// 句柄属于哪个服务
+    fn service_of(&self, handle: Handle) -> Option<Service> {
+        Service::ALL.into_iter().find(|&service| self.service_handle(service) == Some(handle))
+    }
+
+    /// 只能读取、订阅的特征，写入时回复Request Not Supported
+    fn is_read_only(&self, handle: Handle) -> bool {
+        let handle = Some(handle);
+        handle == self.ind_handle || handle == self.tel_handle || handle == self.battery.level_handle
+    }
+
+    /// 写入的是哪个属性，不是我们的属性时返回None
+    fn write_target(&self, handle: Handle) -> Option<WriteTarget> {
+        let handle = Some(handle);
+        if handle == self.recv_handle {
+            Some(WriteTarget::Recv)
+        } else if handle == self.ind_cccd_handle {
+            Some(WriteTarget::IndicationCccd)
+        } else if handle == self.tel_cccd_handle {
+            Some(WriteTarget::TelemetryCccd)
+        } else if handle == self.battery.cccd_handle {
+            Some(WriteTarget::BatteryCccd)
+        } else {
+            None
+        }
+    }
 }
 
 pub struct BluetoothManager {
-    gap: Arc<EspBleGap<'static, Ble, Arc<BtDriver<'static, Ble>>>>,
-    gatts: Arc<EspGatts<'static, Ble, Arc<BtDriver<'static, Ble>>>>,
+    /// 蓝牙驱动初始化失败时为None，见`unavailable`
+    gap: Option<Arc<Gap>>,
+    gatts: Option<Arc<Gatts>>,
     state: Arc<Mutex<State>>,
     condvar: Arc<Condvar>,
-    is_connected: Arc<Mutex<bool>>,
-    received_data: Arc<Mutex<Vec<u8>>>,
+    ind_timeout_ms: Arc<AtomicU32>,
+    next_message_id: Arc<AtomicU8>,
+    received_data: Arc<Mutex<ReceiveQueue>>,
+    /// 发给所有客户端的事件，不随蓝牙关闭清空，客户端重新连接后可以取回错过的事件
+    events: Arc<Mutex<EventLog>>,
+    write_policy: Arc<Mutex<Arc<dyn WritePolicy>>>,
+    /// `send_data`等方法的接收方，见`for_peer`
+    recipient: Recipient,
 }
 
 impl BluetoothManager {
-    pub fn new(
-        gap: Arc<EspBleGap<'static, Ble, Arc<BtDriver<'static, Ble>>>>,
-        gatts: Arc<EspGatts<'static, Ble, Arc<BtDriver<'static, Ble>>>>,
-    ) -> Self {
+    pub fn new(gap: Arc<Gap>, gatts: Arc<Gatts>) -> Self {
+        Self {
+            gap: Some(gap),
+            gatts: Some(gatts),
+            ..Self::unavailable()
+        }
+    }
+
+    /// 蓝牙驱动不可用时的替代：没有客户端连接，控制台的命令和回复照常处理
+    pub fn unavailable() -> Self {
         Self {
-            gap,
-            gatts,
-            state: Arc::new(Mutex::new(Default::default())),
+            gap: None,
+            gatts: None,
+            state: Arc::new(Mutex::new(State {
+                device_name: DEFAULT_DEVICE_NAME.to_string(),
+                hardware_revision: DEFAULT_HARDWARE_REVISION.to_string(),
+                ..Default::default()
+            })),
             condvar: Arc::new(Condvar::new()),
-            is_connected: Arc::new(Mutex::new(false)),
-            received_data: Arc::new(Mutex::new(Vec::new())),
+            ind_timeout_ms: Arc::new(AtomicU32::new(DEFAULT_INDICATION_TIMEOUT_MS)),
+            next_message_id: Arc::new(AtomicU8::new(0)),
+            received_data: Arc::new(Mutex::new(ReceiveQueue::default())),
+            events: Arc::new(Mutex::new(EventLog::default())),
+            write_policy: Arc::new(Mutex::new(Arc::new(AllowAll))),
+            recipient: Recipient::All,
         }
     }
 
-    pub fn initialize(&self) -> Result<(), Box<dyn std::error::Error>> {
+    fn gap(&self) -> Result<&Gap, EspError> {
+        self.gap.as_deref().ok_or(EspError::from_infallible::<ESP_ERR_INVALID_STATE>())
+    }
+
+    fn gatts(&self) -> Result<&Gatts, EspError> {
+        self.gatts.as_deref().ok_or(EspError::from_infallible::<ESP_ERR_INVALID_STATE>())
+    }
+
+    pub fn initialize(&self) -> Result<(), Error> {
         info!("初始化BLE GATT服务器...");
-        
-        let gap_server = self.clone();
-        self.gap.subscribe(move |event| {
-            gap_server.check_esp_status(gap_server.on_gap_event(event));
-        })?;
+        self.state.lock().unwrap().running = true;
 
-        let gatts_server = self.clone();
-        self.gatts.subscribe(move |(gatt_if, event)| {
-            gatts_server.check_esp_status(gatts_server.on_gatts_event(gatt_if, event))
-        })?;
+        // 事件处理器不属于某个客户端的命令，即使从for_peer得到的管理器重启也要用广播
+        let server = Self {
+            recipient: Recipient::All,
+            ..self.clone()
+        };
+        let gap_server = server.clone();
+        self.gap()?
+            .subscribe(move |event| {
+                gap_server.check_esp_status(gap_server.on_gap_event(event));
+            })
+            .map_err(Error::Ble)?;
+
+        let gatts_server = server;
+        self.gatts()?
+            .subscribe(move |(gatt_if, event)| {
+                gatts_server.check_esp_status(gatts_server.on_gatts_event(gatt_if, event))
+            })
+            .map_err(Error::Ble)?;
 
         info!("BLE Gap和Gatts订阅初始化完成");
 
-        self.gatts.register_app(APP_ID)?;
+        self.gatts()?.register_app(APP_ID).map_err(Error::Ble)?;
         info!("Gatts BTP应用已注册");
 
         Ok(())
     }
 
+    /// 关闭蓝牙：停止广播、断开所有客户端、删除服务、注销应用并取消事件订阅
+    ///
+    /// 等待确认或拥塞解除的发送会被唤醒并返回`Error::Shutdown`。设备名称、电池等设置保留，
+    /// 之后可以用`initialize`重新开始。关闭过程中的错误只记录，尽量完成剩下的步骤。
+    pub fn shutdown(&self) {
+        info!("正在关闭BLE...");
+
+        let (gatt_if, services, peers) = {
+            let mut state = self.state.lock().unwrap();
+            let old = std::mem::take(&mut *state);
+            *state = State {
+                device_name: old.device_name,
+                user_id: old.user_id,
+                hardware_revision: old.hardware_revision,
+                battery: BatteryService {
+                    enabled: old.battery.enabled,
+                    level: old.battery.level,
+                    ..Default::default()
+                },
+                whitelist: old.whitelist,
+                slow_advertising: old.slow_advertising,
+                rejected_connections: old.rejected_connections,
+                congestion_stalls: old.congestion_stalls,
+                indications_sent: old.indications_sent,
+                indication_timeouts: old.indication_timeouts,
+                disconnects: old.disconnects,
+                status: old.status,
+                ..Default::default()
+            };
+            let peers: Vec<_> = old.connections.iter().map(|conn| conn.peer).collect();
+            (old.gatt_if, old.services, peers)
+        };
+        self.condvar.notify_all();
+
+        let warn_on_error = |step: &str, result: Result<(), EspError>| {
+            if let Err(e) = result {
+                warn!("{}失败: {:?}", step, e);
+            }
+        };
+
+        // 蓝牙驱动不可用时没有需要停止的广播和服务
+        let (Some(gap), Some(gatts)) = (&self.gap, &self.gatts) else {
+            info!("BLE已关闭");
+            return;
+        };
+        warn_on_error("停止广播", gap.stop_advertising());
+        for peer in peers {
+            warn_on_error("断开连接", gap.disconnect(peer));
+            self.write_policy.lock().unwrap().disconnected(peer);
+        }
+        // 先删除后创建的服务
+        for service_handle in services.into_iter().rev().flatten() {
+            warn_on_error("停止服务", gatts.stop_service(service_handle));
+            warn_on_error("删除服务", gatts.delete_service(service_handle));
+        }
+        if let Some(gatt_if) = gatt_if {
+            warn_on_error("注销应用", gatts.unregister_app(gatt_if));
+        }
+        // 订阅的回调持有管理器的克隆，取消订阅后一起释放
+        warn_on_error("取消GATTS订阅", gatts.unsubscribe());
+        warn_on_error("取消GAP订阅", gap.unsubscribe());
+
+        info!("BLE已关闭");
+    }
+
+    /// 关闭后重新初始化，用于协议栈卡住时恢复
+    pub fn restart(&self) -> Result<(), Error> {
+        self.shutdown();
+        // 断开连接和删除服务是异步完成的，留出时间让协议栈处理
+        std::thread::sleep(RESTART_DELAY);
+        self.initialize()
+    }
+
     /// GAP事件处理器
     fn on_gap_event(&self, event: BleGapEvent) -> Result<(), EspError> {
         info!("收到GAP事件: {event:?}");
 
         match event {
-            BleGapEvent::AdvertisingConfigured(status) => {
+            BleGapEvent::AdvertisingConfigured(status) | BleGapEvent::RawAdvertisingConfigured(status) => {
                 if let Err(e) = self.check_bt_status(status) {
                     warn!("广播配置状态错误: {:?}", e);
                     return Err(e);
                 }
-                if let Err(e) = self.gap.start_advertising() {
+                // 连接数已满时只更新广播内容，有连接断开后再开始广播
+                if !self.accepts_connections() {
+                    return Ok(());
+                }
+                if let Err(e) = self.start_advertising() {
                     warn!("开始广播失败: {:?}", e);
                     return Err(e);
                 }
@@ -110,12 +742,48 @@ impl BluetoothManager {
                 }
                 info!("BLE广播已开始");
             }
+            BleGapEvent::SecurityRequest(addr) => {
+                // 客户端请求加密，同意后由协议栈按配对参数完成配对
+                let mut raw = addr.raw();
+                if let Err(e) = EspError::convert(unsafe { sys::esp_ble_gap_security_rsp(raw.as_mut_ptr(), true) }) {
+                    warn!("响应 {} 的加密请求失败: {:?}", addr, e);
+                    return Err(e);
+                }
+            }
+            BleGapEvent::ReadRssiConfigured { bd_addr, rssi, status } => {
+                let mut state = self.state.lock().unwrap();
+                if let Err(e) = self.check_bt_status(status) {
+                    // 读取失败也算完成，不再等待
+                    warn!("读取 {} 的RSSI失败: {:?}", bd_addr, e);
+                    state.rssi_pending.remove(&bd_addr);
+                } else {
+                    if let Some(pending) = state.rssi_pending.get_mut(&bd_addr) {
+                        *pending = Some(rssi);
+                    }
+                    if let Some(conn) = state.connections.find_peer_mut(bd_addr) {
+                        conn.rssi = Some(rssi);
+                    }
+                }
+                self.condvar.notify_all();
+            }
+            BleGapEvent::ConnectionParamsConfigured { addr, status, conn_int, .. } => {
+                if status == BtStatus::Success {
+                    info!("{} 的连接参数已更新: 间隔{}", addr, conn_int);
+                } else {
+                    self.retry_conn_params(addr, status);
+                }
+            }
+            BleGapEvent::PasskeyNotification { addr, passkey } => {
+                info!("客户端 {} 配对，配对码: {:06}", addr, passkey);
+            }
             BleGapEvent::AdvertisingStopped(status) => {
                 warn!("广播已停止: {:?}", status);
-                // 广播停止后，尝试重新开始广播
-                if let Err(e) = self.gap.start_advertising() {
-                    warn!("重新开始广播失败: {:?}", e);
-                    return Err(e);
+                // 连接数已满时是有意停止的，否则重新开始广播
+                if self.accepts_connections() {
+                    if let Err(e) = self.start_advertising() {
+                        warn!("重新开始广播失败: {:?}", e);
+                        return Err(e);
+                    }
                 }
             }
             _ => {
@@ -146,13 +814,17 @@ impl BluetoothManager {
             GattsEvent::ServiceCreated {
                 status,
                 service_handle,
-                ..
+                service_id,
             } => {
                 if let Err(e) = self.check_gatt_status(status) {
                     warn!("服务创建状态错误: {:?}", e);
                     return Err(e);
                 }
-                if let Err(e) = self.configure_and_start_service(service_handle) {
+                let configured = match Service::from_uuid(&service_id.id.uuid) {
+                    Some(service) => self.configure_service(service, service_handle),
+                    None => Ok(()),
+                };
+                if let Err(e) = configured {
                     warn!("配置服务失败: {:?}", e);
                     return Err(e);
                 }
@@ -217,55 +889,13 @@ impl BluetoothManager {
                 // 处理读取请求
                 info!("收到读取请求: conn_id={}, handle={}, offset={}, addr={}", conn_id, handle, offset, addr);
                 
-                // 检查是否是我们的特征值
-                let state = self.state.lock().unwrap();
-                if Some(handle) == state.recv_handle {
-                    info!("客户端读取RECV特征值");
-                    // 对于RECV特征值，返回空数据
-                    self.gatts.send_response(
-                        gatt_if,
-                        conn_id,
-                        trans_id,
-                        GattStatus::Ok,
-                        None,
-                    )?;
-                } else if Some(handle) == state.ind_handle {
-                    info!("客户端读取IND特征值");
-                    // 对于IND特征值，返回空数据
-                    self.gatts.send_response(
-                        gatt_if,
-                        conn_id,
-                        trans_id,
-                        GattStatus::Ok,
-                        None,
-                    )?;
-                } else if Some(handle) == state.ind_cccd_handle {
-                    info!("客户端读取CCCD描述符");
-                    // 对于CCCD描述符，需要返回具体的值
-                    let mut response = GattResponse::new();
-                    response.attr_handle(handle)
-                        .auth_req(0)
-                        .offset(offset)
-                        .value(&[0x00, 0x00])  // CCCD默认值：未订阅
-                        .map_err(|_| EspError::from_infallible::<ESP_FAIL>())?;
-                    
-                    self.gatts.send_response(
-                        gatt_if,
-                        conn_id,
-                        trans_id,
-                        GattStatus::Ok,
-                        Some(&response),
-                    )?;
-                } else {
-                    info!("客户端读取未知特征值: handle={}", handle);
-                    // 对于未知特征值，返回错误
-                    self.gatts.send_response(
-                        gatt_if,
-                        conn_id,
-                        trans_id,
-                        GattStatus::Error,
-                        None,
-                    )?;
+                let result = read_value(&mut self.state.lock().unwrap(), conn_id, handle, offset);
+                match result {
+                    Ok(value) => self.send_read_response(gatt_if, conn_id, trans_id, handle, offset, &value)?,
+                    Err(status) => {
+                        warn!("拒绝 {} 的读取: handle={}, offset={}, {:?}", addr, handle, offset, status);
+                        self.gatts()?.send_response(gatt_if, conn_id, trans_id, status, None)?;
+                    }
                 }
             }
             GattsEvent::Write {
@@ -282,31 +912,45 @@ impl BluetoothManager {
                       conn_id, handle, offset, addr, value.len());
                 info!("写入数据内容: {:?}", value);
                 
-                match self.recv(
-                    gatt_if, conn_id, trans_id, addr, handle, offset, need_rsp, is_prep, value,
-                ) {
-                    Ok(handled) => {
-                        if handled {
-                            if let Err(e) = self.send_write_response(
-                                gatt_if, conn_id, trans_id, handle, offset, need_rsp, is_prep, value,
-                            ) {
-                                warn!("发送写入响应失败: {:?}", e);
-                                return Err(e);
-                            }
-                        }
+                let status = match self.recv(conn_id, addr, handle, offset, is_prep, value) {
+                    Ok(()) => GattStatus::Ok,
+                    Err(status) => {
+                        warn!("拒绝 {} 的写入: handle={}, offset={}, len={}, {:?}", addr, handle, offset, value.len(), status);
+                        status
                     }
-                    Err(e) => {
-                        warn!("处理写入数据失败: {:?}", e);
-                        return Err(e);
+                };
+                if let Err(e) = self.send_write_response(
+                    gatt_if, conn_id, trans_id, handle, offset, need_rsp, is_prep, value, status,
+                ) {
+                    warn!("发送写入响应失败: {:?}", e);
+                    return Err(e);
+                }
+            }
+            GattsEvent::ExecWrite {
+                conn_id,
+                trans_id,
+                canceled,
+                ..
+            } => {
+                let status = match self.exec_write(conn_id, canceled) {
+                    Ok(()) => GattStatus::Ok,
+                    Err(status) => {
+                        warn!("执行长写入失败: conn_id={}, {:?}", conn_id, status);
+                        status
                     }
+                };
+                if let Err(e) = self.gatts()?.send_response(gatt_if, conn_id, trans_id, status, None) {
+                    warn!("发送执行写入响应失败: {:?}", e);
+                    return Err(e);
                 }
             }
-            GattsEvent::Confirm { status, .. } => {
+            GattsEvent::Congest { conn_id, congested } => self.set_congested(conn_id, congested),
+            GattsEvent::Confirm { status, conn_id, .. } => {
                 if let Err(e) = self.check_gatt_status(status) {
                     warn!("确认状态错误: {:?}", e);
                     return Err(e);
                 }
-                if let Err(e) = self.confirm_indication() {
+                if let Err(e) = self.confirm_indication(conn_id) {
                     warn!("确认指示失败: {:?}", e);
                     return Err(e);
                 }
@@ -321,60 +965,127 @@ impl BluetoothManager {
     fn create_service(&self, gatt_if: GattInterface) -> Result<(), EspError> {
         self.state.lock().unwrap().gatt_if = Some(gatt_if);
 
-        self.gap.set_device_name("ESP32-IR-Recorder")?;
-        self.gap.set_adv_conf(&AdvConfiguration {
-            include_name: true,
-            include_txpower: true,
-            flag: 2,
-            service_uuid: Some(BtUuid::uuid128(SERVICE_UUID)),
-            ..Default::default()
-        })?;
-        self.gatts.create_service(
+        self.load_whitelist()?;
+        self.configure_advertising()?;
+        self.create_gatt_service(gatt_if, Service::Main)?;
+
+        Ok(())
+    }
+
+    fn create_gatt_service(&self, gatt_if: GattInterface, service: Service) -> Result<(), EspError> {
+        self.gatts()?.create_service(
             gatt_if,
             &GattServiceId {
                 id: GattId {
-                    uuid: BtUuid::uuid128(SERVICE_UUID),
+                    uuid: service.uuid(),
                     inst_id: 0,
                 },
                 is_primary: true,
             },
-            8,
-        )?;
+            service.num_handles(),
+        )
+    }
+
+    /// 设置设备名称和广播内容，广播配置完成后由GAP事件开始广播
+    fn configure_advertising(&self) -> Result<(), EspError> {
+        let (name, user_id) = {
+            let state = self.state.lock().unwrap();
+            (state.device_name.clone(), state.user_id)
+        };
+
+        self.gap()?.set_device_name(&name)?;
+        let mut adv = adv_payload(&name);
+        let mut scan_rsp = scan_response_payload(&name, user_id);
+        EspError::convert(unsafe { sys::esp_ble_gap_config_scan_rsp_data_raw(scan_rsp.as_mut_ptr(), scan_rsp.len() as u32) })?;
+        EspError::convert(unsafe { sys::esp_ble_gap_config_adv_data_raw(adv.as_mut_ptr(), adv.len() as u32) })?;
+
+        Ok(())
+    }
+
+    /// 按白名单设置过滤策略开始广播
+    fn start_advertising(&self) -> Result<(), EspError> {
+        let state = self.state.lock().unwrap();
+        start_advertising(&state.whitelist, state.slow_advertising)
+    }
 
+    /// 把白名单写入控制器，绑定过的客户端使用绑定时记录的地址类型
+    fn load_whitelist(&self) -> Result<(), EspError> {
+        let peers = self.state.lock().unwrap().whitelist.peers.clone();
+        let bonded = bonded_devices().unwrap_or_default();
+
+        EspError::convert(unsafe { sys::esp_ble_gap_clear_whitelist() })?;
+        for peer in peers {
+            let addr_type = match bonded.iter().find(|device| device.bd_addr == *peer.addr()) {
+                Some(device)
+                    if device.bd_addr_type == sys::esp_ble_addr_type_t_BLE_ADDR_TYPE_RANDOM
+                        || device.bd_addr_type == sys::esp_ble_addr_type_t_BLE_ADDR_TYPE_RPA_RANDOM =>
+                {
+                    sys::esp_ble_wl_addr_type_t_BLE_WL_ADDR_TYPE_RANDOM
+                }
+                _ => sys::esp_ble_wl_addr_type_t_BLE_WL_ADDR_TYPE_PUBLIC,
+            };
+            let mut raw = peer.raw();
+            EspError::convert(unsafe { sys::esp_ble_gap_update_whitelist(true, raw.as_mut_ptr(), addr_type) })?;
+        }
         Ok(())
     }
 
-    /// 配置并启动服务
-    fn configure_and_start_service(&self, service_handle: Handle) -> Result<(), EspError> {
-        self.state.lock().unwrap().service_handle = Some(service_handle);
+    /// 记录服务句柄，配置并启动服务
+    fn configure_service(&self, service: Service, service_handle: Handle) -> Result<(), EspError> {
+        self.state.lock().unwrap().services[service as usize] = Some(service_handle);
 
-        self.gatts.start_service(service_handle)?;
-        self.add_characteristics(service_handle)?;
+        match service {
+            Service::Main => {
+                self.gatts()?.start_service(service_handle)?;
+                self.add_characteristics(service_handle)?;
+            }
+            Service::Battery => self.configure_battery_service(service_handle)?,
+            Service::DeviceInfo => self.configure_device_info_service(service_handle)?,
+        }
 
         Ok(())
     }
 
     /// 添加特征到服务
     fn add_characteristics(&self, service_handle: Handle) -> Result<(), EspError> {
-        self.gatts.add_characteristic(
+        self.gatts()?.add_characteristic(
             service_handle,
             &GattCharacteristic {
                 uuid: BtUuid::uuid128(RECV_CHARACTERISTIC_UUID),
-                permissions: enum_set!(Permission::Write | Permission::Read),
+                permissions: enum_set!(Permission::WriteEncryptedMitm | Permission::ReadEncryptedMitm),
                 properties: enum_set!(Property::Write | Property::Read),
-                max_len: 200, // 最大接收数据
+                max_len: MAX_PREPARED_LEN, // 最大接收数据，长写入拼接后的长度
                 auto_rsp: AutoResponse::ByGatt,
             },
             &[],
         )?;
 
-        self.gatts.add_characteristic(
+        self.gatts()?.add_characteristic(
             service_handle,
             &GattCharacteristic {
                 uuid: BtUuid::uuid128(IND_CHARACTERISTIC_UUID),
-                permissions: enum_set!(Permission::Write | Permission::Read),
-                properties: enum_set!(Property::Indicate | Property::Read),
+                permissions: enum_set!(Permission::WriteEncryptedMitm | Permission::ReadEncryptedMitm),
+                properties: enum_set!(Property::Indicate | Property::Notify | Property::Read),
                 max_len: 200, // 最大指示数据
+                auto_rsp: AutoResponse::ByApp, // 读取时由应用返回设备状态
+            },
+            &[],
+        )?;
+
+        Ok(())
+    }
+
+    /// 添加日志特征
+    ///
+    /// 描述符总是加到最后添加的特征上，因此等指示特征的CCCD添加完成后再添加。
+    fn add_telemetry_characteristic(&self, service_handle: Handle) -> Result<(), EspError> {
+        self.gatts()?.add_characteristic(
+            service_handle,
+            &GattCharacteristic {
+                uuid: BtUuid::uuid128(TELEMETRY_CHARACTERISTIC_UUID),
+                permissions: enum_set!(Permission::ReadEncryptedMitm),
+                properties: enum_set!(Property::Notify),
+                max_len: crate::telemetry::MAX_LINE_LEN,
                 auto_rsp: AutoResponse::ByGatt,
             },
             &[],
@@ -383,6 +1094,59 @@ impl BluetoothManager {
         Ok(())
     }
 
+    /// 添加电量特征，服务关闭时先不启动
+    fn configure_battery_service(&self, service_handle: Handle) -> Result<(), EspError> {
+        let enabled = self.state.lock().unwrap().battery.enabled;
+
+        if enabled {
+            self.gatts()?.start_service(service_handle)?;
+        }
+        self.gatts()?.add_characteristic(
+            service_handle,
+            &GattCharacteristic {
+                uuid: BtUuid::uuid16(BATTERY_LEVEL_UUID),
+                permissions: enum_set!(Permission::ReadEncryptedMitm),
+                properties: enum_set!(Property::Read | Property::Notify),
+                max_len: 1,
+                auto_rsp: AutoResponse::ByApp, // 读取时由应用返回最近一次采样
+            },
+            &[],
+        )?;
+
+        Ok(())
+    }
+
+    /// 添加设备信息特征，值由协议栈保存并直接响应读取
+    ///
+    /// 这些特征没有描述符，可以一起添加；设备信息不需要加密即可读取。
+    fn configure_device_info_service(&self, service_handle: Handle) -> Result<(), EspError> {
+        let hardware_revision = self.state.lock().unwrap().hardware_revision.clone();
+
+        self.gatts()?.start_service(service_handle)?;
+        let values = [
+            (MANUFACTURER_NAME_UUID, MANUFACTURER_NAME),
+            (MODEL_NUMBER_UUID, MODEL_NUMBER),
+            (FIRMWARE_REVISION_UUID, FIRMWARE_REVISION),
+            (HARDWARE_REVISION_UUID, hardware_revision.as_str()),
+        ];
+        for (uuid, value) in values {
+            self.gatts()?.add_characteristic(
+                service_handle,
+                &GattCharacteristic {
+                    uuid: BtUuid::uuid16(uuid),
+                    permissions: enum_set!(Permission::Read),
+                    properties: enum_set!(Property::Read),
+                    // 硬件版本可以在运行时修改，按最大长度预留
+                    max_len: value.len().max(MAX_HARDWARE_REVISION_LEN),
+                    auto_rsp: AutoResponse::ByGatt,
+                },
+                value.as_bytes(),
+            )?;
+        }
+
+        Ok(())
+    }
+
     /// 注册特征
     fn register_characteristic(
         &self,
@@ -390,28 +1154,42 @@ impl BluetoothManager {
         attr_handle: Handle,
         char_uuid: BtUuid,
     ) -> Result<(), EspError> {
-        let indicate_char = {
+        let needs_cccd = {
             let mut state = self.state.lock().unwrap();
 
-            if state.service_handle != Some(service_handle) {
-                false
-            } else if char_uuid == BtUuid::uuid128(RECV_CHARACTERISTIC_UUID) {
-                state.recv_handle = Some(attr_handle);
-                false
-            } else if char_uuid == BtUuid::uuid128(IND_CHARACTERISTIC_UUID) {
-                state.ind_handle = Some(attr_handle);
-                true
-            } else {
-                false
+            match state.service_of(service_handle) {
+                Some(Service::Main) => {
+                    if char_uuid == BtUuid::uuid128(RECV_CHARACTERISTIC_UUID) {
+                        state.recv_handle = Some(attr_handle);
+                        false
+                    } else if char_uuid == BtUuid::uuid128(IND_CHARACTERISTIC_UUID) {
+                        state.ind_handle = Some(attr_handle);
+                        true
+                    } else if char_uuid == BtUuid::uuid128(TELEMETRY_CHARACTERISTIC_UUID) {
+                        state.tel_handle = Some(attr_handle);
+                        true
+                    } else {
+                        false
+                    }
+                }
+                Some(Service::Battery) if char_uuid == BtUuid::uuid16(BATTERY_LEVEL_UUID) => {
+                    state.battery.level_handle = Some(attr_handle);
+                    true
+                }
+                Some(Service::DeviceInfo) if char_uuid == BtUuid::uuid16(HARDWARE_REVISION_UUID) => {
+                    state.hw_rev_handle = Some(attr_handle);
+                    false
+                }
+                _ => false,
             }
         };
 
-        if indicate_char {
-            self.gatts.add_descriptor(
+        if needs_cccd {
+            self.gatts()?.add_descriptor(
                 service_handle,
                 &GattDescriptor {
                     uuid: BtUuid::uuid16(0x2902), // CCCD
-                    permissions: enum_set!(Permission::Read | Permission::Write),
+                    permissions: enum_set!(Permission::ReadEncryptedMitm | Permission::WriteEncryptedMitm),
                 },
             )?;
         }
@@ -426,12 +1204,38 @@ impl BluetoothManager {
         attr_handle: Handle,
         descr_uuid: BtUuid,
     ) -> Result<(), EspError> {
-        let mut state = self.state.lock().unwrap();
+        // 特征和描述符依次添加，每个CCCD添加完成后再添加下一个特征或服务
+        let (add_telemetry, completed, gatt_if) = {
+            let mut state = self.state.lock().unwrap();
+
+            let (add_telemetry, completed) = if descr_uuid != BtUuid::uuid16(0x2902) { // CCCD UUID
+                (false, None)
+            } else {
+                match state.service_of(service_handle) {
+                    Some(Service::Main) if state.ind_cccd_handle.is_none() => {
+                        state.ind_cccd_handle = Some(attr_handle);
+                        (true, None)
+                    }
+                    Some(Service::Main) => {
+                        state.tel_cccd_handle = Some(attr_handle);
+                        (false, Some(Service::Main))
+                    }
+                    Some(Service::Battery) => {
+                        state.battery.cccd_handle = Some(attr_handle);
+                        (false, Some(Service::Battery))
+                    }
+                    _ => (false, None),
+                }
+            };
+            (add_telemetry, completed, state.gatt_if)
+        };
 
-        if descr_uuid == BtUuid::uuid16(0x2902) // CCCD UUID
-            && state.service_handle == Some(service_handle)
-        {
-            state.ind_cccd_handle = Some(attr_handle);
+        if add_telemetry {
+            self.add_telemetry_characteristic(service_handle)?;
+        }
+        // 这个服务已经添加完成，创建下一个
+        if let (Some(next), Some(gatt_if)) = (completed.and_then(Service::next), gatt_if) {
+            self.create_gatt_service(gatt_if, next)?;
         }
 
         Ok(())
@@ -441,47 +1245,47 @@ impl BluetoothManager {
     fn register_conn_mtu(&self, conn_id: ConnectionId, mtu: u16) -> Result<(), EspError> {
         let mut state = self.state.lock().unwrap();
 
-        if let Some(conn) = state
-            .connections
-            .iter_mut()
-            .find(|conn| conn.conn_id == conn_id)
-        {
+        if let Some(conn) = state.connections.find_mut(conn_id) {
             conn.mtu = Some(mtu);
         }
 
         Ok(())
     }
 
-    /// 创建新连接
+    /// 还能接受新的连接
+    fn accepts_connections(&self) -> bool {
+        self.state.lock().unwrap().connections.has_room()
+    }
+
+    /// 创建新连接
     fn create_conn(&self, conn_id: ConnectionId, addr: BdAddr) -> Result<(), EspError> {
-        let added = {
+        let (added, has_room) = {
             let mut state = self.state.lock().unwrap();
 
-            if state.connections.len() < MAX_CONNECTIONS {
-                state
-                    .connections
-                    .push(Connection {
-                        peer: addr,
-                        conn_id,
-                        subscribed: false,
-                        mtu: None,
-                    })
-                    .map_err(|_| ())
-                    .unwrap();
-
-                true
+            if state.connections.add(conn_id, addr, Instant::now()) {
+                (true, state.connections.has_room())
             } else {
-                false
+                state.rejected_connections += 1;
+                (false, false)
             }
         };
 
-        if added {
-            self.gap.set_conn_params_conf(addr, 10, 20, 0, 400)?;
-            // 更新连接状态
-            if let Ok(mut connected) = self.is_connected.lock() {
-                *connected = true;
+        if !added {
+            warn!("连接数已满，拒绝 {}", addr);
+            if let Err(e) = self.gap()?.disconnect(addr) {
+                warn!("断开 {} 失败: {:?}", addr, e);
+            }
+            return Ok(());
+        }
+
+        self.request_conn_params(addr, LinkMode::Balanced.params());
+        info!("BLE客户端连接: {}", addr);
+
+        // 协议栈在连接建立时停止广播，还有空位时继续广播让其他客户端连接
+        if has_room {
+            if let Err(e) = self.start_advertising() {
+                warn!("重新开始广播失败: {:?}", e);
             }
-            info!("BLE客户端连接: {}", addr);
         }
 
         Ok(())
@@ -491,25 +1295,27 @@ impl BluetoothManager {
     fn delete_conn(&self, addr: BdAddr) -> Result<(), EspError> {
         let mut state = self.state.lock().unwrap();
 
-        if let Some(index) = state
-            .connections
-            .iter()
-            .position(|Connection { peer, .. }| *peer == addr)
-        {
-            state.connections.swap_remove(index);
+        // 订阅状态随连接一起删除，重新连接的客户端需要重新订阅
+        if let Some(conn) = state.connections.remove(addr) {
+            state.disconnects = state.disconnects.wrapping_add(1);
+            if conn.cccd != 0 {
+                info!("客户端 {} 断开，取消其订阅", conn.peer);
+            }
+            self.write_policy.lock().unwrap().disconnected(conn.peer);
         }
 
-        // 更新连接状态
-        if let Ok(mut connected) = self.is_connected.lock() {
-            *connected = false;
+        // 断开的客户端不会再确认指示，唤醒等待中的发送
+        if state.ind_confirmed == Some(addr) {
+            state.ind_confirmed = None;
+            self.condvar.notify_all();
         }
+
         info!("BLE客户端断开连接: {}", addr);
 
-        // 如果没有其他连接，重新开始广播
-        if state.connections.is_empty() {
-            info!("所有客户端已断开，重新开始广播...");
-            // 重新开始广播
-            if let Err(e) = self.gap.start_advertising() {
+        // 空出了位置，重新开始广播
+        if state.connections.has_room() {
+            info!("当前{}个连接，重新开始广播...", state.connections.len());
+            if let Err(e) = start_advertising(&state.whitelist, state.slow_advertising) {
                 warn!("重新开始广播失败: {:?}", e);
             }
         }
@@ -517,60 +1323,145 @@ impl BluetoothManager {
         Ok(())
     }
 
-    /// 接收数据
-    #[allow(clippy::too_many_arguments)]
+    /// 接收数据，失败时返回写入响应的ATT错误码
     fn recv(
         &self,
-        _gatt_if: GattInterface,
         conn_id: ConnectionId,
-        _trans_id: TransferId,
         addr: BdAddr,
         handle: Handle,
         offset: u16,
-        _need_rsp: bool,
-        _is_prep: bool,
+        is_prep: bool,
         value: &[u8],
-    ) -> Result<bool, EspError> {
+    ) -> AttResult {
         let mut state = self.state.lock().unwrap();
 
-        let recv_handle = state.recv_handle;
-        let ind_cccd_handle = state.ind_cccd_handle;
+        let Some(target) = state.write_target(handle) else {
+            return Err(if state.is_read_only(handle) {
+                GattStatus::RequestNotSupported
+            } else {
+                GattStatus::InvalidHandle
+            });
+        };
 
-        let Some(conn) = state
-            .connections
-            .iter_mut()
-            .find(|conn| conn.conn_id == conn_id)
-        else {
-            return Ok(false);
+        let conn = state.connections.find_mut(conn_id).ok_or(GattStatus::Error)?;
+        conn.last_activity = Instant::now();
+
+        let request = WriteRequest {
+            peer: addr,
+            bonded: is_bonded(addr),
+            target,
+            is_prep,
+            value,
+        };
+        if !self.write_policy.lock().unwrap().allows(&request) {
+            warn!("写入策略拒绝了 {} 的写入: {:?}", addr, target);
+            return Err(GattStatus::WriteNotPermitted);
+        }
+
+        if is_prep {
+            // 长写入的片段先按偏移放入缓冲区，执行写入时才处理
+            return conn.prepare(handle, offset, value);
+        }
+
+        self.apply_write(conn, target, offset, value)
+    }
+
+    /// 执行或取消该连接缓冲的长写入，遇到第一个失败的写入时停止
+    fn exec_write(&self, conn_id: ConnectionId, canceled: bool) -> AttResult {
+        let mut state = self.state.lock().unwrap();
+
+        let Some(index) = state.connections.position(conn_id) else {
+            return Err(GattStatus::Error);
         };
 
-        if Some(handle) == ind_cccd_handle {
-            // 订阅或取消订阅指示特征
-            if offset == 0 && value.len() == 2 {
-                let value = u16::from_le_bytes([value[0], value[1]]);
-                if value == 0x02 {
-                    if !conn.subscribed {
-                        conn.subscribed = true;
-                        info!("客户端 {} 订阅了指示特征", conn.peer);
+        let prepared = std::mem::take(&mut state.connections[index].prepared);
+        if canceled {
+            info!("客户端 {} 取消了长写入", state.connections[index].peer);
+            return Ok(());
+        }
+        for PreparedWrite { handle, value } in prepared {
+            let Some(target) = state.write_target(handle) else {
+                continue;
+            };
+            let conn = &mut state.connections[index];
+            info!("客户端 {} 的长写入完成: handle={}, len={}", conn.peer, handle, value.len());
+            self.apply_write(conn, target, 0, &value)?;
+        }
+        Ok(())
+    }
+
+    /// 处理一次完整的写入
+    fn apply_write(&self, conn: &mut Connection, target: WriteTarget, offset: u16, value: &[u8]) -> AttResult {
+        match target {
+            WriteTarget::IndicationCccd => {
+                // 订阅或取消订阅通知和指示
+                let value = cccd_value(offset, value, CCCD_NOTIFY | CCCD_INDICATE)?;
+                if value != conn.cccd {
+                    conn.cccd = value;
+                    match (value & CCCD_NOTIFY != 0, value & CCCD_INDICATE != 0) {
+                        (true, _) => info!("客户端 {} 订阅了通知", conn.peer),
+                        (false, true) => info!("客户端 {} 订阅了指示", conn.peer),
+                        (false, false) => info!("客户端 {} 取消了订阅", conn.peer),
                     }
-                } else if conn.subscribed {
-                    conn.subscribed = false;
-                    info!("客户端 {} 取消订阅指示特征", conn.peer);
                 }
             }
-        } else if Some(handle) == recv_handle {
-            // 在recv特征上接收数据
-            info!("从 {} 接收数据: {:?}", addr, value);
-            
-            // 将数据添加到接收缓冲区
-            if let Ok(mut data_vec) = self.received_data.lock() {
-                data_vec.extend_from_slice(value);
+            WriteTarget::TelemetryCccd => {
+                // 日志特征只支持通知
+                let value = cccd_value(offset, value, CCCD_NOTIFY)?;
+                if value != conn.telemetry_cccd {
+                    conn.telemetry_cccd = value;
+                    if value != 0 {
+                        info!("客户端 {} 订阅了日志", conn.peer);
+                    } else {
+                        info!("客户端 {} 取消了日志订阅", conn.peer);
+                    }
+                }
+            }
+            WriteTarget::BatteryCccd => {
+                let value = cccd_value(offset, value, CCCD_NOTIFY)?;
+                if value != conn.battery_cccd {
+                    conn.battery_cccd = value;
+                    if value != 0 {
+                        info!("客户端 {} 订阅了电量", conn.peer);
+                    } else {
+                        info!("客户端 {} 取消了电量订阅", conn.peer);
+                    }
+                }
+            }
+            WriteTarget::Recv => {
+                // 在recv特征上接收数据
+                info!("从 {} 接收数据: {:?}", conn.peer, value);
+
+                // 每次写入作为一条消息交给主循环
+                let mut queue = self.received_data.lock().map_err(|_| GattStatus::Error)?;
+                queue.push(Message {
+                    conn_id: conn.conn_id,
+                    data: value.to_vec(),
+                })?;
             }
-        } else {
-            return Ok(false);
         }
+        Ok(())
+    }
 
-        Ok(true)
+    /// 发送带值的读取响应
+    fn send_read_response(
+        &self,
+        gatt_if: GattInterface,
+        conn_id: ConnectionId,
+        trans_id: TransferId,
+        handle: Handle,
+        offset: u16,
+        value: &[u8],
+    ) -> Result<(), EspError> {
+        let mut response = GattResponse::new();
+        response
+            .attr_handle(handle)
+            .auth_req(0)
+            .offset(offset)
+            .value(value)
+            .map_err(|_| EspError::from_infallible::<ESP_FAIL>())?;
+
+        self.gatts()?.send_response(gatt_if, conn_id, trans_id, GattStatus::Ok, Some(&response))
     }
 
     /// 发送写入响应
@@ -585,12 +1476,15 @@ impl BluetoothManager {
         need_rsp: bool,
         is_prep: bool,
         value: &[u8],
+        status: GattStatus,
     ) -> Result<(), EspError> {
         if !need_rsp {
             return Ok(());
         }
 
-        if is_prep {
+        if status != GattStatus::Ok {
+            self.gatts()?.send_response(gatt_if, conn_id, trans_id, status, None)?;
+        } else if is_prep {
             let mut state = self.state.lock().unwrap();
 
             state
@@ -601,7 +1495,7 @@ impl BluetoothManager {
                 .value(value)
                 .map_err(|_| EspError::from_infallible::<ESP_FAIL>())?;
 
-            self.gatts.send_response(
+            self.gatts()?.send_response(
                 gatt_if,
                 conn_id,
                 trans_id,
@@ -609,7 +1503,7 @@ impl BluetoothManager {
                 Some(&state.response),
             )?;
         } else {
-            self.gatts
+            self.gatts()?
                 .send_response(gatt_if, conn_id, trans_id, GattStatus::Ok, None)?;
         }
 
@@ -617,10 +1511,14 @@ impl BluetoothManager {
     }
 
     /// 确认指示
-    fn confirm_indication(&self) -> Result<(), EspError> {
+    fn confirm_indication(&self, conn_id: ConnectionId) -> Result<(), EspError> {
         let mut state = self.state.lock().unwrap();
-        if state.ind_confirmed.is_none() {
-            unreachable!();
+        let peer = state.connections.find(conn_id).map(|conn| conn.peer);
+
+        // 超时之后才到达的确认不属于正在等待的指示，忽略
+        if state.ind_confirmed.is_none() || state.ind_confirmed != peer {
+            warn!("收到迟到的指示确认: conn_id={}", conn_id);
+            return Ok(());
         }
 
         state.ind_confirmed = None;
@@ -629,16 +1527,38 @@ impl BluetoothManager {
         Ok(())
     }
 
-    /// 发送指示数据到所有订阅的客户端
-    fn indicate(&self, data: &[u8]) -> Result<(), EspError> {
+    /// 记录协议栈的拥塞状态，解除时唤醒等待中的通知
+    fn set_congested(&self, conn_id: ConnectionId, congested: bool) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(conn) = state.connections.find_mut(conn_id) {
+            conn.congested = congested;
+            info!("客户端 {} 拥塞状态: {}", conn.peer, congested);
+        }
+        if !congested {
+            self.condvar.notify_all();
+        }
+    }
+
+    /// 发送指示数据到只订阅了指示的客户端
+    ///
+    /// 上一个指示超时没有确认时放弃等待，断开该客户端并返回`Error::Timeout`。
+    fn indicate(&self, recipient: Recipient, data: &[u8]) -> Result<(), Error> {
+        let timeout = Duration::from_millis(self.ind_timeout_ms.load(Ordering::Relaxed) as u64);
         for peer_index in 0..MAX_CONNECTIONS {
             let mut state = self.state.lock().unwrap();
+            let deadline = Instant::now() + timeout;
 
             loop {
                 if state.connections.len() <= peer_index {
                     break;
                 }
 
+                // 没有订阅的客户端不会确认指示，订阅了通知的客户端由send_notify发送
+                let conn = &state.connections[peer_index];
+                if !conn.uses_indicate() || !recipient.includes(conn.conn_id) {
+                    break;
+                }
+
                 let Some(gatt_if) = state.gatt_if else {
                     break;
                 };
@@ -650,16 +1570,81 @@ impl BluetoothManager {
                 if state.ind_confirmed.is_none() {
                     let conn = &state.connections[peer_index];
 
-                    self.gatts
-                        .indicate(gatt_if, conn.conn_id, ind_handle, data)?;
+                    self.gatts()?
+                        .indicate(gatt_if, conn.conn_id, ind_handle, data)
+                        .map_err(Error::Ble)?;
 
                     state.ind_confirmed = Some(conn.peer);
+                    state.indications_sent = state.indications_sent.wrapping_add(1);
+                    state.connections[peer_index].last_activity = Instant::now();
                     let conn = &state.connections[peer_index];
 
                     info!("向 {} 发送指示数据", conn.peer);
                     break;
                 } else {
-                    state = self.condvar.wait(state).unwrap();
+                    let now = Instant::now();
+                    if now >= deadline {
+                        let peer = state.ind_confirmed.take().unwrap();
+                        state.indication_timeouts = state.indication_timeouts.wrapping_add(1);
+                        drop(state);
+                        warn!("等待 {} 确认指示超时，断开连接", peer);
+                        if let Err(e) = self.gap()?.disconnect(peer) {
+                            warn!("断开 {} 失败: {:?}", peer, e);
+                        }
+                        return Err(Error::Timeout { peer });
+                    }
+                    state = self.condvar.wait_timeout(state, deadline - now).unwrap().0;
+                    if !state.running {
+                        return Err(Error::Shutdown);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 向订阅了通知的客户端发送通知，不等待确认
+    ///
+    /// 协议栈报告拥塞时等待拥塞解除，超时后返回`Error::Busy`。
+    fn send_notify(&self, recipient: Recipient, data: &[u8]) -> Result<(), Error> {
+        let timeout = self.indication_timeout();
+        for peer_index in 0..MAX_CONNECTIONS {
+            let mut state = self.state.lock().unwrap();
+            let deadline = Instant::now() + timeout;
+            let mut stalled = false;
+
+            while let Some(conn) = state.connections.get(peer_index) {
+                if !conn.uses_notify() || !recipient.includes(conn.conn_id) {
+                    break;
+                }
+                let (Some(gatt_if), Some(ind_handle)) = (state.gatt_if, state.ind_handle) else {
+                    break;
+                };
+
+                if !conn.congested {
+                    self.gatts()?
+                        .notify(gatt_if, conn.conn_id, ind_handle, data)
+                        .map_err(Error::Ble)?;
+                    info!("向 {} 发送通知数据", conn.peer);
+                    state.connections[peer_index].last_activity = Instant::now();
+                    break;
+                }
+
+                let now = Instant::now();
+                if now >= deadline {
+                    let peer = conn.peer;
+                    warn!("{} 拥塞超时，丢弃通知", peer);
+                    return Err(Error::Busy { peer });
+                }
+                // 同一个通知等待多次只计一次
+                if !stalled {
+                    stalled = true;
+                    state.congestion_stalls += 1;
+                }
+                state = self.condvar.wait_timeout(state, deadline - now).unwrap().0;
+                if !state.running {
+                    return Err(Error::Shutdown);
                 }
             }
         }
@@ -667,36 +1652,562 @@ impl BluetoothManager {
         Ok(())
     }
 
+    /// 向订阅了日志的客户端发送一行日志，拥塞的客户端跳过这一行
+    ///
+    /// 不等待拥塞解除，避免日志发送拖慢设备；超过MTU的部分截断。
+    pub fn send_telemetry(&self, line: &str) -> Result<(), EspError> {
+        let state = self.state.lock().unwrap();
+        let (Some(gatt_if), Some(tel_handle)) = (state.gatt_if, state.tel_handle) else {
+            return Ok(());
+        };
+
+        for conn in state.connections.iter() {
+            if conn.telemetry_cccd & CCCD_NOTIFY == 0 || conn.congested {
+                continue;
+            }
+            let payload = (conn.mtu.unwrap_or(DEFAULT_MTU) as usize).saturating_sub(ATT_HEADER_LEN);
+            let data = &line.as_bytes()[..line.len().min(payload)];
+            self.gatts()?.notify(gatt_if, conn.conn_id, tel_handle, data)?;
+        }
+
+        Ok(())
+    }
+
+    /// 更新电量，变化达到`BATTERY_NOTIFY_STEP`时通知订阅的客户端
+    pub fn set_battery_level(&self, level: Option<u8>) {
+        let mut state = self.state.lock().unwrap();
+        state.battery.level = level;
+
+        let Some(level) = level else {
+            return;
+        };
+        if state.battery.notified.is_some_and(|notified| notified.abs_diff(level) < BATTERY_NOTIFY_STEP) {
+            return;
+        }
+        state.battery.notified = Some(level);
+
+        let (Some(gatt_if), Some(level_handle), true) =
+            (state.gatt_if, state.battery.level_handle, state.battery.enabled)
+        else {
+            return;
+        };
+        for conn in state.connections.iter() {
+            if conn.battery_cccd & CCCD_NOTIFY != 0 {
+                let notified = self
+                    .gatts()
+                    .and_then(|gatts| gatts.notify(gatt_if, conn.conn_id, level_handle, &[level]));
+                if let Err(e) = notified {
+                    warn!("向 {} 发送电量失败: {:?}", conn.peer, e);
+                }
+            }
+        }
+    }
+
+    /// 打开或关闭电池服务，服务已经创建时立即启动或停止
+    pub fn set_battery_enabled(&self, enabled: bool) -> Result<(), EspError> {
+        let service_handle = {
+            let mut state = self.state.lock().unwrap();
+            if state.battery.enabled == enabled {
+                return Ok(());
+            }
+            state.battery.enabled = enabled;
+            // 重新打开后第一次采样总是通知
+            state.battery.notified = None;
+            state.service_handle(Service::Battery)
+        };
+
+        if let Some(service_handle) = service_handle {
+            if enabled {
+                self.gatts()?.start_service(service_handle)?;
+            } else {
+                self.gatts()?.stop_service(service_handle)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 设置设备信息服务中的硬件版本，服务已经创建时立即更新
+    pub fn set_hardware_revision(&self, revision: &str) -> Result<(), EspError> {
+        let handle = {
+            let mut state = self.state.lock().unwrap();
+            state.hardware_revision = revision.to_string();
+            state.hw_rev_handle
+        };
+
+        if let Some(handle) = handle {
+            self.gatts()?.set_attr(handle, revision.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// 更换写入策略，立即对之后的写入生效
+    pub fn set_write_policy(&self, policy: Arc<dyn WritePolicy>) {
+        *self.write_policy.lock().unwrap() = policy;
+    }
+
+    /// 授权该连接的客户端写入，连接不存在时返回false
+    pub fn authorize(&self, conn_id: ConnectionId) -> bool {
+        let peer = self
+            .state
+            .lock()
+            .unwrap()
+            .connections
+            .find(conn_id)
+            .map(|conn| conn.peer);
+        if let Some(peer) = peer {
+            info!("授权 {} 写入", peer);
+            self.write_policy.lock().unwrap().authorize(peer);
+        }
+        peer.is_some()
+    }
+
+    /// 是否有客户端订阅了日志
+    pub fn telemetry_subscribed(&self) -> bool {
+        self.state
+            .lock()
+            .is_ok_and(|state| state.connections.iter().any(|conn| conn.telemetry_cccd != 0))
+    }
+
     // 公共接口方法
+    /// 是否还有客户端连接，多个客户端时一个断开不影响其他的
     pub fn is_connected(&self) -> bool {
-        if let Ok(connected) = self.is_connected.lock() {
-            *connected
-        } else {
-            false
-        }
+        self.state.lock().is_ok_and(|state| state.connections.is_connected())
     }
 
-    pub fn get_received_data(&self) -> Vec<u8> {
-        if let Ok(mut data) = self.received_data.lock() {
-            let result = data.clone();
-            data.clear();
-            result
-        } else {
-            Vec::new()
+    /// 设置等待客户端确认指示的时间
+    pub fn set_indication_timeout(&self, timeout: Duration) {
+        self.ind_timeout_ms.store(timeout.as_millis().min(u32::MAX as u128) as u32, Ordering::Relaxed);
+    }
+
+    pub fn indication_timeout(&self) -> Duration {
+        Duration::from_millis(self.ind_timeout_ms.load(Ordering::Relaxed) as u64)
+    }
+
+    /// 当前连接的客户端数量
+    pub fn connection_count(&self) -> usize {
+        self.state.lock().map_or(0, |state| state.connections.len())
+    }
+
+    /// 主循环处理完一条消息后调用，腾出接收队列的位置；控制台的命令只补上回复末尾的换行
+    pub fn message_handled(&self) {
+        if self.recipient == Recipient::Console {
+            console::end_reply();
+            return;
+        }
+        if let Ok(mut queue) = self.received_data.lock() {
+            queue.pending = queue.pending.saturating_sub(1);
         }
     }
 
-    pub fn send_data(&self, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    /// 更新读取IND特征时返回的录制数量和录制状态
+    pub fn set_status(&self, slot_count: usize, recording_state: u8) {
+        let mut state = self.state.lock().unwrap();
+        state.status.slot_count = slot_count.min(u16::MAX as usize) as u16;
+        state.status.recording_state = recording_state;
+    }
+
+    /// 更新读取IND特征时附带的最近一次捕获
+    pub fn set_last_capture(&self, durations: &[u32]) {
+        let mut state = self.state.lock().unwrap();
+        state.status.pulse_count = durations.len().min(u16::MAX as usize) as u16;
+        state.status.durations = durations[..durations.len().min(STATUS_CAPTURE_DURATIONS)].to_vec();
+    }
+
+    /// 连接数已满时被拒绝的连接数量
+    pub fn rejected_connections(&self) -> u32 {
+        self.state.lock().map_or(0, |state| state.rejected_connections)
+    }
+
+    /// 发送通知时因为拥塞而等待的次数
+    pub fn congestion_stalls(&self) -> u32 {
+        self.state.lock().map_or(0, |state| state.congestion_stalls)
+    }
+
+    /// 启动以来发出的指示数量
+    pub fn indications_sent(&self) -> u32 {
+        self.state.lock().map_or(0, |state| state.indications_sent)
+    }
+
+    /// 客户端没有在超时时间内确认指示的次数
+    pub fn indication_timeouts(&self) -> u32 {
+        self.state.lock().map_or(0, |state| state.indication_timeouts)
+    }
+
+    /// 启动以来客户端断开的次数
+    pub fn disconnects(&self) -> u32 {
+        self.state.lock().map_or(0, |state| state.disconnects)
+    }
+
+    /// 接收队列满时拒绝的写入数量
+    pub fn dropped_messages(&self) -> u32 {
+        self.received_data.lock().map_or(0, |queue| queue.dropped)
+    }
+
+    /// 发送给接收方；发给所有客户端的是主动上报的事件，加上序号信封并保存在重发缓冲区中
+    ///
+    /// 没有客户端连接时事件只保存，之后连接的客户端可以用`resync`取回。
+    pub fn send_data(&self, data: &[u8]) -> Result<(), Error> {
+        match self.recipient {
+            Recipient::Peer(conn_id) => return self.send_to(conn_id, data),
+            Recipient::Console => {
+                console::write(data);
+                return Ok(());
+            }
+            Recipient::All => (),
+        }
+        let envelope = self.events.lock().unwrap().push(data);
         if !self.is_connected() {
-            return Err("蓝牙未连接".into());
+            return Ok(());
+        }
+
+        // 订阅了通知的客户端不需要逐包确认，优先使用通知
+        self.send_notify(Recipient::All, &envelope)?;
+        self.indicate(Recipient::All, &envelope)?;
+        info!("通过BLE发送数据: {:?}", envelope);
+        Ok(())
+    }
+
+    /// 客户端最后收到`last_seen`之后的事件（带信封），不在缓冲区中时返回`SequenceGap`
+    pub fn resync(&self, last_seen: u16) -> Result<Vec<Vec<u8>>, SequenceGap> {
+        self.events.lock().unwrap().since(last_seen)
+    }
+
+    /// 最近一个事件的序号，还没有事件时为None
+    pub fn event_seq(&self) -> Option<u16> {
+        self.events.lock().unwrap().latest()
+    }
+
+    /// 只发送给一个客户端，该客户端已经断开时返回`Error::NotConnected`
+    pub fn send_to(&self, conn_id: ConnectionId, data: &[u8]) -> Result<(), Error> {
+        if !self.has_connection(conn_id) {
+            return Err(Error::NotConnected { conn_id });
+        }
+
+        self.send_notify(Recipient::Peer(conn_id), data)?;
+        self.indicate(Recipient::Peer(conn_id), data)?;
+        info!("通过BLE向conn_id={}发送数据: {:?}", conn_id, data);
+        Ok(())
+    }
+
+    /// 共用同一个蓝牙连接状态，但`send_data`和分段发送都只发给该客户端
+    ///
+    /// 分段发送的过程中客户端断开时，剩下的分段返回`Error::NotConnected`，不影响其他客户端。
+    pub fn for_peer(&self, conn_id: ConnectionId) -> Self {
+        Self {
+            recipient: Recipient::Peer(conn_id),
+            ..self.clone()
+        }
+    }
+
+    /// `send_data`和分段发送都写到串口控制台，用于控制台命令的回复
+    pub fn for_console(&self) -> Self {
+        Self {
+            recipient: Recipient::Console,
+            ..self.clone()
+        }
+    }
+
+    fn has_connection(&self, conn_id: ConnectionId) -> bool {
+        self.state
+            .lock()
+            .is_ok_and(|state| state.connections.find(conn_id).is_some())
+    }
+
+    /// 按所有连接中最小的MTU分段发送，客户端需要自行拼接
+    pub fn send_chunked(&self, data: &[u8]) -> Result<(), Error> {
+        for chunk in data.chunks(self.max_payload()) {
+            self.send_data(chunk)?;
+        }
+        Ok(())
+    }
+
+    /// 边生成边发送的写入器，每攒满一个分段发送一次，结束时需要调用`finish`
+    pub fn chunk_writer(&self) -> ChunkWriter<'_> {
+        let payload = self.max_payload();
+        ChunkWriter {
+            manager: self,
+            buf: Vec::with_capacity(payload),
+            payload,
+            error: None,
+        }
+    }
+
+    /// 把一条消息切成带分段头的分段发送，客户端按消息编号和序号拼接
+    pub fn send_large(&self, data: &[u8]) -> Result<(), Error> {
+        let mut writer = self.large_writer();
+        writer.write(data)?;
+        writer.finish()
+    }
+
+    /// 分多次写入同一条带分段头的消息，结束时需要调用`finish`
+    pub fn large_writer(&self) -> LargeWriter<'_> {
+        let message_id = self.next_message_id.fetch_add(1, Ordering::Relaxed);
+        let payload = self.max_payload().saturating_sub(CHUNK_HEADER_LEN);
+        LargeWriter {
+            manager: self,
+            chunker: Chunker::new(message_id, payload),
+        }
+    }
+
+    /// 设置广播和连接的发射功率（dBm），ESP32-S3从-24dBm开始按3dBm一档
+    pub fn set_tx_power(&self, dbm: i8) -> Result<(), EspError> {
+        let level = ((dbm as i32 + 24) / 3).max(0) as sys::esp_power_level_t;
+        for power_type in [
+            sys::esp_ble_power_type_t_ESP_BLE_PWR_TYPE_DEFAULT,
+            sys::esp_ble_power_type_t_ESP_BLE_PWR_TYPE_ADV,
+        ] {
+            EspError::convert(unsafe { sys::esp_ble_tx_power_set(power_type, level) })?;
+        }
+        info!("蓝牙发射功率: {}dBm", dbm);
+        Ok(())
+    }
+
+    /// 设置配对参数：要求绑定和MITM保护的安全连接，设备没有输入能力，客户端输入固定的配对码
+    ///
+    /// 特征都要求加密，未配对的客户端读写时协议栈回复Insufficient Authentication。绑定信息由协议栈保存在NVS中。
+    pub fn configure_security(&self, passkey: u32) -> Result<(), EspError> {
+        set_security_param(
+            sys::esp_ble_sm_param_t_ESP_BLE_SM_AUTHEN_REQ_MODE,
+            sys::ESP_LE_AUTH_REQ_SC_MITM_BOND as u8,
+        )?;
+        set_security_param(sys::esp_ble_sm_param_t_ESP_BLE_SM_IOCAP_MODE, sys::ESP_IO_CAP_OUT as u8)?;
+        let keys = (sys::ESP_BLE_ENC_KEY_MASK | sys::ESP_BLE_ID_KEY_MASK) as u8;
+        set_security_param(sys::esp_ble_sm_param_t_ESP_BLE_SM_SET_INIT_KEY, keys)?;
+        set_security_param(sys::esp_ble_sm_param_t_ESP_BLE_SM_SET_RSP_KEY, keys)?;
+        set_security_param(sys::esp_ble_sm_param_t_ESP_BLE_SM_MAX_KEY_SIZE, MAX_KEY_SIZE)?;
+        set_security_param(
+            sys::esp_ble_sm_param_t_ESP_BLE_SM_ONLY_ACCEPT_SPECIFIED_SEC_AUTH,
+            sys::ESP_BLE_ONLY_ACCEPT_SPECIFIED_AUTH_ENABLE as u8,
+        )?;
+        self.set_passkey(passkey)
+    }
+
+    /// 修改配对码，只影响之后的配对
+    pub fn set_passkey(&self, passkey: u32) -> Result<(), EspError> {
+        set_security_param(sys::esp_ble_sm_param_t_ESP_BLE_SM_SET_STATIC_PASSKEY, passkey)
+    }
+
+    /// 修改设备名称和用户编号，服务已创建时立即重新配置广播
+    ///
+    /// 名称需要先用`check_device_name`检查。
+    pub fn set_identity(&self, name: &str, user_id: Option<u16>) -> Result<(), EspError> {
+        let created = {
+            let mut state = self.state.lock().unwrap();
+            state.device_name = name.to_string();
+            state.user_id = user_id;
+            state.gatt_if.is_some()
+        };
+        if created {
+            self.configure_advertising()?;
+        }
+        Ok(())
+    }
+
+    /// 修改连接白名单，服务已创建时立即更新控制器并重新开始广播
+    ///
+    /// 地址数量需要先检查不超过`MAX_WHITELIST`。
+    pub fn set_whitelist(&self, enabled: bool, peers: &[BdAddr]) -> Result<(), EspError> {
+        let created = {
+            let mut state = self.state.lock().unwrap();
+            state.whitelist = Whitelist {
+                enabled,
+                peers: peers.to_vec(),
+            };
+            state.gatt_if.is_some()
+        };
+        if created {
+            // 广播使用白名单时控制器不允许修改，先停止广播，停止事件中再按新的策略开始广播
+            if let Err(e) = self.gap().and_then(|gap| gap.stop_advertising()) {
+                warn!("停止广播失败: {:?}", e);
+            }
+            self.load_whitelist()?;
         }
-        
-        self.indicate(data)?;
-        info!("通过BLE发送数据: {:?}", data);
         Ok(())
     }
 
-    pub fn start_data_receiver(&self) {
+    /// 切换省电模式的广播间隔，正在广播时停止广播，停止事件中按新的间隔重新开始
+    pub fn set_slow_advertising(&self, slow: bool) {
+        let restart = {
+            let mut state = self.state.lock().unwrap();
+            let changed = state.slow_advertising != slow;
+            state.slow_advertising = slow;
+            changed && state.gatt_if.is_some() && state.connections.has_room()
+        };
+        if restart {
+            if let Err(e) = self.gap().and_then(|gap| gap.stop_advertising()) {
+                warn!("停止广播失败: {:?}", e);
+            }
+        }
+    }
+
+    /// 白名单是否打开，以及名单中的地址
+    pub fn whitelist(&self) -> (bool, Vec<BdAddr>) {
+        let state = self.state.lock().unwrap();
+        (state.whitelist.enabled, state.whitelist.peers.clone())
+    }
+
+    /// 读取每个连接的信号强度，返回客户端地址和dBm，读取失败或超时的为None
+    ///
+    /// 结果由GAP事件异步返回，最多等待`RSSI_TIMEOUT`。
+    pub fn read_rssi(&self) -> Vec<(BdAddr, Option<i8>)> {
+        let mut state = self.state.lock().unwrap();
+        let peers: Vec<BdAddr> = state.connections.iter().map(|conn| conn.peer).collect();
+        for &peer in &peers {
+            let mut raw = peer.raw();
+            match EspError::convert(unsafe { sys::esp_ble_gap_read_rssi(raw.as_mut_ptr()) }) {
+                Ok(()) => {
+                    state.rssi_pending.insert(peer, None);
+                }
+                Err(e) => warn!("读取 {} 的RSSI失败: {:?}", peer, e),
+            }
+        }
+
+        let deadline = Instant::now() + RSSI_TIMEOUT;
+        while state.running && state.rssi_pending.values().any(Option::is_none) {
+            let now = Instant::now();
+            if now >= deadline {
+                warn!("等待RSSI读数超时");
+                break;
+            }
+            state = self.condvar.wait_timeout(state, deadline - now).unwrap().0;
+        }
+
+        let results = peers
+            .into_iter()
+            .map(|peer| (peer, state.rssi_pending.get(&peer).copied().flatten()))
+            .collect();
+        state.rssi_pending.clear();
+        results
+    }
+
+    /// 切换连接参数预设，已经是该预设时不重复请求
+    ///
+    /// 请求由客户端决定是否接受，被拒绝时在GAP事件中用放宽的参数重试一次。
+    pub fn set_link_mode(&self, conn_id: ConnectionId, mode: LinkMode) {
+        let peer = {
+            let mut state = self.state.lock().unwrap();
+            let Some(conn) = state.connections.find_mut(conn_id) else {
+                return;
+            };
+            // 切换本身也算活动，避免刚切换就因为空闲切回低功耗
+            conn.last_activity = Instant::now();
+            if conn.link_mode == mode {
+                return;
+            }
+            conn.link_mode = mode;
+            conn.link_retried = false;
+            conn.peer
+        };
+        info!("{} 切换连接参数: {:?}", peer, mode);
+        self.request_conn_params(peer, mode.params());
+    }
+
+    /// 切换发出命令的客户端的连接参数，回复发给所有客户端或控制台时不做任何事
+    pub fn set_reply_link_mode(&self, mode: LinkMode) {
+        if let Recipient::Peer(conn_id) = self.recipient {
+            self.set_link_mode(conn_id, mode);
+        }
+    }
+
+    /// 空闲超过`LINK_IDLE_TIMEOUT`的连接切换为低功耗，低功耗的连接有了新的收发后恢复默认参数
+    ///
+    /// 由主循环定期调用。
+    pub fn update_link_modes(&self, now: Instant) {
+        let changes: Vec<(BdAddr, LinkMode)> = {
+            let mut state = self.state.lock().unwrap();
+            state
+                .connections
+                .iter_mut()
+                .filter_map(|conn| {
+                    let idle = now.saturating_duration_since(conn.last_activity) >= LINK_IDLE_TIMEOUT;
+                    let mode = match conn.link_mode {
+                        LinkMode::LowPower if !idle => LinkMode::Balanced,
+                        mode if idle && mode != LinkMode::LowPower => LinkMode::LowPower,
+                        _ => return None,
+                    };
+                    conn.link_mode = mode;
+                    conn.link_retried = false;
+                    Some((conn.peer, mode))
+                })
+                .collect()
+        };
+        for (peer, mode) in changes {
+            info!("{} 切换连接参数: {:?}", peer, mode);
+            self.request_conn_params(peer, mode.params());
+        }
+    }
+
+    fn request_conn_params(&self, peer: BdAddr, params: ConnParams) {
+        let requested = self.gap().and_then(|gap| {
+            gap.set_conn_params_conf(
+                peer,
+                params.min_int_ms,
+                params.max_int_ms,
+                params.latency_ms,
+                params.timeout_ms,
+            )
+        });
+        if let Err(e) = requested {
+            warn!("请求 {} 更新连接参数失败: {:?}", peer, e);
+        }
+    }
+
+    /// 客户端拒绝了连接参数，第一次用放宽的参数重试，之后保持客户端当前的参数
+    fn retry_conn_params(&self, peer: BdAddr, status: BtStatus) {
+        let mode = {
+            let mut state = self.state.lock().unwrap();
+            let Some(conn) = state.connections.find_peer_mut(peer) else {
+                return;
+            };
+            if conn.link_retried {
+                warn!("{} 再次拒绝连接参数{:?}: {:?}，保持当前参数", peer, conn.link_mode, status);
+                return;
+            }
+            conn.link_retried = true;
+            conn.link_mode
+        };
+        warn!("{} 拒绝连接参数{:?}: {:?}，放宽后重试", peer, mode, status);
+        self.request_conn_params(peer, mode.params().relaxed());
+    }
+
+    /// 连接的客户端地址，已经断开时为None
+    pub fn peer_addr(&self, conn_id: ConnectionId) -> Option<BdAddr> {
+        let state = self.state.lock().unwrap();
+        state.connections.find(conn_id).map(|conn| conn.peer)
+    }
+
+    /// 删除所有绑定的设备，返回删除的数量
+    pub fn clear_bonds(&self) -> Result<usize, EspError> {
+        let mut devices = bonded_devices()?;
+        for device in &mut devices {
+            EspError::convert(unsafe { sys::esp_ble_remove_bond_device(device.bd_addr.as_mut_ptr()) })?;
+        }
+        Ok(devices.len())
+    }
+
+    /// 单次指示可携带的最大字节数，按接收方中最小的MTU计算
+    fn max_payload(&self) -> usize {
+        if self.recipient == Recipient::Console {
+            return console::MAX_PAYLOAD;
+        }
+        let state = self.state.lock().unwrap();
+        let mtu = state
+            .connections
+            .iter()
+            .filter(|conn| self.recipient.includes(conn.conn_id))
+            .filter_map(|conn| conn.mtu)
+            .min()
+            .unwrap_or(DEFAULT_MTU);
+        (mtu as usize).saturating_sub(ATT_HEADER_LEN).max(1)
+    }
+
+    /// 之后收到的每条消息都交给`sink`，由它转交给主循环
+    pub fn start_data_receiver(&self, sink: impl Fn(Message) + Send + 'static) {
+        if let Ok(mut queue) = self.received_data.lock() {
+            queue.sink = Some(Box::new(sink));
+        }
         info!("BLE GATT服务器已启动，等待客户端连接...");
     }
 
@@ -754,6 +2265,205 @@ impl BluetoothManager {
     }
 }
 
+/// 协议栈保存的所有绑定设备
+fn bonded_devices() -> Result<Vec<sys::esp_ble_bond_dev_t>, EspError> {
+    let mut count = unsafe { sys::esp_ble_get_bond_device_num() };
+    if count <= 0 {
+        return Ok(Vec::new());
+    }
+    let mut devices = vec![unsafe { std::mem::zeroed::<sys::esp_ble_bond_dev_t>() }; count as usize];
+    EspError::convert(unsafe { sys::esp_ble_get_bond_device_list(&mut count, devices.as_mut_ptr()) })?;
+    devices.truncate(count.max(0) as usize);
+    Ok(devices)
+}
+
+/// 客户端是否已经绑定，读取绑定列表失败时按未绑定处理
+fn is_bonded(peer: BdAddr) -> bool {
+    bonded_devices().is_ok_and(|devices| devices.iter().any(|device| device.bd_addr == *peer.addr()))
+}
+
+/// 开始可连接的广播，白名单生效时只接受名单中的客户端连接
+fn start_advertising(whitelist: &Whitelist, slow: bool) -> Result<(), EspError> {
+    let (adv_int_min, adv_int_max) = if slow {
+        (ADV_INTERVAL_SLOW_MIN, ADV_INTERVAL_SLOW_MAX)
+    } else {
+        (ADV_INTERVAL_MIN, ADV_INTERVAL_MAX)
+    };
+    let mut params = sys::esp_ble_adv_params_t {
+        adv_int_min,
+        adv_int_max,
+        adv_type: sys::esp_ble_adv_type_t_ADV_TYPE_IND,
+        own_addr_type: sys::esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC,
+        peer_addr: [0; 6],
+        peer_addr_type: sys::esp_ble_addr_type_t_BLE_ADDR_TYPE_PUBLIC,
+        channel_map: sys::esp_ble_adv_channel_t_ADV_CHNL_ALL,
+        adv_filter_policy: if whitelist.active() {
+            sys::esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_WLST
+        } else {
+            sys::esp_ble_adv_filter_t_ADV_FILTER_ALLOW_SCAN_ANY_CON_ANY
+        },
+    };
+    EspError::convert(unsafe { sys::esp_ble_gap_start_advertising(&mut params) })
+}
+
+/// 解析`aa:bb:cc:dd:ee:ff`格式的地址，大小写均可
+pub fn parse_addr(text: &str) -> Option<BdAddr> {
+    let mut addr = [0; 6];
+    let mut parts = text.split(':');
+    for byte in &mut addr {
+        let part = parts.next()?;
+        if part.len() != 2 || !part.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return None;
+        }
+        *byte = u8::from_str_radix(part, 16).ok()?;
+    }
+    parts.next().is_none().then(|| BdAddr::from_bytes(addr))
+}
+
+/// 解析写入CCCD的两个字节，只保留`mask`中支持的位
+fn cccd_value(offset: u16, value: &[u8], mask: u16) -> AttResult<u16> {
+    if offset != 0 {
+        return Err(GattStatus::InvalidOffset);
+    }
+    match value {
+        &[low, high] => Ok(u16::from_le_bytes([low, high]) & mask),
+        _ => Err(GattStatus::InvalidAttrLen),
+    }
+}
+
+/// 读取我们的特征或描述符，超过MTU的部分由客户端用长读取按偏移继续读
+fn read_value(state: &mut State, conn_id: ConnectionId, handle: Handle, offset: u16) -> AttResult<Vec<u8>> {
+    let conn = state.connections.find(conn_id);
+    let handle = Some(handle);
+    if handle == state.recv_handle {
+        info!("客户端读取RECV特征值");
+        // 对于RECV特征值，返回空数据
+        read_at(&[], offset)
+    } else if handle == state.ind_handle {
+        info!("客户端读取IND特征值");
+        // 对于IND特征值，返回设备状态
+        read_status(state, conn_id, offset)
+    } else if handle == state.ind_cccd_handle {
+        info!("客户端读取CCCD描述符");
+        // 对于CCCD描述符，返回该连接自己的订阅状态
+        read_at(&conn.map_or(0, |conn| conn.cccd).to_le_bytes(), offset)
+    } else if handle == state.tel_cccd_handle {
+        info!("客户端读取日志CCCD描述符");
+        read_at(&conn.map_or(0, |conn| conn.telemetry_cccd).to_le_bytes(), offset)
+    } else if handle == state.battery.level_handle {
+        info!("客户端读取电量特征值");
+        // 还没有采样时返回错误，不让客户端看到0%
+        read_at(&[state.battery.level.ok_or(GattStatus::Busy)?], offset)
+    } else if handle == state.battery.cccd_handle {
+        info!("客户端读取电量CCCD描述符");
+        read_at(&conn.map_or(0, |conn| conn.battery_cccd).to_le_bytes(), offset)
+    } else {
+        Err(GattStatus::InvalidHandle)
+    }
+}
+
+/// 从偏移处读取较短的值，偏移超过长度时返回Invalid Offset
+fn read_at(value: &[u8], offset: u16) -> AttResult<Vec<u8>> {
+    value.get(offset as usize..).map(<[u8]>::to_vec).ok_or(GattStatus::InvalidOffset)
+}
+
+/// 从偏移处读取状态，偏移为0时重新生成快照，返回不超过一个MTU的内容
+fn read_status(state: &mut State, conn_id: ConnectionId, offset: u16) -> AttResult<Vec<u8>> {
+    let rssi = state.connections.find(conn_id).ok_or(GattStatus::Error)?.rssi;
+    let blob = status_blob(state, rssi);
+    let conn = state.connections.find_mut(conn_id).ok_or(GattStatus::Error)?;
+    let mtu = conn.mtu.unwrap_or(DEFAULT_MTU) as usize;
+    if offset == 0 || conn.read_snapshot.is_none() {
+        conn.read_snapshot = Some(blob);
+    }
+    let snapshot = conn.read_snapshot.as_deref().unwrap_or_default();
+
+    let offset = offset as usize;
+    if offset > snapshot.len() {
+        return Err(GattStatus::InvalidOffset);
+    }
+    // 读取响应的头部占1字节
+    let end = snapshot.len().min(offset + mtu - 1);
+    Ok(snapshot[offset..end].to_vec())
+}
+
+/// 状态：固件版本（主、次、修订各1字节）、连接数、录制数量（u16）、录制状态、
+/// 最近一次捕获的时长总数（u16），之后是该捕获的前一部分时长（各u32），多字节都是小端序；
+/// 最后是读取方最近一次的RSSI（i8），没有读数时为`RSSI_UNKNOWN`
+fn status_blob(state: &State, rssi: Option<i8>) -> Vec<u8> {
+    let status = &state.status;
+    let mut blob = Vec::with_capacity(STATUS_HEADER_LEN + status.durations.len() * 4 + 1);
+    let mut version = env!("CARGO_PKG_VERSION").split('.').map(|part| part.parse::<u8>().unwrap_or(0));
+    blob.extend((0..3).map(|_| version.next().unwrap_or(0)));
+    blob.push(state.connections.len() as u8);
+    blob.extend_from_slice(&status.slot_count.to_le_bytes());
+    blob.push(status.recording_state);
+    blob.extend_from_slice(&status.pulse_count.to_le_bytes());
+    for duration in &status.durations {
+        blob.extend_from_slice(&duration.to_le_bytes());
+    }
+    blob.push(rssi.unwrap_or(RSSI_UNKNOWN) as u8);
+    blob
+}
+
+/// 设备名称不能为空、不能超过扫描响应的长度，也不能包含控制字符
+pub fn check_device_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= MAX_DEVICE_NAME_LEN && !name.chars().any(char::is_control)
+}
+
+/// 硬件版本不能为空、不能超过预留的长度，也不能包含控制字符
+pub fn check_hardware_revision(revision: &str) -> bool {
+    !revision.is_empty() && revision.len() <= MAX_HARDWARE_REVISION_LEN && !revision.chars().any(char::is_control)
+}
+
+/// 广播包：标志、服务UUID和名称，放不下时只放名称的前一部分
+fn adv_payload(name: &str) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(ADV_MAX_LEN);
+    push_ad(&mut payload, AD_TYPE_FLAGS, &[ADV_FLAGS]);
+    push_ad(&mut payload, AD_TYPE_UUID128_COMPLETE, &SERVICE_UUID.to_le_bytes());
+
+    let room = ADV_MAX_LEN - payload.len() - AD_HEADER_LEN;
+    if name.len() <= room {
+        push_ad(&mut payload, AD_TYPE_NAME_COMPLETE, name.as_bytes());
+    } else {
+        // 在字符边界截断，不把多字节字符切开
+        let end = (0..=room).rev().find(|&end| name.is_char_boundary(end)).unwrap_or(0);
+        push_ad(&mut payload, AD_TYPE_NAME_SHORT, &name.as_bytes()[..end]);
+    }
+    payload
+}
+
+/// 扫描响应：完整名称和可选的用户编号
+fn scan_response_payload(name: &str, user_id: Option<u16>) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(ADV_MAX_LEN);
+    push_ad(&mut payload, AD_TYPE_NAME_COMPLETE, name.as_bytes());
+    if let Some(user_id) = user_id {
+        let mut data = [0; MANUFACTURER_DATA_LEN];
+        data[..2].copy_from_slice(&MANUFACTURER_ID.to_le_bytes());
+        data[2..].copy_from_slice(&user_id.to_le_bytes());
+        push_ad(&mut payload, AD_TYPE_MANUFACTURER, &data);
+    }
+    payload
+}
+
+/// 追加一个AD结构：长度（类型加内容）、类型和内容
+fn push_ad(payload: &mut Vec<u8>, ad_type: u8, data: &[u8]) {
+    payload.push(data.len() as u8 + 1);
+    payload.push(ad_type);
+    payload.extend_from_slice(data);
+}
+
+/// 设置一项配对参数
+fn set_security_param<T>(param: sys::esp_ble_sm_param_t, mut value: T) -> Result<(), EspError> {
+    EspError::convert(unsafe {
+        sys::esp_ble_gap_set_security_param(
+            param,
+            &mut value as *mut T as *mut core::ffi::c_void,
+            std::mem::size_of::<T>() as u8,
+        )
+    })
+}
+
 impl Clone for BluetoothManager {
     fn clone(&self) -> Self {
         Self {
@@ -761,15 +2471,134 @@ impl Clone for BluetoothManager {
             gatts: self.gatts.clone(),
             state: self.state.clone(),
             condvar: self.condvar.clone(),
-            is_connected: self.is_connected.clone(),
+            ind_timeout_ms: self.ind_timeout_ms.clone(),
+            next_message_id: self.next_message_id.clone(),
             received_data: self.received_data.clone(),
+            events: self.events.clone(),
+            write_policy: self.write_policy.clone(),
+            recipient: self.recipient,
+        }
+    }
+}
+
+/// 按MTU分段发送的文本写入器，内存中最多保留一个分段
+///
+/// 发送失败后丢弃之后写入的内容，错误在`finish`时返回。
+pub struct ChunkWriter<'a> {
+    manager: &'a BluetoothManager,
+    buf: Vec<u8>,
+    payload: usize,
+    error: Option<Error>,
+}
+
+impl ChunkWriter<'_> {
+    /// 发送剩余的内容，返回发送过程中的第一个错误
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.flush();
+        self.error.map_or(Ok(()), Err)
+    }
+
+    fn flush(&mut self) {
+        if self.error.is_none() && !self.buf.is_empty() {
+            if let Err(e) = self.manager.send_data(&self.buf) {
+                self.error = Some(e);
+            }
         }
+        self.buf.clear();
     }
 }
 
-impl Drop for BluetoothManager {
-    fn drop(&mut self) {
-        info!("正在关闭BLE连接...");
-        info!("BLE连接已关闭");
+impl std::fmt::Write for ChunkWriter<'_> {
+    fn write_str(&mut self, text: &str) -> std::fmt::Result {
+        // 出错后返回Err，让调用方尽早停止生成
+        if self.error.is_some() {
+            return Err(std::fmt::Error);
+        }
+        let mut rest = text.as_bytes();
+        while !rest.is_empty() {
+            let take = rest.len().min(self.payload - self.buf.len());
+            self.buf.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            if self.buf.len() == self.payload {
+                self.flush();
+                if self.error.is_some() {
+                    return Err(std::fmt::Error);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 带分段头的消息写入器，最后一个分段在`finish`时发送
+pub struct LargeWriter<'a> {
+    manager: &'a BluetoothManager,
+    chunker: Chunker,
+}
+
+impl LargeWriter<'_> {
+    /// 追加消息内容，攒满的分段立即发送
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        for chunk in self.chunker.push(data)? {
+            self.manager.send_data(&chunk)?;
+        }
+        Ok(())
+    }
+
+    /// 发送带结束标记的最后一个分段
+    pub fn finish(self) -> Result<(), Error> {
+        self.manager.send_data(&self.chunker.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER_A: BdAddr = BdAddr::from_bytes([0xA0, 0, 0, 0, 0, 1]);
+    const PEER_B: BdAddr = BdAddr::from_bytes([0xB0, 0, 0, 0, 0, 2]);
+    const PEER_C: BdAddr = BdAddr::from_bytes([0xC0, 0, 0, 0, 0, 3]);
+
+    #[test]
+    fn stays_connected_while_another_client_remains() {
+        let now = Instant::now();
+        let mut connections = Connections::default();
+        assert!(!connections.is_connected());
+
+        assert!(connections.add(0, PEER_A, now));
+        assert!(connections.add(1, PEER_B, now));
+        assert_eq!(connections.len(), 2);
+
+        assert_eq!(connections.remove(PEER_A).map(|conn| conn.peer), Some(PEER_A));
+        assert!(connections.is_connected());
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections.find(1).map(|conn| conn.peer), Some(PEER_B));
+        assert!(connections.find(0).is_none());
+
+        connections.remove(PEER_B);
+        assert!(!connections.is_connected());
+    }
+
+    #[test]
+    fn rejects_connections_when_full() {
+        let now = Instant::now();
+        let mut connections = Connections::default();
+        assert!(connections.add(0, PEER_A, now));
+        assert!(connections.add(1, PEER_B, now));
+        assert!(!connections.has_room());
+        assert!(!connections.add(2, PEER_C, now));
+        assert_eq!(connections.len(), MAX_CONNECTIONS);
+
+        connections.remove(PEER_B);
+        assert!(connections.has_room());
+        assert!(connections.add(2, PEER_C, now));
+    }
+
+    #[test]
+    fn removing_unknown_peer_keeps_connections() {
+        let mut connections = Connections::default();
+        connections.add(0, PEER_A, Instant::now());
+        assert!(connections.remove(PEER_B).is_none());
+        assert!(connections.is_connected());
     }
 }
@@ -0,0 +1,184 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use esp_idf_hal::gpio::{AnyIOPin, Input, PinDriver, Pull};
+use esp_idf_svc::sys::EspError;
+
+/// 轮询按键的间隔
+pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// 电平保持不变超过该时长才算按下或松开
+const DEBOUNCE: Duration = Duration::from_millis(30);
+
+/// 松开后在该时长内再次按下算作双击
+const DOUBLE_PRESS_GAP: Duration = Duration::from_millis(400);
+
+/// 按住超过该时长视为长按
+const LONG_PRESS: Duration = Duration::from_secs(2);
+
+/// 按住超过该时长视为一直按住
+const HOLD: Duration = Duration::from_secs(10);
+
+/// 快捷录制和重放使用的录制名称
+pub const QUICK_SLOT: &str = "quick";
+
+/// 双击依次重放的最近录制数量
+pub const RECENT_SLOTS: usize = 3;
+
+/// 去抖后识别出的按键手势
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonEvent {
+    /// 短按一次，松开后等待双击超时才上报
+    Press,
+    DoublePress,
+    /// 按住2秒，不等松开
+    LongPress,
+    /// 长按后继续按住到10秒
+    Hold,
+}
+
+/// 按键手势对应的操作，通过`set_config`修改
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonAction {
+    None,
+    /// 重放`quick`
+    PlayQuick,
+    /// 依次重放最近创建的三个录制
+    CycleRecent,
+    /// 开始录制，完成后保存到`quick`
+    RecordQuick,
+    /// 清除蓝牙绑定并关闭连接白名单
+    ClearBonds,
+    /// 不需要确认，直接恢复出厂设置
+    FactoryReset,
+}
+
+impl ButtonAction {
+    pub const ALL: [ButtonAction; 6] = [
+        Self::None,
+        Self::PlayQuick,
+        Self::CycleRecent,
+        Self::RecordQuick,
+        Self::ClearBonds,
+        Self::FactoryReset,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::PlayQuick => "play_quick",
+            Self::CycleRecent => "cycle_recent",
+            Self::RecordQuick => "record_quick",
+            Self::ClearBonds => "clear_bonds",
+            Self::FactoryReset => "factory_reset",
+        }
+    }
+
+    /// 没有修改过时各手势的操作
+    pub fn default_for(event: ButtonEvent) -> Self {
+        match event {
+            ButtonEvent::Press => Self::PlayQuick,
+            ButtonEvent::DoublePress => Self::CycleRecent,
+            ButtonEvent::LongPress => Self::RecordQuick,
+            ButtonEvent::Hold => Self::ClearBonds,
+        }
+    }
+}
+
+impl fmt::Display for ButtonAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ButtonAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter().find(|action| action.name() == s).ok_or(())
+    }
+}
+
+/// 低电平有效的按键，由定时器线程按`POLL_INTERVAL`轮询去抖
+pub struct Button {
+    driver: PinDriver<'static, AnyIOPin, Input>,
+    /// 最近一次读到的电平，以及它开始的时刻
+    raw: bool,
+    raw_since: Instant,
+    /// 去抖后按下的时刻，松开时为None
+    pressed_since: Option<Instant>,
+    /// 本次按住已经触发的最后一个事件
+    fired: Option<ButtonEvent>,
+    /// 第一次短按松开的时刻，等待第二次按下
+    released_at: Option<Instant>,
+}
+
+impl Button {
+    pub fn new(pin: AnyIOPin) -> Result<Self, EspError> {
+        let mut driver = PinDriver::input(pin)?;
+        driver.set_pull(Pull::Up)?;
+        Ok(Self {
+            driver,
+            raw: false,
+            raw_since: Instant::now(),
+            pressed_since: None,
+            fired: None,
+            released_at: None,
+        })
+    }
+
+    /// 按住2秒返回LongPress，继续按住到10秒再返回Hold，之后松开不再返回事件
+    ///
+    /// 没有触发长按的按下在松开时识别为单击或双击，单击要等双击间隔过去才能确定。
+    pub fn poll(&mut self, now: Instant) -> Option<ButtonEvent> {
+        let raw = self.driver.is_low();
+        if raw != self.raw {
+            self.raw = raw;
+            self.raw_since = now;
+        }
+        let stable = now.duration_since(self.raw_since) >= DEBOUNCE;
+
+        match (self.pressed_since, raw && stable, !raw && stable) {
+            (None, true, _) => {
+                self.pressed_since = Some(now);
+                None
+            }
+            (Some(_), _, true) => self.release(now),
+            (Some(since), _, _) => self.hold(now.duration_since(since)),
+            (None, _, _) => match self.released_at {
+                Some(at) if now.duration_since(at) >= DOUBLE_PRESS_GAP => {
+                    self.released_at = None;
+                    Some(ButtonEvent::Press)
+                }
+                _ => None,
+            },
+        }
+    }
+
+    fn release(&mut self, now: Instant) -> Option<ButtonEvent> {
+        self.pressed_since = None;
+        if self.fired.take().is_some() {
+            return None;
+        }
+        match self.released_at.take() {
+            Some(_) => Some(ButtonEvent::DoublePress),
+            None => {
+                self.released_at = Some(now);
+                None
+            }
+        }
+    }
+
+    fn hold(&mut self, held: Duration) -> Option<ButtonEvent> {
+        let event = match self.fired {
+            None if held >= LONG_PRESS => ButtonEvent::LongPress,
+            Some(ButtonEvent::LongPress) if held >= HOLD => ButtonEvent::Hold,
+            _ => return None,
+        };
+        // 单击后紧接着长按，只算长按
+        self.released_at = None;
+        self.fired = Some(event);
+        Some(event)
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt;
+
+/// 分段头：消息编号（1字节）和分段序号（u16，小端序，最高位表示最后一个分段）
+pub const CHUNK_HEADER_LEN: usize = 3;
+
+/// 分段序号中表示最后一个分段的位
+const LAST_CHUNK: u16 = 0x8000;
+
+/// 分段数量超过了序号能表示的范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyChunks;
+
+impl fmt::Display for TooManyChunks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "消息过长，分段数量超过{}", LAST_CHUNK)
+    }
+}
+
+impl std::error::Error for TooManyChunks {}
+
+/// 把一条消息切成带分段头的分段，客户端按消息编号和序号拼接
+///
+/// 数据可以分多次追加，最后一个分段要等`finish`时才能确定，因此总是留下最多一个分段的数据。
+pub struct Chunker {
+    message_id: u8,
+    index: u16,
+    payload: usize,
+    buf: Vec<u8>,
+}
+
+impl Chunker {
+    /// `payload`为每个分段去掉分段头后的字节数
+    pub fn new(message_id: u8, payload: usize) -> Self {
+        Self {
+            message_id,
+            index: 0,
+            payload: payload.max(1),
+            buf: Vec::new(),
+        }
+    }
+
+    /// 追加数据，返回已经确定不是最后一个的完整分段
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<Vec<u8>>, TooManyChunks> {
+        self.buf.extend_from_slice(data);
+        let mut chunks = Vec::new();
+        // 恰好剩下一个分段时可能是最后一个，留到finish发送
+        while self.buf.len() > self.payload {
+            if self.index >= LAST_CHUNK - 1 {
+                return Err(TooManyChunks);
+            }
+            let rest = self.buf.split_off(self.payload);
+            chunks.push(self.chunk(false));
+            self.buf = rest;
+            self.index += 1;
+        }
+        Ok(chunks)
+    }
+
+    /// 返回最后一个分段，消息为空时只有分段头
+    pub fn finish(mut self) -> Vec<u8> {
+        self.chunk(true)
+    }
+
+    fn chunk(&mut self, last: bool) -> Vec<u8> {
+        let index = if last { self.index | LAST_CHUNK } else { self.index };
+        let mut chunk = Vec::with_capacity(CHUNK_HEADER_LEN + self.buf.len());
+        chunk.push(self.message_id);
+        chunk.extend_from_slice(&index.to_le_bytes());
+        chunk.append(&mut self.buf);
+        chunk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 默认MTU 23减去ATT头和分段头后每个分段的数据字节数
+    const PAYLOAD: usize = 23 - 3 - CHUNK_HEADER_LEN;
+
+    fn split(data: &[u8], payload: usize) -> Vec<Vec<u8>> {
+        let mut chunker = Chunker::new(7, payload);
+        let mut chunks = chunker.push(data).unwrap();
+        chunks.push(chunker.finish());
+        chunks
+    }
+
+    fn header(chunk: &[u8]) -> (u8, u16) {
+        (chunk[0], u16::from_le_bytes([chunk[1], chunk[2]]))
+    }
+
+    fn reassemble(chunks: &[Vec<u8>]) -> Vec<u8> {
+        chunks.iter().flat_map(|chunk| chunk[CHUNK_HEADER_LEN..].to_vec()).collect()
+    }
+
+    #[test]
+    fn one_below_boundary_is_single_chunk() {
+        let data: Vec<u8> = (0..PAYLOAD as u8 - 1).collect();
+        let chunks = split(&data, PAYLOAD);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(header(&chunks[0]), (7, LAST_CHUNK));
+        assert_eq!(reassemble(&chunks), data);
+    }
+
+    #[test]
+    fn exactly_at_boundary_is_single_chunk() {
+        let data: Vec<u8> = (0..PAYLOAD as u8).collect();
+        let chunks = split(&data, PAYLOAD);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].len(), CHUNK_HEADER_LEN + PAYLOAD);
+        assert_eq!(header(&chunks[0]), (7, LAST_CHUNK));
+        assert_eq!(reassemble(&chunks), data);
+    }
+
+    #[test]
+    fn one_above_boundary_splits_in_two() {
+        let data: Vec<u8> = (0..PAYLOAD as u8 + 1).collect();
+        let chunks = split(&data, PAYLOAD);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(header(&chunks[0]), (7, 0));
+        assert_eq!(chunks[0].len(), CHUNK_HEADER_LEN + PAYLOAD);
+        assert_eq!(header(&chunks[1]), (7, 1 | LAST_CHUNK));
+        assert_eq!(chunks[1].len(), CHUNK_HEADER_LEN + 1);
+        assert_eq!(reassemble(&chunks), data);
+    }
+
+    #[test]
+    fn split_pushes_match_single_push() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut chunker = Chunker::new(7, PAYLOAD);
+        let mut chunks = Vec::new();
+        for part in data.chunks(PAYLOAD) {
+            chunks.extend(chunker.push(part).unwrap());
+        }
+        chunks.push(chunker.finish());
+        assert_eq!(chunks, split(&data, PAYLOAD));
+    }
+
+    #[test]
+    fn empty_message_is_header_only() {
+        assert_eq!(split(&[], PAYLOAD), [vec![7, 0x00, 0x80]]);
+    }
+}
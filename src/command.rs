@@ -0,0 +1,171 @@
+use std::fmt;
+
+/// 帧头：操作码、序号和内容长度（u16，小端序）
+pub const FRAME_HEADER_LEN: usize = 4;
+
+/// 操作码从0x80开始，与纯文本命令的ASCII首字节区分
+const OPCODE_MIN: u8 = 0x80;
+
+/// 结构化命令的操作码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    LedSet = 0x81,
+    RecordStart = 0x82,
+    RecordStop = 0x83,
+    Replay = 0x84,
+    List = 0x85,
+    Delete = 0x86,
+    Status = 0x87,
+}
+
+impl Opcode {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0x81 => Some(Self::LedSet),
+            0x82 => Some(Self::RecordStart),
+            0x83 => Some(Self::RecordStop),
+            0x84 => Some(Self::Replay),
+            0x85 => Some(Self::List),
+            0x86 => Some(Self::Delete),
+            0x87 => Some(Self::Status),
+            _ => None,
+        }
+    }
+}
+
+/// 响应中的状态码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok = 0x00,
+    UnknownOpcode = 0x01,
+    /// 帧头不完整，或内容比长度字段短
+    ShortFrame = 0x02,
+    InvalidPayload = 0x03,
+    NotFound = 0x04,
+    Busy = 0x05,
+    Failed = 0x06,
+    /// LED或红外的RMT驱动出错
+    Rmt = 0x07,
+    /// 蓝牙协议栈出错或正在关闭
+    Ble = 0x08,
+    /// NVS读写失败或空间不足
+    Storage = 0x09,
+    NotConnected = 0x0A,
+    /// 客户端没有及时确认指示
+    Timeout = 0x0B,
+}
+
+/// 解析出的请求
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// 内容为红、绿、蓝三个字节
+    LedSet { red: u8, green: u8, blue: u8 },
+    /// 内容为空或录制名称，指定名称时录制完成后直接保存
+    RecordStart(Option<String>),
+    RecordStop,
+    Replay(String),
+    List,
+    Delete(String),
+    Status,
+}
+
+impl Request {
+    pub fn opcode(&self) -> Opcode {
+        match self {
+            Self::LedSet { .. } => Opcode::LedSet,
+            Self::RecordStart(_) => Opcode::RecordStart,
+            Self::RecordStop => Opcode::RecordStop,
+            Self::Replay(_) => Opcode::Replay,
+            Self::List => Opcode::List,
+            Self::Delete(_) => Opcode::Delete,
+            Self::Status => Opcode::Status,
+        }
+    }
+}
+
+/// 一个完整的请求帧
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub seq: u8,
+    pub request: Request,
+}
+
+/// 无法处理的帧，响应时带回操作码和序号
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameError {
+    pub opcode: u8,
+    pub seq: u8,
+    pub status: Status,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.status {
+            Status::UnknownOpcode => "未知的操作码",
+            Status::ShortFrame => "帧不完整",
+            _ => "内容无效",
+        };
+        write!(f, "{}（操作码{:#04x}，序号{}）", reason, self.opcode, self.seq)
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// 写入的数据是否为结构化命令，纯文本命令总是以ASCII字符开始
+pub fn is_frame(data: &[u8]) -> bool {
+    data.first().is_some_and(|&byte| byte >= OPCODE_MIN)
+}
+
+/// 解析一次写入中的所有帧，帧不完整时后面的数据一并丢弃
+pub fn parse(data: &[u8]) -> Vec<Result<Frame, FrameError>> {
+    let mut frames = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (opcode, seq) = (rest[0], rest.get(1).copied().unwrap_or(0));
+        let error = |status| FrameError { opcode, seq, status };
+        let Some(&[_, _, len_low, len_high]) = rest.get(..FRAME_HEADER_LEN) else {
+            frames.push(Err(error(Status::ShortFrame)));
+            break;
+        };
+        let len = u16::from_le_bytes([len_low, len_high]) as usize;
+        let Some(payload) = rest.get(FRAME_HEADER_LEN..FRAME_HEADER_LEN + len) else {
+            frames.push(Err(error(Status::ShortFrame)));
+            break;
+        };
+        rest = &rest[FRAME_HEADER_LEN + len..];
+        let request = match Opcode::from_byte(opcode) {
+            Some(opcode) => parse_request(opcode, payload).ok_or(error(Status::InvalidPayload)),
+            None => Err(error(Status::UnknownOpcode)),
+        };
+        frames.push(request.map(|request| Frame { seq, request }));
+    }
+    frames
+}
+
+fn parse_request(opcode: Opcode, payload: &[u8]) -> Option<Request> {
+    let text = || std::str::from_utf8(payload).ok().filter(|text| !text.is_empty()).map(str::to_string);
+    let request = match (opcode, payload) {
+        (Opcode::LedSet, &[red, green, blue]) => Request::LedSet { red, green, blue },
+        (Opcode::RecordStart, []) => Request::RecordStart(None),
+        (Opcode::RecordStart, _) => Request::RecordStart(Some(text()?)),
+        (Opcode::RecordStop, []) => Request::RecordStop,
+        (Opcode::Replay, _) => Request::Replay(text()?),
+        (Opcode::List, []) => Request::List,
+        (Opcode::Delete, _) => Request::Delete(text()?),
+        (Opcode::Status, []) => Request::Status,
+        _ => return None,
+    };
+    Some(request)
+}
+
+/// 响应帧：操作码、序号、状态码、内容长度（u16，小端序）和内容
+///
+/// 内容超过u16上限时截断。
+pub fn response(opcode: u8, seq: u8, status: Status, payload: &[u8]) -> Vec<u8> {
+    let payload = &payload[..payload.len().min(u16::MAX as usize)];
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + 1 + payload.len());
+    frame.extend_from_slice(&[opcode, seq, status as u8]);
+    frame.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
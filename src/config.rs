@@ -0,0 +1,592 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use esp_idf_svc::sys::EspError;
+
+use crate::bluetooth::{check_device_name, INDICATION_TIMEOUT_RANGE_MS};
+use crate::button::{ButtonAction, ButtonEvent};
+use crate::http::{check_token, DEFAULT_MAX_BODY, MAX_BODY_RANGE};
+use crate::led::{Timing, BRIGHTNESS_RANGE};
+use crate::mqtt::{check_credential, check_device, check_url};
+use crate::powersave::{DEFAULT_IDLE_S, IDLE_RANGE_S};
+use crate::rmt::TX_CHANNELS;
+use crate::settings::Settings;
+use crate::wifi::{check_password, check_ssid};
+
+/// 板载RGB LED接在GPIO48
+pub const DEFAULT_LED_PIN: u8 = 48;
+/// 红外发射管接在GPIO17
+pub const DEFAULT_IR_TX_PIN: u8 = 17;
+/// 红外接收头接在GPIO21
+pub const DEFAULT_IR_RX_PIN: u8 = 21;
+/// LED发射通道不分频，12.5ns分辨率
+pub const DEFAULT_LED_CLOCK_DIVIDER: u8 = 1;
+pub const LED_CLOCK_DIVIDER_RANGE: RangeInclusive<u8> = 1..=8;
+/// 10ms没有跳变即认为一帧结束
+pub const DEFAULT_IDLE_THRESHOLD_US: u32 = 10_000;
+/// 接收按1µs计数，空闲阈值是16位的tick数
+pub const IDLE_THRESHOLD_RANGE_US: RangeInclusive<u32> = 2_000..=30_000;
+/// 蓝牙发射功率（dBm），ESP32-S3按3dBm一档
+pub const DEFAULT_BLE_TX_POWER_DBM: i8 = 9;
+pub const BLE_TX_POWER_RANGE_DBM: RangeInclusive<i8> = -24..=18;
+
+/// 可以通过`get_config`/`set_config`读写的配置项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigKey {
+    LedPin,
+    IrTxPin,
+    IrRxPin,
+    LedClockDivider,
+    RmtLedBlocks,
+    RmtIrBlocks,
+    IdleThreshold,
+    DeviceName,
+    LedBrightness,
+    LedTiming,
+    BleTxPower,
+    IndicationTimeout,
+    ButtonPress,
+    ButtonDoublePress,
+    ButtonLongPress,
+    ButtonHold,
+    PowerSave,
+    SleepIdle,
+    WifiSsid,
+    WifiPassword,
+    MqttUrl,
+    MqttUsername,
+    MqttPassword,
+    MqttDevice,
+    HttpToken,
+    HttpMaxBody,
+}
+
+impl ConfigKey {
+    pub const ALL: [ConfigKey; 26] = [
+        Self::LedPin,
+        Self::IrTxPin,
+        Self::IrRxPin,
+        Self::LedClockDivider,
+        Self::RmtLedBlocks,
+        Self::RmtIrBlocks,
+        Self::IdleThreshold,
+        Self::DeviceName,
+        Self::LedBrightness,
+        Self::LedTiming,
+        Self::BleTxPower,
+        Self::IndicationTimeout,
+        Self::ButtonPress,
+        Self::ButtonDoublePress,
+        Self::ButtonLongPress,
+        Self::ButtonHold,
+        Self::PowerSave,
+        Self::SleepIdle,
+        Self::WifiSsid,
+        Self::WifiPassword,
+        Self::MqttUrl,
+        Self::MqttUsername,
+        Self::MqttPassword,
+        Self::MqttDevice,
+        Self::HttpToken,
+        Self::HttpMaxBody,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::LedPin => "led_pin",
+            Self::IrTxPin => "ir_tx_pin",
+            Self::IrRxPin => "ir_rx_pin",
+            Self::LedClockDivider => "led_clk_div",
+            Self::RmtLedBlocks => "rmt_led_blocks",
+            Self::RmtIrBlocks => "rmt_ir_blocks",
+            Self::IdleThreshold => "idle_us",
+            Self::DeviceName => "name",
+            Self::LedBrightness => "brightness",
+            Self::LedTiming => "timing",
+            Self::BleTxPower => "ble_tx_power",
+            Self::IndicationTimeout => "ind_timeout_ms",
+            Self::ButtonPress => "btn_press",
+            Self::ButtonDoublePress => "btn_double",
+            Self::ButtonLongPress => "btn_long",
+            Self::ButtonHold => "btn_hold",
+            Self::PowerSave => "power_save",
+            Self::SleepIdle => "sleep_idle_s",
+            Self::WifiSsid => "wifi_ssid",
+            Self::WifiPassword => "wifi_pass",
+            Self::MqttUrl => "mqtt_url",
+            Self::MqttUsername => "mqtt_user",
+            Self::MqttPassword => "mqtt_pass",
+            Self::MqttDevice => "mqtt_device",
+            Self::HttpToken => "http_token",
+            Self::HttpMaxBody => "http_max_body",
+        }
+    }
+
+    /// 引脚、RMT通道、Wi-Fi、MQTT和HTTP在启动时创建驱动，修改后重启才生效；其余立即生效
+    pub fn apply(&self) -> Apply {
+        match self {
+            Self::LedPin
+            | Self::IrTxPin
+            | Self::IrRxPin
+            | Self::LedClockDivider
+            | Self::RmtLedBlocks
+            | Self::RmtIrBlocks
+            | Self::WifiSsid
+            | Self::WifiPassword
+            | Self::MqttUrl
+            | Self::MqttUsername
+            | Self::MqttPassword
+            | Self::MqttDevice
+            | Self::HttpToken
+            | Self::HttpMaxBody => Apply::AfterRestart,
+            _ => Apply::Now,
+        }
+    }
+
+    /// 读取时不显示值的配置项
+    pub fn secret(&self) -> bool {
+        matches!(self, Self::WifiPassword | Self::MqttPassword | Self::HttpToken)
+    }
+}
+
+impl FromStr for ConfigKey {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|key| key.name() == s)
+            .ok_or_else(|| ConfigError::UnknownKey(s.to_string()))
+    }
+}
+
+/// 修改什么时候生效
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Apply {
+    Now,
+    AfterRestart,
+}
+
+/// 配置项的值无效
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    UnknownKey(String),
+    /// 无法解析或超出范围
+    InvalidValue(ConfigKey),
+    /// 引脚不可用：GPIO0是BOOT按键，GPIO19/20是USB，GPIO22~32不存在或接了Flash
+    InvalidPin(u8),
+    /// 两个功能使用了同一个引脚
+    PinConflict(ConfigKey, ConfigKey),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownKey(key) => write!(f, "未知的配置项: {}", key),
+            Self::InvalidValue(key) => write!(f, "配置项{}的值无效", key.name()),
+            Self::InvalidPin(pin) => write!(f, "GPIO{}不可用", pin),
+            Self::PinConflict(a, b) => write!(f, "{}和{}使用了同一个引脚", a.name(), b.name()),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// 启动时从NVS读取的硬件和蓝牙配置，未保存过的项使用默认值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub led_pin: u8,
+    pub ir_tx_pin: u8,
+    pub ir_rx_pin: u8,
+    pub led_clock_divider: u8,
+    pub rmt_led_blocks: u8,
+    pub rmt_ir_blocks: u8,
+    pub idle_threshold_us: u32,
+    pub device_name: String,
+    pub led_brightness: u8,
+    pub led_timing: Timing,
+    pub ble_tx_power_dbm: i8,
+    pub indication_timeout_ms: u32,
+    pub button_press: ButtonAction,
+    pub button_double_press: ButtonAction,
+    pub button_long_press: ButtonAction,
+    pub button_hold: ButtonAction,
+    pub power_save: bool,
+    pub sleep_idle_s: u32,
+    /// 为空时不使用Wi-Fi
+    pub wifi_ssid: String,
+    pub wifi_password: String,
+    /// 为空时不连接MQTT
+    pub mqtt_url: String,
+    pub mqtt_username: String,
+    pub mqtt_password: String,
+    /// 为空时由设备名称生成
+    pub mqtt_device: String,
+    /// 为空时不启动HTTP服务
+    pub http_token: String,
+    pub http_max_body: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            led_pin: DEFAULT_LED_PIN,
+            ir_tx_pin: DEFAULT_IR_TX_PIN,
+            ir_rx_pin: DEFAULT_IR_RX_PIN,
+            led_clock_divider: DEFAULT_LED_CLOCK_DIVIDER,
+            rmt_led_blocks: 1,
+            rmt_ir_blocks: 1,
+            idle_threshold_us: DEFAULT_IDLE_THRESHOLD_US,
+            device_name: crate::bluetooth::DEFAULT_DEVICE_NAME.to_string(),
+            led_brightness: crate::led::DEFAULT_BRIGHTNESS_PERCENT,
+            led_timing: Timing::default(),
+            ble_tx_power_dbm: DEFAULT_BLE_TX_POWER_DBM,
+            indication_timeout_ms: crate::bluetooth::DEFAULT_INDICATION_TIMEOUT_MS,
+            button_press: ButtonAction::default_for(ButtonEvent::Press),
+            button_double_press: ButtonAction::default_for(ButtonEvent::DoublePress),
+            button_long_press: ButtonAction::default_for(ButtonEvent::LongPress),
+            button_hold: ButtonAction::default_for(ButtonEvent::Hold),
+            power_save: false,
+            sleep_idle_s: DEFAULT_IDLE_S,
+            wifi_ssid: String::new(),
+            wifi_password: String::new(),
+            mqtt_url: String::new(),
+            mqtt_username: String::new(),
+            mqtt_password: String::new(),
+            mqtt_device: String::new(),
+            http_token: String::new(),
+            http_max_body: DEFAULT_MAX_BODY,
+        }
+    }
+}
+
+impl Config {
+    /// 从设置中读取，无效的项记录日志后换成默认值
+    pub fn load(settings: &Settings) -> Self {
+        let saved = Self {
+            led_pin: settings.led_pin(),
+            ir_tx_pin: settings.ir_tx_pin(),
+            ir_rx_pin: settings.ir_rx_pin(),
+            led_clock_divider: settings.led_clock_divider(),
+            rmt_led_blocks: settings.rmt_led_blocks(),
+            rmt_ir_blocks: settings.rmt_ir_blocks(),
+            idle_threshold_us: settings.idle_threshold_us(),
+            device_name: settings.device_name(),
+            led_brightness: settings.led_brightness(),
+            led_timing: settings.led_timing(),
+            ble_tx_power_dbm: settings.ble_tx_power(),
+            indication_timeout_ms: settings.indication_timeout_ms(),
+            button_press: settings.button_action(ButtonEvent::Press),
+            button_double_press: settings.button_action(ButtonEvent::DoublePress),
+            button_long_press: settings.button_action(ButtonEvent::LongPress),
+            button_hold: settings.button_action(ButtonEvent::Hold),
+            power_save: settings.power_save(),
+            sleep_idle_s: settings.sleep_idle_s(),
+            wifi_ssid: settings.wifi_ssid(),
+            wifi_password: settings.wifi_password(),
+            mqtt_url: settings.mqtt_url(),
+            mqtt_username: settings.mqtt_username(),
+            mqtt_password: settings.mqtt_password(),
+            mqtt_device: settings.mqtt_device(),
+            http_token: settings.http_token(),
+            http_max_body: settings.http_max_body(),
+        };
+        // 保存的值经过和set相同的检查，引脚在全部读取后再检查冲突
+        let mut config = Self::default();
+        for key in ConfigKey::ALL {
+            let value = saved.get(key);
+            if let Err(e) = config.assign(key, &value) {
+                log::warn!("{}（{}），使用默认值{}", e, saved.display(key), config.display(key));
+            }
+        }
+        if let Err(e) = config.validate() {
+            let defaults = Self::default();
+            log::warn!("{}，引脚恢复为默认值", e);
+            config.led_pin = defaults.led_pin;
+            config.ir_tx_pin = defaults.ir_tx_pin;
+            config.ir_rx_pin = defaults.ir_rx_pin;
+        }
+        config
+    }
+
+    /// 配置项的值，格式与`set`接受的相同
+    pub fn get(&self, key: ConfigKey) -> String {
+        match key {
+            ConfigKey::LedPin => self.led_pin.to_string(),
+            ConfigKey::IrTxPin => self.ir_tx_pin.to_string(),
+            ConfigKey::IrRxPin => self.ir_rx_pin.to_string(),
+            ConfigKey::LedClockDivider => self.led_clock_divider.to_string(),
+            ConfigKey::RmtLedBlocks => self.rmt_led_blocks.to_string(),
+            ConfigKey::RmtIrBlocks => self.rmt_ir_blocks.to_string(),
+            ConfigKey::IdleThreshold => self.idle_threshold_us.to_string(),
+            ConfigKey::DeviceName => self.device_name.clone(),
+            ConfigKey::LedBrightness => self.led_brightness.to_string(),
+            ConfigKey::LedTiming => self.led_timing.to_string(),
+            ConfigKey::BleTxPower => self.ble_tx_power_dbm.to_string(),
+            ConfigKey::IndicationTimeout => self.indication_timeout_ms.to_string(),
+            ConfigKey::ButtonPress => self.button_press.to_string(),
+            ConfigKey::ButtonDoublePress => self.button_double_press.to_string(),
+            ConfigKey::ButtonLongPress => self.button_long_press.to_string(),
+            ConfigKey::ButtonHold => self.button_hold.to_string(),
+            ConfigKey::PowerSave => if self.power_save { "on" } else { "off" }.to_string(),
+            ConfigKey::SleepIdle => self.sleep_idle_s.to_string(),
+            ConfigKey::WifiSsid => self.wifi_ssid.clone(),
+            ConfigKey::WifiPassword => self.wifi_password.clone(),
+            ConfigKey::MqttUrl => self.mqtt_url.clone(),
+            ConfigKey::MqttUsername => self.mqtt_username.clone(),
+            ConfigKey::MqttPassword => self.mqtt_password.clone(),
+            ConfigKey::MqttDevice => self.mqtt_device.clone(),
+            ConfigKey::HttpToken => self.http_token.clone(),
+            ConfigKey::HttpMaxBody => self.http_max_body.to_string(),
+        }
+    }
+
+    /// 解析并检查一个配置项，无效时不修改配置
+    pub fn set(&mut self, key: ConfigKey, value: &str) -> Result<Apply, ConfigError> {
+        let mut config = self.clone();
+        config.assign(key, value)?;
+        config.validate()?;
+        *self = config;
+        Ok(key.apply())
+    }
+
+    /// 解析一个配置项，不检查引脚冲突
+    fn assign(&mut self, key: ConfigKey, value: &str) -> Result<(), ConfigError> {
+        let invalid = || ConfigError::InvalidValue(key);
+        match key {
+            ConfigKey::LedPin => self.led_pin = value.parse().map_err(|_| invalid())?,
+            ConfigKey::IrTxPin => self.ir_tx_pin = value.parse().map_err(|_| invalid())?,
+            ConfigKey::IrRxPin => self.ir_rx_pin = value.parse().map_err(|_| invalid())?,
+            ConfigKey::LedClockDivider => {
+                self.led_clock_divider = parse_in(value, LED_CLOCK_DIVIDER_RANGE).ok_or_else(invalid)?
+            }
+            ConfigKey::RmtLedBlocks => self.rmt_led_blocks = parse_in(value, 1..=TX_CHANNELS as u8).ok_or_else(invalid)?,
+            ConfigKey::RmtIrBlocks => self.rmt_ir_blocks = parse_in(value, 1..=TX_CHANNELS as u8).ok_or_else(invalid)?,
+            ConfigKey::IdleThreshold => {
+                self.idle_threshold_us = parse_in(value, IDLE_THRESHOLD_RANGE_US).ok_or_else(invalid)?
+            }
+            ConfigKey::DeviceName if check_device_name(value) => self.device_name = value.to_string(),
+            ConfigKey::DeviceName => return Err(invalid()),
+            ConfigKey::LedBrightness => self.led_brightness = parse_in(value, BRIGHTNESS_RANGE).ok_or_else(invalid)?,
+            ConfigKey::LedTiming => self.led_timing = value.parse().map_err(|_| invalid())?,
+            ConfigKey::BleTxPower => {
+                self.ble_tx_power_dbm = parse_in(value, BLE_TX_POWER_RANGE_DBM)
+                    .filter(|dbm| dbm % 3 == 0)
+                    .ok_or_else(invalid)?
+            }
+            ConfigKey::IndicationTimeout => {
+                self.indication_timeout_ms = parse_in(value, INDICATION_TIMEOUT_RANGE_MS).ok_or_else(invalid)?
+            }
+            ConfigKey::ButtonPress => self.button_press = value.parse().map_err(|_| invalid())?,
+            ConfigKey::ButtonDoublePress => self.button_double_press = value.parse().map_err(|_| invalid())?,
+            ConfigKey::ButtonLongPress => self.button_long_press = value.parse().map_err(|_| invalid())?,
+            ConfigKey::ButtonHold => self.button_hold = value.parse().map_err(|_| invalid())?,
+            ConfigKey::PowerSave => {
+                self.power_save = match value {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(invalid()),
+                }
+            }
+            ConfigKey::SleepIdle => self.sleep_idle_s = parse_in(value, IDLE_RANGE_S).ok_or_else(invalid)?,
+            ConfigKey::WifiSsid if check_ssid(value) => self.wifi_ssid = value.to_string(),
+            ConfigKey::WifiPassword if check_password(value) => self.wifi_password = value.to_string(),
+            ConfigKey::WifiSsid | ConfigKey::WifiPassword => return Err(invalid()),
+            ConfigKey::MqttUrl if value.is_empty() || check_url(value) => self.mqtt_url = value.to_string(),
+            ConfigKey::MqttUsername if check_credential(value) => self.mqtt_username = value.to_string(),
+            ConfigKey::MqttPassword if check_credential(value) => self.mqtt_password = value.to_string(),
+            ConfigKey::MqttDevice if check_device(value) => self.mqtt_device = value.to_string(),
+            ConfigKey::MqttUrl | ConfigKey::MqttUsername | ConfigKey::MqttPassword | ConfigKey::MqttDevice => {
+                return Err(invalid())
+            }
+            ConfigKey::HttpToken if check_token(value) => self.http_token = value.to_string(),
+            ConfigKey::HttpToken => return Err(invalid()),
+            ConfigKey::HttpMaxBody => self.http_max_body = parse_in(value, MAX_BODY_RANGE).ok_or_else(invalid)?,
+        }
+        Ok(())
+    }
+
+    /// 检查引脚是否可用、是否互相冲突
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let pins = [
+            (ConfigKey::LedPin, self.led_pin),
+            (ConfigKey::IrTxPin, self.ir_tx_pin),
+            (ConfigKey::IrRxPin, self.ir_rx_pin),
+        ];
+        for (index, &(key, pin)) in pins.iter().enumerate() {
+            if !valid_pin(pin) {
+                return Err(ConfigError::InvalidPin(pin));
+            }
+            if let Some(&(other, _)) = pins[index + 1..].iter().find(|&&(_, other)| other == pin) {
+                return Err(ConfigError::PinConflict(key, other));
+            }
+        }
+        Ok(())
+    }
+
+    /// 把一个配置项保存到NVS
+    pub fn save(&self, settings: &mut Settings, key: ConfigKey) -> Result<(), EspError> {
+        match key {
+            ConfigKey::LedPin => settings.set_led_pin(self.led_pin),
+            ConfigKey::IrTxPin => settings.set_ir_tx_pin(self.ir_tx_pin),
+            ConfigKey::IrRxPin => settings.set_ir_rx_pin(self.ir_rx_pin),
+            ConfigKey::LedClockDivider => settings.set_led_clock_divider(self.led_clock_divider),
+            ConfigKey::RmtLedBlocks => settings.set_rmt_led_blocks(self.rmt_led_blocks),
+            ConfigKey::RmtIrBlocks => settings.set_rmt_ir_blocks(self.rmt_ir_blocks),
+            ConfigKey::IdleThreshold => settings.set_idle_threshold_us(self.idle_threshold_us),
+            ConfigKey::DeviceName => settings.set_device_name(&self.device_name),
+            ConfigKey::LedBrightness => settings.set_led_brightness(self.led_brightness),
+            ConfigKey::LedTiming => settings.set_led_timing(self.led_timing),
+            ConfigKey::BleTxPower => settings.set_ble_tx_power(self.ble_tx_power_dbm),
+            ConfigKey::IndicationTimeout => settings.set_indication_timeout_ms(self.indication_timeout_ms),
+            ConfigKey::ButtonPress => settings.set_button_action(ButtonEvent::Press, self.button_press),
+            ConfigKey::ButtonDoublePress => settings.set_button_action(ButtonEvent::DoublePress, self.button_double_press),
+            ConfigKey::ButtonLongPress => settings.set_button_action(ButtonEvent::LongPress, self.button_long_press),
+            ConfigKey::ButtonHold => settings.set_button_action(ButtonEvent::Hold, self.button_hold),
+            ConfigKey::PowerSave => settings.set_power_save(self.power_save),
+            ConfigKey::SleepIdle => settings.set_sleep_idle_s(self.sleep_idle_s),
+            ConfigKey::WifiSsid => settings.set_wifi_ssid(&self.wifi_ssid),
+            ConfigKey::WifiPassword => settings.set_wifi_password(&self.wifi_password),
+            ConfigKey::MqttUrl => settings.set_mqtt_url(&self.mqtt_url),
+            ConfigKey::MqttUsername => settings.set_mqtt_username(&self.mqtt_username),
+            ConfigKey::MqttPassword => settings.set_mqtt_password(&self.mqtt_password),
+            ConfigKey::MqttDevice => settings.set_mqtt_device(&self.mqtt_device),
+            ConfigKey::HttpToken => settings.set_http_token(&self.http_token),
+            ConfigKey::HttpMaxBody => settings.set_http_max_body(self.http_max_body),
+        }
+    }
+
+    /// 回复和日志中显示的值，密码只显示是否设置
+    pub fn display(&self, key: ConfigKey) -> String {
+        match self.get(key) {
+            value if key.secret() && !value.is_empty() => "***".to_string(),
+            value => value,
+        }
+    }
+
+    /// 按键手势对应的操作
+    pub fn button_action(&self, event: ButtonEvent) -> ButtonAction {
+        match event {
+            ButtonEvent::Press => self.button_press,
+            ButtonEvent::DoublePress => self.button_double_press,
+            ButtonEvent::LongPress => self.button_long_press,
+            ButtonEvent::Hold => self.button_hold,
+        }
+    }
+}
+
+/// GPIO0是BOOT按键，GPIO19/20是USB，GPIO22~32不存在或接了Flash
+fn valid_pin(pin: u8) -> bool {
+    matches!(pin, 1..=18 | 21 | 33..=48)
+}
+
+fn parse_in<T: FromStr + PartialOrd>(value: &str, range: RangeInclusive<T>) -> Option<T> {
+    value.parse().ok().filter(|value| range.contains(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 每个配置项的一个有效值（与默认值不同）和一个无效值
+    fn samples(key: ConfigKey) -> (&'static str, &'static str) {
+        match key {
+            ConfigKey::LedPin => ("4", "19"),
+            ConfigKey::IrTxPin => ("5", "0"),
+            ConfigKey::IrRxPin => ("6", "26"),
+            ConfigKey::LedClockDivider => ("4", "9"),
+            ConfigKey::RmtLedBlocks => ("2", "0"),
+            ConfigKey::RmtIrBlocks => ("3", "5"),
+            ConfigKey::IdleThreshold => ("20000", "1999"),
+            ConfigKey::DeviceName => ("Living Room", ""),
+            ConfigKey::LedBrightness => ("50", "101"),
+            ConfigKey::LedTiming => ("300:900:600:650:100", "300:900:600:650:401"),
+            ConfigKey::BleTxPower => ("-3", "21"),
+            ConfigKey::IndicationTimeout => ("2500", "99"),
+            ConfigKey::ButtonPress => ("none", "explode"),
+            ConfigKey::ButtonDoublePress => ("record_quick", ""),
+            ConfigKey::ButtonLongPress => ("factory_reset", "Record_Quick"),
+            ConfigKey::ButtonHold => ("play_quick", "hold"),
+            ConfigKey::PowerSave => ("on", "yes"),
+            ConfigKey::SleepIdle => ("600", "3601"),
+            ConfigKey::WifiSsid => ("home", "a-very-long-network-name-over-32b"),
+            ConfigKey::WifiPassword => ("correct horse", "short"),
+            ConfigKey::MqttUrl => ("mqtt://broker.local:1883", "http://broker.local"),
+            ConfigKey::MqttUsername => ("ir", "bad\nname"),
+            ConfigKey::MqttPassword => ("secret", "bad\tpassword"),
+            ConfigKey::MqttDevice => ("remote", "home/remote"),
+            ConfigKey::HttpToken => ("s3cret-token", "has space"),
+            ConfigKey::HttpMaxBody => ("4096", "1023"),
+        }
+    }
+
+    #[test]
+    fn every_key_round_trips_through_settings() {
+        let mut settings = Settings::in_memory();
+        let mut config = Config::default();
+        for key in ConfigKey::ALL {
+            let (valid, _) = samples(key);
+            assert_ne!(config.get(key), valid, "{}", key.name());
+            assert_eq!(config.set(key, valid), Ok(key.apply()), "{}", key.name());
+            assert_eq!(config.get(key), valid);
+            config.save(&mut settings, key).unwrap();
+
+            // 保存后重新读取，得到相同的值
+            assert_eq!(Config::load(&settings).get(key), valid, "{}", key.name());
+        }
+        assert_eq!(Config::load(&settings), config);
+    }
+
+    #[test]
+    fn every_key_rejects_invalid_values() {
+        for key in ConfigKey::ALL {
+            let (_, invalid) = samples(key);
+            let mut config = Config::default();
+            let expected = match key {
+                ConfigKey::LedPin | ConfigKey::IrTxPin | ConfigKey::IrRxPin => {
+                    ConfigError::InvalidPin(invalid.parse().unwrap())
+                }
+                _ => ConfigError::InvalidValue(key),
+            };
+            assert_eq!(config.set(key, invalid), Err(expected), "{}", key.name());
+            // 无效的值不修改配置
+            assert_eq!(config, Config::default());
+        }
+    }
+
+    #[test]
+    fn validate_rejects_unusable_and_shared_pins() {
+        assert_eq!(Config::default().validate(), Ok(()));
+
+        for pin in [0, 19, 20, 22, 32, 49] {
+            let config = Config {
+                led_pin: pin,
+                ..Config::default()
+            };
+            assert_eq!(config.validate(), Err(ConfigError::InvalidPin(pin)));
+        }
+
+        let config = Config {
+            ir_rx_pin: DEFAULT_IR_TX_PIN,
+            ..Config::default()
+        };
+        assert_eq!(config.validate(), Err(ConfigError::PinConflict(ConfigKey::IrTxPin, ConfigKey::IrRxPin)));
+
+        let mut config = Config::default();
+        assert_eq!(
+            config.set(ConfigKey::LedPin, &DEFAULT_IR_RX_PIN.to_string()),
+            Err(ConfigError::PinConflict(ConfigKey::LedPin, ConfigKey::IrRxPin))
+        );
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn load_replaces_invalid_saved_values_with_defaults() {
+        let settings = Settings::in_memory();
+        settings.set_led_clock_divider(9).unwrap();
+        settings.set_ble_tx_power(10).unwrap();
+        settings.set_led_pin(DEFAULT_IR_TX_PIN).unwrap();
+        assert_eq!(Config::load(&settings), Config::default());
+    }
+}
@@ -0,0 +1,112 @@
+use std::io::Write;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use esp_idf_svc::hal::delay::BLOCK;
+use esp_idf_svc::sys::{self, EspError, ESP_FAIL};
+
+/// UART0的TX和RX引脚，配置给LED或红外时不启动控制台
+pub const CONSOLE_PINS: [u8; 2] = [43, 44];
+
+/// 一行最多的字节数，超过的部分丢弃
+const MAX_LINE_LEN: usize = 256;
+
+/// 控制台回复一次最多写出的字节数，分段发送的回复按此拆分
+pub const MAX_PAYLOAD: usize = 1024;
+
+const RX_BUFFER_SIZE: i32 = 512;
+const CONSOLE_STACK_SIZE: usize = 4096;
+
+/// 最后写出的内容没有以换行结束
+static LINE_OPEN: AtomicBool = AtomicBool::new(false);
+
+/// 控制台线程上报给主循环的事件
+#[derive(Debug)]
+pub enum ConsoleEvent {
+    /// 按回车提交的一行，已去掉首尾空白
+    Line(String),
+}
+
+/// 从UART0逐行读取命令，交给与蓝牙文本命令相同的解析；日志仍然输出到同一个串口
+pub fn spawn<E: From<ConsoleEvent> + Send + 'static>(events: Sender<E>) -> Result<(), EspError> {
+    // 只接收用驱动，发送仍然走标准输出
+    if !unsafe { sys::uart_is_driver_installed(sys::uart_port_t_UART_NUM_0) } {
+        EspError::convert(unsafe {
+            sys::uart_driver_install(sys::uart_port_t_UART_NUM_0, RX_BUFFER_SIZE, 0, 0, ptr::null_mut(), 0)
+        })?;
+    }
+    thread::Builder::new()
+        .name("console".into())
+        .stack_size(CONSOLE_STACK_SIZE)
+        .spawn(move || run(&events))
+        .map_err(|_| EspError::from_infallible::<ESP_FAIL>())?;
+    Ok(())
+}
+
+fn run<E: From<ConsoleEvent>>(events: &Sender<E>) {
+    let mut line = Vec::with_capacity(MAX_LINE_LEN);
+    let mut buf = [0u8; 64];
+    loop {
+        let read = unsafe {
+            sys::uart_read_bytes(sys::uart_port_t_UART_NUM_0, buf.as_mut_ptr().cast(), buf.len() as u32, BLOCK)
+        };
+        if read <= 0 {
+            continue;
+        }
+        for &byte in &buf[..read as usize] {
+            match byte {
+                // 回车和换行都提交，CRLF中的第二个字节遇到空行忽略
+                b'\r' | b'\n' => {
+                    if line.is_empty() {
+                        continue;
+                    }
+                    echo(b"\r\n");
+                    let text = String::from_utf8_lossy(&line).trim().to_string();
+                    line.clear();
+                    if text.is_empty() {
+                        continue;
+                    }
+                    if events.send(ConsoleEvent::Line(text).into()).is_err() {
+                        return;
+                    }
+                }
+                // 退格删除一个完整的UTF-8字符
+                0x08 | 0x7f if !line.is_empty() => {
+                    while line.pop().is_some_and(|byte| byte & 0xc0 == 0x80) {}
+                    echo(b"\x08 \x08");
+                }
+                // 其他控制字符和空行上的退格忽略
+                byte if byte.is_ascii_control() => (),
+                byte if line.len() < MAX_LINE_LEN => {
+                    line.push(byte);
+                    echo(&[byte]);
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+fn echo(data: &[u8]) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(data);
+    let _ = stdout.flush();
+}
+
+/// 写出命令的回复，分段的回复依次写出，拼接后与蓝牙收到的内容相同
+pub fn write(data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    echo(data);
+    LINE_OPEN.store(!data.ends_with(b"\n"), Ordering::Relaxed);
+}
+
+/// 一条命令处理完后调用，回复没有以换行结束时补上
+pub fn end_reply() {
+    if LINE_OPEN.swap(false, Ordering::Relaxed) {
+        echo(b"\r\n");
+    }
+}
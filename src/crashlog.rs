@@ -0,0 +1,90 @@
+use std::sync::Mutex;
+
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sys::{self, EspError};
+
+use crate::led;
+use crate::namespace::Namespace;
+
+/// 崩溃记录所在的NVS命名空间
+const NAMESPACE: &str = "ir_crash";
+
+const KEY_LAST_CRASH: &str = "last_crash";
+const KEY_CRASH_COUNT: &str = "crash_count";
+
+/// 保存的崩溃消息最多的字节数
+const MAX_MESSAGE_LEN: usize = 200;
+
+/// 最近一次崩溃的消息，以及清除之后崩溃重启的次数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crash {
+    pub message: String,
+    pub count: u32,
+}
+
+/// 读取和清除崩溃记录，panic钩子使用另一个句柄写入
+pub struct CrashLog {
+    nvs: Namespace,
+}
+
+impl CrashLog {
+    pub fn new(partition: &EspDefaultNvsPartition) -> Result<Self, EspError> {
+        Ok(Self {
+            nvs: Namespace::open(partition, NAMESPACE)?,
+        })
+    }
+
+    /// NVS不可用时使用，只记录本次运行的看门狗复位
+    pub fn in_memory() -> Self {
+        Self {
+            nvs: Namespace::in_memory(NAMESPACE),
+        }
+    }
+
+    /// 看门狗超时重启不经过panic钩子，启动时按复位原因补记一条
+    pub fn record_reset_reason(&mut self) -> Result<(), EspError> {
+        if unsafe { sys::esp_reset_reason() } == sys::esp_reset_reason_t_ESP_RST_TASK_WDT {
+            log::warn!("上次因任务看门狗超时重启");
+            record(&mut self.nvs, "task watchdog timeout")?;
+        }
+        Ok(())
+    }
+
+    /// 取出崩溃记录并清除，没有崩溃过时返回None
+    pub fn take(&mut self) -> Result<Option<Crash>, EspError> {
+        let count = self.nvs.get_u32(KEY_CRASH_COUNT)?.unwrap_or(0);
+        let mut buf = [0; MAX_MESSAGE_LEN + 1];
+        let message = self.nvs.get_str(KEY_LAST_CRASH, &mut buf)?.map(str::to_string);
+        let Some(message) = message else {
+            return Ok(None);
+        };
+        self.nvs.remove(KEY_LAST_CRASH)?;
+        self.nvs.remove(KEY_CRASH_COUNT)?;
+        Ok(Some(Crash { message, count }))
+    }
+}
+
+/// 安装panic钩子：先把LED点亮为红色，再把消息写入NVS并增加崩溃次数，最后交给默认钩子打印并中止
+pub fn install_panic_hook(partition: &EspDefaultNvsPartition) -> Result<(), EspError> {
+    let nvs = Mutex::new(Namespace::open(partition, NAMESPACE)?);
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        led::show_panic_red();
+        // 其他线程同时panic时不等待
+        if let Ok(mut nvs) = nvs.try_lock() {
+            let _ = record(&mut nvs, &info.to_string());
+        }
+        default_hook(info);
+    }));
+    Ok(())
+}
+
+fn record(nvs: &mut Namespace, message: &str) -> Result<(), EspError> {
+    let mut end = message.len().min(MAX_MESSAGE_LEN);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    let count = nvs.get_u32(KEY_CRASH_COUNT)?.unwrap_or(0);
+    nvs.set_str(KEY_LAST_CRASH, &message[..end])?;
+    nvs.set_u32(KEY_CRASH_COUNT, count.saturating_add(1))
+}
@@ -0,0 +1,631 @@
+use std::str::FromStr;
+
+use crate::led::{HsvColor, RgbColor, HUE_STEPS};
+
+/// 效果运行时每一帧的间隔（毫秒），主循环按这个间隔推进效果
+pub const FRAME_MS: u32 = 20;
+
+/// 呼吸灯默认由暗到亮再到暗的周期（毫秒）
+pub const BREATHING_PERIOD_MS: u32 = 4000;
+
+/// `alert`命令的频闪时长（毫秒）
+pub const ALERT_DURATION_MS: u32 = 5000;
+
+/// 频闪每次亮和灭的时长（毫秒）
+const STROBE_ON_MS: u64 = 40;
+const STROBE_OFF_MS: u64 = 80;
+
+/// 非阻塞的LED效果，由主循环反复调用`tick`推进，效果内部不等待
+pub trait Effect: Send {
+    /// 返回`now_ms`时要显示的颜色，效果结束后返回None
+    fn tick(&mut self, now_ms: u64) -> Option<RgbColor>;
+}
+
+/// 按像素绘制的效果，用于灯带；同样由主循环反复调用推进
+pub trait StripEffect: Send {
+    /// 把`now_ms`时的画面写入`pixels`，效果结束后返回false
+    fn render(&mut self, now_ms: u64, pixels: &mut [RgbColor]) -> bool;
+}
+
+/// xorshift32伪随机数，种子相同时序列相同
+#[derive(Debug, Clone, Copy)]
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        // 种子为0时xorshift一直输出0
+        Self(if seed == 0 { 0x9E37_79B9 } else { seed })
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+}
+
+/// 效果的时间线：第一次tick时开始计时，到时后再返回一次结束时刻，之后返回None
+struct Timeline {
+    start: Option<u64>,
+    /// 为None时一直运行到被取消
+    duration_ms: Option<u64>,
+    finished: bool,
+}
+
+impl Timeline {
+    fn new(duration_ms: Option<u64>) -> Self {
+        Self {
+            start: None,
+            duration_ms,
+            finished: false,
+        }
+    }
+
+    /// 从开始到现在经过的时间，不超过总时长
+    fn elapsed(&mut self, now_ms: u64) -> Option<u64> {
+        if self.finished {
+            return None;
+        }
+        let start = *self.start.get_or_insert(now_ms);
+        let elapsed = now_ms.saturating_sub(start);
+        match self.duration_ms {
+            Some(duration_ms) if elapsed >= duration_ms => {
+                self.finished = true;
+                Some(duration_ms)
+            }
+            _ => Some(elapsed),
+        }
+    }
+
+    /// `elapsed`返回的是结束时刻
+    fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+/// 渐变的缓动曲线，把经过的时间比例换算为颜色的插值比例
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Easing {
+    #[default]
+    Linear,
+    /// 开始和结束时较慢（smoothstep）
+    EaseInOut,
+    /// 开始时很快，越接近目标越慢
+    Exponential,
+}
+
+impl Easing {
+    /// `t`在0到1之间，返回值在两端分别为0和1
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Self::Linear => t,
+            Self::EaseInOut => t * t * (3.0 - 2.0 * t),
+            // 1 - 2^(-10t)在t=1时只有0.999，结束时直接取1
+            Self::Exponential if t >= 1.0 => 1.0,
+            Self::Exponential => 1.0 - 2f32.powf(-10.0 * t),
+        }
+    }
+}
+
+impl FromStr for Easing {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Self::Linear),
+            "ease" => Ok(Self::EaseInOut),
+            "exp" => Ok(Self::Exponential),
+            _ => Err(()),
+        }
+    }
+}
+
+/// 从起始颜色渐变到目标颜色
+///
+/// 起始颜色在创建时确定，不随LED之后的颜色变化；时长为0时第一帧就是目标颜色。
+pub struct Fade {
+    from: RgbColor,
+    to: RgbColor,
+    easing: Easing,
+    timeline: Timeline,
+}
+
+impl Fade {
+    pub fn new(from: RgbColor, to: RgbColor, duration_ms: u32, easing: Easing) -> Self {
+        Self {
+            from,
+            to,
+            easing,
+            timeline: Timeline::new(Some(duration_ms as u64)),
+        }
+    }
+}
+
+impl Effect for Fade {
+    fn tick(&mut self, now_ms: u64) -> Option<RgbColor> {
+        let elapsed = self.timeline.elapsed(now_ms)?;
+        let duration_ms = self.timeline.duration_ms.unwrap_or_default();
+        let t = if duration_ms == 0 { 1.0 } else { elapsed as f32 / duration_ms as f32 };
+        Some(self.from.lerp(&self.to, self.easing.apply(t)))
+    }
+}
+
+/// 以最大饱和度和亮度连续转过一圈色相
+pub struct Rainbow {
+    timeline: Timeline,
+}
+
+impl Rainbow {
+    pub fn new(duration_ms: u32) -> Self {
+        Self {
+            timeline: Timeline::new(Some(duration_ms.max(1) as u64)),
+        }
+    }
+}
+
+impl Effect for Rainbow {
+    fn tick(&mut self, now_ms: u64) -> Option<RgbColor> {
+        let elapsed = self.timeline.elapsed(now_ms)?;
+        let duration_ms = self.timeline.duration_ms.unwrap_or(1);
+        let hue = HUE_STEPS as u64 * elapsed / duration_ms;
+        Some(HsvColor::new((hue % HUE_STEPS as u64) as u16, 255, 255).into())
+    }
+}
+
+/// 呼吸灯：在黑色和指定颜色之间往复，结束时为黑色
+pub struct Breathing {
+    color: RgbColor,
+    period_ms: u64,
+    timeline: Timeline,
+}
+
+impl Breathing {
+    /// `cycles`为None时一直运行到被取消
+    pub fn new(color: RgbColor, cycles: Option<u32>, period_ms: u32) -> Self {
+        let period_ms = (period_ms as u64).max(2);
+        Self {
+            color,
+            period_ms,
+            timeline: Timeline::new(cycles.map(|cycles| cycles as u64 * period_ms)),
+        }
+    }
+}
+
+impl Effect for Breathing {
+    fn tick(&mut self, now_ms: u64) -> Option<RgbColor> {
+        let phase = self.timeline.elapsed(now_ms)? % self.period_ms;
+        let half = self.period_ms / 2;
+        // 前半个周期变亮，后半个周期变暗
+        let level = if phase < half { phase } else { self.period_ms - phase };
+        Some(RgbColor::black().lerp(&self.color, level as f32 / half as f32))
+    }
+}
+
+/// 闪烁：亮`on_ms`、灭`off_ms`，结束时为黑色
+pub struct Blink {
+    color: RgbColor,
+    on_ms: u64,
+    period_ms: u64,
+    timeline: Timeline,
+}
+
+impl Blink {
+    /// `times`为None时一直闪烁到被取消
+    pub fn new(color: RgbColor, times: Option<u32>, on_ms: u32, off_ms: u32) -> Self {
+        let period_ms = (on_ms as u64 + off_ms as u64).max(1);
+        Self {
+            color,
+            on_ms: on_ms as u64,
+            period_ms,
+            timeline: Timeline::new(times.map(|times| times as u64 * period_ms)),
+        }
+    }
+}
+
+impl Effect for Blink {
+    fn tick(&mut self, now_ms: u64) -> Option<RgbColor> {
+        let phase = self.timeline.elapsed(now_ms)? % self.period_ms;
+        if phase < self.on_ms && !self.timeline.is_finished() {
+            Some(self.color)
+        } else {
+            Some(RgbColor::black())
+        }
+    }
+}
+
+/// 频闪：在`duration_ms`内快速闪烁，结束时为黑色
+pub struct Strobe {
+    color: RgbColor,
+    timeline: Timeline,
+}
+
+impl Strobe {
+    pub fn new(color: RgbColor, duration_ms: u32) -> Self {
+        Self {
+            color,
+            timeline: Timeline::new(Some(duration_ms as u64)),
+        }
+    }
+}
+
+impl Effect for Strobe {
+    fn tick(&mut self, now_ms: u64) -> Option<RgbColor> {
+        let phase = self.timeline.elapsed(now_ms)? % (STROBE_ON_MS + STROBE_OFF_MS);
+        if phase < STROBE_ON_MS && !self.timeline.is_finished() {
+            Some(self.color)
+        } else {
+            Some(RgbColor::black())
+        }
+    }
+}
+
+/// 烛光：亮度按两层平滑的随机噪声起伏，一直运行到被取消
+pub struct Candle {
+    color: RgbColor,
+    seed: u32,
+    timeline: Timeline,
+}
+
+impl Candle {
+    pub fn new(color: RgbColor, seed: u32) -> Self {
+        Self {
+            color,
+            seed,
+            timeline: Timeline::new(None),
+        }
+    }
+
+    /// 一维值噪声：每`scale_ms`一个随机值，之间平滑插值，结果在0到1之间
+    fn noise(&self, now_ms: u64, scale_ms: u64, octave: u32) -> f32 {
+        let knot = now_ms / scale_ms;
+        let t = (now_ms % scale_ms) as f32 / scale_ms as f32;
+        let value = |knot: u64| hash(self.seed ^ octave.wrapping_mul(0x68E3_1DA4), knot as u32) as f32 / u32::MAX as f32;
+        let (a, b) = (value(knot), value(knot + 1));
+        a + (b - a) * Easing::EaseInOut.apply(t)
+    }
+}
+
+impl Effect for Candle {
+    fn tick(&mut self, now_ms: u64) -> Option<RgbColor> {
+        let elapsed = self.timeline.elapsed(now_ms)?;
+        // 缓慢的起伏加上较快的跳动，亮度保持在55%以上
+        let flicker = 0.65 * self.noise(elapsed, 180, 0) + 0.35 * self.noise(elapsed, 55, 1);
+        Some(RgbColor::black().lerp(&self.color, 0.55 + 0.45 * flicker))
+    }
+}
+
+/// 把种子和位置混合为均匀分布的32位数
+fn hash(seed: u32, x: u32) -> u32 {
+    let mut h = x.wrapping_mul(0x9E37_79B1) ^ seed;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x85EB_CA6B);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xC2B2_AE35);
+    h ^ (h >> 16)
+}
+
+/// 剧院追逐：每3个像素亮一个，按`step_ms`移动一格，一直运行到被取消
+pub struct TheaterChase {
+    color: RgbColor,
+    background: RgbColor,
+    step_ms: u64,
+    timeline: Timeline,
+}
+
+impl TheaterChase {
+    pub fn new(color: RgbColor, background: RgbColor, step_ms: u32) -> Self {
+        Self {
+            color,
+            background,
+            step_ms: (step_ms as u64).max(1),
+            timeline: Timeline::new(None),
+        }
+    }
+}
+
+impl StripEffect for TheaterChase {
+    fn render(&mut self, now_ms: u64, pixels: &mut [RgbColor]) -> bool {
+        let Some(elapsed) = self.timeline.elapsed(now_ms) else {
+            return false;
+        };
+        let offset = (elapsed / self.step_ms % 3) as usize;
+        for (index, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = if index % 3 == offset { self.color } else { self.background };
+        }
+        true
+    }
+}
+
+/// 颜色擦除：每`step_ms`点亮一个像素直到填满，再从头逐个熄灭，之后结束
+pub struct ColorWipe {
+    color: RgbColor,
+    step_ms: u64,
+    timeline: Timeline,
+}
+
+impl ColorWipe {
+    pub fn new(color: RgbColor, step_ms: u32) -> Self {
+        Self {
+            color,
+            step_ms: (step_ms as u64).max(1),
+            timeline: Timeline::new(None),
+        }
+    }
+}
+
+impl StripEffect for ColorWipe {
+    fn render(&mut self, now_ms: u64, pixels: &mut [RgbColor]) -> bool {
+        let Some(elapsed) = self.timeline.elapsed(now_ms) else {
+            return false;
+        };
+        let len = pixels.len();
+        let step = (elapsed / self.step_ms) as usize;
+        if step >= 2 * len {
+            return false;
+        }
+        for (index, pixel) in pixels.iter_mut().enumerate() {
+            // 前len步点亮前step+1个像素，后len步熄灭前step-len+1个像素
+            let lit = if step < len { index <= step } else { index > step - len };
+            *pixel = if lit { self.color } else { RgbColor::black() };
+        }
+        true
+    }
+}
+
+/// 闪烁的星点：每`step_ms`重新随机选择一批像素显示`color`，其余显示背景色，一直运行到被取消
+pub struct Sparkle {
+    color: RgbColor,
+    background: RgbColor,
+    /// 每个像素被选中的概率（百分比）
+    density: u8,
+    step_ms: u64,
+    rng: Rng,
+    /// 上一次随机选择时的步数
+    last_step: Option<u64>,
+    timeline: Timeline,
+}
+
+impl Sparkle {
+    pub fn new(color: RgbColor, background: RgbColor, density: u8, step_ms: u32, seed: u32) -> Self {
+        Self {
+            color,
+            background,
+            density: density.min(100),
+            step_ms: (step_ms as u64).max(1),
+            rng: Rng::new(seed),
+            last_step: None,
+            timeline: Timeline::new(None),
+        }
+    }
+}
+
+impl StripEffect for Sparkle {
+    fn render(&mut self, now_ms: u64, pixels: &mut [RgbColor]) -> bool {
+        let Some(elapsed) = self.timeline.elapsed(now_ms) else {
+            return false;
+        };
+        let step = elapsed / self.step_ms;
+        if self.last_step == Some(step) {
+            return true;
+        }
+        self.last_step = Some(step);
+        for pixel in pixels.iter_mut() {
+            let lit = self.rng.next_u32() % 100 < self.density as u32;
+            *pixel = if lit { self.color } else { self.background };
+        }
+        true
+    }
+}
+
+/// `EffectRunner::tick`的结果
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    /// 没有运行中的效果
+    Idle,
+    /// 整条灯带显示一个颜色
+    Show(RgbColor),
+    /// 按像素绘制的效果已经写入了像素
+    Rendered,
+    /// 效果刚刚结束
+    Finished,
+}
+
+/// 效果的优先级，高优先级的效果运行时低优先级的效果暂停，结束后继续
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// 氛围灯，例如烛光
+    Ambient,
+    /// 状态指示和命令启动的效果
+    Status,
+    /// 需要立即注意的提醒
+    Alert,
+}
+
+/// 优先级的数量，每个优先级最多保存一个效果
+const PRIORITY_LEVELS: usize = 3;
+
+/// 正在运行的效果
+enum Active {
+    Solid(Box<dyn Effect>),
+    Strip(Box<dyn StripEffect>),
+}
+
+/// 一个优先级上的效果和它暂停过的时间
+struct Slot {
+    effect: Active,
+    /// 暂停的总时长，效果看到的时间减去这部分，继续时不会跳过暂停期间
+    paused_ms: u64,
+    /// 被更高优先级的效果暂停的时刻
+    paused_at: Option<u64>,
+}
+
+/// 保存正在运行的效果，每个优先级最多一个，只推进优先级最高的效果
+///
+/// 启动效果时替换同一优先级上的效果；更低优先级的效果暂停，最多同时保存`PRIORITY_LEVELS`个。
+#[derive(Default)]
+pub struct EffectRunner {
+    slots: [Option<Slot>; PRIORITY_LEVELS],
+}
+
+impl EffectRunner {
+    pub fn start(&mut self, effect: Box<dyn Effect>, priority: Priority) {
+        self.insert(Active::Solid(effect), priority);
+    }
+
+    pub fn start_strip(&mut self, effect: Box<dyn StripEffect>, priority: Priority) {
+        self.insert(Active::Strip(effect), priority);
+    }
+
+    fn insert(&mut self, effect: Active, priority: Priority) {
+        self.slots[priority as usize] = Some(Slot {
+            effect,
+            paused_ms: 0,
+            paused_at: None,
+        });
+    }
+
+    /// 取消所有效果，返回之前是否有效果在运行
+    pub fn cancel(&mut self) -> bool {
+        self.cancel_up_to(Priority::Alert)
+    }
+
+    /// 取消不高于`priority`的效果，返回之前是否有这样的效果
+    pub fn cancel_up_to(&mut self, priority: Priority) -> bool {
+        let mut cancelled = false;
+        for slot in &mut self.slots[..=priority as usize] {
+            cancelled |= slot.take().is_some();
+        }
+        cancelled
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.slots.iter().any(Option::is_some)
+    }
+
+    /// `priority`上是否有效果，包括暂停的
+    pub fn is_running_at(&self, priority: Priority) -> bool {
+        self.slots[priority as usize].is_some()
+    }
+
+    /// 推进优先级最高的效果，按像素绘制的效果写入`pixels`
+    ///
+    /// 最高优先级的效果结束时，同一次调用中继续推进下一个暂停的效果，中间不会闪回静态颜色。
+    pub fn tick(&mut self, now_ms: u64, pixels: &mut [RgbColor]) -> Step {
+        let mut finished = false;
+        while let Some(top) = self.slots.iter().rposition(Option::is_some) {
+            for slot in self.slots[..top].iter_mut().flatten() {
+                slot.paused_at.get_or_insert(now_ms);
+            }
+            let Some(slot) = self.slots[top].as_mut() else {
+                break;
+            };
+            if let Some(paused_at) = slot.paused_at.take() {
+                slot.paused_ms += now_ms.saturating_sub(paused_at);
+            }
+            let local_ms = now_ms.saturating_sub(slot.paused_ms);
+            let step = match &mut slot.effect {
+                Active::Solid(effect) => effect.tick(local_ms).map(Step::Show),
+                Active::Strip(effect) => effect.render(local_ms, pixels).then_some(Step::Rendered),
+            };
+            match step {
+                Some(step) => return step,
+                None => {
+                    self.slots[top] = None;
+                    finished = true;
+                }
+            }
+        }
+        if finished {
+            Step::Finished
+        } else {
+            Step::Idle
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ON: RgbColor = RgbColor::new(255, 0, 0);
+    const BG: RgbColor = RgbColor::new(0, 0, 8);
+    const OFF: RgbColor = RgbColor::black();
+
+    /// 在给定时刻依次绘制到同一个帧缓冲区，返回每一帧的像素，效果结束后为None
+    fn frames(effect: &mut dyn StripEffect, len: usize, times: &[u64]) -> Vec<Option<Vec<RgbColor>>> {
+        let mut pixels = vec![OFF; len];
+        times
+            .iter()
+            .map(|&now_ms| effect.render(now_ms, &mut pixels).then(|| pixels.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn rng_is_xorshift32() {
+        let mut rng = Rng::new(1);
+        assert_eq!([rng.next_u32(), rng.next_u32()], [270_369, 67_634_689]);
+        // 种子为0时换成固定的非零种子
+        assert_eq!(Rng::new(0).next_u32(), Rng::new(0x9E37_79B9).next_u32());
+    }
+
+    #[test]
+    fn theater_chase_moves_one_pixel_per_step() {
+        let mut chase = TheaterChase::new(ON, BG, 100);
+        let frames = frames(&mut chase, 6, &[1000, 1099, 1100, 1200, 1300]);
+        let first = Some(vec![ON, BG, BG, ON, BG, BG]);
+        let second = Some(vec![BG, ON, BG, BG, ON, BG]);
+        let third = Some(vec![BG, BG, ON, BG, BG, ON]);
+        assert_eq!(frames, [first.clone(), first.clone(), second, third, first]);
+    }
+
+    #[test]
+    fn color_wipe_fills_then_clears_and_finishes() {
+        let mut wipe = ColorWipe::new(ON, 50);
+        let times: Vec<u64> = (0..=6).map(|step| step * 50).collect();
+        assert_eq!(
+            frames(&mut wipe, 3, &times),
+            [
+                Some(vec![ON, OFF, OFF]),
+                Some(vec![ON, ON, OFF]),
+                Some(vec![ON, ON, ON]),
+                Some(vec![OFF, ON, ON]),
+                Some(vec![OFF, OFF, ON]),
+                Some(vec![OFF, OFF, OFF]),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn sparkle_is_deterministic_for_a_seed() {
+        let times = [0, 10, 50, 100, 150];
+        let mut first = Sparkle::new(ON, BG, 30, 50, 0x1234_5678);
+        let mut second = Sparkle::new(ON, BG, 30, 50, 0x1234_5678);
+        let expected = frames(&mut first, 16, &times);
+        assert_eq!(frames(&mut second, 16, &times), expected);
+
+        // 同一步内画面不变，每一步按随机数重新选择
+        assert_eq!(expected[0], expected[1]);
+        let mut rng = Rng::new(0x1234_5678);
+        for frame in [&expected[0], &expected[2], &expected[3], &expected[4]] {
+            let reference: Vec<RgbColor> =
+                (0..16).map(|_| if rng.next_u32() % 100 < 30 { ON } else { BG }).collect();
+            assert_eq!(frame.as_ref(), Some(&reference));
+        }
+
+        let mut other = Sparkle::new(ON, BG, 30, 50, 0x8765_4321);
+        assert_ne!(frames(&mut other, 16, &times), expected);
+    }
+
+    #[test]
+    fn sparkle_density_limits() {
+        let mut none = Sparkle::new(ON, BG, 0, 50, 7);
+        assert_eq!(frames(&mut none, 8, &[0]), [Some(vec![BG; 8])]);
+        let mut all = Sparkle::new(ON, BG, 100, 50, 7);
+        assert_eq!(frames(&mut all, 8, &[0]), [Some(vec![ON; 8])]);
+    }
+}
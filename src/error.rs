@@ -0,0 +1,85 @@
+use std::fmt;
+
+use esp_idf_svc::bt::ble::gatt::server::ConnectionId;
+use esp_idf_svc::bt::BdAddr;
+use esp_idf_svc::sys::EspError;
+
+use crate::chunk::TooManyChunks;
+use crate::command::Status;
+use crate::ir::storage::StorageError;
+
+/// LED、蓝牙等模块公开接口共用的错误，调用方可以按种类处理
+#[derive(Debug)]
+pub enum Error {
+    /// RMT驱动调用失败
+    Rmt(EspError),
+    /// 蓝牙协议栈调用失败
+    Ble(EspError),
+    /// 读写NVS中的录制失败
+    Storage(StorageError),
+    /// 消息不符合分段协议
+    Protocol(TooManyChunks),
+    /// 发出命令的客户端已经断开，剩下的回复不再发送
+    NotConnected { conn_id: ConnectionId },
+    /// 发送缓冲区拥塞在超时时间内没有解除，这次通知没有发出
+    Busy { peer: BdAddr },
+    /// 客户端没有在超时时间内确认上一个指示
+    Timeout { peer: BdAddr },
+    /// 蓝牙正在关闭，等待中的发送被放弃
+    Shutdown,
+}
+
+impl Error {
+    /// 结构化命令响应中的状态码，数值固定不变
+    pub fn status(&self) -> Status {
+        match self {
+            Self::Rmt(_) => Status::Rmt,
+            Self::Ble(_) | Self::Shutdown => Status::Ble,
+            Self::Storage(StorageError::Nvs(_) | StorageError::StorageFull { .. } | StorageError::PartialDelete { .. }) => {
+                Status::Storage
+            }
+            Self::Storage(StorageError::UnknownSlot | StorageError::UnknownRemote) => Status::NotFound,
+            Self::Storage(_) => Status::InvalidPayload,
+            Self::Protocol(_) => Status::Failed,
+            Self::NotConnected { .. } => Status::NotConnected,
+            Self::Busy { .. } => Status::Busy,
+            Self::Timeout { .. } => Status::Timeout,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rmt(e) => write!(f, "RMT错误: {:?}", e),
+            Self::Ble(e) => write!(f, "蓝牙错误: {:?}", e),
+            Self::Storage(e) => write!(f, "存储错误: {}", e),
+            Self::Protocol(e) => write!(f, "{}", e),
+            Self::NotConnected { conn_id } => write!(f, "客户端已断开: conn_id={}", conn_id),
+            Self::Busy { peer } => write!(f, "向 {} 发送通知时拥塞超时", peer),
+            Self::Timeout { peer } => write!(f, "等待 {} 确认指示超时", peer),
+            Self::Shutdown => write!(f, "蓝牙已关闭"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// 没有注明来源的ESP-IDF错误来自RMT驱动，蓝牙协议栈的错误用`Error::Ble`包装
+impl From<EspError> for Error {
+    fn from(e: EspError) -> Self {
+        Self::Rmt(e)
+    }
+}
+
+impl From<StorageError> for Error {
+    fn from(e: StorageError) -> Self {
+        Self::Storage(e)
+    }
+}
+
+impl From<TooManyChunks> for Error {
+    fn from(e: TooManyChunks) -> Self {
+        Self::Protocol(e)
+    }
+}
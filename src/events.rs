@@ -0,0 +1,70 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+/// 重发缓冲区保留的事件数量
+pub const REPLAY_LEN: usize = 16;
+
+/// 事件信封的前缀，之后是序号、一个空格和事件内容
+const ENVELOPE_PREFIX: &str = "EVT ";
+
+/// 客户端错过的事件已经不在重发缓冲区中，需要重新读取完整状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceGap {
+    pub last_seen: u16,
+    /// 最近一个事件的序号，还没有事件时为None
+    pub latest: Option<u16>,
+}
+
+impl fmt::Display for SequenceGap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "客户端最后收到的事件{}已不在重发缓冲区中", self.last_seen)
+    }
+}
+
+impl std::error::Error for SequenceGap {}
+
+/// 主动上报的事件，按16位序号编号并保留最近的`REPLAY_LEN`个
+///
+/// 序号从0开始，到65535后回到0。只在内存中保存，重启后重新编号。
+#[derive(Default)]
+pub struct EventLog {
+    next_seq: u16,
+    events: VecDeque<(u16, Vec<u8>)>,
+}
+
+impl EventLog {
+    /// 分配序号并保存，返回加上信封`EVT <序号> `的事件
+    pub fn push(&mut self, data: &[u8]) -> Vec<u8> {
+        let seq = self.next_seq;
+        self.next_seq = seq.wrapping_add(1);
+
+        let mut envelope = format!("{}{} ", ENVELOPE_PREFIX, seq).into_bytes();
+        envelope.extend_from_slice(data);
+        if self.events.len() >= REPLAY_LEN {
+            self.events.pop_front();
+        }
+        self.events.push_back((seq, envelope.clone()));
+        envelope
+    }
+
+    /// 最近一个事件的序号，还没有事件时为None
+    pub fn latest(&self) -> Option<u16> {
+        self.events.back().map(|(seq, _)| *seq)
+    }
+
+    /// 客户端最后收到`last_seen`之后的所有事件（带信封），按序号排列
+    ///
+    /// 按回绕后的距离计算错过的数量，超过缓冲区中的事件数量时返回`SequenceGap`，
+    /// 包括设备重启后序号重新开始的情况。还没有收到过事件的客户端可以用65535。
+    pub fn since(&self, last_seen: u16) -> Result<Vec<Vec<u8>>, SequenceGap> {
+        let missed = self.next_seq.wrapping_sub(last_seen).wrapping_sub(1) as usize;
+        if missed > self.events.len() {
+            return Err(SequenceGap {
+                last_seen,
+                latest: self.latest(),
+            });
+        }
+        let skip = self.events.len() - missed;
+        Ok(self.events.iter().skip(skip).map(|(_, envelope)| envelope.clone()).collect())
+    }
+}
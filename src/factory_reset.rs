@@ -0,0 +1,115 @@
+use std::collections::BTreeSet;
+use std::ffi::{CStr, CString};
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::sys::{self, EspError};
+
+use crate::ir::storage::{REMOTE_META_PREFIX, REMOTE_PREFIX};
+use crate::namespace::erase_namespace;
+
+/// 发送随机数后等待客户端原样发回的时间
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 确认期间LED闪烁的半周期
+const FLASH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// 本固件使用的命名空间前缀：录制、附加信息、遥控器列表、宏和设置都以`ir_`开头，
+/// 其他遥控器的录制和附加信息以`r:`和`m:`开头；蓝牙协议栈的配对信息不在其中
+const APP_NAMESPACE_PREFIX: &str = "ir_";
+
+/// 等待确认的恢复出厂设置
+pub struct PendingReset {
+    nonce: u32,
+    started: Instant,
+    /// LED当前是否点亮
+    led_on: bool,
+}
+
+impl PendingReset {
+    /// 生成新的随机数，开始等待确认
+    pub fn new(now: Instant) -> Self {
+        Self {
+            nonce: unsafe { sys::esp_random() },
+            started: now,
+            led_on: false,
+        }
+    }
+
+    /// 回复给客户端、需要原样发回的随机数
+    pub fn nonce(&self) -> String {
+        format!("{:08x}", self.nonce)
+    }
+
+    /// 发回的随机数是否正确，十六进制已由命令解析转为字节
+    pub fn confirms(&self, nonce: &[u8]) -> bool {
+        nonce == self.nonce.to_be_bytes().as_slice()
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.started) >= CONFIRM_TIMEOUT
+    }
+
+    /// LED需要切换时返回新的状态，确认期间红色闪烁
+    pub fn poll_led(&mut self, now: Instant) -> Option<bool> {
+        let phase = now.saturating_duration_since(self.started).as_millis() / FLASH_INTERVAL.as_millis();
+        let led_on = phase % 2 == 0;
+        (led_on != self.led_on).then(|| {
+            self.led_on = led_on;
+            led_on
+        })
+    }
+}
+
+/// 擦除本固件的所有命名空间，返回擦除的数量，蓝牙配对信息所在的命名空间保持不变
+///
+/// 某个命名空间擦除失败时继续擦除其余的，最后返回第一个错误。
+pub fn erase_app_data() -> Result<usize, EspError> {
+    let namespaces = app_namespaces();
+    let mut result = Ok(namespaces.len());
+    for namespace in &namespaces {
+        match erase_namespace(namespace) {
+            Ok(()) => log::info!("已擦除命名空间: {:?}", namespace),
+            Err(e) => {
+                log::error!("擦除命名空间{:?}失败: {:?}", namespace, e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+    }
+    result
+}
+
+/// 默认NVS分区中属于本固件的命名空间
+fn app_namespaces() -> BTreeSet<CString> {
+    let mut namespaces = BTreeSet::new();
+    let mut iterator: sys::nvs_iterator_t = std::ptr::null_mut();
+    let mut result = unsafe {
+        sys::nvs_entry_find(
+            sys::NVS_DEFAULT_PART_NAME.as_ptr() as *const _,
+            std::ptr::null(),
+            sys::nvs_type_t_NVS_TYPE_ANY,
+            &mut iterator,
+        )
+    };
+    while result == sys::ESP_OK {
+        let mut info = sys::nvs_entry_info_t::default();
+        if unsafe { sys::nvs_entry_info(iterator, &mut info) } == sys::ESP_OK {
+            let namespace = unsafe { CStr::from_ptr(info.namespace_name.as_ptr()) };
+            let owned = namespace.to_str().is_ok_and(|name| {
+                [APP_NAMESPACE_PREFIX, REMOTE_PREFIX, REMOTE_META_PREFIX]
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix))
+            });
+            if owned {
+                namespaces.insert(namespace.to_owned());
+            }
+        }
+        result = unsafe { sys::nvs_entry_next(&mut iterator) };
+    }
+    unsafe { sys::nvs_release_iterator(iterator) };
+    if result != sys::ESP_ERR_NVS_NOT_FOUND {
+        log::warn!("遍历NVS失败: {:?}", EspError::from(result));
+    }
+    namespaces
+}
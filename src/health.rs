@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use esp_idf_svc::sys;
+
+use crate::bluetooth::BluetoothManager;
+
+/// 运行状况写入日志的间隔
+pub const LOG_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// 长期运行的设备的运行状况，只读取计数器和堆统计，可以在主循环中随时采集
+#[derive(Debug, Clone, Copy)]
+pub struct Health {
+    pub uptime_s: u64,
+    pub free_heap: u32,
+    /// 启动以来空闲堆的最小值
+    pub min_free_heap: u32,
+    pub largest_free_block: usize,
+    /// NVS中的启动次数，每次启动加一
+    pub boots: u32,
+    pub ir_captures: u32,
+    pub indications_sent: u32,
+    pub indication_timeouts: u32,
+    pub ble_disconnects: u32,
+}
+
+impl Health {
+    pub fn collect(boots: u32, ir_captures: u32, bluetooth_manager: &BluetoothManager) -> Self {
+        Self {
+            uptime_s: (unsafe { sys::esp_timer_get_time() } / 1_000_000) as u64,
+            free_heap: unsafe { sys::esp_get_free_heap_size() },
+            min_free_heap: unsafe { sys::esp_get_minimum_free_heap_size() },
+            largest_free_block: unsafe { sys::heap_caps_get_largest_free_block(sys::MALLOC_CAP_DEFAULT) },
+            boots,
+            ir_captures,
+            indications_sent: bluetooth_manager.indications_sent(),
+            indication_timeouts: bluetooth_manager.indication_timeouts(),
+            ble_disconnects: bluetooth_manager.disconnects(),
+        }
+    }
+
+    /// 序列化为紧凑的JSON
+    pub fn to_json(self) -> String {
+        format!(
+            "{{\"uptime_s\":{},\"free_heap\":{},\"min_free_heap\":{},\"largest_free_block\":{},\"boots\":{},\
+             \"ir_captures\":{},\"indications\":{},\"indication_timeouts\":{},\"ble_disconnects\":{}}}",
+            self.uptime_s,
+            self.free_heap,
+            self.min_free_heap,
+            self.largest_free_block,
+            self.boots,
+            self.ir_captures,
+            self.indications_sent,
+            self.indication_timeouts,
+            self.ble_disconnects
+        )
+    }
+}
@@ -0,0 +1,243 @@
+use std::ops::RangeInclusive;
+use std::sync::mpsc::{self, Sender, SyncSender};
+use std::time::Duration;
+
+use esp_idf_svc::http::server::{Configuration, EspHttpConnection, EspHttpServer, Request};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::io::{EspIOError, Read, Write};
+use esp_idf_svc::sys::EspError;
+
+use crate::ir::dump::{parse_json, DumpError};
+use crate::ir::receiver::Capture;
+
+/// 请求体的默认上限和允许设置的范围（字节）
+pub const DEFAULT_MAX_BODY: u32 = 16 * 1024;
+pub const MAX_BODY_RANGE: RangeInclusive<u32> = 1024..=64 * 1024;
+
+/// 访问令牌最长64字节，为空时不启动HTTP服务
+pub const MAX_TOKEN_LEN: usize = 64;
+
+/// 客户端在该请求头中携带访问令牌
+const AUTH_HEADER: &str = "X-Auth-Token";
+
+/// 等待主循环处理请求的最长时间
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const HTTP_STACK_SIZE: usize = 8192;
+
+/// 交给主循环处理的请求，与蓝牙命令使用相同的存储和发射队列
+#[derive(Debug)]
+pub enum HttpRequest {
+    /// GET /codes
+    ListCodes,
+    /// GET /codes/{name}
+    GetCode(String),
+    /// POST /codes/{name}
+    ImportCode(String, Capture),
+    /// DELETE /codes/{name}
+    DeleteCode(String),
+    /// POST /send/{name}
+    Send(String),
+    /// GET /status
+    Status,
+}
+
+/// 状态码和JSON内容
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl HttpResponse {
+    pub fn json(status: u16, body: String) -> Self {
+        Self { status, body }
+    }
+
+    /// 错误内容为`{"error":"..."}`
+    pub fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            body: format!("{{\"error\":\"{}\"}}", message),
+        }
+    }
+}
+
+/// 发给主循环的请求，处理完后通过`respond`交回HTTP任务
+#[derive(Debug)]
+pub struct HttpEvent {
+    pub request: HttpRequest,
+    responder: SyncSender<HttpResponse>,
+}
+
+impl HttpEvent {
+    pub fn respond(self, response: HttpResponse) {
+        // HTTP任务等待超时后已经不再接收
+        let _ = self.responder.send(response);
+    }
+}
+
+/// 管理录制的REST接口，只在连上Wi-Fi并设置了访问令牌时启动
+pub struct HttpApi {
+    _server: EspHttpServer<'static>,
+}
+
+impl HttpApi {
+    pub fn start<E: From<HttpEvent> + Send + 'static>(
+        token: String,
+        max_body: usize,
+        events: Sender<E>,
+    ) -> Result<Self, EspError> {
+        let mut server = EspHttpServer::new(&Configuration {
+            stack_size: HTTP_STACK_SIZE,
+            uri_match_wildcard: true,
+            ..Default::default()
+        })?;
+
+        let api = Api { token, max_body, events };
+        api.route(&mut server, "/codes", Method::Get, |_, _| Ok(HttpRequest::ListCodes))?;
+        api.route(&mut server, "/codes/*", Method::Get, |name, _| Ok(HttpRequest::GetCode(name?)))?;
+        api.route(&mut server, "/codes/*", Method::Post, |name, body| {
+            let name = name?;
+            let capture = parse_json(&body?).map_err(|e| {
+                log::warn!("导入录制{}失败: {}", name, e);
+                HttpResponse::error(400, &dump_error_reason(&e))
+            })?;
+            Ok(HttpRequest::ImportCode(name, capture))
+        })?;
+        api.route(&mut server, "/codes/*", Method::Delete, |name, _| Ok(HttpRequest::DeleteCode(name?)))?;
+        api.route(&mut server, "/send/*", Method::Post, |name, _| Ok(HttpRequest::Send(name?)))?;
+        api.route(&mut server, "/status", Method::Get, |_, _| Ok(HttpRequest::Status))?;
+
+        Ok(Self { _server: server })
+    }
+}
+
+/// 各个处理函数共用的令牌、请求体上限和事件通道
+struct Api<E> {
+    token: String,
+    max_body: usize,
+    events: Sender<E>,
+}
+
+// 事件类型不需要实现Clone
+impl<E> Clone for Api<E> {
+    fn clone(&self) -> Self {
+        Self {
+            token: self.token.clone(),
+            max_body: self.max_body,
+            events: self.events.clone(),
+        }
+    }
+}
+
+type Parsed<T> = Result<T, HttpResponse>;
+
+impl<E: From<HttpEvent> + Send + 'static> Api<E> {
+    /// 注册一个处理函数，`parse`收到路径中的录制名称和请求体，不需要的部分忽略其中的错误
+    fn route(
+        &self,
+        server: &mut EspHttpServer<'static>,
+        uri: &str,
+        method: Method,
+        parse: fn(Parsed<String>, Parsed<String>) -> Parsed<HttpRequest>,
+    ) -> Result<(), EspError> {
+        let api = self.clone();
+        let prefix = uri.trim_end_matches('*').to_string();
+        server.fn_handler(uri, method, move |mut request| -> Result<(), EspIOError> {
+            let response = if !token_matches(request.header(AUTH_HEADER), &api.token) {
+                HttpResponse::error(401, "invalid token")
+            } else {
+                let name = slot_name(request.uri(), &prefix);
+                let body = api.read_body(&mut request);
+                match parse(name, body) {
+                    Ok(parsed) => api.dispatch(parsed),
+                    Err(response) => response,
+                }
+            };
+            let mut out = request.into_response(response.status, None, &[("Content-Type", "application/json")])?;
+            out.write_all(response.body.as_bytes())
+        })?;
+        Ok(())
+    }
+
+    /// 读取UTF-8的请求体，超过上限时返回413
+    fn read_body(&self, request: &mut Request<&mut EspHttpConnection<'_>>) -> Parsed<String> {
+        if request.content_len().is_some_and(|len| len > self.max_body as u64) {
+            return Err(HttpResponse::error(413, "body too large"));
+        }
+        let mut body = Vec::new();
+        let mut buf = [0; 512];
+        loop {
+            let read = request.read(&mut buf).map_err(|e| {
+                log::warn!("读取HTTP请求失败: {:?}", e);
+                HttpResponse::error(400, "read failed")
+            })?;
+            if read == 0 {
+                break;
+            }
+            if body.len() + read > self.max_body {
+                return Err(HttpResponse::error(413, "body too large"));
+            }
+            body.extend_from_slice(&buf[..read]);
+        }
+        String::from_utf8(body).map_err(|_| HttpResponse::error(400, "body must be UTF-8"))
+    }
+
+    /// 交给主循环处理并等待结果
+    fn dispatch(&self, request: HttpRequest) -> HttpResponse {
+        let (responder, response) = mpsc::sync_channel(1);
+        if self.events.send(HttpEvent { request, responder }.into()).is_err() {
+            return HttpResponse::error(503, "unavailable");
+        }
+        response.recv_timeout(RESPONSE_TIMEOUT).unwrap_or_else(|_| {
+            log::warn!("主循环处理HTTP请求超时");
+            HttpResponse::error(503, "timeout")
+        })
+    }
+}
+
+/// 路径中前缀之后的部分，去掉查询参数并解码`%XX`，`/codes/tv/power`对应`tv/power`
+fn slot_name(uri: &str, prefix: &str) -> Parsed<String> {
+    let path = uri.split('?').next().unwrap_or_default();
+    let encoded = path.strip_prefix(prefix).unwrap_or_default();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' && tail.len() >= 2 {
+            let hex = std::str::from_utf8(&tail[..2]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if let Some(decoded) = hex {
+                bytes.push(decoded);
+                rest = &tail[2..];
+                continue;
+            }
+        }
+        bytes.push(byte);
+        rest = tail;
+    }
+    match String::from_utf8(bytes) {
+        Ok(name) if !name.is_empty() => Ok(name),
+        _ => Err(HttpResponse::error(400, "invalid name")),
+    }
+}
+
+/// 逐字节比较全部内容，耗时不随第一个不同字节的位置变化
+fn token_matches(header: Option<&str>, token: &str) -> bool {
+    let Some(header) = header else {
+        return false;
+    };
+    header.len() == token.len() && header.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn dump_error_reason(error: &DumpError) -> String {
+    match error {
+        DumpError::Syntax(offset) => format!("invalid JSON at byte {}", offset),
+        DumpError::MissingField(field) => format!("missing field {}", field),
+        DumpError::InvalidValue(field) => format!("invalid {}", field),
+    }
+}
+
+/// 访问令牌只能包含可见的ASCII字符，空字符串表示关闭HTTP服务
+pub fn check_token(token: &str) -> bool {
+    token.len() <= MAX_TOKEN_LEN && token.bytes().all(|byte| byte.is_ascii_graphic())
+}
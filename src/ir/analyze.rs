@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::DECODERS;
+
+/// 默认的直方图桶宽（微秒）
+pub const DEFAULT_BUCKET_WIDTH_US: u32 = 100;
+
+/// mark长度超过典型mark的该倍数时视为可能的引导码
+const HEADER_MARK_RATIO: u32 = 2;
+
+/// 一组时长的统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DurationStats {
+    pub count: usize,
+    pub min: u32,
+    pub max: u32,
+    pub mean: u32,
+}
+
+impl DurationStats {
+    fn new(durations: impl Iterator<Item = u32>) -> Self {
+        let (mut count, mut min, mut max, mut sum) = (0usize, u32::MAX, 0u32, 0u64);
+        for duration in durations {
+            count += 1;
+            min = min.min(duration);
+            max = max.max(duration);
+            sum += duration as u64;
+        }
+        match sum.checked_div(count as u64) {
+            Some(mean) => Self { count, min, max, mean: mean as u32 },
+            None => Self::default(),
+        }
+    }
+
+    fn write_json(&self, out: &mut String) {
+        let _ = write!(
+            out,
+            "{{\"count\":{},\"min\":{},\"max\":{},\"mean\":{}}}",
+            self.count, self.min, self.max, self.mean
+        );
+    }
+}
+
+/// 可能的引导码位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderCandidate {
+    /// mark在时长序列中的下标
+    pub index: usize,
+    pub mark: u32,
+    pub space: Option<u32>,
+    /// 引导码特征匹配的协议
+    pub protocols: Vec<&'static str>,
+}
+
+/// 捕获的脉冲统计
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PulseStats {
+    pub pulse_count: usize,
+    /// 整帧时长（微秒）
+    pub total_us: u64,
+    pub marks: DurationStats,
+    pub spaces: DurationStats,
+    pub bucket_width_us: u32,
+    /// 按桶起点排列的非空桶及其计数
+    pub histogram: Vec<(u32, usize)>,
+    pub headers: Vec<HeaderCandidate>,
+}
+
+/// 统计捕获中的mark和space时长，用于排查解码失败的原因
+pub fn analyze(durations: &[u32], bucket_width_us: u32) -> PulseStats {
+    let bucket_width_us = bucket_width_us.max(1);
+    let marks = DurationStats::new(durations.iter().step_by(2).copied());
+    let spaces = DurationStats::new(durations.iter().skip(1).step_by(2).copied());
+
+    let mut buckets = BTreeMap::new();
+    for &duration in durations {
+        *buckets.entry(duration / bucket_width_us * bucket_width_us).or_insert(0) += 1;
+    }
+
+    // 明显长于平均mark的mark可能是引导码，多帧捕获中每帧都会有一个
+    let headers = durations
+        .iter()
+        .enumerate()
+        .step_by(2)
+        .filter(|&(_, &mark)| mark >= marks.mean * HEADER_MARK_RATIO)
+        .map(|(index, &mark)| HeaderCandidate {
+            index,
+            mark,
+            space: durations.get(index + 1).copied(),
+            protocols: DECODERS
+                .iter()
+                .filter(|decoder| decoder.matches_header(&durations[index..]))
+                .map(|decoder| decoder.protocol().name())
+                .collect(),
+        })
+        .collect();
+
+    PulseStats {
+        pulse_count: durations.len(),
+        total_us: durations.iter().map(|&duration| duration as u64).sum(),
+        marks,
+        spaces,
+        bucket_width_us,
+        histogram: buckets.into_iter().collect(),
+        headers,
+    }
+}
+
+impl PulseStats {
+    /// 序列化为紧凑的JSON
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        let _ = write!(out, "{{\"pulses\":{},\"total_us\":{},\"mark\":", self.pulse_count, self.total_us);
+        self.marks.write_json(&mut out);
+        out.push_str(",\"space\":");
+        self.spaces.write_json(&mut out);
+
+        let _ = write!(out, ",\"bucket_us\":{},\"histogram\":[", self.bucket_width_us);
+        for (i, (start, count)) in self.histogram.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "[{},{}]", start, count);
+        }
+
+        out.push_str("],\"headers\":[");
+        for (i, header) in self.headers.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(out, "{{\"index\":{},\"mark\":{},\"space\":", header.index, header.mark);
+            match header.space {
+                Some(space) => {
+                    let _ = write!(out, "{}", space);
+                }
+                None => out.push_str("null"),
+            }
+            out.push_str(",\"protocols\":[");
+            for (j, protocol) in header.protocols.iter().enumerate() {
+                if j > 0 {
+                    out.push(',');
+                }
+                let _ = write!(out, "\"{}\"", protocol);
+            }
+            out.push_str("]}");
+        }
+        out.push_str("]}");
+        out
+    }
+}
@@ -0,0 +1,473 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use super::format::crc32_update;
+use super::receiver::Capture;
+use super::storage::{canonical_path, check_name, split_path, MAX_NAME_LEN};
+
+/// 记录类型：一个录制，内容为名称长度、名称和序列化后的录制
+const RECORD_SLOT: u8 = 0x01;
+/// 记录类型：归档结束，内容为录制数量（u16）和之前所有记录的CRC32
+const RECORD_END: u8 = 0x02;
+/// 记录类型：导出中止，内容为UTF-8的原因
+const RECORD_ERROR: u8 = 0x7F;
+
+/// 记录头：类型（u8）和内容长度（u16，小端序）
+const RECORD_HEADER_LEN: usize = 3;
+
+/// 导入时超过该时间没有收到数据即放弃
+pub const IMPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 重命名时尝试的最大后缀
+const MAX_RENAME_SUFFIX: u32 = 99;
+
+/// 导入的录制与已有录制同名时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionPolicy {
+    /// 保留已有的录制，跳过导入的录制
+    Skip,
+    /// 覆盖已有的录制
+    #[default]
+    Overwrite,
+    /// 在按键名后加`_2`、`_3`等后缀另存
+    Rename,
+}
+
+impl CollisionPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "skip" => Some(Self::Skip),
+            "overwrite" => Some(Self::Overwrite),
+            "rename" => Some(Self::Rename),
+            _ => None,
+        }
+    }
+}
+
+/// 一个录制按处理方式得到的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// 没有同名的录制
+    New(String),
+    Overwrite(String),
+    /// 改名后保存，附带新名称
+    Renamed(String),
+    /// 保留已有的录制
+    Kept,
+    /// 名称无效，或者找不到可用的新名称
+    Invalid,
+}
+
+/// 生成导出归档的记录，同时累计CRC
+#[derive(Debug, Default)]
+pub struct ArchiveWriter {
+    crc: u32,
+    count: u16,
+}
+
+impl ArchiveWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 一个录制的记录，`bytes`是`Capture::to_bytes`的结果
+    pub fn slot(&mut self, name: &str, bytes: &[u8]) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(1 + name.len() + bytes.len());
+        payload.push(name.len() as u8);
+        payload.extend_from_slice(name.as_bytes());
+        payload.extend_from_slice(bytes);
+        let record = encode_record(RECORD_SLOT, &payload);
+        self.crc = crc32_update(self.crc, &record);
+        self.count += 1;
+        record
+    }
+
+    /// 结束记录，包含录制数量和之前所有记录的CRC32
+    pub fn finish(self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(6);
+        payload.extend_from_slice(&self.count.to_le_bytes());
+        payload.extend_from_slice(&self.crc.to_le_bytes());
+        encode_record(RECORD_END, &payload)
+    }
+}
+
+/// 导出中止时发送的记录
+pub fn error_record(reason: &str) -> Vec<u8> {
+    encode_record(RECORD_ERROR, reason.as_bytes())
+}
+
+fn encode_record(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    record.push(kind);
+    record.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+/// 导入时解析出的记录
+#[derive(Debug)]
+pub enum ImportRecord {
+    /// 校验通过的录制
+    Slot { name: String, capture: Capture },
+    /// 归档结束，`verified`表示录制数量和整体CRC都正确
+    End { verified: bool },
+    /// 发送方中止了导出
+    Aborted(String),
+}
+
+/// 接收导入的归档，数据可能在任意位置分段到达
+///
+/// 校验失败的录制被跳过并记下名称，导入结束后一起报告。
+/// 试导入时只统计每个录制的处理结果和需要的空间，不写入NVS。
+pub struct ArchiveImport {
+    buffer: Vec<u8>,
+    crc: u32,
+    count: u16,
+    policy: CollisionPolicy,
+    dry_run: bool,
+    /// 本次归档中已经处理过的规范名称，试导入时用来模拟已写入的录制
+    seen: HashSet<String>,
+    /// 按处理方式统计的录制数量
+    new: usize,
+    overwrite: usize,
+    rename: usize,
+    kept: Vec<String>,
+    /// 要写入的录制序列化后的字节数
+    bytes: usize,
+    saved: usize,
+    renamed: Vec<(String, String)>,
+    skipped: Vec<String>,
+    deadline: Instant,
+}
+
+impl ArchiveImport {
+    pub fn new(now: Instant, policy: CollisionPolicy, dry_run: bool) -> Self {
+        Self {
+            buffer: Vec::new(),
+            crc: 0,
+            count: 0,
+            policy,
+            dry_run,
+            seen: HashSet::new(),
+            new: 0,
+            overwrite: 0,
+            rename: 0,
+            kept: Vec::new(),
+            bytes: 0,
+            saved: 0,
+            renamed: Vec::new(),
+            skipped: Vec::new(),
+            deadline: now + IMPORT_TIMEOUT,
+        }
+    }
+
+    /// 是否为试导入
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// 按处理方式决定录制保存到哪个名称，`exists`检查NVS中是否已有该录制
+    ///
+    /// 结果计入统计；返回Kept或Invalid时录制不应保存，Invalid的名称已记为跳过。
+    pub fn resolve(&mut self, name: &str, capture: &Capture, exists: impl Fn(&str) -> bool) -> Resolution {
+        if check_name(name).is_err() {
+            self.skip(name);
+            return Resolution::Invalid;
+        }
+        let path = canonical_path(name);
+        let taken = |path: &str| self.seen.contains(path) || exists(path);
+        let resolution = match (taken(&path), self.policy) {
+            (false, _) => Resolution::New(path),
+            (true, CollisionPolicy::Overwrite) => Resolution::Overwrite(path),
+            (true, CollisionPolicy::Skip) => Resolution::Kept,
+            (true, CollisionPolicy::Rename) => match rename_with_suffix(&path, taken) {
+                Some(renamed) => Resolution::Renamed(renamed),
+                None => Resolution::Invalid,
+            },
+        };
+        let target = match &resolution {
+            Resolution::New(target) => {
+                self.new += 1;
+                target
+            }
+            Resolution::Overwrite(target) => {
+                self.overwrite += 1;
+                target
+            }
+            Resolution::Renamed(target) => {
+                self.rename += 1;
+                target
+            }
+            Resolution::Kept => {
+                self.kept.push(canonical_path(name));
+                return resolution;
+            }
+            Resolution::Invalid => {
+                self.skip(name);
+                return resolution;
+            }
+        };
+        self.seen.insert(target.clone());
+        // 无法序列化的录制保存时会失败，不占空间
+        self.bytes += capture.to_bytes().map_or(0, |bytes| bytes.len());
+        resolution
+    }
+
+    /// 记下已保存的录制，`name`是归档中的名称，`target`是实际保存的名称
+    pub fn saved(&mut self, name: &str, target: &str) {
+        self.saved += 1;
+        if canonical_path(name) != target {
+            self.renamed.push((name.to_string(), target.to_string()));
+        }
+    }
+
+    /// 输入收到的数据，返回其中完整的记录
+    pub fn push(&mut self, data: &[u8], now: Instant) -> Vec<ImportRecord> {
+        self.deadline = now + IMPORT_TIMEOUT;
+        self.buffer.extend_from_slice(data);
+
+        let mut records = Vec::new();
+        while self.buffer.len() >= RECORD_HEADER_LEN {
+            let len = u16::from_le_bytes([self.buffer[1], self.buffer[2]]) as usize;
+            if self.buffer.len() < RECORD_HEADER_LEN + len {
+                break;
+            }
+            let record: Vec<u8> = self.buffer.drain(..RECORD_HEADER_LEN + len).collect();
+            let payload = &record[RECORD_HEADER_LEN..];
+            match record[0] {
+                RECORD_SLOT => {
+                    self.crc = crc32_update(self.crc, &record);
+                    self.count = self.count.wrapping_add(1);
+                    match parse_slot(payload) {
+                        Ok((name, capture)) => records.push(ImportRecord::Slot { name, capture }),
+                        Err(name) => self.skip(&name),
+                    }
+                }
+                RECORD_END => {
+                    let verified = payload.len() == 6
+                        && payload[..2] == self.count.to_le_bytes()
+                        && payload[2..] == self.crc.to_le_bytes();
+                    records.push(ImportRecord::End { verified });
+                    break;
+                }
+                RECORD_ERROR => {
+                    records.push(ImportRecord::Aborted(String::from_utf8_lossy(payload).into_owned()));
+                    break;
+                }
+                kind => {
+                    // 新版本固件可能增加记录类型，按长度跳过
+                    log::warn!("跳过未知的归档记录类型: {:#04x}", kind);
+                    self.crc = crc32_update(self.crc, &record);
+                }
+            }
+        }
+        records
+    }
+
+    /// 记下没有导入的录制
+    pub fn skip(&mut self, name: &str) {
+        self.skipped.push(name.to_string());
+    }
+
+    /// 导入结果，例如`3 saved, 1 skipped: tv_power`，有保留或改名的录制时附加在后面
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} saved, {} skipped", self.saved, self.skipped.len());
+        if !self.skipped.is_empty() {
+            summary.push_str(": ");
+            summary.push_str(&self.skipped.join(","));
+        }
+        if !self.kept.is_empty() {
+            summary.push_str(&format!(", {} kept: {}", self.kept.len(), self.kept.join(",")));
+        }
+        if !self.renamed.is_empty() {
+            let renamed = self
+                .renamed
+                .iter()
+                .map(|(from, to)| format!("{}->{}", from, to))
+                .collect::<Vec<_>>();
+            summary.push_str(&format!(", {} renamed: {}", renamed.len(), renamed.join(",")));
+        }
+        summary
+    }
+
+    /// 试导入的结果，例如`2 new, 1 overwrite, 0 rename, 0 keep, 1 invalid: bad, 412 bytes`
+    pub fn dry_run_summary(&self) -> String {
+        let mut summary = format!(
+            "{} new, {} overwrite, {} rename, {} keep, {} invalid",
+            self.new,
+            self.overwrite,
+            self.rename,
+            self.kept.len(),
+            self.skipped.len()
+        );
+        if !self.skipped.is_empty() {
+            summary.push_str(": ");
+            summary.push_str(&self.skipped.join(","));
+        }
+        summary.push_str(&format!(", {} bytes", self.bytes));
+        summary
+    }
+
+    /// 要写入的录制序列化后的字节数
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// 是否已经超时
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now >= self.deadline
+    }
+}
+
+/// 解析录制记录，失败时返回能读出的名称
+fn parse_slot(payload: &[u8]) -> Result<(String, Capture), String> {
+    let Some((&name_len, rest)) = payload.split_first() else {
+        return Err(String::new());
+    };
+    if rest.len() < name_len as usize {
+        return Err(String::from_utf8_lossy(rest).into_owned());
+    }
+    let (name, bytes) = rest.split_at(name_len as usize);
+    let name = String::from_utf8_lossy(name).into_owned();
+    match Capture::from_bytes(bytes) {
+        Ok(capture) => Ok((name, capture)),
+        Err(e) => {
+            log::warn!("跳过录制{}: {}", name, e);
+            Err(name)
+        }
+    }
+}
+
+/// 在按键名后加`_2`、`_3`等后缀，按键名过长时截短，找不到可用的名称时返回None
+fn rename_with_suffix(path: &str, taken: impl Fn(&str) -> bool) -> Option<String> {
+    let (remote, key) = split_path(path);
+    (2..=MAX_RENAME_SUFFIX).find_map(|number| {
+        let suffix = format!("_{}", number);
+        let mut end = key.len().min(MAX_NAME_LEN - suffix.len());
+        while !key.is_char_boundary(end) {
+            end -= 1;
+        }
+        let renamed = canonical_path(&format!("{}/{}{}", remote, &key[..end], suffix));
+        (!taken(&renamed)).then_some(renamed)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture() -> Capture {
+        Capture::new(vec![9000, 4500, 560, 560, 560, 1690, 560])
+    }
+
+    /// 一个新录制、一个与`tv/power`同名的录制和一个CRC损坏的录制
+    fn archive() -> Vec<u8> {
+        let bytes = capture().to_bytes().unwrap();
+        let mut corrupted = bytes.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0x01;
+
+        let mut writer = ArchiveWriter::new();
+        let mut data = writer.slot("tv/volume", &bytes);
+        data.extend(writer.slot("tv/power", &bytes));
+        data.extend(writer.slot("tv/mute", &corrupted));
+        data.extend(writer.finish());
+        data
+    }
+
+    /// 按主循环的方式试导入，NVS中已有`tv/power`；返回导入状态、各录制的处理结果和结束记录
+    fn dry_run(policy: CollisionPolicy, data: &[u8]) -> (ArchiveImport, Vec<Resolution>, Option<bool>) {
+        let mut import = ArchiveImport::new(Instant::now(), policy, true);
+        let mut resolutions = Vec::new();
+        let mut end = None;
+        // 分段到达，记录跨越分段的边界
+        for chunk in data.chunks(7) {
+            for record in import.push(chunk, Instant::now()) {
+                match record {
+                    ImportRecord::Slot { name, capture } => {
+                        resolutions.push(import.resolve(&name, &capture, |path| path == "tv/power"));
+                    }
+                    ImportRecord::End { verified } => end = Some(verified),
+                    ImportRecord::Aborted(reason) => panic!("导出中止: {}", reason),
+                }
+            }
+        }
+        (import, resolutions, end)
+    }
+
+    fn capture_len() -> usize {
+        capture().to_bytes().unwrap().len()
+    }
+
+    #[test]
+    fn dry_run_overwrites_existing_slot() {
+        let (import, resolutions, end) = dry_run(CollisionPolicy::Overwrite, &archive());
+        assert_eq!(
+            resolutions,
+            [Resolution::New("tv/volume".to_string()), Resolution::Overwrite("tv/power".to_string())]
+        );
+        assert_eq!(end, Some(true));
+        assert_eq!(
+            import.dry_run_summary(),
+            format!("1 new, 1 overwrite, 0 rename, 0 keep, 1 invalid: tv/mute, {} bytes", 2 * capture_len())
+        );
+    }
+
+    #[test]
+    fn dry_run_keeps_existing_slot() {
+        let (import, resolutions, end) = dry_run(CollisionPolicy::Skip, &archive());
+        assert_eq!(resolutions, [Resolution::New("tv/volume".to_string()), Resolution::Kept]);
+        assert_eq!(end, Some(true));
+        assert_eq!(
+            import.dry_run_summary(),
+            format!("1 new, 0 overwrite, 0 rename, 1 keep, 1 invalid: tv/mute, {} bytes", capture_len())
+        );
+    }
+
+    #[test]
+    fn dry_run_renames_existing_slot() {
+        let (import, resolutions, end) = dry_run(CollisionPolicy::Rename, &archive());
+        assert_eq!(
+            resolutions,
+            [Resolution::New("tv/volume".to_string()), Resolution::Renamed("tv/power_2".to_string())]
+        );
+        assert_eq!(end, Some(true));
+        assert_eq!(
+            import.dry_run_summary(),
+            format!("1 new, 0 overwrite, 1 rename, 0 keep, 1 invalid: tv/mute, {} bytes", 2 * capture_len())
+        );
+    }
+
+    #[test]
+    fn skips_invalid_name() {
+        let bytes = capture().to_bytes().unwrap();
+        let mut writer = ArchiveWriter::new();
+        let mut data = writer.slot("tv/", &bytes);
+        data.extend(writer.finish());
+
+        let (import, resolutions, end) = dry_run(CollisionPolicy::Overwrite, &data);
+        assert_eq!(resolutions, [Resolution::Invalid]);
+        assert_eq!(end, Some(true));
+        assert_eq!(import.dry_run_summary(), "0 new, 0 overwrite, 0 rename, 0 keep, 1 invalid: tv/, 0 bytes");
+    }
+
+    #[test]
+    fn rejects_wrong_count_or_crc() {
+        let data = archive();
+        // 结束记录的内容：录制数量（u16）和CRC32
+        let count_at = data.len() - 6;
+
+        let mut wrong_count = data.clone();
+        wrong_count[count_at] ^= 0x01;
+        assert_eq!(dry_run(CollisionPolicy::Overwrite, &wrong_count).2, Some(false));
+
+        let mut wrong_crc = data.clone();
+        let last = wrong_crc.len() - 1;
+        wrong_crc[last] ^= 0x01;
+        assert_eq!(dry_run(CollisionPolicy::Overwrite, &wrong_crc).2, Some(false));
+
+        // 丢失一个录制时数量和CRC都不对
+        let slot_len = RECORD_HEADER_LEN + u16::from_le_bytes([data[1], data[2]]) as usize;
+        assert_eq!(dry_run(CollisionPolicy::Overwrite, &data[slot_len..]).2, Some(false));
+    }
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+
+use esp_idf_hal::rmt::{PinState, Pulse};
+
+use super::receiver::Capture;
+use super::{TickRate, MARK_LEVEL};
+
+/// 默认的最大捕获长度（脉冲对），足够容纳空调遥控器的长帧
+pub const DEFAULT_MAX_CAPTURE_PAIRS: usize = 1024;
+
+/// 捕获组装错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureError {
+    /// 超过最大捕获长度，`capture`只保留前面的部分
+    Truncated { capture: Capture, pulses: usize },
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated { pulses, .. } => write!(f, "捕获被截断，共{}个脉冲", pulses),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+/// 把多次receive()读到的分段拼接成完整捕获
+///
+/// 空闲阈值触发时RMT会在帧末写入0 tick的结束标记，只有读到结束标记才输出捕获。
+pub struct CaptureAssembler {
+    tick: TickRate,
+    max_pulses: usize,
+    durations: Vec<u32>,
+    last_level: Option<PinState>,
+    /// 当前帧已接收的脉冲总数，包括超出上限被丢弃的部分
+    pulses: usize,
+}
+
+impl CaptureAssembler {
+    pub fn new(tick: TickRate, max_pairs: usize) -> Self {
+        Self {
+            tick,
+            max_pulses: max_pairs * 2,
+            durations: Vec::new(),
+            last_level: None,
+            pulses: 0,
+        }
+    }
+
+    pub fn tick(&self) -> TickRate {
+        self.tick
+    }
+
+    /// 最大捕获长度（脉冲对）
+    pub fn max_pairs(&self) -> usize {
+        self.max_pulses / 2
+    }
+
+    /// 输入一段读取结果，帧结束时返回完整捕获
+    pub fn push(&mut self, pulses: &[(Pulse, Pulse)]) -> Option<Result<Capture, CaptureError>> {
+        for pulse in pulses.iter().flat_map(|(p0, p1)| [p0, p1]) {
+            let ticks = pulse.ticks.ticks();
+            if ticks == 0 {
+                return self.finish();
+            }
+            self.append(pulse.pin_state, self.tick.ticks_to_us(ticks));
+        }
+        None
+    }
+
+    /// 接收缓冲区放不下一帧时，连同已拼接的部分按截断处理
+    pub fn overflow(&mut self, pulses: &[(Pulse, Pulse)]) -> CaptureError {
+        for pulse in pulses.iter().flat_map(|(p0, p1)| [p0, p1]) {
+            let ticks = pulse.ticks.ticks();
+            if ticks == 0 {
+                break;
+            }
+            self.append(pulse.pin_state, self.tick.ticks_to_us(ticks));
+        }
+        let (capture, pulses) = self.take();
+        CaptureError::Truncated { capture, pulses }
+    }
+
+    fn append(&mut self, level: PinState, duration: u32) {
+        // 丢弃捕获开头可能出现的空闲电平
+        if self.last_level.is_none() && level != MARK_LEVEL {
+            return;
+        }
+
+        // 分段边界可能把同一个电平切成两段
+        if self.last_level == Some(level) {
+            if let Some(last) = self.durations.last_mut() {
+                *last += duration;
+            }
+            return;
+        }
+
+        self.last_level = Some(level);
+        self.pulses += 1;
+        if self.durations.len() < self.max_pulses {
+            self.durations.push(duration);
+        }
+    }
+
+    /// 结束当前帧
+    fn finish(&mut self) -> Option<Result<Capture, CaptureError>> {
+        let (capture, pulses) = self.take();
+        if pulses > self.max_pulses {
+            Some(Err(CaptureError::Truncated { capture, pulses }))
+        } else if capture.durations().is_empty() {
+            None
+        } else {
+            Some(Ok(capture))
+        }
+    }
+
+    /// 取出已拼接的捕获和脉冲总数，并重置状态
+    fn take(&mut self) -> (Capture, usize) {
+        self.last_level = None;
+        let capture = Capture::new(std::mem::take(&mut self.durations));
+        (capture, std::mem::replace(&mut self.pulses, 0))
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt;
+
+use super::receiver::{Capture, MIN_FRAME_GAP_US};
+
+/// 数据包类型：红外
+const KIND_IR: u8 = 0x26;
+/// 数据包类型：433MHz和315MHz射频
+const KIND_RF_433: u8 = 0xB2;
+const KIND_RF_315: u8 = 0xD7;
+
+/// 类型、重复次数和数据长度（u16，小端序）
+const HEADER_LEN: usize = 4;
+
+/// 数据结尾的标记，同时作为扩展编码时表示最后的帧间空闲（0x0D05个tick，约109ms）
+const TRAILER: [u8; 2] = [0x0D, 0x05];
+
+/// 整个数据包补零到该长度的整数倍
+const PACKET_ALIGN: usize = 16;
+
+/// 一个tick为269/8192毫秒（约32.84µs），以纳秒表示分子
+const TICK_NUMERATOR_NS: u64 = 269_000_000;
+const TICK_DENOMINATOR: u64 = 8192;
+
+/// 解析Broadlink数据包失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BroadlinkError {
+    InvalidBase64,
+    /// 射频数据包，附带类型字节
+    Rf(u8),
+    UnknownKind(u8),
+    /// 数据在中途结束
+    Truncated,
+    /// 没有时长，或重复后帧数过多
+    Malformed,
+}
+
+impl fmt::Display for BroadlinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidBase64 => write!(f, "base64格式错误"),
+            Self::Rf(kind) => write!(f, "射频数据包（类型{:#04x}），只支持红外", kind),
+            Self::UnknownKind(kind) => write!(f, "未知的数据包类型{:#04x}", kind),
+            Self::Truncated => write!(f, "数据包不完整"),
+            Self::Malformed => write!(f, "数据包内容无效"),
+        }
+    }
+}
+
+impl std::error::Error for BroadlinkError {}
+
+/// 解析base64编码的Broadlink数据包
+pub fn parse_base64(text: &str) -> Result<Capture, BroadlinkError> {
+    parse(&base64_decode(text).ok_or(BroadlinkError::InvalidBase64)?)
+}
+
+/// 解析Broadlink数据包，重复次数展开为多帧
+///
+/// 每个时长为一个字节的tick数，为0时后面两个字节（大端序）是较长时长的tick数。
+pub fn parse(packet: &[u8]) -> Result<Capture, BroadlinkError> {
+    let Some(&[kind, repeat, len_low, len_high]) = packet.get(..HEADER_LEN) else {
+        return Err(BroadlinkError::Truncated);
+    };
+    match kind {
+        KIND_IR => {}
+        KIND_RF_433 | KIND_RF_315 => return Err(BroadlinkError::Rf(kind)),
+        kind => return Err(BroadlinkError::UnknownKind(kind)),
+    }
+    let len = u16::from_le_bytes([len_low, len_high]) as usize;
+    let data = packet
+        .get(HEADER_LEN..HEADER_LEN + len)
+        .ok_or(BroadlinkError::Truncated)?;
+
+    let mut durations = Vec::new();
+    let mut rest = data;
+    while rest != TRAILER {
+        let (ticks, tail) = match rest {
+            [0, high, low, tail @ ..] => (u16::from_be_bytes([*high, *low]), tail),
+            [0, ..] => return Err(BroadlinkError::Truncated),
+            [ticks, tail @ ..] => (*ticks as u16, tail),
+            [] => break,
+        };
+        durations.push(ticks_to_us(ticks));
+        rest = tail;
+    }
+    if durations.is_empty() {
+        return Err(BroadlinkError::Malformed);
+    }
+
+    // 结尾的space作为重复时的帧间空闲
+    let gap_us = if durations.len() % 2 == 0 { durations.pop().unwrap_or(0) } else { 0 };
+    let mut capture = Capture::from_flat(durations, MIN_FRAME_GAP_US);
+    let once = capture.frames.clone();
+    if once.len() * (repeat as usize + 1) > u8::MAX as usize {
+        return Err(BroadlinkError::Malformed);
+    }
+    for _ in 0..repeat {
+        if let Some(last) = capture.frames.last_mut() {
+            last.gap_us = gap_us.max(MIN_FRAME_GAP_US);
+        }
+        capture.frames.extend_from_slice(&once);
+    }
+    Ok(capture)
+}
+
+/// 把录制编码为base64的Broadlink数据包
+pub fn to_base64(capture: &Capture) -> String {
+    base64_encode(&to_packet(capture))
+}
+
+/// 把录制编码为Broadlink数据包，重复次数为0，以`0x00 0x0D 0x05`结尾
+pub fn to_packet(capture: &Capture) -> Vec<u8> {
+    let mut data = Vec::new();
+    for duration in capture.flatten() {
+        match us_to_ticks(duration) {
+            ticks @ 1..=0xFF => data.push(ticks as u8),
+            ticks => {
+                data.push(0);
+                data.extend_from_slice(&ticks.to_be_bytes());
+            }
+        }
+    }
+    data.push(0);
+    data.extend_from_slice(&TRAILER);
+
+    let mut packet = Vec::with_capacity(HEADER_LEN + data.len() + PACKET_ALIGN);
+    packet.extend_from_slice(&[KIND_IR, 0]);
+    packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    packet.extend_from_slice(&data);
+    packet.resize(packet.len().div_ceil(PACKET_ALIGN) * PACKET_ALIGN, 0);
+    packet
+}
+
+fn ticks_to_us(ticks: u16) -> u32 {
+    ((ticks as u64 * TICK_NUMERATOR_NS / TICK_DENOMINATOR + 500) / 1000) as u32
+}
+
+/// 四舍五入到最近的tick，至少1个tick，超过两字节上限的时长被截断
+fn us_to_ticks(us: u32) -> u16 {
+    ((us as u64 * 1000 * TICK_DENOMINATOR + TICK_NUMERATOR_NS / 2) / TICK_NUMERATOR_NS).clamp(1, 0xFFFF) as u16
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let value = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |value, (index, &byte)| value | (byte as u32) << (16 - 8 * index));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(BASE64_ALPHABET[(value >> (18 - 6 * index) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// 解码base64，忽略空白，兼容URL安全字母表和省略的填充
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+        // 最多保留两个字符的位，避免移位溢出
+        buffer = (buffer << 6 | value as u32) & 0xFFF;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+
+use esp_idf_hal::delay::TickType;
+use esp_idf_hal::rmt::{Pulse, Receive, RxRmtDriver};
+use esp_idf_svc::sys::EspError;
+
+use super::TickRate;
+
+/// 测量通道的脉冲缓冲区大小，足够容纳一个9ms引导码内的全部载波周期
+pub const MEASURE_BUFFER_PAIRS: usize = 1024;
+
+/// 等待遥控器按键的时长
+const MEASURE_TIMEOUT_MS: u64 = 5000;
+
+/// 至少需要的完整载波周期数，太少时估计值不可靠
+const MIN_CYCLES: usize = 16;
+
+/// 常见载波频率范围之外的结果视为噪声
+const MIN_CARRIER_HZ: u32 = 20_000;
+const MAX_CARRIER_HZ: u32 = 100_000;
+
+/// 载波测量失败的原因
+#[derive(Debug)]
+pub enum CarrierError {
+    /// 没有接入未解调的接收管，解调后的信号里已经没有载波
+    NoRawReceiver,
+    Rmt(EspError),
+}
+
+impl fmt::Display for CarrierError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoRawReceiver => write!(f, "没有配置未解调的接收通道，无法测量载波"),
+            Self::Rmt(e) => write!(f, "RMT接收错误: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for CarrierError {}
+
+impl From<EspError> for CarrierError {
+    fn from(e: EspError) -> Self {
+        Self::Rmt(e)
+    }
+}
+
+/// 在未解调的接收管上采样载波，估计调制频率
+///
+/// 测量通道使用不分频的APB时钟，空闲阈值设为载波周期的几倍，每个载波突发作为一帧。
+pub struct CarrierMeter {
+    receiver: Option<RxRmtDriver<'static>>,
+    tick: TickRate,
+}
+
+impl CarrierMeter {
+    #[cfg(feature = "carrier-meter")]
+    pub fn new(receiver: RxRmtDriver<'static>, tick: TickRate) -> Self {
+        Self {
+            receiver: Some(receiver),
+            tick,
+        }
+    }
+
+    /// 当前硬件不支持测量，或测量通道创建失败
+    pub fn unavailable() -> Self {
+        Self {
+            receiver: None,
+            tick: TickRate::from_clock_divider(1),
+        }
+    }
+
+    /// 等待一次按键并测量载波频率（Hz），超时没有信号时返回None
+    pub fn measure_carrier(&mut self) -> Result<Option<u32>, CarrierError> {
+        let receiver = self.receiver.as_mut().ok_or(CarrierError::NoRawReceiver)?;
+
+        let mut pulses = vec![(Pulse::zero(), Pulse::zero()); MEASURE_BUFFER_PAIRS];
+        receiver.start()?;
+        let result = receiver.receive(&mut pulses, TickType::new_millis(MEASURE_TIMEOUT_MS).ticks());
+        receiver.stop()?;
+
+        match result? {
+            Receive::Read(count) | Receive::Overflow(count) => {
+                let count = count.min(pulses.len());
+                Ok(estimate_carrier(&pulses[..count], self.tick))
+            }
+            Receive::Timeout => Ok(None),
+        }
+    }
+}
+
+/// 用载波周期的中位数估计频率，结果取整到100Hz
+///
+/// 每个脉冲对是一个载波周期的高低电平；首尾两个周期可能不完整，不参与计算。
+pub fn estimate_carrier(pulses: &[(Pulse, Pulse)], tick: TickRate) -> Option<u32> {
+    let mut periods: Vec<u32> = pulses
+        .iter()
+        .map(|(high, low)| (high.ticks.ticks(), low.ticks.ticks()))
+        .take_while(|&(high, low)| high != 0 && low != 0)
+        .map(|(high, low)| high as u32 + low as u32)
+        .collect();
+
+    if periods.len() < MIN_CYCLES + 2 {
+        return None;
+    }
+    periods.pop();
+    periods.remove(0);
+    periods.sort_unstable();
+
+    let median = periods[periods.len() / 2];
+    let hz = (tick.hz() + median / 2) / median;
+    let hz = (hz + 50) / 100 * 100;
+    (MIN_CARRIER_HZ..=MAX_CARRIER_HZ).contains(&hz).then_some(hz)
+}
@@ -0,0 +1,295 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::{decode_pulse_distance, encode_pulse_distance, matches, CaptureEvent, Decoder, IrCommand, Protocol};
+
+// Denon/Sharp协议时序（微秒），没有引导码
+const BIT_MARK: u32 = 260;
+const ZERO_SPACE: u32 = 780;
+const ONE_SPACE: u32 = 1820;
+
+/// 数据位数量：5位地址、8位命令、2位扩展
+const DATA_BITS: usize = 15;
+const ADDRESS_BITS: u32 = 5;
+
+/// 第二帧取反的部分：命令和扩展位，共10位
+const INVERTED_MASK: u16 = 0x3FF;
+
+/// 扩展位的高位在第一帧中为0、在取反的第二帧中为1
+const INVERTED_FLAG: u16 = 0x200;
+
+/// 两帧起始之间的间隔
+const FRAME_PERIOD: u32 = 65_000;
+
+/// 第二帧必须在第一帧结束后该时间内开始
+const PAIR_WINDOW: Duration = Duration::from_millis(67);
+
+/// 第一帧结束后等待第二帧的时间，包括第二帧本身和接收端的空闲阈值
+const PAIR_TIMEOUT: Duration = Duration::from_millis(150);
+
+/// Denon/Sharp使用38kHz载波
+pub const CARRIER_HZ: u32 = 38_000;
+
+/// 解码后的Denon/Sharp命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DenonCommand {
+    /// 5位地址
+    pub address: u8,
+    pub command: u8,
+    /// 扩展位，Denon为0，Sharp为1
+    pub extension: u8,
+    pub check: DenonCheck,
+}
+
+/// 两帧校验的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenonCheck {
+    /// 只收到一帧，无法确认不是噪声
+    Unverified,
+    /// 取反发送的第二帧，内容已还原
+    Inverted,
+    /// 第二帧与第一帧互为反码
+    Verified,
+}
+
+impl DenonCommand {
+    /// 除校验状态外内容完全相同
+    pub fn same_code(&self, other: &DenonCommand) -> bool {
+        self.address == other.address && self.command == other.command && self.extension == other.extension
+    }
+}
+
+impl fmt::Display for DenonCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DENON addr={:#04x} cmd={:#04x} ext={}",
+            self.address, self.command, self.extension
+        )?;
+        if self.check != DenonCheck::Verified {
+            write!(f, " unverified")?;
+        }
+        Ok(())
+    }
+}
+
+/// 从微秒时长序列解码一帧，第二帧的命令和扩展位会还原为第一帧的值
+pub fn decode(durations: &[u32]) -> Option<DenonCommand> {
+    // 没有引导码，必须恰好是一帧数据才能与噪声区分
+    if durations.len() != DATA_BITS * 2 + 1 {
+        return None;
+    }
+
+    let raw = decode_pulse_distance(durations, DATA_BITS, BIT_MARK, ZERO_SPACE, ONE_SPACE)?;
+    let address = (raw & 0x1F) as u8;
+    let mut data = (raw >> ADDRESS_BITS) as u16 & INVERTED_MASK;
+    let check = if data & INVERTED_FLAG != 0 {
+        data = !data & INVERTED_MASK;
+        DenonCheck::Inverted
+    } else {
+        DenonCheck::Unverified
+    };
+
+    Some(DenonCommand {
+        address,
+        command: data as u8,
+        extension: (data >> 8) as u8,
+        check,
+    })
+}
+
+/// 编码一次按键：第一帧之后间隔到帧周期，再发送命令和扩展位取反的第二帧
+///
+/// 地址超过5位或扩展位不是0或1时返回None。
+pub fn encode_denon(address: u8, command: u8, extension: u8) -> Option<Vec<u32>> {
+    if address >= 1 << ADDRESS_BITS || extension > 1 {
+        return None;
+    }
+
+    let data = (extension as u16) << 8 | command as u16;
+    let frame = |data: u16| {
+        let raw = (data as u64) << ADDRESS_BITS | address as u64;
+        let mut durations = Vec::with_capacity(DATA_BITS * 2 + 1);
+        encode_pulse_distance(&mut durations, raw, DATA_BITS, BIT_MARK, ZERO_SPACE, ONE_SPACE);
+        durations
+    };
+
+    let mut durations = frame(data);
+    let first_frame_len: u32 = durations.iter().sum();
+    durations.push(FRAME_PERIOD - first_frame_len);
+    durations.extend(frame(!data & INVERTED_MASK));
+    Some(durations)
+}
+
+/// Denon/Sharp解码器，每次只解码一帧，两帧的校验由`DenonFramePairer`完成
+pub struct DenonDecoder;
+
+impl Decoder for DenonDecoder {
+    fn protocol(&self) -> Protocol {
+        Protocol::Denon
+    }
+
+    fn matches_header(&self, durations: &[u32]) -> bool {
+        durations.len() == DATA_BITS * 2 + 1 && matches(durations[0], BIT_MARK)
+    }
+
+    fn decode(&self, durations: &[u32]) -> Option<IrCommand> {
+        decode(durations).map(IrCommand::Denon)
+    }
+}
+
+/// 等待第二帧并校验：收到互为反码的第二帧后上报一次已校验的命令，
+/// 超时、第二帧不匹配或单独收到第二帧时按未校验上报
+#[derive(Default)]
+pub struct DenonFramePairer {
+    /// 等待第二帧的第一帧事件及其结束时刻
+    pending: Option<(CaptureEvent, Instant)>,
+}
+
+impl DenonFramePairer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 输入一个捕获事件及其开始、结束时刻，返回可以立即上报的事件
+    pub fn push(&mut self, mut event: CaptureEvent, started_at: Instant, ended_at: Instant) -> Vec<CaptureEvent> {
+        let mut ready = Vec::new();
+        let pending = self.pending.take();
+
+        let IrCommand::Denon(command) = &mut event.command else {
+            ready.extend(pending.map(|(pending, _)| pending));
+            ready.push(event);
+            return ready;
+        };
+
+        match command.check {
+            DenonCheck::Inverted => {
+                let paired = pending.and_then(|(mut pending, first_end)| {
+                    let in_window = started_at.saturating_duration_since(first_end) <= PAIR_WINDOW;
+                    match &mut pending.command {
+                        IrCommand::Denon(first) if in_window && first.same_code(command) => {
+                            first.check = DenonCheck::Verified;
+                            Some(pending)
+                        }
+                        _ => {
+                            log::debug!("Denon第二帧与第一帧不匹配");
+                            ready.push(pending);
+                            None
+                        }
+                    }
+                });
+                match paired {
+                    Some(pending) => ready.push(pending),
+                    None => {
+                        command.check = DenonCheck::Unverified;
+                        ready.push(event);
+                    }
+                }
+            }
+            _ => {
+                ready.extend(pending.map(|(pending, _)| pending));
+                self.pending = Some((event, ended_at));
+            }
+        }
+
+        ready
+    }
+
+    /// 等待超时后按未校验取出第一帧
+    pub fn poll(&mut self, now: Instant) -> Option<CaptureEvent> {
+        match &self.pending {
+            Some((_, first_end)) if now.saturating_duration_since(*first_end) >= PAIR_TIMEOUT => {
+                self.pending.take().map(|(pending, _)| pending)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 编码一次按键并拆成两帧的捕获事件
+    fn frames(address: u8, command: u8, extension: u8) -> (CaptureEvent, CaptureEvent) {
+        let durations = encode_denon(address, command, extension).unwrap();
+        let (first, second) = durations.split_at(DATA_BITS * 2 + 1);
+        (event(first), event(&second[1..]))
+    }
+
+    fn event(durations: &[u32]) -> CaptureEvent {
+        CaptureEvent {
+            pulse_count: durations.len(),
+            command: IrCommand::Denon(decode(durations).unwrap()),
+            truncated: false,
+            matched: None,
+        }
+    }
+
+    fn denon_of(event: &CaptureEvent) -> DenonCommand {
+        match &event.command {
+            IrCommand::Denon(command) => *command,
+            other => panic!("不是Denon命令: {:?}", other),
+        }
+    }
+
+    fn check_of(event: &CaptureEvent) -> DenonCheck {
+        denon_of(event).check
+    }
+
+    #[test]
+    fn decodes_inverted_second_frame() {
+        let (first, second) = frames(0x02, 0xE1, 1);
+        let (first, second) = (denon_of(&first), denon_of(&second));
+        assert_eq!(first.check, DenonCheck::Unverified);
+        assert_eq!(second.check, DenonCheck::Inverted);
+        assert!(first.same_code(&second));
+        assert_eq!((first.address, first.command, first.extension), (0x02, 0xE1, 1));
+    }
+
+    #[test]
+    fn verifies_matching_inverted_second_frame() {
+        let (first, second) = frames(0x02, 0xE1, 0);
+        let start = Instant::now();
+        let first_end = start + Duration::from_millis(15);
+        let mut pairer = DenonFramePairer::new();
+
+        assert!(pairer.push(first, start, first_end).is_empty());
+        let ready = pairer.push(second, start + Duration::from_millis(65), start + Duration::from_millis(80));
+        assert_eq!(ready.len(), 1);
+        assert_eq!(check_of(&ready[0]), DenonCheck::Verified);
+        assert_eq!(pairer.poll(start + Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn reports_mismatched_second_frame_unverified() {
+        let (first, _) = frames(0x02, 0xE1, 0);
+        let (_, other) = frames(0x02, 0x10, 0);
+        let start = Instant::now();
+        let mut pairer = DenonFramePairer::new();
+
+        pairer.push(first, start, start + Duration::from_millis(15));
+        let ready = pairer.push(other, start + Duration::from_millis(65), start + Duration::from_millis(80));
+        assert_eq!(ready.len(), 2);
+        assert!(ready.iter().all(|event| check_of(event) == DenonCheck::Unverified));
+    }
+
+    #[test]
+    fn reports_missing_second_frame_after_timeout() {
+        let (first, _) = frames(0x02, 0xE1, 0);
+        let start = Instant::now();
+        let first_end = start + Duration::from_millis(15);
+        let mut pairer = DenonFramePairer::new();
+
+        pairer.push(first, start, first_end);
+        assert_eq!(pairer.poll(first_end + PAIR_TIMEOUT - Duration::from_millis(1)), None);
+        let event = pairer.poll(first_end + PAIR_TIMEOUT).unwrap();
+        assert_eq!(check_of(&event), DenonCheck::Unverified);
+    }
+
+    #[test]
+    fn rejects_out_of_range_fields() {
+        assert_eq!(encode_denon(0x20, 0, 0), None);
+        assert_eq!(encode_denon(0, 0, 2), None);
+    }
+}
@@ -0,0 +1,379 @@
+use std::fmt::{self, Write};
+
+use super::denon::DenonCheck;
+use super::receiver::{Capture, Frame};
+use super::session::MAX_FRAMES;
+use super::transmitter::{MAX_CARRIER_HZ, MAX_RAW_DURATIONS, MAX_RAW_DURATION_US, MIN_CARRIER_HZ};
+use super::{detect_and_decode, IrCommand, NecVariant};
+
+/// 时长数组每行的数量
+const DURATIONS_PER_LINE: usize = 16;
+
+/// 读入时允许的最大嵌套层数
+const MAX_DEPTH: usize = 8;
+
+/// 读入JSON录制失败的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DumpError {
+    /// 不是有效的JSON，附带出错的字节位置
+    Syntax(usize),
+    /// 缺少必需的字段，附带字段名
+    MissingField(&'static str),
+    /// 字段取值无效，附带字段名
+    InvalidValue(&'static str),
+}
+
+impl fmt::Display for DumpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax(offset) => write!(f, "JSON格式错误，位置{}", offset),
+            Self::MissingField(field) => write!(f, "缺少字段{}", field),
+            Self::InvalidValue(field) => write!(f, "字段{}的取值无效", field),
+        }
+    }
+}
+
+/// 把录制写成缩进的JSON，用于比较两次录制的差异
+///
+/// 边写边输出，时长数组不会先拼成完整的字符串。
+pub fn write_json(out: &mut impl Write, name: &str, capture: &Capture) -> fmt::Result {
+    let command = detect_and_decode(capture.durations());
+    out.write_str("{\n")?;
+    write!(out, "  \"name\": ")?;
+    write_string(out, name)?;
+    write!(out, ",\n  \"protocol\": \"{}\",\n", command.protocol().name())?;
+    out.write_str("  \"decoded\": ")?;
+    write_decoded(out, &command)?;
+    out.write_str(",\n  \"carrier_hz\": ")?;
+    match capture.carrier_hz {
+        Some(carrier_hz) => write!(out, "{}", carrier_hz)?,
+        None => out.write_str("null")?,
+    }
+    out.write_str(",\n  \"timing\": ")?;
+    match capture.timing {
+        Some(timing) => write!(
+            out,
+            "{{\"frame_period_us\": {}, \"min_gap_us\": {}, \"repeat\": \"{:?}\"}}",
+            timing.frame_period_us, timing.min_gap_us, timing.repeat
+        )?,
+        None => out.write_str("null")?,
+    }
+    write!(out, ",\n  \"pulse_count\": {},\n  \"frames\": [", capture.pulse_count())?;
+    for (index, frame) in capture.frames.iter().enumerate() {
+        if index > 0 {
+            out.write_char(',')?;
+        }
+        write!(out, "\n    {{\n      \"gap_us\": {},\n      \"durations\": [", frame.gap_us)?;
+        for (line, durations) in frame.durations.chunks(DURATIONS_PER_LINE).enumerate() {
+            if line > 0 {
+                out.write_char(',')?;
+            }
+            out.write_str("\n        ")?;
+            for (index, duration) in durations.iter().enumerate() {
+                if index > 0 {
+                    out.write_str(", ")?;
+                }
+                write!(out, "{}", duration)?;
+            }
+        }
+        if !frame.durations.is_empty() {
+            out.write_str("\n      ")?;
+        }
+        out.write_str("]\n    }")?;
+    }
+    if !capture.frames.is_empty() {
+        out.write_str("\n  ")?;
+    }
+    out.write_str("]\n}\n")
+}
+
+/// 解码出的字段，无法识别时为指纹
+pub fn write_decoded(out: &mut impl Write, command: &IrCommand) -> fmt::Result {
+    match command {
+        IrCommand::Nec(command) => write!(
+            out,
+            "{{\"address\": {}, \"command\": {}, \"extended\": {}, \"repeat\": {}}}",
+            command.address,
+            command.command,
+            command.variant == NecVariant::Extended,
+            command.repeat
+        ),
+        IrCommand::Rc5(command) => write!(
+            out,
+            "{{\"address\": {}, \"command\": {}, \"toggle\": {}}}",
+            command.address, command.command, command.toggle
+        ),
+        IrCommand::Rc6(command) => write!(
+            out,
+            "{{\"mode\": {}, \"address\": {}, \"command\": {}, \"toggle\": {}}}",
+            command.mode, command.address, command.command, command.toggle
+        ),
+        IrCommand::Sirc(command) => {
+            write!(
+                out,
+                "{{\"device\": {}, \"command\": {}, \"bits\": {}, \"extended\": ",
+                command.device,
+                command.command,
+                command.bits.count()
+            )?;
+            match command.extended {
+                Some(extended) => write!(out, "{}", extended)?,
+                None => out.write_str("null")?,
+            }
+            write!(out, ", \"frame_count\": {}}}", command.frame_count)
+        }
+        IrCommand::Samsung(command) => write!(
+            out,
+            "{{\"address\": {}, \"command\": {}}}",
+            command.address, command.command
+        ),
+        IrCommand::Samsung36(command) => write!(
+            out,
+            "{{\"address\": {}, \"data\": {}}}",
+            command.address, command.data
+        ),
+        IrCommand::Kaseikyo(command) => write!(
+            out,
+            "{{\"vendor\": {}, \"device\": {}, \"subdevice\": {}, \"command\": {}}}",
+            command.vendor, command.device, command.subdevice, command.command
+        ),
+        IrCommand::Jvc(command) => write!(
+            out,
+            "{{\"address\": {}, \"command\": {}, \"repeated\": {}}}",
+            command.address, command.command, command.repeated
+        ),
+        IrCommand::Lg(command) => {
+            write!(out, "{{\"raw\": {}, \"checksum_ok\": {}, \"ac\": ", command.raw, command.checksum_ok)?;
+            match command.ac {
+                Some(ac) => write!(
+                    out,
+                    "{{\"power\": {}, \"mode\": \"{:?}\", \"temperature\": {}, \"fan\": \"{:?}\"}}}}",
+                    ac.power, ac.mode, ac.temperature, ac.fan
+                ),
+                None => out.write_str("null}"),
+            }
+        }
+        IrCommand::Denon(command) => write!(
+            out,
+            "{{\"address\": {}, \"command\": {}, \"extension\": {}, \"verified\": {}}}",
+            command.address,
+            command.command,
+            command.extension,
+            command.check != DenonCheck::Unverified
+        ),
+        IrCommand::Raw { fingerprint, .. } => write!(out, "{{\"fingerprint\": {}}}", fingerprint),
+    }
+}
+
+/// 写入带引号的字符串，转义引号、反斜杠和控制字符
+pub fn write_string(out: &mut impl Write, text: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for c in text.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+/// 读入`write_json`生成的录制，只使用`frames`和`carrier_hz`，其他字段由时长重新计算
+pub fn parse_json(text: &str) -> Result<Capture, DumpError> {
+    let mut parser = Parser { text: text.as_bytes(), pos: 0 };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.pos != parser.text.len() {
+        return Err(DumpError::Syntax(parser.pos));
+    }
+    let Value::Object(fields) = value else {
+        return Err(DumpError::InvalidValue("capture"));
+    };
+
+    let carrier_hz = match field(&fields, "carrier_hz") {
+        None | Some(Value::Null) => None,
+        Some(value) => Some(
+            value
+                .as_u32()
+                .filter(|hz| (MIN_CARRIER_HZ..=MAX_CARRIER_HZ).contains(hz))
+                .ok_or(DumpError::InvalidValue("carrier_hz"))?,
+        ),
+    };
+    let Value::Array(items) = field(&fields, "frames").ok_or(DumpError::MissingField("frames"))? else {
+        return Err(DumpError::InvalidValue("frames"));
+    };
+    if items.is_empty() || items.len() > MAX_FRAMES {
+        return Err(DumpError::InvalidValue("frames"));
+    }
+    let mut frames = Vec::with_capacity(items.len());
+    for item in items {
+        let Value::Object(frame) = item else {
+            return Err(DumpError::InvalidValue("frames"));
+        };
+        let gap_us = match field(frame, "gap_us") {
+            None => 0,
+            Some(value) => value.as_u32().ok_or(DumpError::InvalidValue("gap_us"))?,
+        };
+        let Value::Array(values) = field(frame, "durations").ok_or(DumpError::MissingField("durations"))? else {
+            return Err(DumpError::InvalidValue("durations"));
+        };
+        let durations = values
+            .iter()
+            .map(|value| value.as_u32().filter(|duration| (1..=MAX_RAW_DURATION_US).contains(duration)))
+            .collect::<Option<Vec<_>>>()
+            .filter(|durations| !durations.is_empty())
+            .ok_or(DumpError::InvalidValue("durations"))?;
+        frames.push(Frame { durations, gap_us });
+    }
+    if frames.iter().map(|frame| frame.durations.len()).sum::<usize>() > MAX_RAW_DURATIONS {
+        return Err(DumpError::InvalidValue("durations"));
+    }
+    Ok(Capture {
+        frames,
+        carrier_hz,
+        timing: None,
+    })
+}
+
+/// 解析出的JSON值，数字只保留原文，用到时再转换
+enum Value<'a> {
+    Null,
+    Bool,
+    Number(&'a str),
+    String,
+    Array(Vec<Value<'a>>),
+    Object(Vec<(String, Value<'a>)>),
+}
+
+impl Value<'_> {
+    fn as_u32(&self) -> Option<u32> {
+        match self {
+            Self::Number(text) => text.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+fn field<'v, 'a>(fields: &'v [(String, Value<'a>)], name: &str) -> Option<&'v Value<'a>> {
+    fields.iter().find(|(key, _)| key == name).map(|(_, value)| value)
+}
+
+/// 只为读入录制实现的JSON解析器，字符串内容除了对象的键都丢弃
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn value(&mut self, depth: usize) -> Result<Value<'a>, DumpError> {
+        if depth > MAX_DEPTH {
+            return Err(DumpError::Syntax(self.pos));
+        }
+        self.skip_whitespace();
+        match self.text.get(self.pos) {
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if self.eat(b'}') {
+                    return Ok(Value::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(b':')?;
+                    fields.push((key, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    if self.eat(b'}') {
+                        return Ok(Value::Object(fields));
+                    }
+                    self.expect(b',')?;
+                }
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.eat(b']') {
+                    return Ok(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    if self.eat(b']') {
+                        return Ok(Value::Array(items));
+                    }
+                    self.expect(b',')?;
+                }
+            }
+            Some(b'"') => self.string().map(|_| Value::String),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b't') => self.literal("true", Value::Bool),
+            Some(b'f') => self.literal("false", Value::Bool),
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.pos;
+                while matches!(self.text.get(self.pos), Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+                    self.pos += 1;
+                }
+                // 数字只由ASCII字符组成
+                let number = std::str::from_utf8(&self.text[start..self.pos]).map_err(|_| DumpError::Syntax(start))?;
+                Ok(Value::Number(number))
+            }
+            _ => Err(DumpError::Syntax(self.pos)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, DumpError> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            match self.text.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return String::from_utf8(bytes).map_err(|_| DumpError::Syntax(self.pos));
+                }
+                Some(b'\\') => {
+                    // 转义字符只需要跳过，键名中不会出现
+                    bytes.push(*self.text.get(self.pos + 1).ok_or(DumpError::Syntax(self.pos))?);
+                    self.pos += 2;
+                }
+                Some(&byte) if byte >= 0x20 => {
+                    bytes.push(byte);
+                    self.pos += 1;
+                }
+                _ => return Err(DumpError::Syntax(self.pos)),
+            }
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value<'a>) -> Result<Value<'a>, DumpError> {
+        if !self.text[self.pos..].starts_with(word.as_bytes()) {
+            return Err(DumpError::Syntax(self.pos));
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.text.get(self.pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let matched = self.text.get(self.pos) == Some(&byte);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), DumpError> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err(DumpError::Syntax(self.pos))
+        }
+    }
+}
@@ -0,0 +1,67 @@
+/// 默认的软件毛刺滤波阈值（微秒）
+pub const DEFAULT_MIN_PULSE_US: u32 = 100;
+
+/// 软件滤波阈值上限，再大会吃掉正常协议的最短脉冲
+pub const MAX_MIN_PULSE_US: u32 = 400;
+
+/// 去除短于`min_pulse_us`的毛刺
+///
+/// 毛刺会把一个电平切成两段，因此把毛刺和它后面的一段一起并回前一个电平，
+/// mark与space的交替顺序保持不变。阈值为0时不做处理。
+pub fn remove_glitches(durations: &[u32], min_pulse_us: u32) -> Vec<u32> {
+    let mut cleaned: Vec<u32> = Vec::with_capacity(durations.len());
+    let mut iter = durations.iter().copied();
+
+    while let Some(duration) = iter.next() {
+        if duration >= min_pulse_us {
+            cleaned.push(duration);
+            continue;
+        }
+
+        match cleaned.last_mut() {
+            // 毛刺和后一段与前一个电平相同，合并为一段
+            Some(previous) => *previous += duration + iter.next().unwrap_or(0),
+            // 开头的毛刺mark连同后面的space一起丢弃，保证捕获仍从mark开始
+            None => {
+                iter.next();
+            }
+        }
+    }
+
+    cleaned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_short_spikes() {
+        // 560µs的space中间出现30µs的毛刺mark，被切成了270和260两段
+        let durations = [9000, 4500, 560, 270, 30, 260, 560, 1690, 560];
+        assert_eq!(
+            remove_glitches(&durations, DEFAULT_MIN_PULSE_US),
+            [9000, 4500, 560, 560, 560, 1690, 560]
+        );
+    }
+
+    #[test]
+    fn removes_leading_spike() {
+        let durations = [30, 2000, 9000, 4500, 560];
+        assert_eq!(remove_glitches(&durations, DEFAULT_MIN_PULSE_US), [9000, 4500, 560]);
+    }
+
+    #[test]
+    fn keeps_real_pulses() {
+        let durations = [9000, 4500, 560, 560, 560, 1690, 560];
+        assert_eq!(remove_glitches(&durations, DEFAULT_MIN_PULSE_US), durations);
+        // 恰好等于阈值的脉冲保留
+        assert_eq!(remove_glitches(&[100, 100, 100], 100), [100, 100, 100]);
+    }
+
+    #[test]
+    fn zero_threshold_keeps_everything() {
+        let durations = [9000, 30, 30, 560];
+        assert_eq!(remove_glitches(&durations, 0), durations);
+    }
+}
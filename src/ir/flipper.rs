@@ -0,0 +1,298 @@
+use std::fmt;
+use std::time::Instant;
+
+use super::archive::IMPORT_TIMEOUT;
+use super::nec::{encode_nec, encode_nec_ext};
+use super::rc5::encode_rc5;
+use super::receiver::{Capture, MIN_FRAME_GAP_US};
+use super::samsung::encode_samsung;
+use super::sirc::{self, encode_sirc, SircBits};
+use super::transmitter::DEFAULT_CARRIER_HZ;
+use super::{detect_and_decode, IrCommand, NecVariant};
+
+/// 文件头，Flipper要求以这两行开始
+const FILE_HEADER: &str = "Filetype: IR signals file\nVersion: 1\n";
+
+/// Flipper录制原始信号时使用的默认占空比
+const DEFAULT_DUTY_CYCLE: &str = "0.330000";
+
+/// 通过蓝牙导入时，单独一行的结束标记
+const END_MARKER: &str = "END";
+
+/// 解析信号失败的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlipperError {
+    /// 缺少必需的字段，附带字段名
+    MissingField(&'static str),
+    /// 字段取值无法解析，附带字段名
+    InvalidValue(&'static str),
+    /// 不是raw或parsed类型
+    UnsupportedType(String),
+    /// 无法编码的协议
+    UnsupportedProtocol(String),
+    /// 地址或命令超出协议的位数
+    OutOfRange,
+}
+
+impl fmt::Display for FlipperError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingField(field) => write!(f, "缺少字段{}", field),
+            Self::InvalidValue(field) => write!(f, "字段{}的取值无效", field),
+            Self::UnsupportedType(kind) => write!(f, "不支持的信号类型{}", kind),
+            Self::UnsupportedProtocol(protocol) => write!(f, "不支持的协议{}", protocol),
+            Self::OutOfRange => write!(f, "地址或命令超出协议范围"),
+        }
+    }
+}
+
+impl std::error::Error for FlipperError {}
+
+/// 文件中的一个信号，解析失败的信号保留名称以便报告
+#[derive(Debug)]
+pub struct Signal {
+    pub name: String,
+    pub capture: Result<Capture, FlipperError>,
+}
+
+/// 把若干录制渲染为Flipper的.ir文件
+pub fn to_file<'a>(signals: impl IntoIterator<Item = (&'a str, &'a Capture)>) -> String {
+    let mut text = FILE_HEADER.to_string();
+    for (name, capture) in signals {
+        text.push_str("# \n");
+        text.push_str(&render_signal(name, capture));
+    }
+    text
+}
+
+/// 能识别为Flipper支持的协议时输出parsed类型，否则输出raw类型
+fn render_signal(name: &str, capture: &Capture) -> String {
+    match parsed_fields(capture) {
+        Some((protocol, address, command)) => format!(
+            "name: {}\ntype: parsed\nprotocol: {}\naddress: {}\ncommand: {}\n",
+            name,
+            protocol,
+            hex_bytes(address),
+            hex_bytes(command)
+        ),
+        None => {
+            let data = capture
+                .flatten()
+                .iter()
+                .map(|duration| duration.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!(
+                "name: {}\ntype: raw\nfrequency: {}\nduty_cycle: {}\ndata: {}\n",
+                name,
+                capture.carrier_hz.unwrap_or(DEFAULT_CARRIER_HZ),
+                DEFAULT_DUTY_CYCLE,
+                data
+            )
+        }
+    }
+}
+
+/// Flipper的协议名、地址和命令，本设备无法按原样重新编码的命令返回None
+fn parsed_fields(capture: &Capture) -> Option<(&'static str, u32, u32)> {
+    match detect_and_decode(capture.durations()) {
+        IrCommand::Nec(command) if command.repeat => None,
+        IrCommand::Nec(command) => match command.variant {
+            NecVariant::Standard => Some(("NEC", command.address as u32, command.command as u32)),
+            NecVariant::Extended => Some((
+                "NECext",
+                command.address as u32,
+                u16::from_le_bytes([command.command, !command.command]) as u32,
+            )),
+        },
+        IrCommand::Samsung(command) => {
+            let [low, high] = command.address.to_le_bytes();
+            (low == high).then_some(("Samsung32", low as u32, command.command as u32))
+        }
+        IrCommand::Rc5(command) if command.command > 0x3F => {
+            Some(("RC5X", command.address as u32, (command.command & 0x3F) as u32))
+        }
+        IrCommand::Rc5(command) => Some(("RC5", command.address as u32, command.command as u32)),
+        IrCommand::Sirc(command) if command.extended.unwrap_or(0) == 0 => {
+            let protocol = match command.bits {
+                SircBits::Twelve => "SIRC",
+                SircBits::Fifteen => "SIRC15",
+                SircBits::Twenty => "SIRC20",
+            };
+            Some((protocol, command.device as u32, command.command as u32))
+        }
+        _ => None,
+    }
+}
+
+/// 按小端序输出4个十六进制字节，例如`04 00 00 00`
+fn hex_bytes(value: u32) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 解析.ir文件，每个`name:`开始一个信号
+///
+/// 兼容CRLF换行，`#`开头的注释行和空行被忽略，第一个信号之前的文件头不做检查。
+pub fn parse(text: &str) -> Vec<Signal> {
+    let mut signals = Vec::new();
+    let mut current: Option<(String, Vec<(String, String)>)> = None;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            log::warn!("跳过无法识别的行: {}", line);
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if key == "name" {
+            if let Some((name, fields)) = current.take() {
+                signals.push(build_signal(name, &fields));
+            }
+            current = Some((value.to_string(), Vec::new()));
+        } else if let Some((_, fields)) = current.as_mut() {
+            fields.push((key.to_string(), value.to_string()));
+        }
+    }
+    if let Some((name, fields)) = current {
+        signals.push(build_signal(name, &fields));
+    }
+    signals
+}
+
+fn build_signal(name: String, fields: &[(String, String)]) -> Signal {
+    let field = |key: &str| fields.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+    // raw信号的数据可能分成多行data
+    let data = fields
+        .iter()
+        .filter(|(k, _)| k == "data")
+        .map(|(_, v)| v.as_str())
+        .collect::<Vec<_>>();
+    let capture = match field("type") {
+        None => Err(FlipperError::MissingField("type")),
+        Some("raw") => parse_raw(field("frequency"), &data),
+        Some("parsed") => parse_parsed(field("protocol"), field("address"), field("command")),
+        Some(kind) => Err(FlipperError::UnsupportedType(kind.to_string())),
+    };
+    Signal { name, capture }
+}
+
+fn parse_raw(frequency: Option<&str>, data: &[&str]) -> Result<Capture, FlipperError> {
+    let carrier_hz = match frequency {
+        Some(frequency) => frequency.parse().map_err(|_| FlipperError::InvalidValue("frequency"))?,
+        None => DEFAULT_CARRIER_HZ,
+    };
+    if data.is_empty() {
+        return Err(FlipperError::MissingField("data"));
+    }
+    let durations = data
+        .iter()
+        .flat_map(|line| line.split_whitespace())
+        .map(|word| word.parse::<u32>().ok().filter(|&duration| duration > 0))
+        .collect::<Option<Vec<_>>>()
+        .filter(|durations| !durations.is_empty())
+        .ok_or(FlipperError::InvalidValue("data"))?;
+
+    // 按较长的space拆分成多帧，与接收到的多帧捕获保持一致
+    Ok(Capture {
+        carrier_hz: Some(carrier_hz),
+        ..Capture::from_flat(durations, MIN_FRAME_GAP_US)
+    })
+}
+
+fn parse_parsed(protocol: Option<&str>, address: Option<&str>, command: Option<&str>) -> Result<Capture, FlipperError> {
+    let protocol = protocol.ok_or(FlipperError::MissingField("protocol"))?;
+    let address = parse_hex_bytes(address.ok_or(FlipperError::MissingField("address"))?)
+        .ok_or(FlipperError::InvalidValue("address"))?;
+    let command = parse_hex_bytes(command.ok_or(FlipperError::MissingField("command"))?)
+        .ok_or(FlipperError::InvalidValue("command"))?;
+    let byte = |value: u32| u8::try_from(value).map_err(|_| FlipperError::OutOfRange);
+
+    let (durations, carrier_hz) = match protocol {
+        "NEC" => (encode_nec(byte(address)?, byte(command)?), DEFAULT_CARRIER_HZ),
+        "NECext" => {
+            let address = u16::try_from(address).map_err(|_| FlipperError::OutOfRange)?;
+            let command = u16::try_from(command).map_err(|_| FlipperError::OutOfRange)?;
+            (encode_nec_ext(address, command), DEFAULT_CARRIER_HZ)
+        }
+        "Samsung32" => (encode_samsung(byte(address)? as u16, byte(command)?), DEFAULT_CARRIER_HZ),
+        "RC5" | "RC5X" => {
+            let command = match protocol {
+                "RC5X" if command <= 0x3F => command | 0x40,
+                "RC5X" => return Err(FlipperError::OutOfRange),
+                _ => command,
+            };
+            let durations = encode_rc5(byte(address)?, byte(command)?, false).ok_or(FlipperError::OutOfRange)?;
+            (durations, DEFAULT_CARRIER_HZ)
+        }
+        "SIRC" | "SIRC15" | "SIRC20" => {
+            let bits = match protocol {
+                "SIRC" => SircBits::Twelve,
+                "SIRC15" => SircBits::Fifteen,
+                _ => SircBits::Twenty,
+            };
+            let durations = encode_sirc(byte(address)?, byte(command)?, bits).ok_or(FlipperError::OutOfRange)?;
+            (durations, sirc::CARRIER_HZ)
+        }
+        _ => return Err(FlipperError::UnsupportedProtocol(protocol.to_string())),
+    };
+
+    Ok(Capture {
+        carrier_hz: Some(carrier_hz),
+        ..Capture::new(durations)
+    })
+}
+
+/// 解析空格分隔的十六进制字节，按小端序组成整数，最多4个字节
+fn parse_hex_bytes(text: &str) -> Option<u32> {
+    let bytes = text
+        .split_whitespace()
+        .map(|word| u8::from_str_radix(word, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    if bytes.is_empty() || bytes.len() > 4 {
+        return None;
+    }
+    Some(bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as u32))
+}
+
+/// 通过蓝牙接收的.ir文件，以单独一行`END`结束
+pub struct FlipperImport {
+    text: Vec<u8>,
+    deadline: Instant,
+}
+
+impl FlipperImport {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            text: Vec::new(),
+            deadline: now + IMPORT_TIMEOUT,
+        }
+    }
+
+    /// 输入收到的数据，收到结束标记时返回结束标记之前的全部内容
+    pub fn push(&mut self, data: &[u8], now: Instant) -> Option<String> {
+        self.deadline = now + IMPORT_TIMEOUT;
+        self.text.extend_from_slice(data);
+        let text = String::from_utf8_lossy(&self.text);
+        let mut offset = 0;
+        for line in text.split_inclusive('\n') {
+            // 最后一行没有换行时也可能是结束标记
+            if line.trim() == END_MARKER {
+                return Some(text[..offset].to_string());
+            }
+            offset += line.len();
+        }
+        None
+    }
+
+    /// 是否已经超时
+    pub fn is_expired(&self, now: Instant) -> bool {
+        now >= self.deadline
+    }
+}
@@ -0,0 +1,338 @@
+use std::fmt;
+
+use super::receiver::{Capture, Frame};
+use super::timing::{ProtocolTiming, RepeatStyle};
+use super::{detect_and_decode, Protocol};
+
+/// 序列化内容开头的魔数
+const MAGIC: [u8; 4] = *b"IRCP";
+
+/// 当前的格式版本
+pub const FORMAT_VERSION: u8 = 1;
+
+/// 魔数、版本、协议编号、载波、时序标记和帧数
+const MIN_HEADER_LEN: usize = 12;
+
+/// 结尾的CRC32
+const CRC_LEN: usize = 4;
+
+/// 差值超出i16范围时写入该标记，后面跟完整的u32时长
+const ESCAPE: i16 = i16::MIN;
+
+/// 反序列化失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatError {
+    /// 开头不是魔数，不是本格式的内容
+    BadMagic,
+    /// 由更新版本的固件写入
+    UnsupportedVersion(u8),
+    CrcMismatch,
+    /// 内容在中途结束
+    Truncated,
+    /// CRC正确但字段取值无效
+    Malformed,
+    /// 帧数超过帧数字段能表示的范围，附带实际帧数
+    TooManyFrames(usize),
+    /// 一帧的时长数量超过数量字段能表示的范围，附带实际数量
+    FrameTooLong(usize),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "魔数不匹配"),
+            Self::UnsupportedVersion(version) => write!(f, "不支持的格式版本{}", version),
+            Self::CrcMismatch => write!(f, "CRC校验失败"),
+            Self::Truncated => write!(f, "内容不完整"),
+            Self::Malformed => write!(f, "字段取值无效"),
+            Self::TooManyFrames(count) => write!(f, "有{}帧，最多{}帧", count, u8::MAX),
+            Self::FrameTooLong(count) => write!(f, "一帧有{}个时长，最多{}个", count, u16::MAX),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl Capture {
+    /// 序列化：魔数、版本、协议编号、载波、发射时序、帧数，然后是每帧的帧间空闲、
+    /// 时长数量和时长，最后是之前所有字节的CRC32
+    ///
+    /// 时长与前一个同类（mark或space）时长的差值能放进i16时只占两个字节。
+    /// 帧数超过255或一帧超过65535个时长时返回错误，不截断。
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FormatError> {
+        let frame_count = u8::try_from(self.frames.len()).map_err(|_| FormatError::TooManyFrames(self.frames.len()))?;
+        let mut bytes = Vec::with_capacity(MIN_HEADER_LEN + 9 + self.pulse_count() * 2 + CRC_LEN);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(FORMAT_VERSION);
+        bytes.push(detect_and_decode(self.durations()).protocol().id());
+        bytes.extend_from_slice(&self.carrier_hz.unwrap_or(0).to_le_bytes());
+        match self.timing {
+            Some(timing) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&timing.frame_period_us.to_le_bytes());
+                bytes.extend_from_slice(&timing.min_gap_us.to_le_bytes());
+                bytes.push(repeat_style_id(timing.repeat));
+            }
+            None => bytes.push(0),
+        }
+        bytes.push(frame_count);
+        for frame in &self.frames {
+            let count = frame.durations.len();
+            let count = u16::try_from(count).map_err(|_| FormatError::FrameTooLong(count))?;
+            bytes.extend_from_slice(&frame.gap_us.to_le_bytes());
+            bytes.extend_from_slice(&count.to_le_bytes());
+            encode_durations(&mut bytes, &frame.durations);
+        }
+        let crc = crc32(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        Ok(bytes)
+    }
+
+    /// 反序列化`to_bytes`的结果
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut reader = Reader::new(&verify(bytes)?[MAGIC.len() + 2..]);
+        let carrier_hz = Some(reader.u32()?).filter(|&hz| hz != 0);
+        let timing = match reader.u8()? {
+            0 => None,
+            1 => Some(ProtocolTiming {
+                frame_period_us: reader.u32()?,
+                min_gap_us: reader.u32()?,
+                repeat: repeat_style_from_id(reader.u8()?).ok_or(FormatError::Malformed)?,
+            }),
+            _ => return Err(FormatError::Malformed),
+        };
+        let frame_count = reader.u8()?;
+        let frames = (0..frame_count)
+            .map(|_| {
+                let gap_us = reader.u32()?;
+                let count = reader.u16()? as usize;
+                let durations = decode_durations(&mut reader, count)?;
+                Ok(Frame { durations, gap_us })
+            })
+            .collect::<Result<Vec<_>, FormatError>>()?;
+        if frames.is_empty() || !reader.bytes.is_empty() {
+            return Err(FormatError::Malformed);
+        }
+        Ok(Self {
+            frames,
+            carrier_hz,
+            timing,
+        })
+    }
+}
+
+/// 读取序列化时识别出的协议，不解析时长
+pub fn stored_protocol(bytes: &[u8]) -> Result<Protocol, FormatError> {
+    Ok(Protocol::from_id(verify(bytes)?[MAGIC.len() + 1]))
+}
+
+/// 检查魔数、版本和CRC，返回去掉CRC后的内容
+fn verify(bytes: &[u8]) -> Result<&[u8], FormatError> {
+    if !bytes.starts_with(&MAGIC[..bytes.len().min(MAGIC.len())]) {
+        return Err(FormatError::BadMagic);
+    }
+    match bytes.get(MAGIC.len()) {
+        None => return Err(FormatError::Truncated),
+        Some(&version) if version != FORMAT_VERSION => return Err(FormatError::UnsupportedVersion(version)),
+        Some(_) => {}
+    }
+    if bytes.len() < MIN_HEADER_LEN + CRC_LEN {
+        return Err(FormatError::Truncated);
+    }
+    let (content, crc) = bytes.split_at(bytes.len() - CRC_LEN);
+    if crc32(content).to_le_bytes() != crc {
+        return Err(FormatError::CrcMismatch);
+    }
+    Ok(content)
+}
+
+/// IEEE 802.3 CRC32（与zlib相同）
+pub fn crc32(bytes: &[u8]) -> u32 {
+    crc32_update(0, bytes)
+}
+
+/// 在之前的CRC32上继续计算，`crc32_update(crc32(a), b)`等于`a`与`b`拼接后的CRC32
+pub fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn encode_durations(bytes: &mut Vec<u8>, durations: &[u32]) {
+    let mut previous = [0u32; 2];
+    for (index, &duration) in durations.iter().enumerate() {
+        let delta = duration as i64 - previous[index % 2] as i64;
+        match i16::try_from(delta) {
+            Ok(delta) if delta != ESCAPE => bytes.extend_from_slice(&delta.to_le_bytes()),
+            _ => {
+                bytes.extend_from_slice(&ESCAPE.to_le_bytes());
+                bytes.extend_from_slice(&duration.to_le_bytes());
+            }
+        }
+        previous[index % 2] = duration;
+    }
+}
+
+fn decode_durations(reader: &mut Reader, count: usize) -> Result<Vec<u32>, FormatError> {
+    let mut previous = [0u32; 2];
+    let mut durations = Vec::with_capacity(count);
+    for index in 0..count {
+        let duration = match reader.u16()? as i16 {
+            ESCAPE => reader.u32()?,
+            delta => u32::try_from(previous[index % 2] as i64 + delta as i64).map_err(|_| FormatError::Malformed)?,
+        };
+        previous[index % 2] = duration;
+        durations.push(duration);
+    }
+    Ok(durations)
+}
+
+fn repeat_style_id(repeat: RepeatStyle) -> u8 {
+    match repeat {
+        RepeatStyle::FullFrame => 0,
+        RepeatStyle::Toggle => 1,
+        RepeatStyle::RepeatCode => 2,
+    }
+}
+
+fn repeat_style_from_id(id: u8) -> Option<RepeatStyle> {
+    match id {
+        0 => Some(RepeatStyle::FullFrame),
+        1 => Some(RepeatStyle::Toggle),
+        2 => Some(RepeatStyle::RepeatCode),
+        _ => None,
+    }
+}
+
+/// 按小端序依次读取，剩余字节不足时返回Truncated
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], FormatError> {
+        if self.bytes.len() < N {
+            return Err(FormatError::Truncated);
+        }
+        let (head, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        head.try_into().map_err(|_| FormatError::Truncated)
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        self.take::<1>().map(|[byte]| byte)
+    }
+
+    fn u16(&mut self) -> Result<u16, FormatError> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        self.take().map(u32::from_le_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nec_frame() -> Vec<u32> {
+        let mut durations = vec![9000, 4500];
+        for bit in 0..32 {
+            durations.push(560);
+            durations.push(if bit % 3 == 0 { 1690 } else { 560 });
+        }
+        durations.push(560);
+        durations
+    }
+
+    fn sample() -> Capture {
+        Capture {
+            frames: vec![
+                Frame {
+                    durations: nec_frame(),
+                    gap_us: 40_000,
+                },
+                // 差值超出i16范围，需要转义
+                Frame {
+                    durations: vec![9000, 2250, 560, 100_000, 560],
+                    gap_us: 96_000,
+                },
+            ],
+            carrier_hz: Some(38_000),
+            timing: Some(ProtocolTiming {
+                frame_period_us: 108_000,
+                min_gap_us: 40_000,
+                repeat: RepeatStyle::RepeatCode,
+            }),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let capture = sample();
+        let bytes = capture.to_bytes().unwrap();
+        assert_eq!(Capture::from_bytes(&bytes), Ok(capture));
+    }
+
+    #[test]
+    fn round_trip_without_carrier_and_timing() {
+        let capture = Capture {
+            carrier_hz: None,
+            timing: None,
+            ..sample()
+        };
+        let bytes = capture.to_bytes().unwrap();
+        assert_eq!(Capture::from_bytes(&bytes), Ok(capture));
+    }
+
+    #[test]
+    fn rejects_corrupted_crc() {
+        let mut bytes = sample().to_bytes().unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        assert_eq!(Capture::from_bytes(&bytes), Err(FormatError::CrcMismatch));
+
+        // 内容损坏同样无法通过CRC
+        let mut bytes = sample().to_bytes().unwrap();
+        bytes[MIN_HEADER_LEN + 2] ^= 0x80;
+        assert_eq!(Capture::from_bytes(&bytes), Err(FormatError::CrcMismatch));
+    }
+
+    #[test]
+    fn rejects_too_many_frames() {
+        let frame = Frame {
+            durations: vec![560],
+            gap_us: 0,
+        };
+        let capture = Capture {
+            frames: vec![frame; 256],
+            carrier_hz: None,
+            timing: None,
+        };
+        assert_eq!(capture.to_bytes(), Err(FormatError::TooManyFrames(256)));
+    }
+
+    #[test]
+    fn rejects_too_long_frame() {
+        let capture = Capture {
+            frames: vec![Frame {
+                durations: vec![560; 65_536],
+                gap_us: 0,
+            }],
+            carrier_hz: None,
+            timing: None,
+        };
+        assert_eq!(capture.to_bytes(), Err(FormatError::FrameTooLong(65_536)));
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+
+use super::{decode_pulse_distance, matches, Decoder, IrCommand, Protocol};
+
+// JVC协议时序（微秒）
+const HEADER_MARK: u32 = 8400;
+const HEADER_SPACE: u32 = 4200;
+const BIT_MARK: u32 = 525;
+const ZERO_SPACE: u32 = 525;
+const ONE_SPACE: u32 = 1575;
+
+/// 数据位数量：8位地址、8位命令，没有反码
+const DATA_BITS: usize = 16;
+
+/// 解码后的JVC命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JvcCommand {
+    pub address: u8,
+    pub command: u8,
+    /// 按住按键时发送的不带引导码的重复帧
+    pub repeated: bool,
+}
+
+impl fmt::Display for JvcCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "JVC addr={:#04x} cmd={:#04x}", self.address, self.command)?;
+        if self.repeated {
+            write!(f, " repeat")?;
+        }
+        Ok(())
+    }
+}
+
+/// 从微秒时长序列解码JVC帧
+///
+/// 遥控器只在第一帧发送引导码，之后的重复帧直接从数据位开始，两种帧都能解码。
+pub fn decode(durations: &[u32]) -> Option<JvcCommand> {
+    let has_header = durations.len() >= 2
+        && matches(durations[0], HEADER_MARK)
+        && matches(durations[1], HEADER_SPACE);
+    let data = if has_header { &durations[2..] } else { durations };
+
+    // 必须恰好是一帧数据，避免把LG等更长的帧或其他协议的片段误认为JVC
+    if data.len() != DATA_BITS * 2 + 1 {
+        return None;
+    }
+
+    let raw = decode_pulse_distance(data, DATA_BITS, BIT_MARK, ZERO_SPACE, ONE_SPACE)?;
+    let [address, command, ..] = raw.to_le_bytes();
+
+    Some(JvcCommand {
+        address,
+        command,
+        repeated: !has_header,
+    })
+}
+
+/// JVC解码器
+pub struct JvcDecoder;
+
+impl Decoder for JvcDecoder {
+    fn protocol(&self) -> Protocol {
+        Protocol::Jvc
+    }
+
+    fn matches_header(&self, durations: &[u32]) -> bool {
+        !durations.is_empty()
+            && (matches(durations[0], HEADER_MARK) || matches(durations[0], BIT_MARK))
+    }
+
+    fn decode(&self, durations: &[u32]) -> Option<IrCommand> {
+        decode(durations).map(IrCommand::Jvc)
+    }
+}
@@ -0,0 +1,150 @@
+use std::fmt;
+
+use super::{decode_pulse_distance, matches, Decoder, IrCommand, Protocol};
+
+// Kaseikyo协议时序（微秒），以432µs为单位：引导码8/4个单位，数据位1/1或1/3个单位
+const HEADER_MARK: u32 = 3456;
+const HEADER_SPACE: u32 = 1728;
+const BIT_MARK: u32 = 432;
+const ZERO_SPACE: u32 = 432;
+const ONE_SPACE: u32 = 1296;
+
+/// 数据位数量：16位厂商编号、4位厂商校验、4位设备、8位子设备、8位命令、8位校验
+const DATA_BITS: usize = 48;
+
+/// 已知的厂商编号
+pub const VENDOR_PANASONIC: u16 = 0x2002;
+pub const VENDOR_DENON: u16 = 0x3254;
+pub const VENDOR_JVC: u16 = 0x0103;
+
+/// 解码后的Kaseikyo命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KaseikyoCommand {
+    pub vendor: u16,
+    /// 4位设备编号
+    pub device: u8,
+    pub subdevice: u8,
+    pub command: u8,
+}
+
+impl KaseikyoCommand {
+    /// 已知厂商的名称
+    pub fn vendor_name(&self) -> Option<&'static str> {
+        match self.vendor {
+            VENDOR_PANASONIC => Some("PANASONIC"),
+            VENDOR_DENON => Some("DENON"),
+            VENDOR_JVC => Some("JVC"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for KaseikyoCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "KASEIKYO vendor={:#06x}", self.vendor)?;
+        if let Some(name) = self.vendor_name() {
+            write!(f, "({})", name)?;
+        }
+        write!(
+            f,
+            " dev={:#03x} sub={:#04x} cmd={:#04x}",
+            self.device, self.subdevice, self.command
+        )
+    }
+}
+
+/// 厂商编号4个半字节的异或
+fn vendor_parity(vendor: u16) -> u8 {
+    let [low, high] = vendor.to_le_bytes();
+    let byte = low ^ high;
+    (byte ^ (byte >> 4)) & 0x0F
+}
+
+/// 从微秒时长序列解码Kaseikyo帧，校验厂商校验半字节和数据校验字节
+pub fn decode(durations: &[u32]) -> Option<KaseikyoCommand> {
+    if durations.len() < 2
+        || !matches(durations[0], HEADER_MARK)
+        || !matches(durations[1], HEADER_SPACE)
+    {
+        return None;
+    }
+
+    let raw = decode_pulse_distance(&durations[2..], DATA_BITS, BIT_MARK, ZERO_SPACE, ONE_SPACE)?;
+    let [vendor_low, vendor_high, genre, subdevice, command, checksum, ..] = raw.to_le_bytes();
+    let vendor = u16::from_le_bytes([vendor_low, vendor_high]);
+
+    if genre & 0x0F != vendor_parity(vendor) || checksum != genre ^ subdevice ^ command {
+        return None;
+    }
+
+    Some(KaseikyoCommand {
+        vendor,
+        device: genre >> 4,
+        subdevice,
+        command,
+    })
+}
+
+/// Kaseikyo解码器
+pub struct KaseikyoDecoder;
+
+impl Decoder for KaseikyoDecoder {
+    fn protocol(&self) -> Protocol {
+        Protocol::Kaseikyo
+    }
+
+    fn matches_header(&self, durations: &[u32]) -> bool {
+        durations.len() >= 2
+            && matches(durations[0], HEADER_MARK)
+            && matches(durations[1], HEADER_SPACE)
+    }
+
+    fn decode(&self, durations: &[u32]) -> Option<IrCommand> {
+        decode(durations).map(IrCommand::Kaseikyo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按LSB优先把字节编码成时长，带引导码和结束mark
+    fn frame(bytes: [u8; 6]) -> Vec<u32> {
+        let mut durations = vec![HEADER_MARK, HEADER_SPACE];
+        for byte in bytes {
+            for bit in 0..8 {
+                durations.push(BIT_MARK);
+                durations.push(if byte >> bit & 1 == 1 { ONE_SPACE } else { ZERO_SPACE });
+            }
+        }
+        durations.push(BIT_MARK);
+        durations
+    }
+
+    #[test]
+    fn decodes_panasonic_power() {
+        // 松下电视电源键，按MSB优先书写为40 04 01 00 BC BD
+        let command = decode(&frame([0x02, 0x20, 0x80, 0x00, 0x3D, 0xBD])).unwrap();
+        assert_eq!(
+            command,
+            KaseikyoCommand {
+                vendor: VENDOR_PANASONIC,
+                device: 8,
+                subdevice: 0x00,
+                command: 0x3D,
+            }
+        );
+        assert_eq!(command.vendor_name(), Some("PANASONIC"));
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        assert_eq!(decode(&frame([0x02, 0x20, 0x80, 0x00, 0x3D, 0xBC])), None);
+    }
+
+    #[test]
+    fn rejects_bad_vendor_parity() {
+        let checksum = 0x81 ^ 0x3D;
+        assert_eq!(decode(&frame([0x02, 0x20, 0x81, 0x00, 0x3D, checksum])), None);
+    }
+}
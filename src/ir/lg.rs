@@ -0,0 +1,201 @@
+use std::fmt;
+
+use super::{decode_pulse_distance, matches, Decoder, IrCommand, Protocol};
+
+// LG协议时序（微秒）
+const HEADER_MARK: u32 = 8500;
+const HEADER_SPACE: u32 = 4250;
+const BIT_MARK: u32 = 550;
+const ZERO_SPACE: u32 = 550;
+const ONE_SPACE: u32 = 1600;
+
+/// 数据位数量：8位地址、16位命令、4位校验，MSB优先
+const DATA_BITS: usize = 28;
+
+/// 空调使用的地址
+const AC_ADDRESS: u8 = 0x88;
+
+/// 温度字段加上该值为摄氏度
+const TEMPERATURE_OFFSET: u8 = 15;
+
+/// 空调运行模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LgAcMode {
+    Cool,
+    Dry,
+    Fan,
+    Auto,
+    Heat,
+}
+
+impl LgAcMode {
+    fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0 => Some(Self::Cool),
+            1 => Some(Self::Dry),
+            2 => Some(Self::Fan),
+            3 => Some(Self::Auto),
+            4 => Some(Self::Heat),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cool => "Cool",
+            Self::Dry => "Dry",
+            Self::Fan => "Fan",
+            Self::Auto => "Auto",
+            Self::Heat => "Heat",
+        }
+    }
+}
+
+/// 空调风速
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LgAcFan {
+    Lowest,
+    Low,
+    Medium,
+    High,
+    Auto,
+}
+
+impl LgAcFan {
+    fn from_bits(bits: u32) -> Option<Self> {
+        match bits {
+            0 => Some(Self::Lowest),
+            1 => Some(Self::Low),
+            2 => Some(Self::Medium),
+            4 => Some(Self::High),
+            5 => Some(Self::Auto),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Lowest => "lowest",
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+            Self::Auto => "auto",
+        }
+    }
+}
+
+/// 从空调命令解析出的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LgAcState {
+    pub power: bool,
+    pub mode: LgAcMode,
+    /// 摄氏度
+    pub temperature: u8,
+    pub fan: LgAcFan,
+}
+
+impl LgAcState {
+    /// 解析空调地址的28位帧，字段取值未知时返回None
+    fn from_raw(raw: u32) -> Option<Self> {
+        if (raw >> 20) as u8 != AC_ADDRESS {
+            return None;
+        }
+        // 电源位为0b11表示关机，此时其余字段没有意义
+        let power = (raw >> 18) & 0x3 != 0x3;
+        Some(Self {
+            power,
+            mode: LgAcMode::from_bits((raw >> 12) & 0x7)?,
+            temperature: ((raw >> 8) & 0xF) as u8 + TEMPERATURE_OFFSET,
+            fan: LgAcFan::from_bits((raw >> 4) & 0xF)?,
+        })
+    }
+}
+
+impl fmt::Display for LgAcState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.power {
+            return write!(f, "off");
+        }
+        write!(f, "{} {}°C fan {}", self.mode.name(), self.temperature, self.fan.name())
+    }
+}
+
+/// 解码后的LG命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LgCommand {
+    /// 原始的28位数据
+    pub raw: u32,
+    /// 校验通过时才有效
+    pub checksum_ok: bool,
+    /// 校验通过且为空调命令时解析出的状态
+    pub ac: Option<LgAcState>,
+}
+
+impl LgCommand {
+    pub fn address(&self) -> u8 {
+        (self.raw >> 20) as u8
+    }
+
+    pub fn command(&self) -> u16 {
+        (self.raw >> 4) as u16
+    }
+}
+
+impl fmt::Display for LgCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LG raw={:#09x}", self.raw)?;
+        match (self.checksum_ok, self.ac) {
+            (false, _) => write!(f, " checksum error"),
+            (true, Some(state)) => write!(f, " {}", state),
+            (true, None) => write!(f, " addr={:#04x} cmd={:#06x}", self.address(), self.command()),
+        }
+    }
+}
+
+/// 前6个半字节之和的低4位
+fn checksum(raw: u32) -> u32 {
+    (1..7).map(|nibble| (raw >> (nibble * 4)) & 0xF).sum::<u32>() & 0xF
+}
+
+/// 从微秒时长序列解码LG帧，校验失败时只保留原始数据
+pub fn decode(durations: &[u32]) -> Option<LgCommand> {
+    if durations.len() != 2 + DATA_BITS * 2 + 1
+        || !matches(durations[0], HEADER_MARK)
+        || !matches(durations[1], HEADER_SPACE)
+    {
+        return None;
+    }
+
+    let lsb_first = decode_pulse_distance(&durations[2..], DATA_BITS, BIT_MARK, ZERO_SPACE, ONE_SPACE)?;
+    let raw = (lsb_first as u32).reverse_bits() >> (32 - DATA_BITS);
+    let checksum_ok = raw & 0xF == checksum(raw);
+
+    Some(LgCommand {
+        raw,
+        checksum_ok,
+        ac: checksum_ok.then(|| LgAcState::from_raw(raw)).flatten(),
+    })
+}
+
+/// LG解码器
+pub struct LgDecoder;
+
+impl Decoder for LgDecoder {
+    fn protocol(&self) -> Protocol {
+        Protocol::Lg
+    }
+
+    fn matches_header(&self, durations: &[u32]) -> bool {
+        durations.len() >= 2
+            && matches(durations[0], HEADER_MARK)
+            && matches(durations[1], HEADER_SPACE)
+    }
+
+    fn decode(&self, durations: &[u32]) -> Option<IrCommand> {
+        let command = decode(durations)?;
+        if !command.checksum_ok {
+            log::debug!("LG校验失败: {:#09x}", command.raw);
+        }
+        Some(IrCommand::Lg(command))
+    }
+}
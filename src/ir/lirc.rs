@@ -0,0 +1,215 @@
+use std::fmt::Write;
+
+use super::normalize::{normalize, BUCKET_TOLERANCE_PERCENT};
+use super::receiver::{Capture, MIN_FRAME_GAP_US};
+use super::timing::{protocol_timing, ProtocolTiming};
+use super::transmitter::DEFAULT_CARRIER_HZ;
+use super::{detect_and_decode, within_tolerance, IrCommand};
+
+/// LIRC接收时的相对容差（百分比）和绝对容差（微秒）
+const EPS_PERCENT: u32 = 30;
+const AEPS_US: u32 = 100;
+
+/// raw_codes中每行的时长数量
+const RAW_VALUES_PER_LINE: usize = 6;
+
+/// 由引导码、1和0的mark/space对以及结尾mark描述的编码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SpaceEncoding {
+    header: Option<(u32, u32)>,
+    one: (u32, u32),
+    zero: (u32, u32),
+    ptrail: Option<u32>,
+    bits: usize,
+}
+
+impl SpaceEncoding {
+    /// 两个按键能否放在同一个codes段中
+    fn compatible(&self, other: &SpaceEncoding) -> bool {
+        let close = |a: u32, b: u32| within_tolerance(a, b, BUCKET_TOLERANCE_PERCENT);
+        let close_pair = |a: (u32, u32), b: (u32, u32)| close(a.0, b.0) && close(a.1, b.1);
+        let header = match (self.header, other.header) {
+            (Some(a), Some(b)) => close_pair(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        let ptrail = match (self.ptrail, other.ptrail) {
+            (Some(a), Some(b)) => close(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        header && ptrail && self.bits == other.bits && close_pair(self.one, other.one) && close_pair(self.zero, other.zero)
+    }
+}
+
+/// 已解码为具体命令的录制，从归一化后的桶中推算编码，按发送顺序（MSB优先）组成码值
+fn space_encoding(capture: &Capture) -> Option<(SpaceEncoding, u64)> {
+    if let IrCommand::Raw { .. } = detect_and_decode(capture.durations()) {
+        return None;
+    }
+    let durations = normalize(capture.durations(), BUCKET_TOLERANCE_PERCENT);
+    // 只取第一帧编码
+    let end = (1..durations.len())
+        .step_by(2)
+        .find(|&index| durations[index] >= MIN_FRAME_GAP_US)
+        .unwrap_or(durations.len());
+    // 先按有引导码推算，Denon等没有引导码的协议再从第一个时长开始
+    [true, false]
+        .into_iter()
+        .find_map(|with_header| encode_bits(&durations[..end], with_header))
+}
+
+fn encode_bits(durations: &[u32], with_header: bool) -> Option<(SpaceEncoding, u64)> {
+    let (header, body) = match (with_header, durations) {
+        (true, [mark, space, body @ ..]) => (Some((*mark, *space)), body),
+        (true, _) => return None,
+        (false, body) => (None, body),
+    };
+    let pairs = body.chunks_exact(2).map(|pair| (pair[0], pair[1])).collect::<Vec<_>>();
+    let lone_mark = body.chunks_exact(2).remainder().first().copied();
+
+    let mut kinds = pairs.clone();
+    kinds.sort_unstable();
+    kinds.dedup();
+    let &[a, b] = kinds.as_slice() else {
+        return None;
+    };
+    if header == Some(a) || header == Some(b) {
+        return None;
+    }
+    // 脉冲宽度编码（SIRC）的space相同，脉冲间隔编码（NEC）的mark相同
+    let pulse_width = a.0 != b.0;
+    if pulse_width && a.1 != b.1 {
+        return None;
+    }
+    let (zero, one) = if (pulse_width && a.0 < b.0) || (!pulse_width && a.1 < b.1) { (a, b) } else { (b, a) };
+
+    let mut bits = pairs.iter().map(|&pair| pair == one).collect::<Vec<_>>();
+    let ptrail = match lone_mark {
+        // 脉冲宽度编码的最后一位只有mark，space并入帧间空闲
+        Some(mark) if pulse_width => {
+            bits.push(match mark {
+                mark if mark == one.0 => true,
+                mark if mark == zero.0 => false,
+                _ => return None,
+            });
+            None
+        }
+        ptrail => ptrail,
+    };
+    if bits.is_empty() || bits.len() > 64 {
+        return None;
+    }
+    let code = bits.iter().fold(0u64, |code, &bit| code << 1 | bit as u64);
+    let encoding = SpaceEncoding {
+        header,
+        one,
+        zero,
+        ptrail,
+        bits: bits.len(),
+    };
+    Some((encoding, code))
+}
+
+/// 录制的发射时序，没有单独设置时按协议查表
+fn capture_timing(capture: &Capture) -> ProtocolTiming {
+    capture
+        .timing
+        .unwrap_or_else(|| protocol_timing(detect_and_decode(capture.durations()).protocol()))
+}
+
+/// 生成一个遥控器的remote.conf内容
+///
+/// 与第一个能推算出编码的按键编码一致的按键写入`begin codes`，其余按键写入`begin raw_codes`。
+/// 两者都有时原始码放在名为`<遥控器>_raw`的第二个remote中，因为一个remote只能使用一种编码。
+pub fn remote_conf(remote: &str, keys: &[(&str, &Capture)]) -> String {
+    let mut encoded: Vec<(&str, u64)> = Vec::new();
+    let mut reference: Option<(SpaceEncoding, &Capture)> = None;
+    let mut raw: Vec<(&str, &Capture)> = Vec::new();
+    for &(key, capture) in keys {
+        match (space_encoding(capture), reference) {
+            (Some((encoding, code)), None) => {
+                reference = Some((encoding, capture));
+                encoded.push((key, code));
+            }
+            (Some((encoding, code)), Some((first, _))) if encoding.compatible(&first) => encoded.push((key, code)),
+            _ => raw.push((key, capture)),
+        }
+    }
+
+    let mut text = String::new();
+    if let Some((encoding, capture)) = reference {
+        write_codes_remote(&mut text, remote, encoding, capture, &encoded);
+    }
+    if !raw.is_empty() {
+        let name = if encoded.is_empty() { remote.to_string() } else { format!("{}_raw", remote) };
+        write_raw_remote(&mut text, &name, &raw);
+    }
+    text
+}
+
+fn write_codes_remote(text: &mut String, remote: &str, encoding: SpaceEncoding, capture: &Capture, codes: &[(&str, u64)]) {
+    let timing = capture_timing(capture);
+    // 有帧周期时按固定长度发送，gap为整个周期
+    let (flags, gap) = match timing.frame_period_us {
+        0 => ("SPACE_ENC", timing.min_gap_us),
+        period => ("SPACE_ENC|CONST_LENGTH", period),
+    };
+    let _ = writeln!(text, "begin remote\n");
+    let _ = writeln!(text, "  name  {}", remote);
+    let _ = writeln!(text, "  bits  {:>11}", encoding.bits);
+    let _ = writeln!(text, "  flags {}", flags);
+    let _ = writeln!(text, "  eps   {:>11}", EPS_PERCENT);
+    let _ = writeln!(text, "  aeps  {:>11}\n", AEPS_US);
+    if let Some((mark, space)) = encoding.header {
+        let _ = writeln!(text, "  header  {:>9} {:>5}", mark, space);
+    }
+    let _ = writeln!(text, "  one     {:>9} {:>5}", encoding.one.0, encoding.one.1);
+    let _ = writeln!(text, "  zero    {:>9} {:>5}", encoding.zero.0, encoding.zero.1);
+    if let Some(ptrail) = encoding.ptrail {
+        let _ = writeln!(text, "  ptrail  {:>9}", ptrail);
+    }
+    let _ = writeln!(text, "  gap     {:>9}", gap);
+    let _ = writeln!(text, "  frequency {:>7}\n", capture.carrier_hz.unwrap_or(DEFAULT_CARRIER_HZ));
+    let _ = writeln!(text, "      begin codes");
+    let digits = encoding.bits.div_ceil(4);
+    for (key, code) in codes {
+        let _ = writeln!(text, "          {:<24} 0x{:0digits$X}", key, code, digits = digits);
+    }
+    let _ = writeln!(text, "      end codes\n");
+    let _ = writeln!(text, "end remote");
+}
+
+fn write_raw_remote(text: &mut String, remote: &str, keys: &[(&str, &Capture)]) {
+    let gap = keys
+        .iter()
+        .map(|(_, capture)| capture_timing(capture).min_gap_us)
+        .max()
+        .unwrap_or_default();
+    let carrier_hz = keys[0].1.carrier_hz.unwrap_or(DEFAULT_CARRIER_HZ);
+    let _ = writeln!(text, "begin remote\n");
+    let _ = writeln!(text, "  name  {}", remote);
+    let _ = writeln!(text, "  flags RAW_CODES");
+    let _ = writeln!(text, "  eps   {:>11}", EPS_PERCENT);
+    let _ = writeln!(text, "  aeps  {:>11}\n", AEPS_US);
+    let _ = writeln!(text, "  gap     {:>9}", gap);
+    let _ = writeln!(text, "  frequency {:>7}\n", carrier_hz);
+    let _ = writeln!(text, "      begin raw_codes");
+    for (key, capture) in keys {
+        let mut durations = capture.flatten();
+        // LIRC的原始码以mark结束
+        if durations.len() % 2 == 0 {
+            durations.pop();
+        }
+        let _ = writeln!(text, "\n          name {}", key);
+        for line in durations.chunks(RAW_VALUES_PER_LINE) {
+            text.push_str("         ");
+            for duration in line {
+                let _ = write!(text, " {:>7}", duration);
+            }
+            text.push('\n');
+        }
+    }
+    let _ = writeln!(text, "      end raw_codes\n");
+    let _ = writeln!(text, "end remote");
+}
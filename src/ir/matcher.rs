@@ -0,0 +1,133 @@
+use super::normalize::{normalize, BUCKET_TOLERANCE_PERCENT};
+use super::{is_repeat_frame, within_tolerance, DEFAULT_TOLERANCE_PERCENT};
+
+/// 默认的相似度阈值（百分比）
+pub const DEFAULT_SIMILARITY_PERCENT: u32 = 90;
+
+/// 已录制的参考码
+#[derive(Debug, Clone)]
+struct Reference {
+    name: String,
+    durations: Vec<u32>,
+}
+
+/// 一次匹配结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeMatch {
+    /// 匹配到的参考码名称
+    pub slot: String,
+    /// 相似度百分比
+    pub similarity: u32,
+    /// 由NEC重复帧沿用上一次的匹配
+    pub repeat: bool,
+}
+
+/// 把捕获与已录制的参考码逐脉冲比较
+pub struct CodeMatcher {
+    references: Vec<Reference>,
+    tolerance_percent: u32,
+    similarity_percent: u32,
+    last_match: Option<CodeMatch>,
+}
+
+impl Default for CodeMatcher {
+    fn default() -> Self {
+        Self::new(DEFAULT_TOLERANCE_PERCENT, DEFAULT_SIMILARITY_PERCENT)
+    }
+}
+
+impl CodeMatcher {
+    pub fn new(tolerance_percent: u32, similarity_percent: u32) -> Self {
+        Self {
+            references: Vec::new(),
+            tolerance_percent,
+            similarity_percent,
+            last_match: None,
+        }
+    }
+
+    /// 添加或替换参考码
+    pub fn insert(&mut self, name: &str, durations: &[u32]) {
+        let durations = normalize(durations, BUCKET_TOLERANCE_PERCENT);
+        match self.references.iter_mut().find(|reference| reference.name == name) {
+            Some(reference) => reference.durations = durations,
+            None => self.references.push(Reference {
+                name: name.to_string(),
+                durations,
+            }),
+        }
+    }
+
+    /// 删除参考码
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.references.len();
+        self.references.retain(|reference| reference.name != name);
+        len != self.references.len()
+    }
+
+    /// 重命名参考码，不存在时返回false
+    pub fn rename(&mut self, from: &str, to: &str) -> bool {
+        self.last_match = None;
+        match self.references.iter_mut().find(|reference| reference.name == from) {
+            Some(reference) => {
+                reference.name = to.to_string();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 参考码数量，与保存的录制数量相同
+    pub fn reference_count(&self) -> usize {
+        self.references.len()
+    }
+
+    /// 检查捕获是否匹配某个参考码，返回相似度最高且超过阈值的一个
+    pub fn check(&mut self, durations: &[u32], truncated: bool) -> Option<CodeMatch> {
+        // 溢出的捕获不完整，永远不参与匹配
+        if truncated {
+            self.last_match = None;
+            return None;
+        }
+
+        // 重复帧与录制的完整帧长度不同，沿用上一次匹配到的按键
+        if is_repeat_frame(durations) {
+            return self.last_match.clone().map(|last| CodeMatch {
+                repeat: true,
+                ..last
+            });
+        }
+
+        let candidate = normalize(durations, BUCKET_TOLERANCE_PERCENT);
+        self.last_match = self
+            .references
+            .iter()
+            .map(|reference| (reference, self.similarity(&reference.durations, &candidate)))
+            .filter(|&(_, similarity)| similarity >= self.similarity_percent)
+            .max_by_key(|&(_, similarity)| similarity)
+            .map(|(reference, similarity)| CodeMatch {
+                slot: reference.name.clone(),
+                similarity,
+                repeat: false,
+            });
+
+        self.last_match.clone()
+    }
+
+    /// 逐脉冲比较，返回落在容差内的脉冲占比
+    fn similarity(&self, reference: &[u32], candidate: &[u32]) -> u32 {
+        // 长度最多允许相差一个结尾脉冲
+        let longest = reference.len().max(candidate.len());
+        if longest == 0 || reference.len().abs_diff(candidate.len()) > 1 {
+            return 0;
+        }
+
+        let matched = reference
+            .iter()
+            .zip(candidate)
+            .filter(|&(&expected, &actual)| within_tolerance(actual, expected, self.tolerance_percent))
+            .count();
+
+        (matched * 100 / longest) as u32
+    }
+}
@@ -0,0 +1,213 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use esp_idf_svc::sys;
+
+/// 标签最长32字节
+pub const MAX_LABEL_LEN: usize = 32;
+
+/// 序列化格式的版本号，版本2增加了最近使用时间和保护标记，版本3增加了质量分数
+const META_VERSION: u8 = 3;
+
+/// 标记位：录制受保护，淘汰时不会被删除
+const FLAG_PROTECTED: u8 = 0x01;
+/// 标记位：后面跟最近使用时间
+const FLAG_LAST_USED: u8 = 0x02;
+/// 标记位：后面跟质量分数
+const FLAG_QUALITY: u8 = 0x04;
+
+/// 序列化后的最大长度
+pub const MAX_META_LEN: usize = 26 + MAX_LABEL_LEN;
+
+/// 系统时间早于2024-01-01时认为没有通过SNTP同步
+const MIN_SYNCED_UNIX_SECS: u64 = 1_704_067_200;
+
+/// 已经通过SNTP同步时返回当前的UNIX时间（秒）
+pub fn synced_unix_secs() -> Option<u64> {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    (secs >= MIN_SYNCED_UNIX_SECS).then_some(secs)
+}
+
+/// 把UNIX时间格式化为`2024-01-01T00:00:00Z`
+pub fn format_utc(secs: u64) -> String {
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // 从1970-01-01起的天数换算成公历日期，按3月开始的年份计算闰日
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+/// 录制的创建时间
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timestamp {
+    /// 已同步的UNIX时间（秒）
+    Unix(u64),
+    /// 没有同步时间时记录开机后的秒数，只在同一次开机内有意义
+    Uptime(u64),
+}
+
+impl Timestamp {
+    /// 当前时间，系统时间已同步时使用UNIX时间
+    pub fn now() -> Self {
+        match synced_unix_secs() {
+            Some(secs) => Self::Unix(secs),
+            None => Self::Uptime((unsafe { sys::esp_timer_get_time() } / 1_000_000) as u64),
+        }
+    }
+
+    /// 用于比较新旧的键，开机时间无法与UNIX时间比较，一律视为更早
+    pub fn age_key(&self) -> (u8, u64) {
+        match *self {
+            Self::Uptime(secs) => (0, secs),
+            Self::Unix(secs) => (1, secs),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 9] {
+        let (kind, secs) = match self {
+            Self::Unix(secs) => (0u8, secs),
+            Self::Uptime(secs) => (1u8, secs),
+        };
+        let mut bytes = [0u8; 9];
+        bytes[0] = kind;
+        bytes[1..].copy_from_slice(&secs.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(kind: u8, secs: [u8; 8]) -> Option<Self> {
+        let secs = u64::from_le_bytes(secs);
+        match kind {
+            0 => Some(Self::Unix(secs)),
+            1 => Some(Self::Uptime(secs)),
+            _ => None,
+        }
+    }
+}
+
+/// UNIX时间直接显示秒数，开机时间显示为`uptime+<秒>s`
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unix(secs) => write!(f, "{}", secs),
+            Self::Uptime(secs) => write!(f, "uptime+{}s", secs),
+        }
+    }
+}
+
+/// 录制的附加信息，与录制分开保存，更新计数时不需要重写录制
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotMeta {
+    pub created: Timestamp,
+    /// 成功重放的次数
+    pub uses: u32,
+    /// 最近一次成功重放的时间，旧版本固件没有记录
+    pub last_used: Option<Timestamp>,
+    /// 受保护的录制不会被自动淘汰
+    pub protected: bool,
+    /// 用户设置的说明，最长32字节
+    pub label: String,
+    /// 保存时的捕获质量（0–100），旧版本固件没有记录
+    pub quality: Option<u8>,
+}
+
+impl SlotMeta {
+    pub fn new(label: String) -> Self {
+        Self {
+            created: Timestamp::now(),
+            uses: 0,
+            last_used: None,
+            protected: false,
+            label,
+            quality: None,
+        }
+    }
+
+    /// 版本、创建时间、使用次数、标记、最近使用时间和质量分数（有标记时）、标签长度和标签
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MAX_META_LEN);
+        bytes.push(META_VERSION);
+        bytes.extend_from_slice(&self.created.to_bytes());
+        bytes.extend_from_slice(&self.uses.to_le_bytes());
+        let mut flags = 0;
+        if self.protected {
+            flags |= FLAG_PROTECTED;
+        }
+        if self.last_used.is_some() {
+            flags |= FLAG_LAST_USED;
+        }
+        if self.quality.is_some() {
+            flags |= FLAG_QUALITY;
+        }
+        bytes.push(flags);
+        if let Some(last_used) = self.last_used {
+            bytes.extend_from_slice(&last_used.to_bytes());
+        }
+        if let Some(quality) = self.quality {
+            bytes.push(quality);
+        }
+        bytes.push(self.label.len() as u8);
+        bytes.extend_from_slice(self.label.as_bytes());
+        bytes
+    }
+
+    /// 解析`to_bytes`的结果，兼容版本1和2，格式不符时返回None
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (&[version, kind], rest) = bytes.split_first_chunk::<2>()?;
+        if !(1..=META_VERSION).contains(&version) {
+            return None;
+        }
+        let (secs, rest) = rest.split_first_chunk::<8>()?;
+        let (uses, mut rest) = rest.split_first_chunk::<4>()?;
+        let mut flags = 0;
+        let mut last_used = None;
+        let mut quality = None;
+        if version >= 2 {
+            let (&stored_flags, tail) = rest.split_first()?;
+            flags = stored_flags;
+            rest = tail;
+            if flags & FLAG_LAST_USED != 0 {
+                let (&[kind], tail) = rest.split_first_chunk::<1>()?;
+                let (secs, tail) = tail.split_first_chunk::<8>()?;
+                last_used = Some(Timestamp::from_bytes(kind, *secs)?);
+                rest = tail;
+            }
+            if flags & FLAG_QUALITY != 0 {
+                let (&stored_quality, tail) = rest.split_first()?;
+                quality = Some(stored_quality);
+                rest = tail;
+            }
+        }
+        let (&label_len, label) = rest.split_first()?;
+        if label.len() != label_len as usize {
+            return None;
+        }
+        Some(Self {
+            created: Timestamp::from_bytes(kind, *secs)?,
+            uses: u32::from_le_bytes(*uses),
+            last_used,
+            protected: flags & FLAG_PROTECTED != 0,
+            label: String::from_utf8(label.to_vec()).ok()?,
+            quality,
+        })
+    }
+
+    /// 淘汰时比较新旧的键，从没有重放过的录制按创建时间计算
+    pub fn recency(&self) -> (u8, u64) {
+        self.last_used.unwrap_or(self.created).age_key()
+    }
+}
@@ -0,0 +1,438 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use esp_idf_hal::rmt::{PinState, Pulse};
+
+pub mod analyze;
+pub mod archive;
+pub mod assembler;
+pub mod broadlink;
+pub mod carrier;
+pub mod denon;
+pub mod dump;
+pub mod filter;
+pub mod flipper;
+pub mod format;
+pub mod jvc;
+pub mod kaseikyo;
+pub mod lg;
+pub mod lirc;
+pub mod matcher;
+pub mod metadata;
+pub mod nec;
+pub mod noise;
+pub mod normalize;
+pub mod power;
+pub mod pronto;
+pub mod quality;
+pub mod queue;
+pub mod rc5;
+pub mod rc6;
+pub mod receiver;
+pub mod repeat;
+pub mod samsung;
+pub mod selftest;
+pub mod session;
+pub mod sirc;
+pub mod storage;
+pub mod timing;
+pub mod transmitter;
+
+pub use denon::DenonCommand;
+pub use jvc::JvcCommand;
+pub use kaseikyo::KaseikyoCommand;
+pub use lg::LgCommand;
+pub use nec::{NecCommand, NecVariant};
+pub use normalize::Normalized;
+pub use rc5::Rc5Command;
+pub use rc6::Rc6Command;
+pub use receiver::Capture;
+pub use samsung::{Samsung36Command, SamsungCommand};
+pub use sirc::SircCommand;
+
+/// RMT外设的APB源时钟频率
+const APB_CLK_HZ: u32 = 80_000_000;
+
+/// 红外接收头输出为低电平有效，低电平代表载波存在（mark）
+const MARK_LEVEL: PinState = PinState::Low;
+
+/// 默认的时序容差（百分比）
+pub const DEFAULT_TOLERANCE_PERCENT: u32 = 20;
+
+/// 协议解码使用的时序容差，自检期间临时放宽
+static TOLERANCE_PERCENT: AtomicU32 = AtomicU32::new(DEFAULT_TOLERANCE_PERCENT);
+
+/// 当前的解码容差（百分比）
+pub fn tolerance_percent() -> u32 {
+    TOLERANCE_PERCENT.load(Ordering::Relaxed)
+}
+
+/// 设置解码容差（百分比）
+pub fn set_tolerance_percent(percent: u32) {
+    TOLERANCE_PERCENT.store(percent, Ordering::Relaxed);
+}
+
+/// RMT计数时钟，用于把tick数换算为微秒
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickRate {
+    hz: u32,
+}
+
+impl TickRate {
+    /// 根据接收通道的时钟分频器计算tick频率
+    pub fn from_clock_divider(divider: u8) -> Self {
+        Self {
+            hz: APB_CLK_HZ / divider.max(1) as u32,
+        }
+    }
+
+    /// 每秒的tick数
+    pub fn hz(&self) -> u32 {
+        self.hz
+    }
+
+    /// 把tick数换算为微秒
+    pub fn ticks_to_us(&self, ticks: u16) -> u32 {
+        (ticks as u64 * 1_000_000 / self.hz as u64) as u32
+    }
+
+    /// 把微秒换算为tick数
+    pub fn us_to_ticks(&self, us: u32) -> u32 {
+        (us as u64 * self.hz as u64 / 1_000_000) as u32
+    }
+}
+
+/// 把RMT脉冲对展开为以微秒为单位的时长序列
+///
+/// 序列总是从mark开始，mark与space交替出现；遇到0 tick的结束标记即停止。
+pub fn pulses_to_durations(pulses: &[(Pulse, Pulse)], tick: TickRate) -> Vec<u32> {
+    let mut durations = Vec::with_capacity(pulses.len() * 2);
+
+    for pulse in pulses.iter().flat_map(|(p0, p1)| [p0, p1]) {
+        let ticks = pulse.ticks.ticks();
+        if ticks == 0 {
+            break;
+        }
+        // 丢弃捕获开头可能出现的空闲电平
+        if durations.is_empty() && pulse.pin_state != MARK_LEVEL {
+            continue;
+        }
+        durations.push(tick.ticks_to_us(ticks));
+    }
+
+    durations
+}
+
+/// 判断实测时长是否落在期望值的容差范围内
+pub fn within_tolerance(actual: u32, expected: u32, tolerance_percent: u32) -> bool {
+    let delta = expected * tolerance_percent / 100;
+    actual >= expected.saturating_sub(delta) && actual <= expected + delta
+}
+
+/// 使用当前的解码容差比较时长
+pub fn matches(actual: u32, expected: u32) -> bool {
+    within_tolerance(actual, expected, tolerance_percent())
+}
+
+/// 解码脉冲间隔编码的数据位（LSB优先），并校验结束mark
+///
+/// `durations`从第一个数据位的mark开始，每位由固定宽度的mark和表示0/1的space组成。
+pub fn decode_pulse_distance(
+    durations: &[u32],
+    bits: usize,
+    bit_mark: u32,
+    zero_space: u32,
+    one_space: u32,
+) -> Option<u64> {
+    if durations.len() < bits * 2 + 1 {
+        return None;
+    }
+
+    let mut raw: u64 = 0;
+    for bit in 0..bits {
+        let mark = durations[bit * 2];
+        let space = durations[bit * 2 + 1];
+        if !matches(mark, bit_mark) {
+            return None;
+        }
+        if matches(space, one_space) {
+            raw |= 1 << bit;
+        } else if !matches(space, zero_space) {
+            return None;
+        }
+    }
+
+    matches(durations[bits * 2], bit_mark).then_some(raw)
+}
+
+/// 按脉冲间隔编码追加数据位（LSB优先）和结束mark，与`decode_pulse_distance`对应
+pub fn encode_pulse_distance(
+    durations: &mut Vec<u32>,
+    raw: u64,
+    bits: usize,
+    bit_mark: u32,
+    zero_space: u32,
+    one_space: u32,
+) {
+    for bit in 0..bits {
+        durations.push(bit_mark);
+        durations.push(if raw >> bit & 1 == 1 { one_space } else { zero_space });
+    }
+    durations.push(bit_mark);
+}
+
+/// 把曼彻斯特编码的时长序列展开为半位电平（true为mark）
+///
+/// 每个时长必须接近`unit`的整数倍（不超过`max_units`倍），序列从mark开始。
+pub fn expand_manchester(durations: &[u32], unit: u32, max_units: u32) -> Option<Vec<bool>> {
+    let mut levels = Vec::with_capacity(durations.len() * 2);
+    let mut mark = true;
+
+    for &duration in durations {
+        let units = (duration + unit / 2) / unit;
+        if units == 0 || units > max_units || !matches(duration, units * unit) {
+            return None;
+        }
+        levels.resize(levels.len() + units as usize, mark);
+        mark = !mark;
+    }
+
+    Some(levels)
+}
+
+/// 红外协议
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Nec,
+    NecExt,
+    Rc5,
+    Rc6,
+    Sirc,
+    Samsung,
+    Samsung36,
+    Kaseikyo,
+    Jvc,
+    Lg,
+    Denon,
+    Unknown,
+}
+
+impl Protocol {
+    /// 上报给客户端的协议名称
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::Nec => "NEC",
+            Protocol::NecExt => "NEC_EXT",
+            Protocol::Rc5 => "RC5",
+            Protocol::Rc6 => "RC6",
+            Protocol::Sirc => "SIRC",
+            Protocol::Samsung => "SAMSUNG",
+            Protocol::Samsung36 => "SAMSUNG36",
+            Protocol::Kaseikyo => "KASEIKYO",
+            Protocol::Jvc => "JVC",
+            Protocol::Lg => "LG",
+            Protocol::Denon => "DENON",
+            Protocol::Unknown => "UNKNOWN",
+        }
+    }
+
+    /// 序列化格式中的协议编号，已分配的编号不能修改
+    pub fn id(&self) -> u8 {
+        match self {
+            Protocol::Unknown => 0,
+            Protocol::Nec => 1,
+            Protocol::NecExt => 2,
+            Protocol::Rc5 => 3,
+            Protocol::Rc6 => 4,
+            Protocol::Sirc => 5,
+            Protocol::Samsung => 6,
+            Protocol::Samsung36 => 7,
+            Protocol::Kaseikyo => 8,
+            Protocol::Jvc => 9,
+            Protocol::Lg => 10,
+            Protocol::Denon => 11,
+        }
+    }
+
+    /// 由协议编号还原，未知的编号（例如新版本固件写入的）返回Unknown
+    pub fn from_id(id: u8) -> Self {
+        match id {
+            1 => Protocol::Nec,
+            2 => Protocol::NecExt,
+            3 => Protocol::Rc5,
+            4 => Protocol::Rc6,
+            5 => Protocol::Sirc,
+            6 => Protocol::Samsung,
+            7 => Protocol::Samsung36,
+            8 => Protocol::Kaseikyo,
+            9 => Protocol::Jvc,
+            10 => Protocol::Lg,
+            11 => Protocol::Denon,
+            _ => Protocol::Unknown,
+        }
+    }
+}
+
+/// 检测协议后的解码结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IrCommand {
+    Nec(NecCommand),
+    Rc5(Rc5Command),
+    Rc6(Rc6Command),
+    Sirc(SircCommand),
+    Samsung(SamsungCommand),
+    Samsung36(Samsung36Command),
+    Kaseikyo(KaseikyoCommand),
+    Jvc(JvcCommand),
+    Lg(LgCommand),
+    Denon(DenonCommand),
+    /// 无法识别的信号，保留归一化后的时长和指纹
+    Raw { durations: Vec<u32>, fingerprint: u32 },
+}
+
+impl IrCommand {
+    /// 把无法识别的时长归一化后作为原始命令保存
+    pub fn raw(durations: &[u32]) -> Self {
+        let normalized = Normalized::new(durations, normalize::BUCKET_TOLERANCE_PERCENT);
+        IrCommand::Raw {
+            durations: normalized.durations(),
+            fingerprint: normalized.fingerprint(),
+        }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        match self {
+            IrCommand::Nec(command) => match command.variant {
+                NecVariant::Standard => Protocol::Nec,
+                NecVariant::Extended => Protocol::NecExt,
+            },
+            IrCommand::Rc5(_) => Protocol::Rc5,
+            IrCommand::Rc6(_) => Protocol::Rc6,
+            IrCommand::Sirc(_) => Protocol::Sirc,
+            IrCommand::Samsung(_) => Protocol::Samsung,
+            IrCommand::Samsung36(_) => Protocol::Samsung36,
+            IrCommand::Kaseikyo(_) => Protocol::Kaseikyo,
+            IrCommand::Jvc(_) => Protocol::Jvc,
+            IrCommand::Lg(_) => Protocol::Lg,
+            IrCommand::Denon(_) => Protocol::Denon,
+            IrCommand::Raw { .. } => Protocol::Unknown,
+        }
+    }
+
+    /// 是否为同一个按键，忽略帧计数等每次发送都可能变化的字段
+    pub fn same_key(&self, other: &IrCommand) -> bool {
+        match (self, other) {
+            (IrCommand::Sirc(a), IrCommand::Sirc(b)) => a.same_code(b),
+            (IrCommand::Jvc(a), IrCommand::Jvc(b)) => a.address == b.address && a.command == b.command,
+            (IrCommand::Denon(a), IrCommand::Denon(b)) => a.same_code(b),
+            (IrCommand::Raw { fingerprint: a, .. }, IrCommand::Raw { fingerprint: b, .. }) => a == b,
+            _ => self == other,
+        }
+    }
+}
+
+impl fmt::Display for IrCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IrCommand::Nec(command) => command.fmt(f),
+            IrCommand::Rc5(command) => command.fmt(f),
+            IrCommand::Rc6(command) => command.fmt(f),
+            IrCommand::Sirc(command) => command.fmt(f),
+            IrCommand::Samsung(command) => command.fmt(f),
+            IrCommand::Samsung36(command) => command.fmt(f),
+            IrCommand::Kaseikyo(command) => command.fmt(f),
+            IrCommand::Jvc(command) => command.fmt(f),
+            IrCommand::Lg(command) => command.fmt(f),
+            IrCommand::Denon(command) => command.fmt(f),
+            IrCommand::Raw { fingerprint, .. } => {
+                write!(f, "{} fp={:#010x}", self.protocol().name(), fingerprint)
+            }
+        }
+    }
+}
+
+/// 协议解码器
+pub trait Decoder: Sync {
+    fn protocol(&self) -> Protocol;
+
+    /// 只检查引导码，用于快速排除不匹配的协议
+    fn matches_header(&self, durations: &[u32]) -> bool;
+
+    fn decode(&self, durations: &[u32]) -> Option<IrCommand>;
+}
+
+/// 已注册的解码器，按引导码特征从长到短排列
+static DECODERS: &[&dyn Decoder] = &[
+    &nec::NecDecoder,
+    // JVC引导码落在NEC的容差范围内，需要排在NEC之后
+    &jvc::JvcDecoder,
+    &lg::LgDecoder,
+    &samsung::SamsungDecoder,
+    // 与Samsung引导码相同，16位地址之后多一个4.5ms间隔
+    &samsung::Samsung36Decoder,
+    &kaseikyo::KaseikyoDecoder,
+    &rc6::Rc6Decoder,
+    &sirc::SircDecoder,
+    &rc5::Rc5Decoder,
+    // 没有引导码，只按帧长度和第一个mark识别
+    &denon::DenonDecoder,
+];
+
+/// 依次尝试已注册的解码器，都无法识别时返回原始时长
+pub fn detect_and_decode(durations: &[u32]) -> IrCommand {
+    DECODERS
+        .iter()
+        .filter(|decoder| decoder.matches_header(durations))
+        .find_map(|decoder| {
+            let command = decoder.decode(durations);
+            if command.is_none() {
+                log::debug!("{}引导码匹配但解码失败", decoder.protocol().name());
+            }
+            command
+        })
+        .unwrap_or_else(|| IrCommand::raw(durations))
+}
+
+/// 是否为只表示"按键仍被按住"的重复帧
+///
+/// NEC重复帧只有引导码；JVC重复帧虽然携带完整数据，但没有引导码。
+pub fn is_repeat_frame(durations: &[u32]) -> bool {
+    nec::decode(durations).is_some_and(|command| command.repeat)
+        || jvc::decode(durations).is_some_and(|command| command.repeated)
+}
+
+/// 上报给蓝牙客户端的捕获事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureEvent {
+    pub pulse_count: usize,
+    pub command: IrCommand,
+    /// 接收缓冲区溢出导致捕获不完整
+    pub truncated: bool,
+    /// 与已保存的录制匹配时为录制名称
+    pub matched: Option<String>,
+}
+
+impl CaptureEvent {
+    /// 解码捕获并生成事件，被截断的捕获不做解码
+    pub fn new(capture: &Capture, truncated: bool) -> Self {
+        Self {
+            pulse_count: capture.pulse_count(),
+            command: if truncated {
+                IrCommand::raw(capture.durations())
+            } else {
+                detect_and_decode(capture.durations())
+            },
+            truncated,
+            matched: None,
+        }
+    }
+}
+
+impl fmt::Display for CaptureEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.truncated {
+            return write!(f, "IR_OVERFLOW: {} pulses", self.pulse_count);
+        }
+        write!(f, "IR: {} pulses {}", self.pulse_count, self.command)
+    }
+}
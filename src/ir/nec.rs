@@ -0,0 +1,154 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use esp_idf_hal::rmt::Pulse;
+
+use super::{
+    decode_pulse_distance, encode_pulse_distance, matches, pulses_to_durations, Decoder, IrCommand, Protocol, TickRate,
+};
+
+// NEC协议时序（微秒）
+const HEADER_MARK: u32 = 9000;
+const HEADER_SPACE: u32 = 4500;
+const REPEAT_SPACE: u32 = 2250;
+const BIT_MARK: u32 = 560;
+const ZERO_SPACE: u32 = 560;
+const ONE_SPACE: u32 = 1690;
+
+/// 数据位数量：地址、地址反码、命令、命令反码各8位
+const DATA_BITS: usize = 32;
+
+/// 严格模式下地址字节必须互为反码，扩展地址的帧会被丢弃
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// 设置是否使用严格模式
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+/// NEC地址格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NecVariant {
+    /// 8位地址加地址反码
+    Standard,
+    /// 16位地址，两个字节不互为反码
+    Extended,
+}
+
+/// 解码后的NEC命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NecCommand {
+    /// 标准格式只有低8位有效
+    pub address: u16,
+    pub command: u8,
+    pub variant: NecVariant,
+    /// 是否为按住按键时发送的重复帧
+    pub repeat: bool,
+}
+
+impl fmt::Display for NecCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.repeat, self.variant) {
+            (true, _) => write!(f, "NEC repeat"),
+            (false, NecVariant::Standard) => {
+                write!(f, "NEC addr={:#04x} cmd={:#04x}", self.address, self.command)
+            }
+            (false, NecVariant::Extended) => {
+                write!(f, "NEC_EXT addr={:#06x} cmd={:#04x}", self.address, self.command)
+            }
+        }
+    }
+}
+
+/// 从RMT脉冲解码NEC帧
+pub fn decode_nec(pulses: &[(Pulse, Pulse)], tick: TickRate) -> Option<NecCommand> {
+    decode(&pulses_to_durations(pulses, tick))
+}
+
+/// 从微秒时长序列解码NEC帧
+///
+/// 地址字节不互为反码时按扩展格式解码，严格模式下则认为是噪声。
+pub fn decode(durations: &[u32]) -> Option<NecCommand> {
+    if durations.len() < 3 || !matches(durations[0], HEADER_MARK) {
+        return None;
+    }
+
+    // 9ms/2.25ms的重复帧，只携带一个结束mark
+    if matches(durations[1], REPEAT_SPACE) {
+        return matches(durations[2], BIT_MARK).then_some(NecCommand {
+            address: 0,
+            command: 0,
+            variant: NecVariant::Standard,
+            repeat: true,
+        });
+    }
+
+    if !matches(durations[1], HEADER_SPACE) {
+        return None;
+    }
+
+    let raw = decode_pulse_distance(&durations[2..], DATA_BITS, BIT_MARK, ZERO_SPACE, ONE_SPACE)?;
+    let [address_low, address_high, command, command_inv] = (raw as u32).to_le_bytes();
+    if command != !command_inv {
+        return None;
+    }
+
+    let (address, variant) = if address_low == !address_high {
+        (address_low as u16, NecVariant::Standard)
+    } else if STRICT.load(Ordering::Relaxed) {
+        return None;
+    } else {
+        (u16::from_le_bytes([address_low, address_high]), NecVariant::Extended)
+    };
+
+    Some(NecCommand {
+        address,
+        command,
+        variant,
+        repeat: false,
+    })
+}
+
+/// 编码标准NEC帧，地址和命令后各跟一个反码字节
+pub fn encode_nec(address: u8, command: u8) -> Vec<u32> {
+    let raw = u32::from_le_bytes([address, !address, command, !command]);
+
+    let mut durations = vec![HEADER_MARK, HEADER_SPACE];
+    encode_pulse_distance(&mut durations, raw as u64, DATA_BITS, BIT_MARK, ZERO_SPACE, ONE_SPACE);
+    durations
+}
+
+/// 编码扩展格式的NEC帧，16位地址和16位命令按小端序原样发送，不补反码
+pub fn encode_nec_ext(address: u16, command: u16) -> Vec<u32> {
+    let raw = address as u32 | (command as u32) << 16;
+
+    let mut durations = vec![HEADER_MARK, HEADER_SPACE];
+    encode_pulse_distance(&mut durations, raw as u64, DATA_BITS, BIT_MARK, ZERO_SPACE, ONE_SPACE);
+    durations
+}
+
+/// 生成9ms/2.25ms重复帧的时长序列
+pub fn encode_repeat() -> Vec<u32> {
+    vec![HEADER_MARK, REPEAT_SPACE, BIT_MARK]
+}
+
+/// NEC解码器
+pub struct NecDecoder;
+
+impl Decoder for NecDecoder {
+    fn protocol(&self) -> Protocol {
+        Protocol::Nec
+    }
+
+    fn matches_header(&self, durations: &[u32]) -> bool {
+        !durations.is_empty() && matches(durations[0], HEADER_MARK)
+    }
+
+    fn decode(&self, durations: &[u32]) -> Option<IrCommand> {
+        let command = decode(durations)?;
+        if !command.repeat {
+            log::debug!("NEC地址格式: {:?}", command.variant);
+        }
+        Some(IrCommand::Nec(command))
+    }
+}
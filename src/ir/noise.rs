@@ -0,0 +1,96 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::receiver::Capture;
+
+/// 默认的最小脉冲数量，日光灯和阳光产生的干扰通常只有1~5个脉冲
+pub const DEFAULT_MIN_PULSES: u32 = 6;
+
+/// 默认的最短引导mark（微秒），低于JVC不带引导码的重复帧的首个mark
+pub const DEFAULT_MIN_HEADER_US: u32 = 400;
+
+/// 捕获被丢弃的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    TooFewPulses,
+    ShortHeader,
+}
+
+/// 噪声过滤的统计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoiseStats {
+    pub accepted: u32,
+    pub too_few_pulses: u32,
+    pub short_header: u32,
+}
+
+impl fmt::Display for NoiseStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "accepted={} too_few_pulses={} short_header={}",
+            self.accepted, self.too_few_pulses, self.short_header
+        )
+    }
+}
+
+/// 按最小脉冲数量和最短引导mark丢弃干扰产生的捕获
+///
+/// 阈值可以在运行时修改，接收线程和主循环共享同一个实例。
+pub struct NoiseFilter {
+    min_pulses: AtomicU32,
+    min_header_us: AtomicU32,
+    accepted: AtomicU32,
+    too_few_pulses: AtomicU32,
+    short_header: AtomicU32,
+}
+
+impl NoiseFilter {
+    pub fn new(min_pulses: u32, min_header_us: u32) -> Self {
+        Self {
+            min_pulses: AtomicU32::new(min_pulses),
+            min_header_us: AtomicU32::new(min_header_us),
+            accepted: AtomicU32::new(0),
+            too_few_pulses: AtomicU32::new(0),
+            short_header: AtomicU32::new(0),
+        }
+    }
+
+    pub fn min_pulses(&self) -> u32 {
+        self.min_pulses.load(Ordering::Relaxed)
+    }
+
+    pub fn set_min_pulses(&self, value: u32) {
+        self.min_pulses.store(value, Ordering::Relaxed);
+    }
+
+    pub fn min_header_us(&self) -> u32 {
+        self.min_header_us.load(Ordering::Relaxed)
+    }
+
+    pub fn set_min_header_us(&self, value: u32) {
+        self.min_header_us.store(value, Ordering::Relaxed);
+    }
+
+    /// 检查捕获并更新统计
+    pub fn check(&self, capture: &Capture) -> Result<(), Rejection> {
+        // 空捕获总是丢弃，即使最小脉冲数量设为0
+        let (result, counter) = if (capture.pulse_count() as u32) < self.min_pulses().max(1) {
+            (Err(Rejection::TooFewPulses), &self.too_few_pulses)
+        } else if capture.durations()[0] < self.min_header_us() {
+            (Err(Rejection::ShortHeader), &self.short_header)
+        } else {
+            (Ok(()), &self.accepted)
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+
+    pub fn stats(&self) -> NoiseStats {
+        NoiseStats {
+            accepted: self.accepted.load(Ordering::Relaxed),
+            too_few_pulses: self.too_few_pulses.load(Ordering::Relaxed),
+            short_header: self.short_header.load(Ordering::Relaxed),
+        }
+    }
+}
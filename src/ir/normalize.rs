@@ -0,0 +1,128 @@
+use super::within_tolerance;
+
+/// 把时长聚类到桶中，用桶的中心值替换原始时长
+///
+/// 同一按键每次发送的时长都会有抖动，归一化后两次捕获的桶序列完全一致，
+/// 可以直接比较或计算指纹。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Normalized {
+    /// 各桶的中心值（微秒），从小到大排列
+    pub centroids: Vec<u32>,
+    /// 每个时长所属的桶编号
+    pub indices: Vec<u16>,
+}
+
+/// 默认的分桶容差（百分比）
+pub const BUCKET_TOLERANCE_PERCENT: u32 = 15;
+
+/// k-means细化的迭代次数
+const REFINE_ITERATIONS: usize = 2;
+
+impl Normalized {
+    /// 按给定容差（百分比）对时长聚类
+    pub fn new(durations: &[u32], tolerance_percent: u32) -> Self {
+        let mut centroids = initial_centroids(durations, tolerance_percent);
+
+        // 以贪心分桶结果为初值，做几轮k-means细化
+        for _ in 0..REFINE_ITERATIONS {
+            let mut sums = vec![(0u64, 0u32); centroids.len()];
+            for &duration in durations {
+                let (sum, count) = &mut sums[nearest(&centroids, duration)];
+                *sum += duration as u64;
+                *count += 1;
+            }
+            centroids = sums
+                .into_iter()
+                .filter(|&(_, count)| count > 0)
+                .map(|(sum, count)| (sum / count as u64) as u32)
+                .collect();
+        }
+
+        let indices = durations
+            .iter()
+            .map(|&duration| nearest(&centroids, duration) as u16)
+            .collect();
+
+        Self { centroids, indices }
+    }
+
+    /// 用桶中心值表示的时长序列
+    pub fn durations(&self) -> Vec<u32> {
+        self.indices
+            .iter()
+            .map(|&index| self.centroids[index as usize])
+            .collect()
+    }
+
+    /// 与另一次捕获的相似度（百分比）：逐个位置比较两者所属桶的中心值，落在容差内的占比
+    ///
+    /// 比较的是桶中心值而不是原始时长，两次按键之间的抖动已被平均掉；长度最多允许相差一个结尾脉冲。
+    pub fn similarity(&self, other: &Normalized, tolerance_percent: u32) -> u32 {
+        let longest = self.indices.len().max(other.indices.len());
+        if longest == 0 || self.indices.len().abs_diff(other.indices.len()) > 1 {
+            return 0;
+        }
+        let matching = self
+            .indices
+            .iter()
+            .zip(&other.indices)
+            .filter(|&(&a, &b)| {
+                within_tolerance(other.centroids[b as usize], self.centroids[a as usize], tolerance_percent)
+            })
+            .count();
+        (matching * 100 / longest) as u32
+    }
+
+    /// 基于桶编号序列的FNV-1a哈希，与具体的中心值无关
+    pub fn fingerprint(&self) -> u32 {
+        const FNV_OFFSET: u32 = 0x811c9dc5;
+        const FNV_PRIME: u32 = 0x01000193;
+
+        self.indices
+            .iter()
+            .flat_map(|index| index.to_le_bytes())
+            .fold(FNV_OFFSET, |hash, byte| (hash ^ byte as u32).wrapping_mul(FNV_PRIME))
+    }
+}
+
+/// 归一化时长序列
+pub fn normalize(durations: &[u32], tolerance_percent: u32) -> Vec<u32> {
+    Normalized::new(durations, tolerance_percent).durations()
+}
+
+/// 对排序后的时长做贪心分桶：与当前桶均值相差不超过容差的归入同一桶
+fn initial_centroids(durations: &[u32], tolerance_percent: u32) -> Vec<u32> {
+    let mut sorted = durations.to_vec();
+    sorted.sort_unstable();
+
+    let mut centroids = Vec::new();
+    let mut sum: u64 = 0;
+    let mut count: u64 = 0;
+
+    for duration in sorted {
+        if let Some(mean) = sum.checked_div(count) {
+            if duration as u64 > mean + mean * tolerance_percent as u64 / 100 {
+                centroids.push(mean as u32);
+                sum = 0;
+                count = 0;
+            }
+        }
+        sum += duration as u64;
+        count += 1;
+    }
+    if let Some(mean) = sum.checked_div(count) {
+        centroids.push(mean as u32);
+    }
+
+    centroids
+}
+
+/// 距离最近的桶
+fn nearest(centroids: &[u32], duration: u32) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, &centroid)| centroid.abs_diff(duration))
+        .map(|(index, _)| index)
+        .unwrap_or(0)
+}
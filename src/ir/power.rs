@@ -0,0 +1,100 @@
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::sync::Arc;
+
+use esp_idf_hal::delay::Ets;
+use esp_idf_hal::ledc::LedcDriver;
+use esp_idf_svc::sys::EspError;
+#[cfg(feature = "tx-power")]
+use esp_idf_hal::{
+    gpio::OutputPin,
+    ledc::{config::TimerConfig, LedcChannel, LedcTimer, LedcTimerDriver, Resolution},
+    peripheral::Peripheral,
+    units::FromValueType,
+};
+
+/// PWM频率远高于载波频率，使能电路需要RC滤波得到平稳的电流
+#[cfg(feature = "tx-power")]
+const PWM_FREQUENCY_HZ: u32 = 200_000;
+
+/// 默认全功率发射
+pub const DEFAULT_TX_POWER_PERCENT: u8 = 100;
+
+/// 打开使能引脚后等待驱动电路稳定的默认时长（微秒）
+pub const DEFAULT_WARM_UP_US: u32 = 100;
+pub const MAX_WARM_UP_US: u32 = 10_000;
+
+/// 发射功率设置，可以在运行时修改，下一次发射时生效
+pub struct PowerSettings {
+    percent: AtomicU8,
+    warm_up_us: AtomicU32,
+}
+
+impl PowerSettings {
+    pub fn new(percent: u8, warm_up_us: u32) -> Self {
+        let settings = Self {
+            percent: AtomicU8::new(DEFAULT_TX_POWER_PERCENT),
+            warm_up_us: AtomicU32::new(DEFAULT_WARM_UP_US),
+        };
+        settings.set_tx_power(percent);
+        settings.set_warm_up_us(warm_up_us);
+        settings
+    }
+
+    /// 发射功率（百分比）
+    pub fn tx_power(&self) -> u8 {
+        self.percent.load(Ordering::Relaxed)
+    }
+
+    /// 设置发射功率，超出1~100%时取最近的有效值
+    pub fn set_tx_power(&self, percent: u8) {
+        self.percent.store(percent.clamp(1, 100), Ordering::Relaxed);
+    }
+
+    /// 打开使能引脚到第一个mark之间的等待时间（微秒）
+    pub fn warm_up_us(&self) -> u32 {
+        self.warm_up_us.load(Ordering::Relaxed)
+    }
+
+    pub fn set_warm_up_us(&self, warm_up_us: u32) {
+        self.warm_up_us.store(warm_up_us.min(MAX_WARM_UP_US), Ordering::Relaxed);
+    }
+}
+
+/// 发射管使能引脚，PWM占空比决定发射功率
+///
+/// 只在发射期间输出，其余时间保持低电平以省电。
+pub struct TxPower {
+    driver: LedcDriver<'static>,
+    settings: Arc<PowerSettings>,
+}
+
+impl TxPower {
+    #[cfg(feature = "tx-power")]
+    pub fn new<C: LedcChannel, T: LedcTimer + 'static>(
+        channel: impl Peripheral<P = C> + 'static,
+        timer: impl Peripheral<P = T> + 'static,
+        pin: impl Peripheral<P = impl OutputPin> + 'static,
+        settings: Arc<PowerSettings>,
+    ) -> Result<Self, EspError> {
+        let config = TimerConfig::new()
+            .frequency(PWM_FREQUENCY_HZ.Hz())
+            .resolution(Resolution::Bits8);
+        let timer = LedcTimerDriver::new(timer, &config)?;
+        let mut driver = LedcDriver::new(channel, timer, pin)?;
+        driver.set_duty(0)?;
+
+        Ok(Self { driver, settings })
+    }
+
+    /// 按设置的功率打开使能引脚，并等待驱动电路稳定
+    pub fn enable(&mut self) -> Result<(), EspError> {
+        let duty = self.driver.get_max_duty() * self.settings.tx_power() as u32 / 100;
+        self.driver.set_duty(duty)?;
+        Ets::delay_us(self.settings.warm_up_us());
+        Ok(())
+    }
+
+    pub fn disable(&mut self) -> Result<(), EspError> {
+        self.driver.set_duty(0)
+    }
+}
@@ -0,0 +1,204 @@
+use std::fmt;
+
+use super::receiver::Capture;
+use super::transmitter::DEFAULT_CARRIER_HZ;
+
+/// 学习码格式，时长以载波周期为单位
+const FORMAT_LEARNED: u16 = 0x0000;
+
+/// Pronto时钟周期，单位为百万分之一微秒（0.241246µs）
+const CLOCK_PERIOD_PICOS: u64 = 241_246;
+
+/// 头部的四个字：格式、频率、单次序列的脉冲对数、重复序列的脉冲对数
+const HEADER_WORDS: usize = 4;
+
+/// 导出时补在最后一个mark之后的帧间空闲（微秒）
+const TRAILING_GAP_US: u32 = 40_000;
+
+/// Pronto解析失败的原因
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProntoError {
+    /// 第几个字不是合法的十六进制
+    InvalidWord(usize),
+    /// 只支持学习码格式0000
+    UnsupportedFormat(u16),
+    /// 频率字为0
+    ZeroFrequency,
+    /// 脉冲对数量与头部声明的不一致
+    LengthMismatch { expected: usize, actual: usize },
+}
+
+impl fmt::Display for ProntoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidWord(index) => write!(f, "第{}个字不是4位十六进制数", index),
+            Self::UnsupportedFormat(0x0100) => write!(f, "不支持未调制的格式0100，只支持学习码格式0000"),
+            Self::UnsupportedFormat(0x0900) => write!(f, "不支持PPM格式0900，只支持学习码格式0000"),
+            Self::UnsupportedFormat(format) => write!(f, "不支持的格式{:04X}，只支持学习码格式0000", format),
+            Self::ZeroFrequency => write!(f, "载波频率字为0"),
+            Self::LengthMismatch { expected, actual } => {
+                write!(f, "头部声明{}个字，实际有{}个字", expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProntoError {}
+
+/// 解析后的Pronto学习码，时长以微秒为单位，每个序列都以space结束
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProntoCode {
+    pub carrier_hz: u32,
+    /// 按键时发送一次的序列
+    pub once: Vec<u32>,
+    /// 按住按键时重复发送的序列
+    pub repeat: Vec<u32>,
+}
+
+/// 解析空白分隔的Pronto十六进制字符串
+pub fn parse(text: &str) -> Result<ProntoCode, ProntoError> {
+    let words = text
+        .split_whitespace()
+        .enumerate()
+        .map(|(index, word)| match word.len() {
+            1..=4 => u16::from_str_radix(word, 16).map_err(|_| ProntoError::InvalidWord(index)),
+            _ => Err(ProntoError::InvalidWord(index)),
+        })
+        .collect::<Result<Vec<u16>, _>>()?;
+
+    let Some(&[format, frequency, once_pairs, repeat_pairs]) = words.get(..HEADER_WORDS) else {
+        return Err(ProntoError::LengthMismatch {
+            expected: HEADER_WORDS,
+            actual: words.len(),
+        });
+    };
+    if format != FORMAT_LEARNED {
+        return Err(ProntoError::UnsupportedFormat(format));
+    }
+    if frequency == 0 {
+        return Err(ProntoError::ZeroFrequency);
+    }
+
+    let once_len = once_pairs as usize * 2;
+    let expected = HEADER_WORDS + once_len + repeat_pairs as usize * 2;
+    if words.len() != expected {
+        return Err(ProntoError::LengthMismatch {
+            expected,
+            actual: words.len(),
+        });
+    }
+
+    // 每个时长都是载波周期的整数倍
+    let period_picos = frequency as u64 * CLOCK_PERIOD_PICOS;
+    let to_us = |&cycles: &u16| ((cycles as u64 * period_picos + 500_000) / 1_000_000) as u32;
+    let bursts = &words[HEADER_WORDS..];
+
+    Ok(ProntoCode {
+        carrier_hz: ((1_000_000_000_000 + period_picos / 2) / period_picos) as u32,
+        once: bursts[..once_len].iter().map(to_us).collect(),
+        repeat: bursts[once_len..].iter().map(to_us).collect(),
+    })
+}
+
+/// 把捕获导出为格式0000的Pronto字符串，没有测量载波时按38kHz计算
+///
+/// 多帧捕获连同帧间空闲展开；以mark结束时补一个帧间空闲，使脉冲对完整；全部时长作为单次序列。
+pub fn from_capture(capture: &Capture) -> String {
+    let carrier_hz = capture.carrier_hz.unwrap_or(DEFAULT_CARRIER_HZ).max(1) as u64;
+    let frequency = ((1_000_000_000_000 / carrier_hz + CLOCK_PERIOD_PICOS / 2) / CLOCK_PERIOD_PICOS).clamp(1, 0xFFFF);
+    let period_picos = frequency * CLOCK_PERIOD_PICOS;
+
+    let mut durations = capture.flatten();
+    if durations.len() % 2 == 1 {
+        durations.push(TRAILING_GAP_US);
+    }
+
+    let mut words = vec![FORMAT_LEARNED, frequency as u16, (durations.len() / 2) as u16, 0];
+    words.extend(durations.iter().map(|&us| {
+        // 四舍五入到最近的载波周期，至少为1个周期
+        ((us as u64 * 1_000_000 + period_picos / 2) / period_picos).clamp(1, 0xFFFF) as u16
+    }));
+
+    words
+        .iter()
+        .map(|word| format!("{:04X}", word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::receiver::Frame;
+
+    fn nec_capture(carrier_hz: Option<u32>) -> Capture {
+        let mut durations = vec![9000, 4500];
+        for bit in 0..32 {
+            durations.push(560);
+            durations.push(if bit % 2 == 0 { 1690 } else { 560 });
+        }
+        durations.push(560);
+        Capture {
+            frames: vec![
+                Frame {
+                    durations,
+                    gap_us: 40_000,
+                },
+                Frame {
+                    durations: vec![9000, 2250, 560],
+                    gap_us: 96_000,
+                },
+            ],
+            carrier_hz,
+            timing: None,
+        }
+    }
+
+    /// 两个时长相差不超过半个载波周期
+    fn assert_close(actual: &[u32], expected: &[u32], carrier_hz: u32) {
+        let half_period = 1_000_000 / carrier_hz / 2 + 1;
+        assert_eq!(actual.len(), expected.len());
+        for (&actual, &expected) in actual.iter().zip(expected) {
+            assert!(actual.abs_diff(expected) <= half_period, "{} != {}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn round_trips_capture() {
+        for carrier_hz in [36_000, 38_000, 40_000, 56_000] {
+            let capture = nec_capture(Some(carrier_hz));
+            let code = parse(&from_capture(&capture)).unwrap();
+
+            // 载波被量化到Pronto时钟周期的整数倍，误差在1%以内
+            assert!(code.carrier_hz.abs_diff(carrier_hz) * 100 <= carrier_hz, "{}", code.carrier_hz);
+            assert!(code.repeat.is_empty());
+
+            // 以mark结束的捕获会补上帧间空闲
+            let mut expected = capture.flatten();
+            expected.push(TRAILING_GAP_US);
+            assert_close(&code.once, &expected, carrier_hz);
+        }
+    }
+
+    #[test]
+    fn defaults_to_38khz_without_carrier() {
+        let code = parse(&from_capture(&nec_capture(None))).unwrap();
+        assert!(code.carrier_hz.abs_diff(DEFAULT_CARRIER_HZ) * 100 <= DEFAULT_CARRIER_HZ);
+    }
+
+    #[test]
+    fn parses_reference_code() {
+        let code = parse("0000 006D 0001 0001 0157 00AC 0015 05E7").unwrap();
+        assert_eq!(code.carrier_hz, 38_029);
+        assert_eq!(code.once, [9019, 4523]);
+        assert_eq!(code.repeat, [552, 39_733]);
+    }
+
+    #[test]
+    fn rejects_invalid_codes() {
+        assert_eq!(parse("0100 006D 0000 0000"), Err(ProntoError::UnsupportedFormat(0x0100)));
+        assert_eq!(parse("0000 0000 0000 0000"), Err(ProntoError::ZeroFrequency));
+        assert_eq!(parse("0000 006D 0001 0000 0157"), Err(ProntoError::LengthMismatch { expected: 6, actual: 5 }));
+        assert_eq!(parse("0000 006D XYZ"), Err(ProntoError::InvalidWord(2)));
+    }
+}
@@ -0,0 +1,74 @@
+use super::normalize::{Normalized, BUCKET_TOLERANCE_PERCENT};
+use super::receiver::Capture;
+use super::{detect_and_decode, Protocol};
+
+/// 默认的质量阈值，低于该分数时提示重新录制
+pub const DEFAULT_THRESHOLD: u8 = 60;
+
+/// 识别出协议
+const DECODE_POINTS: u32 = 30;
+/// 脉冲数量与协议一致
+const PULSE_COUNT_POINTS: u32 = 20;
+/// 每帧都以帧末空闲结束
+const TRAILING_GAP_POINTS: u32 = 15;
+/// 没有溢出
+const NO_OVERFLOW_POINTS: u32 = 15;
+/// 同一桶内时长的离散程度
+const VARIANCE_POINTS: u32 = 20;
+
+/// 时长偏离桶中心的平均比例（百分比）不超过该值时得满分
+const FULL_DEVIATION_PERCENT: u32 = 3;
+/// 平均偏离达到该值时不得分
+const ZERO_DEVIATION_PERCENT: u32 = 12;
+
+/// 给捕获打分（0–100），分数低说明捕获可能不完整，重放时容易失败
+///
+/// 依次考虑：能否识别出协议、协议是否用到了全部脉冲、每帧是否以mark结束（即由帧末空闲结束而不是被截断）、
+/// 是否溢出，以及归一化后同一桶内时长的离散程度。无法识别的信号没有协议可对照，只按后几项计分。
+pub fn score(capture: &Capture, truncated: bool) -> u8 {
+    let durations = capture.durations();
+    let protocol = detect_and_decode(durations).protocol();
+
+    let mut points = 0;
+    if protocol != Protocol::Unknown {
+        points += DECODE_POINTS;
+        if uses_all_pulses(protocol, durations) {
+            points += PULSE_COUNT_POINTS;
+        }
+    }
+    // 接收端在帧末空闲处结束一帧，结尾的space不会记录，完整的一帧总是以mark结束
+    if !capture.frames.is_empty() && capture.frames.iter().all(|frame| frame.durations.len() % 2 == 1) {
+        points += TRAILING_GAP_POINTS;
+    }
+    if !truncated {
+        points += NO_OVERFLOW_POINTS;
+    }
+    points += variance_points(capture);
+    points.min(100) as u8
+}
+
+/// 去掉最后一对mark和space后协议就无法解码，说明帧中没有多余的脉冲
+fn uses_all_pulses(protocol: Protocol, durations: &[u32]) -> bool {
+    match durations.len().checked_sub(2) {
+        Some(len) => detect_and_decode(&durations[..len]).protocol() != protocol,
+        None => true,
+    }
+}
+
+/// 按所有帧中时长偏离所属桶中心的平均比例计分
+fn variance_points(capture: &Capture) -> u32 {
+    let (mut deviation, mut count) = (0u64, 0u64);
+    for frame in &capture.frames {
+        let normalized = Normalized::new(&frame.durations, BUCKET_TOLERANCE_PERCENT);
+        for (&duration, &index) in frame.durations.iter().zip(&normalized.indices) {
+            let centroid = normalized.centroids[index as usize].max(1);
+            deviation += duration.abs_diff(centroid) as u64 * 100 / centroid as u64;
+            count += 1;
+        }
+    }
+    let Some(mean) = deviation.checked_div(count) else {
+        return 0;
+    };
+    let mean = (mean as u32).clamp(FULL_DEVIATION_PERCENT, ZERO_DEVIATION_PERCENT);
+    VARIANCE_POINTS * (ZERO_DEVIATION_PERCENT - mean) / (ZERO_DEVIATION_PERCENT - FULL_DEVIATION_PERCENT)
+}
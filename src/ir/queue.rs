@@ -0,0 +1,158 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::pronto::ProntoCode;
+use super::storage::CaptureStorage;
+use super::transmitter::{Carrier, IrTransmitter, TransmitError};
+
+/// 排队等待发射的最大请求数量，不含正在发射的请求
+pub const QUEUE_CAPACITY: usize = 8;
+
+/// 发射线程栈大小，脉冲缓冲区放在堆上
+const QUEUE_STACK_SIZE: usize = 4096;
+
+/// 一次发射请求
+#[derive(Debug)]
+pub enum TransmitRequest {
+    Raw { durations: Vec<u32>, carrier: Carrier },
+    /// 重放保存的录制
+    Replay(String),
+    Pronto { code: ProntoCode, repeats: usize },
+    /// 按住重复发送，重复代数变化后停止
+    Repeat {
+        slot: String,
+        protocol_aware: bool,
+        generation: u32,
+    },
+}
+
+/// 发射线程上报给主循环的事件
+#[derive(Debug)]
+pub enum TransmitEvent {
+    Started(u32),
+    Finished { ticket: u32, result: Result<(), TransmitError> },
+}
+
+/// 队列已满，客户端可以稍后重试
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFull;
+
+/// 发射队列，请求按入队顺序在独立线程中依次发射
+///
+/// 前后两次发射之间的最小间隔由`IrTransmitter`保证。
+pub struct TransmitQueue {
+    requests: SyncSender<(u32, TransmitRequest)>,
+    next_ticket: u32,
+    /// 每次开始或停止重复都会递增，旧的重复循环据此退出
+    repeat_generation: Arc<AtomicU32>,
+}
+
+impl TransmitQueue {
+    pub fn spawn<E: From<TransmitEvent> + Send + 'static>(
+        transmitter: Arc<Mutex<IrTransmitter>>,
+        store: Arc<Mutex<CaptureStorage>>,
+        events: Sender<E>,
+    ) -> std::io::Result<Self> {
+        let (requests, pending) = mpsc::sync_channel::<(u32, TransmitRequest)>(QUEUE_CAPACITY);
+        let repeat_generation = Arc::new(AtomicU32::new(0));
+        let current_generation = repeat_generation.clone();
+
+        thread::Builder::new()
+            .name("ir-transmit".into())
+            .stack_size(QUEUE_STACK_SIZE)
+            .spawn(move || {
+                for (ticket, request) in pending {
+                    let _ = events.send(TransmitEvent::Started(ticket).into());
+
+                    // 与主循环相同，先锁发射器再锁录制
+                    let mut transmitter = transmitter.lock().unwrap();
+                    let result = match &request {
+                        TransmitRequest::Raw { durations, carrier } => transmitter.send_raw(durations, *carrier),
+                        TransmitRequest::Replay(slot) => {
+                            let mut store = store.lock().unwrap();
+                            let result = transmitter.replay(&store, slot);
+                            if result.is_ok() {
+                                store.record_use(slot);
+                            }
+                            result
+                        }
+                        TransmitRequest::Pronto { code, repeats } => transmitter.send_pronto(code, *repeats),
+                        TransmitRequest::Repeat {
+                            slot,
+                            protocol_aware,
+                            generation,
+                        } => {
+                            // 读出录制后释放锁，重复期间主循环仍可访问录制
+                            let capture = store.lock().unwrap().load(slot);
+                            match capture {
+                                Some(capture) => transmitter
+                                    .repeat_while(&capture, *protocol_aware, || {
+                                        current_generation.load(Ordering::Relaxed) == *generation
+                                    })
+                                    .map(|frames| {
+                                        log::info!("重复发送{}结束，共{}帧", slot, frames);
+                                        store.lock().unwrap().record_use(slot);
+                                    }),
+                                None => Err(TransmitError::UnknownSlot(slot.clone())),
+                            }
+                        }
+                    };
+                    drop(transmitter);
+
+                    if events.send(TransmitEvent::Finished { ticket, result }.into()).is_err() {
+                        log::warn!("发射事件通道已关闭，发射线程退出");
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            requests,
+            next_ticket: 1,
+            repeat_generation,
+        })
+    }
+
+    /// 发射线程启动失败时使用，所有请求都被拒绝
+    pub fn unavailable() -> Self {
+        let (requests, _) = mpsc::sync_channel(QUEUE_CAPACITY);
+        Self {
+            requests,
+            next_ticket: 1,
+            repeat_generation: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    /// 请求入队，返回用于匹配完成事件的编号
+    pub fn enqueue(&mut self, request: TransmitRequest) -> Result<u32, QueueFull> {
+        let ticket = self.next_ticket;
+        match self.requests.try_send((ticket, request)) {
+            Ok(()) => {
+                self.next_ticket = self.next_ticket.wrapping_add(1).max(1);
+                Ok(ticket)
+            }
+            Err(TrySendError::Full(_)) => Err(QueueFull),
+            Err(TrySendError::Disconnected(_)) => {
+                log::error!("发射线程没有运行");
+                Err(QueueFull)
+            }
+        }
+    }
+
+    /// 开始按住重复发送录制，之前的重复会先停止
+    pub fn start_repeating(&mut self, slot: &str, protocol_aware: bool) -> Result<u32, QueueFull> {
+        let generation = self.repeat_generation.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        self.enqueue(TransmitRequest::Repeat {
+            slot: slot.to_string(),
+            protocol_aware,
+            generation,
+        })
+    }
+
+    /// 停止正在进行或尚在排队的重复发送
+    pub fn stop_repeating(&self) {
+        self.repeat_generation.fetch_add(1, Ordering::Relaxed);
+    }
+}
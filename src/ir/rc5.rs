@@ -0,0 +1,208 @@
+use std::fmt;
+
+use super::{expand_manchester, matches, Decoder, IrCommand, Protocol};
+
+/// RC5半位时长（微秒）
+const HALF_BIT: u32 = 889;
+
+/// 起始位、字段位、翻转位、5位地址、6位命令
+const FRAME_BITS: usize = 14;
+
+/// RC5使用36kHz载波
+pub const CARRIER_HZ: u32 = 36_000;
+
+/// 解码后的Philips RC5命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rc5Command {
+    pub address: u8,
+    /// 7位命令，最高位来自RC5X的字段位
+    pub command: u8,
+    pub toggle: bool,
+}
+
+impl fmt::Display for Rc5Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "RC5 addr={:#04x} cmd={:#04x} toggle={}",
+            self.address, self.command, self.toggle as u8
+        )
+    }
+}
+
+/// 从微秒时长序列解码RC5帧
+///
+/// RC5中1编码为先space后mark，第一个起始位的前半段是空闲电平，不会出现在捕获中。
+pub fn decode(durations: &[u32]) -> Option<Rc5Command> {
+    let mut levels = vec![false];
+    levels.extend(expand_manchester(durations, HALF_BIT, 2)?);
+    if levels.len() > FRAME_BITS * 2 {
+        return None;
+    }
+    // 最后一位为0时结尾的space半位同样不会被捕获
+    levels.resize(FRAME_BITS * 2, false);
+
+    let mut raw: u16 = 0;
+    for pair in levels.chunks(2) {
+        let bit = match (pair[0], pair[1]) {
+            (false, true) => 1,
+            (true, false) => 0,
+            _ => return None,
+        };
+        raw = raw << 1 | bit;
+    }
+
+    // 第一个起始位固定为1，第二个起始位取反后作为命令的第7位
+    if raw >> 13 & 1 != 1 {
+        return None;
+    }
+    let field = raw >> 12 & 1;
+
+    Some(Rc5Command {
+        address: (raw >> 6 & 0x1F) as u8,
+        command: (raw & 0x3F) as u8 | ((field ^ 1) << 6) as u8,
+        toggle: raw >> 11 & 1 == 1,
+    })
+}
+
+/// 编码RC5帧，返回从mark开始的曼彻斯特时长序列
+///
+/// 地址为5位，命令为7位（超过6位时使用RC5X的字段位），超出范围时返回None。
+pub fn encode_rc5(address: u8, command: u8, toggle: bool) -> Option<Vec<u32>> {
+    if address > 0x1F || command > 0x7F {
+        return None;
+    }
+
+    let field = (command >> 6 & 1) ^ 1;
+    let raw = 1 << 13 | (field as u16) << 12 | (toggle as u16) << 11 | (address as u16) << 6 | (command & 0x3F) as u16;
+
+    let levels = (0..FRAME_BITS)
+        .rev()
+        .flat_map(|bit| if raw >> bit & 1 == 1 { [false, true] } else { [true, false] });
+
+    // 第一个起始位的前半段是空闲电平，不需要发送
+    let mut durations: Vec<u32> = Vec::with_capacity(FRAME_BITS * 2);
+    let mut mark = false;
+    for level in levels.skip(1) {
+        match durations.last_mut() {
+            Some(last) if level == mark => *last += HALF_BIT,
+            _ => {
+                durations.push(HALF_BIT);
+                mark = level;
+            }
+        }
+    }
+
+    // 结尾的space与空闲电平相同
+    if !mark {
+        durations.pop();
+    }
+    Some(durations)
+}
+
+/// 记录RC5翻转位，每次新的按键都会翻转
+///
+/// 接收端靠翻转位区分连续两次按下同一按键和一直按住按键。
+#[derive(Debug, Default)]
+pub struct Rc5Session {
+    /// 上一次按键使用的翻转位，还没有按过键时为None
+    last_toggle: Option<bool>,
+}
+
+impl Rc5Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 编码一次新的按键，翻转位与上一次按键相反
+    pub fn press(&mut self, address: u8, command: u8) -> Option<Vec<u32>> {
+        let toggle = self.last_toggle == Some(false);
+        let durations = encode_rc5(address, command, toggle)?;
+        self.last_toggle = Some(toggle);
+        Some(durations)
+    }
+
+    /// 按住按键时的重复帧，翻转位与上一次按键相同；还没有按过键时等同于`press`
+    pub fn repeat(&mut self, address: u8, command: u8) -> Option<Vec<u32>> {
+        match self.last_toggle {
+            Some(toggle) => encode_rc5(address, command, toggle),
+            None => self.press(address, command),
+        }
+    }
+}
+
+/// RC5解码器
+pub struct Rc5Decoder;
+
+impl Decoder for Rc5Decoder {
+    fn protocol(&self) -> Protocol {
+        Protocol::Rc5
+    }
+
+    fn matches_header(&self, durations: &[u32]) -> bool {
+        durations
+            .first()
+            .is_some_and(|&first| matches(first, HALF_BIT) || matches(first, HALF_BIT * 2))
+    }
+
+    fn decode(&self, durations: &[u32]) -> Option<IrCommand> {
+        decode(durations).map(IrCommand::Rc5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toggle_of(durations: &[u32]) -> bool {
+        decode(durations).unwrap().toggle
+    }
+
+    #[test]
+    fn round_trips_both_toggle_states() {
+        for toggle in [false, true] {
+            let durations = encode_rc5(0x05, 0x0C, toggle).unwrap();
+            assert_eq!(
+                decode(&durations),
+                Some(Rc5Command {
+                    address: 0x05,
+                    command: 0x0C,
+                    toggle,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn round_trips_rc5x_command() {
+        let durations = encode_rc5(0x1F, 0x7F, false).unwrap();
+        assert_eq!(decode(&durations).map(|command| command.command), Some(0x7F));
+    }
+
+    #[test]
+    fn toggle_flips_between_presses() {
+        let mut session = Rc5Session::new();
+        let first = toggle_of(&session.press(0x05, 0x0C).unwrap());
+        let second = toggle_of(&session.press(0x05, 0x0C).unwrap());
+        let third = toggle_of(&session.press(0x05, 0x0C).unwrap());
+        assert_ne!(first, second);
+        assert_eq!(first, third);
+    }
+
+    #[test]
+    fn toggle_holds_during_repeat() {
+        let mut session = Rc5Session::new();
+        let pressed = toggle_of(&session.press(0x05, 0x0C).unwrap());
+        for _ in 0..3 {
+            assert_eq!(toggle_of(&session.repeat(0x05, 0x0C).unwrap()), pressed);
+        }
+        // 松开后再次按下才翻转
+        assert_ne!(toggle_of(&session.press(0x05, 0x0C).unwrap()), pressed);
+    }
+
+    #[test]
+    fn rejects_out_of_range_fields() {
+        assert_eq!(encode_rc5(0x20, 0, false), None);
+        assert_eq!(encode_rc5(0, 0x80, false), None);
+    }
+}
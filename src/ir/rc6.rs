@@ -0,0 +1,101 @@
+use std::fmt;
+
+use super::{expand_manchester, matches, Decoder, IrCommand, Protocol};
+
+// RC6协议时序（微秒）
+const LEADER_MARK: u32 = 2666;
+const LEADER_SPACE: u32 = 889;
+const UNIT: u32 = 444;
+
+/// 引导码之后的半位数量：起始位2、模式位6、双倍宽度的翻转位4、地址和命令32
+const FRAME_UNITS: usize = 44;
+
+/// 翻转位在半位序列中的位置
+const TRAILER_POS: usize = 8;
+
+/// 解码后的Philips RC6命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rc6Command {
+    pub mode: u8,
+    pub address: u8,
+    pub command: u8,
+    pub toggle: bool,
+}
+
+impl fmt::Display for Rc6Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "RC6 mode={} addr={:#04x} cmd={:#04x} toggle={}",
+            self.mode, self.address, self.command, self.toggle as u8
+        )
+    }
+}
+
+/// 读取一个普通宽度的位，RC6中1编码为先mark后space
+fn read_bit(levels: &[bool], pos: usize) -> Option<u8> {
+    match (levels[pos], levels[pos + 1]) {
+        (true, false) => Some(1),
+        (false, true) => Some(0),
+        _ => None,
+    }
+}
+
+fn read_bits(levels: &[bool], pos: usize, count: usize) -> Option<u8> {
+    (0..count).try_fold(0u8, |value, bit| Some(value << 1 | read_bit(levels, pos + bit * 2)?))
+}
+
+/// 从微秒时长序列解码RC6帧
+pub fn decode(durations: &[u32]) -> Option<Rc6Command> {
+    if durations.len() < 3
+        || !matches(durations[0], LEADER_MARK)
+        || !matches(durations[1], LEADER_SPACE)
+    {
+        return None;
+    }
+
+    let mut levels = expand_manchester(&durations[2..], UNIT, 3)?;
+    if levels.len() > FRAME_UNITS {
+        return None;
+    }
+    levels.resize(FRAME_UNITS, false);
+
+    // 起始位固定为1
+    if read_bit(&levels, 0)? != 1 {
+        return None;
+    }
+    let mode = read_bits(&levels, 2, 3)?;
+
+    let trailer = &levels[TRAILER_POS..TRAILER_POS + 4];
+    let toggle = match trailer {
+        [true, true, false, false] => true,
+        [false, false, true, true] => false,
+        _ => return None,
+    };
+
+    Some(Rc6Command {
+        mode,
+        address: read_bits(&levels, TRAILER_POS + 4, 8)?,
+        command: read_bits(&levels, TRAILER_POS + 20, 8)?,
+        toggle,
+    })
+}
+
+/// RC6解码器
+pub struct Rc6Decoder;
+
+impl Decoder for Rc6Decoder {
+    fn protocol(&self) -> Protocol {
+        Protocol::Rc6
+    }
+
+    fn matches_header(&self, durations: &[u32]) -> bool {
+        durations.len() >= 2
+            && matches(durations[0], LEADER_MARK)
+            && matches(durations[1], LEADER_SPACE)
+    }
+
+    fn decode(&self, durations: &[u32]) -> Option<IrCommand> {
+        decode(durations).map(IrCommand::Rc6)
+    }
+}
@@ -0,0 +1,229 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use esp_idf_hal::delay::FreeRtos;
+use esp_idf_hal::rmt::{Pulse, Receive, RxRmtDriver};
+use esp_idf_svc::sys::{self, EspError};
+
+use super::assembler::{CaptureAssembler, CaptureError};
+use super::filter::remove_glitches;
+use super::noise::NoiseFilter;
+use super::timing::ProtocolTiming;
+use crate::powersave::IrWake;
+use crate::watchdog;
+
+/// 接收线程栈大小，脉冲缓冲区放在堆上
+const RECEIVER_STACK_SIZE: usize = 4096;
+
+/// RMT驱动环形缓冲区可容纳的脉冲对数量，需要能放下若干个最长的捕获
+pub const RING_BUFFER_PAIRS: usize = 4096;
+
+/// 导入的时长序列中超过该时长的space视为帧间空闲，与接收端的空闲阈值一致
+pub const MIN_FRAME_GAP_US: u32 = 10_000;
+
+/// 每次等待信号的FreeRTOS tick数，超时后回到循环检查通道是否仍然有效
+const RECEIVE_TIMEOUT_TICKS: u32 = 100;
+
+/// 一帧信号，时长以微秒为单位，mark与space交替
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub durations: Vec<u32>,
+    /// 本帧结束到下一帧开始的空闲（微秒），最后一帧为0
+    pub gap_us: u32,
+}
+
+/// 一次红外捕获，空调等协议的一次按键可能包含多帧
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capture {
+    pub frames: Vec<Frame>,
+    /// 测量到的载波频率（Hz），发射时使用
+    pub carrier_hz: Option<u32>,
+    /// 发射时代替协议时序表，用于帧间隔特殊的设备
+    pub timing: Option<ProtocolTiming>,
+}
+
+impl Capture {
+    /// 只包含一帧的捕获
+    pub fn new(durations: Vec<u32>) -> Self {
+        Self {
+            frames: vec![Frame { durations, gap_us: 0 }],
+            carrier_hz: None,
+            timing: None,
+        }
+    }
+
+    /// 第一帧的时长，解码、匹配和过滤都只看第一帧
+    pub fn durations(&self) -> &[u32] {
+        self.frames.first().map_or(&[], |frame| &frame.durations)
+    }
+
+    /// 所有帧的脉冲（mark与space）数量
+    pub fn pulse_count(&self) -> usize {
+        self.frames.iter().map(|frame| frame.durations.len()).sum()
+    }
+
+    /// 与`flatten`相反，在不短于`min_gap_us`的space处拆分成多帧，结尾的space被丢弃
+    pub fn from_flat(durations: Vec<u32>, min_gap_us: u32) -> Self {
+        let mut frames = Vec::new();
+        let mut frame = Vec::new();
+        for (index, duration) in durations.into_iter().enumerate() {
+            if index % 2 == 1 && duration >= min_gap_us {
+                frames.push(Frame {
+                    durations: std::mem::take(&mut frame),
+                    gap_us: duration,
+                });
+            } else {
+                frame.push(duration);
+            }
+        }
+        match frames.last_mut() {
+            Some(last) if frame.is_empty() => last.gap_us = 0,
+            _ => frames.push(Frame {
+                durations: frame,
+                gap_us: 0,
+            }),
+        }
+        Self {
+            frames,
+            carrier_hz: None,
+            timing: None,
+        }
+    }
+
+    /// 把所有帧连同帧间空闲拼成一个时长序列
+    pub fn flatten(&self) -> Vec<u32> {
+        let mut durations = Vec::with_capacity(self.pulse_count() + self.frames.len());
+        for (index, frame) in self.frames.iter().enumerate() {
+            durations.extend_from_slice(&frame.durations);
+            if index + 1 < self.frames.len() {
+                durations.push(frame.gap_us);
+            }
+        }
+        durations
+    }
+}
+
+/// 接收线程上报给主循环的事件
+#[derive(Debug)]
+pub enum IrEvent {
+    /// 完整接收到一帧信号，`ended_at`是检测到帧末空闲的时刻
+    Captured { capture: Capture, ended_at: Instant },
+    /// 超过最大捕获长度，捕获被截断
+    Overflow(Capture),
+}
+
+/// 在独立线程中运行RMT接收，把完成的捕获推送到通道
+///
+/// `receiver`需要已经调用过`start`，启动失败应在创建线程之前报告给自检。
+/// 分段读取的结果由`assembler`拼接成完整捕获；`min_pulse_us`是软件毛刺滤波阈值，
+/// `noise`丢弃干扰产生的短捕获，`idle_threshold_us`是帧末空闲阈值，都可以在运行时修改。
+/// 省电模式中由`wake`唤醒后，一帧结束时通知它允许再次进入light sleep。
+pub fn spawn_receiver<E: From<IrEvent> + Send + 'static>(
+    mut receiver: RxRmtDriver<'static>,
+    mut assembler: CaptureAssembler,
+    min_pulse_us: Arc<AtomicU32>,
+    idle_threshold_us: Arc<AtomicU32>,
+    noise: Arc<NoiseFilter>,
+    wake: Option<Arc<IrWake>>,
+    events: Sender<E>,
+) -> std::io::Result<JoinHandle<()>> {
+    thread::Builder::new()
+        .name("ir-receiver".into())
+        .stack_size(RECEIVER_STACK_SIZE)
+        .spawn(move || {
+            log::info!("RMT接收已启动，最大捕获长度: {}个脉冲对", assembler.max_pairs());
+
+            // 多留一个位置给帧末的结束标记
+            let mut pulses = vec![(Pulse::zero(), Pulse::zero()); assembler.max_pairs() + 1];
+
+            // 拼接完成后再做软件毛刺滤波
+            let filter = |capture: Capture| {
+                Capture::new(remove_glitches(capture.durations(), min_pulse_us.load(Ordering::Relaxed)))
+            };
+
+            // 驱动创建时已经按启动时的配置设置了空闲阈值
+            let mut applied_idle_us = idle_threshold_us.load(Ordering::Relaxed);
+
+            // 每次等待信号超时后都会喂狗，读取卡住时由任务看门狗重启
+            let watchdog = match watchdog::Subscription::current() {
+                Ok(subscription) => Some(subscription),
+                Err(e) => {
+                    log::error!("接收线程订阅任务看门狗失败: {:?}", e);
+                    None
+                }
+            };
+
+            loop {
+                if let Some(watchdog) = &watchdog {
+                    watchdog.feed();
+                }
+                let idle_us = idle_threshold_us.load(Ordering::Relaxed);
+                if idle_us != applied_idle_us {
+                    applied_idle_us = idle_us;
+                    match set_idle_threshold(&receiver, assembler.tick().us_to_ticks(idle_us)) {
+                        Ok(()) => log::info!("接收空闲阈值: {}µs", idle_us),
+                        Err(e) => log::error!("设置接收空闲阈值失败: {:?}", e),
+                    }
+                }
+
+                let result = match receiver.receive(&mut pulses, RECEIVE_TIMEOUT_TICKS) {
+                    Ok(Receive::Read(count)) => match assembler.push(&pulses[..count]) {
+                        Some(result) => result,
+                        // 帧尚未结束，继续读取下一段
+                        None => continue,
+                    },
+                    Ok(Receive::Overflow(count)) => {
+                        let count = count.min(pulses.len());
+                        Err(assembler.overflow(&pulses[..count]))
+                    }
+                    // 不记录超时，减少日志输出；唤醒后没有收到完整的帧时也允许再次休眠
+                    Ok(Receive::Timeout) => {
+                        if let Some(wake) = &wake {
+                            wake.frame_done();
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        log::error!("RMT接收错误: {:?}", e);
+                        FreeRtos::delay_ms(100);
+                        continue;
+                    }
+                };
+
+                if let Some(wake) = &wake {
+                    wake.frame_done();
+                }
+
+                let event = match result.map(filter) {
+                    Ok(capture) => match noise.check(&capture) {
+                        Ok(()) => IrEvent::Captured {
+                            capture,
+                            ended_at: Instant::now(),
+                        },
+                        Err(rejection) => {
+                            log::debug!("丢弃干扰捕获: {:?}, 脉冲数量: {}", rejection, capture.pulse_count());
+                            continue;
+                        }
+                    },
+                    Err(e) => {
+                        log::warn!("{}", e);
+                        let CaptureError::Truncated { capture, .. } = e;
+                        IrEvent::Overflow(filter(capture))
+                    }
+                };
+
+                if events.send(event.into()).is_err() {
+                    log::warn!("红外事件通道已关闭，接收线程退出");
+                    break;
+                }
+            }
+        })
+}
+
+/// 修改帧末空闲阈值，接收过程中也可以调用
+fn set_idle_threshold(receiver: &RxRmtDriver<'static>, ticks: u32) -> Result<(), EspError> {
+    EspError::convert(unsafe { sys::rmt_set_rx_idle_thresh(receiver.channel(), ticks.min(u16::MAX as u32) as u16) })
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::{CaptureEvent, IrCommand, NecCommand};
+
+/// NEC重复帧的发送周期
+const REPEAT_PERIOD: Duration = Duration::from_millis(108);
+
+/// 按住期间Held事件的最小间隔
+const HELD_INTERVAL: Duration = Duration::from_millis(300);
+
+/// 合并后的按键事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyEvent {
+    /// 新的按键，携带第一帧的捕获事件
+    Pressed(CaptureEvent),
+    /// 按键仍被按住，`repeats`为至今收到的重复帧数量
+    Held { command: IrCommand, repeats: u32 },
+    Released(IrCommand),
+}
+
+impl fmt::Display for KeyEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pressed(event) => event.fmt(f),
+            Self::Held { command, repeats } => write!(f, "IR_HELD: {} repeats={}", command, repeats),
+            Self::Released(command) => write!(f, "IR_RELEASED: {}", command),
+        }
+    }
+}
+
+/// 当前按住的按键
+struct HeldKey {
+    command: IrCommand,
+    repeats: u32,
+    last_seen: Instant,
+    last_held: Instant,
+}
+
+/// 把按住按键时的重复帧合并为按下、按住、松开三种事件
+pub struct RepeatCoalescer {
+    release_timeout: Duration,
+    current: Option<HeldKey>,
+}
+
+impl RepeatCoalescer {
+    /// 捕获在最后一个跳变之后再经过空闲阈值才会上报，
+    /// 因此松开超时取重复周期加上几倍空闲阈值的余量
+    pub fn new(idle_threshold: Duration) -> Self {
+        Self {
+            release_timeout: REPEAT_PERIOD + idle_threshold * 4,
+            current: None,
+        }
+    }
+
+    /// 空闲阈值修改后重新计算松开超时
+    pub fn set_idle_threshold(&mut self, idle_threshold: Duration) {
+        self.release_timeout = REPEAT_PERIOD + idle_threshold * 4;
+    }
+
+    /// 输入一个完整的捕获事件，返回产生的按键事件
+    pub fn push(&mut self, event: CaptureEvent, now: Instant) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+
+        // NEC重复帧和重复发送的完整帧都算作按住
+        let is_repeat = matches!(event.command, IrCommand::Nec(NecCommand { repeat: true, .. }));
+        if let Some(key) = self.current.as_mut() {
+            if is_repeat || key.command.same_key(&event.command) {
+                key.repeats += 1;
+                key.last_seen = now;
+                if now.duration_since(key.last_held) >= HELD_INTERVAL {
+                    key.last_held = now;
+                    events.push(KeyEvent::Held {
+                        command: key.command.clone(),
+                        repeats: key.repeats,
+                    });
+                }
+                return events;
+            }
+        }
+
+        events.extend(self.release());
+        // 没有按下过的按键，单独的重复帧无法确定是哪个键
+        if !is_repeat {
+            self.current = Some(HeldKey {
+                command: event.command.clone(),
+                repeats: 0,
+                last_seen: now,
+                last_held: now,
+            });
+            events.push(KeyEvent::Pressed(event));
+        }
+        events
+    }
+
+    /// 超时没有收到重复帧时上报松开
+    pub fn poll(&mut self, now: Instant) -> Option<KeyEvent> {
+        match &self.current {
+            Some(key) if now.duration_since(key.last_seen) >= self.release_timeout => self.release(),
+            _ => None,
+        }
+    }
+
+    fn release(&mut self) -> Option<KeyEvent> {
+        self.current.take().map(|key| KeyEvent::Released(key.command))
+    }
+}
@@ -0,0 +1,200 @@
+use std::fmt;
+
+use super::{decode_pulse_distance, encode_pulse_distance, matches, Decoder, IrCommand, Protocol};
+
+// Samsung协议时序（微秒），与NEC相同的位编码，但引导码为4.5ms/4.5ms
+const HEADER_MARK: u32 = 4500;
+const HEADER_SPACE: u32 = 4500;
+const BIT_MARK: u32 = 560;
+const ZERO_SPACE: u32 = 560;
+const ONE_SPACE: u32 = 1690;
+
+/// 数据位数量：16位地址、命令、命令反码
+const DATA_BITS: usize = 32;
+
+/// Samsung36：16位地址，4.5ms间隔后是20位数据
+const ADDRESS_BITS_36: usize = 16;
+const DATA_BITS_36: usize = 20;
+
+/// Samsung使用38kHz载波
+pub const CARRIER_HZ: u32 = 38_000;
+
+/// 解码后的Samsung命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SamsungCommand {
+    /// 16位地址，两个字节不要求互为反码
+    pub address: u16,
+    pub command: u8,
+}
+
+impl fmt::Display for SamsungCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SAMSUNG addr={:#06x} cmd={:#04x}", self.address, self.command)
+    }
+}
+
+/// 从微秒时长序列解码Samsung帧，只校验命令字节的反码
+pub fn decode(durations: &[u32]) -> Option<SamsungCommand> {
+    if durations.len() < 2
+        || !matches(durations[0], HEADER_MARK)
+        || !matches(durations[1], HEADER_SPACE)
+    {
+        return None;
+    }
+
+    let raw = decode_pulse_distance(&durations[2..], DATA_BITS, BIT_MARK, ZERO_SPACE, ONE_SPACE)?;
+    let [address_low, address_high, command, command_inv] = (raw as u32).to_le_bytes();
+    if command != !command_inv {
+        return None;
+    }
+
+    Some(SamsungCommand {
+        address: u16::from_le_bytes([address_low, address_high]),
+        command,
+    })
+}
+
+/// 编码32位Samsung帧
+///
+/// 地址不超过0xFF时按Samsung电视的格式把地址字节重复发送两次，例如0x07编码为0x0707。
+pub fn encode_samsung(address: u16, command: u8) -> Vec<u32> {
+    let address = if address <= 0xFF { address << 8 | address } else { address };
+    let raw = u32::from_le_bytes([address as u8, (address >> 8) as u8, command, !command]);
+
+    let mut durations = vec![HEADER_MARK, HEADER_SPACE];
+    encode_pulse_distance(&mut durations, raw as u64, DATA_BITS, BIT_MARK, ZERO_SPACE, ONE_SPACE);
+    durations
+}
+
+/// 解码后的Samsung36命令，常见于回音壁
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Samsung36Command {
+    pub address: u16,
+    /// 20位数据
+    pub data: u32,
+}
+
+impl fmt::Display for Samsung36Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SAMSUNG36 addr={:#06x} data={:#07x}", self.address, self.data)
+    }
+}
+
+/// 编码Samsung36帧，数据超过20位时返回None
+pub fn encode_samsung36(address: u16, data: u32) -> Option<Vec<u32>> {
+    if data >> DATA_BITS_36 != 0 {
+        return None;
+    }
+
+    let mut durations = vec![HEADER_MARK, HEADER_SPACE];
+    encode_pulse_distance(&mut durations, address as u64, ADDRESS_BITS_36, BIT_MARK, ZERO_SPACE, ONE_SPACE);
+    durations.push(HEADER_SPACE);
+    encode_pulse_distance(&mut durations, data as u64, DATA_BITS_36, BIT_MARK, ZERO_SPACE, ONE_SPACE);
+    Some(durations)
+}
+
+/// 从微秒时长序列解码Samsung36帧
+pub fn decode36(durations: &[u32]) -> Option<Samsung36Command> {
+    if durations.len() < 2
+        || !matches(durations[0], HEADER_MARK)
+        || !matches(durations[1], HEADER_SPACE)
+    {
+        return None;
+    }
+
+    let address = decode_pulse_distance(&durations[2..], ADDRESS_BITS_36, BIT_MARK, ZERO_SPACE, ONE_SPACE)?;
+    // 地址的结束mark之后是一个与引导码相同的space
+    let data_start = 2 + ADDRESS_BITS_36 * 2 + 2;
+    if !matches(*durations.get(data_start - 1)?, HEADER_SPACE) {
+        return None;
+    }
+    let data = decode_pulse_distance(&durations[data_start..], DATA_BITS_36, BIT_MARK, ZERO_SPACE, ONE_SPACE)?;
+
+    Some(Samsung36Command {
+        address: address as u16,
+        data: data as u32,
+    })
+}
+
+/// Samsung解码器
+pub struct SamsungDecoder;
+
+impl Decoder for SamsungDecoder {
+    fn protocol(&self) -> Protocol {
+        Protocol::Samsung
+    }
+
+    fn matches_header(&self, durations: &[u32]) -> bool {
+        durations.len() >= 2
+            && matches(durations[0], HEADER_MARK)
+            && matches(durations[1], HEADER_SPACE)
+    }
+
+    fn decode(&self, durations: &[u32]) -> Option<IrCommand> {
+        decode(durations).map(IrCommand::Samsung)
+    }
+}
+
+/// Samsung36解码器
+pub struct Samsung36Decoder;
+
+impl Decoder for Samsung36Decoder {
+    fn protocol(&self) -> Protocol {
+        Protocol::Samsung36
+    }
+
+    fn matches_header(&self, durations: &[u32]) -> bool {
+        durations.len() >= 2
+            && matches(durations[0], HEADER_MARK)
+            && matches(durations[1], HEADER_SPACE)
+    }
+
+    fn decode(&self, durations: &[u32]) -> Option<IrCommand> {
+        decode36(durations).map(IrCommand::Samsung36)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_samsung() {
+        for (address, command) in [(0x0707, 0x02), (0xE0E0, 0x40), (0x1234, 0xFF)] {
+            assert_eq!(
+                decode(&encode_samsung(address, command)),
+                Some(SamsungCommand { address, command })
+            );
+        }
+    }
+
+    #[test]
+    fn repeats_short_address() {
+        assert_eq!(
+            decode(&encode_samsung(0x07, 0x02)),
+            Some(SamsungCommand {
+                address: 0x0707,
+                command: 0x02,
+            })
+        );
+    }
+
+    #[test]
+    fn round_trips_samsung36() {
+        for (address, data) in [(0x0400, 0xCF00E), (0xFFFF, 0), (0x0001, 0xFFFFF)] {
+            let durations = encode_samsung36(address, data).unwrap();
+            assert_eq!(decode36(&durations), Some(Samsung36Command { address, data }));
+        }
+    }
+
+    #[test]
+    fn rejects_samsung36_data_over_20_bits() {
+        assert_eq!(encode_samsung36(0x0400, 0x100000), None);
+    }
+
+    #[test]
+    fn variants_do_not_cross_decode() {
+        assert_eq!(decode36(&encode_samsung(0x0707, 0x02)), None);
+        assert_eq!(decode(&encode_samsung36(0x0400, 0xCF00E).unwrap()), None);
+    }
+}
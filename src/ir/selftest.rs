@@ -0,0 +1,171 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::nec::{self, NecVariant};
+use super::receiver::Capture;
+use super::{
+    detect_and_decode, is_repeat_frame, set_tolerance_percent, tolerance_percent, IrCommand, DEFAULT_TOLERANCE_PERCENT,
+};
+
+/// 自检发送的NEC地址和命令
+pub const TEST_ADDRESS: u8 = 0x5A;
+pub const TEST_COMMAND: u8 = 0xA5;
+
+/// 自检期间的解码容差，发射管与接收头只靠光路耦合时时序偏差较大
+const SELFTEST_TOLERANCE_PERCENT: u32 = 35;
+
+/// 发射完成后继续等待捕获的时长，需要覆盖接收端的空闲阈值和主循环周期
+const RESULT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// 最多保留的捕获数量，多余的捕获多半是环境干扰
+const MAX_CAPTURES: usize = 4;
+
+/// 收到的信号与发送的帧之间的时序误差（百分比，取最大值）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimingError {
+    pub mark_percent: u32,
+    pub space_percent: u32,
+}
+
+impl TimingError {
+    fn measure(expected: &[u32], actual: &[u32]) -> Self {
+        let mut error = Self {
+            mark_percent: 0,
+            space_percent: 0,
+        };
+        for (index, (&expected, &actual)) in expected.iter().zip(actual).enumerate() {
+            let percent = expected.abs_diff(actual) * 100 / expected.max(1);
+            let worst = if index % 2 == 0 {
+                &mut error.mark_percent
+            } else {
+                &mut error.space_percent
+            };
+            *worst = (*worst).max(percent);
+        }
+        error
+    }
+
+    /// 默认容差下能否正常解码
+    fn within_default_tolerance(&self) -> bool {
+        self.mark_percent <= DEFAULT_TOLERANCE_PERCENT && self.space_percent <= DEFAULT_TOLERANCE_PERCENT
+    }
+}
+
+impl fmt::Display for TimingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "mark_error={}% space_error={}%", self.mark_percent, self.space_percent)
+    }
+}
+
+/// 自检结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelfTestResult {
+    Passed(TimingError),
+    /// 没有收到任何信号，通常是发射管接错或没有对准接收头
+    NothingReceived,
+    /// 收到了信号但不是发送的帧
+    WrongCode(IrCommand),
+    /// 放宽容差后才能解码，默认容差下会解码失败
+    TimingOff(TimingError),
+}
+
+impl fmt::Display for SelfTestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Passed(error) => write!(f, "SELFTEST: PASS {}", error),
+            Self::NothingReceived => write!(f, "SELFTEST: FAIL nothing received"),
+            Self::WrongCode(command) => write!(f, "SELFTEST: FAIL wrong code {}", command),
+            Self::TimingOff(error) => write!(f, "SELFTEST: FAIL timing off {}", error),
+        }
+    }
+}
+
+/// 自检发送的帧
+pub fn test_frame() -> Vec<u32> {
+    nec::encode_nec(TEST_ADDRESS, TEST_COMMAND)
+}
+
+/// 一次进行中的回环自检
+///
+/// 创建时放宽解码容差，结束或丢弃时恢复原来的容差。
+pub struct SelfTest {
+    ticket: u32,
+    expected: Vec<u32>,
+    previous_tolerance: u32,
+    captures: Vec<Vec<u32>>,
+    /// 发射完成后开始计时
+    deadline: Option<Instant>,
+}
+
+impl SelfTest {
+    /// 开始自检，`ticket`是发射`expected`的队列编号
+    pub fn start(ticket: u32, expected: Vec<u32>) -> Self {
+        let previous_tolerance = tolerance_percent();
+        set_tolerance_percent(SELFTEST_TOLERANCE_PERCENT);
+        Self {
+            ticket,
+            expected,
+            previous_tolerance,
+            captures: Vec::new(),
+            deadline: None,
+        }
+    }
+
+    pub fn ticket(&self) -> u32 {
+        self.ticket
+    }
+
+    /// 自检期间收到的捕获，重复帧和多余的捕获被忽略
+    pub fn on_capture(&mut self, capture: &Capture) {
+        let durations = capture.durations();
+        if self.captures.len() < MAX_CAPTURES && !durations.is_empty() && !is_repeat_frame(durations) {
+            self.captures.push(durations.to_vec());
+        }
+    }
+
+    /// 测试帧已发射完成
+    pub fn on_transmitted(&mut self, now: Instant) {
+        self.deadline = Some(now + RESULT_TIMEOUT);
+    }
+
+    /// 是否已经可以给出结果
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.deadline.is_some_and(|deadline| now >= deadline)
+    }
+
+    /// 结束自检并判定结果，优先使用解码正确的捕获
+    pub fn finish(self) -> SelfTestResult {
+        let is_expected = |command: &IrCommand| {
+            matches!(command, IrCommand::Nec(nec) if !nec.repeat
+                && nec.variant == NecVariant::Standard
+                && nec.address == TEST_ADDRESS as u16
+                && nec.command == TEST_COMMAND)
+        };
+
+        let decoded: Vec<_> = self
+            .captures
+            .iter()
+            .map(|durations| (durations, detect_and_decode(durations)))
+            .collect();
+        match decoded.iter().find(|(_, command)| is_expected(command)) {
+            Some((durations, _)) => {
+                let error = TimingError::measure(&self.expected, durations);
+                if error.within_default_tolerance() {
+                    SelfTestResult::Passed(error)
+                } else {
+                    SelfTestResult::TimingOff(error)
+                }
+            }
+            None => match decoded.into_iter().next() {
+                Some((_, command)) => SelfTestResult::WrongCode(command),
+                None => SelfTestResult::NothingReceived,
+            },
+        }
+    }
+}
+
+impl Drop for SelfTest {
+    fn drop(&mut self) {
+        set_tolerance_percent(self.previous_tolerance);
+    }
+}
@@ -0,0 +1,292 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::quality::{self, DEFAULT_THRESHOLD};
+use super::receiver::{Capture, Frame};
+use super::{detect_and_decode, is_repeat_frame, Protocol};
+
+/// 等待信号的默认超时时间
+pub const DEFAULT_ARM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 收到第一帧后等待该时长，期间到达的重复帧不会覆盖录制结果
+const SETTLE_TIME: Duration = Duration::from_millis(150);
+
+/// 多帧录制时最后一帧结束后的等待时长，需要大于空调协议中最长一帧的发送时间
+const MULTI_FRAME_SETTLE_TIME: Duration = Duration::from_millis(1000);
+
+/// 一次多帧录制最多保留的帧数
+pub const MAX_FRAMES: usize = 4;
+
+/// 录制会话的状态
+#[derive(Debug)]
+pub enum SessionState {
+    Idle,
+    /// 已开始录制，等待信号
+    Armed { since: Instant },
+    /// 已收到信号，等待按键发送结束；`since`是最后一帧结束的时刻
+    Capturing { capture: Capture, since: Instant },
+    Complete,
+    Failed,
+}
+
+impl SessionState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Idle => "IDLE",
+            Self::Armed { .. } => "ARMED",
+            Self::Capturing { .. } => "CAPTURING",
+            Self::Complete => "COMPLETE",
+            Self::Failed => "FAILED",
+        }
+    }
+
+    /// 二进制状态中使用的编号
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Idle => 0,
+            Self::Armed { .. } => 1,
+            Self::Capturing { .. } => 2,
+            Self::Complete => 3,
+            Self::Failed => 4,
+        }
+    }
+}
+
+/// 录制会话上报的事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEvent {
+    Complete {
+        pulse_count: usize,
+        frames: usize,
+        protocol: Protocol,
+        /// 捕获质量（0–100）
+        quality: u8,
+    },
+    /// 捕获质量低于阈值，已重新开始等待信号
+    LowQuality { score: u8 },
+    Failed(&'static str),
+    TimedOut,
+}
+
+impl fmt::Display for SessionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Complete {
+                pulse_count,
+                frames,
+                protocol,
+                quality,
+            } => {
+                write!(f, "RECORD_COMPLETE: {} pulses {}", pulse_count, protocol.name())?;
+                if *frames > 1 {
+                    write!(f, " {} frames", frames)?;
+                }
+                write!(f, " quality={}", quality)
+            }
+            Self::LowQuality { score } => {
+                write!(f, "RECORD_LOW_QUALITY: score={}, press the button again", score)
+            }
+            Self::Failed(reason) => write!(f, "RECORD_FAILED: {}", reason),
+            Self::TimedOut => write!(f, "RECORD_TIMEOUT"),
+        }
+    }
+}
+
+/// 已有录制正在进行
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionBusy;
+
+/// 录制状态机：Idle → Armed → Capturing → Complete/Failed
+///
+/// 录制完成的捕获保存在待定槽中，直到被取走或下一次录制完成。
+/// 多帧模式下，第一帧之后陆续到达的帧连同测量到的帧间空闲一起并入同一个捕获。
+/// 捕获质量低于阈值时先不完成，重新等待一次按键，再保留两次中分数较高的一次。
+pub struct RecordingSession {
+    state: SessionState,
+    arm_timeout: Duration,
+    pending: Option<Capture>,
+    multi_frame: bool,
+    quality_threshold: u8,
+    /// 质量不合格的上一次捕获及其分数
+    retry: Option<(Capture, u8)>,
+}
+
+impl Default for RecordingSession {
+    fn default() -> Self {
+        Self::new(DEFAULT_ARM_TIMEOUT)
+    }
+}
+
+impl RecordingSession {
+    pub fn new(arm_timeout: Duration) -> Self {
+        Self {
+            state: SessionState::Idle,
+            arm_timeout,
+            pending: None,
+            multi_frame: false,
+            quality_threshold: DEFAULT_THRESHOLD,
+            retry: None,
+        }
+    }
+
+    /// 设置是否按帧间空闲把一次按键的多帧录制为一个捕获
+    pub fn set_multi_frame(&mut self, multi_frame: bool) {
+        self.multi_frame = multi_frame;
+    }
+
+    /// 设置质量阈值，为0时不检查质量
+    pub fn set_quality_threshold(&mut self, threshold: u8) {
+        self.quality_threshold = threshold;
+    }
+
+    pub fn quality_threshold(&self) -> u8 {
+        self.quality_threshold
+    }
+
+    pub fn state(&self) -> &SessionState {
+        &self.state
+    }
+
+    /// 是否正在等待信号
+    pub fn is_armed(&self) -> bool {
+        matches!(self.state, SessionState::Armed { .. })
+    }
+
+    /// 开始录制，已有录制进行中时拒绝
+    pub fn start(&mut self, now: Instant) -> Result<(), SessionBusy> {
+        match self.state {
+            SessionState::Armed { .. } | SessionState::Capturing { .. } => Err(SessionBusy),
+            _ => {
+                self.state = SessionState::Armed { since: now };
+                self.retry = None;
+                Ok(())
+            }
+        }
+    }
+
+    /// 取消正在进行的录制，返回是否有录制被取消
+    pub fn stop(&mut self) -> bool {
+        let active = matches!(self.state, SessionState::Armed { .. } | SessionState::Capturing { .. });
+        if active {
+            self.state = SessionState::Idle;
+            self.retry = None;
+        }
+        active
+    }
+
+    /// 输入一次捕获，`ended_at`是该帧结束的时刻
+    ///
+    /// 只在等待信号时生效；多帧模式下正在录制时到达的帧也会并入捕获。
+    pub fn on_capture(&mut self, capture: &Capture, truncated: bool, ended_at: Instant) -> Option<SessionEvent> {
+        let appending = self.multi_frame && matches!(self.state, SessionState::Capturing { .. });
+        if !self.is_armed() && !appending {
+            return None;
+        }
+
+        if truncated {
+            // 重新录制时溢出，保留上一次的捕获
+            if let Some((capture, score)) = self.retry.take() {
+                self.state = SessionState::Complete;
+                return Some(self.complete(capture, score));
+            }
+            self.state = SessionState::Failed;
+            return Some(SessionEvent::Failed("overflow"));
+        }
+        // 重复帧不完整，不能作为录制结果
+        if capture.durations().is_empty() || is_repeat_frame(capture.durations()) {
+            return None;
+        }
+
+        match &mut self.state {
+            SessionState::Capturing { capture: recorded, since } => {
+                if recorded.frames.len() >= MAX_FRAMES {
+                    log::warn!("多帧录制超过{}帧，忽略后续帧", MAX_FRAMES);
+                    return None;
+                }
+                // 两帧的结束时刻都晚于实际结束一个空闲阈值，相减后抵消
+                let frame_us: u32 = capture.durations().iter().sum();
+                let gap_us = (ended_at.saturating_duration_since(*since).as_micros() as u32).saturating_sub(frame_us);
+                if let Some(last) = recorded.frames.last_mut() {
+                    last.gap_us = gap_us;
+                }
+                recorded.frames.push(Frame {
+                    durations: capture.durations().to_vec(),
+                    gap_us: 0,
+                });
+                *since = ended_at;
+            }
+            _ => {
+                self.state = SessionState::Capturing {
+                    capture: capture.clone(),
+                    since: ended_at,
+                };
+            }
+        }
+        None
+    }
+
+    /// 推进超时，返回状态变化产生的事件
+    pub fn poll(&mut self, now: Instant) -> Option<SessionEvent> {
+        let (state, event) = match std::mem::replace(&mut self.state, SessionState::Idle) {
+            SessionState::Armed { since } if now.duration_since(since) >= self.arm_timeout => match self.retry.take() {
+                // 没有重新录制，使用质量不合格的那一次
+                Some((capture, score)) => (SessionState::Complete, Some(self.complete(capture, score))),
+                None => (SessionState::Idle, Some(SessionEvent::TimedOut)),
+            },
+            SessionState::Capturing { capture, since } if now.saturating_duration_since(since) >= self.settle_time() => {
+                let score = quality::score(&capture, false);
+                match self.retry.take() {
+                    None if score < self.quality_threshold => {
+                        log::warn!("录制质量{}低于阈值{}，等待重新录制", score, self.quality_threshold);
+                        self.retry = Some((capture, score));
+                        (SessionState::Armed { since: now }, Some(SessionEvent::LowQuality { score }))
+                    }
+                    Some((previous, previous_score)) if previous_score > score => {
+                        (SessionState::Complete, Some(self.complete(previous, previous_score)))
+                    }
+                    _ => (SessionState::Complete, Some(self.complete(capture, score))),
+                }
+            }
+            state => (state, None),
+        };
+
+        self.state = state;
+        event
+    }
+
+    /// 把捕获放入待定槽，返回录制完成事件
+    fn complete(&mut self, capture: Capture, quality: u8) -> SessionEvent {
+        let event = SessionEvent::Complete {
+            pulse_count: capture.pulse_count(),
+            frames: capture.frames.len(),
+            protocol: detect_and_decode(capture.durations()).protocol(),
+            quality,
+        };
+        self.pending = Some(capture);
+        event
+    }
+
+    fn settle_time(&self) -> Duration {
+        if self.multi_frame {
+            MULTI_FRAME_SETTLE_TIME
+        } else {
+            SETTLE_TIME
+        }
+    }
+
+    /// 为待定槽中的捕获记录载波频率，没有待定捕获时返回false
+    pub fn set_pending_carrier(&mut self, carrier_hz: u32) -> bool {
+        match self.pending.as_mut() {
+            Some(capture) => {
+                capture.carrier_hz = Some(carrier_hz);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 最近一次录制完成的捕获
+    pub fn pending(&self) -> Option<&Capture> {
+        self.pending.as_ref()
+    }
+}
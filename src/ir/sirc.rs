@@ -0,0 +1,320 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::{matches, CaptureEvent, Decoder, IrCommand, Protocol};
+
+// Sony SIRC协议时序（微秒）
+const HEADER_MARK: u32 = 2400;
+const SPACE: u32 = 600;
+const ONE_MARK: u32 = 1200;
+const ZERO_MARK: u32 = 600;
+
+/// 同一次捕获中，超过该时长的space视为帧间隔
+const FRAME_GAP_MIN: u32 = 5000;
+
+/// 帧周期，从一帧的起始脉冲到下一帧的起始脉冲
+const FRAME_PERIOD: u32 = 45_000;
+
+/// Sony设备会忽略单独的一帧，每次按键至少发送三帧
+const TRANSMIT_FRAMES: usize = 3;
+
+/// SIRC使用40kHz载波
+pub const CARRIER_HZ: u32 = 40_000;
+
+/// 帧周期为45ms，超过该窗口没有新帧则认为按键发送结束
+const MERGE_WINDOW: Duration = Duration::from_millis(100);
+
+/// SIRC帧的位数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SircBits {
+    Twelve,
+    Fifteen,
+    Twenty,
+}
+
+impl SircBits {
+    pub fn from_count(bits: usize) -> Option<Self> {
+        match bits {
+            12 => Some(Self::Twelve),
+            15 => Some(Self::Fifteen),
+            20 => Some(Self::Twenty),
+            _ => None,
+        }
+    }
+
+    /// 帧内的数据位数
+    pub fn count(&self) -> usize {
+        match self {
+            Self::Twelve => 12,
+            Self::Fifteen => 15,
+            Self::Twenty => 20,
+        }
+    }
+
+    /// 设备地址的位数，20位帧的扩展字节不计入
+    fn device_bits(&self) -> u32 {
+        match self {
+            Self::Fifteen => 8,
+            Self::Twelve | Self::Twenty => 5,
+        }
+    }
+}
+
+/// 解码后的SIRC命令
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SircCommand {
+    pub command: u8,
+    pub device: u8,
+    /// 20位帧的扩展字节
+    pub extended: Option<u8>,
+    pub bits: SircBits,
+    /// 合并的重复帧数量
+    pub frame_count: u8,
+}
+
+impl SircCommand {
+    /// 除帧数外内容完全相同
+    pub fn same_code(&self, other: &SircCommand) -> bool {
+        self.command == other.command
+            && self.device == other.device
+            && self.extended == other.extended
+            && self.bits == other.bits
+    }
+}
+
+impl fmt::Display for SircCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SIRC{} dev={:#04x}", self.bits.count(), self.device)?;
+        if let Some(extended) = self.extended {
+            write!(f, " ext={:#04x}", extended)?;
+        }
+        write!(f, " cmd={:#04x} x{}", self.command, self.frame_count)
+    }
+}
+
+/// 从微秒时长序列解码SIRC，同一捕获中的相同帧会被合并计数
+pub fn decode(durations: &[u32]) -> Option<SircCommand> {
+    let mut frames = durations
+        .split(|&duration| duration >= FRAME_GAP_MIN)
+        .filter(|frame| !frame.is_empty());
+
+    let mut command = decode_frame(frames.next()?)?;
+    for frame in frames {
+        match decode_frame(frame) {
+            Some(next) if next.same_code(&command) => {
+                command.frame_count = command.frame_count.saturating_add(1)
+            }
+            _ => break,
+        }
+    }
+
+    Some(command)
+}
+
+/// 编码SIRC命令，返回连续三帧、帧周期45ms的时长序列
+///
+/// 命令为7位，设备地址在15位帧中为8位，其余为5位，超出范围时返回None；20位帧的扩展字节为0。
+pub fn encode_sirc(device: u8, command: u8, bits: SircBits) -> Option<Vec<u32>> {
+    if command > 0x7F || u32::from(device) >> bits.device_bits() != 0 {
+        return None;
+    }
+
+    let raw = command as u32 | (device as u32) << 7;
+    let mut frame = vec![HEADER_MARK, SPACE];
+    for bit in 0..bits.count() {
+        if bit > 0 {
+            frame.push(SPACE);
+        }
+        frame.push(if raw >> bit & 1 == 1 { ONE_MARK } else { ZERO_MARK });
+    }
+
+    // 最后一位的space并入帧间隔
+    let gap = FRAME_PERIOD - frame.iter().sum::<u32>();
+    let mut durations = Vec::with_capacity((frame.len() + 1) * TRANSMIT_FRAMES);
+    for index in 0..TRANSMIT_FRAMES {
+        if index > 0 {
+            durations.push(gap);
+        }
+        durations.extend_from_slice(&frame);
+    }
+    Some(durations)
+}
+
+/// SIRC解码器
+pub struct SircDecoder;
+
+impl Decoder for SircDecoder {
+    fn protocol(&self) -> Protocol {
+        Protocol::Sirc
+    }
+
+    fn matches_header(&self, durations: &[u32]) -> bool {
+        durations.len() >= 2 && matches(durations[0], HEADER_MARK) && matches(durations[1], SPACE)
+    }
+
+    fn decode(&self, durations: &[u32]) -> Option<IrCommand> {
+        decode(durations).map(IrCommand::Sirc)
+    }
+}
+
+/// 解码单个SIRC帧：起始脉冲后每位为一个mark和一个space，最后一位的space是空闲
+fn decode_frame(frame: &[u32]) -> Option<SircCommand> {
+    if frame.len() < 3 {
+        return None;
+    }
+    if !matches(frame[0], HEADER_MARK) || !matches(frame[1], SPACE) {
+        return None;
+    }
+
+    let bits = SircBits::from_count((frame.len() - 1) / 2)?;
+    let mut raw: u32 = 0;
+    for bit in 0..bits.count() {
+        let mark = frame[2 + bit * 2];
+        if matches(mark, ONE_MARK) {
+            raw |= 1 << bit;
+        } else if !matches(mark, ZERO_MARK) {
+            return None;
+        }
+        if let Some(&space) = frame.get(3 + bit * 2) {
+            if !matches(space, SPACE) {
+                return None;
+            }
+        }
+    }
+
+    // 数据以LSB优先发送：7位命令，随后是设备地址和扩展位
+    let command = (raw & 0x7F) as u8;
+    let (device, extended) = match bits {
+        SircBits::Twelve => ((raw >> 7 & 0x1F) as u8, None),
+        SircBits::Fifteen => ((raw >> 7 & 0xFF) as u8, None),
+        SircBits::Twenty => ((raw >> 7 & 0x1F) as u8, Some((raw >> 12 & 0xFF) as u8)),
+    };
+
+    Some(SircCommand {
+        command,
+        device,
+        extended,
+        bits,
+        frame_count: 1,
+    })
+}
+
+/// 把SIRC遥控器连续发送的三帧合并为一次上报
+///
+/// 每帧之间的空闲超过接收阈值，因此会作为独立捕获到达，需要按时间窗口合并。
+#[derive(Default)]
+pub struct SircFrameMerger {
+    pending: Option<(CaptureEvent, Instant)>,
+}
+
+impl SircFrameMerger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 输入一个捕获事件，返回可以立即上报的事件
+    pub fn push(&mut self, event: CaptureEvent, now: Instant) -> Vec<CaptureEvent> {
+        let mut ready = Vec::new();
+
+        if let IrCommand::Sirc(command) = event.command {
+            if let Some((pending, last_seen)) = self.pending.as_mut() {
+                if let IrCommand::Sirc(pending_command) = &mut pending.command {
+                    if pending_command.same_code(&command)
+                        && now.duration_since(*last_seen) < MERGE_WINDOW
+                    {
+                        pending_command.frame_count = pending_command
+                            .frame_count
+                            .saturating_add(command.frame_count);
+                        *last_seen = now;
+                        return ready;
+                    }
+                }
+            }
+
+            ready.extend(self.pending.take().map(|(pending, _)| pending));
+            self.pending = Some((event, now));
+        } else {
+            ready.extend(self.pending.take().map(|(pending, _)| pending));
+            ready.push(event);
+        }
+
+        ready
+    }
+
+    /// 合并窗口结束后取出等待中的事件
+    pub fn poll(&mut self, now: Instant) -> Option<CaptureEvent> {
+        match &self.pending {
+            Some((_, last_seen)) if now.duration_since(*last_seen) >= MERGE_WINDOW => {
+                self.pending.take().map(|(pending, _)| pending)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 参考时序：2400µs起始mark，之后每位600µs space加1200µs（1）或600µs（0）mark，LSB优先
+    fn reference_frame(raw: u32, bits: usize) -> Vec<u32> {
+        let mut frame = vec![2400];
+        for bit in 0..bits {
+            frame.push(600);
+            frame.push(if raw >> bit & 1 == 1 { 1200 } else { 600 });
+        }
+        frame
+    }
+
+    /// 检查编码结果由三帧组成，每帧与参考时序一致，帧周期为45ms
+    fn assert_matches_reference(durations: &[u32], raw: u32, bits: usize) {
+        let frame = reference_frame(raw, bits);
+        let gap = 45_000 - frame.iter().sum::<u32>();
+        let frames: Vec<&[u32]> = durations.split(|&duration| duration == gap).collect();
+        assert_eq!(frames.len(), 3);
+        for encoded in frames {
+            assert_eq!(encoded, frame.as_slice());
+        }
+    }
+
+    #[test]
+    fn encodes_12_bit_reference() {
+        // 电视电源键：设备1，命令0x15
+        let durations = encode_sirc(1, 0x15, SircBits::Twelve).unwrap();
+        assert_matches_reference(&durations, 0x15 | 1 << 7, 12);
+    }
+
+    #[test]
+    fn encodes_15_bit_reference() {
+        let durations = encode_sirc(0xA4, 0x2A, SircBits::Fifteen).unwrap();
+        assert_matches_reference(&durations, 0x2A | 0xA4 << 7, 15);
+    }
+
+    #[test]
+    fn encodes_20_bit_reference() {
+        let durations = encode_sirc(0x1A, 0x7F, SircBits::Twenty).unwrap();
+        assert_matches_reference(&durations, 0x7F | 0x1A << 7, 20);
+    }
+
+    #[test]
+    fn decodes_encoded_frames() {
+        let durations = encode_sirc(0x1A, 0x15, SircBits::Fifteen).unwrap();
+        assert_eq!(
+            decode(&durations),
+            Some(SircCommand {
+                command: 0x15,
+                device: 0x1A,
+                extended: None,
+                bits: SircBits::Fifteen,
+                frame_count: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_fields() {
+        assert_eq!(encode_sirc(0, 0x80, SircBits::Twelve), None);
+        assert_eq!(encode_sirc(0x20, 0, SircBits::Twelve), None);
+        assert!(encode_sirc(0xFF, 0, SircBits::Fifteen).is_some());
+    }
+}
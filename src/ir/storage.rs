@@ -0,0 +1,744 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sys::{self, esp, EspError};
+
+use super::format::{stored_protocol, FormatError};
+use super::metadata::{SlotMeta, Timestamp, MAX_LABEL_LEN, MAX_META_LEN};
+use super::normalize::{normalize, Normalized, BUCKET_TOLERANCE_PERCENT};
+use super::quality;
+use super::receiver::{Capture, Frame};
+use super::timing::ProtocolTiming;
+use super::Protocol;
+use crate::namespace::Namespace;
+
+/// 没有`<遥控器>/`前缀的录制所在的遥控器
+pub const DEFAULT_REMOTE: &str = "default";
+
+/// default遥控器的录制所在的NVS命名空间，与旧版本固件相同，按键名直接作为键名
+const NAMESPACE: &str = "ir_captures";
+
+/// default遥控器的录制附加信息所在的命名空间，键名与录制相同
+const META_NAMESPACE: &str = "ir_slot_meta";
+
+/// 其他遥控器的录制和附加信息所在命名空间的前缀，后面跟遥控器名称
+pub const REMOTE_PREFIX: &str = "r:";
+pub const REMOTE_META_PREFIX: &str = "m:";
+
+/// 已创建的遥控器，遥控器名称作为键名
+const REGISTRY_NAMESPACE: &str = "ir_remotes";
+
+/// NVS键名最长15个字符
+pub const MAX_NAME_LEN: usize = 15;
+
+/// 命名空间名称同样最长15个字符，去掉前缀后遥控器名称最长13个字符
+pub const MAX_REMOTE_LEN: usize = 13;
+
+/// 同一遥控器中与新录制的相似度达到该值（百分比）时视为重复
+pub const DUPLICATE_SIMILARITY_PERCENT: u32 = 95;
+
+/// 比较桶中心值时的容差，覆盖两次按键之间约20%的抖动
+const DUPLICATE_TOLERANCE_PERCENT: u32 = 25;
+
+/// 累计这么多次重放后把使用次数写入NVS
+const FLUSH_EVERY_USES: u32 = 16;
+
+/// 使用次数最多在内存中保留这么久
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// NVS每个条目占32字节
+pub const ENTRY_SIZE: usize = 32;
+
+/// 录制操作失败的原因
+#[derive(Debug)]
+pub enum StorageError {
+    EmptyName,
+    /// 按键名超过NVS键名长度，附带实际长度
+    NameTooLong(usize),
+    /// 遥控器名称超过命名空间名称长度，附带实际长度
+    RemoteNameTooLong(usize),
+    /// 遥控器名称或按键名中包含多余的`/`
+    InvalidPath,
+    /// 标签超过32字节，附带实际长度
+    LabelTooLong(usize),
+    /// 录制不存在
+    UnknownSlot,
+    /// 遥控器不存在
+    UnknownRemote,
+    /// 重命名的目标遥控器已存在
+    RemoteExists,
+    /// NVS空间不足，附带录制需要的字节数，以及在放弃前已经淘汰的录制
+    StorageFull { needed: usize, evicted: Vec<String> },
+    /// 擦除遥控器失败，附带已经删除和仍然存在的录制
+    PartialDelete { deleted: Vec<String>, remaining: Vec<String> },
+    /// 同一遥控器中已有内容相同的录制，附带它的名称
+    DuplicateOf(String),
+    /// 捕获无法序列化，例如帧数过多
+    Format(FormatError),
+    Nvs(EspError),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyName => write!(f, "录制名称不能为空"),
+            Self::NameTooLong(len) => write!(f, "录制名称有{}个字符，最多{}个", len, MAX_NAME_LEN),
+            Self::RemoteNameTooLong(len) => write!(f, "遥控器名称有{}个字符，最多{}个", len, MAX_REMOTE_LEN),
+            Self::InvalidPath => write!(f, "路径只能是<遥控器>/<按键>或<按键>"),
+            Self::LabelTooLong(len) => write!(f, "标签有{}字节，最多{}字节", len, MAX_LABEL_LEN),
+            Self::UnknownSlot => write!(f, "录制不存在"),
+            Self::UnknownRemote => write!(f, "遥控器不存在"),
+            Self::RemoteExists => write!(f, "遥控器已存在"),
+            Self::StorageFull { needed, .. } => write!(f, "NVS空间不足，需要{}字节", needed),
+            Self::PartialDelete { deleted, remaining } => write!(
+                f,
+                "遥控器只删除了{}个录制，剩余{}个: {}",
+                deleted.len(),
+                remaining.len(),
+                remaining.join(",")
+            ),
+            Self::DuplicateOf(existing) => write!(f, "与录制{}重复", existing),
+            Self::Format(e) => write!(f, "录制无法保存: {}", e),
+            Self::Nvs(e) => write!(f, "NVS错误: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<EspError> for StorageError {
+    fn from(e: EspError) -> Self {
+        Self::Nvs(e)
+    }
+}
+
+impl From<FormatError> for StorageError {
+    fn from(e: FormatError) -> Self {
+        Self::Format(e)
+    }
+}
+
+/// 把路径拆分为遥控器和按键，例如`tv/power`，没有前缀时属于default遥控器
+pub fn split_path(path: &str) -> (&str, &str) {
+    path.split_once('/').unwrap_or((DEFAULT_REMOTE, path))
+}
+
+/// 录制的规范名称，default遥控器的录制不带前缀，`default/power`和`power`都是`power`
+pub fn canonical_path(path: &str) -> String {
+    let (remote, key) = split_path(path);
+    join_path(remote, key)
+}
+
+fn join_path(remote: &str, key: &str) -> String {
+    match remote {
+        DEFAULT_REMOTE => key.to_string(),
+        remote => format!("{}/{}", remote, key),
+    }
+}
+
+/// 检查遥控器名称能否作为命名空间名称
+pub fn check_remote(remote: &str) -> Result<(), StorageError> {
+    match remote.len() {
+        0 => Err(StorageError::EmptyName),
+        len if len > MAX_REMOTE_LEN => Err(StorageError::RemoteNameTooLong(len)),
+        _ if remote.contains(['/', '\0']) => Err(StorageError::InvalidPath),
+        _ => Ok(()),
+    }
+}
+
+/// 检查路径，遥控器名称要能作为命名空间名称，按键名要能作为NVS键名
+pub fn check_name(path: &str) -> Result<(), StorageError> {
+    let (remote, key) = split_path(path);
+    check_remote(remote)?;
+    match key.len() {
+        0 => Err(StorageError::EmptyName),
+        len if len > MAX_NAME_LEN => Err(StorageError::NameTooLong(len)),
+        _ if key.contains('/') => Err(StorageError::InvalidPath),
+        _ => Ok(()),
+    }
+}
+
+/// `list`返回的录制概要
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotInfo {
+    /// 规范名称，default以外的遥控器带有`<遥控器>/`前缀
+    pub name: String,
+    /// 保存时识别出的协议
+    pub protocol: Protocol,
+    pub pulse_count: usize,
+    /// 序列化后占用的字节数
+    pub size: usize,
+    /// 旧版本固件保存的录制没有附加信息
+    pub created: Option<Timestamp>,
+    /// 成功重放的次数，包括尚未写入NVS的部分
+    pub uses: u32,
+    pub last_used: Option<Timestamp>,
+    pub protected: bool,
+    pub label: String,
+    /// 保存时的捕获质量（0–100）
+    pub quality: Option<u8>,
+}
+
+/// NVS分区的使用情况
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageStats {
+    /// 整个分区已使用和空闲的条目数
+    pub used_entries: usize,
+    pub free_entries: usize,
+    pub total_entries: usize,
+    pub slot_count: usize,
+    /// 所有录制序列化后的字节数
+    pub slot_bytes: usize,
+}
+
+impl StorageStats {
+    /// 空闲条目能容纳的字节数，不计每个键的条目头
+    pub fn free_bytes(&self) -> usize {
+        self.free_entries * ENTRY_SIZE
+    }
+}
+
+/// 一个遥控器的录制和附加信息，各占一个命名空间
+struct Remote {
+    nvs: Namespace,
+    meta: Namespace,
+}
+
+impl Remote {
+    /// 名称需要先经过`check_remote`检查；没有分区时录制只保存在内存中
+    fn open(partition: Option<&EspDefaultNvsPartition>, name: &str) -> Result<Self, EspError> {
+        let (namespace, meta_namespace) = match name {
+            DEFAULT_REMOTE => (NAMESPACE.to_string(), META_NAMESPACE.to_string()),
+            name => (
+                format!("{}{}", REMOTE_PREFIX, name),
+                format!("{}{}", REMOTE_META_PREFIX, name),
+            ),
+        };
+        Ok(Self {
+            nvs: Namespace::open_or_memory(partition, &namespace)?,
+            meta: Namespace::open_or_memory(partition, &meta_namespace)?,
+        })
+    }
+
+    /// 所有录制的按键名
+    fn keys(&self) -> Vec<String> {
+        self.nvs.keys(sys::nvs_type_t_NVS_TYPE_BLOB)
+    }
+
+    /// 读取序列化后的内容
+    fn read(&self, key: &str) -> Result<Option<Vec<u8>>, EspError> {
+        match self.nvs.blob_len(key)? {
+            Some(len) => {
+                let mut buf = vec![0u8; len];
+                let len = self.nvs.get_blob(key, &mut buf)?.map(|bytes| bytes.len());
+                Ok(len.map(|len| {
+                    buf.truncate(len);
+                    buf
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// 读取附加信息，不存在或无法解析时返回None
+    fn load_meta(&self, key: &str) -> Option<SlotMeta> {
+        let mut buf = [0u8; MAX_META_LEN];
+        match self.meta.get_blob(key, &mut buf) {
+            Ok(bytes) => bytes.and_then(SlotMeta::from_bytes),
+            Err(e) => {
+                log::warn!("读取录制{}的附加信息失败: {:?}", key, e);
+                None
+            }
+        }
+    }
+}
+
+/// 按遥控器分组保存在NVS中的录制，重启后仍然有效
+///
+/// 每个遥控器的录制占一个命名空间，删除遥控器时整个命名空间一次擦除。
+/// 重放次数先在内存中累计，达到一定次数或时间后再批量写入，避免每次重放都写NVS。
+pub struct CaptureStorage {
+    /// 为None时录制只保存在内存中
+    partition: Option<EspDefaultNvsPartition>,
+    /// 包括default在内的所有遥控器
+    remotes: HashMap<String, Remote>,
+    registry: Namespace,
+    /// 空间不足时是否淘汰最久没有使用的录制
+    evict: bool,
+    /// 尚未写入NVS的重放次数和最近一次重放的时间，以规范名称为键
+    pending_uses: HashMap<String, (u32, Timestamp)>,
+    /// 最早一次未写入的重放时刻
+    pending_since: Option<Instant>,
+}
+
+impl CaptureStorage {
+    /// NVS不可用时使用，录制只保留到重启
+    pub fn in_memory() -> Self {
+        let default = Remote {
+            nvs: Namespace::in_memory(NAMESPACE),
+            meta: Namespace::in_memory(META_NAMESPACE),
+        };
+        Self {
+            partition: None,
+            remotes: HashMap::from([(DEFAULT_REMOTE.to_string(), default)]),
+            registry: Namespace::in_memory(REGISTRY_NAMESPACE),
+            evict: false,
+            pending_uses: HashMap::new(),
+            pending_since: None,
+        }
+    }
+
+    pub fn new(partition: &EspDefaultNvsPartition) -> Result<Self, EspError> {
+        let registry = Namespace::open(partition, REGISTRY_NAMESPACE)?;
+        let mut remotes = HashMap::new();
+        remotes.insert(DEFAULT_REMOTE.to_string(), Remote::open(Some(partition), DEFAULT_REMOTE)?);
+        for name in registry.keys(sys::nvs_type_t_NVS_TYPE_U8) {
+            if check_remote(&name).is_err() || name == DEFAULT_REMOTE {
+                log::warn!("跳过无效的遥控器: {}", name);
+                continue;
+            }
+            match Remote::open(Some(partition), &name) {
+                Ok(remote) => {
+                    remotes.insert(name, remote);
+                }
+                Err(e) => log::warn!("打开遥控器{}失败: {:?}", name, e),
+            }
+        }
+        Ok(Self {
+            registry,
+            partition: Some(partition.clone()),
+            remotes,
+            evict: false,
+            pending_uses: HashMap::new(),
+            pending_since: None,
+        })
+    }
+
+    /// 设置空间不足时是否淘汰录制
+    pub fn set_eviction(&mut self, evict: bool) {
+        self.evict = evict;
+    }
+
+    /// 按名称排序的所有遥控器，包括default
+    pub fn remotes(&self) -> Vec<String> {
+        let mut names = self.remotes.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// 创建空的遥控器，已经存在时返回false
+    pub fn create_remote(&mut self, name: &str) -> Result<bool, StorageError> {
+        check_remote(name)?;
+        if self.remotes.contains_key(name) {
+            return Ok(false);
+        }
+        let remote = Remote::open(self.partition.as_ref(), name)?;
+        self.registry.set_u8(name, 1)?;
+        self.remotes.insert(name.to_string(), remote);
+        Ok(true)
+    }
+
+    /// 删除遥控器和它的所有录制，返回被删除录制的规范名称
+    ///
+    /// 录制所在的命名空间一次擦除，失败时返回实际已经删除和仍然存在的录制。
+    /// default遥控器只清空录制，遥控器本身保留。
+    pub fn delete_remote(&mut self, name: &str) -> Result<Vec<String>, StorageError> {
+        let remote = self.remotes.get_mut(name).ok_or(StorageError::UnknownRemote)?;
+        let keys = remote.keys();
+        let erased = remote.nvs.erase_all();
+        let remaining = match erased {
+            Ok(()) => Vec::new(),
+            Err(e) => {
+                log::error!("擦除遥控器{}失败: {:?}", name, e);
+                remote.keys()
+            }
+        };
+        let deleted = keys
+            .iter()
+            .filter(|key| !remaining.contains(key))
+            .map(|key| join_path(name, key))
+            .collect::<Vec<_>>();
+        for path in &deleted {
+            self.pending_uses.remove(path);
+        }
+        if erased.is_err() {
+            return Err(StorageError::PartialDelete {
+                deleted,
+                remaining: remaining.iter().map(|key| join_path(name, key)).collect(),
+            });
+        }
+
+        // 录制已经删除，附加信息擦除失败只会留下无用的条目
+        if let Err(e) = remote.meta.erase_all() {
+            log::warn!("擦除遥控器{}的附加信息失败: {:?}", name, e);
+        }
+        if name != DEFAULT_REMOTE {
+            self.registry.remove(name)?;
+            self.remotes.remove(name);
+        }
+        Ok(deleted)
+    }
+
+    /// 重命名遥控器，返回每个录制原来和新的规范名称
+    ///
+    /// 录制先复制到新的遥控器，复制失败时删除新的遥控器，原来的遥控器保持不变。
+    pub fn rename_remote(&mut self, old: &str, new: &str) -> Result<Vec<(String, String)>, StorageError> {
+        check_remote(new)?;
+        if old == DEFAULT_REMOTE || !self.remotes.contains_key(old) {
+            return Err(StorageError::UnknownRemote);
+        }
+        if self.remotes.contains_key(new) {
+            return Err(StorageError::RemoteExists);
+        }
+        // 未写入的重放次数随附加信息一起复制
+        self.flush_uses();
+        self.create_remote(new)?;
+        if let Err(e) = self.copy_remote(old, new) {
+            if let Err(e) = self.delete_remote(new) {
+                log::error!("清理遥控器{}失败: {}", new, e);
+            }
+            return Err(e);
+        }
+        let keys = self.remotes[old].keys();
+        self.delete_remote(old)?;
+        Ok(keys
+            .iter()
+            .map(|key| (join_path(old, key), join_path(new, key)))
+            .collect())
+    }
+
+    fn copy_remote(&mut self, from: &str, to: &str) -> Result<(), StorageError> {
+        let from = &self.remotes[from];
+        let entries = from
+            .keys()
+            .into_iter()
+            .map(|key| {
+                let bytes = from.read(&key)?.ok_or(StorageError::UnknownSlot)?;
+                let meta = from.load_meta(&key);
+                Ok((key, bytes, meta))
+            })
+            .collect::<Result<Vec<_>, StorageError>>()?;
+        let to = self.remotes.get_mut(to).ok_or(StorageError::UnknownRemote)?;
+        for (key, bytes, meta) in entries {
+            to.nvs.set_blob(&key, &bytes)?;
+            if let Some(meta) = meta {
+                to.meta.set_blob(&key, &meta.to_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 保存逐帧归一化后的捕获，帧间空闲保持原样，同名的录制会被覆盖；遥控器不存在时自动创建
+    ///
+    /// 空间不足且开启了淘汰时，依次删除最久没有使用的录制直到保存成功，返回被淘汰的录制。
+    /// 有标签或受保护的录制不会被淘汰。
+    pub fn save(&mut self, path: &str, capture: &Capture) -> Result<Vec<String>, StorageError> {
+        check_name(path)?;
+        let (remote, key) = split_path(path);
+        self.create_remote(remote)?;
+        // 归一化会抹掉时长的离散程度，先按原始捕获打分；保存的捕获都没有溢出
+        let quality = quality::score(capture, false);
+        let frames = capture
+            .frames
+            .iter()
+            .map(|frame| Frame {
+                durations: normalize(&frame.durations, BUCKET_TOLERANCE_PERCENT),
+                gap_us: frame.gap_us,
+            })
+            .collect();
+        let capture = Capture {
+            frames,
+            carrier_hz: capture.carrier_hz,
+            timing: capture.timing,
+        };
+        let bytes = capture.to_bytes()?;
+        let path = join_path(remote, key);
+        let mut evicted = Vec::new();
+        while let Err(e) = self.remote_mut(remote)?.nvs.set_blob(key, &bytes) {
+            if e.code() != sys::ESP_ERR_NVS_NOT_ENOUGH_SPACE {
+                return Err(e.into());
+            }
+            let Some(victim) = self.evict.then(|| self.eviction_candidate(&path)).flatten() else {
+                return Err(StorageError::StorageFull {
+                    needed: bytes.len(),
+                    evicted,
+                });
+            };
+            self.delete(&victim)?;
+            log::warn!("NVS空间不足，淘汰录制: {}", victim);
+            evicted.push(victim);
+        }
+
+        // 重新录制时保留标签和保护标记，创建时间和使用次数从头计算
+        self.pending_uses.remove(&path);
+        let remote = self.remote_mut(remote)?;
+        let mut meta = SlotMeta::new(String::new());
+        meta.quality = Some(quality);
+        if let Some(old) = remote.load_meta(key) {
+            meta.label = old.label;
+            meta.protected = old.protected;
+        }
+        remote.meta.set_blob(key, &meta.to_bytes())?;
+        Ok(evicted)
+    }
+
+    /// 所有遥控器中最久没有使用的可淘汰录制，相同时先淘汰使用次数少的；旧版本固件保存的录制最先淘汰
+    fn eviction_candidate(&self, except: &str) -> Option<String> {
+        self.remotes
+            .iter()
+            .flat_map(|(name, remote)| remote.keys().into_iter().map(move |key| (name, remote, key)))
+            .filter_map(|(name, remote, key)| {
+                let path = join_path(name, &key);
+                if path == except {
+                    return None;
+                }
+                let meta = remote.load_meta(&key);
+                if meta.as_ref().is_some_and(|meta| meta.protected || !meta.label.is_empty()) {
+                    return None;
+                }
+                let pending = self.pending_uses.get(&path);
+                let recency = match (pending, &meta) {
+                    (Some(&(_, last_used)), _) => last_used.age_key(),
+                    (None, Some(meta)) => meta.recency(),
+                    (None, None) => (0, 0),
+                };
+                let uses = meta.map_or(0, |meta| meta.uses) + pending.map_or(0, |&(uses, _)| uses);
+                Some(((recency, uses), path))
+            })
+            .min()
+            .map(|(_, path)| path)
+    }
+
+    /// 在目标遥控器中查找与新录制相同的其他录制，返回相似度最高的一个
+    ///
+    /// 比较归一化后的桶序列而不是原始时长，同一按键按两次时的抖动不影响结果；同名的录制会被覆盖，不算重复。
+    pub fn find_duplicate(&self, path: &str, capture: &Capture) -> Option<String> {
+        let (remote, key) = split_path(path);
+        let candidate = Normalized::new(capture.durations(), BUCKET_TOLERANCE_PERCENT);
+        self.remotes
+            .get(remote)?
+            .keys()
+            .into_iter()
+            .filter(|existing| existing.as_str() != key)
+            .filter_map(|existing| {
+                let path = join_path(remote, &existing);
+                let stored = self.load(&path)?;
+                let similarity =
+                    Normalized::new(stored.durations(), BUCKET_TOLERANCE_PERCENT).similarity(&candidate, DUPLICATE_TOLERANCE_PERCENT);
+                (similarity >= DUPLICATE_SIMILARITY_PERCENT).then_some((similarity, path))
+            })
+            .max_by_key(|&(similarity, _)| similarity)
+            .map(|(_, path)| path)
+    }
+
+    /// 读取录制，不存在、读取失败或无法解析时返回None
+    pub fn load(&self, path: &str) -> Option<Capture> {
+        let bytes = self.read(path)?;
+        Capture::from_bytes(&bytes)
+            .map_err(|e| log::warn!("录制{}无法解析，可能由旧版本固件写入: {}", path, e))
+            .ok()
+    }
+
+    /// 录制是否存在
+    pub fn contains(&self, path: &str) -> bool {
+        self.slot(path)
+            .is_some_and(|(remote, key)| remote.nvs.contains(key).unwrap_or(false))
+    }
+
+    /// 设置录制的发射时序，`None`表示恢复按协议查表；录制不存在时返回false
+    pub fn set_timing(&mut self, path: &str, timing: Option<ProtocolTiming>) -> Result<bool, StorageError> {
+        check_name(path)?;
+        let Some(mut capture) = self.load(path) else {
+            return Ok(false);
+        };
+        capture.timing = timing;
+        let (remote, key) = split_path(path);
+        self.remote_mut(remote)?.nvs.set_blob(key, &capture.to_bytes()?)?;
+        Ok(true)
+    }
+
+    /// 删除录制，录制不存在时返回false
+    pub fn delete(&mut self, path: &str) -> Result<bool, StorageError> {
+        check_name(path)?;
+        self.pending_uses.remove(&canonical_path(path));
+        let (remote, key) = split_path(path);
+        let Some(remote) = self.remotes.get_mut(remote) else {
+            return Ok(false);
+        };
+        remote.meta.remove(key)?;
+        Ok(remote.nvs.remove(key)?)
+    }
+
+    /// 设置录制的标签，最长32字节
+    pub fn set_label(&mut self, path: &str, label: &str) -> Result<(), StorageError> {
+        check_name(path)?;
+        if label.len() > MAX_LABEL_LEN {
+            return Err(StorageError::LabelTooLong(label.len()));
+        }
+        self.update_meta(path, |meta| meta.label = label.to_string())
+    }
+
+    /// 设置录制是否受保护，受保护的录制不会被淘汰
+    pub fn set_protected(&mut self, path: &str, protected: bool) -> Result<(), StorageError> {
+        self.update_meta(path, |meta| meta.protected = protected)
+    }
+
+    /// 修改已有录制的附加信息，旧版本固件保存的录制补上附加信息
+    fn update_meta(&mut self, path: &str, update: impl FnOnce(&mut SlotMeta)) -> Result<(), StorageError> {
+        check_name(path)?;
+        if !self.contains(path) {
+            return Err(StorageError::UnknownSlot);
+        }
+        let (remote, key) = split_path(path);
+        let remote = self.remote_mut(remote)?;
+        let mut meta = remote.load_meta(key).unwrap_or_else(|| SlotMeta::new(String::new()));
+        update(&mut meta);
+        remote.meta.set_blob(key, &meta.to_bytes())?;
+        Ok(())
+    }
+
+    /// 记录一次成功的重放，累计到一定次数时写入NVS
+    pub fn record_use(&mut self, path: &str) {
+        let now = Timestamp::now();
+        let (uses, last_used) = self.pending_uses.entry(canonical_path(path)).or_insert((0, now));
+        *uses += 1;
+        *last_used = now;
+        self.pending_since.get_or_insert_with(Instant::now);
+        if self.pending_uses.values().map(|&(uses, _)| uses).sum::<u32>() >= FLUSH_EVERY_USES {
+            self.flush_uses();
+        }
+    }
+
+    /// 未写入的重放次数保留超过一分钟时写入NVS，由主循环定期调用
+    pub fn flush_if_due(&mut self, now: Instant) {
+        if self
+            .pending_since
+            .is_some_and(|since| now.saturating_duration_since(since) >= FLUSH_INTERVAL)
+        {
+            self.flush_uses();
+        }
+    }
+
+    /// 把内存中累计的重放次数写入NVS
+    fn flush_uses(&mut self) {
+        for (path, (uses, last_used)) in std::mem::take(&mut self.pending_uses) {
+            let (remote, key) = split_path(&path);
+            let Some(remote) = self.remotes.get_mut(remote) else {
+                continue;
+            };
+            // 旧版本固件保存的录制在第一次重放时补上附加信息
+            let mut meta = remote.load_meta(key).unwrap_or_else(|| SlotMeta::new(String::new()));
+            meta.uses = meta.uses.saturating_add(uses);
+            meta.last_used = Some(last_used);
+            if let Err(e) = remote.meta.set_blob(key, &meta.to_bytes()) {
+                log::warn!("保存录制{}的使用次数失败: {:?}", path, e);
+            }
+        }
+        self.pending_since = None;
+    }
+
+    /// 按规范名称排序列出所有遥控器中能解析的录制，其他类型的键和无法解析的内容被跳过
+    pub fn list(&self) -> Vec<SlotInfo> {
+        let mut slots = self
+            .remotes
+            .keys()
+            .flat_map(|remote| self.list_remote(remote).unwrap_or_default())
+            .collect::<Vec<_>>();
+        slots.sort_by(|a, b| a.name.cmp(&b.name));
+        slots
+    }
+
+    /// 最近创建的`count`个录制的名称，最新的在前
+    pub fn newest(&self, count: usize) -> Vec<String> {
+        let mut slots = self.list();
+        slots.sort_by_key(|slot| std::cmp::Reverse(slot.created.map(|created| created.age_key())));
+        slots.into_iter().take(count).map(|slot| slot.name).collect()
+    }
+
+    /// 按按键名排序列出一个遥控器中的录制，遥控器不存在时返回None
+    pub fn list_remote(&self, name: &str) -> Option<Vec<SlotInfo>> {
+        let remote = self.remotes.get(name)?;
+        let mut keys = remote.keys();
+        keys.sort();
+        let slots = keys
+            .into_iter()
+            .filter_map(|key| {
+                let path = join_path(name, &key);
+                let bytes = self.read(&path)?;
+                let parsed = Capture::from_bytes(&bytes).and_then(|capture| Ok((capture, stored_protocol(&bytes)?)));
+                let (capture, protocol) = match parsed {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        log::warn!("跳过无法解析的录制{}: {}", path, e);
+                        return None;
+                    }
+                };
+                let meta = remote.load_meta(&key);
+                let pending = self.pending_uses.get(&path).copied();
+                Some(SlotInfo {
+                    protocol,
+                    pulse_count: capture.pulse_count(),
+                    size: bytes.len(),
+                    created: meta.as_ref().map(|meta| meta.created),
+                    uses: meta
+                        .as_ref()
+                        .map_or(0, |meta| meta.uses)
+                        .saturating_add(pending.map_or(0, |(uses, _)| uses)),
+                    last_used: pending
+                        .map(|(_, last_used)| last_used)
+                        .or(meta.as_ref().and_then(|meta| meta.last_used)),
+                    protected: meta.as_ref().is_some_and(|meta| meta.protected),
+                    quality: meta.as_ref().and_then(|meta| meta.quality),
+                    label: meta.map(|meta| meta.label).unwrap_or_default(),
+                    name: path,
+                })
+            })
+            .collect();
+        Some(slots)
+    }
+
+    /// NVS分区的条目使用情况和录制占用的字节数
+    pub fn stats(&self) -> Result<StorageStats, StorageError> {
+        let mut stats = sys::nvs_stats_t::default();
+        esp!(unsafe { sys::nvs_get_stats(sys::NVS_DEFAULT_PART_NAME.as_ptr() as *const _, &mut stats) })?;
+        let sizes = self
+            .remotes
+            .values()
+            .flat_map(|remote| {
+                remote
+                    .keys()
+                    .into_iter()
+                    .filter_map(|key| remote.nvs.blob_len(&key).ok().flatten())
+            })
+            .collect::<Vec<_>>();
+        Ok(StorageStats {
+            used_entries: stats.used_entries,
+            free_entries: stats.free_entries,
+            total_entries: stats.total_entries,
+            slot_count: sizes.len(),
+            slot_bytes: sizes.iter().sum(),
+        })
+    }
+
+    /// 读取序列化后的内容
+    pub fn read(&self, path: &str) -> Option<Vec<u8>> {
+        let (remote, key) = self.slot(path)?;
+        remote.read(key).unwrap_or_else(|e| {
+            log::warn!("读取录制{}失败: {:?}", path, e);
+            None
+        })
+    }
+
+    /// 路径所在的遥控器和按键名，路径无效或遥控器不存在时返回None
+    fn slot<'a>(&self, path: &'a str) -> Option<(&Remote, &'a str)> {
+        check_name(path).ok()?;
+        let (remote, key) = split_path(path);
+        Some((self.remotes.get(remote)?, key))
+    }
+
+    fn remote_mut(&mut self, name: &str) -> Result<&mut Remote, StorageError> {
+        self.remotes.get_mut(name).ok_or(StorageError::UnknownRemote)
+    }
+}
@@ -0,0 +1,128 @@
+use std::time::{Duration, Instant};
+
+use super::Protocol;
+
+/// 按住按键时协议的重复方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepeatStyle {
+    /// 重发完整帧
+    FullFrame,
+    /// 重发完整帧，按住期间toggle位保持不变
+    Toggle,
+    /// 第一帧之后只发送9ms/2.25ms的重复帧
+    RepeatCode,
+}
+
+/// 协议的发射时序
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolTiming {
+    /// 从一帧开始到下一帧开始的最短时间（微秒），0表示不限制
+    pub frame_period_us: u32,
+    /// 帧结束后至少保持的空闲（微秒）
+    pub min_gap_us: u32,
+    pub repeat: RepeatStyle,
+}
+
+/// 无法识别协议时只保证帧间空闲，接收端需要据此区分前后两帧
+pub const DEFAULT_TIMING: ProtocolTiming = ProtocolTiming {
+    frame_period_us: 0,
+    min_gap_us: 40_000,
+    repeat: RepeatStyle::FullFrame,
+};
+
+/// 各协议的帧周期、最小帧间空闲和重复方式
+pub fn protocol_timing(protocol: Protocol) -> ProtocolTiming {
+    let (frame_period_us, min_gap_us, repeat) = match protocol {
+        Protocol::Nec | Protocol::NecExt => (110_000, 40_000, RepeatStyle::RepeatCode),
+        Protocol::Samsung | Protocol::Samsung36 | Protocol::Lg => (108_000, 40_000, RepeatStyle::FullFrame),
+        Protocol::Rc5 | Protocol::Rc6 => (114_000, 40_000, RepeatStyle::Toggle),
+        Protocol::Kaseikyo => (75_000, 20_000, RepeatStyle::FullFrame),
+        Protocol::Jvc => (55_000, 10_000, RepeatStyle::FullFrame),
+        Protocol::Sirc => (45_000, 10_000, RepeatStyle::FullFrame),
+        Protocol::Denon => (65_000, 20_000, RepeatStyle::FullFrame),
+        Protocol::Unknown => return DEFAULT_TIMING,
+    };
+    ProtocolTiming {
+        frame_period_us,
+        min_gap_us,
+        repeat,
+    }
+}
+
+/// 记录上一次发射，计算下一帧最早可以开始的时刻
+#[derive(Debug, Default)]
+pub struct FrameSpacing {
+    next_start: Option<Instant>,
+}
+
+impl FrameSpacing {
+    /// 距离下一帧可以开始还需等待的时间
+    pub fn wait(&self, now: Instant) -> Duration {
+        self.next_start
+            .map_or(Duration::ZERO, |next_start| next_start.saturating_duration_since(now))
+    }
+
+    /// 记录一次在`end`时刻结束的发射，`last_frame_us`是其中最后一帧的长度
+    ///
+    /// 下一帧既要距最后一帧开始满一个帧周期，也要在结束后保持最小空闲。
+    pub fn sent(&mut self, end: Instant, last_frame_us: u32, timing: ProtocolTiming) {
+        let period_left = timing.frame_period_us.saturating_sub(last_frame_us);
+        let gap = Duration::from_micros(period_left.max(timing.min_gap_us) as u64);
+        self.next_start = Some(end + gap);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::nec::{encode_nec, encode_repeat};
+
+    /// 模拟依次发射若干帧，每帧在允许的最早时刻开始，返回各帧的开始时刻
+    fn simulate(frames: &[Vec<u32>], timing: ProtocolTiming) -> Vec<Instant> {
+        let mut spacing = FrameSpacing::default();
+        let mut now = Instant::now();
+        let mut starts = Vec::new();
+        for frame in frames {
+            now += spacing.wait(now);
+            starts.push(now);
+            let frame_us = frame.iter().sum::<u32>();
+            now += Duration::from_micros(frame_us as u64);
+            spacing.sent(now, frame_us, timing);
+        }
+        starts
+    }
+
+    #[test]
+    fn nec_sends_are_one_frame_period_apart() {
+        let frame = encode_nec(0x04, 0x08);
+        let starts = simulate(&[frame.clone(), frame.clone(), frame], protocol_timing(Protocol::Nec));
+        assert_eq!(starts.len(), 3);
+        for pair in starts.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_millis(110), "{:?}", pair[1] - pair[0]);
+        }
+    }
+
+    #[test]
+    fn short_repeat_frames_keep_frame_period() {
+        let frames = [encode_nec(0x04, 0x08), encode_repeat(), encode_repeat()];
+        let starts = simulate(&frames, protocol_timing(Protocol::Nec));
+        for pair in starts.windows(2) {
+            assert_eq!(pair[1] - pair[0], Duration::from_millis(110));
+        }
+    }
+
+    #[test]
+    fn long_frames_keep_minimum_gap() {
+        let timing = protocol_timing(Protocol::Nec);
+        let mut spacing = FrameSpacing::default();
+        let end = Instant::now();
+        // 帧长100ms，距帧周期只剩10ms，仍要保持40ms空闲
+        spacing.sent(end, 100_000, timing);
+        assert_eq!(spacing.wait(end), Duration::from_micros(timing.min_gap_us as u64));
+    }
+
+    #[test]
+    fn first_send_does_not_wait() {
+        assert_eq!(FrameSpacing::default().wait(Instant::now()), Duration::ZERO);
+    }
+}
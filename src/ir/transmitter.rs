@@ -0,0 +1,426 @@
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_hal::rmt::config::{CarrierConfig, DutyPercent, TransmitConfig};
+use esp_idf_hal::rmt::{PinState, Pulse, PulseTicks, TxRmtDriver, VariableLengthSignal};
+use esp_idf_hal::units::FromValueType;
+use esp_idf_svc::sys::{self, esp, EspError};
+
+use crate::rmt::TxGate;
+
+use super::assembler::DEFAULT_MAX_CAPTURE_PAIRS;
+use super::nec;
+use super::power::TxPower;
+use super::pronto::ProntoCode;
+use super::receiver::Capture;
+use super::storage::CaptureStorage;
+use super::timing::{protocol_timing, FrameSpacing, ProtocolTiming, RepeatStyle};
+use super::{detect_and_decode, TickRate, APB_CLK_HZ};
+
+/// 发射通道分频到1MHz，每个tick为1µs
+const CLOCK_DIVIDER: u8 = 80;
+
+/// 默认载波频率和占空比
+pub const DEFAULT_CARRIER_HZ: u32 = 38_000;
+pub const DEFAULT_DUTY_PERCENT: u8 = 33;
+
+/// 允许的载波频率范围，常见设备使用36、38、40或56kHz
+pub const MIN_CARRIER_HZ: u32 = 20_000;
+pub const MAX_CARRIER_HZ: u32 = 60_000;
+
+/// 单个RMT脉冲能表示的最大tick数，更长的时长拆成多个同电平脉冲
+const MAX_PULSE_TICKS: u32 = 32_767;
+
+/// 客户端直接发送的原始时长上限，与接收端的最大捕获长度相同
+pub const MAX_RAW_DURATIONS: usize = DEFAULT_MAX_CAPTURE_PAIRS * 2;
+
+/// 原始时长中单个mark或space的上限（微秒）
+pub const MAX_RAW_DURATION_US: u32 = 100_000;
+
+/// 按住重复发送的安全上限，连接中断时也不会一直发射
+const MAX_REPEAT_DURATION: Duration = Duration::from_secs(10);
+
+/// 重复发送期间检查停止请求的间隔
+const REPEAT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// 录制中超过该长度的space视为帧间隔，已支持协议帧内最长的space约为4.5ms
+const FRAME_GAP_US: u32 = 6000;
+
+/// 发射失败的原因
+#[derive(Debug)]
+pub enum TransmitError {
+    /// 没有可发送的时长
+    Empty,
+    /// 没有该名称的录制
+    UnknownSlot(String),
+    /// 载波频率超出允许范围
+    CarrierOutOfRange(u32),
+    /// 占空比必须在1~99%之间
+    InvalidDuty(u8),
+    /// 第`index`个时长为0或超过100ms
+    InvalidDuration { index: usize, duration: u32 },
+    /// 时长数量超过上限
+    TooManyDurations(usize),
+    /// 启动时发射通道初始化失败
+    Unavailable,
+    Rmt(EspError),
+}
+
+impl fmt::Display for TransmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "没有要发送的红外信号"),
+            Self::UnknownSlot(slot) => write!(f, "没有名为{}的录制", slot),
+            Self::CarrierOutOfRange(hz) => write!(
+                f,
+                "载波频率{}Hz超出{}~{}Hz的范围",
+                hz, MIN_CARRIER_HZ, MAX_CARRIER_HZ
+            ),
+            Self::InvalidDuty(duty) => write!(f, "无效的载波占空比: {}%", duty),
+            Self::InvalidDuration { index, duration } => {
+                write!(f, "第{}个时长{}µs无效，必须在1~{}µs之间", index, duration, MAX_RAW_DURATION_US)
+            }
+            Self::TooManyDurations(count) => {
+                write!(f, "时长数量{}超过上限{}", count, MAX_RAW_DURATIONS)
+            }
+            Self::Unavailable => write!(f, "红外发射器不可用"),
+            Self::Rmt(e) => write!(f, "RMT发射错误: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for TransmitError {}
+
+impl From<EspError> for TransmitError {
+    fn from(e: EspError) -> Self {
+        Self::Rmt(e)
+    }
+}
+
+/// 发射时使用的载波参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Carrier {
+    frequency_hz: u32,
+    duty_percent: u8,
+}
+
+impl Default for Carrier {
+    fn default() -> Self {
+        Self {
+            frequency_hz: DEFAULT_CARRIER_HZ,
+            duty_percent: DEFAULT_DUTY_PERCENT,
+        }
+    }
+}
+
+impl Carrier {
+    pub fn new(frequency_hz: u32, duty_percent: u8) -> Result<Self, TransmitError> {
+        if !(MIN_CARRIER_HZ..=MAX_CARRIER_HZ).contains(&frequency_hz) {
+            return Err(TransmitError::CarrierOutOfRange(frequency_hz));
+        }
+        if !(1..100).contains(&duty_percent) {
+            return Err(TransmitError::InvalidDuty(duty_percent));
+        }
+        Ok(Self {
+            frequency_hz,
+            duty_percent,
+        })
+    }
+
+    /// 使用默认占空比
+    pub fn with_frequency(frequency_hz: u32) -> Result<Self, TransmitError> {
+        Self::new(frequency_hz, DEFAULT_DUTY_PERCENT)
+    }
+}
+
+/// 红外发射器，在独立的RMT通道上输出带载波的信号
+///
+/// 通道和内存块由`RmtAllocator`分配，不会和WS2812驱动重叠。
+/// 载波频率和占空比在每次发送前重新配置。
+pub struct IrTransmitter {
+    /// 启动时发射通道初始化失败时为None，所有发射返回`Unavailable`
+    driver: Option<TxRmtDriver<'static>>,
+    tick: TickRate,
+    /// 上一次发射要求的帧间空闲
+    spacing: FrameSpacing,
+    /// 可选的发射功率控制，没有时发射管一直使能
+    power: Option<TxPower>,
+    /// 和其他发射通道互斥
+    gate: Option<TxGate>,
+}
+
+impl IrTransmitter {
+    /// 红外发射通道的配置，`mem_blocks`个内存块，长帧需要的续写更少
+    pub fn config(mem_blocks: u8) -> Result<TransmitConfig, EspError> {
+        let carrier = CarrierConfig::new()
+            .frequency(DEFAULT_CARRIER_HZ.Hz())
+            .carrier_level(PinState::High)
+            .duty_percent(DutyPercent::new(DEFAULT_DUTY_PERCENT)?);
+        Ok(TransmitConfig::new()
+            .clock_divider(CLOCK_DIVIDER)
+            .mem_block_num(mem_blocks)
+            .carrier(Some(carrier))
+            .idle(Some(PinState::Low)))
+    }
+
+    /// 在按`config`创建的驱动上发射
+    pub fn new(driver: TxRmtDriver<'static>) -> Self {
+        Self::with_driver(Some(driver))
+    }
+
+    /// 发射通道初始化失败时使用
+    pub fn unavailable() -> Self {
+        Self::with_driver(None)
+    }
+
+    fn with_driver(driver: Option<TxRmtDriver<'static>>) -> Self {
+        Self {
+            driver,
+            tick: TickRate::from_clock_divider(CLOCK_DIVIDER),
+            spacing: FrameSpacing::default(),
+            power: None,
+            gate: None,
+        }
+    }
+
+    /// 发射时等待其他发射通道发送完成
+    pub fn with_gate(mut self, gate: TxGate) -> Self {
+        self.gate = Some(gate);
+        self
+    }
+
+    /// 通过使能引脚控制发射功率，只在发射期间打开
+    #[cfg(feature = "tx-power")]
+    pub fn with_power(mut self, power: TxPower) -> Self {
+        self.power = Some(power);
+        self
+    }
+
+    /// 发送以微秒为单位、从mark开始交替的时长序列，发送完成后返回
+    ///
+    /// 帧间空闲按识别出的协议从时序表中查找。
+    pub fn send_raw(&mut self, durations: &[u32], carrier: Carrier) -> Result<(), TransmitError> {
+        self.transmit(durations, carrier, detect_timing(durations))
+    }
+
+    /// 重放保存的录制，使用录制时测量到的载波频率（没有测量时为38kHz）
+    ///
+    /// 多帧录制按录制时测量到的帧间空闲发送；单帧录制中的帧间隔按协议的帧周期重新计算。
+    pub fn replay(&mut self, store: &CaptureStorage, slot: &str) -> Result<(), TransmitError> {
+        let capture = store
+            .load(slot)
+            .ok_or_else(|| TransmitError::UnknownSlot(slot.to_string()))?;
+
+        let timing = capture_timing(&capture);
+        let durations = match capture.frames.len() {
+            1 => join_frames(capture.durations(), timing),
+            _ => capture.flatten(),
+        };
+        self.transmit(&durations, capture_carrier(&capture)?, timing)
+    }
+
+    /// 重复发送录制的第一帧（多帧录制为全部帧），直到`keep_going`返回false或达到10秒上限，返回发送的帧数
+    ///
+    /// `protocol_aware`时使用重复帧的协议（NEC）只发一次完整帧，之后按帧周期发送重复帧；
+    /// 其他情况按帧周期重发完整帧。
+    pub fn repeat_while(
+        &mut self,
+        capture: &Capture,
+        protocol_aware: bool,
+        keep_going: impl Fn() -> bool,
+    ) -> Result<usize, TransmitError> {
+        let carrier = capture_carrier(capture)?;
+        let frame = match capture.frames.len() {
+            1 => capture.durations()[..first_frame_len(capture.durations())].to_vec(),
+            _ => capture.flatten(),
+        };
+        let timing = capture_timing(capture);
+        let repeat_code = protocol_aware && timing.repeat == RepeatStyle::RepeatCode;
+        let repeat_frame = nec::encode_repeat();
+
+        let started = Instant::now();
+        let mut sent = 0;
+        while keep_going() {
+            if started.elapsed() >= MAX_REPEAT_DURATION {
+                log::warn!("重复发送已达{}秒上限，自动停止", MAX_REPEAT_DURATION.as_secs());
+                break;
+            }
+
+            let durations = if repeat_code && sent > 0 { &repeat_frame } else { &frame };
+            self.transmit(durations, carrier, timing)?;
+            sent += 1;
+
+            // 分段等待到下一帧，停止请求最多延迟一个检查间隔
+            while keep_going() {
+                let remaining = self.spacing.wait(Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                thread::sleep(remaining.min(REPEAT_POLL_INTERVAL));
+            }
+        }
+        Ok(sent)
+    }
+
+    /// 发送Pronto学习码：单次序列一次，随后重复序列`repeats`次
+    pub fn send_pronto(&mut self, code: &ProntoCode, repeats: usize) -> Result<(), TransmitError> {
+        let mut durations = code.once.clone();
+        for _ in 0..repeats {
+            durations.extend_from_slice(&code.repeat);
+        }
+        // 结尾的space只是帧间空闲，不需要发送
+        durations.pop();
+
+        self.send_raw(&durations, Carrier::with_frequency(code.carrier_hz)?)
+    }
+
+    /// 按`timing`发送，开始前等待上一次发射要求的帧间空闲
+    fn transmit(&mut self, durations: &[u32], carrier: Carrier, timing: ProtocolTiming) -> Result<(), TransmitError> {
+        if durations.iter().all(|&duration| duration == 0) {
+            return Err(TransmitError::Empty);
+        }
+
+        let pulses = self.to_pulses(durations)?;
+        let mut signal = VariableLengthSignal::with_capacity(pulses.len());
+        signal.push(&pulses)?;
+        let Some(driver) = self.driver.as_mut() else {
+            return Err(TransmitError::Unavailable);
+        };
+        set_carrier(driver, carrier)?;
+
+        thread::sleep(self.spacing.wait(Instant::now()));
+        let _guard = self.gate.as_ref().map(TxGate::lock);
+        if let Some(power) = self.power.as_mut() {
+            power.enable()?;
+        }
+        let result = driver.start_blocking(&signal);
+        if let Some(power) = self.power.as_mut() {
+            if let Err(e) = power.disable() {
+                log::error!("关闭发射管使能引脚失败: {:?}", e);
+            }
+        }
+        self.spacing.sent(Instant::now(), last_frame_us(durations), timing);
+        Ok(result?)
+    }
+
+    /// 把时长换算为RMT脉冲，mark输出高电平（叠加载波），space输出低电平
+    fn to_pulses(&self, durations: &[u32]) -> Result<Vec<Pulse>, EspError> {
+        let mut pulses = Vec::with_capacity(durations.len());
+        for (index, &duration) in durations.iter().enumerate() {
+            let level = if index % 2 == 0 { PinState::High } else { PinState::Low };
+            let mut ticks = self.tick.us_to_ticks(duration);
+            while ticks > 0 {
+                let chunk = ticks.min(MAX_PULSE_TICKS);
+                pulses.push(Pulse::new(level, PulseTicks::new(chunk as u16)?));
+                ticks -= chunk;
+            }
+        }
+        Ok(pulses)
+    }
+}
+
+/// 检查客户端发送的原始时长，返回实际要发射的部分
+///
+/// 序列从mark开始；数量为偶数时最后一个space只是结尾的空闲，不需要发射。
+/// 超过RMT单个脉冲上限的时长由`send_raw`自动拆分，超过通道内存的部分由驱动在发射过程中续写。
+pub fn validate_raw(mut durations: Vec<u32>) -> Result<Vec<u32>, TransmitError> {
+    if durations.is_empty() {
+        return Err(TransmitError::Empty);
+    }
+    if durations.len() > MAX_RAW_DURATIONS {
+        return Err(TransmitError::TooManyDurations(durations.len()));
+    }
+    if let Some((index, &duration)) = durations
+        .iter()
+        .enumerate()
+        .find(|&(_, &duration)| duration == 0 || duration > MAX_RAW_DURATION_US)
+    {
+        return Err(TransmitError::InvalidDuration { index, duration });
+    }
+
+    if durations.len() % 2 == 0 {
+        durations.pop();
+    }
+    Ok(durations)
+}
+
+/// 配置载波的频率和占空比
+fn set_carrier(driver: &TxRmtDriver<'static>, carrier: Carrier) -> Result<(), EspError> {
+    // 载波的高低电平以RMT源时钟计数，不受通道分频影响
+    let period = APB_CLK_HZ / carrier.frequency_hz;
+    let high = period * carrier.duty_percent as u32 / 100;
+    esp!(unsafe {
+        sys::rmt_set_tx_carrier(
+            driver.channel(),
+            true,
+            high as u16,
+            (period - high) as u16,
+            sys::rmt_carrier_level_t_RMT_CARRIER_LEVEL_HIGH,
+        )
+    })?;
+    log::debug!("载波: {}Hz, 占空比{}%", carrier.frequency_hz, carrier.duty_percent);
+    Ok(())
+}
+
+/// 录制使用的载波，没有测量到载波时为38kHz
+fn capture_carrier(capture: &Capture) -> Result<Carrier, TransmitError> {
+    match capture.carrier_hz {
+        Some(frequency_hz) => Carrier::with_frequency(frequency_hz),
+        None => Ok(Carrier::default()),
+    }
+}
+
+/// 录制的发射时序，没有单独设置时按第一帧识别出的协议查表
+fn capture_timing(capture: &Capture) -> ProtocolTiming {
+    capture.timing.unwrap_or_else(|| detect_timing(capture.durations()))
+}
+
+/// 按第一帧识别协议并查找时序
+fn detect_timing(durations: &[u32]) -> ProtocolTiming {
+    protocol_timing(detect_and_decode(&durations[..first_frame_len(durations)]).protocol())
+}
+
+fn is_gap(index: usize, duration: u32) -> bool {
+    index % 2 == 1 && duration >= FRAME_GAP_US
+}
+
+/// 第一帧的时长数量，不含帧后的间隔
+fn first_frame_len(durations: &[u32]) -> usize {
+    durations
+        .iter()
+        .enumerate()
+        .position(|(index, &duration)| is_gap(index, duration))
+        .unwrap_or(durations.len())
+}
+
+/// 最后一帧的长度（微秒），即最后一个帧间隔之后的部分
+fn last_frame_us(durations: &[u32]) -> u32 {
+    let start = durations
+        .iter()
+        .enumerate()
+        .rposition(|(index, &duration)| is_gap(index, duration))
+        .map_or(0, |index| index + 1);
+    durations[start..].iter().sum()
+}
+
+/// 按帧周期重新计算帧间隔，没有帧周期时保留录制到的间隔
+fn join_frames(durations: &[u32], timing: ProtocolTiming) -> Vec<u32> {
+    let period = timing.frame_period_us;
+    if period == 0 {
+        return durations.to_vec();
+    }
+
+    let mut joined = Vec::with_capacity(durations.len());
+    let mut frame_len = 0;
+    for (index, &duration) in durations.iter().enumerate() {
+        if is_gap(index, duration) {
+            joined.push(period.saturating_sub(frame_len).max(timing.min_gap_us));
+            frame_len = 0;
+        } else {
+            joined.push(duration);
+            frame_len += duration;
+        }
+    }
+    joined
+}
@@ -1,7 +1,65 @@
-use esp_idf_svc::hal::rmt::{FixedLengthSignal, PinState, Pulse, TxRmtDriver};
-use esp_idf_svc::hal::delay::FreeRtos;
+use esp_idf_svc::hal::rmt::{PinState, Pulse, Signal, TxRmtDriver, VariableLengthSignal};
+use esp_idf_svc::hal::units::Hertz;
+use esp_idf_svc::sys::{self, rmt_channel_t, rmt_item32_t, EspError};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
 use std::time::Duration;
 
+use crate::error::Error;
+use crate::rmt::TxGate;
+use crate::effect::{
+    Blink, Breathing, Candle, ColorWipe, Easing, Effect, EffectRunner, Fade, Priority, Rainbow, Sparkle, Step, Strobe,
+    StripEffect, TheaterChase,
+};
+
+/// 色相一圈的步数，每60°的区间分为256步
+pub const HUE_STEPS: u16 = 6 * 256;
+
+/// 默认亮度（百分比）
+pub const DEFAULT_BRIGHTNESS_PERCENT: u8 = 100;
+
+/// 允许设置的亮度范围（百分比）
+pub const BRIGHTNESS_RANGE: std::ops::RangeInclusive<u8> = 1..=100;
+
+/// 灯带最多的像素数量，每个像素24或32位，发送时每位占一个RMT符号（4字节）
+pub const MAX_STRIP_LEN: usize = 64;
+
+/// 每个通道满占空比时的默认电流（毫安），WS2812约为20mA
+pub const DEFAULT_CHANNEL_MA: u16 = 20;
+
+/// 允许设置的每通道电流（毫安）
+pub const CHANNEL_MA_RANGE: std::ops::RangeInclusive<u16> = 1..=60;
+
+/// 允许设置的电流上限（毫安），0表示不限制
+pub const MAX_CURRENT_MA_RANGE: std::ops::RangeInclusive<u32> = 100..=20_000;
+
+/// 允许设置的复位时间（微秒），一个RMT脉冲最长约400µs
+pub const RESET_US_RANGE: std::ops::RangeInclusive<u16> = 50..=400;
+
+/// 允许设置的每个高低电平时长（纳秒）
+const PULSE_NS_RANGE: std::ops::RangeInclusive<u16> = 100..=5000;
+
+/// 伽马2.2校正表，把感知上线性的亮度换算为LED的占空比
+const GAMMA: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2,
+    3, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 6, 6, 6,
+    6, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10, 10, 11, 11, 11, 12,
+    12, 13, 13, 13, 14, 14, 15, 15, 16, 16, 17, 17, 18, 18, 19, 19,
+    20, 20, 21, 22, 22, 23, 23, 24, 25, 25, 26, 26, 27, 28, 28, 29,
+    30, 30, 31, 32, 33, 33, 34, 35, 35, 36, 37, 38, 39, 39, 40, 41,
+    42, 43, 43, 44, 45, 46, 47, 48, 49, 49, 50, 51, 52, 53, 54, 55,
+    56, 57, 58, 59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71,
+    73, 74, 75, 76, 77, 78, 79, 81, 82, 83, 84, 85, 87, 88, 89, 90,
+    91, 93, 94, 95, 97, 98, 99, 100, 102, 103, 105, 106, 107, 109, 110, 111,
+    113, 114, 116, 117, 119, 120, 121, 123, 124, 126, 127, 129, 130, 132, 133, 135,
+    137, 138, 140, 141, 143, 145, 146, 148, 149, 151, 153, 154, 156, 158, 159, 161,
+    163, 165, 166, 168, 170, 172, 173, 175, 177, 179, 181, 182, 184, 186, 188, 190,
+    192, 194, 196, 197, 199, 201, 203, 205, 207, 209, 211, 213, 215, 217, 219, 221,
+    223, 225, 227, 229, 231, 234, 236, 238, 240, 242, 244, 246, 248, 251, 253, 255,
+];
+
 /// RGB颜色结构体
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct RgbColor {
@@ -12,187 +70,1064 @@ pub struct RgbColor {
 
 impl RgbColor {
     /// 创建新的RGB颜色
-    pub fn new(red: u8, green: u8, blue: u8) -> Self {
+    pub const fn new(red: u8, green: u8, blue: u8) -> Self {
         Self { red, green, blue }
     }
     
     /// 创建黑色（关闭）
-    pub fn black() -> Self {
+    pub const fn black() -> Self {
         Self { red: 0, green: 0, blue: 0 }
     }
     
     /// 创建白色
-    pub fn white() -> Self {
+    pub const fn white() -> Self {
         Self { red: 255, green: 255, blue: 255 }
     }
     
     /// 创建红色
-    pub fn red() -> Self {
+    pub const fn red() -> Self {
         Self { red: 255, green: 0, blue: 0 }
     }
     
     /// 创建绿色
-    pub fn green() -> Self {
+    pub const fn green() -> Self {
         Self { red: 0, green: 255, blue: 0 }
     }
     
     /// 创建蓝色
-    pub fn blue() -> Self {
+    pub const fn blue() -> Self {
         Self { red: 0, green: 0, blue: 255 }
     }
     
+    /// 按名称查找颜色，不区分大小写
+    pub fn from_name(name: &str) -> Option<Self> {
+        NAMED_COLORS
+            .iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(name))
+            .map(|(_, color)| *color)
+    }
+
+    /// 解析不带`#`的十六进制颜色，RGB简写时每一位重复一次
+    pub fn from_hex(hex: &str) -> Result<Self, ParseColorError> {
+        let digits = hex
+            .chars()
+            .map(|c| c.to_digit(16).map(|digit| digit as u8).ok_or(ParseColorError::InvalidDigit(c)))
+            .collect::<Result<Vec<_>, _>>()?;
+        match digits[..] {
+            [red, green, blue] => Ok(Self::new(red * 0x11, green * 0x11, blue * 0x11)),
+            [r1, r0, g1, g0, b1, b0] => Ok(Self::new(r1 << 4 | r0, g1 << 4 | g0, b1 << 4 | b0)),
+            _ => Err(ParseColorError::InvalidLength(digits.len())),
+        }
+    }
+    
     /// 线性插值计算两个颜色之间的中间颜色
+    ///
+    /// 在伽马校正之前的逻辑颜色上插值，亮度的变化在感知上是线性的，渐暗时不会在暗端突然跳变。
     pub fn lerp(&self, other: &RgbColor, t: f32) -> RgbColor {
         let t = t.clamp(0.0, 1.0);
+        let mix = |from: u8, to: u8| ((1.0 - t) * from as f32 + t * to as f32).round() as u8;
         RgbColor {
-            red: ((1.0 - t) * self.red as f32 + t * other.red as f32) as u8,
-            green: ((1.0 - t) * self.green as f32 + t * other.green as f32) as u8,
-            blue: ((1.0 - t) * self.blue as f32 + t * other.blue as f32) as u8,
+            red: mix(self.red, other.red),
+            green: mix(self.green, other.green),
+            blue: mix(self.blue, other.blue),
+        }
+    }
+}
+
+/// 可以按名称使用的颜色，基本沿用CSS的取值，green和CSS的lime相同
+const NAMED_COLORS: [(&str, RgbColor); 30] = [
+    ("off", RgbColor::black()),
+    ("black", RgbColor::black()),
+    ("white", RgbColor::white()),
+    ("warmwhite", RgbColor::new(255, 180, 107)),
+    ("red", RgbColor::red()),
+    ("green", RgbColor::green()),
+    ("blue", RgbColor::blue()),
+    ("yellow", RgbColor::new(255, 255, 0)),
+    ("cyan", RgbColor::new(0, 255, 255)),
+    ("magenta", RgbColor::new(255, 0, 255)),
+    ("orange", RgbColor::new(255, 165, 0)),
+    ("purple", RgbColor::new(128, 0, 128)),
+    ("pink", RgbColor::new(255, 192, 203)),
+    ("brown", RgbColor::new(165, 42, 42)),
+    ("gray", RgbColor::new(128, 128, 128)),
+    ("grey", RgbColor::new(128, 128, 128)),
+    ("silver", RgbColor::new(192, 192, 192)),
+    ("maroon", RgbColor::new(128, 0, 0)),
+    ("olive", RgbColor::new(128, 128, 0)),
+    ("lime", RgbColor::new(0, 255, 0)),
+    ("navy", RgbColor::new(0, 0, 128)),
+    ("teal", RgbColor::new(0, 128, 128)),
+    ("violet", RgbColor::new(238, 130, 238)),
+    ("indigo", RgbColor::new(75, 0, 130)),
+    ("gold", RgbColor::new(255, 215, 0)),
+    ("coral", RgbColor::new(255, 127, 80)),
+    ("salmon", RgbColor::new(250, 128, 114)),
+    ("crimson", RgbColor::new(220, 20, 60)),
+    ("turquoise", RgbColor::new(64, 224, 208)),
+    ("skyblue", RgbColor::new(135, 206, 235)),
+];
+
+/// 颜色字符串无法解析
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseColorError {
+    /// 十六进制颜色中有非十六进制的字符
+    InvalidDigit(char),
+    /// 十六进制颜色不是3位或6位
+    InvalidLength(usize),
+    /// 既不是已知的颜色名称，也不是十六进制颜色
+    UnknownName,
+}
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidDigit(c) => write!(f, "无效的十六进制字符: {:?}", c),
+            Self::InvalidLength(len) => write!(f, "十六进制颜色应为3位或6位，实际为{}位", len),
+            Self::UnknownName => write!(f, "未知的颜色名称"),
+        }
+    }
+}
+
+impl std::error::Error for ParseColorError {}
+
+impl FromStr for RgbColor {
+    type Err = ParseColorError;
+
+    /// 颜色名称、`#RRGGBB`或`#RGB`，不区分大小写；没有`#`时先按名称查找，全是十六进制字符时再按十六进制解析
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(hex) = s.strip_prefix('#') {
+            return Self::from_hex(hex);
+        }
+        if let Some(color) = Self::from_name(s) {
+            return Ok(color);
+        }
+        if !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Self::from_hex(s);
+        }
+        Err(ParseColorError::UnknownName)
+    }
+}
+
+/// RGBW颜色，SK6812等带白光芯片的像素使用
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RgbwColor {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub white: u8,
+}
+
+impl RgbwColor {
+    /// `extract_white`为true时把三个通道共同的部分（最小值）交给白光芯片，否则白光不亮
+    pub fn from_rgb(color: RgbColor, extract_white: bool) -> Self {
+        let white = if extract_white { color.red.min(color.green).min(color.blue) } else { 0 };
+        Self {
+            red: color.red - white,
+            green: color.green - white,
+            blue: color.blue - white,
+            white,
+        }
+    }
+}
+
+/// 像素的数据格式，决定每个像素的通道顺序和位数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PixelFormat {
+    /// WS2812等，GRB各8位
+    #[default]
+    Grb24,
+    /// RGB顺序的WS2811等
+    Rgb24,
+    /// SK6812 RGBW，GRB之后还有8位白光
+    Grbw32,
+}
+
+impl PixelFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Grb24 => "grb24",
+            Self::Rgb24 => "rgb24",
+            Self::Grbw32 => "grbw32",
+        }
+    }
+
+    /// 每个像素的位数
+    pub fn bits_per_pixel(&self) -> usize {
+        match self {
+            Self::Grb24 | Self::Rgb24 => 24,
+            Self::Grbw32 => 32,
+        }
+    }
+
+    /// 按发送顺序排列的通道字节，24位格式只用前3个
+    fn encode(&self, color: RgbwColor) -> [u8; 4] {
+        match self {
+            Self::Grb24 => [color.green, color.red, color.blue, 0],
+            Self::Rgb24 => [color.red, color.green, color.blue, 0],
+            Self::Grbw32 => [color.green, color.red, color.blue, color.white],
+        }
+    }
+}
+
+impl FromStr for PixelFormat {
+    type Err = ();
+
+    /// 不区分大小写，也接受不带位数的grb、rgb和grbw
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "grb24" | "grb" => Ok(Self::Grb24),
+            "rgb24" | "rgb" => Ok(Self::Rgb24),
+            "grbw32" | "grbw" => Ok(Self::Grbw32),
+            _ => Err(()),
+        }
+    }
+}
+
+/// 灯带的时序：每一位的高低电平时长（纳秒），以及一帧之后保持低电平的复位时间（微秒）
+///
+/// 复位时间作为最后一个低电平脉冲发送，`show`返回时已经过了复位时间，连续发送的两帧不会连在一起。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timing {
+    pub t0h_ns: u16,
+    pub t0l_ns: u16,
+    pub t1h_ns: u16,
+    pub t1l_ns: u16,
+    pub reset_us: u16,
+}
+
+impl Timing {
+    /// 初代WS2812，复位时间至少50µs
+    pub const WS2812: Timing = Timing::new(350, 800, 700, 600, 80);
+    /// 新款WS2812B，复位时间至少280µs
+    pub const WS2812B: Timing = Timing::new(400, 850, 800, 450, 300);
+    /// SK6812（包括RGBW），复位时间至少80µs
+    pub const SK6812: Timing = Timing::new(300, 900, 600, 600, 100);
+
+    /// 有名称的预设
+    pub const PRESETS: [(&'static str, Timing); 3] =
+        [("ws2812", Self::WS2812), ("ws2812b", Self::WS2812B), ("sk6812", Self::SK6812)];
+
+    pub const fn new(t0h_ns: u16, t0l_ns: u16, t1h_ns: u16, t1l_ns: u16, reset_us: u16) -> Self {
+        Self { t0h_ns, t0l_ns, t1h_ns, t1l_ns, reset_us }
+    }
+
+    /// 预设的名称，自定义的时序返回None
+    pub fn preset_name(&self) -> Option<&'static str> {
+        Self::PRESETS.iter().find(|(_, preset)| preset == self).map(|(name, _)| *name)
+    }
+}
+
+impl Default for Timing {
+    /// 兼容要求最严格的新款WS2812B
+    fn default() -> Self {
+        Self::WS2812B
+    }
+}
+
+impl fmt::Display for Timing {
+    /// 预设显示名称，自定义的时序显示为`t0h:t0l:t1h:t1l:reset`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.preset_name() {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{}:{}:{}:{}:{}", self.t0h_ns, self.t0l_ns, self.t1h_ns, self.t1l_ns, self.reset_us),
+        }
+    }
+}
+
+impl FromStr for Timing {
+    type Err = ();
+
+    /// 预设名称（不区分大小写），或者`t0h:t0l:t1h:t1l:reset`，电平时长和复位时间需要在允许的范围内
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, preset)) = Self::PRESETS.iter().find(|(name, _)| name.eq_ignore_ascii_case(s)) {
+            return Ok(*preset);
+        }
+        let values = s.split(':').map(|value| value.parse::<u16>().map_err(|_| ())).collect::<Result<Vec<_>, _>>()?;
+        match values[..] {
+            [t0h_ns, t0l_ns, t1h_ns, t1l_ns, reset_us]
+                if [t0h_ns, t0l_ns, t1h_ns, t1l_ns].iter().all(|ns| PULSE_NS_RANGE.contains(ns))
+                    && RESET_US_RANGE.contains(&reset_us) =>
+            {
+                Ok(Self::new(t0h_ns, t0l_ns, t1h_ns, t1l_ns, reset_us))
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+/// 灯带的电流预算
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerBudget {
+    /// 每个通道满占空比时的电流（毫安）
+    pub channel_ma: u16,
+    /// 一帧的电流上限（毫安），0表示不限制
+    pub max_current_ma: u32,
+}
+
+impl Default for PowerBudget {
+    fn default() -> Self {
+        Self {
+            channel_ma: DEFAULT_CHANNEL_MA,
+            max_current_ma: 0,
+        }
+    }
+}
+
+impl PowerBudget {
+    /// 按每个通道的占空比估算一帧的电流（毫安）
+    pub fn estimate_ma(&self, frame: &[u8]) -> u32 {
+        let total: u32 = frame.iter().map(|&channel| channel as u32).sum();
+        total * self.channel_ma as u32 / 255
+    }
+
+    /// 估算的电流超过上限时按比例缩小所有通道，返回缩放比例（千分比）
+    ///
+    /// 在伽马校正之后的占空比上缩放，电流和发出的光按同一比例减少，颜色的比例不变。
+    pub fn limit(&self, frame: &mut [u8]) -> u16 {
+        let estimate = self.estimate_ma(frame);
+        if self.max_current_ma == 0 || estimate <= self.max_current_ma {
+            return 1000;
+        }
+        for channel in frame.iter_mut() {
+            *channel = (*channel as u32 * self.max_current_ma / estimate) as u8;
+        }
+        (self.max_current_ma * 1000 / estimate) as u16
+    }
+}
+
+/// HSV颜色，色相的单位是`HUE_STEPS`分之一圈，饱和度和亮度为0-255
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HsvColor {
+    pub hue: u16,
+    pub sat: u8,
+    pub val: u8,
+}
+
+impl HsvColor {
+    pub fn new(hue: u16, sat: u8, val: u8) -> Self {
+        Self { hue: hue % HUE_STEPS, sat, val }
+    }
+
+    /// 按角度（0-359）指定色相
+    pub fn from_degrees(degrees: u16, sat: u8, val: u8) -> Self {
+        let hue = (degrees as u32 % 360 * HUE_STEPS as u32 + 180) / 360;
+        Self::new(hue as u16, sat, val)
+    }
+}
+
+impl From<HsvColor> for RgbColor {
+    /// 只用整数运算，与`From<RgbColor>`往返后每个通道相差不超过1
+    fn from(hsv: HsvColor) -> Self {
+        let val = hsv.val as u32;
+        let chroma = (val * hsv.sat as u32 + 127) / 255;
+        let min = val - chroma;
+        let hue = (hsv.hue % HUE_STEPS) as u32;
+        // 区间内从min升到val的通道
+        let rise = min + (chroma * (hue % 256) + 128) / 256;
+        // 区间内从val降到min的通道
+        let fall = val + min - rise;
+        let (red, green, blue) = match hue / 256 {
+            0 => (val, rise, min),
+            1 => (fall, val, min),
+            2 => (min, val, rise),
+            3 => (min, fall, val),
+            4 => (rise, min, val),
+            _ => (val, min, fall),
+        };
+        RgbColor::new(red as u8, green as u8, blue as u8)
+    }
+}
+
+impl From<RgbColor> for HsvColor {
+    fn from(rgb: RgbColor) -> Self {
+        let (red, green, blue) = (rgb.red as i32, rgb.green as i32, rgb.blue as i32);
+        let max = red.max(green).max(blue);
+        let min = red.min(green).min(blue);
+        let chroma = max - min;
+        if chroma == 0 {
+            // 灰色没有色相
+            return HsvColor::new(0, 0, max as u8);
+        }
+
+        // 最大的通道决定所在的两个区间，另外两个通道的差决定偏移
+        let (base, diff) = if max == red {
+            (0, green - blue)
+        } else if max == green {
+            (512, blue - red)
+        } else {
+            (1024, red - green)
+        };
+        let offset = (2 * 256 * diff + chroma * diff.signum()) / (2 * chroma);
+        let hue = (base + offset).rem_euclid(HUE_STEPS as i32);
+        let sat = (255 * chroma + max / 2) / max;
+        HsvColor::new(hue as u16, sat as u8, max as u8)
+    }
+}
+
+/// 崩溃时把灯带点亮为红色的信号，时序、格式、长度、亮度或电流预算变化后下一次`show`时重新编码
+static PANIC_SIGNAL: Mutex<Option<(rmt_channel_t, Vec<rmt_item32_t>)>> = Mutex::new(None);
+
+/// 供panic钩子调用，直接把预先编码的信号交给RMT驱动，不分配内存，失败时什么也不做
+pub fn show_panic_red() {
+    let Ok(signal) = PANIC_SIGNAL.try_lock() else {
+        return;
+    };
+    if let Some((channel, items)) = signal.as_ref() {
+        unsafe { sys::rmt_write_items(*channel, items.as_ptr(), items.len() as i32, true) };
+    }
+}
+
+/// WS2812灯带驱动，先把每个像素的颜色写入帧缓冲区，`show`时一起发送
+///
+/// 整条灯带的信号超过通道的RMT内存块时，由驱动在发送过程中循环填充内存块。
+/// SK6812的时序与WS2812兼容，只有像素格式不同。
+pub struct Ws2812Strip {
+    /// 启动时发射通道初始化失败时为None，只更新帧缓冲区不发送
+    rmt: Option<TxRmtDriver<'static>>,
+    pixels: Vec<RgbColor>,
+    format: PixelFormat,
+    timing: Timing,
+    /// RGBW格式下把RGB共同的部分换成白光
+    auto_white: bool,
+    /// 亮度上限（百分比），发送前缩放所有颜色
+    brightness: u8,
+    /// 第一次发送时计算的时序脉冲
+    pulses: Option<BitPulses>,
+    power: PowerBudget,
+    /// 最近一帧限流后估算的电流（毫安）
+    frame_current_ma: u32,
+    /// 最近一帧的限流比例（千分比），1000表示没有缩放
+    frame_scale_permille: u16,
+    /// 和其他发射通道互斥
+    gate: Option<TxGate>,
+    /// 其他通道正在发送，这一帧推迟到下一次`tick`
+    pending: bool,
+    /// 需要重新编码崩溃时显示的红色
+    panic_signal_stale: bool,
+}
+
+impl Ws2812Strip {
+    /// 创建有`len`个像素的灯带，像素数量限制在1到`MAX_STRIP_LEN`之间
+    pub fn new(rmt: TxRmtDriver<'static>, len: usize, format: PixelFormat, timing: Timing) -> Self {
+        Self::with_driver(Some(rmt), len, format, timing)
+    }
+
+    fn with_driver(rmt: Option<TxRmtDriver<'static>>, len: usize, format: PixelFormat, timing: Timing) -> Self {
+        Self {
+            rmt,
+            pixels: vec![RgbColor::black(); len.clamp(1, MAX_STRIP_LEN)],
+            format,
+            timing,
+            auto_white: false,
+            brightness: DEFAULT_BRIGHTNESS_PERCENT,
+            pulses: None,
+            power: PowerBudget::default(),
+            frame_current_ma: 0,
+            frame_scale_permille: 1000,
+            gate: None,
+            pending: false,
+            panic_signal_stale: true,
+        }
+    }
+
+    /// 其他发射通道正在发送时推迟这一帧，不等待
+    pub fn with_gate(mut self, gate: TxGate) -> Self {
+        self.gate = Some(gate);
+        self
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    pub fn auto_white(&self) -> bool {
+        self.auto_white
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    pub fn power(&self) -> PowerBudget {
+        self.power
+    }
+
+    /// 修改电流预算，`show`之后生效
+    pub fn set_power(&mut self, power: PowerBudget) {
+        self.power = power;
+        self.panic_signal_stale = true;
+    }
+
+    /// 最近一帧限流后估算的电流（毫安）
+    pub fn frame_current_ma(&self) -> u32 {
+        self.frame_current_ma
+    }
+
+    /// 最近一帧的限流比例（千分比）
+    pub fn frame_scale_permille(&self) -> u16 {
+        self.frame_scale_permille
+    }
+
+    /// 修改时序，`show`时按新的时序重新计算脉冲
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.pulses = None;
+        self.panic_signal_stale = true;
+    }
+
+    /// 修改像素格式和是否自动提取白光，`show`之后生效；`auto_white`只对RGBW格式有效
+    pub fn set_format(&mut self, format: PixelFormat, auto_white: bool) {
+        self.format = format;
+        self.auto_white = auto_white;
+        self.panic_signal_stale = true;
+    }
+
+    pub fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    /// 修改像素数量，新增的像素为黑色，同样限制在1到`MAX_STRIP_LEN`之间
+    pub fn resize(&mut self, len: usize) {
+        self.pixels.resize(len.clamp(1, MAX_STRIP_LEN), RgbColor::black());
+        self.panic_signal_stale = true;
+    }
+
+    /// 设置一个像素的颜色，`show`之后生效；超出范围时返回false
+    pub fn set_pixel(&mut self, index: usize, color: RgbColor) -> bool {
+        match self.pixels.get_mut(index) {
+            Some(pixel) => {
+                *pixel = color;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn pixels(&self) -> &[RgbColor] {
+        &self.pixels
+    }
+
+    /// 帧缓冲区，修改后`show`生效
+    pub fn pixels_mut(&mut self) -> &mut [RgbColor] {
+        &mut self.pixels
+    }
+
+    /// 所有像素设为同一个颜色，`show`之后生效
+    pub fn fill(&mut self, color: RgbColor) {
+        self.pixels.fill(color);
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// 设置亮度上限（百分比），`show`之后生效
+    pub fn set_brightness(&mut self, percent: u8) {
+        self.brightness = percent.clamp(*BRIGHTNESS_RANGE.start(), *BRIGHTNESS_RANGE.end());
+        self.panic_signal_stale = true;
+    }
+
+    /// 发送帧缓冲区中所有像素的颜色，其他通道正在发送时推迟到`show_pending`
+    pub fn show(&mut self) -> Result<(), Error> {
+        let Some(rmt) = self.rmt.as_mut() else {
+            return Ok(());
+        };
+        let gate = self.gate.clone();
+        let _guard = match &gate {
+            Some(gate) => match gate.try_lock() {
+                Some(guard) => Some(guard),
+                None => {
+                    self.pending = true;
+                    return Ok(());
+                }
+            },
+            None => None,
+        };
+        self.pending = false;
+
+        let pulses = match self.pulses {
+            Some(pulses) => pulses,
+            None => *self.pulses.insert(BitPulses::new(rmt.counter_clock()?, &self.timing)?),
+        };
+        let mut frame = encode_frame(&self.pixels, self.format, self.auto_white, self.brightness);
+        let scale = self.power.limit(&mut frame);
+        if scale != self.frame_scale_permille {
+            if scale < 1000 {
+                log::warn!("LED电流超过上限{}mA，亮度缩放到{}‰", self.power.max_current_ma, scale);
+            } else {
+                log::info!("LED电流回到上限以内，不再缩放");
+            }
         }
+        self.frame_scale_permille = scale;
+        self.frame_current_ma = self.power.estimate_ma(&frame);
+
+        let signal = pulses.encode(&frame)?;
+
+        if self.panic_signal_stale {
+            let red = vec![RgbColor::red(); self.pixels.len()];
+            let mut frame = encode_frame(&red, self.format, self.auto_white, self.brightness);
+            self.power.limit(&mut frame);
+            let items = pulses.encode(&frame)?.as_slice().to_vec();
+            if let Ok(mut panic_signal) = PANIC_SIGNAL.lock() {
+                *panic_signal = Some((rmt.channel(), items));
+                self.panic_signal_stale = false;
+            }
+        }
+
+        rmt.start_blocking(&signal)?;
+        log::debug!("RMT信号发送成功");
+        Ok(())
+    }
+
+    /// 补发之前推迟的帧
+    pub fn show_pending(&mut self) -> Result<(), Error> {
+        if self.pending {
+            self.show()?;
+        }
+        Ok(())
+    }
+}
+
+/// 按`Timing`换算的脉冲，只取决于时序和RMT时钟频率，第一次发送时计算一次
+#[derive(Debug, Clone, Copy)]
+struct BitPulses<P = Pulse> {
+    zero: (P, P),
+    one: (P, P),
+    reset: P,
+}
+
+impl BitPulses {
+    fn new(ticks_hz: Hertz, timing: &Timing) -> Result<Self, EspError> {
+        Self::from_timing(timing, |state, duration| Pulse::new_with_duration(ticks_hz, state, &duration))
+    }
+
+    fn encode(&self, frame: &[u8]) -> Result<VariableLengthSignal, EspError> {
+        let mut signal = VariableLengthSignal::with_capacity(frame.len() * 16 + 1);
+        signal.push(self.sequence(frame))?;
+        Ok(signal)
+    }
+}
+
+impl<P> BitPulses<P> {
+    /// `pulse`把电平和时长换算为脉冲
+    fn from_timing<E>(timing: &Timing, pulse: impl Fn(PinState, Duration) -> Result<P, E>) -> Result<Self, E> {
+        let ns = |ns: u16| Duration::from_nanos(ns as u64);
+        Ok(Self {
+            zero: (pulse(PinState::High, ns(timing.t0h_ns))?, pulse(PinState::Low, ns(timing.t0l_ns))?),
+            one: (pulse(PinState::High, ns(timing.t1h_ns))?, pulse(PinState::Low, ns(timing.t1l_ns))?),
+            reset: pulse(PinState::Low, Duration::from_micros(timing.reset_us as u64))?,
+        })
+    }
+
+    /// 每一位一高一低两个脉冲，从最高位开始，最后是复位时间
+    fn sequence<'a>(&'a self, frame: &'a [u8]) -> impl Iterator<Item = &'a P> + 'a {
+        frame
+            .iter()
+            .flat_map(|&byte| (0..8).rev().map(move |bit| byte >> bit & 1 == 1))
+            .flat_map(move |bit| {
+                let (high, low) = if bit { &self.one } else { &self.zero };
+                [high, low]
+            })
+            .chain([&self.reset])
+    }
+}
+
+/// 把帧缓冲区编码为按发送顺序排列的字节，不涉及硬件
+///
+/// 每个通道先按亮度缩放再做伽马校正，在校正后的占空比上提取白光，发出的光量不变。
+pub fn encode_frame(pixels: &[RgbColor], format: PixelFormat, auto_white: bool, brightness: u8) -> Vec<u8> {
+    let output = |channel: u8| GAMMA[(channel as u32 * brightness as u32 / 100) as usize];
+    let extract_white = auto_white && format == PixelFormat::Grbw32;
+    let bytes_per_pixel = format.bits_per_pixel() / 8;
+    let mut frame = Vec::with_capacity(pixels.len() * bytes_per_pixel);
+    for color in pixels {
+        let color = RgbColor::new(output(color.red), output(color.green), output(color.blue));
+        let bytes = format.encode(RgbwColor::from_rgb(color, extract_white));
+        frame.extend_from_slice(&bytes[..bytes_per_pixel]);
     }
+    frame
 }
 
-/// WS2812 LED控制器
+/// WS2812 LED控制器，整条灯带显示同一个颜色
+///
+/// 效果不阻塞调用方：启动后由主循环调用`tick`逐帧推进，`set_color`会取消正在运行的效果。
 pub struct Ws2812Led {
-    rmt: TxRmtDriver<'static>,
+    strip: Ws2812Strip,
+    /// 正在显示的颜色，效果运行时随每一帧变化
     current_color: RgbColor,
+    /// 最近一次`set_color`设置的颜色，效果结束或取消后恢复
+    static_color: RgbColor,
+    effects: EffectRunner,
+    /// 设置颜色、启动效果或修改像素的次数，状态指示借此发现其他地方修改了LED
+    revision: u32,
 }
 
 impl Ws2812Led {
-    /// 创建新的WS2812 LED控制器
-    pub fn new(rmt: TxRmtDriver<'static>) -> Self {
+    /// 创建新的WS2812 LED控制器，默认只有板载的一个LED
+    pub fn new(rmt: TxRmtDriver<'static>, format: PixelFormat, timing: Timing) -> Self {
         Self {
-            rmt,
+            strip: Ws2812Strip::new(rmt, 1, format, timing),
             current_color: RgbColor::black(),
+            static_color: RgbColor::black(),
+            effects: EffectRunner::default(),
+            revision: 0,
         }
     }
     
-    /// 设置LED颜色
-    pub fn set_color(&mut self, color: RgbColor) -> Result<(), Box<dyn std::error::Error>> {
-        // log::info!("设置LED颜色: R={}, G={}, B={}", color.red, color.green, color.blue);
-        
-        // 获取RMT时钟频率
-        let ticks_hz = self.rmt.counter_clock()?;
-        
-        // 创建正确的WS2812时序脉冲
-        let t0h = Pulse::new_with_duration(ticks_hz, PinState::High, &Duration::from_nanos(350))?;
-        let t0l = Pulse::new_with_duration(ticks_hz, PinState::Low, &Duration::from_nanos(800))?;
-        let t1h = Pulse::new_with_duration(ticks_hz, PinState::High, &Duration::from_nanos(700))?;
-        let t1l = Pulse::new_with_duration(ticks_hz, PinState::Low, &Duration::from_nanos(600))?;
-        
-        // 构建24位信号
-        let mut signal = FixedLengthSignal::<24>::new();
-        
-        // 按照GRB顺序编码颜色数据
-        let color_data: u32 = ((color.green as u32) << 16) | ((color.red as u32) << 8) | (color.blue as u32);
-        
-        // 从最高位开始设置每一位
-        for i in (0..24).rev() {
-            let bit_mask = 2_u32.pow(i);
-            let bit: bool = (bit_mask & color_data) != 0;
-            let (high_pulse, low_pulse) = if bit { (t1h, t1l) } else { (t0h, t0l) };
-            
-            signal.set(23 - i as usize, &(high_pulse, low_pulse))?;
-        }
-        
-        // 发送信号
-        self.rmt.start_blocking(&signal)?;
-        self.current_color = color;
-        
-        log::info!("RMT信号发送成功");
-        Ok(())
+    /// 发射通道初始化失败时使用，颜色和效果照常计算但不发送
+    pub fn unavailable(format: PixelFormat, timing: Timing) -> Self {
+        Self {
+            strip: Ws2812Strip::with_driver(None, 1, format, timing),
+            current_color: RgbColor::black(),
+            static_color: RgbColor::black(),
+            effects: EffectRunner::default(),
+            revision: 0,
+        }
+    }
+
+    /// 其他发射通道正在发送时推迟LED的帧
+    pub fn with_gate(mut self, gate: TxGate) -> Self {
+        self.strip = self.strip.with_gate(gate);
+        self
+    }
+
+    /// 设置LED颜色，取消提醒以外的效果；提醒运行时等提醒结束后再显示
+    pub fn set_color(&mut self, color: RgbColor) -> Result<(), Error> {
+        self.effects.cancel_up_to(Priority::Status);
+        self.static_color = color;
+        self.revision = self.revision.wrapping_add(1);
+        if self.effects.is_running() {
+            return Ok(());
+        }
+        self.show(color)
     }
     
-    /// 获取当前颜色
+    /// 按HSV设置LED颜色
+    pub fn set_hsv(&mut self, color: HsvColor) -> Result<(), Error> {
+        self.set_color(color.into())
+    }
+
+    /// 获取当前颜色（未经亮度缩放和伽马校正）
     pub fn current_color(&self) -> RgbColor {
         self.current_color
     }
-    
-    /// 渐变到目标颜色
-    pub fn fade_to(&mut self, target_color: RgbColor, duration_ms: u32, steps: u32) -> Result<(), Box<dyn std::error::Error>> {
-        log::info!("开始渐变: 从 {:?} 到 {:?}, 持续时间: {}ms, 步数: {}", 
-                  self.current_color, target_color, duration_ms, steps);
-        
-        let step_duration = duration_ms / steps;
-        
-        for step in 0..=steps {
-            let t = if steps == 0 { 1.0 } else { step as f32 / steps as f32 };
-            let intermediate_color = self.current_color.lerp(&target_color, t);
-            
-            self.set_color(intermediate_color)?;
-            FreeRtos::delay_ms(step_duration);
-        }
-        
-        // 确保最终颜色准确
-        self.set_color(target_color)?;
-        log::info!("渐变完成");
+
+    pub fn brightness(&self) -> u8 {
+        self.strip.brightness()
+    }
+
+    /// 设置亮度上限（百分比），并按新的亮度重新发送当前颜色
+    pub fn set_brightness(&mut self, percent: u8) -> Result<(), Error> {
+        self.strip.set_brightness(percent);
+        self.strip.show()
+    }
+
+    /// 修改灯带的像素数量，所有像素重新显示当前颜色
+    pub fn set_len(&mut self, len: usize) -> Result<(), Error> {
+        self.strip.resize(len);
+        self.show(self.current_color)
+    }
+
+    /// 修改像素格式，按新的格式重新发送当前颜色
+    pub fn set_format(&mut self, format: PixelFormat, auto_white: bool) -> Result<(), Error> {
+        self.strip.set_format(format, auto_white);
+        self.strip.show()
+    }
+
+    /// 修改时序，按新的时序重新发送当前颜色
+    pub fn set_timing(&mut self, timing: Timing) -> Result<(), Error> {
+        self.strip.set_timing(timing);
+        self.strip.show()
+    }
+
+    /// 修改电流预算，按新的预算重新发送当前画面
+    pub fn set_power(&mut self, power: PowerBudget) -> Result<(), Error> {
+        self.strip.set_power(power);
+        self.strip.show()
+    }
+
+    pub fn strip(&self) -> &Ws2812Strip {
+        &self.strip
+    }
+
+    /// 直接访问灯带设置单个像素，之后的`set_color`会覆盖所有像素
+    pub fn strip_mut(&mut self) -> &mut Ws2812Strip {
+        self.revision = self.revision.wrapping_add(1);
+        &mut self.strip
+    }
+
+    /// 启动效果，替换同一优先级上的效果，暂停更低优先级的效果
+    pub fn start_effect(&mut self, effect: impl Effect + 'static, priority: Priority) {
+        self.revision = self.revision.wrapping_add(1);
+        self.effects.start(Box::new(effect), priority);
+    }
+
+    /// 启动按像素绘制的效果，替换同一优先级上的效果，暂停更低优先级的效果
+    pub fn start_strip_effect(&mut self, effect: impl StripEffect + 'static, priority: Priority) {
+        self.revision = self.revision.wrapping_add(1);
+        self.effects.start_strip(Box::new(effect), priority);
+    }
+
+    pub fn revision(&self) -> u32 {
+        self.revision
+    }
+
+    pub fn effect_running(&self) -> bool {
+        self.effects.is_running()
+    }
+
+    /// `priority`上是否有效果，包括被更高优先级暂停的
+    pub fn effect_running_at(&self, priority: Priority) -> bool {
+        self.effects.is_running_at(priority)
+    }
+
+    /// 停止所有优先级上的效果，恢复最近一次设置的颜色
+    pub fn stop_effect(&mut self) -> Result<(), Error> {
+        if self.effects.cancel() {
+            self.show(self.static_color)?;
+        }
         Ok(())
     }
-    
-    /// 彩虹渐变效果
-    pub fn rainbow(&mut self, duration_ms: u32) -> Result<(), Box<dyn std::error::Error>> {
+
+    /// 推进正在运行的效果，效果结束时恢复最近一次设置的颜色
+    pub fn tick(&mut self, now_ms: u64) -> Result<(), Error> {
+        match self.effects.tick(now_ms, self.strip.pixels_mut()) {
+            Step::Idle => self.strip.show_pending(),
+            Step::Show(color) => self.show(color),
+            Step::Rendered => {
+                self.strip.show()?;
+                // 之后的渐变从第一个像素的颜色开始
+                self.current_color = self.strip.pixels()[0];
+                Ok(())
+            }
+            Step::Finished => self.show(self.static_color),
+        }
+    }
+
+    /// 渐变到目标颜色，结束后保持目标颜色
+    ///
+    /// 从正在显示的颜色开始：渐变中途再次调用时，从上一帧插值出的颜色转向新的目标，不会跳回旧的起点或终点。
+    pub fn fade_to(&mut self, target_color: RgbColor, duration_ms: u32, easing: Easing) {
+        log::info!(
+            "开始渐变: 从 {:?} 到 {:?}, 持续时间: {}ms, 曲线: {:?}",
+            self.current_color,
+            target_color,
+            duration_ms,
+            easing
+        );
+        self.static_color = target_color;
+        self.start_effect(Fade::new(self.current_color, target_color, duration_ms, easing), Priority::Status);
+    }
+
+    /// 彩虹渐变效果，在`duration_ms`内连续转过一圈色相
+    pub fn rainbow(&mut self, duration_ms: u32) {
         log::info!("开始彩虹渐变效果");
-        
+        self.start_effect(Rainbow::new(duration_ms), Priority::Status);
+    }
+    
+    /// 呼吸灯效果，`cycles`为None时一直运行到被取消
+    pub fn breathing(&mut self, color: RgbColor, cycles: Option<u32>, period_ms: u32) {
+        log::info!("开始呼吸灯效果: {:?}, 循环次数: {:?}", color, cycles);
+        self.start_effect(Breathing::new(color, cycles, period_ms), Priority::Status);
+    }
+    
+    /// 闪烁效果，`times`为None时一直闪烁到被取消
+    pub fn blink(&mut self, color: RgbColor, times: Option<u32>, on_duration_ms: u32, off_duration_ms: u32) {
+        log::info!("开始闪烁效果: {:?}, 次数: {:?}", color, times);
+        self.start_effect(Blink::new(color, times, on_duration_ms, off_duration_ms), Priority::Status);
+    }
+
+    /// 剧院追逐效果，每`step_ms`移动一格，一直运行到被取消
+    pub fn theater_chase(&mut self, color: RgbColor, background: RgbColor, step_ms: u32) {
+        log::info!("开始追逐效果: {:?}, 背景: {:?}", color, background);
+        self.start_strip_effect(TheaterChase::new(color, background, step_ms), Priority::Status);
+    }
+
+    /// 颜色擦除效果，逐个点亮再逐个熄灭，每个像素`step_ms`
+    pub fn color_wipe(&mut self, color: RgbColor, step_ms: u32) {
+        log::info!("开始擦除效果: {:?}", color);
+        self.start_strip_effect(ColorWipe::new(color, step_ms), Priority::Status);
+    }
+
+    /// 星点效果，`density`为每次闪烁时像素被选中的百分比，一直运行到被取消
+    pub fn sparkle(&mut self, color: RgbColor, background: RgbColor, density: u8, step_ms: u32, seed: u32) {
+        log::info!("开始星点效果: {:?}, 背景: {:?}, 密度: {}%", color, background, density);
+        self.start_strip_effect(Sparkle::new(color, background, density, step_ms, seed), Priority::Status);
+    }
+
+    /// 烛光效果，作为氛围灯在其他效果结束后继续，一直运行到被取消
+    ///
+    /// 同时取消状态指示等同一优先级上的效果，否则烛光会一直被它们暂停。
+    pub fn candle(&mut self, color: RgbColor, seed: u32) {
+        log::info!("开始烛光效果: {:?}", color);
+        self.effects.cancel_up_to(Priority::Status);
+        self.start_effect(Candle::new(color, seed), Priority::Ambient);
+    }
+
+    /// 提醒频闪，暂停其他效果，结束后恢复
+    pub fn alert(&mut self, color: RgbColor, duration_ms: u32) {
+        log::info!("开始提醒频闪: {:?}, 持续时间: {}ms", color, duration_ms);
+        self.start_effect(Strobe::new(color, duration_ms), Priority::Alert);
+    }
+
+    /// 显示启动自检结果：全部通过时绿色亮1秒，否则红色闪烁`failed`次
+    pub fn self_check_result(&mut self, failed: Option<u32>) {
+        let effect = match failed {
+            None => Blink::new(RgbColor::green(), Some(1), 1000, 0),
+            Some(times) => Blink::new(RgbColor::red(), Some(times), 300, 300),
+        };
+        self.start_effect(effect, Priority::Alert);
+    }
+
+    /// 整条灯带显示一个颜色
+    fn show(&mut self, color: RgbColor) -> Result<(), Error> {
+        self.strip.fill(color);
+        self.strip.show()?;
+        self.current_color = color;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 脉冲用电平和时长表示，便于比较
+    type TestPulse = (PinState, Duration);
+
+    fn test_pulses(timing: &Timing) -> BitPulses<TestPulse> {
+        BitPulses::from_timing(timing, |state, duration| Ok::<_, ()>((state, duration))).unwrap()
+    }
+
+    /// 缓存脉冲之前的编码方式：逐个像素缩放、校正、按格式排列，每个字节从最高位逐位取脉冲
+    fn per_bit_reference(pixels: &[RgbColor], format: PixelFormat, auto_white: bool, brightness: u8, timing: &Timing) -> Vec<TestPulse> {
+        let ns = |ns: u16| Duration::from_nanos(ns as u64);
+        let (t0h, t0l) = ((PinState::High, ns(timing.t0h_ns)), (PinState::Low, ns(timing.t0l_ns)));
+        let (t1h, t1l) = ((PinState::High, ns(timing.t1h_ns)), (PinState::Low, ns(timing.t1l_ns)));
+        let output = |channel: u8| GAMMA[(channel as u32 * brightness as u32 / 100) as usize];
+        let bits = format.bits_per_pixel();
+        let extract_white = auto_white && format == PixelFormat::Grbw32;
+
+        let mut signal = Vec::new();
+        for color in pixels {
+            let output = RgbColor::new(output(color.red), output(color.green), output(color.blue));
+            let bytes = format.encode(RgbwColor::from_rgb(output, extract_white));
+            for byte in &bytes[..bits / 8] {
+                for i in (0..8).rev() {
+                    let (high, low) = if byte & (1 << i) != 0 { (t1h, t1l) } else { (t0h, t0l) };
+                    signal.push(high);
+                    signal.push(low);
+                }
+            }
+        }
+        signal.push((PinState::Low, Duration::from_micros(timing.reset_us as u64)));
+        signal
+    }
+
+    #[test]
+    fn cached_pulses_match_per_bit_encoding() {
         let colors = [
             RgbColor::red(),
-            RgbColor::new(255, 127, 0), // 橙色
-            RgbColor::new(255, 255, 0), // 黄色
-            RgbColor::green(),
-            RgbColor::new(0, 255, 255), // 青色
-            RgbColor::blue(),
-            RgbColor::new(127, 0, 255), // 紫色
+            RgbColor::new(0x12, 0x34, 0x56),
+            RgbColor::white(),
+            RgbColor::new(200, 180, 20),
         ];
-        
-        let step_duration = duration_ms / colors.len() as u32;
-        
-        for color in colors.iter() {
-            self.fade_to(*color, step_duration, 20)?;
-        }
-        
-        log::info!("彩虹渐变完成");
-        Ok(())
-    }
-    
-    /// 呼吸灯效果
-    pub fn breathing(&mut self, color: RgbColor, cycles: u32) -> Result<(), Box<dyn std::error::Error>> {
-        log::info!("开始呼吸灯效果: {:?}, 循环次数: {}", color, cycles);
-        
-        for _ in 0..cycles {
-            // 从暗到亮
-            for brightness in 0..=100 {
-                let t = brightness as f32 / 100.0;
-                let dimmed_color = RgbColor::black().lerp(&color, t);
-                self.set_color(dimmed_color)?;
-                FreeRtos::delay_ms(20);
+        let timing = Timing::default();
+        let pulses = test_pulses(&timing);
+        for format in [PixelFormat::Grb24, PixelFormat::Rgb24, PixelFormat::Grbw32] {
+            for (auto_white, brightness) in [(false, 100), (true, 100), (true, 40)] {
+                let frame = encode_frame(&colors, format, auto_white, brightness);
+                let encoded: Vec<TestPulse> = pulses.sequence(&frame).copied().collect();
+                assert_eq!(
+                    encoded,
+                    per_bit_reference(&colors, format, auto_white, brightness, &timing),
+                    "{:?} auto_white={} brightness={}",
+                    format,
+                    auto_white,
+                    brightness
+                );
             }
-            
-            // 从亮到暗
-            for brightness in (0..=100).rev() {
-                let t = brightness as f32 / 100.0;
-                let dimmed_color = RgbColor::black().lerp(&color, t);
-                self.set_color(dimmed_color)?;
-                FreeRtos::delay_ms(20);
+        }
+    }
+
+    #[test]
+    fn frames_end_with_preset_reset_latch() {
+        let pixels = [RgbColor::red(), RgbColor::blue(), RgbColor::white()];
+        for (name, timing) in Timing::PRESETS {
+            let pulses = test_pulses(&timing);
+            for format in [PixelFormat::Grb24, PixelFormat::Grbw32] {
+                let frame = encode_frame(&pixels, format, false, 100);
+                let encoded: Vec<TestPulse> = pulses.sequence(&frame).copied().collect();
+                // 每一位两个脉冲，最后只有一个复位脉冲
+                assert_eq!(encoded.len(), frame.len() * 16 + 1, "{}", name);
+                assert_eq!(
+                    encoded.last(),
+                    Some(&(PinState::Low, Duration::from_micros(timing.reset_us as u64))),
+                    "{}",
+                    name
+                );
+                assert!(encoded[..encoded.len() - 1].iter().all(|&(_, duration)| duration < Duration::from_micros(5)));
             }
         }
-        
-        // 最后关闭
-        self.set_color(RgbColor::black())?;
-        log::info!("呼吸灯效果完成");
-        Ok(())
     }
-    
-    /// 闪烁效果
-    pub fn blink(&mut self, color: RgbColor, times: u32, on_duration_ms: u32, off_duration_ms: u32) -> Result<(), Box<dyn std::error::Error>> {
-        log::info!("开始闪烁效果: {:?}, 次数: {}", color, times);
-        
-        for _ in 0..times {
-            self.set_color(color)?;
-            FreeRtos::delay_ms(on_duration_ms);
-            self.set_color(RgbColor::black())?;
-            FreeRtos::delay_ms(off_duration_ms);
-        }
-        
-        log::info!("闪烁效果完成");
-        Ok(())
+
+    #[test]
+    fn empty_frame_is_only_reset_latch() {
+        let pulses = test_pulses(&Timing::SK6812);
+        let encoded: Vec<TestPulse> = pulses.sequence(&[]).copied().collect();
+        assert_eq!(encoded, [(PinState::Low, Duration::from_micros(100))]);
+    }
+
+    #[test]
+    fn parses_custom_timing_within_ranges() {
+        assert_eq!("ws2812".parse(), Ok(Timing::WS2812));
+        assert_eq!("300:900:600:600:280".parse(), Ok(Timing::new(300, 900, 600, 600, 280)));
+        // 复位时间不足50µs时灯带无法锁存
+        assert_eq!("300:900:600:600:20".parse::<Timing>(), Err(()));
+        assert_eq!("300:900:600:600".parse::<Timing>(), Err(()));
+    }
+
+    #[test]
+    fn encodes_channel_order() {
+        let color = [RgbColor::new(0x10, 0x20, 0x30)];
+        let frame = |format| encode_frame(&color, format, false, 100);
+        let [red, green, blue] = [0x10, 0x20, 0x30].map(|channel| GAMMA[channel]);
+        assert_eq!(frame(PixelFormat::Grb24), [green, red, blue]);
+        assert_eq!(frame(PixelFormat::Rgb24), [red, green, blue]);
+        assert_eq!(frame(PixelFormat::Grbw32), [green, red, blue, 0]);
+        // 白光提取后三个通道共同的部分交给白光芯片
+        assert_eq!(encode_frame(&color, PixelFormat::Grbw32, true, 100), [green - red, 0, blue - red, red]);
+    }
+
+    #[test]
+    fn parses_hex_with_hash() {
+        assert_eq!("#ff8000".parse(), Ok(RgbColor::new(0xFF, 0x80, 0x00)));
+        assert_eq!("#FF8000".parse(), Ok(RgbColor::new(0xFF, 0x80, 0x00)));
+        assert_eq!("#f80".parse(), Ok(RgbColor::new(0xFF, 0x88, 0x00)));
+    }
+
+    #[test]
+    fn parses_hex_without_hash() {
+        assert_eq!("00ff7f".parse(), Ok(RgbColor::new(0x00, 0xFF, 0x7F)));
+        assert_eq!("0a0".parse(), Ok(RgbColor::new(0x00, 0xAA, 0x00)));
+    }
+
+    #[test]
+    fn parses_named_colors() {
+        assert_eq!("red".parse(), Ok(RgbColor::red()));
+        assert_eq!("Orange".parse(), Ok(RgbColor::new(255, 165, 0)));
+        assert_eq!("OFF".parse(), Ok(RgbColor::black()));
+        assert_eq!("grey".parse(), "gray".parse::<RgbColor>());
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        assert_eq!("#12345g".parse::<RgbColor>(), Err(ParseColorError::InvalidDigit('g')));
+        assert_eq!("#1234".parse::<RgbColor>(), Err(ParseColorError::InvalidLength(4)));
+        assert_eq!("#".parse::<RgbColor>(), Err(ParseColorError::InvalidLength(0)));
+        assert_eq!("abcd".parse::<RgbColor>(), Err(ParseColorError::InvalidLength(4)));
+        assert_eq!("chartreuse".parse::<RgbColor>(), Err(ParseColorError::UnknownName));
+        assert_eq!("".parse::<RgbColor>(), Err(ParseColorError::UnknownName));
     }
 }
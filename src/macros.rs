@@ -0,0 +1,292 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sys::EspError;
+
+use crate::ir::storage::CaptureStorage;
+use crate::ir::transmitter::{IrTransmitter, TransmitError};
+use crate::namespace::Namespace;
+
+/// 宏所在的NVS命名空间，宏名称直接作为键名
+const NAMESPACE: &str = "ir_macros";
+
+/// NVS键名最长15个字符
+pub const MAX_NAME_LEN: usize = 15;
+
+/// 序列化后的最大长度（字节）
+const MAX_ENCODED_LEN: usize = 512;
+
+/// 执行线程栈大小，发射用的脉冲缓冲区放在堆上
+const RUNNER_STACK_SIZE: usize = 4096;
+
+/// 宏操作失败的原因
+#[derive(Debug)]
+pub enum MacroError {
+    /// 名称为空或超过NVS键名长度
+    InvalidName,
+    /// 步骤列表为空或序列化后太长
+    InvalidSteps,
+    /// 已有宏正在执行
+    Busy,
+    Spawn(std::io::Error),
+    Nvs(EspError),
+}
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidName => write!(f, "宏名称必须为1~{}个字符", MAX_NAME_LEN),
+            Self::InvalidSteps => write!(f, "宏步骤为空或超过{}字节", MAX_ENCODED_LEN),
+            Self::Busy => write!(f, "已有宏正在执行"),
+            Self::Spawn(e) => write!(f, "创建宏执行线程失败: {}", e),
+            Self::Nvs(e) => write!(f, "NVS错误: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for MacroError {}
+
+impl From<EspError> for MacroError {
+    fn from(e: EspError) -> Self {
+        Self::Nvs(e)
+    }
+}
+
+/// 宏的一步：发射一个录制，然后等待一段时间
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroStep {
+    pub slot: String,
+    pub delay_ms: u32,
+}
+
+/// 按顺序发射的一组录制
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Macro {
+    pub steps: Vec<MacroStep>,
+}
+
+impl Macro {
+    /// 解析"<槽>[:<延时毫秒>],..."形式的步骤列表
+    pub fn parse(text: &str) -> Option<Self> {
+        let steps = text
+            .split(',')
+            .map(|step| {
+                let (slot, delay_ms) = match step.split_once(':') {
+                    Some((slot, delay_ms)) => (slot, delay_ms.parse().ok()?),
+                    None => (step, 0),
+                };
+                (!slot.is_empty()).then(|| MacroStep {
+                    slot: slot.to_string(),
+                    delay_ms,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self { steps })
+    }
+}
+
+/// 与`parse`的格式相同，也是NVS中保存的格式
+impl fmt::Display for Macro {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, step) in self.steps.iter().enumerate() {
+            if index > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}:{}", step.slot, step.delay_ms)?;
+        }
+        Ok(())
+    }
+}
+
+/// 保存在NVS中的宏
+pub struct MacroStore {
+    nvs: Namespace,
+}
+
+impl MacroStore {
+    pub fn new(partition: &EspDefaultNvsPartition) -> Result<Self, EspError> {
+        Ok(Self {
+            nvs: Namespace::open(partition, NAMESPACE)?,
+        })
+    }
+
+    /// NVS不可用时使用，宏只保留到重启
+    pub fn in_memory() -> Self {
+        Self {
+            nvs: Namespace::in_memory(NAMESPACE),
+        }
+    }
+
+    /// 保存宏，同名的宏会被覆盖
+    pub fn save(&mut self, name: &str, steps: &Macro) -> Result<(), MacroError> {
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(MacroError::InvalidName);
+        }
+        let encoded = steps.to_string();
+        if steps.steps.is_empty() || encoded.len() > MAX_ENCODED_LEN {
+            return Err(MacroError::InvalidSteps);
+        }
+        self.nvs.set_str(name, &encoded)?;
+        Ok(())
+    }
+
+    /// 读取宏，不存在或无法解析时返回None
+    pub fn load(&self, name: &str) -> Result<Option<Macro>, MacroError> {
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(MacroError::InvalidName);
+        }
+        let mut buf = [0u8; MAX_ENCODED_LEN + 1];
+        Ok(self.nvs.get_str(name, &mut buf)?.and_then(Macro::parse))
+    }
+
+    /// 删除宏，宏不存在时返回false
+    pub fn delete(&mut self, name: &str) -> Result<bool, MacroError> {
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            return Err(MacroError::InvalidName);
+        }
+        Ok(self.nvs.remove(name)?)
+    }
+}
+
+/// 宏执行过程中上报给主循环的事件
+#[derive(Debug)]
+pub enum MacroEvent {
+    /// 开始执行第`index`步（从1开始）
+    Step {
+        name: String,
+        index: usize,
+        total: usize,
+        slot: String,
+    },
+    Done {
+        name: String,
+    },
+    /// 某一步失败，后续步骤不再执行
+    Aborted {
+        name: String,
+        index: usize,
+        error: TransmitError,
+    },
+}
+
+impl fmt::Display for MacroEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Step {
+                name,
+                index,
+                total,
+                slot,
+            } => write!(f, "MACRO_STEP: {} {}/{} {}", name, index, total, slot),
+            Self::Done { name } => write!(f, "MACRO_DONE: {}", name),
+            Self::Aborted { name, index, error } => {
+                write!(f, "MACRO_ABORTED: {} step {}: ", name, index)?;
+                match error {
+                    TransmitError::UnknownSlot(slot) => write!(f, "unknown slot {}", slot),
+                    _ => write!(f, "transmit failed"),
+                }
+            }
+        }
+    }
+}
+
+/// 在工作线程中执行宏，蓝牙命令在执行期间仍然可以处理
+///
+/// 同一时间只能执行一个宏。
+pub struct MacroRunner<E> {
+    transmitter: Arc<Mutex<IrTransmitter>>,
+    store: Arc<Mutex<CaptureStorage>>,
+    events: Sender<E>,
+    running: Arc<AtomicBool>,
+}
+
+impl<E: From<MacroEvent> + Send + 'static> MacroRunner<E> {
+    pub fn new(
+        transmitter: Arc<Mutex<IrTransmitter>>,
+        store: Arc<Mutex<CaptureStorage>>,
+        events: Sender<E>,
+    ) -> Self {
+        Self {
+            transmitter,
+            store,
+            events,
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// 开始执行宏，进度通过事件通道上报
+    pub fn run_macro(&self, name: &str, steps: Macro) -> Result<(), MacroError> {
+        if self.running.swap(true, Ordering::AcqRel) {
+            return Err(MacroError::Busy);
+        }
+
+        let name = name.to_string();
+        let transmitter = self.transmitter.clone();
+        let store = self.store.clone();
+        let events = self.events.clone();
+        let running = self.running.clone();
+        let spawned = thread::Builder::new()
+            .name("ir-macro".into())
+            .stack_size(RUNNER_STACK_SIZE)
+            .spawn(move || {
+                let event = execute(&name, &steps, &transmitter, &store, &events);
+                running.store(false, Ordering::Release);
+                // 主循环退出时没有人接收事件，忽略即可
+                let _ = events.send(event.into());
+            });
+
+        spawned.map(|_| ()).map_err(|e| {
+            self.running.store(false, Ordering::Release);
+            MacroError::Spawn(e)
+        })
+    }
+}
+
+/// 依次发射每一步，返回结束事件
+fn execute<E: From<MacroEvent>>(
+    name: &str,
+    steps: &Macro,
+    transmitter: &Mutex<IrTransmitter>,
+    store: &Mutex<CaptureStorage>,
+    events: &Sender<E>,
+) -> MacroEvent {
+    let total = steps.steps.len();
+    for (index, step) in (1..).zip(&steps.steps) {
+        let step_event = MacroEvent::Step {
+            name: name.to_string(),
+            index,
+            total,
+            slot: step.slot.clone(),
+        };
+        let _ = events.send(step_event.into());
+
+        // 与主循环相同，先锁发射器再锁录制，等待下一步期间不持有锁
+        let result = {
+            let mut transmitter = transmitter.lock().unwrap();
+            let mut store = store.lock().unwrap();
+            let result = transmitter.replay(&store, &step.slot);
+            if result.is_ok() {
+                store.record_use(&step.slot);
+            }
+            result
+        };
+        if let Err(error) = result {
+            return MacroEvent::Aborted {
+                name: name.to_string(),
+                index,
+                error,
+            };
+        }
+
+        if index < total {
+            thread::sleep(Duration::from_millis(step.delay_ms as u64));
+        }
+    }
+
+    MacroEvent::Done { name: name.to_string() }
+}
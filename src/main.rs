@@ -1,22 +1,129 @@
-use esp_idf_hal::rmt::{Pulse, RxRmtDriver};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use esp_idf_hal::rmt::RxRmtDriver;
 use esp_idf_hal::rmt::config::ReceiveConfig;
+use esp_idf_svc::bt::ble::gatt::server::ConnectionId;
 use esp_idf_svc::hal::peripherals::Peripherals;
 use esp_idf_svc::hal::delay::FreeRtos;
-use esp_idf_svc::hal::rmt::{config::TransmitConfig, TxRmtDriver};
+use esp_idf_svc::hal::gpio::{AnyIOPin, IOPin};
+use esp_idf_svc::hal::rmt::config::TransmitConfig;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sys::EspError;
 
 mod led;
+mod app;
+mod battery;
 mod bluetooth;
-use led::{Ws2812Led, RgbColor};
-use bluetooth::BluetoothManager;
+mod button;
+mod chunk;
+mod command;
+mod config;
+mod console;
+mod crashlog;
+mod effect;
+mod error;
+mod events;
+mod factory_reset;
+mod health;
+mod http;
+mod ir;
+mod macros;
+mod mqtt;
+mod namespace;
+mod powersave;
+mod rmt;
+mod selfcheck;
+mod settings;
+mod status_led;
+mod telemetry;
+mod text_command;
+mod watchdog;
+mod wifi;
+mod write_policy;
+use led::{
+    HsvColor, ParseColorError, PixelFormat, PowerBudget, Timing, Ws2812Led, RgbColor, BRIGHTNESS_RANGE, CHANNEL_MA_RANGE,
+    MAX_CURRENT_MA_RANGE, MAX_STRIP_LEN, RESET_US_RANGE,
+};
+use app::{AppEvent, Ticker, IDLE_TICK_MS, SLEEP_TICK_MS};
+use battery::{BatteryMonitor, ADC_PINS, DIVIDER_RANGE_PERMILLE};
+use bluetooth::{
+    check_device_name, check_hardware_revision, parse_addr, BluetoothManager, LinkMode, Message, FIRMWARE_REVISION,
+    INDICATION_TIMEOUT_RANGE_MS, MAX_CONNECTIONS, MAX_DEVICE_NAME_LEN, MAX_HARDWARE_REVISION_LEN, MAX_PASSKEY,
+    MAX_WHITELIST,
+};
+use button::{Button, ButtonAction, ButtonEvent, QUICK_SLOT, RECENT_SLOTS};
+use command::{Frame, Opcode, Request, Status};
+use config::{Apply, Config, ConfigError, ConfigKey};
+use console::ConsoleEvent;
+use crashlog::CrashLog;
+use error::Error;
+use effect::{Easing, ALERT_DURATION_MS, BREATHING_PERIOD_MS, FRAME_MS};
+use factory_reset::PendingReset;
+use health::Health;
+use http::{HttpApi, HttpEvent, HttpRequest, HttpResponse};
+use macros::{Macro, MacroError, MacroEvent, MacroRunner, MacroStore};
+use mqtt::{MqttBridge, MqttEvent, MqttSettings};
+use powersave::{IrWake, PowerSave, PowerState};
+use rmt::{RmtAllocator, RmtError, OWNER_IR, OWNER_LED, TX_CHANNELS};
+use selfcheck::{SelfCheck, Subsystem};
+use settings::Settings;
+use status_led::{DeviceState, Notice, StatusLed};
+use text_command::{optional, required, ArgKind, CommandContext, CommandError, CommandRegistry, InvalidArgument};
+use wifi::{WifiLink, WifiState};
+use write_policy::{check_pin, AllowAll, Locked, PIN_LEN};
+use ir::{detect_and_decode, Capture, CaptureEvent, TickRate};
+use ir::analyze::{analyze, DEFAULT_BUCKET_WIDTH_US};
+use ir::archive::{error_record, ArchiveImport, ArchiveWriter, CollisionPolicy, ImportRecord, Resolution};
+use ir::assembler::{CaptureAssembler, DEFAULT_MAX_CAPTURE_PAIRS};
+use ir::broadlink::BroadlinkError;
+use ir::carrier::CarrierMeter;
+#[cfg(feature = "carrier-meter")]
+use ir::carrier::MEASURE_BUFFER_PAIRS;
+use ir::denon::{encode_denon, DenonFramePairer};
+use ir::filter::MAX_MIN_PULSE_US;
+use ir::flipper::FlipperImport;
+use ir::matcher::CodeMatcher;
+use ir::noise::NoiseFilter;
+#[cfg(feature = "tx-power")]
+use ir::power::TxPower;
+use ir::power::{PowerSettings, MAX_WARM_UP_US};
+use ir::pronto::ProntoError;
+use ir::quality;
+use ir::queue::{QueueFull, TransmitEvent, TransmitQueue, TransmitRequest};
+use ir::nec::{encode_nec, encode_nec_ext};
+use ir::rc5::Rc5Session;
+use ir::samsung::{encode_samsung, encode_samsung36};
+use ir::receiver::{IrEvent, RING_BUFFER_PAIRS};
+use ir::repeat::{KeyEvent, RepeatCoalescer};
+use ir::selftest::{test_frame, SelfTest};
+use ir::session::{RecordingSession, SessionEvent};
+use ir::sirc::{encode_sirc, SircBits, SircFrameMerger};
+use ir::metadata::MAX_LABEL_LEN;
+use ir::format::FormatError;
+use ir::storage::{canonical_path, check_name, split_path, CaptureStorage, StorageError, MAX_NAME_LEN, MAX_REMOTE_LEN};
+use ir::timing::{protocol_timing, ProtocolTiming};
+use ir::transmitter::{
+    validate_raw, Carrier, IrTransmitter, TransmitError, DEFAULT_CARRIER_HZ, MAX_CARRIER_HZ, MAX_RAW_DURATIONS,
+    MAX_RAW_DURATION_US, MIN_CARRIER_HZ,
+};
+
+/// timing命令允许的最大帧周期和帧间空闲（毫秒）
+const MAX_TIMING_MS: u32 = 1000;
 
+/// list命令每页列出的录制数量
+const LIST_PAGE_SIZE: usize = 10;
 
 fn main() {
     // It is necessary to call this function once. Otherwise some patches to the runtime
     // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
     esp_idf_svc::sys::link_patches();
 
-    // Bind the log crate to the ESP Logging facilities
-    esp_idf_svc::log::EspLogger::initialize_default();
+    // Bind the log crate to the ESP Logging facilities, and keep a copy for the BLE telemetry characteristic
+    telemetry::init();
 
     log::info!("ESP32-S3 RGB LED 控制程序启动!");
 
@@ -24,170 +131,3619 @@ fn main() {
     let peripherals = Peripherals::take().unwrap();
     
     // 创建系统事件循环
-    let _sys_loop = esp_idf_svc::eventloop::EspSystemEventLoop::take().unwrap();
+    let sys_loop = esp_idf_svc::eventloop::EspSystemEventLoop::take().unwrap();
     
-    // 创建NVS分区
-    let nvs = esp_idf_svc::nvs::EspDefaultNvsPartition::take().unwrap();
+    // 启动自检：失败的子系统不中止启动，设备缺少该功能继续运行
+    let mut self_check = SelfCheck::default();
 
-    // 初始化蓝牙驱动
-    let bt = std::sync::Arc::new(esp_idf_svc::bt::BtDriver::new(peripherals.modem, Some(nvs.clone())).unwrap());
-    
-    // 创建GAP和GATTS
-    let gap = std::sync::Arc::new(esp_idf_svc::bt::ble::gap::EspBleGap::new(bt.clone()).unwrap());
-    let gatts = std::sync::Arc::new(esp_idf_svc::bt::ble::gatt::server::EspGatts::new(bt.clone()).unwrap());
+    // 创建NVS分区，不可用时设置、录制和宏只保存在内存中，重启后丢失
+    let nvs = self_check.check(Subsystem::Nvs, esp_idf_svc::nvs::EspDefaultNvsPartition::take());
 
-    // 初始化蓝牙管理器
-    let bluetooth_manager = BluetoothManager::new(gap, gatts);
-    match bluetooth_manager.initialize() {
-        Ok(_) => {
-            log::info!("BLE GATT服务器初始化成功!");
-            bluetooth_manager.start_data_receiver();
+    // panic时点亮红色LED并把消息保存到NVS，重启后用crashlog命令读取
+    if let Some(nvs) = &nvs {
+        if let Err(e) = crashlog::install_panic_hook(nvs) {
+            log::error!("安装panic钩子失败: {:?}", e);
         }
-        Err(e) => {
-            log::error!("BLE初始化失败: {:?}", e);
+    }
+    let mut crash_log = open_nvs(&mut self_check, nvs.as_ref(), CrashLog::new, CrashLog::in_memory);
+    if let Err(e) = crash_log.record_reset_reason() {
+        log::error!("保存崩溃记录失败: {:?}", e);
+    }
+
+    // 读取保存在NVS中的设置
+    let settings = open_nvs(&mut self_check, nvs.as_ref(), Settings::new, Settings::in_memory);
+    self_check.check(Subsystem::Nvs, settings.probe());
+    telemetry::set_level(settings.log_level());
+    // 启动次数保存在NVS中，运行状况中报告
+    let boots = settings.boot_count().wrapping_add(1);
+    if let Err(e) = settings.set_boot_count(boots) {
+        log::error!("保存启动次数失败: {:?}", e);
+    }
+    // 引脚、RMT和蓝牙等配置，检查过的值才会使用
+    let config = Config::load(&settings);
+
+    // Wi-Fi和蓝牙共用射频，拆分后分别创建驱动
+    let (wifi_modem, bt_modem) = peripherals.modem.split();
+
+    // 初始化蓝牙驱动，创建GAP和GATTS
+    let ble = esp_idf_svc::bt::BtDriver::new(bt_modem, nvs.clone()).and_then(|bt| {
+        let bt = std::sync::Arc::new(bt);
+        let gap = esp_idf_svc::bt::ble::gap::EspBleGap::new(bt.clone())?;
+        let gatts = esp_idf_svc::bt::ble::gatt::server::EspGatts::new(bt)?;
+        Ok((std::sync::Arc::new(gap), std::sync::Arc::new(gatts)))
+    });
+
+    // 蓝牙、红外接收、发射和定时器都把事件发到这个通道，由主循环依次处理
+    let (app_event_tx, app_events) = mpsc::channel::<AppEvent>();
+
+    // 初始化蓝牙管理器，蓝牙驱动不可用时没有客户端，串口控制台照常可用
+    let bluetooth_manager = match self_check.check(Subsystem::Ble, ble) {
+        Some((gap, gatts)) => BluetoothManager::new(gap, gatts),
+        None => BluetoothManager::unavailable(),
+    };
+    bluetooth_manager.set_indication_timeout(Duration::from_millis(config.indication_timeout_ms as u64));
+    log::info!("蓝牙设备名称: {}", config.device_name);
+    if let Err(e) = bluetooth_manager.set_identity(&config.device_name, settings.user_id()) {
+        log::error!("设置蓝牙设备名称失败: {:?}", e);
+    }
+    let hardware_revision = settings.hardware_revision();
+    log::info!("固件版本: {}, 硬件版本: {}", FIRMWARE_REVISION, hardware_revision);
+    if let Err(e) = bluetooth_manager.set_hardware_revision(&hardware_revision) {
+        log::error!("设置硬件版本失败: {:?}", e);
+    }
+    if settings.lock_pin().is_some() {
+        log::info!("蓝牙写入已锁定");
+        bluetooth_manager.set_write_policy(Arc::new(Locked::default()));
+    }
+    if settings.whitelist_enabled() {
+        log::info!("蓝牙连接白名单已打开");
+    }
+    if let Err(e) = bluetooth_manager.set_whitelist(settings.whitelist_enabled(), &settings.whitelist()) {
+        log::error!("设置蓝牙连接白名单失败: {:?}", e);
+    }
+    if let Err(e) = bluetooth_manager.set_battery_enabled(settings.battery_enabled()) {
+        log::error!("设置电池服务失败: {:?}", e);
+    }
+    if self_check.passed(Subsystem::Ble) {
+        if let Err(e) = bluetooth_manager.set_tx_power(config.ble_tx_power_dbm) {
+            log::error!("设置蓝牙发射功率失败: {:?}", e);
+        }
+        if let Err(e) = bluetooth_manager.configure_security(settings.passkey()) {
+            log::error!("设置蓝牙配对参数失败: {:?}", e);
+        }
+        match bluetooth_manager.initialize() {
+            Ok(_) => {
+                log::info!("BLE GATT服务器初始化成功!");
+                let message_tx = app_event_tx.clone();
+                bluetooth_manager.start_data_receiver(move |message| {
+                    let _ = message_tx.send(message.into());
+                });
+                if let Err(e) = telemetry::spawn_sender(bluetooth_manager.clone()) {
+                    log::error!("启动蓝牙日志线程失败: {:?}", e);
+                }
+            }
+            Err(e) => {
+                log::error!("BLE初始化失败: {:?}", e);
+                self_check.fail(Subsystem::Ble);
+            }
+        }
+    }
+
+    // 保存了SSID时才初始化Wi-Fi，在后台连接并通过SNTP同步时间
+    let wifi = if config.wifi_ssid.is_empty() {
+        None
+    } else {
+        match WifiLink::start(wifi_modem, sys_loop.clone(), nvs.clone(), &config.wifi_ssid, &config.wifi_password) {
+            Ok(link) => Some(link),
+            Err(e) => {
+                log::error!("Wi-Fi初始化失败: {:?}", e);
+                None
+            }
+        }
+    };
+
+    // 有Wi-Fi并且设置了代理地址时连接MQTT，发布捕获并接收发射请求
+    let mqtt = if wifi.is_none() || config.mqtt_url.is_empty() {
+        None
+    } else {
+        let settings = MqttSettings {
+            url: config.mqtt_url.clone(),
+            username: config.mqtt_username.clone(),
+            password: config.mqtt_password.clone(),
+            device: if config.mqtt_device.is_empty() {
+                mqtt::default_device(&config.device_name)
+            } else {
+                config.mqtt_device.clone()
+            },
+        };
+        log::info!("MQTT代理: {}, 设备: {}", settings.url, settings.device);
+        match MqttBridge::spawn(settings, app_event_tx.clone()) {
+            Ok(bridge) => Some(bridge),
+            Err(e) => {
+                log::error!("启动MQTT线程失败: {:?}", e);
+                None
+            }
+        }
+    };
+
+    // 有Wi-Fi并且设置了访问令牌时启动HTTP服务，请求交给主循环处理
+    let _http = if wifi.is_none() || config.http_token.is_empty() {
+        None
+    } else {
+        match HttpApi::start(config.http_token.clone(), config.http_max_body as usize, app_event_tx.clone()) {
+            Ok(api) => {
+                log::info!("HTTP服务已启动");
+                Some(api)
+            }
+            Err(e) => {
+                log::error!("启动HTTP服务失败: {:?}", e);
+                None
+            }
         }
+    };
+
+    // UART0上的串口控制台，命令和回复与蓝牙文本命令相同
+    if [config.led_pin, config.ir_tx_pin, config.ir_rx_pin]
+        .iter()
+        .any(|pin| console::CONSOLE_PINS.contains(pin))
+    {
+        log::warn!("UART0的引脚已配置给其他功能，不启动串口控制台");
+    } else if let Err(e) = console::spawn(app_event_tx.clone()) {
+        log::error!("启动串口控制台失败: {:?}", e);
     }
+
+    // 电池电压通过分压电路接在ADC1的引脚上，引脚和分压比可通过蓝牙修改
+    let battery = BatteryMonitor::spawn(
+        settings.battery_enabled(),
+        settings.battery_pin(),
+        settings.battery_divider_permille(),
+        bluetooth_manager.clone(),
+    );
+    let battery = match self_check.check(Subsystem::Battery, battery) {
+        Some(battery) => battery,
+        None => BatteryMonitor::unavailable(
+            settings.battery_enabled(),
+            settings.battery_pin(),
+            settings.battery_divider_permille(),
+        ),
+    };
     
-    // ESP32-S3 RGB LED 引脚配置 - 使用GPIO48
-    // 根据ESP32-S3硬件，RGB LED连接在GPIO48
-    let led_pin = peripherals.pins.gpio48;
+    // ESP32-S3 RGB LED 引脚配置 - 默认使用GPIO48
+    // 根据ESP32-S3硬件，RGB LED连接在GPIO48；引脚已由Config检查，不与其他功能冲突
+    log::info!("LED引脚: GPIO{}", config.led_pin);
+    let led_pin = unsafe { AnyIOPin::new(config.led_pin as i32) };
     
+    // 发射通道由分配器按配置的内存块数量分给LED和红外发射，放不下时退回每个通道1个内存块
+    let mut rmt_allocator = RmtAllocator::new(
+        peripherals.rmt.channel0,
+        peripherals.rmt.channel1,
+        peripherals.rmt.channel2,
+        peripherals.rmt.channel3,
+    );
+    let (led_blocks, ir_blocks) = match RmtAllocator::check(&[
+        (OWNER_LED, config.rmt_led_blocks),
+        (OWNER_IR, config.rmt_ir_blocks),
+    ]) {
+        Ok(_) => (config.rmt_led_blocks, config.rmt_ir_blocks),
+        Err(e) => {
+            log::error!("{}，改用每个通道1个内存块", e);
+            (1, 1)
+        }
+    };
+
     // 配置RMT传输
-    let config = TransmitConfig::new()
-        .clock_divider(1);  // 时钟分频器 - 高分辨率
+    let led_config = TransmitConfig::new()
+        .clock_divider(config.led_clock_divider)  // 时钟分频器 - 默认不分频，高分辨率
+        .mem_block_num(led_blocks);
     
-    // 创建RMT传输驱动
-    let rmt = TxRmtDriver::new(
-        peripherals.rmt.channel0,
-        led_pin,
-        &config,
-    ).unwrap();
+    // 创建RMT传输驱动，同时确认计数时钟可用；失败时LED只计算颜色不发送
+    let rmt = rmt_allocator.tx_driver(OWNER_LED, led_pin, &led_config).and_then(|rmt| {
+        rmt.counter_clock()?;
+        Ok(rmt)
+    });
     
     // 创建LED控制器
-    let mut led = Ws2812Led::new(rmt);
+    let mut led = match self_check.check(Subsystem::LedTx, rmt) {
+        Some(rmt) => Ws2812Led::new(rmt, settings.led_format(), config.led_timing),
+        None => Ws2812Led::unavailable(settings.led_format(), config.led_timing),
+    }
+    .with_gate(rmt_allocator.gate());
+    // 在发送第一帧之前设置电流上限
+    led.strip_mut().set_power(settings.led_power());
+    if settings.led_auto_white() {
+        if let Err(e) = led.set_format(settings.led_format(), true) {
+            log::warn!("设置LED像素格式失败: {:?}", e);
+        }
+    }
+    if let Err(e) = led.set_brightness(config.led_brightness) {
+        log::warn!("设置LED亮度失败: {:?}", e);
+    }
+    if let Err(e) = led.set_len(settings.led_count()) {
+        log::warn!("设置灯带像素数量失败: {:?}", e);
+    }
     
     // 确保所有LED初始状态为关闭
     log::info!("初始化LED状态 - 确保所有LED关闭");
-    led.set_color(RgbColor::black()).unwrap();
+    if let Err(e) = led.set_color(RgbColor::black()) {
+        log::warn!("关闭LED失败: {:?}", e);
+    }
     
+    // 红外发射管接在GPIO17上，使用LED之后的通道，和LED的发射互斥
+    // 创建失败时发射命令回复transmitter unavailable
+    let ir_driver = IrTransmitter::config(ir_blocks)
+        .map_err(RmtError::from)
+        .and_then(|ir_config| rmt_allocator.tx_driver(OWNER_IR, unsafe { AnyIOPin::new(config.ir_tx_pin as i32) }, &ir_config));
+    let ir_transmitter = match self_check.check(Subsystem::IrTx, ir_driver) {
+        Some(driver) => IrTransmitter::new(driver),
+        None => IrTransmitter::unavailable(),
+    }
+    .with_gate(rmt_allocator.gate());
 
-    // 红外接收配置
-    let ir_recv_pin = peripherals.pins.gpio21;
+    // 发射功率保存在NVS中，发射管使能引脚接在GPIO18上时由LEDC PWM控制，LEDC不可用时全功率发射
+    let tx_power = Arc::new(PowerSettings::new(settings.tx_power(), settings.warm_up_us()));
+    #[cfg(feature = "tx-power")]
+    let ir_transmitter = {
+        let power = TxPower::new(peripherals.ledc.channel0, peripherals.ledc.timer0, peripherals.pins.gpio18, tx_power.clone());
+        log::info!("发射功率: {}%, 预热{}µs", tx_power.tx_power(), tx_power.warm_up_us());
+        match self_check.check(Subsystem::TxPower, power) {
+            Some(power) => ir_transmitter.with_power(power),
+            None => ir_transmitter,
+        }
+    };
 
+    // 主循环和宏执行线程共用发射器
+    let transmitter = Arc::new(Mutex::new(ir_transmitter));
+    log::info!("红外发射器初始化完成: GPIO17, 38kHz载波");
+
+    // BOOT按键（GPIO0），单击、双击、长按和一直按住的操作可以通过配置修改
+    let button = self_check.check(Subsystem::Button, Button::new(peripherals.pins.gpio0.downgrade()));
+
+    // 红外接收配置
+    let ir_recv_pin = unsafe { AnyIOPin::new(config.ir_rx_pin as i32) };
 
-    
     let receive_config = ReceiveConfig::new()
-        .idle_threshold(10000u16);  // 空闲阈值 - 10ms空闲后认为信号结束
+        .mem_block_num(2)  // 占用通道4、5的内存块，减少长帧的分段；通道6留给载波测量
+        .filter_en(true)
+        .filter_ticks_thresh(255);  // 硬件滤波 - 按APB时钟计数，最多只能滤掉约3µs的尖峰
         // .carrier(Some(CarrierConfig::new().carrier_level(PinState::High)));
     
-    const STACK_SIZE: usize = 250;
+    // 解码器按实际分频后的tick时长换算脉宽
+    let ir_tick = TickRate::from_clock_divider(receive_config.clock_divider);
+    // 空闲阈值 - 默认10ms空闲后认为信号结束，可通过蓝牙修改
+    let receive_config = receive_config.idle_threshold(ir_tick.us_to_ticks(config.idle_threshold_us) as u16);
+
     // 创建RMT接收驱动
-    let mut ir_receiver = RxRmtDriver::new(
+    let ir_receiver = RxRmtDriver::new(
         peripherals.rmt.channel4,
         ir_recv_pin,
         &receive_config,
-        STACK_SIZE,  // 缓冲区大小
-    ).unwrap();
+        RING_BUFFER_PAIRS,  // 环形缓冲区大小
+    )
+    .and_then(|mut receiver| receiver.start().map(|()| receiver));
+    // 创建或启动失败时不启动接收线程，主循环收不到捕获
+    let ir_receiver = self_check.check(Subsystem::IrRx, ir_receiver);
     
     log::info!("红外接收器初始化完成，开始监听...");
-    log::info!("IR接收器引脚: GPIO44");
+    log::info!("IR接收器引脚: GPIO{}", config.ir_rx_pin);
     log::info!("RMT通道: Channel4");
-    log::info!("时钟分频: 80, 空闲阈值: 10000, 滤波器: 启用");
+    log::info!("时钟分频: {}, 空闲阈值: {}µs, 滤波器: 启用", receive_config.clock_divider, config.idle_threshold_us);
     
-    // 启动RMT接收
-    ir_receiver.start().unwrap();
-    log::info!("RMT接收已启动");
+    // 载波测量需要一个未解调的接收管，接在GPIO14上，使用通道6
+    #[cfg(feature = "carrier-meter")]
+    let carrier_meter = {
+        let config = ReceiveConfig::new()
+            .clock_divider(1)  // 不分频 - 12.5ns分辨率
+            .mem_block_num(2)
+            .idle_threshold(8000u16)  // 100µs没有跳变即认为一个载波突发结束
+            .filter_ticks_thresh(10);
+        let receiver = RxRmtDriver::new(
+            peripherals.rmt.channel6,
+            peripherals.pins.gpio14,
+            &config,
+            MEASURE_BUFFER_PAIRS,
+        );
+        // 创建失败时carrier命令回复没有未解调的接收管
+        match self_check.check(Subsystem::Carrier, receiver) {
+            Some(receiver) => CarrierMeter::new(receiver, TickRate::from_clock_divider(config.clock_divider)),
+            None => CarrierMeter::unavailable(),
+        }
+    };
+    #[cfg(not(feature = "carrier-meter"))]
+    let carrier_meter = CarrierMeter::unavailable();
+
+    // 在独立线程中接收红外信号，主循环通过通道获取捕获结果
+    // 更长的毛刺由接收线程做软件滤波，阈值可通过蓝牙修改
+    let min_pulse_us = Arc::new(AtomicU32::new(settings.min_pulse_us()));
+    log::info!("软件毛刺滤波阈值: {}µs", min_pulse_us.load(Ordering::Relaxed));
+
+    // NEC严格模式会丢弃地址字节不互为反码的扩展格式帧
+    ir::nec::set_strict(settings.nec_strict());
+
+    // 丢弃日光灯等干扰产生的短捕获，阈值可通过蓝牙修改
+    let noise = Arc::new(NoiseFilter::new(settings.min_pulses(), settings.min_header_us()));
+    log::info!("噪声过滤: 最少{}个脉冲, 引导mark至少{}µs", noise.min_pulses(), noise.min_header_us());
+
+    // 帧末空闲阈值由接收线程在修改后重新设置
+    let idle_threshold_us = Arc::new(AtomicU32::new(config.idle_threshold_us));
+
+    let assembler = CaptureAssembler::new(ir_tick, DEFAULT_MAX_CAPTURE_PAIRS);
+    // 省电模式中红外接收引脚作为唤醒源，sdkconfig没有打开电源管理时不可用
+    let mut ir_wake = None;
+    if let Some(receiver) = ir_receiver {
+        ir_wake = match IrWake::new(config.ir_rx_pin) {
+            Ok(wake) => Some(wake),
+            Err(e) => {
+                log::warn!("红外唤醒源不可用: {:?}", e);
+                None
+            }
+        };
+        let spawned = ir::receiver::spawn_receiver(
+            receiver,
+            assembler,
+            min_pulse_us.clone(),
+            idle_threshold_us.clone(),
+            noise.clone(),
+            ir_wake.clone(),
+            app_event_tx.clone(),
+        );
+        self_check.check(Subsystem::IrRx, spawned);
+    }
+    // 没有连接和红外活动一段时间后进入省电模式
+    let power_save = PowerSave::new(ir_wake, config.power_save, config.sleep_idle_s);
     
-    // 主循环 - 持续监听红外信号和蓝牙数据
-    let mut connection_check_counter = 0;
-    loop {
-        // 检查蓝牙连接状态
-        if bluetooth_manager.is_connected() {
-            if connection_check_counter % 100 == 0 {  // 每10秒打印一次
-                log::info!("蓝牙已连接");
+    // SIRC遥控器每次按键发送三帧，合并后再上报
+    let sirc_merger = SircFrameMerger::new();
+    // Denon/Sharp每次按键发送一对互为反码的帧，校验后再上报
+    let denon_pairer = DenonFramePairer::new();
+
+    // 按住按键时的重复帧合并为按下、按住、松开事件，松开超时由空闲阈值推算
+    let keys = RepeatCoalescer::new(Duration::from_micros(config.idle_threshold_us as u64));
+
+    // 已学习的参考码，以及匹配后要设置的LED颜色
+    let mut matcher = CodeMatcher::default();
+    let match_colors: HashMap<String, RgbColor> = HashMap::new();
+    // 学习时同时把归一化后的录制保存到NVS，供play命令和宏重放
+    let mut store = open_nvs(&mut self_check, nvs.as_ref(), CaptureStorage::new, CaptureStorage::in_memory);
+    // 开启淘汰后，空间不足时删除最久没有使用、没有标签且未受保护的录制
+    store.set_eviction(settings.lru_evict());
+    // 保存过的录制重启后重新作为参考码
+    let slots = store.list();
+    for slot in &slots {
+        if let Some(capture) = store.load(&slot.name) {
+            matcher.insert(&slot.name, capture.durations());
+        }
+    }
+    log::info!("已从NVS加载{}个录制", slots.len());
+    let store = Arc::new(Mutex::new(store));
+    // RC5每次按键翻转一次翻转位
+    let rc5_session = Rc5Session::new();
+
+    // 蓝牙命令的发射请求排队后由发射线程依次处理，完成后上报TX_DONE
+    let transmit_queue = TransmitQueue::spawn(transmitter.clone(), store.clone(), app_event_tx.clone());
+    let transmit_queue = self_check
+        .check(Subsystem::IrTx, transmit_queue)
+        .unwrap_or_else(TransmitQueue::unavailable);
+    // 重放请求的编号，以及是否正在重放
+    let play_tickets: HashSet<u32> = HashSet::new();
+    // 双击下一次重放的最近录制
+    let recent_index = 0usize;
+    let playing = false;
+    // 正在进行的重复发送，蓝牙断开时停止
+    let repeat_ticket: Option<u32> = None;
+    // 正在进行的回环自检，期间的捕获只交给自检判定
+    let selftest: Option<SelfTest> = None;
+
+    // 宏保存在NVS中，在独立线程中执行，进度通过通道交给主循环上报
+    let macro_store = open_nvs(&mut self_check, nvs.as_ref(), MacroStore::new, MacroStore::in_memory);
+    let macro_runner = MacroRunner::new(transmitter.clone(), store.clone(), app_event_tx.clone());
+
+    // 录制会话，由蓝牙record命令或长按按键开始
+    let mut session = RecordingSession::default();
+    // record:<名称>开始的录制，完成后直接保存到该名称；第二项表示跳过重复检查
+    let record_slot: Option<(String, bool)> = None;
+    // 正在接收的导入归档和发起导入的连接，期间该连接发来的数据不作为命令解析
+    let import: Option<(ConnectionId, ArchiveImport)> = None;
+    // 正在接收的Flipper .ir文件和发起导入的连接
+    let flipper_import: Option<(ConnectionId, FlipperImport)> = None;
+    // 等待客户端发回随机数确认的恢复出厂设置
+    let pending_reset: Option<PendingReset> = None;
+    // 蓝牙文本命令和串口控制台共用的命令注册表
+    let commands = Rc::new(command_registry());
+    session.set_multi_frame(settings.multi_frame());
+    session.set_quality_threshold(settings.quality_threshold());
+    // 最近一次捕获，供analyze命令诊断
+    let last_capture: Option<Capture> = None;
+    // 启动以来收到的红外捕获数量
+    let ir_captures: u32 = 0;
+    // LED按设备状态显示不同的样式
+    let status_led = StatusLed::new(&led);
+    // LED效果按距离启动的毫秒数推进
+    let started = Instant::now();
+
+    // 定时器线程定期发送Tick并轮询按键
+    let ticker = Ticker::spawn(button, app_event_tx);
+    let ticker = self_check.check(Subsystem::Ticker, ticker).unwrap_or_else(Ticker::unavailable);
+
+    // 自检结果写入日志并用LED显示，第一个蓝牙客户端连接时再发送一次
+    let self_check_report = self_check.report();
+    log::info!("{}", self_check_report);
+    led.self_check_result(self_check.first_failure().map(|subsystem| subsystem.number()));
+    let self_check_sent = false;
+
+    // 主循环卡住时由任务看门狗重启，Tick保证没有其他事件时也会定期喂狗
+    if let Err(e) = watchdog::configure(watchdog::TIMEOUT) {
+        log::error!("设置任务看门狗失败: {:?}", e);
+    }
+    let watchdog = if !self_check.passed(Subsystem::Ticker) {
+        // 定时器线程没有运行时空闲的主循环无法喂狗，不订阅
+        None
+    } else {
+        match watchdog::Subscription::current() {
+            Ok(subscription) => Some(subscription),
+            Err(e) => {
+                log::error!("主循环订阅任务看门狗失败: {:?}", e);
+                None
             }
-            
-            // 处理接收到的蓝牙数据
-            let bluetooth_data = bluetooth_manager.get_received_data();
-            if !bluetooth_data.is_empty() {
-                log::info!("接收到蓝牙数据: {:?}", bluetooth_data);
-                
-                // 将蓝牙数据转换为字符串并记录
-                if let Ok(data_str) = String::from_utf8(bluetooth_data.clone()) {
-                    log::info!("蓝牙数据内容: {}", data_str);
-                    
-                    // 根据接收到的数据控制LED
-                    match data_str.trim() {
-                        "red" => {
-                            log::info!("设置LED为红色");
-                            led.set_color(RgbColor::red()).unwrap();
-                        }
-                        "green" => {
-                            log::info!("设置LED为绿色");
-                            led.set_color(RgbColor::green()).unwrap();
-                        }
-                        "blue" => {
-                            log::info!("设置LED为蓝色");
-                            led.set_color(RgbColor::blue()).unwrap();
-                        }
-                        "off" => {
-                            log::info!("关闭LED");
-                            led.set_color(RgbColor::black()).unwrap();
-                        }
-                        _ => {
-                            log::info!("未知的LED命令: {}", data_str);
+        }
+    };
+
+    // 主循环 - 按事件处理蓝牙数据、红外信号和按键，没有事件时阻塞等待
+    let mut app = App {
+        led,
+        status_led,
+        started,
+        settings,
+        config,
+        bluetooth_manager,
+        store,
+        matcher,
+        match_colors,
+        session,
+        record_slot,
+        last_capture,
+        ir_captures,
+        boots,
+        transmit_queue,
+        play_tickets,
+        recent_index,
+        playing,
+        repeat_ticket,
+        selftest,
+        rc5_session,
+        macro_store,
+        macro_runner,
+        import,
+        flipper_import,
+        pending_reset,
+        commands,
+        sirc_merger,
+        denon_pairer,
+        keys,
+        power_save,
+        ticker,
+        watchdog,
+        wifi,
+        mqtt,
+        _http,
+        battery,
+        rmt_allocator,
+        carrier_meter,
+        tx_power,
+        min_pulse_us,
+        idle_threshold_us,
+        noise,
+        crash_log,
+        self_check_report,
+        self_check_sent,
+        connection_logged_at: None,
+        health_logged_at: Instant::now(),
+    };
+    for event in app_events {
+        app.handle(event);
+    }
+    log::error!("事件通道已关闭，主循环退出");
+}
+
+/// 主循环的状态，每种事件由一个方法处理
+struct App {
+    led: Ws2812Led,
+    /// LED按设备状态显示不同的样式
+    status_led: StatusLed,
+    /// LED效果按距离启动的毫秒数推进
+    started: Instant,
+    settings: Settings,
+    config: Config,
+    bluetooth_manager: BluetoothManager,
+    store: Arc<Mutex<CaptureStorage>>,
+    /// 已学习的参考码，以及匹配后要设置的LED颜色
+    matcher: CodeMatcher,
+    match_colors: HashMap<String, RgbColor>,
+    session: RecordingSession,
+    /// record:<名称>开始的录制，完成后直接保存到该名称；第二项表示跳过重复检查
+    record_slot: Option<(String, bool)>,
+    /// 最近一次捕获，供analyze命令诊断
+    last_capture: Option<Capture>,
+    /// 启动以来收到的红外捕获数量
+    ir_captures: u32,
+    boots: u32,
+    transmit_queue: TransmitQueue,
+    /// 重放请求的编号，以及是否正在重放
+    play_tickets: HashSet<u32>,
+    /// 双击下一次重放的最近录制
+    recent_index: usize,
+    playing: bool,
+    /// 正在进行的重复发送，蓝牙断开时停止
+    repeat_ticket: Option<u32>,
+    /// 正在进行的回环自检，期间的捕获只交给自检判定
+    selftest: Option<SelfTest>,
+    rc5_session: Rc5Session,
+    macro_store: MacroStore,
+    macro_runner: MacroRunner<AppEvent>,
+    /// 正在接收的导入归档和发起导入的连接，期间该连接发来的数据不作为命令解析
+    import: Option<(ConnectionId, ArchiveImport)>,
+    /// 正在接收的Flipper .ir文件和发起导入的连接，同样只接收该连接的数据
+    flipper_import: Option<(ConnectionId, FlipperImport)>,
+    /// 等待客户端发回随机数确认的恢复出厂设置
+    pending_reset: Option<PendingReset>,
+    /// 处理函数借用App，分发时先克隆一份引用
+    commands: Rc<CommandRegistry>,
+    sirc_merger: SircFrameMerger,
+    denon_pairer: DenonFramePairer,
+    keys: RepeatCoalescer,
+    power_save: PowerSave,
+    ticker: Ticker,
+    watchdog: Option<watchdog::Subscription>,
+    wifi: Option<WifiLink>,
+    mqtt: Option<MqttBridge>,
+    /// 只需要保持运行，请求通过事件通道交给主循环
+    _http: Option<HttpApi>,
+    battery: BatteryMonitor,
+    rmt_allocator: RmtAllocator,
+    carrier_meter: CarrierMeter,
+    tx_power: Arc<PowerSettings>,
+    /// 接收线程读取的毛刺滤波阈值、帧末空闲阈值和噪声过滤阈值
+    min_pulse_us: Arc<AtomicU32>,
+    idle_threshold_us: Arc<AtomicU32>,
+    noise: Arc<NoiseFilter>,
+    crash_log: CrashLog,
+    self_check_report: String,
+    /// 自检结果在第一个蓝牙客户端连接时再发送一次
+    self_check_sent: bool,
+    /// 连接状态和运行状况日志上次输出的时间
+    connection_logged_at: Option<Instant>,
+    health_logged_at: Instant,
+}
+
+impl App {
+    /// 处理一个事件，之后按设备状态更新LED并调整Tick间隔
+    fn handle(&mut self, event: AppEvent) {
+        let now = Instant::now();
+        // 主循环卡住时由任务看门狗重启，Tick保证没有其他事件时也会定期喂狗
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.feed();
+        }
+        // 除了Tick，其他事件都算作活动
+        if !matches!(event, AppEvent::Tick) {
+            if let Some(state) = self.power_save.activity(now) {
+                apply_power_state(&self.bluetooth_manager, &self.ticker, state);
+            }
+        }
+
+        // 更新客户端读取IND特征时返回的状态
+        self.bluetooth_manager.set_status(self.matcher.reference_count(), self.session.state().code());
+
+        match event {
+            AppEvent::BleMessage(message) => self.on_ble_message(message, now),
+            AppEvent::Console(event) => self.on_console(event, now),
+            AppEvent::Ir(event) => self.on_ir(event, now),
+            AppEvent::Transmit(event) => self.on_transmit(event, now),
+            AppEvent::Macro(event) => self.on_macro(event),
+            AppEvent::Mqtt(event) => self.on_mqtt(event),
+            AppEvent::Http(event) => self.on_http(event),
+            AppEvent::Button(event) => self.on_button(event, now),
+            AppEvent::Tick => self.on_tick(now),
+        }
+
+        // 按设备状态切换LED样式
+        let state = if self.session.is_armed() {
+            DeviceState::Recording
+        } else if self.playing {
+            DeviceState::Transmitting
+        } else if self.import.is_some() || self.flipper_import.is_some() {
+            DeviceState::Transferring
+        } else if self.bluetooth_manager.is_connected() {
+            DeviceState::Connected
+        } else if self.power_save.state() == PowerState::Sleep {
+            DeviceState::Sleeping
+        } else {
+            DeviceState::Advertising
+        };
+        self.status_led.set_state(state);
+        if let Err(e) = self.status_led.update(&mut self.led, now) {
+            log::error!("设置LED状态失败: {:?}", e);
+        }
+        if let Err(e) = self.led.tick(now.duration_since(self.started).as_millis() as u64) {
+            log::error!("设置LED颜色失败: {:?}", e);
+        }
+
+        // 没有事件时等到下一个Tick，LED效果运行时按帧间隔推进，省电模式中拉长间隔
+        self.ticker.set_period(if self.led.effect_running() {
+            FRAME_MS
+        } else if self.power_save.state() == PowerState::Sleep {
+            SLEEP_TICK_MS
+        } else {
+            IDLE_TICK_MS
+        });
+    }
+
+    /// 客户端写入recv特征的一条消息，命令的回复只发给发出命令的客户端
+    fn on_ble_message(&mut self, message: Message, now: Instant) {
+        log::info!("接收到蓝牙数据: conn_id={}, {:?}", message.conn_id, message.data);
+        let bluetooth_manager = self.bluetooth_manager.for_peer(message.conn_id);
+        self.on_data(&message.data, Some(message.conn_id), &bluetooth_manager, now);
+    }
+
+    /// 串口控制台的一行与蓝牙文本命令使用同一个解析，conn_id为None
+    fn on_console(&mut self, event: ConsoleEvent, now: Instant) {
+        let ConsoleEvent::Line(line) = event;
+        log::info!("接收到控制台命令: {}", line);
+        let bluetooth_manager = self.bluetooth_manager.for_console();
+        self.on_data(line.as_bytes(), None, &bluetooth_manager, now);
+    }
+
+    /// 导入期间的归档数据、结构化命令帧或文本命令，回复只发给`bluetooth_manager`对应的接收方
+    fn on_data(&mut self, data: &[u8], conn_id: Option<ConnectionId>, bluetooth_manager: &BluetoothManager, now: Instant) {
+        // 导入期间发起导入的客户端发来的是二进制归档，其他客户端的命令照常处理
+        if let Some((conn_id, archive)) = self.import.as_mut().filter(|(importer, _)| Some(*importer) == conn_id) {
+            let conn_id = *conn_id;
+            let records = archive.push(data, now);
+            if import_records(archive, records, &self.store, &mut self.matcher, bluetooth_manager) {
+                self.import = None;
+                bluetooth_manager.set_link_mode(conn_id, LinkMode::Balanced);
+            }
+        } else if let Some((conn_id, receiving)) =
+            self.flipper_import.as_mut().filter(|(importer, _)| Some(*importer) == conn_id)
+        {
+            let conn_id = *conn_id;
+            if let Some(text) = receiving.push(data, now) {
+                self.flipper_import = None;
+                import_flipper(&text, &self.store, &mut self.matcher, bluetooth_manager);
+                bluetooth_manager.set_link_mode(conn_id, LinkMode::Balanced);
+            }
+        } else if command::is_frame(data) {
+            self.on_frames(data, conn_id, bluetooth_manager, now);
+        } else if let Ok(data_str) = std::str::from_utf8(data) {
+            self.on_text_command(data_str, conn_id, bluetooth_manager, now);
+        }
+        bluetooth_manager.message_handled();
+    }
+
+    /// 结构化命令，每个帧回复一个带相同序号的响应帧
+    fn on_frames(
+        &mut self,
+        data: &[u8],
+        conn_id: Option<ConnectionId>,
+        bluetooth_manager: &BluetoothManager,
+        now: Instant,
+    ) {
+        let commands = Rc::clone(&self.commands);
+        for frame in command::parse(data) {
+            let (opcode, seq, status, payload) = match frame {
+                Ok(Frame { seq, request }) => {
+                    let opcode = request.opcode() as u8;
+                    let mut context = CommandContext { app: self, bluetooth_manager, conn_id, now };
+                    let (status, payload) = commands.dispatch_frame(&mut context, request);
+                    (opcode, seq, status, payload)
+                }
+                Err(e) => {
+                    log::warn!("{}", e);
+                    (e.opcode, e.seq, e.status, Vec::new())
+                }
+            };
+            let response = command::response(opcode, seq, status, &payload);
+            if let Err(e) = bluetooth_manager.send_chunked(&response) {
+                log::error!("发送响应帧失败: {:?}", e);
+            }
+        }
+    }
+
+    /// 文本命令，格式为<命令>[:<参数>]
+    fn on_text_command(
+        &mut self,
+        data_str: &str,
+        conn_id: Option<ConnectionId>,
+        bluetooth_manager: &BluetoothManager,
+        now: Instant,
+    ) {
+        log::info!("蓝牙数据内容: {}", data_str);
+        let commands = Rc::clone(&self.commands);
+        let mut context = CommandContext { app: self, bluetooth_manager, conn_id, now };
+        commands.dispatch(&mut context, data_str.trim());
+    }
+
+    /// 接收线程上报的红外捕获
+    fn on_ir(&mut self, event: IrEvent, now: Instant) {
+        self.ir_captures = self.ir_captures.wrapping_add(1);
+        let (capture, truncated, ended_at) = match event {
+            IrEvent::Captured { capture, ended_at } => {
+                log::info!("接收到红外信号，脉冲数量: {}", capture.pulse_count());
+                (capture, false, ended_at)
+            }
+            IrEvent::Overflow(capture) => (capture, true, now),
+        };
+
+        if let Some(test) = self.selftest.as_mut() {
+            test.on_capture(&capture);
+            self.last_capture = Some(capture);
+            return;
+        }
+
+        let mut matched = None;
+        if let Some(code_match) = self.matcher.check(capture.durations(), truncated) {
+            matched = Some(code_match.slot.clone());
+            log::info!("匹配到参考码: {} ({}%)", code_match.slot, code_match.similarity);
+            if let Some(color) = self.match_colors.get(&code_match.slot) {
+                if let Err(e) = self.led.set_color(*color) {
+                    log::error!("设置LED颜色失败: {:?}", e);
+                }
+            }
+            if !code_match.repeat {
+                reply(&self.bluetooth_manager, &format!("MATCH: {} {}%", code_match.slot, code_match.similarity));
+            }
+        }
+
+        if let Some(event) = self.session.on_capture(&capture, truncated, ended_at) {
+            report_session(&self.bluetooth_manager, &event.to_string());
+            match (&event, self.record_slot.take()) {
+                (SessionEvent::Complete { .. }, Some((slot, force))) => {
+                    save_recording(&self.session, &slot, force, &self.store, &mut self.matcher, &self.bluetooth_manager);
+                }
+                // 等待重新录制，完成后再保存
+                (SessionEvent::LowQuality { .. }, slot) => self.record_slot = slot,
+                _ => (),
+            }
+        }
+
+        let mut event = CaptureEvent::new(&capture, truncated);
+        event.matched = matched;
+        let frame_len = Duration::from_micros(capture.durations().iter().sum::<u32>() as u64);
+        let started_at = ended_at.checked_sub(frame_len).unwrap_or(ended_at);
+        self.bluetooth_manager.set_last_capture(capture.durations());
+        self.last_capture = Some(capture);
+        for event in self.denon_pairer.push(event, started_at, ended_at) {
+            for event in self.sirc_merger.push(event, now) {
+                report_event(&mut self.keys, &self.bluetooth_manager, self.mqtt.as_ref(), event, now);
+            }
+        }
+    }
+
+    /// 发射线程上报的进度
+    fn on_transmit(&mut self, event: TransmitEvent, now: Instant) {
+        match event {
+            TransmitEvent::Started(ticket) => {
+                if self.play_tickets.contains(&ticket) {
+                    self.playing = true;
+                }
+            }
+            TransmitEvent::Finished { ticket, result } => {
+                if self.repeat_ticket == Some(ticket) {
+                    self.repeat_ticket = None;
+                }
+                let is_selftest = self.selftest.as_ref().is_some_and(|test| test.ticket() == ticket);
+                if is_selftest && result.is_err() {
+                    // 发射失败时不再等待捕获，原因由TX_FAILED说明
+                    self.selftest = None;
+                } else if let Some(test) = self.selftest.as_mut().filter(|_| is_selftest) {
+                    test.on_transmitted(now);
+                }
+                if self.play_tickets.remove(&ticket) {
+                    self.playing = false;
+                }
+                let message = match result {
+                    Ok(()) => format!("TX_DONE: {}", ticket),
+                    Err(e) => {
+                        log::warn!("{}", e);
+                        self.status_led.notify(Notice::Error);
+                        format!("TX_FAILED: {} {}", ticket, transmit_error_reason(&e))
+                    }
+                };
+                log::info!("发射结果: {}", message);
+                reply(&self.bluetooth_manager, &message);
+            }
+        }
+    }
+
+    /// 宏执行线程上报的进度
+    fn on_macro(&mut self, event: MacroEvent) {
+        log::info!("宏: {}", event);
+        reply(&self.bluetooth_manager, &event.to_string());
+    }
+
+    /// MQTT的send主题收到的发射请求，结果只记录日志
+    fn on_mqtt(&mut self, event: MqttEvent) {
+        let MqttEvent::Send(payload) = event;
+        log::info!("MQTT发射请求: {}", payload);
+        match mqtt_request(&payload, &self.store, &mut self.rc5_session) {
+            Ok(request) => match self.transmit_queue.enqueue(request) {
+                Ok(ticket) => log::info!("发射请求{}已入队: mqtt {}", ticket, payload),
+                Err(QueueFull) => log::warn!("发射队列已满，忽略MQTT请求: {}", payload),
+            },
+            Err(reason) => log::warn!("无效的MQTT请求{}: {}", payload, reason),
+        }
+    }
+
+    /// HTTP任务转来的请求，与蓝牙命令共用存储和发射队列
+    fn on_http(&mut self, event: HttpEvent) {
+        let response = self.http_response(&event.request);
+        event.respond(response);
+    }
+
+    /// 处理HTTP请求，与蓝牙的list、dump、REPLAY和DELETE使用相同的存储和发射队列
+    fn http_response(&mut self, request: &HttpRequest) -> HttpResponse {
+        match request {
+            HttpRequest::ListCodes => {
+                let slots = self.store.lock().unwrap().list();
+                let mut body = String::from("[");
+                for (index, slot) in slots.iter().enumerate() {
+                    if index > 0 {
+                        body.push(',');
+                    }
+                    body.push_str("{\"name\":");
+                    let _ = ir::dump::write_string(&mut body, &slot.name);
+                    body.push_str(&format!(
+                        ",\"protocol\":\"{}\",\"pulse_count\":{},\"size\":{},\"uses\":{},\"protected\":{},\"label\":",
+                        slot.protocol.name(),
+                        slot.pulse_count,
+                        slot.size,
+                        slot.uses,
+                        slot.protected
+                    ));
+                    let _ = ir::dump::write_string(&mut body, &slot.label);
+                    match slot.quality {
+                        Some(quality) => body.push_str(&format!(",\"quality\":{}", quality)),
+                        None => body.push_str(",\"quality\":null"),
+                    }
+                    body.push('}');
+                }
+                body.push(']');
+                HttpResponse::json(200, body)
+            }
+            HttpRequest::GetCode(slot) => {
+                let capture = self.store.lock().unwrap().load(slot);
+                let Some(capture) = capture else {
+                    return HttpResponse::error(404, "unknown slot");
+                };
+                let mut body = String::new();
+                match ir::dump::write_json(&mut body, &canonical_path(slot), &capture) {
+                    Ok(()) => HttpResponse::json(200, body),
+                    Err(_) => HttpResponse::error(500, "dump failed"),
+                }
+            }
+            HttpRequest::ImportCode(slot, capture) => {
+                if check_name(slot).is_err() {
+                    return HttpResponse::error(400, "invalid name");
+                }
+                if self.store.lock().unwrap().contains(slot) {
+                    return HttpResponse::error(409, "slot exists");
+                }
+                match save_slot(slot, capture, true, &self.store, &mut self.matcher, &self.bluetooth_manager) {
+                    Ok(()) => {
+                        log::info!("通过HTTP导入录制: {}", slot);
+                        HttpResponse::json(201, "{}".to_string())
+                    }
+                    Err(e) => {
+                        log::error!("保存录制{}失败: {}", slot, e);
+                        match e {
+                            StorageError::StorageFull { .. } => HttpResponse::error(507, "storage full"),
+                            StorageError::Nvs(_) => HttpResponse::error(500, "storage failed"),
+                            _ => HttpResponse::error(400, "invalid name"),
                         }
                     }
                 }
             }
+            HttpRequest::DeleteCode(slot) => {
+                if check_name(slot).is_err() {
+                    return HttpResponse::error(400, "invalid name");
+                }
+                let slot = canonical_path(slot);
+                match self.store.lock().unwrap().delete(&slot) {
+                    Ok(true) => {
+                        self.matcher.remove(&slot);
+                        self.match_colors.remove(&slot);
+                        log::info!("已删除录制: {}", slot);
+                        HttpResponse::json(200, "{}".to_string())
+                    }
+                    Ok(false) => HttpResponse::error(404, "unknown slot"),
+                    Err(e) => {
+                        log::error!("删除录制{}失败: {}", slot, e);
+                        HttpResponse::error(500, "storage failed")
+                    }
+                }
+            }
+            HttpRequest::Send(slot) => {
+                if !self.store.lock().unwrap().contains(slot) {
+                    return HttpResponse::error(404, "unknown slot");
+                }
+                match self.transmit_queue.enqueue(TransmitRequest::Replay(slot.clone())) {
+                    Ok(ticket) => {
+                        log::info!("发射请求{}已入队: http {}", ticket, slot);
+                        self.play_tickets.insert(ticket);
+                        HttpResponse::json(202, format!("{{\"ticket\":{}}}", ticket))
+                    }
+                    Err(QueueFull) => HttpResponse::error(503, "transmit queue full"),
+                }
+            }
+            HttpRequest::Status => {
+                let slots = self.store.lock().unwrap().list().len();
+                let health = Health::collect(self.boots, self.ir_captures, &self.bluetooth_manager);
+                HttpResponse::json(
+                    200,
+                    format!(
+                        "{{\"slots\":{},\"power\":\"{}\",\"ble_connected\":{},\"health\":{}}}",
+                        slots,
+                        self.power_save.state().name(),
+                        self.bluetooth_manager.is_connected(),
+                        health.to_json()
+                    ),
+                )
+            }
+        }
+    }
+
+    /// 去抖后的BOOT按键手势，执行配置的操作
+    fn on_button(&mut self, event: ButtonEvent, now: Instant) {
+        let action = self.config.button_action(event);
+        log::info!("按键{:?}: {}", event, action);
+        match action {
+            ButtonAction::None => {}
+            ButtonAction::PlayQuick => {
+                if !self.store.lock().unwrap().contains(QUICK_SLOT) {
+                    log::warn!("没有录制{}，忽略按键", QUICK_SLOT);
+                    self.status_led.notify(Notice::Error);
+                } else if let Some(ticket) = enqueue_and_reply(
+                    &mut self.transmit_queue,
+                    &self.bluetooth_manager,
+                    Ok(TransmitRequest::Replay(QUICK_SLOT.to_string())),
+                    &format!("play {}", QUICK_SLOT),
+                ) {
+                    self.play_tickets.insert(ticket);
+                }
+            }
+            ButtonAction::CycleRecent => {
+                // 每次双击重放下一个，录制按创建时间从新到旧排列
+                let recent = self.store.lock().unwrap().newest(RECENT_SLOTS);
+                if recent.is_empty() {
+                    log::warn!("还没有录制，忽略按键");
+                    self.status_led.notify(Notice::Error);
+                } else {
+                    let slot = &recent[self.recent_index % recent.len()];
+                    self.recent_index = self.recent_index.wrapping_add(1);
+                    if let Some(ticket) = enqueue_and_reply(
+                        &mut self.transmit_queue,
+                        &self.bluetooth_manager,
+                        Ok(TransmitRequest::Replay(slot.clone())),
+                        &format!("play {}", slot),
+                    ) {
+                        self.play_tickets.insert(ticket);
+                    }
+                }
+            }
+            ButtonAction::RecordQuick => {
+                // 录制期间LED显示录制状态，完成后直接保存到quick
+                if start_recording(&mut self.session, &self.bluetooth_manager, now) {
+                    // quick是临时位置，不做重复检查
+                    self.record_slot = Some((QUICK_SLOT.to_string(), true));
+                }
+            }
+            ButtonAction::ClearBonds => {
+                // 不依赖蓝牙的恢复手段，忘记配对码时清除绑定后重新配对
+                match self.bluetooth_manager.clear_bonds() {
+                    Ok(count) => log::warn!("按键清除了{}个蓝牙绑定", count),
+                    Err(e) => log::error!("清除蓝牙绑定失败: {:?}", e),
+                }
+                // 同时关闭白名单，否则手机丢失后其他设备都无法连接；名单保留，之后可以重新打开
+                let (_, peers) = self.bluetooth_manager.whitelist();
+                if let Err(e) = self.bluetooth_manager.set_whitelist(false, &peers) {
+                    log::error!("关闭蓝牙连接白名单失败: {:?}", e);
+                }
+                if let Err(e) = self.settings.set_whitelist_enabled(false) {
+                    log::error!("保存白名单设置失败: {:?}", e);
+                }
+            }
+            ButtonAction::FactoryReset => {
+                // 按键本身就是确认，长按时开始的录制作废
+                log::warn!("按键恢复出厂设置");
+                self.session.stop();
+                reset_to_factory(&mut self.led, &self.store, &self.bluetooth_manager);
+            }
+        }
+    }
+
+    /// 定时推进超时判定、按键合并和录制会话
+    fn on_tick(&mut self, now: Instant) {
+        self.ticker.ticked();
+
+        if let Some(state) = self.power_save.poll(now, self.bluetooth_manager.is_connected()) {
+            apply_power_state(&self.bluetooth_manager, &self.ticker, state);
+        }
+
+        // 每10秒打印一次连接状态
+        let log_connection = self.connection_logged_at.map_or(true, |at| now.duration_since(at) >= Duration::from_secs(10));
+        if log_connection {
+            self.connection_logged_at = Some(now);
+        }
+        if self.bluetooth_manager.is_connected() {
+            if log_connection {
+                log::info!("蓝牙已连接");
+            }
+            if !self.self_check_sent {
+                self.self_check_sent = true;
+                reply(&self.bluetooth_manager, &self.self_check_report);
+            }
+            self.bluetooth_manager.update_link_modes(now);
         } else {
-            if connection_check_counter % 100 == 0 {  // 每10秒打印一次
+            if log_connection {
                 log::info!("蓝牙未连接，等待连接...");
             }
+            // 连接断开时不能继续重复发射
+            if self.repeat_ticket.take().is_some() {
+                log::info!("蓝牙断开，停止重复发送");
+                self.transmit_queue.stop_repeating();
+            }
+        }
+
+        if now.duration_since(self.health_logged_at) >= health::LOG_INTERVAL {
+            self.health_logged_at = now;
+            log::info!("运行状况: {}", Health::collect(self.boots, self.ir_captures, &self.bluetooth_manager).to_json());
+        }
+
+        // 重放次数批量写入NVS
+        self.store.lock().unwrap().flush_if_due(now);
+
+        // 发起导入的客户端断开时立即中止，不必等到超时
+        let importer_gone = |conn_id: ConnectionId| self.bluetooth_manager.peer_addr(conn_id).is_none();
+        if let Some((conn_id, archive)) = &self.import {
+            if importer_gone(*conn_id) {
+                log::warn!("蓝牙断开，导入中止: {}", archive.summary());
+                self.import = None;
+            } else if archive.is_expired(now) {
+                log::warn!("导入超时: {}", archive.summary());
+                let message = format!("IMPORT_FAILED: timeout, {}", archive.summary());
+                reply(&self.bluetooth_manager.for_peer(*conn_id), &message);
+                self.import = None;
+            }
+        }
+        if let Some((conn_id, receiving)) = &self.flipper_import {
+            if importer_gone(*conn_id) {
+                log::warn!("蓝牙断开，Flipper文件导入中止");
+                self.flipper_import = None;
+            } else if receiving.is_expired(now) {
+                log::warn!("Flipper文件导入超时");
+                reply(&self.bluetooth_manager.for_peer(*conn_id), "FLIPPER_FAILED: timeout");
+                self.flipper_import = None;
+            }
+        }
+
+        if let Some(reset) = self.pending_reset.as_mut() {
+            if reset.is_expired(now) {
+                self.pending_reset = None;
+                log::info!("恢复出厂设置未确认，已取消");
+                if let Err(e) = self.led.set_color(RgbColor::black()) {
+                    log::warn!("设置LED失败: {:?}", e);
+                }
+                reply(&self.bluetooth_manager, "FACTORY_RESET_CANCELLED: timeout");
+            } else if let Some(on) = reset.poll_led(now) {
+                if let Err(e) = self.led.set_color(if on { RgbColor::red() } else { RgbColor::black() }) {
+                    log::warn!("设置LED失败: {:?}", e);
+                }
+            }
+        }
+
+        if let Some(event) = self.denon_pairer.poll(now) {
+            for event in self.sirc_merger.push(event, now) {
+                report_event(&mut self.keys, &self.bluetooth_manager, self.mqtt.as_ref(), event, now);
+            }
+        }
+        if let Some(event) = self.sirc_merger.poll(now) {
+            report_event(&mut self.keys, &self.bluetooth_manager, self.mqtt.as_ref(), event, now);
+        }
+        if let Some(event) = self.keys.poll(now) {
+            report_key(&self.bluetooth_manager, self.mqtt.as_ref(), &event);
+        }
+
+        if let Some(event) = self.session.poll(now) {
+            report_session(&self.bluetooth_manager, &event.to_string());
+            self.status_led.notify(match event {
+                SessionEvent::Complete { .. } => Notice::Captured,
+                SessionEvent::LowQuality { .. } | SessionEvent::Failed(_) | SessionEvent::TimedOut => Notice::Error,
+            });
+            match (&event, self.record_slot.take()) {
+                (SessionEvent::Complete { .. }, Some((slot, force))) => {
+                    save_recording(&self.session, &slot, force, &self.store, &mut self.matcher, &self.bluetooth_manager);
+                }
+                // 等待重新录制，完成后再保存
+                (SessionEvent::LowQuality { .. }, slot) => self.record_slot = slot,
+                _ => (),
+            }
+        }
+
+        if self.selftest.as_ref().is_some_and(|test| test.is_due(now)) {
+            // 判定结果后恢复解码容差
+            if let Some(test) = self.selftest.take() {
+                let message = test.finish().to_string();
+                log::info!("自检结果: {}", message);
+                reply(&self.bluetooth_manager, &message);
+            }
+        }
+    }
+}
+
+/// 完整的捕获交给按键合并后上报，被截断的捕获直接上报
+fn report_event(
+    keys: &mut RepeatCoalescer,
+    bluetooth_manager: &BluetoothManager,
+    mqtt: Option<&MqttBridge>,
+    event: CaptureEvent,
+    now: Instant,
+) {
+    if event.truncated {
+        report_capture(bluetooth_manager, mqtt, &event);
+        return;
+    }
+    for key in keys.push(event, now) {
+        report_key(bluetooth_manager, mqtt, &key);
+    }
+}
+
+/// 记录按键事件并发送给客户端
+fn report_key(bluetooth_manager: &BluetoothManager, mqtt: Option<&MqttBridge>, event: &KeyEvent) {
+    match event {
+        KeyEvent::Pressed(capture) => return report_capture(bluetooth_manager, mqtt, capture),
+        KeyEvent::Held { .. } => log::debug!("{}", event),
+        KeyEvent::Released(_) => log::info!("{}", event),
+    }
+    reply(bluetooth_manager, &event.to_string());
+}
+
+/// 记录捕获结果并发送给客户端，蓝牙断开期间保存在重发缓冲区中；完整的捕获同时发布到MQTT
+fn report_capture(bluetooth_manager: &BluetoothManager, mqtt: Option<&MqttBridge>, event: &CaptureEvent) {
+    if !event.truncated {
+        log::info!("识别协议: {}, {}", event.command.protocol().name(), event.command);
+        if let Some(mqtt) = mqtt {
+            mqtt.publish_capture(event);
         }
-        
-        connection_check_counter += 1;
-        
-        // // 准备接收缓冲区 - 使用正确的初始化
-        // let mut pulses = [(
-        //     Pulse::zero(), Pulse::zero()
-        // ); STACK_SIZE];
-        
-        // // 等待红外信号 (1000 ticks = 约1秒，假设tick rate为1000Hz)
-        // match ir_receiver.receive(&mut pulses, 1000) {
-        //     Ok(received_count) => {
-        //         match received_count {
-        //             esp_idf_hal::rmt::Receive::Read(count) => {
-        //                 log::info!("接收到红外信号，脉冲数量: {}", count);
-        //                 let pulses = &pulses[..count];
-
-        //                 for (pulse0, pulse1) in pulses {
-        //                     log::info!("0={pulse0:?}, 1={pulse1:?}");
-        //                 }
-                        
-        //                 // 如果蓝牙已连接，发送红外数据到蓝牙
-        //                 if bluetooth_manager.is_connected() {
-        //                     let ir_data = format!("IR: {} pulses", count);
-        //                     if let Err(e) = bluetooth_manager.send_data(ir_data.as_bytes()) {
-        //                         log::error!("发送红外数据到蓝牙失败: {:?}", e);
-        //                     }
-        //                 }
-        //             }
-        //             esp_idf_hal::rmt::Receive::Overflow(count) => {
-        //                 log::warn!("接收缓冲区溢出，脉冲数量: {}", count);
-        //             }
-        //             esp_idf_hal::rmt::Receive::Timeout => {
-        //                 // 不记录超时，减少日志输出
-        //             }
-        //         }
-        //     }
-        //     Err(e) => {
-        //         log::error!("RMT接收错误: {:?}", e);
-        //     }
-        // }
-        
-        // 短暂延时
-        FreeRtos::delay_ms(100);
     }
+
+    let message = event.to_string();
+    if let Err(e) = bluetooth_manager.send_data(message.as_bytes()) {
+        log::error!("发送红外数据到蓝牙失败: {:?}", e);
+    }
+}
+
+/// 命令的注册表，`help`按注册顺序列出
+fn command_registry() -> CommandRegistry {
+    let mut commands = CommandRegistry::default();
+    register_led_commands(&mut commands);
+    register_record_commands(&mut commands);
+    register_transmit_commands(&mut commands);
+    register_storage_commands(&mut commands);
+    register_system_commands(&mut commands);
+    commands
 }
 
+/// LED和灯带的命令，不是命令名称的内容按颜色设置LED
+fn register_led_commands(commands: &mut CommandRegistry) {
+    for (name, color) in [
+        ("red", RgbColor::red()),
+        ("green", RgbColor::green()),
+        ("blue", RgbColor::blue()),
+        ("off", RgbColor::black()),
+    ] {
+        commands.register(name, &[], move |context, _| {
+            log::info!("设置LED为{}", name);
+            if let Err(e) = context.app.led.set_color(color) {
+                log::warn!("设置LED失败: {:?}", e);
+            }
+            Ok(())
+        });
+    }
+    commands.register("led", &[optional("color", ArgKind::String)], |context, args| {
+        // led查询当前颜色，led:<颜色名称>|<#RRGGBB>|<#RGB>设置颜色
+        let Some(text) = args.string(0) else {
+            let color = context.app.led.current_color();
+            context.reply(&format!("LED: #{:02x}{:02x}{:02x}", color.red, color.green, color.blue));
+            return Ok(());
+        };
+        match text.parse::<RgbColor>() {
+            Ok(color) => {
+                log::info!("设置LED颜色: {}", text);
+                if let Err(e) = context.app.led.set_color(color) {
+                    log::warn!("设置LED失败: {:?}", e);
+                }
+            }
+            Err(e) => context.reply(&color_error(text, &e)),
+        }
+        Ok(())
+    });
+    commands.register_frame(Opcode::LedSet, |context, request| {
+        let Request::LedSet { red, green, blue } = request else {
+            return (Status::InvalidPayload, Vec::new());
+        };
+        match context.app.led.set_color(RgbColor::new(red, green, blue)) {
+            Ok(()) => (Status::Ok, Vec::new()),
+            Err(e) => {
+                log::warn!("设置LED失败: {:?}", e);
+                (e.status(), Vec::new())
+            }
+        }
+    });
+    let params = [
+        required("hue 0-359", ArgKind::Int),
+        required("sat 0-255", ArgKind::Int),
+        required("val 0-255", ArgKind::Int),
+    ];
+    commands.register("hsv", &params, |context, args| {
+        // hsv:<色相0-359>:<饱和度0-255>:<亮度0-255>
+        let hue: u16 = narrow(args.int(0), "hue 0-359")?;
+        if hue >= 360 {
+            return Err(InvalidArgument("hue 0-359"));
+        }
+        let hsv = HsvColor::from_degrees(hue, narrow(args.int(1), "sat 0-255")?, narrow(args.int(2), "val 0-255")?);
+        log::info!("设置LED为HSV颜色: {:?}", hsv);
+        if let Err(e) = context.app.led.set_hsv(hsv) {
+            log::warn!("设置LED失败: {:?}", e);
+        }
+        Ok(())
+    });
+    commands.register("brightness", &[optional("percent 1-100", ArgKind::Int)], |context, args| {
+        // brightness查询，brightness:<百分比>设置LED亮度上限
+        if let Some(percent) = args.int(0) {
+            let percent = u8::try_from(percent).ok().filter(|percent| BRIGHTNESS_RANGE.contains(percent));
+            let percent = percent.ok_or(InvalidArgument("percent 1-100"))?;
+            if percent != context.app.led.brightness() {
+                if let Err(e) = context.app.led.set_brightness(percent) {
+                    log::warn!("设置LED亮度失败: {:?}", e);
+                }
+                if let Err(e) = context.app.settings.set_led_brightness(percent) {
+                    log::error!("保存LED亮度失败: {:?}", e);
+                }
+            }
+        }
+        context.reply(&format!("BRIGHTNESS: {}%", context.app.led.brightness()));
+        Ok(())
+    });
+    commands.register("leds", &[optional("count", ArgKind::Int)], |context, args| {
+        // leds查询，leds:<数量>设置灯带的像素数量
+        let len = match args.int(0) {
+            None => context.app.led.strip().len(),
+            Some(len) if (1..=MAX_STRIP_LEN).contains(&(len as usize)) => len as usize,
+            Some(_) => {
+                context.reply(&format!("ERROR: usage leds:<1-{}>", MAX_STRIP_LEN));
+                return Ok(());
+            }
+        };
+        if len != context.app.led.strip().len() {
+            if let Err(e) = context.app.led.set_len(len) {
+                log::warn!("设置灯带像素数量失败: {:?}", e);
+            }
+            if let Err(e) = context.app.settings.set_led_count(len as u8) {
+                log::error!("保存灯带像素数量失败: {:?}", e);
+            }
+        }
+        context.reply(&format!("LEDS: {}", len));
+        Ok(())
+    });
+    let params = [required("index", ArgKind::Int), required("color|hue:sat:val", ArgKind::String)];
+    commands.register("pixel", &params, |context, args| {
+        // pixel:<序号>:<颜色名称>或pixel:<序号>:<色相>:<饱和度>:<亮度>，只设置一个像素
+        let index = args.int(0).unwrap_or_default() as usize;
+        let color = args.string(1).unwrap_or_default();
+        let color = color.parse().ok().or_else(|| parse_hsv(color).map(RgbColor::from));
+        let color = color.ok_or(InvalidArgument("color|hue:sat:val"))?;
+        let strip = context.app.led.strip_mut();
+        if strip.set_pixel(index, color) {
+            if let Err(e) = strip.show() {
+                log::warn!("设置像素失败: {:?}", e);
+            }
+        } else {
+            let last = strip.len() - 1;
+            context.reply(&format!("ERROR: pixel index out of range (0-{})", last));
+        }
+        Ok(())
+    });
+    let params = [optional("grb24|rgb24|grbw32", ArgKind::String), optional("white", ArgKind::Flag)];
+    commands.register("led_format", &params, |context, args| {
+        // led_format查询，led_format:<格式>[:white]设置像素格式，white表示RGBW像素自动提取白光
+        let strip = context.app.led.strip();
+        let (format, auto_white) = match args.string(0) {
+            None if args.flag(1) => return Err(InvalidArgument("grb24|rgb24|grbw32")),
+            None => (strip.format(), strip.auto_white()),
+            Some(format) => {
+                let format = format.parse::<PixelFormat>().map_err(|_| InvalidArgument("grb24|rgb24|grbw32"))?;
+                (format, args.flag(1))
+            }
+        };
+        if (format, auto_white) != (strip.format(), strip.auto_white()) {
+            if let Err(e) = context.app.led.set_format(format, auto_white) {
+                log::warn!("设置LED像素格式失败: {:?}", e);
+            }
+            if let Err(e) = context.app.settings.set_led_format(format) {
+                log::error!("保存LED像素格式失败: {:?}", e);
+            }
+            if let Err(e) = context.app.settings.set_led_auto_white(auto_white) {
+                log::error!("保存LED白光设置失败: {:?}", e);
+            }
+        }
+        let suffix = if auto_white { ":white" } else { "" };
+        context.reply(&format!("LED_FORMAT: {}{}", format.as_str(), suffix));
+        Ok(())
+    });
+    let params = [optional("ws2812|ws2812b|sk6812|t0h:t0l:t1h:t1l:reset_us", ArgKind::String)];
+    commands.register("led_timing", &params, |context, args| {
+        // led_timing查询，led_timing:<预设>或led_timing:<t0h>:<t0l>:<t1h>:<t1l>:<复位微秒>设置时序
+        let timing = match args.string(0) {
+            None => context.app.led.strip().timing(),
+            Some(timing) => match timing.parse::<Timing>() {
+                Ok(timing) => timing,
+                Err(_) => {
+                    context.reply(&format!(
+                        "ERROR: usage led_timing:<ws2812|ws2812b|sk6812> or led_timing:<t0h>:<t0l>:<t1h>:<t1l>:<reset {}-{}us>",
+                        RESET_US_RANGE.start(),
+                        RESET_US_RANGE.end()
+                    ));
+                    return Ok(());
+                }
+            },
+        };
+        if timing != context.app.led.strip().timing() {
+            if let Err(e) = context.app.led.set_timing(timing) {
+                log::warn!("设置LED时序失败: {:?}", e);
+            }
+            if let Err(e) = context.app.settings.set_led_timing(timing) {
+                log::error!("保存LED时序失败: {:?}", e);
+            }
+        }
+        context.reply(&format!("LED_TIMING: {}", timing));
+        Ok(())
+    });
+    let params = [optional("mA|off", ArgKind::String), optional("mA per channel", ArgKind::Int)];
+    commands.register("led_power", &params, |context, args| {
+        // led_power查询，led_power:<上限毫安|off>[:<每通道毫安>]设置灯带的电流上限
+        let current = context.app.led.strip().power();
+        let power = match args.string(0) {
+            None if args.int(1).is_some() => return Err(InvalidArgument("mA|off")),
+            None => Some(current),
+            Some(max) => parse_led_power(max, args.int(1), current),
+        };
+        let Some(power) = power else {
+            context.reply(&format!(
+                "ERROR: usage led_power:<{}-{}|off>[:<{}-{} mA per channel>]",
+                MAX_CURRENT_MA_RANGE.start(),
+                MAX_CURRENT_MA_RANGE.end(),
+                CHANNEL_MA_RANGE.start(),
+                CHANNEL_MA_RANGE.end()
+            ));
+            return Ok(());
+        };
+        if power != current {
+            if let Err(e) = context.app.led.set_power(power) {
+                log::warn!("设置LED电流上限失败: {:?}", e);
+            }
+            if let Err(e) = context.app.settings.set_led_power(power) {
+                log::error!("保存LED电流上限失败: {:?}", e);
+            }
+        }
+        context.reply(&led_power_status(&context.app.led));
+        Ok(())
+    });
+    commands.register("alert", &[optional("color", ArgKind::String)], |context, args| {
+        // alert[:<颜色>]，频闪5秒，暂停其他效果，结束后恢复
+        let color = match args.string(0) {
+            None => Ok(RgbColor::white()),
+            Some(color) => color.parse::<RgbColor>(),
+        };
+        match color {
+            Ok(color) => {
+                context.app.led.alert(color, ALERT_DURATION_MS);
+                context.reply("ALERT: OK");
+            }
+            Err(e) => context.reply(&color_error(args.string(0).unwrap_or_default(), &e)),
+        }
+        Ok(())
+    });
+    let params = [
+        required("rainbow|breathing|blink|fade|chase|wipe|sparkle|candle|stop", ArgKind::String),
+        optional("options", ArgKind::String),
+    ];
+    commands.register("effect", &params, |context, args| {
+        // effect:rainbow[:<毫秒>]、effect:breathing|blink|fade:<颜色>[:<次数或毫秒>]、effect:stop
+        let kind = args.string(0).unwrap_or_default();
+        let options = args.string(1);
+        if kind == "stop" && options.is_none() {
+            if let Err(e) = context.app.led.stop_effect() {
+                log::warn!("停止LED效果失败: {:?}", e);
+            }
+        } else if start_effect(&mut context.app.led, kind, options, unsafe { esp_idf_svc::sys::esp_random() }).is_none() {
+            context.reply(
+                "ERROR: usage effect:rainbow[:<ms>], effect:breathing|blink:<color>[:<count>], effect:fade:<color>[:<ms>[:linear|ease|exp]], effect:chase|wipe|sparkle:<color>[:...], effect:candle[:<color>] or effect:stop",
+            );
+        }
+        Ok(())
+    });
+    commands.register("rmt", &[optional("led|ir", ArgKind::String), optional("blocks", ArgKind::Int)], |context, args| {
+        // rmt查询发射通道分配，rmt:led|ir:<内存块数量>修改配置，重启后生效
+        let (owner, blocks) = match (args.string(0), args.int(1)) {
+            (None, None) => {
+                context.reply(&rmt_status(context.app.rmt_allocator.assigned(), &context.app.settings));
+                return Ok(());
+            }
+            (Some(owner @ ("led" | "ir")), Some(blocks)) => (owner, narrow::<u8>(Some(blocks), "blocks")?),
+            (Some("led" | "ir"), None) => return Err(InvalidArgument("blocks")),
+            _ => return Err(InvalidArgument("led|ir")),
+        };
+        let settings = &context.app.settings;
+        let (led_blocks, ir_blocks) = match owner {
+            "led" => (blocks, settings.rmt_ir_blocks()),
+            _ => (settings.rmt_led_blocks(), blocks),
+        };
+        match RmtAllocator::check(&[(OWNER_LED, led_blocks), (OWNER_IR, ir_blocks)]) {
+            Ok(_) => {
+                let saved = match owner {
+                    "led" => settings.set_rmt_led_blocks(blocks),
+                    _ => settings.set_rmt_ir_blocks(blocks),
+                };
+                if let Err(e) = saved {
+                    log::error!("保存RMT内存块数量失败: {:?}", e);
+                }
+                context.reply(&rmt_status(context.app.rmt_allocator.assigned(), settings));
+            }
+            Err(RmtError::InvalidBlocks { .. }) => {
+                context.reply(&format!("ERROR: memory blocks must be 1-{}", TX_CHANNELS));
+            }
+            Err(e) => {
+                log::warn!("{}", e);
+                context.reply(&format!(
+                    "ERROR: led={} and ir={} blocks do not fit in {} tx blocks",
+                    led_blocks, ir_blocks, TX_CHANNELS
+                ));
+            }
+        }
+        Ok(())
+    });
+    commands.register_fallback(required("color", ArgKind::String), |context, args| {
+        // 其他内容按颜色名称或十六进制颜色设置LED
+        let text = args.string(0).unwrap_or_default();
+        match text.parse::<RgbColor>() {
+            Ok(color) => {
+                log::info!("设置LED颜色: {}", text);
+                if let Err(e) = context.app.led.set_color(color) {
+                    log::warn!("设置LED失败: {:?}", e);
+                }
+            }
+            Err(ParseColorError::UnknownName) => {
+                log::info!("未知的命令: {}", text);
+                context.reply(&CommandError::Unknown(text.to_string()).to_string());
+            }
+            Err(e) => context.reply(&color_error(text, &e)),
+        }
+        Ok(())
+    });
+}
+
+/// 录制、学习参考码和接收头的命令
+fn register_record_commands(commands: &mut CommandRegistry) {
+    let params = [optional("name", ArgKind::String), optional("force", ArgKind::Flag)];
+    commands.register("record", &params, |context, args| {
+        // record[:<名称>[:force]]，指定名称时录制完成后直接保存，force跳过重复检查
+        let slot = args.string(0);
+        if let Some(Err(e)) = slot.map(check_name) {
+            context.reply(&storage_error_reply(&e));
+        } else if start_recording(&mut context.app.session, context.bluetooth_manager, context.now) {
+            context.app.record_slot = slot.map(|slot| (slot.to_string(), args.flag(1)));
+        }
+        Ok(())
+    });
+    commands.register_frame(Opcode::RecordStart, |context, request| {
+        let Request::RecordStart(slot) = request else {
+            return (Status::InvalidPayload, Vec::new());
+        };
+        if slot.as_deref().is_some_and(|slot| check_name(slot).is_err()) {
+            (Status::InvalidPayload, Vec::new())
+        } else if context.app.session.start(context.now).is_ok() {
+            log::info!("开始录制，等待红外信号");
+            context.app.record_slot = slot.map(|slot| (slot, false));
+            (Status::Ok, Vec::new())
+        } else {
+            (Status::Busy, Vec::new())
+        }
+    });
+    commands.register("stop", &[], |context, _| {
+        context.app.record_slot = None;
+        if context.app.session.stop() {
+            log::info!("录制已取消");
+            context.reply("RECORD_STOPPED");
+        } else {
+            context.reply("ERROR: no recording in progress");
+        }
+        Ok(())
+    });
+    commands.register_frame(Opcode::RecordStop, |context, _| {
+        context.app.record_slot = None;
+        let status = if context.app.session.stop() { Status::Ok } else { Status::NotFound };
+        (status, Vec::new())
+    });
+    let params = [
+        required("name", ArgKind::String),
+        optional("color", ArgKind::String),
+        optional("force", ArgKind::Flag),
+    ];
+    commands.register("learn", &params, |context, args| {
+        // learn:<名称>[:<颜色>][:force]，把最近一次捕获记录为参考码，force跳过重复检查
+        let slot = args.string(0).unwrap_or_default();
+        // learn:<名称>:force省略了颜色
+        let (color, force) = match (args.string(1), args.flag(2)) {
+            (Some("force"), false) => (None, true),
+            (color, force) => (color, force),
+        };
+        let color = match color.map(|color| (color, color.parse::<RgbColor>())) {
+            None => None,
+            Some((_, Ok(color))) => Some(color),
+            Some((color, Err(e))) => {
+                context.reply(&color_error(color, &e));
+                return Ok(());
+            }
+        };
+        let app = &mut *context.app;
+        let Some(capture) = app.session.pending() else {
+            reply(context.bluetooth_manager, "ERROR: no recording to learn");
+            return Ok(());
+        };
+        match save_slot(slot, capture, force, &app.store, &mut app.matcher, context.bluetooth_manager) {
+            Ok(()) => {
+                match color {
+                    Some(color) => app.match_colors.insert(canonical_path(slot), color),
+                    None => app.match_colors.remove(&canonical_path(slot)),
+                };
+                log::info!("已学习参考码: {}", slot);
+                reply(context.bluetooth_manager, &format!("LEARNED: {}", slot));
+            }
+            Err(e) => {
+                log::warn!("{}", e);
+                reply(context.bluetooth_manager, &storage_error_reply(&e));
+            }
+        }
+        Ok(())
+    });
+    commands.register("forget", &[required("name", ArgKind::String)], |context, args| {
+        // forget:<遥控器>/<按键>，没有前缀时删除default遥控器中的录制
+        let name = args.string(0).unwrap_or_default();
+        let slot = canonical_path(name);
+        if context.app.matcher.remove(&slot) {
+            if let Err(e) = context.app.store.lock().unwrap().delete(&slot) {
+                log::error!("删除录制{}失败: {}", slot, e);
+            }
+            context.app.match_colors.remove(&slot);
+            log::info!("已删除参考码: {}", name);
+            context.reply(&format!("FORGOT: {}", name));
+        } else {
+            context.reply(&format!("ERROR: unknown code {}", name));
+        }
+        Ok(())
+    });
+    commands.register("quality", &[optional("threshold", ArgKind::Int)], |context, args| {
+        // quality[:<0-100>]，查询或设置录制质量阈值，为0时不检查
+        if let Some(threshold) = args.int(0) {
+            let threshold = u8::try_from(threshold).ok().filter(|&threshold| threshold <= 100);
+            let threshold = threshold.ok_or(InvalidArgument("threshold"))?;
+            context.app.session.set_quality_threshold(threshold);
+            if let Err(e) = context.app.settings.set_quality_threshold(threshold) {
+                log::error!("保存录制质量阈值失败: {:?}", e);
+            }
+            log::info!("录制质量阈值: {}", threshold);
+        }
+        let mut text = format!("QUALITY: threshold={}", context.app.session.quality_threshold());
+        if let Some(capture) = context.app.session.pending() {
+            text.push_str(&format!(" last={}", quality::score(capture, false)));
+        }
+        context.reply(&text);
+        Ok(())
+    });
+    commands.register("analyze", &[optional("bucket_us", ArgKind::Int)], |context, args| {
+        // analyze[:<桶宽微秒>]，以JSON返回最近一次捕获的脉冲统计
+        let Some(capture) = &context.app.last_capture else {
+            context.reply("ERROR: no capture to analyze");
+            return Ok(());
+        };
+        let width = args.int(0).unwrap_or(DEFAULT_BUCKET_WIDTH_US);
+        if width == 0 {
+            return Err(InvalidArgument("bucket_us"));
+        }
+        let mut report = analyze(capture.durations(), width).to_json();
+        // 报告可能跨多个分段，以换行表示结束
+        report.push('\n');
+        if let Err(e) = context.bluetooth_manager.send_chunked(report.as_bytes()) {
+            log::error!("发送分析报告失败: {:?}", e);
+        }
+        Ok(())
+    });
+    commands.register("carrier", &[], |context, _| {
+        // 测量期间主循环会阻塞，最多等待5秒
+        log::info!("开始测量载波频率，请按下遥控器按键");
+        match context.app.carrier_meter.measure_carrier() {
+            Ok(Some(carrier_hz)) => {
+                log::info!("载波频率: {}Hz", carrier_hz);
+                if !context.app.session.set_pending_carrier(carrier_hz) {
+                    log::info!("没有待定的录制，载波频率不会保存");
+                }
+                context.reply(&format!("CARRIER: {}Hz", carrier_hz));
+            }
+            Ok(None) => context.reply("ERROR: no carrier detected"),
+            Err(e) => {
+                log::warn!("{}", e);
+                context.reply("ERROR: carrier measurement not supported");
+            }
+        }
+        Ok(())
+    });
+    commands.register("multiframe", &[required("on|off", ArgKind::String)], |context, args| {
+        // multiframe:on|off，录制时把一次按键的多帧合并为一个捕获
+        let multi_frame = parse_on_off(args.string(0))?;
+        context.app.session.set_multi_frame(multi_frame);
+        if let Err(e) = context.app.settings.set_multi_frame(multi_frame) {
+            log::error!("保存多帧录制模式失败: {:?}", e);
+        }
+        let state = args.string(0).unwrap_or_default();
+        log::info!("多帧录制模式: {}", state);
+        context.reply(&format!("MULTIFRAME: {}", state));
+        Ok(())
+    });
+    commands.register("filter", &[required("min_pulse_us", ArgKind::Int)], |context, args| {
+        // filter:<微秒>，修改软件毛刺滤波阈值并保存
+        let value = args.int(0).unwrap_or_default();
+        if value > MAX_MIN_PULSE_US {
+            context.reply(&format!("ERROR: filter must be 0-{}us", MAX_MIN_PULSE_US));
+            return Ok(());
+        }
+        context.app.min_pulse_us.store(value, Ordering::Relaxed);
+        if let Err(e) = context.app.settings.set_min_pulse_us(value) {
+            log::error!("保存毛刺滤波阈值失败: {:?}", e);
+        }
+        log::info!("软件毛刺滤波阈值: {}µs", value);
+        context.reply(&format!("FILTER: {}us", value));
+        Ok(())
+    });
+    let params = [optional("pulses|header", ArgKind::String), optional("value", ArgKind::Int)];
+    commands.register("noise", &params, |context, args| {
+        // noise查询统计，noise:pulses:<数量>或noise:header:<微秒>修改阈值并保存
+        let noise = &context.app.noise;
+        let settings = &context.app.settings;
+        let saved = match (args.string(0), args.int(1)) {
+            (None, None) => Ok(()),
+            (Some("pulses"), Some(value)) => {
+                noise.set_min_pulses(value);
+                settings.set_min_pulses(value)
+            }
+            (Some("header"), Some(value)) => {
+                noise.set_min_header_us(value);
+                settings.set_min_header_us(value)
+            }
+            (Some("pulses" | "header"), None) => return Err(InvalidArgument("value")),
+            _ => return Err(InvalidArgument("pulses|header")),
+        };
+        if let Err(e) = saved {
+            log::error!("保存噪声过滤阈值失败: {:?}", e);
+        }
+        context.reply(&format!(
+            "NOISE: min_pulses={} min_header={}us {}",
+            noise.min_pulses(),
+            noise.min_header_us(),
+            noise.stats()
+        ));
+        Ok(())
+    });
+    commands.register("nec_strict", &[required("on|off", ArgKind::String)], |context, args| {
+        // nec_strict:on|off，切换NEC严格模式并保存
+        let strict = parse_on_off(args.string(0))?;
+        ir::nec::set_strict(strict);
+        if let Err(e) = context.app.settings.set_nec_strict(strict) {
+            log::error!("保存NEC严格模式失败: {:?}", e);
+        }
+        let state = args.string(0).unwrap_or_default();
+        log::info!("NEC严格模式: {}", state);
+        context.reply(&format!("NEC_STRICT: {}", state));
+        Ok(())
+    });
+}
+
+/// 发射录制、协议编码、宏和发射参数的命令
+fn register_transmit_commands(commands: &mut CommandRegistry) {
+    commands.register("play", &[required("name", ArgKind::String)], |context, args| {
+        // play:<名称>，发射期间LED显示绿色
+        let slot = args.string(0).unwrap_or_default();
+        if !context.app.store.lock().unwrap().contains(slot) {
+            context.reply(&format!("ERROR: unknown slot {}", slot));
+        } else if let Some(ticket) = enqueue_and_reply(
+            &mut context.app.transmit_queue,
+            context.bluetooth_manager,
+            Ok(TransmitRequest::Replay(slot.to_string())),
+            &format!("play {}", slot),
+        ) {
+            context.app.play_tickets.insert(ticket);
+        }
+        Ok(())
+    });
+    commands.register_frame(Opcode::Replay, |context, request| {
+        let Request::Replay(slot) = request else {
+            return (Status::InvalidPayload, Vec::new());
+        };
+        if !context.app.store.lock().unwrap().contains(&slot) {
+            return (Status::NotFound, Vec::new());
+        }
+        match context.app.transmit_queue.enqueue(TransmitRequest::Replay(slot)) {
+            Ok(ticket) => {
+                context.app.play_tickets.insert(ticket);
+                (Status::Ok, ticket.to_le_bytes().to_vec())
+            }
+            Err(QueueFull) => (Status::Busy, Vec::new()),
+        }
+    });
+    commands.register("repeat", &[required("name", ArgKind::String), optional("raw", ArgKind::Flag)], |context, args| {
+        // repeat:<名称>[:raw]，按住期间重复发送，raw表示NEC也重发完整帧
+        let slot = args.string(0).unwrap_or_default();
+        if !context.app.store.lock().unwrap().contains(slot) {
+            context.reply(&format!("ERROR: unknown slot {}", slot));
+            return Ok(());
+        }
+        let result = context.app.transmit_queue.start_repeating(slot, !args.flag(1));
+        let description = format!("repeat {}", slot);
+        if let Some(ticket) = reply_enqueued(context.bluetooth_manager, result, &description) {
+            context.app.repeat_ticket = Some(ticket);
+        }
+        Ok(())
+    });
+    commands.register("repeat_stop", &[], |context, _| {
+        context.app.transmit_queue.stop_repeating();
+        context.app.repeat_ticket = None;
+        context.reply("REPEAT_STOPPED");
+        Ok(())
+    });
+    commands.register("send", &[required("[carrier_hz:]mark,space,...", ArgKind::String)], |context, args| {
+        // send[:<载波Hz>]:<mark>,<space>,<mark>,...，时长单位为微秒
+        let args = args.string(0).unwrap_or_default();
+        let (frequency, durations) = match args.split_once(':') {
+            Some((frequency, durations)) => (frequency.parse::<u32>().ok(), durations),
+            None => (Some(DEFAULT_CARRIER_HZ), args),
+        };
+        let frequency = frequency.ok_or(InvalidArgument("[carrier_hz:]mark,space,..."))?;
+        // 无法解析时记录出错的序号
+        let durations: Result<Vec<u32>, usize> = durations
+            .split(',')
+            .enumerate()
+            .map(|(index, duration)| duration.trim().parse::<u32>().map_err(|_| index))
+            .collect();
+        match durations {
+            Ok(durations) => {
+                let description = format!("{} pulses", durations.len());
+                let request = validate_raw(durations).and_then(|durations| raw_request(durations, frequency));
+                enqueue_and_reply(&mut context.app.transmit_queue, context.bluetooth_manager, request, &description);
+            }
+            Err(index) => context.reply(&format!("ERROR: invalid duration at index {}", index)),
+        }
+        Ok(())
+    });
+    let params = [
+        required("device", ArgKind::Int),
+        required("command", ArgKind::Int),
+        optional("12|15|20", ArgKind::Int),
+    ];
+    commands.register("sirc", &params, |context, args| {
+        // sirc:<设备>:<命令>[:<位数>]，默认12位，数字可以用0x前缀的十六进制
+        let device = narrow(args.int(0), "device")?;
+        let command: u8 = narrow(args.int(1), "command")?;
+        let bits = match args.int(2) {
+            Some(bits) => SircBits::from_count(bits as usize).ok_or(InvalidArgument("12|15|20"))?,
+            None => SircBits::Twelve,
+        };
+        if command > 0x7F {
+            return Err(InvalidArgument("command"));
+        }
+        let durations = encode_sirc(device, command, bits).ok_or(InvalidArgument("device"))?;
+        let description = format!("sirc {}:{}:{}", device, command, bits.count());
+        let request = raw_request(durations, ir::sirc::CARRIER_HZ);
+        enqueue_and_reply(&mut context.app.transmit_queue, context.bluetooth_manager, request, &description);
+        Ok(())
+    });
+    let params = [
+        required("address", ArgKind::Int),
+        required("command", ArgKind::Int),
+        optional("hold", ArgKind::Flag),
+    ];
+    commands.register("rc5", &params, |context, args| {
+        // rc5:<地址>:<命令>[:hold]，翻转位由会话自动维护，hold表示按住按键的重复帧
+        let address = narrow(args.int(0), "address")?;
+        let command = narrow(args.int(1), "command")?;
+        let durations = match args.flag(2) {
+            false => context.app.rc5_session.press(address, command),
+            true => context.app.rc5_session.repeat(address, command),
+        };
+        let durations = durations.ok_or(InvalidArgument("command"))?;
+        let hold = if args.flag(2) { ":hold" } else { "" };
+        let description = format!("rc5 {}:{}{}", address, command, hold);
+        let request = raw_request(durations, ir::rc5::CARRIER_HZ);
+        enqueue_and_reply(&mut context.app.transmit_queue, context.bluetooth_manager, request, &description);
+        Ok(())
+    });
+    let params = [
+        required("addr 0-31", ArgKind::Int),
+        required("cmd 0-255", ArgKind::Int),
+        optional("ext 0-1", ArgKind::Int),
+    ];
+    commands.register("denon", &params, |context, args| {
+        // denon:<地址>:<命令>[:<扩展位>]，Sharp设备的扩展位为1
+        let address: u8 = narrow(args.int(0), "addr 0-31")?;
+        let command = narrow(args.int(1), "cmd 0-255")?;
+        let extension: u8 = narrow(Some(args.int(2).unwrap_or(0)), "ext 0-1")?;
+        if address > 31 {
+            return Err(InvalidArgument("addr 0-31"));
+        }
+        let durations = encode_denon(address, command, extension).ok_or(InvalidArgument("ext 0-1"))?;
+        let description = format!("denon {}:{}:{}", address, command, extension);
+        let request = raw_request(durations, ir::denon::CARRIER_HZ);
+        enqueue_and_reply(&mut context.app.transmit_queue, context.bluetooth_manager, request, &description);
+        Ok(())
+    });
+    commands.register("samsung", &[required("address", ArgKind::Int), required("command", ArgKind::Int)], |context, args| {
+        // samsung:<地址>:<命令>
+        let address = narrow(args.int(0), "address")?;
+        let command = narrow(args.int(1), "command")?;
+        let description = format!("samsung {}:{}", address, command);
+        let request = raw_request(encode_samsung(address, command), ir::samsung::CARRIER_HZ);
+        enqueue_and_reply(&mut context.app.transmit_queue, context.bluetooth_manager, request, &description);
+        Ok(())
+    });
+    commands.register("samsung36", &[required("address", ArgKind::Int), required("data", ArgKind::Int)], |context, args| {
+        // samsung36:<地址>:<20位数据>
+        let address = narrow(args.int(0), "address")?;
+        let data = args.int(1).unwrap_or_default();
+        let durations = encode_samsung36(address, data).ok_or(InvalidArgument("data"))?;
+        let description = format!("samsung36 {}:{}", address, data);
+        let request = raw_request(durations, ir::samsung::CARRIER_HZ);
+        enqueue_and_reply(&mut context.app.transmit_queue, context.bluetooth_manager, request, &description);
+        Ok(())
+    });
+    commands.register("pronto", &[required("hex", ArgKind::String)], |context, args| {
+        // pronto:<十六进制字符串>，只有重复序列的码发送一次重复序列
+        match ir::pronto::parse(args.string(0).unwrap_or_default()) {
+            Ok(code) => {
+                let repeats = if code.once.is_empty() { 1 } else { 0 };
+                let description = format!("pronto {}Hz", code.carrier_hz);
+                let request = Ok(TransmitRequest::Pronto { code, repeats });
+                enqueue_and_reply(&mut context.app.transmit_queue, context.bluetooth_manager, request, &description);
+            }
+            Err(e) => {
+                log::warn!("Pronto解析失败: {}", e);
+                let message = match e {
+                    ProntoError::UnsupportedFormat(format) => {
+                        format!("ERROR: unsupported pronto format {:04X}, only 0000 is supported", format)
+                    }
+                    _ => "ERROR: invalid pronto code".to_string(),
+                };
+                context.reply(&message);
+            }
+        }
+        Ok(())
+    });
+    commands.register("selftest", &[], |context, _| {
+        // 发射NEC测试帧并由接收头捕获，检查发射管接线
+        if context.app.selftest.is_some() {
+            context.reply("ERROR: selftest already running");
+        } else if let Some(ticket) = enqueue_and_reply(
+            &mut context.app.transmit_queue,
+            context.bluetooth_manager,
+            raw_request(test_frame(), DEFAULT_CARRIER_HZ),
+            "selftest",
+        ) {
+            context.app.selftest = Some(SelfTest::start(ticket, test_frame()));
+        }
+        Ok(())
+    });
+    commands.register("power", &[optional("1-100|warmup", ArgKind::String), optional("us", ArgKind::Int)], |context, args| {
+        // power查询，power:<百分比>设置发射功率，power:warmup:<微秒>设置预热时间
+        if !cfg!(feature = "tx-power") {
+            context.reply("ERROR: tx power control not supported");
+            return Ok(());
+        }
+        let tx_power = &context.app.tx_power;
+        let settings = &context.app.settings;
+        let saved = match (args.string(0), args.int(1)) {
+            (None, None) => Ok(()),
+            (Some("warmup"), Some(value)) if value <= MAX_WARM_UP_US => {
+                tx_power.set_warm_up_us(value);
+                settings.set_warm_up_us(value)
+            }
+            (Some("warmup"), _) => {
+                context.reply(&format!("ERROR: usage power:<1-100> or power:warmup:<0-{}>", MAX_WARM_UP_US));
+                return Ok(());
+            }
+            (Some(percent), None) => match percent.parse::<u8>() {
+                Ok(percent) if (1..=100).contains(&percent) => {
+                    tx_power.set_tx_power(percent);
+                    settings.set_tx_power(percent)
+                }
+                _ => return Err(InvalidArgument("1-100|warmup")),
+            },
+            _ => return Err(InvalidArgument("1-100|warmup")),
+        };
+        if let Err(e) = saved {
+            log::error!("保存发射功率失败: {:?}", e);
+        }
+        let message = format!("POWER: {}% warmup={}us", tx_power.tx_power(), tx_power.warm_up_us());
+        log::info!("发射功率: {}", message);
+        context.reply(&message);
+        Ok(())
+    });
+    let params = [required("name", ArgKind::String), required("slot[:delay_ms],...", ArgKind::String)];
+    commands.register("macro", &params, |context, args| {
+        // macro:<名称>:<槽>[:<延时毫秒>],<槽>[:<延时毫秒>],...
+        let name = args.string(0).unwrap_or_default();
+        let result = match Macro::parse(args.string(1).unwrap_or_default()) {
+            Some(steps) => context.app.macro_store.save(name, &steps),
+            None => Err(MacroError::InvalidSteps),
+        };
+        match result {
+            Ok(()) => {
+                log::info!("已保存宏: {}", name);
+                context.reply(&format!("MACRO_SAVED: {}", name));
+            }
+            Err(e) => {
+                log::warn!("{}", e);
+                context.reply(&macro_error_reply(&e));
+            }
+        }
+        Ok(())
+    });
+    commands.register("run", &[required("macro", ArgKind::String)], |context, args| {
+        // run:<名称>，宏在后台执行，进度以MACRO_STEP事件上报
+        let name = args.string(0).unwrap_or_default();
+        let result = match context.app.macro_store.load(name) {
+            Ok(Some(steps)) => context.app.macro_runner.run_macro(name, steps).map(|_| true),
+            Ok(None) => Ok(false),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(true) => log::info!("开始执行宏: {}", name),
+            Ok(false) => context.reply(&format!("ERROR: unknown macro {}", name)),
+            Err(e) => {
+                log::warn!("{}", e);
+                context.reply(&macro_error_reply(&e));
+            }
+        }
+        Ok(())
+    });
+    commands.register("macro_delete", &[required("macro", ArgKind::String)], |context, args| {
+        let name = args.string(0).unwrap_or_default();
+        match context.app.macro_store.delete(name) {
+            Ok(true) => {
+                log::info!("已删除宏: {}", name);
+                context.reply(&format!("MACRO_DELETED: {}", name));
+            }
+            Ok(false) => context.reply(&format!("ERROR: unknown macro {}", name)),
+            Err(e) => {
+                log::warn!("{}", e);
+                context.reply(&macro_error_reply(&e));
+            }
+        }
+        Ok(())
+    });
+}
+
+/// 录制和遥控器的管理、导入导出
+fn register_storage_commands(commands: &mut CommandRegistry) {
+    commands.register("list", &[optional("page", ArgKind::Int)], |context, args| {
+        // list[:<页码>]，每页列出10个录制，每行一个，以空行结束
+        let page = args.int(0).unwrap_or(1) as usize;
+        if page == 0 {
+            return Err(InvalidArgument("page"));
+        }
+        let slots = context.app.store.lock().unwrap().list();
+        let pages = slots.len().div_ceil(LIST_PAGE_SIZE).max(1);
+        let mut text = format!("SLOTS: page {}/{} total {}\n", page, pages, slots.len());
+        for slot in slots.iter().skip((page - 1) * LIST_PAGE_SIZE).take(LIST_PAGE_SIZE) {
+            text.push_str(&format!(
+                "SLOT: {} {} {} pulses {} bytes uses={}",
+                slot.name,
+                slot.protocol.name(),
+                slot.pulse_count,
+                slot.size,
+                slot.uses
+            ));
+            if let Some(created) = slot.created {
+                text.push_str(&format!(" created={}", created));
+            }
+            if let Some(last_used) = slot.last_used {
+                text.push_str(&format!(" last_used={}", last_used));
+            }
+            if let Some(quality) = slot.quality {
+                text.push_str(&format!(" quality={}", quality));
+            }
+            if slot.protected {
+                text.push_str(" protected");
+            }
+            if !slot.label.is_empty() {
+                text.push_str(&format!(" label={}", slot.label));
+            }
+            text.push('\n');
+        }
+        text.push('\n');
+        if let Err(e) = context.bluetooth_manager.send_chunked(text.as_bytes()) {
+            log::error!("发送录制列表失败: {:?}", e);
+        }
+        Ok(())
+    });
+    commands.register_frame(Opcode::List, |context, _| {
+        // 名称以换行分隔
+        let slots = context.app.store.lock().unwrap().list();
+        let names = slots.into_iter().map(|slot| slot.name).collect::<Vec<_>>();
+        (Status::Ok, names.join("\n").into_bytes())
+    });
+    commands.register_frame(Opcode::Delete, |context, request| {
+        let Request::Delete(slot) = request else {
+            return (Status::InvalidPayload, Vec::new());
+        };
+        let slot = canonical_path(&slot);
+        let deleted = context.app.store.lock().unwrap().delete(&slot);
+        match deleted {
+            Ok(true) => {
+                context.app.matcher.remove(&slot);
+                context.app.match_colors.remove(&slot);
+                log::info!("已删除录制: {}", slot);
+                (Status::Ok, Vec::new())
+            }
+            Ok(false) => (Status::NotFound, Vec::new()),
+            Err(e) => {
+                log::error!("删除录制{}失败: {}", slot, e);
+                (Error::from(e).status(), Vec::new())
+            }
+        }
+    });
+    commands.register("label", &[required("name", ArgKind::String), optional("text", ArgKind::String)], |context, args| {
+        // label:<名称>:<标签>，标签为空时清除
+        let slot = args.string(0).unwrap_or_default();
+        let result = context.app.store.lock().unwrap().set_label(slot, args.string(1).unwrap_or_default());
+        match result {
+            Ok(()) => {
+                log::info!("已设置录制{}的标签", slot);
+                context.reply(&format!("LABELED: {}", slot));
+            }
+            Err(StorageError::UnknownSlot) => context.reply(&format!("ERROR: unknown slot {}", slot)),
+            Err(e) => {
+                log::warn!("{}", e);
+                context.reply(&storage_error_reply(&e));
+            }
+        }
+        Ok(())
+    });
+    commands.register("dump", &[required("name", ArgKind::String)], |context, args| {
+        // dump:<名称>，以缩进的JSON返回录制的协议、解码字段、载波和全部时长
+        let name = args.string(0).unwrap_or_default();
+        let capture = context.app.store.lock().unwrap().load(name);
+        let Some(capture) = capture else {
+            context.reply(&format!("ERROR: unknown slot {}", name));
+            return Ok(());
+        };
+        let mut writer = context.bluetooth_manager.chunk_writer();
+        let result = ir::dump::write_json(&mut writer, &canonical_path(name), &capture);
+        if let Err(e) = writer.finish() {
+            log::error!("发送录制{}的JSON失败: {:?}", name, e);
+        } else if result.is_err() {
+            log::error!("生成录制{}的JSON失败", name);
+        }
+        Ok(())
+    });
+    let params = [
+        required("name", ArgKind::String),
+        required("period_ms|auto", ArgKind::String),
+        optional("gap_ms", ArgKind::Int),
+    ];
+    commands.register("timing", &params, |context, args| {
+        // timing:<名称>:<帧周期毫秒>:<最小空闲毫秒>，或timing:<名称>:auto恢复按协议查表
+        let slot = args.string(0).unwrap_or_default();
+        let update = match (args.string(1).unwrap_or_default(), args.int(2)) {
+            ("auto", None) => None,
+            ("auto", Some(_)) => return Err(InvalidArgument("gap_ms")),
+            (period, gap) => {
+                let period = period.parse::<u32>().map_err(|_| InvalidArgument("period_ms|auto"))?;
+                let gap = gap.ok_or(InvalidArgument("gap_ms"))?;
+                if period > MAX_TIMING_MS || gap > MAX_TIMING_MS {
+                    context.reply(&format!("ERROR: period and gap must be 0-{}ms", MAX_TIMING_MS));
+                    return Ok(());
+                }
+                Some((period, gap))
+            }
+        };
+        let mut store = context.app.store.lock().unwrap();
+        // 重复方式仍按识别出的协议决定
+        let Some(capture) = store.load(slot) else {
+            drop(store);
+            context.reply(&format!("ERROR: unknown slot {}", slot));
+            return Ok(());
+        };
+        let repeat = protocol_timing(detect_and_decode(capture.durations()).protocol()).repeat;
+        let timing = update.map(|(period, gap)| ProtocolTiming {
+            frame_period_us: period * 1000,
+            min_gap_us: gap * 1000,
+            repeat,
+        });
+        let message = match (store.set_timing(slot, timing), update) {
+            (Err(e), _) => {
+                log::error!("保存发射时序失败: {}", e);
+                storage_error_reply(&e)
+            }
+            (Ok(_), Some((period, gap))) => format!("TIMING: {} period={}ms gap={}ms", slot, period, gap),
+            (Ok(_), None) => format!("TIMING: {} auto", slot),
+        };
+        drop(store);
+        log::info!("发射时序: {}", message);
+        context.reply(&message);
+        Ok(())
+    });
+    commands.register("protect", &[required("name", ArgKind::String), required("on|off", ArgKind::String)], |context, args| {
+        // protect:<名称>:on|off，受保护的录制不会被淘汰
+        let slot = args.string(0).unwrap_or_default();
+        let protected = parse_on_off(args.string(1))?;
+        let result = context.app.store.lock().unwrap().set_protected(slot, protected);
+        match result {
+            Ok(()) => {
+                log::info!("录制{}保护: {}", slot, protected);
+                let state = if protected { "on" } else { "off" };
+                context.reply(&format!("PROTECTED: {} {}", slot, state));
+            }
+            Err(StorageError::UnknownSlot) => context.reply(&format!("ERROR: unknown slot {}", slot)),
+            Err(e) => {
+                log::warn!("{}", e);
+                context.reply(&storage_error_reply(&e));
+            }
+        }
+        Ok(())
+    });
+    commands.register("evict", &[required("on|off", ArgKind::String)], |context, args| {
+        // evict:on|off，空间不足时是否淘汰最久没有使用的录制
+        let evict = parse_on_off(args.string(0))?;
+        context.app.store.lock().unwrap().set_eviction(evict);
+        if let Err(e) = context.app.settings.set_lru_evict(evict) {
+            log::error!("保存淘汰设置失败: {:?}", e);
+        }
+        let state = args.string(0).unwrap_or_default();
+        log::info!("录制淘汰: {}", state);
+        context.reply(&format!("EVICT: {}", state));
+        Ok(())
+    });
+    commands.register("remotes", &[], |context, _| {
+        // 每个遥控器一行，以空行结束
+        let text = {
+            let store = context.app.store.lock().unwrap();
+            let remotes = store.remotes();
+            let mut text = format!("REMOTES: {}\n", remotes.len());
+            for remote in &remotes {
+                let keys = store.list_remote(remote).map_or(0, |slots| slots.len());
+                text.push_str(&format!("REMOTE: {} keys={}\n", remote, keys));
+            }
+            text
+        };
+        if let Err(e) = context.bluetooth_manager.send_chunked(format!("{}\n", text).as_bytes()) {
+            log::error!("发送遥控器列表失败: {:?}", e);
+        }
+        Ok(())
+    });
+    commands.register("keys", &[optional("remote", ArgKind::String)], |context, args| {
+        // keys:<遥控器>，列出遥控器中的按键，以空行结束
+        let remote = args.string(0).unwrap_or(ir::storage::DEFAULT_REMOTE);
+        let slots = context.app.store.lock().unwrap().list_remote(remote);
+        let Some(slots) = slots else {
+            context.reply(&format!("ERROR: unknown remote {}", remote));
+            return Ok(());
+        };
+        let mut text = format!("KEYS: {} total {}\n", remote, slots.len());
+        for slot in &slots {
+            let (_, key) = split_path(&slot.name);
+            text.push_str(&format!("KEY: {} {}\n", key, slot.protocol.name()));
+        }
+        text.push('\n');
+        if let Err(e) = context.bluetooth_manager.send_chunked(text.as_bytes()) {
+            log::error!("发送按键列表失败: {:?}", e);
+        }
+        Ok(())
+    });
+    commands.register("remote_create", &[required("remote", ArgKind::String)], |context, args| {
+        let remote = args.string(0).unwrap_or_default();
+        let result = context.app.store.lock().unwrap().create_remote(remote);
+        match result {
+            Ok(true) => {
+                log::info!("已创建遥控器: {}", remote);
+                context.reply(&format!("REMOTE_CREATED: {}", remote));
+            }
+            Ok(false) => context.reply(&format!("ERROR: remote {} already exists", remote)),
+            Err(e) => {
+                log::warn!("{}", e);
+                context.reply(&storage_error_reply(&e));
+            }
+        }
+        Ok(())
+    });
+    commands.register("remote_rename", &[required("old", ArgKind::String), required("new", ArgKind::String)], |context, args| {
+        // remote_rename:<原名称>:<新名称>，参考码和LED颜色随之改名
+        let (old, new) = (args.string(0).unwrap_or_default(), args.string(1).unwrap_or_default());
+        let result = context.app.store.lock().unwrap().rename_remote(old, new);
+        match result {
+            Ok(renamed) => {
+                for (from, to) in &renamed {
+                    context.app.matcher.rename(from, to);
+                    if let Some(color) = context.app.match_colors.remove(from) {
+                        context.app.match_colors.insert(to.clone(), color);
+                    }
+                }
+                log::info!("已把遥控器{}重命名为{}", old, new);
+                context.reply(&format!("REMOTE_RENAMED: {} {}", old, new));
+            }
+            Err(StorageError::UnknownRemote) => context.reply(&format!("ERROR: unknown remote {}", old)),
+            Err(e) => {
+                log::warn!("{}", e);
+                context.reply(&storage_error_reply(&e));
+            }
+        }
+        Ok(())
+    });
+    commands.register("remote_delete", &[required("remote", ArgKind::String)], |context, args| {
+        // remote_delete:<遥控器>，删除遥控器和它的所有录制
+        let remote = args.string(0).unwrap_or_default();
+        let result = context.app.store.lock().unwrap().delete_remote(remote);
+        let deleted = match &result {
+            Ok(deleted) | Err(StorageError::PartialDelete { deleted, .. }) => deleted.as_slice(),
+            Err(_) => &[],
+        };
+        for slot in deleted {
+            context.app.matcher.remove(slot);
+            context.app.match_colors.remove(slot);
+        }
+        match result {
+            Ok(deleted) => {
+                log::info!("已删除遥控器{}，{}个录制", remote, deleted.len());
+                context.reply(&format!("REMOTE_DELETED: {} {} codes", remote, deleted.len()));
+            }
+            Err(StorageError::UnknownRemote) => context.reply(&format!("ERROR: unknown remote {}", remote)),
+            Err(e) => {
+                log::error!("{}", e);
+                context.reply(&storage_error_reply(&e));
+            }
+        }
+        Ok(())
+    });
+    commands.register_ble_only("export", &[optional("name", ArgKind::String)], |context, args| {
+        // 传输期间使用短连接间隔，结束后恢复默认参数
+        let bluetooth_manager = context.bluetooth_manager;
+        let Some(name) = args.string(0) else {
+            bluetooth_manager.set_reply_link_mode(LinkMode::LowLatency);
+            export_archive(&context.app.store, bluetooth_manager);
+            bluetooth_manager.set_reply_link_mode(LinkMode::Balanced);
+            return Ok(());
+        };
+        // export:<名称>，只导出一个录制，格式与完整归档相同
+        let slot = canonical_path(name);
+        let bytes = context.app.store.lock().unwrap().read(&slot);
+        let Some(bytes) = bytes else {
+            context.reply(&format!("ERROR: unknown slot {}", slot));
+            return Ok(());
+        };
+        let mut archive = ArchiveWriter::new();
+        let mut data = archive.slot(&slot, &bytes);
+        data.extend_from_slice(&archive.finish());
+        bluetooth_manager.set_reply_link_mode(LinkMode::LowLatency);
+        match bluetooth_manager.send_large(&data) {
+            Ok(()) => log::info!("已导出录制{}", slot),
+            Err(e) => log::warn!("导出中止: {}", e),
+        }
+        bluetooth_manager.set_reply_link_mode(LinkMode::Balanced);
+        Ok(())
+    });
+    let params = [optional("skip|overwrite|rename", ArgKind::String), optional("dry_run", ArgKind::Flag)];
+    commands.register_ble_only("import", &params, |context, args| {
+        // import[:skip|overwrite|rename][:dry_run]，回复IMPORT_READY后客户端开始发送export格式的归档
+        let (policy, dry_run) = match (args.string(0), args.flag(1)) {
+            // import:dry_run省略了冲突处理方式
+            (Some("dry_run"), false) => (None, true),
+            (policy, dry_run) => (policy, dry_run),
+        };
+        let policy = match policy {
+            Some(policy) => CollisionPolicy::from_name(policy).ok_or(InvalidArgument("skip|overwrite|rename"))?,
+            None => CollisionPolicy::default(),
+        };
+        let Some(conn_id) = context.conn_id else {
+            return Ok(());
+        };
+        context.app.import = Some((conn_id, ArchiveImport::new(context.now, policy, dry_run)));
+        // 导入结束后恢复默认参数，超时中止时由空闲检测切换为低功耗
+        context.bluetooth_manager.set_reply_link_mode(LinkMode::LowLatency);
+        log::info!("开始导入录制: {:?}, 试导入: {}", policy, dry_run);
+        context.reply("IMPORT_READY");
+        Ok(())
+    });
+    commands.register("pronto_export", &[required("name", ArgKind::String)], |context, args| {
+        // pronto_export:<名称>，把学习过的录制导出为Pronto字符串
+        let name = args.string(0).unwrap_or_default();
+        let capture = context.app.store.lock().unwrap().load(name);
+        match capture {
+            Some(capture) => {
+                let mut code = ir::pronto::from_capture(&capture);
+                // 字符串可能跨多个分段，以换行表示结束
+                code.push('\n');
+                if let Err(e) = context.bluetooth_manager.send_chunked(code.as_bytes()) {
+                    log::error!("发送Pronto码失败: {:?}", e);
+                }
+            }
+            None => context.reply(&format!("ERROR: unknown slot {}", name)),
+        }
+        Ok(())
+    });
+    let params = [required("name", ArgKind::String), required("base64", ArgKind::String)];
+    commands.register("import_broadlink", &params, |context, args| {
+        // import_broadlink:<名称>:<base64>，保存为录制并作为参考码
+        let slot = args.string(0).unwrap_or_default();
+        let capture = match ir::broadlink::parse_base64(args.string(1).unwrap_or_default()) {
+            Ok(capture) => capture,
+            Err(e) => {
+                log::warn!("Broadlink码解析失败: {}", e);
+                let message = match e {
+                    BroadlinkError::InvalidBase64 => "ERROR: invalid base64".to_string(),
+                    BroadlinkError::Rf(kind) => format!("ERROR: RF packet {:#04x} not supported, only IR (0x26)", kind),
+                    _ => "ERROR: invalid broadlink packet".to_string(),
+                };
+                context.reply(&message);
+                return Ok(());
+            }
+        };
+        let app = &mut *context.app;
+        match save_slot(slot, &capture, true, &app.store, &mut app.matcher, context.bluetooth_manager) {
+            Ok(()) => {
+                log::info!("已导入Broadlink码: {}", slot);
+                reply(context.bluetooth_manager, &format!("SAVED: {}", slot));
+            }
+            Err(e) => {
+                log::warn!("保存录制{}失败: {}", slot, e);
+                reply(context.bluetooth_manager, &storage_error_reply(&e));
+            }
+        }
+        Ok(())
+    });
+    commands.register("broadlink_export", &[required("name", ArgKind::String)], |context, args| {
+        // broadlink_export:<名称>，把录制导出为base64的Broadlink数据包
+        let name = args.string(0).unwrap_or_default();
+        let capture = context.app.store.lock().unwrap().load(name);
+        match capture {
+            Some(capture) => {
+                let packet = format!("{}\n", ir::broadlink::to_base64(&capture));
+                if let Err(e) = context.bluetooth_manager.send_chunked(packet.as_bytes()) {
+                    log::error!("发送Broadlink码失败: {:?}", e);
+                }
+            }
+            None => context.reply(&format!("ERROR: unknown slot {}", name)),
+        }
+        Ok(())
+    });
+    commands.register("lirc", &[optional("remote", ArgKind::String)], |context, args| {
+        export_lirc(args.string(0).unwrap_or_default(), &context.app.store, context.bluetooth_manager);
+        Ok(())
+    });
+    commands.register("flipper_export", &[optional("name", ArgKind::String)], |context, args| {
+        export_flipper(args.string(0).unwrap_or_default(), &context.app.store, context.bluetooth_manager);
+        Ok(())
+    });
+    commands.register_ble_only("flipper_import", &[], |context, _| {
+        // 回复FLIPPER_READY后客户端发送.ir文件的文本，以单独一行END结束
+        let Some(conn_id) = context.conn_id else {
+            return Ok(());
+        };
+        context.app.flipper_import = Some((conn_id, FlipperImport::new(context.now)));
+        context.bluetooth_manager.set_reply_link_mode(LinkMode::LowLatency);
+        log::info!("开始导入Flipper文件");
+        context.reply("FLIPPER_READY");
+        Ok(())
+    });
+    commands.register("factory_reset", &[optional("nonce", ArgKind::Hex)], |context, args| {
+        // 先发送factory_reset获取随机数，10秒内发送factory_reset:<随机数>确认
+        match (args.hex(0), context.app.pending_reset.take()) {
+            (None, _) => {
+                let reset = PendingReset::new(context.now);
+                log::warn!("等待确认恢复出厂设置");
+                context.reply(&format!("FACTORY_RESET_CONFIRM: {}", reset.nonce()));
+                context.app.pending_reset = Some(reset);
+            }
+            (Some(nonce), Some(reset)) if reset.confirms(nonce) => {
+                reset_to_factory(&mut context.app.led, &context.app.store, context.bluetooth_manager);
+            }
+            (Some(_), Some(_)) => {
+                log::warn!("恢复出厂设置的随机数不正确，已取消");
+                if let Err(e) = context.app.led.set_color(RgbColor::black()) {
+                    log::warn!("设置LED失败: {:?}", e);
+                }
+                context.reply("ERROR: invalid nonce, factory reset cancelled");
+            }
+            (Some(_), None) => context.reply("ERROR: no factory reset pending"),
+        }
+        Ok(())
+    });
+}
+
+/// 设备状态、蓝牙、电池和配置的命令
+fn register_system_commands(commands: &mut CommandRegistry) {
+    commands.register("status", &[], |context, _| {
+        let app = &*context.app;
+        context.reply(&app.self_check_report);
+        let pulses = app.session.pending().map_or(0, |capture| capture.pulse_count());
+        context.reply(&format!("RECORD_STATUS: {} pending={} pulses", app.session.state().name(), pulses));
+        let bluetooth_manager = context.bluetooth_manager;
+        context.reply(&format!(
+            "BLE_CONNECTIONS: {}/{} rejected={}",
+            bluetooth_manager.connection_count(),
+            MAX_CONNECTIONS,
+            bluetooth_manager.rejected_connections()
+        ));
+        context.reply(&format!(
+            "BLE_QUEUE: dropped={} congestion_stalls={}",
+            bluetooth_manager.dropped_messages(),
+            bluetooth_manager.congestion_stalls()
+        ));
+        let stats = app.store.lock().unwrap().stats();
+        match stats {
+            Ok(stats) => context.reply(&format!(
+                "STORAGE: slots={} slot_bytes={} used_entries={} free_entries={} total_entries={} free_bytes={}",
+                stats.slot_count,
+                stats.slot_bytes,
+                stats.used_entries,
+                stats.free_entries,
+                stats.total_entries,
+                stats.free_bytes()
+            )),
+            Err(e) => log::error!("读取NVS使用情况失败: {}", e),
+        }
+        context.reply(&led_power_status(&app.led));
+        context.reply(&format!(
+            "POWER: state={} power_save={} slept={}s",
+            app.power_save.state().name(),
+            if app.power_save.enabled() { "on" } else { "off" },
+            app.power_save.slept(context.now).as_secs()
+        ));
+        context.reply(&wifi_status(app.wifi.as_ref()));
+        context.reply(&mqtt_status(app.mqtt.as_ref()));
+        let health = Health::collect(app.boots, app.ir_captures, bluetooth_manager);
+        context.reply(&format!("HEALTH: {}", health.to_json()));
+        Ok(())
+    });
+    commands.register_frame(Opcode::Status, |context, _| {
+        // 会话状态（1字节）、待保存的脉冲数量（u16）、录制数量（u16）和NVS空闲字节数（u32）
+        let session = &context.app.session;
+        let state = session.state().code();
+        let pulses = session.pending().map_or(0, |capture| capture.pulse_count());
+        let (slots, free_bytes) = match context.app.store.lock().unwrap().stats() {
+            Ok(stats) => (stats.slot_count, stats.free_bytes()),
+            Err(e) => {
+                log::warn!("读取NVS使用情况失败: {}", e);
+                (0, 0)
+            }
+        };
+        let mut payload = vec![state];
+        payload.extend_from_slice(&(pulses.min(u16::MAX as usize) as u16).to_le_bytes());
+        payload.extend_from_slice(&(slots.min(u16::MAX as usize) as u16).to_le_bytes());
+        payload.extend_from_slice(&(free_bytes.min(u32::MAX as usize) as u32).to_le_bytes());
+        (Status::Ok, payload)
+    });
+    commands.register_ble_only("resync", &[optional("last_seen_seq 0-65535", ArgKind::Int)], |context, args| {
+        let bluetooth_manager = context.bluetooth_manager;
+        let Some(last_seen) = args.int(0) else {
+            let seq = bluetooth_manager.event_seq().map_or("none".to_string(), |seq| seq.to_string());
+            context.reply(&format!("SEQ: {}", seq));
+            return Ok(());
+        };
+        // resync:<最后收到的序号>，重发之后的事件，只发给发出命令的客户端
+        let last_seen = narrow(Some(last_seen), "last_seen_seq 0-65535")?;
+        match bluetooth_manager.resync(last_seen) {
+            Ok(events) => {
+                log::info!("重发{}个事件: 最后收到{}", events.len(), last_seen);
+                for event in &events {
+                    if let Err(e) = bluetooth_manager.send_data(event) {
+                        log::error!("重发事件失败: {:?}", e);
+                        break;
+                    }
+                }
+                context.reply(&format!("RESYNC: {} events", events.len()));
+            }
+            Err(gap) => {
+                log::warn!("{}", gap);
+                let latest = gap.latest.map_or("none".to_string(), |seq| seq.to_string());
+                context.reply(&format!("RESYNC_GAP: latest={}, do a full refresh", latest));
+            }
+        }
+        Ok(())
+    });
+    commands.register("rssi", &[], |context, _| {
+        // 读取每个连接的信号强度，用于调整设备摆放位置
+        let readings: Vec<String> = context
+            .bluetooth_manager
+            .read_rssi()
+            .into_iter()
+            .map(|(peer, rssi)| match rssi {
+                Some(rssi) => format!("{} {}dBm", peer, rssi),
+                None => format!("{} unknown", peer),
+            })
+            .collect();
+        let message = format!("RSSI: {}", readings.join(", "));
+        log::info!("信号强度: {}", message);
+        context.reply(&message);
+        Ok(())
+    });
+    commands.register("crashlog", &[], |context, _| {
+        // 读取后清除，之后的崩溃重新计数
+        match context.app.crash_log.take() {
+            Ok(Some(crash)) => context.reply(&format!("CRASHLOG: count={} {}", crash.count, crash.message)),
+            Ok(None) => context.reply("CRASHLOG: none"),
+            Err(e) => {
+                log::error!("读取崩溃记录失败: {:?}", e);
+                context.reply("ERROR: storage failed");
+            }
+        }
+        Ok(())
+    });
+    let params = [optional("on|off|pin|divider", ArgKind::String), optional("value", ArgKind::String)];
+    commands.register("battery", &params, |context, args| {
+        // battery查询，battery:on|off打开或关闭电池服务，battery:pin:<GPIO>和battery:divider:<分压比>修改电路参数
+        let battery = &mut context.app.battery;
+        let settings = &context.app.settings;
+        let saved = match (args.string(0), args.string(1)) {
+            (None, None) => Some(Ok(())),
+            (Some(state @ ("on" | "off")), None) => {
+                let enabled = state == "on";
+                battery.set_enabled(enabled);
+                if let Err(e) = context.bluetooth_manager.set_battery_enabled(enabled) {
+                    log::error!("切换电池服务失败: {:?}", e);
+                }
+                Some(settings.set_battery_enabled(enabled))
+            }
+            (Some("pin"), Some(value)) => match value.parse::<u8>() {
+                Ok(pin) if ADC_PINS.contains(&pin) => {
+                    battery.set_pin(pin);
+                    Some(settings.set_battery_pin(pin))
+                }
+                _ => None,
+            },
+            (Some("divider"), Some(value)) => match value.parse::<f32>() {
+                Ok(ratio) if DIVIDER_RANGE_PERMILLE.contains(&((ratio * 1000.0).round() as u32)) => {
+                    let divider = (ratio * 1000.0).round() as u32;
+                    battery.set_divider_permille(divider);
+                    Some(settings.set_battery_divider_permille(divider))
+                }
+                _ => None,
+            },
+            (Some("pin" | "divider"), None) => return Err(InvalidArgument("value")),
+            _ => return Err(InvalidArgument("on|off|pin|divider")),
+        };
+        let Some(saved) = saved else {
+            context.reply(&format!(
+                "ERROR: usage battery:on|off, battery:pin:<{}-{}> or battery:divider:<1.0-10.0>",
+                ADC_PINS.start(),
+                ADC_PINS.end()
+            ));
+            return Ok(());
+        };
+        if let Err(e) = saved {
+            log::error!("保存电池设置失败: {:?}", e);
+        }
+        let level = match (battery.enabled(), battery.reading()) {
+            (false, _) => "off".to_string(),
+            (true, None) => "no reading".to_string(),
+            (true, Some(reading)) => format!("{}% {}mV", reading.percent, reading.millivolts),
+        };
+        let divider = battery.divider_permille();
+        let message = format!(
+            "BATTERY: {} pin={} divider={}.{:03}",
+            level,
+            battery.pin(),
+            divider / 1000,
+            divider % 1000
+        );
+        log::info!("电池: {}", message);
+        reply(context.bluetooth_manager, &message);
+        Ok(())
+    });
+    commands.register("set_name", &[required("name", ArgKind::String)], |context, args| {
+        // set_name:<名称>，修改蓝牙设备名称并重新配置广播，重启后仍然有效
+        let name = args.string(0).unwrap_or_default();
+        if !check_device_name(name) {
+            context.reply(&format!(
+                "ERROR: name must be 1-{} bytes without control characters",
+                MAX_DEVICE_NAME_LEN
+            ));
+            return Ok(());
+        }
+        let settings = &mut context.app.settings;
+        if let Err(e) = context.bluetooth_manager.set_identity(name, settings.user_id()) {
+            log::error!("重新配置广播失败: {:?}", e);
+        }
+        if let Err(e) = settings.set_device_name(name) {
+            log::error!("保存设备名称失败: {:?}", e);
+        }
+        log::info!("蓝牙设备名称: {}", name);
+        context.reply(&format!("NAME: {}", name));
+        Ok(())
+    });
+    commands.register("hw_rev", &[optional("revision", ArgKind::String)], |context, args| {
+        let Some(revision) = args.string(0) else {
+            let revision = context.app.settings.hardware_revision();
+            context.reply(&format!("HW_REV: {} firmware={}", revision, FIRMWARE_REVISION));
+            return Ok(());
+        };
+        // hw_rev:<版本>，写入设备信息服务中的硬件版本并保存
+        if !check_hardware_revision(revision) {
+            context.reply(&format!(
+                "ERROR: hardware revision must be 1-{} bytes without control characters",
+                MAX_HARDWARE_REVISION_LEN
+            ));
+            return Ok(());
+        }
+        if let Err(e) = context.bluetooth_manager.set_hardware_revision(revision) {
+            log::error!("更新硬件版本失败: {:?}", e);
+        }
+        if let Err(e) = context.app.settings.set_hardware_revision(revision) {
+            log::error!("保存硬件版本失败: {:?}", e);
+        }
+        log::info!("硬件版本: {}", revision);
+        context.reply(&format!("HW_REV: {} firmware={}", revision, FIRMWARE_REVISION));
+        Ok(())
+    });
+    commands.register("set_user_id", &[required("0-65535|off", ArgKind::String)], |context, args| {
+        // set_user_id:<0-65535>放入广播的厂商数据，set_user_id:off不放
+        let user_id = match args.string(0).unwrap_or_default() {
+            "off" => None,
+            user_id => Some(parse_number::<u16>(user_id).ok_or(InvalidArgument("0-65535|off"))?),
+        };
+        let settings = &mut context.app.settings;
+        if let Err(e) = context.bluetooth_manager.set_identity(&settings.device_name(), user_id) {
+            log::error!("重新配置广播失败: {:?}", e);
+        }
+        if let Err(e) = settings.set_user_id(user_id) {
+            log::error!("保存用户编号失败: {:?}", e);
+        }
+        let message = match user_id {
+            Some(user_id) => format!("USER_ID: {}", user_id),
+            None => "USER_ID: off".to_string(),
+        };
+        log::info!("广播用户编号: {}", message);
+        context.reply(&message);
+        Ok(())
+    });
+    commands.register("passkey", &[required("6 digits", ArgKind::String)], |context, args| {
+        // passkey:<6位数字>，修改之后配对使用的配对码并保存，已绑定的设备不受影响
+        let text = args.string(0).unwrap_or_default();
+        let passkey = text.parse::<u32>().ok().filter(|&passkey| text.len() == 6 && passkey <= MAX_PASSKEY);
+        let passkey = passkey.ok_or(InvalidArgument("6 digits"))?;
+        if let Err(e) = context.bluetooth_manager.set_passkey(passkey) {
+            log::error!("设置配对码失败: {:?}", e);
+        }
+        if let Err(e) = context.app.settings.set_passkey(passkey) {
+            log::error!("保存配对码失败: {:?}", e);
+        }
+        log::info!("配对码已修改");
+        context.reply("PASSKEY: updated");
+        Ok(())
+    });
+    commands.register("lock", &[optional("pin|off", ArgKind::String)], |context, args| {
+        let bluetooth_manager = context.bluetooth_manager;
+        let settings = &mut context.app.settings;
+        match args.string(0) {
+            None => {
+                let locked = if settings.lock_pin().is_some() { "on" } else { "off" };
+                context.reply(&format!("LOCK: {}", locked));
+            }
+            Some("off") => {
+                // lock:off，恢复为允许所有写入；锁定时只有已授权的客户端能发出这条命令
+                bluetooth_manager.set_write_policy(Arc::new(AllowAll));
+                if let Err(e) = settings.set_lock_pin(None) {
+                    log::error!("保存锁定状态失败: {:?}", e);
+                }
+                log::info!("蓝牙写入已解除锁定");
+                context.reply("LOCK: off");
+            }
+            Some(pin) if check_pin(pin) => {
+                // lock:<PIN>，设置PIN并锁定写入，发出命令的客户端保持授权
+                bluetooth_manager.set_write_policy(Arc::new(Locked::default()));
+                if let Some(conn_id) = context.conn_id {
+                    bluetooth_manager.authorize(conn_id);
+                }
+                if let Err(e) = settings.set_lock_pin(Some(pin)) {
+                    log::error!("保存锁定状态失败: {:?}", e);
+                }
+                log::info!("蓝牙写入已锁定");
+                context.reply("LOCK: on");
+            }
+            Some(_) => context.reply(&format!(
+                "ERROR: usage lock:<{}-{} digit pin> or lock:off",
+                PIN_LEN.start(),
+                PIN_LEN.end()
+            )),
+        }
+        Ok(())
+    });
+    commands.register_ble_only("unlock", &[required("pin", ArgKind::String)], |context, args| {
+        // unlock:<PIN>，授权本次连接写入，断开后失效
+        let Some(conn_id) = context.conn_id else {
+            return Ok(());
+        };
+        match context.app.settings.lock_pin() {
+            Some(pin) if pin != args.string(0).unwrap_or_default() => {
+                log::warn!("解锁PIN错误: conn_id={}", conn_id);
+                context.reply("ERROR: wrong pin");
+            }
+            _ => {
+                context.bluetooth_manager.authorize(conn_id);
+                context.reply("UNLOCKED");
+            }
+        }
+        Ok(())
+    });
+    let params = [optional("on|off|add|remove", ArgKind::String), optional("addr", ArgKind::String)];
+    commands.register("whitelist", &params, |context, args| {
+        // whitelist查询，whitelist:on|off打开或关闭，whitelist:add添加发出命令的客户端，
+        // whitelist:add:<地址>和whitelist:remove:<地址>修改名单
+        let bluetooth_manager = context.bluetooth_manager;
+        let (mut enabled, mut peers) = bluetooth_manager.whitelist();
+        let (action, addr) = (args.string(0), args.string(1));
+        let peer = match (action, addr) {
+            (Some("add"), None) => context.conn_id.and_then(|conn_id| bluetooth_manager.peer_addr(conn_id)),
+            (Some("add" | "remove"), Some(addr)) => Some(parse_addr(addr).ok_or(InvalidArgument("addr"))?),
+            _ => None,
+        };
+        let changed = match (action, peer) {
+            (None, _) if addr.is_none() => Ok(false),
+            (Some(state @ ("on" | "off")), _) if addr.is_none() => {
+                enabled = state == "on";
+                Ok(true)
+            }
+            (Some("add"), Some(peer)) if peers.contains(&peer) => Ok(false),
+            (Some("add"), Some(_)) if peers.len() >= MAX_WHITELIST => {
+                Err(format!("ERROR: whitelist full ({} addresses)", MAX_WHITELIST))
+            }
+            (Some("add"), Some(peer)) => {
+                peers.push(peer);
+                Ok(true)
+            }
+            (Some("remove"), Some(peer)) if peers.contains(&peer) => {
+                peers.retain(|addr| *addr != peer);
+                Ok(true)
+            }
+            (Some("remove"), Some(_)) => Err("ERROR: address not in whitelist".to_string()),
+            (Some("remove"), None) => return Err(InvalidArgument("addr")),
+            _ => return Err(InvalidArgument("on|off|add|remove")),
+        };
+        // 打开时名单不能为空，否则所有设备都无法连接
+        let changed = match changed {
+            Ok(true) if enabled && peers.is_empty() => {
+                Err("ERROR: whitelist is empty, add an address or turn it off first".to_string())
+            }
+            changed => changed,
+        };
+        match changed {
+            Ok(changed) => {
+                if changed {
+                    let settings = &mut context.app.settings;
+                    if let Err(e) = bluetooth_manager.set_whitelist(enabled, &peers) {
+                        log::error!("更新蓝牙连接白名单失败: {:?}", e);
+                    }
+                    if let Err(e) = settings.set_whitelist_enabled(enabled) {
+                        log::error!("保存白名单设置失败: {:?}", e);
+                    }
+                    if let Err(e) = settings.set_whitelist(&peers) {
+                        log::error!("保存白名单失败: {:?}", e);
+                    }
+                }
+                let message = whitelist_reply(enabled, &peers);
+                log::info!("蓝牙连接白名单: {}", message);
+                context.reply(&message);
+            }
+            Err(message) => context.reply(&message),
+        }
+        Ok(())
+    });
+    commands.register("ble_restart", &[], |context, _| {
+        // 关闭并重新初始化蓝牙，所有客户端都会断开，需要重新连接
+        context.reply("BLE_RESTART");
+        log::warn!("重启蓝牙");
+        if let Err(e) = context.bluetooth_manager.restart() {
+            log::error!("重启蓝牙失败: {:?}", e);
+        }
+        Ok(())
+    });
+    let params = [optional("off|error|warn|info|debug|trace", ArgKind::String)];
+    commands.register("log_level", &params, |context, args| {
+        // log_level:<off|error|warn|info|debug|trace>，修改通过蓝牙发送的日志级别并保存
+        if let Some(level) = args.string(0) {
+            let level = level
+                .parse::<log::LevelFilter>()
+                .map_err(|_| InvalidArgument("off|error|warn|info|debug|trace"))?;
+            telemetry::set_level(level);
+            if let Err(e) = context.app.settings.set_log_level(level) {
+                log::error!("保存日志级别失败: {:?}", e);
+            }
+            log::info!("蓝牙日志级别: {}", level);
+        }
+        let level = telemetry::level().as_str().to_lowercase();
+        context.reply(&format!("LOG_LEVEL: {}", level));
+        Ok(())
+    });
+    commands.register("ind_timeout", &[optional("ms", ArgKind::Int)], |context, args| {
+        // ind_timeout:<毫秒>，修改等待客户端确认指示的时间并保存
+        let bluetooth_manager = context.bluetooth_manager;
+        if let Some(value) = args.int(0) {
+            if !INDICATION_TIMEOUT_RANGE_MS.contains(&value) {
+                context.reply(&format!(
+                    "ERROR: ind_timeout must be {}-{}ms",
+                    INDICATION_TIMEOUT_RANGE_MS.start(),
+                    INDICATION_TIMEOUT_RANGE_MS.end()
+                ));
+                return Ok(());
+            }
+            bluetooth_manager.set_indication_timeout(Duration::from_millis(value as u64));
+            if let Err(e) = context.app.settings.set_indication_timeout_ms(value) {
+                log::error!("保存指示确认超时失败: {:?}", e);
+            }
+            log::info!("指示确认超时: {}ms", value);
+        }
+        let timeout = bluetooth_manager.indication_timeout().as_millis();
+        context.reply(&format!("IND_TIMEOUT: {}ms", timeout));
+        Ok(())
+    });
+    commands.register("get_config", &[optional("key", ArgKind::String)], |context, args| {
+        // get_config列出所有配置项，get_config:<项>只查询一项
+        let config = Config::load(&context.app.settings);
+        let keys = match args.string(0).map(str::parse::<ConfigKey>) {
+            None => ConfigKey::ALL.to_vec(),
+            Some(Ok(key)) => vec![key],
+            Some(Err(e)) => {
+                context.reply(&format!("ERROR: {}", config_error_reason(&e)));
+                return Ok(());
+            }
+        };
+        for key in keys {
+            context.reply(&format!("CONFIG: {}={}", key.name(), config.display(key)));
+        }
+        Ok(())
+    });
+    commands.register("set_config", &[required("key", ArgKind::String), required("value", ArgKind::String)], |context, args| {
+        // set_config:<项>:<值>，检查后保存；引脚和RMT重启后生效，其余立即生效
+        let value = args.string(1).unwrap_or_default();
+        let app = &mut *context.app;
+        let bluetooth_manager = context.bluetooth_manager;
+        let mut config = Config::load(&app.settings);
+        let applied = args
+            .string(0)
+            .unwrap_or_default()
+            .parse::<ConfigKey>()
+            .and_then(|key| Ok((key, config.set(key, value)?)));
+        let (key, apply) = match applied {
+            Ok(applied) => applied,
+            Err(e) => {
+                reply(bluetooth_manager, &format!("ERROR: {}", config_error_reason(&e)));
+                return Ok(());
+            }
+        };
+        if let Err(e) = config.save(&mut app.settings, key) {
+            log::error!("保存配置{}失败: {:?}", key.name(), e);
+        }
+        if apply == Apply::Now {
+            let result = match key {
+                ConfigKey::IdleThreshold => {
+                    app.idle_threshold_us.store(config.idle_threshold_us, Ordering::Relaxed);
+                    app.keys.set_idle_threshold(Duration::from_micros(config.idle_threshold_us as u64));
+                    Ok(())
+                }
+                ConfigKey::DeviceName => bluetooth_manager
+                    .set_identity(&config.device_name, app.settings.user_id())
+                    .map_err(Error::Ble),
+                ConfigKey::LedBrightness => app.led.set_brightness(config.led_brightness),
+                ConfigKey::LedTiming => app.led.set_timing(config.led_timing),
+                ConfigKey::BleTxPower => bluetooth_manager.set_tx_power(config.ble_tx_power_dbm).map_err(Error::Ble),
+                ConfigKey::IndicationTimeout => {
+                    bluetooth_manager.set_indication_timeout(Duration::from_millis(config.indication_timeout_ms as u64));
+                    Ok(())
+                }
+                ConfigKey::PowerSave => {
+                    if let Some(state) = app.power_save.set_enabled(config.power_save, context.now) {
+                        apply_power_state(bluetooth_manager, &app.ticker, state);
+                    }
+                    Ok(())
+                }
+                ConfigKey::SleepIdle => {
+                    app.power_save.set_idle_after(config.sleep_idle_s);
+                    Ok(())
+                }
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                log::error!("应用配置{}失败: {:?}", key.name(), e);
+            }
+        }
+        log::info!("配置{}: {}", key.name(), config.display(key));
+        let suffix = if apply == Apply::AfterRestart { " after restart" } else { "" };
+        reply(bluetooth_manager, &format!("CONFIG: {}={}{}", key.name(), config.display(key), suffix));
+        Ok(())
+    });
+}
+
+/// 把Int参数转换为协议字段的类型，超出范围时返回参数名称
+fn narrow<T: TryFrom<u32>>(value: Option<u32>, param: &'static str) -> Result<T, InvalidArgument> {
+    value.and_then(|value| T::try_from(value).ok()).ok_or(InvalidArgument(param))
+}
+
+/// on|off参数
+fn parse_on_off(value: Option<&str>) -> Result<bool, InvalidArgument> {
+    match value {
+        Some("on") => Ok(true),
+        Some("off") => Ok(false),
+        _ => Err(InvalidArgument("on|off")),
+    }
+}
+
+/// 开始录制，已有录制进行中时通过蓝牙报告并返回false
+fn start_recording(session: &mut RecordingSession, bluetooth_manager: &BluetoothManager, now: Instant) -> bool {
+    let (started, message) = match session.start(now) {
+        Ok(()) => {
+            log::info!("开始录制，等待红外信号");
+            (true, "RECORD_ARMED")
+        }
+        Err(_) => {
+            log::warn!("录制已在进行中");
+            (false, "ERROR: recording already in progress")
+        }
+    };
+    reply(bluetooth_manager, message);
+    started
+}
+
+/// 把刚完成的录制保存到record命令指定的名称，同时作为参考码
+fn save_recording(
+    session: &RecordingSession,
+    slot: &str,
+    force: bool,
+    store: &Mutex<CaptureStorage>,
+    matcher: &mut CodeMatcher,
+    bluetooth_manager: &BluetoothManager,
+) {
+    let Some(capture) = session.pending() else {
+        return;
+    };
+    let message = match save_slot(slot, capture, force, store, matcher, bluetooth_manager) {
+        Ok(()) => {
+            log::info!("录制已保存: {}", slot);
+            format!("SAVED: {}", slot)
+        }
+        Err(e) => {
+            log::error!("保存录制{}失败: {}", slot, e);
+            storage_error_reply(&e)
+        }
+    };
+    reply(bluetooth_manager, &message);
+}
+
+/// 保存录制并以规范名称作为参考码
+///
+/// 不是`force`时先在同一遥控器中查找重复的录制，找到时返回`DuplicateOf`，不保存。
+/// 保存时被淘汰的录制同时从参考码中删除，并回复`EVICTED: <名称>,...`。
+fn save_slot(
+    slot: &str,
+    capture: &Capture,
+    force: bool,
+    store: &Mutex<CaptureStorage>,
+    matcher: &mut CodeMatcher,
+    bluetooth_manager: &BluetoothManager,
+) -> Result<(), StorageError> {
+    let result = {
+        let mut store = store.lock().unwrap();
+        let duplicate = if force { None } else { store.find_duplicate(slot, capture) };
+        match duplicate {
+            Some(existing) => Err(StorageError::DuplicateOf(existing)),
+            None => store.save(slot, capture),
+        }
+    };
+    let evicted = match &result {
+        Ok(evicted) | Err(StorageError::StorageFull { evicted, .. }) => evicted.as_slice(),
+        Err(_) => &[],
+    };
+    for name in evicted {
+        matcher.remove(name);
+    }
+    if !evicted.is_empty() {
+        reply(bluetooth_manager, &format!("EVICTED: {}", evicted.join(",")));
+    }
+    if result.is_ok() {
+        matcher.insert(&canonical_path(slot), capture.durations());
+    }
+    result.map(|_| ())
+}
+
+/// 擦除录制、宏和设置后重启，蓝牙配对信息保留
+///
+/// 擦除期间LED常亮红色，并持有录制的锁，避免发射线程写入重放次数。
+fn reset_to_factory(led: &mut Ws2812Led, store: &Mutex<CaptureStorage>, bluetooth_manager: &BluetoothManager) -> ! {
+    if let Err(e) = led.set_color(RgbColor::red()) {
+        log::warn!("设置LED失败: {:?}", e);
+    }
+    let message = {
+        let _store = store.lock().unwrap();
+        match factory_reset::erase_app_data() {
+            Ok(count) => format!("FACTORY_RESET_DONE: {} namespaces erased, rebooting", count),
+            Err(e) => {
+                log::error!("恢复出厂设置未完成: {:?}", e);
+                "ERROR: factory reset incomplete, rebooting".to_string()
+            }
+        }
+    };
+    log::warn!("{}", message);
+    if bluetooth_manager.is_connected() {
+        reply(bluetooth_manager, &message);
+        // 等待指示发出后再重启
+        FreeRtos::delay_ms(500);
+    }
+    unsafe { esp_idf_svc::sys::esp_restart() }
+}
+
+/// 记录录制会话事件并发送给客户端
+fn report_session(bluetooth_manager: &BluetoothManager, message: &str) {
+    log::info!("录制状态: {}", message);
+    reply(bluetooth_manager, message);
+}
+
+/// 把所有录制作为一条带分段头的消息逐条发送，每个分段等待客户端确认后再发送下一个
+///
+/// 发送失败（通常是客户端断开）时中止，并尽量把错误记录作为最后一个分段发送。
+fn export_archive(store: &Mutex<CaptureStorage>, bluetooth_manager: &BluetoothManager) {
+    let slots = store.lock().unwrap().list();
+    let mut archive = ArchiveWriter::new();
+    let mut writer = bluetooth_manager.large_writer();
+    for slot in &slots {
+        // 每个录制单独加锁读取，导出期间发射线程仍可重放
+        let Some(bytes) = store.lock().unwrap().read(&slot.name) else {
+            continue;
+        };
+        let record = archive.slot(&slot.name, &bytes);
+        if let Err(e) = writer.write(&record) {
+            log::warn!("导出中止: {}", e);
+            let _ = writer.write(&error_record("transfer interrupted")).and_then(|()| writer.finish());
+            return;
+        }
+    }
+    let result = writer.write(&archive.finish()).and_then(|()| writer.finish());
+    match result {
+        Ok(()) => log::info!("已导出{}个录制", slots.len()),
+        Err(e) => log::warn!("导出中止: {}", e),
+    }
+}
+
+/// 按处理方式保存导入的录制，试导入时只统计不保存；归档结束或被中止时回复结果并返回true
+fn import_records(
+    archive: &mut ArchiveImport,
+    records: Vec<ImportRecord>,
+    store: &Mutex<CaptureStorage>,
+    matcher: &mut CodeMatcher,
+    bluetooth_manager: &BluetoothManager,
+) -> bool {
+    for record in records {
+        let message = match record {
+            ImportRecord::Slot { name, capture } => {
+                let resolution = archive.resolve(&name, &capture, |path| store.lock().unwrap().contains(path));
+                let target = match resolution {
+                    Resolution::New(target) | Resolution::Overwrite(target) | Resolution::Renamed(target) => target,
+                    Resolution::Kept | Resolution::Invalid => continue,
+                };
+                if archive.is_dry_run() {
+                    continue;
+                }
+                match save_slot(&target, &capture, true, store, matcher, bluetooth_manager) {
+                    Ok(()) => {
+                        archive.saved(&name, &target);
+                        log::info!("已导入录制: {}", target);
+                    }
+                    Err(e) => {
+                        log::warn!("跳过录制{}: {}", name, e);
+                        archive.skip(&name);
+                    }
+                }
+                continue;
+            }
+            ImportRecord::End { verified } if archive.is_dry_run() => {
+                let mut message = format!("IMPORT_DRY_RUN: {}", archive.dry_run_summary());
+                match store.lock().unwrap().stats() {
+                    Ok(stats) if archive.bytes() > stats.free_bytes() => {
+                        message.push_str(&format!(", {} bytes free, storage full", stats.free_bytes()));
+                    }
+                    Ok(stats) => message.push_str(&format!(", {} bytes free", stats.free_bytes())),
+                    Err(e) => log::warn!("读取NVS使用情况失败: {}", e),
+                }
+                if !verified {
+                    message.push_str(", archive crc mismatch");
+                }
+                message
+            }
+            ImportRecord::End { verified: true } => format!("IMPORT_DONE: {}", archive.summary()),
+            ImportRecord::End { verified: false } => {
+                format!("IMPORT_DONE: {}, archive crc mismatch", archive.summary())
+            }
+            ImportRecord::Aborted(reason) => format!("IMPORT_FAILED: {}, {}", reason, archive.summary()),
+        };
+        log::info!("导入结果: {}", message);
+        reply(bluetooth_manager, &message);
+        return true;
+    }
+    false
+}
+
+/// 把遥控器导出为LIRC的remote.conf，遥控器为空时导出所有遥控器
+fn export_lirc(remote: &str, store: &Mutex<CaptureStorage>, bluetooth_manager: &BluetoothManager) {
+    let mut remotes: BTreeMap<String, Vec<(String, Capture)>> = BTreeMap::new();
+    {
+        let store = store.lock().unwrap();
+        for slot in store.list() {
+            let (name, key) = split_path(&slot.name);
+            if !remote.is_empty() && name != remote {
+                continue;
+            }
+            if let Some(capture) = store.load(&slot.name) {
+                remotes.entry(name.to_string()).or_default().push((key.to_string(), capture));
+            }
+        }
+    }
+    if remotes.is_empty() && !remote.is_empty() {
+        reply(bluetooth_manager, &format!("ERROR: unknown remote {}", remote));
+        return;
+    }
+    let mut text = String::new();
+    for (name, keys) in &remotes {
+        let keys = keys.iter().map(|(key, capture)| (key.as_str(), capture)).collect::<Vec<_>>();
+        text.push_str(&ir::lirc::remote_conf(name, &keys));
+        text.push('\n');
+    }
+    // 最后一个remote之后同样有空行，客户端以此判断接收完成
+    if text.is_empty() {
+        text.push('\n');
+    }
+    match bluetooth_manager.send_chunked(text.as_bytes()) {
+        Ok(()) => log::info!("已导出{}个遥控器为LIRC配置", remotes.len()),
+        Err(e) => log::warn!("LIRC配置导出中止: {}", e),
+    }
+}
+
+/// 把录制导出为Flipper的.ir文件，名称为空时导出所有录制
+fn export_flipper(name: &str, store: &Mutex<CaptureStorage>, bluetooth_manager: &BluetoothManager) {
+    let captures = {
+        let store = store.lock().unwrap();
+        if name.is_empty() {
+            store
+                .list()
+                .into_iter()
+                .filter_map(|slot| Some((store.load(&slot.name)?, slot.name)))
+                .collect::<Vec<_>>()
+        } else {
+            match store.load(name) {
+                Some(capture) => vec![(capture, name.to_string())],
+                None => {
+                    reply(bluetooth_manager, &format!("ERROR: unknown slot {}", name));
+                    return;
+                }
+            }
+        }
+    };
+    let text = ir::flipper::to_file(captures.iter().map(|(capture, name)| (name.as_str(), capture)));
+    // 文件以空行结束，便于客户端判断接收完成
+    let text = format!("{}\n", text);
+    match bluetooth_manager.send_chunked(text.as_bytes()) {
+        Ok(()) => log::info!("已导出{}个录制为Flipper文件", captures.len()),
+        Err(e) => log::warn!("Flipper文件导出中止: {}", e),
+    }
+}
+
+/// 保存Flipper文件中的每个信号，名称无效或无法解析的信号被跳过并列出
+fn import_flipper(text: &str, store: &Mutex<CaptureStorage>, matcher: &mut CodeMatcher, bluetooth_manager: &BluetoothManager) {
+    let mut saved = 0;
+    let mut skipped = Vec::new();
+    for signal in ir::flipper::parse(text) {
+        let result = match signal.capture {
+            Ok(capture) => {
+                save_slot(&signal.name, &capture, true, store, matcher, bluetooth_manager).map_err(|e| e.to_string())
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        match result {
+            Ok(()) => {
+                log::info!("已导入Flipper信号: {}", signal.name);
+                saved += 1;
+            }
+            Err(e) => {
+                log::warn!("跳过Flipper信号{}: {}", signal.name, e);
+                skipped.push(signal.name);
+            }
+        }
+    }
+    let mut message = format!("FLIPPER_DONE: {} saved, {} skipped", saved, skipped.len());
+    if !skipped.is_empty() {
+        message.push_str(": ");
+        message.push_str(&skipped.join(","));
+    }
+    log::info!("导入结果: {}", message);
+    reply(bluetooth_manager, &message);
+}
+
+/// 解析十进制或0x开头的十六进制数字，超出目标类型范围时返回None
+fn parse_number<T: TryFrom<u32>>(text: &str) -> Option<T> {
+    let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => text.parse().ok()?,
+    };
+    T::try_from(value).ok()
+}
+
+/// 解析MQTT的发射请求：已保存的录制名称，或者`<协议>:<地址>:<命令>`
+fn mqtt_request(
+    payload: &str,
+    store: &Mutex<CaptureStorage>,
+    rc5_session: &mut Rc5Session,
+) -> Result<TransmitRequest, &'static str> {
+    let Some((protocol, code)) = payload.split_once(':') else {
+        let slot = canonical_path(payload);
+        if !store.lock().unwrap().contains(&slot) {
+            return Err("no such slot");
+        }
+        return Ok(TransmitRequest::Replay(slot));
+    };
+    let (address, command) = code.split_once(':').ok_or("expected <protocol>:<address>:<command>")?;
+    let (durations, carrier_hz) = match protocol.to_ascii_lowercase().as_str() {
+        "nec" => (
+            parse_number(address).zip(parse_number(command)).map(|(a, c)| encode_nec(a, c)),
+            DEFAULT_CARRIER_HZ,
+        ),
+        "nec_ext" => (
+            parse_number(address).zip(parse_number(command)).map(|(a, c)| encode_nec_ext(a, c)),
+            DEFAULT_CARRIER_HZ,
+        ),
+        "samsung" => (
+            parse_number(address).zip(parse_number(command)).map(|(a, c)| encode_samsung(a, c)),
+            ir::samsung::CARRIER_HZ,
+        ),
+        "samsung36" => (
+            parse_number(address).zip(parse_number(command)).and_then(|(a, d)| encode_samsung36(a, d)),
+            ir::samsung::CARRIER_HZ,
+        ),
+        "sirc" => (
+            parse_number(address)
+                .zip(parse_number(command))
+                .and_then(|(d, c)| encode_sirc(d, c, SircBits::Twelve)),
+            ir::sirc::CARRIER_HZ,
+        ),
+        "rc5" => (
+            parse_number(address).zip(parse_number(command)).and_then(|(a, c)| rc5_session.press(a, c)),
+            ir::rc5::CARRIER_HZ,
+        ),
+        "denon" => (
+            parse_number(address).zip(parse_number(command)).and_then(|(a, c)| encode_denon(a, c, 0)),
+            ir::denon::CARRIER_HZ,
+        ),
+        _ => return Err("unsupported protocol"),
+    };
+    let durations = durations.ok_or("invalid address or command")?;
+    raw_request(durations, carrier_hz).map_err(|_| "invalid carrier")
+}
+
+/// 以指定载波发送原始时长的请求
+fn raw_request(durations: Vec<u32>, carrier_hz: u32) -> Result<TransmitRequest, TransmitError> {
+    Ok(TransmitRequest::Raw {
+        durations,
+        carrier: Carrier::with_frequency(carrier_hz)?,
+    })
+}
+
+/// 请求入队并回复编号，发射完成后另行上报TX_DONE或TX_FAILED
+fn enqueue_and_reply(
+    queue: &mut TransmitQueue,
+    bluetooth_manager: &BluetoothManager,
+    request: Result<TransmitRequest, TransmitError>,
+    description: &str,
+) -> Option<u32> {
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => {
+            log::warn!("{}", e);
+            reply(bluetooth_manager, &format!("ERROR: {}", transmit_error_reason(&e)));
+            return None;
+        }
+    };
+    reply_enqueued(bluetooth_manager, queue.enqueue(request), description)
+}
+
+/// 回复入队结果
+fn reply_enqueued(bluetooth_manager: &BluetoothManager, result: Result<u32, QueueFull>, description: &str) -> Option<u32> {
+    match result {
+        Ok(ticket) => {
+            log::info!("发射请求{}已入队: {}", ticket, description);
+            reply(bluetooth_manager, &format!("QUEUED: {} {}", ticket, description));
+            Some(ticket)
+        }
+        Err(QueueFull) => {
+            log::warn!("发射队列已满");
+            reply(bluetooth_manager, "BUSY: transmit queue full");
+            None
+        }
+    }
+}
+
+/// 宏操作失败时回复给客户端的错误
+fn macro_error_reply(error: &MacroError) -> String {
+    match error {
+        MacroError::InvalidName => format!("ERROR: macro name must be 1-{} characters", macros::MAX_NAME_LEN),
+        MacroError::InvalidSteps => "ERROR: usage macro:<name>:<slot>[:<delay_ms>],...".to_string(),
+        MacroError::Busy => "ERROR: macro already running".to_string(),
+        MacroError::Spawn(_) | MacroError::Nvs(_) => "ERROR: macro failed".to_string(),
+    }
+}
+
+/// 按effect命令的效果和选项启动LED效果，参数无效时返回None；`seed`是烛光和星点效果的随机数种子
+fn start_effect(led: &mut Ws2812Led, kind: &str, options: Option<&str>, seed: u32) -> Option<()> {
+    let mut parts = options.into_iter().flat_map(|options| options.split(':'));
+    if kind == "rainbow" {
+        let duration_ms = parts.next().map_or(Some(5000), |ms| ms.parse().ok())?;
+        if parts.next().is_some() {
+            return None;
+        }
+        led.rainbow(duration_ms);
+        return Some(());
+    }
+    if kind == "candle" {
+        let color = parts.next().map_or(Some(RgbColor::new(255, 147, 41)), |color| color.parse().ok())?;
+        if parts.next().is_some() {
+            return None;
+        }
+        led.candle(color, seed);
+        return Some(());
+    }
+    if matches!(kind, "chase" | "wipe" | "sparkle") {
+        return start_strip_effect(led, kind, parts.collect::<Vec<_>>().as_slice(), seed);
+    }
+
+    let color = parts.next()?.parse().ok()?;
+    let number = parts.next().map(str::parse::<u32>).transpose().ok()?;
+    // 只有渐变可以再指定缓动曲线
+    let easing = match kind {
+        "fade" => parts.next().map(str::parse::<Easing>).transpose().ok()?,
+        _ => None,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    match kind {
+        "breathing" => led.breathing(color, Some(number.unwrap_or(3)), BREATHING_PERIOD_MS),
+        "blink" => led.blink(color, Some(number.unwrap_or(5)), 250, 250),
+        "fade" => led.fade_to(color, number.unwrap_or(1000), easing.unwrap_or_default()),
+        _ => return None,
+    }
+    Some(())
+}
+
+/// 灯带效果：chase:<颜色>[:<毫秒>[:<背景>]]、wipe:<颜色>[:<毫秒>]、sparkle:<颜色>[:<密度>[:<毫秒>[:<背景>]]]
+fn start_strip_effect(led: &mut Ws2812Led, kind: &str, params: &[&str], seed: u32) -> Option<()> {
+    let color = |index: usize, default: RgbColor| params.get(index).map_or(Some(default), |color| color.parse().ok());
+    let number = |index: usize, default: u32| params.get(index).map_or(Some(default), |number| number.parse().ok());
+    let foreground = params.first()?.parse().ok()?;
+    match (kind, params.len()) {
+        ("chase", 1..=3) => led.theater_chase(foreground, color(2, RgbColor::black())?, number(1, 100)?),
+        ("wipe", 1..=2) => led.color_wipe(foreground, number(1, 50)?),
+        ("sparkle", 1..=4) => {
+            let density = number(1, 10).filter(|density| *density <= 100)?;
+            led.sparkle(foreground, color(3, RgbColor::black())?, density as u8, number(2, 50)?, seed)
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+/// 解析<上限毫安|off>和每通道毫安，没有给出每通道电流时沿用`current`
+fn parse_led_power(max: &str, channel: Option<u32>, current: PowerBudget) -> Option<PowerBudget> {
+    let max_current_ma = match max {
+        "off" => 0,
+        _ => max.parse().ok().filter(|ma| MAX_CURRENT_MA_RANGE.contains(ma))?,
+    };
+    let channel_ma = match channel {
+        Some(channel) => u16::try_from(channel).ok().filter(|ma| CHANNEL_MA_RANGE.contains(ma))?,
+        None => current.channel_ma,
+    };
+    Some(PowerBudget { channel_ma, max_current_ma })
+}
+
+/// 发射通道分配的回复，配置和当前分配不同时提示重启
+fn rmt_status(assigned: &[rmt::Assignment], settings: &Settings) -> String {
+    let mut status = "RMT:".to_string();
+    for assignment in assigned {
+        status.push_str(&format!(" {}=ch{}x{}", assignment.owner, assignment.channel, assignment.mem_blocks));
+    }
+    let configured = [(OWNER_LED, settings.rmt_led_blocks()), (OWNER_IR, settings.rmt_ir_blocks())];
+    let changed = configured.iter().any(|&(owner, blocks)| {
+        assigned.iter().any(|assignment| assignment.owner == owner && assignment.mem_blocks != blocks)
+    });
+    if changed {
+        status.push_str(&format!(" (led={} ir={} after restart)", configured[0].1, configured[1].1));
+    }
+    status
+}
+
+/// Wi-Fi的回复：连接状态、IP和是否已经同步时间
+fn wifi_status(wifi: Option<&WifiLink>) -> String {
+    let Some(wifi) = wifi else {
+        return "WIFI: off".to_string();
+    };
+    let time = ir::metadata::synced_unix_secs().map_or("unsynced".to_string(), ir::metadata::format_utc);
+    match wifi.state() {
+        WifiState::Connecting => format!("WIFI: connecting ssid={} time={}", wifi.ssid(), time),
+        WifiState::Connected(ip) => format!("WIFI: connected ssid={} ip={} time={}", wifi.ssid(), ip, time),
+    }
+}
+
+/// MQTT的回复：连接状态、服务器地址和丢弃的消息数量
+fn mqtt_status(mqtt: Option<&MqttBridge>) -> String {
+    let Some(mqtt) = mqtt else {
+        return "MQTT: off".to_string();
+    };
+    format!(
+        "MQTT: {} url={} dropped={}",
+        if mqtt.is_connected() { "connected" } else { "connecting" },
+        mqtt.url(),
+        mqtt.dropped()
+    )
+}
+
+/// 灯带电流的回复：最近一帧估算的电流、上限和限流比例
+fn led_power_status(led: &Ws2812Led) -> String {
+    let strip = led.strip();
+    let limit = match strip.power().max_current_ma {
+        0 => "off".to_string(),
+        ma => format!("{}mA", ma),
+    };
+    let scale = strip.frame_scale_permille();
+    format!(
+        "LED_POWER: current={}mA limit={} scale={}.{}% channel={}mA",
+        strip.frame_current_ma(),
+        limit,
+        scale / 10,
+        scale % 10,
+        strip.power().channel_ma
+    )
+}
+
+/// 颜色无效时的回复，带上无法解析的内容
+fn color_error(input: &str, e: &ParseColorError) -> String {
+    let reason = match e {
+        ParseColorError::InvalidDigit(c) => format!("invalid hex digit '{}'", c),
+        ParseColorError::InvalidLength(len) => format!("expected 3 or 6 hex digits, got {}", len),
+        ParseColorError::UnknownName => "unknown color name".to_string(),
+    };
+    format!("ERROR: invalid color '{}': {}", input, reason)
+}
+
+/// 解析<色相0-359>:<饱和度0-255>:<亮度0-255>
+fn parse_hsv(args: &str) -> Option<HsvColor> {
+    let mut parts = args.split(':');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(hue), Some(sat), Some(val), None) => {
+            let hue = hue.parse::<u16>().ok().filter(|hue| *hue < 360)?;
+            Some(HsvColor::from_degrees(hue, sat.parse().ok()?, val.parse().ok()?))
+        }
+        _ => None,
+    }
+}
+
+/// 白名单的状态、数量和地址
+fn whitelist_reply(enabled: bool, peers: &[esp_idf_svc::bt::BdAddr]) -> String {
+    let mut message = format!(
+        "WHITELIST: {} {}/{}",
+        if enabled { "on" } else { "off" },
+        peers.len(),
+        MAX_WHITELIST
+    );
+    for (index, peer) in peers.iter().enumerate() {
+        message.push(if index == 0 { ' ' } else { ',' });
+        message.push_str(&peer.to_string());
+    }
+    message
+}
+
+/// 保存录制失败时回复给客户端的错误
+fn storage_error_reply(error: &StorageError) -> String {
+    match error {
+        StorageError::EmptyName => "ERROR: name must not be empty".to_string(),
+        StorageError::NameTooLong(len) => format!("ERROR: name too long ({} > {} characters)", len, MAX_NAME_LEN),
+        StorageError::RemoteNameTooLong(len) => {
+            format!("ERROR: remote name too long ({} > {} characters)", len, MAX_REMOTE_LEN)
+        }
+        StorageError::InvalidPath => "ERROR: path must be <remote>/<key> or <key>".to_string(),
+        StorageError::LabelTooLong(len) => format!("ERROR: label too long ({} > {} bytes)", len, MAX_LABEL_LEN),
+        StorageError::UnknownSlot => "ERROR: unknown slot".to_string(),
+        StorageError::UnknownRemote => "ERROR: unknown remote".to_string(),
+        StorageError::RemoteExists => "ERROR: remote already exists".to_string(),
+        StorageError::StorageFull { needed, .. } => format!("ERROR: storage full ({} bytes needed)", needed),
+        StorageError::PartialDelete { deleted, remaining } => format!(
+            "ERROR: partial delete, {} deleted, {} remaining: {}",
+            deleted.len(),
+            remaining.len(),
+            remaining.join(",")
+        ),
+        StorageError::DuplicateOf(existing) => format!("ERROR: duplicate of {}, add :force to save anyway", existing),
+        StorageError::Format(FormatError::TooManyFrames(count)) => {
+            format!("ERROR: too many frames ({} > {})", count, u8::MAX)
+        }
+        StorageError::Format(FormatError::FrameTooLong(count)) => {
+            format!("ERROR: frame too long ({} > {} durations)", count, u16::MAX)
+        }
+        StorageError::Format(_) => "ERROR: invalid capture".to_string(),
+        StorageError::Nvs(_) => "ERROR: storage failed".to_string(),
+    }
+}
+
+/// 发射失败时回复给客户端的原因
+fn transmit_error_reason(error: &TransmitError) -> String {
+    match error {
+        TransmitError::Empty => "nothing to send".to_string(),
+        TransmitError::UnknownSlot(slot) => format!("unknown slot {}", slot),
+        TransmitError::CarrierOutOfRange(hz) => {
+            format!("carrier {}Hz out of range {}-{}Hz", hz, MIN_CARRIER_HZ, MAX_CARRIER_HZ)
+        }
+        TransmitError::InvalidDuty(duty) => format!("invalid duty {}%", duty),
+        TransmitError::InvalidDuration { index, duration } => format!(
+            "invalid duration {}us at index {}, must be 1-{}us",
+            duration, index, MAX_RAW_DURATION_US
+        ),
+        TransmitError::TooManyDurations(count) => {
+            format!("too many durations {}, max {}", count, MAX_RAW_DURATIONS)
+        }
+        TransmitError::Unavailable => "transmitter unavailable".to_string(),
+        TransmitError::Rmt(_) => "transmit failed".to_string(),
+    }
+}
+
+/// 配置错误回复给客户端的原因
+fn config_error_reason(error: &ConfigError) -> String {
+    match error {
+        ConfigError::UnknownKey(key) => format!("unknown config key {}", key),
+        ConfigError::InvalidValue(key) => format!("invalid value for {}", key.name()),
+        ConfigError::InvalidPin(pin) => format!("GPIO{} is not usable", pin),
+        ConfigError::PinConflict(a, b) => format!("{} and {} use the same pin", a.name(), b.name()),
+    }
+}
+
+/// 进出省电模式时调整广播间隔和按键轮询
+fn apply_power_state(bluetooth_manager: &BluetoothManager, ticker: &Ticker, state: PowerState) {
+    let sleeping = state == PowerState::Sleep;
+    bluetooth_manager.set_slow_advertising(sleeping);
+    ticker.set_low_power(sleeping);
+}
+
+/// 打开保存在NVS中的数据，NVS不可用或打开失败时记入自检，改用只保存在内存中的数据
+fn open_nvs<T>(
+    self_check: &mut SelfCheck,
+    nvs: Option<&EspDefaultNvsPartition>,
+    open: impl FnOnce(&EspDefaultNvsPartition) -> Result<T, EspError>,
+    in_memory: impl FnOnce() -> T,
+) -> T {
+    nvs.and_then(|nvs| self_check.check(Subsystem::Nvs, open(nvs))).unwrap_or_else(|| {
+        log::warn!("NVS不可用，数据只保存在内存中");
+        in_memory()
+    })
+}
+
+/// 向蓝牙客户端发送一条回复
+fn reply(bluetooth_manager: &BluetoothManager, message: &str) {
+    if let Err(e) = bluetooth_manager.send_data(message.as_bytes()) {
+        log::error!("发送蓝牙回复失败: {:?}", e);
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use esp_idf_svc::mqtt::client::{EspMqttClient, EspMqttConnection, EventPayload, MqttClientConfiguration, QoS};
+use esp_idf_svc::sys::EspError;
+
+use crate::ir::dump::{write_decoded, write_string};
+use crate::ir::CaptureEvent;
+
+/// 主题的第一级，完整主题为`esp-ir/<设备>/received`和`esp-ir/<设备>/send`
+const TOPIC_ROOT: &str = "esp-ir";
+
+/// 代理地址、用户名、密码和设备主题的最大长度
+pub const MAX_URL_LEN: usize = 128;
+pub const MAX_CREDENTIAL_LEN: usize = 64;
+pub const MAX_DEVICE_LEN: usize = 32;
+
+/// 等待发布的消息最多保留的条数，满了丢弃最旧的
+const MAX_OUTGOING: usize = 16;
+
+/// 检查发布队列的间隔
+const PUBLISH_POLL: Duration = Duration::from_millis(200);
+
+/// 断开后重连的间隔，每次失败加倍，连上后恢复
+const RETRY_MIN: Duration = Duration::from_secs(2);
+const RETRY_MAX: Duration = Duration::from_secs(120);
+
+const MQTT_STACK_SIZE: usize = 6144;
+const CONNECTION_STACK_SIZE: usize = 4096;
+
+/// MQTT线程上报给主循环的事件
+#[derive(Debug)]
+pub enum MqttEvent {
+    /// `send`主题收到的内容：录制名称，或者`<协议>:<地址>:<命令>`
+    Send(String),
+}
+
+/// 代理地址和登录信息
+#[derive(Debug, Clone)]
+pub struct MqttSettings {
+    pub url: String,
+    pub username: String,
+    pub password: String,
+    /// 主题中的设备名称
+    pub device: String,
+}
+
+/// 连接事件线程转给MQTT线程的事件
+enum SessionEvent {
+    Connected,
+    Disconnected,
+    Received(String),
+}
+
+/// 把捕获发布到MQTT代理，并把`send`主题的请求转给主循环
+///
+/// 发布只放入有界队列，由独立线程在连上代理时发送，代理不可用时不会阻塞蓝牙和红外。
+pub struct MqttBridge {
+    outgoing: Arc<Mutex<VecDeque<String>>>,
+    connected: Arc<AtomicBool>,
+    dropped: AtomicU32,
+    url: String,
+}
+
+impl MqttBridge {
+    pub fn spawn<E: From<MqttEvent> + Send + 'static>(settings: MqttSettings, events: Sender<E>) -> std::io::Result<Self> {
+        let outgoing = Arc::new(Mutex::new(VecDeque::new()));
+        let connected = Arc::new(AtomicBool::new(false));
+        let url = settings.url.clone();
+
+        let queue = outgoing.clone();
+        let state = connected.clone();
+        thread::Builder::new()
+            .name("mqtt".into())
+            .stack_size(MQTT_STACK_SIZE)
+            .spawn(move || run(&settings, &queue, &state, &events))?;
+
+        Ok(Self {
+            outgoing,
+            connected,
+            dropped: AtomicU32::new(0),
+            url,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// 队列满时丢弃的消息数量
+    pub fn dropped(&self) -> u32 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// 以JSON发布捕获：协议、解码出的字段和匹配到的录制
+    pub fn publish_capture(&self, event: &CaptureEvent) {
+        let mut message = format!("{{\"protocol\":\"{}\",\"code\":", event.command.protocol().name());
+        let _ = write_decoded(&mut message, &event.command);
+        message.push_str(",\"slot\":");
+        let _ = match &event.matched {
+            Some(slot) => write_string(&mut message, slot),
+            None => message.write_str("null"),
+        };
+        message.push('}');
+
+        let mut outgoing = self.outgoing.lock().unwrap();
+        if outgoing.len() >= MAX_OUTGOING {
+            outgoing.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        outgoing.push_back(message);
+    }
+}
+
+/// 每次连接创建一个客户端，断开后销毁并按退避间隔重新创建
+fn run<E: From<MqttEvent>>(
+    settings: &MqttSettings,
+    outgoing: &Mutex<VecDeque<String>>,
+    connected: &AtomicBool,
+    events: &Sender<E>,
+) {
+    let received_topic = format!("{}/{}/received", TOPIC_ROOT, settings.device);
+    let send_topic = format!("{}/{}/send", TOPIC_ROOT, settings.device);
+    let mut retry = RETRY_MIN;
+    loop {
+        match session(settings, &received_topic, &send_topic, outgoing, connected, events) {
+            Ok(true) => retry = RETRY_MIN,
+            Ok(false) => (),
+            Err(e) => log::warn!("MQTT连接{}失败: {:?}", settings.url, e),
+        }
+        connected.store(false, Ordering::Relaxed);
+        log::info!("MQTT已断开，{}秒后重连", retry.as_secs());
+        thread::sleep(retry);
+        retry = (retry * 2).min(RETRY_MAX);
+    }
+}
+
+/// 运行一次连接直到断开，连上过代理时返回true
+fn session<E: From<MqttEvent>>(
+    settings: &MqttSettings,
+    received_topic: &str,
+    send_topic: &str,
+    outgoing: &Mutex<VecDeque<String>>,
+    connected: &AtomicBool,
+    events: &Sender<E>,
+) -> Result<bool, EspError> {
+    let configuration = MqttClientConfiguration {
+        client_id: Some(&settings.device),
+        username: (!settings.username.is_empty()).then_some(settings.username.as_str()),
+        password: (!settings.password.is_empty()).then_some(settings.password.as_str()),
+        ..Default::default()
+    };
+    let (mut client, connection) = EspMqttClient::new(&settings.url, &configuration)?;
+
+    // 连接事件必须持续读取，客户端销毁后读取出错，线程随之退出
+    let (session_tx, session_rx) = mpsc::channel();
+    if let Err(e) = thread::Builder::new()
+        .name("mqtt-conn".into())
+        .stack_size(CONNECTION_STACK_SIZE)
+        .spawn(move || forward_events(connection, session_tx))
+    {
+        log::error!("启动MQTT事件线程失败: {:?}", e);
+        return Ok(false);
+    }
+
+    let mut was_connected = false;
+    loop {
+        match session_rx.recv_timeout(PUBLISH_POLL) {
+            Ok(SessionEvent::Connected) => {
+                log::info!("MQTT已连接: {}", settings.url);
+                client.subscribe(send_topic, QoS::AtLeastOnce)?;
+                connected.store(true, Ordering::Relaxed);
+                was_connected = true;
+            }
+            Ok(SessionEvent::Received(payload)) => {
+                if events.send(MqttEvent::Send(payload).into()).is_err() {
+                    return Ok(was_connected);
+                }
+            }
+            Ok(SessionEvent::Disconnected) | Err(RecvTimeoutError::Disconnected) => return Ok(was_connected),
+            Err(RecvTimeoutError::Timeout) => (),
+        }
+
+        if connected.load(Ordering::Relaxed) {
+            // 逐条取出，发送时不持有锁
+            while let Some(message) = outgoing.lock().unwrap().pop_front() {
+                client.enqueue(received_topic, QoS::AtMostOnce, false, message.as_bytes())?;
+            }
+        }
+    }
+}
+
+fn forward_events(mut connection: EspMqttConnection, session: Sender<SessionEvent>) {
+    while let Ok(event) = connection.next() {
+        let event = match event.payload() {
+            EventPayload::Connected(_) => SessionEvent::Connected,
+            EventPayload::Disconnected => SessionEvent::Disconnected,
+            EventPayload::Received { data, .. } => match std::str::from_utf8(data) {
+                Ok(text) => SessionEvent::Received(text.trim().to_string()),
+                Err(_) => {
+                    log::warn!("忽略非UTF-8的MQTT消息");
+                    continue;
+                }
+            },
+            EventPayload::Error(e) => {
+                log::warn!("MQTT错误: {:?}", e);
+                continue;
+            }
+            _ => continue,
+        };
+        if session.send(event).is_err() {
+            break;
+        }
+    }
+}
+
+/// 代理地址只支持`mqtt://`和`mqtts://`
+pub fn check_url(url: &str) -> bool {
+    url.len() <= MAX_URL_LEN && (url.starts_with("mqtt://") || url.starts_with("mqtts://"))
+}
+
+pub fn check_credential(text: &str) -> bool {
+    text.len() <= MAX_CREDENTIAL_LEN && !text.contains(char::is_control)
+}
+
+/// 设备名称是主题中的一级，不能包含分隔符和通配符
+pub fn check_device(device: &str) -> bool {
+    device.len() <= MAX_DEVICE_LEN && !device.contains(|c: char| matches!(c, '/' | '+' | '#') || c.is_control())
+}
+
+/// 没有设置主题中的设备名称时由蓝牙设备名称生成，只保留字母、数字和连字符
+pub fn default_device(device_name: &str) -> String {
+    device_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .take(MAX_DEVICE_LEN)
+        .collect()
+}
@@ -0,0 +1,292 @@
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::sync::Mutex;
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::{self, esp, EspError};
+
+/// 内存中保存的值，按NVS的类型区分，类型不同时与NVS一样视为不存在
+#[derive(Debug, Clone)]
+enum Value {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    I32(i32),
+    Str(String),
+    Blob(Vec<u8>),
+}
+
+impl Value {
+    fn kind(&self) -> sys::nvs_type_t {
+        match self {
+            Self::U8(_) => sys::nvs_type_t_NVS_TYPE_U8,
+            Self::U16(_) => sys::nvs_type_t_NVS_TYPE_U16,
+            Self::U32(_) => sys::nvs_type_t_NVS_TYPE_U32,
+            Self::I32(_) => sys::nvs_type_t_NVS_TYPE_I32,
+            Self::Str(_) => sys::nvs_type_t_NVS_TYPE_STR,
+            Self::Blob(_) => sys::nvs_type_t_NVS_TYPE_BLOB,
+        }
+    }
+}
+
+enum Backend {
+    Nvs(EspNvs<NvsDefault>),
+    Memory(Mutex<BTreeMap<String, Value>>),
+}
+
+/// NVS中的一个命名空间，NVS不可用时退化为内存中的键值表，重启后丢失
+///
+/// 方法与`EspNvs`相同，设置、录制和宏等模块只需替换字段类型。
+pub struct Namespace {
+    name: String,
+    backend: Backend,
+}
+
+impl Namespace {
+    pub fn open(partition: &EspDefaultNvsPartition, name: &str) -> Result<Self, EspError> {
+        Ok(Self {
+            name: name.to_string(),
+            backend: Backend::Nvs(EspNvs::new(partition.clone(), name, true)?),
+        })
+    }
+
+    /// 只保存在内存中的命名空间
+    pub fn in_memory(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            backend: Backend::Memory(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// 有分区时打开NVS命名空间，否则使用内存
+    pub fn open_or_memory(partition: Option<&EspDefaultNvsPartition>, name: &str) -> Result<Self, EspError> {
+        match partition {
+            Some(partition) => Self::open(partition, name),
+            None => Ok(Self::in_memory(name)),
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> Result<bool, EspError> {
+        match &self.backend {
+            Backend::Nvs(nvs) => nvs.contains(key),
+            Backend::Memory(values) => Ok(values.lock().unwrap().contains_key(key)),
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<bool, EspError> {
+        match &mut self.backend {
+            Backend::Nvs(nvs) => nvs.remove(key),
+            Backend::Memory(values) => Ok(values.get_mut().unwrap().remove(key).is_some()),
+        }
+    }
+
+    pub fn get_u8(&self, key: &str) -> Result<Option<u8>, EspError> {
+        match &self.backend {
+            Backend::Nvs(nvs) => nvs.get_u8(key),
+            Backend::Memory(values) => Ok(read(values, key, |value| match value {
+                Value::U8(value) => Some(*value),
+                _ => None,
+            })),
+        }
+    }
+
+    pub fn set_u8(&self, key: &str, value: u8) -> Result<(), EspError> {
+        match &self.backend {
+            Backend::Nvs(nvs) => nvs.set_u8(key, value),
+            Backend::Memory(values) => write(values, key, Value::U8(value)),
+        }
+    }
+
+    pub fn get_u16(&self, key: &str) -> Result<Option<u16>, EspError> {
+        match &self.backend {
+            Backend::Nvs(nvs) => nvs.get_u16(key),
+            Backend::Memory(values) => Ok(read(values, key, |value| match value {
+                Value::U16(value) => Some(*value),
+                _ => None,
+            })),
+        }
+    }
+
+    pub fn set_u16(&self, key: &str, value: u16) -> Result<(), EspError> {
+        match &self.backend {
+            Backend::Nvs(nvs) => nvs.set_u16(key, value),
+            Backend::Memory(values) => write(values, key, Value::U16(value)),
+        }
+    }
+
+    pub fn get_u32(&self, key: &str) -> Result<Option<u32>, EspError> {
+        match &self.backend {
+            Backend::Nvs(nvs) => nvs.get_u32(key),
+            Backend::Memory(values) => Ok(read(values, key, |value| match value {
+                Value::U32(value) => Some(*value),
+                _ => None,
+            })),
+        }
+    }
+
+    pub fn set_u32(&self, key: &str, value: u32) -> Result<(), EspError> {
+        match &self.backend {
+            Backend::Nvs(nvs) => nvs.set_u32(key, value),
+            Backend::Memory(values) => write(values, key, Value::U32(value)),
+        }
+    }
+
+    pub fn get_i32(&self, key: &str) -> Result<Option<i32>, EspError> {
+        match &self.backend {
+            Backend::Nvs(nvs) => nvs.get_i32(key),
+            Backend::Memory(values) => Ok(read(values, key, |value| match value {
+                Value::I32(value) => Some(*value),
+                _ => None,
+            })),
+        }
+    }
+
+    pub fn set_i32(&self, key: &str, value: i32) -> Result<(), EspError> {
+        match &self.backend {
+            Backend::Nvs(nvs) => nvs.set_i32(key, value),
+            Backend::Memory(values) => write(values, key, Value::I32(value)),
+        }
+    }
+
+    /// 与NVS一样，`buf`要能放下结尾的0
+    pub fn get_str<'a>(&self, key: &str, buf: &'a mut [u8]) -> Result<Option<&'a str>, EspError> {
+        match &self.backend {
+            Backend::Nvs(nvs) => nvs.get_str(key, buf),
+            Backend::Memory(values) => {
+                let Some(text) = read(values, key, |value| match value {
+                    Value::Str(text) => Some(text.clone()),
+                    _ => None,
+                }) else {
+                    return Ok(None);
+                };
+                let len = text.len();
+                if len >= buf.len() {
+                    return Err(EspError::from_infallible::<{ sys::ESP_ERR_NVS_INVALID_LENGTH }>());
+                }
+                buf[..len].copy_from_slice(text.as_bytes());
+                // 写入时是合法的字符串，原样复制回来仍然合法
+                Ok(std::str::from_utf8(&buf[..len]).ok())
+            }
+        }
+    }
+
+    pub fn set_str(&mut self, key: &str, value: &str) -> Result<(), EspError> {
+        match &mut self.backend {
+            Backend::Nvs(nvs) => nvs.set_str(key, value),
+            Backend::Memory(values) => write(values, key, Value::Str(value.to_string())),
+        }
+    }
+
+    pub fn blob_len(&self, key: &str) -> Result<Option<usize>, EspError> {
+        match &self.backend {
+            Backend::Nvs(nvs) => nvs.blob_len(key),
+            Backend::Memory(values) => Ok(read(values, key, |value| match value {
+                Value::Blob(bytes) => Some(bytes.len()),
+                _ => None,
+            })),
+        }
+    }
+
+    pub fn get_blob<'a>(&self, key: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, EspError> {
+        match &self.backend {
+            Backend::Nvs(nvs) => nvs.get_blob(key, buf),
+            Backend::Memory(values) => {
+                let Some(bytes) = read(values, key, |value| match value {
+                    Value::Blob(bytes) => Some(bytes.clone()),
+                    _ => None,
+                }) else {
+                    return Ok(None);
+                };
+                if bytes.len() > buf.len() {
+                    return Err(EspError::from_infallible::<{ sys::ESP_ERR_NVS_INVALID_LENGTH }>());
+                }
+                buf[..bytes.len()].copy_from_slice(&bytes);
+                Ok(Some(&buf[..bytes.len()]))
+            }
+        }
+    }
+
+    pub fn set_blob(&mut self, key: &str, bytes: &[u8]) -> Result<(), EspError> {
+        match &mut self.backend {
+            Backend::Nvs(nvs) => nvs.set_blob(key, bytes),
+            Backend::Memory(values) => write(values, key, Value::Blob(bytes.to_vec())),
+        }
+    }
+
+    /// 命名空间中指定类型的所有键名
+    pub fn keys(&self, kind: sys::nvs_type_t) -> Vec<String> {
+        match &self.backend {
+            Backend::Nvs(_) => namespace_keys(&self.name, kind),
+            Backend::Memory(values) => values
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, value)| value.kind() == kind)
+                .map(|(key, _)| key.clone())
+                .collect(),
+        }
+    }
+
+    /// 在一次提交中擦除命名空间中的所有键
+    pub fn erase_all(&mut self) -> Result<(), EspError> {
+        match &mut self.backend {
+            Backend::Nvs(_) => erase_namespace(&CString::new(self.name.as_str()).unwrap()),
+            Backend::Memory(values) => {
+                values.get_mut().unwrap().clear();
+                Ok(())
+            }
+        }
+    }
+}
+
+fn read<T>(values: &Mutex<BTreeMap<String, Value>>, key: &str, extract: impl FnOnce(&Value) -> Option<T>) -> Option<T> {
+    values.lock().unwrap().get(key).and_then(extract)
+}
+
+fn write(values: &Mutex<BTreeMap<String, Value>>, key: &str, value: Value) -> Result<(), EspError> {
+    values.lock().unwrap().insert(key.to_string(), value);
+    Ok(())
+}
+
+/// 命名空间中指定类型的所有键名
+fn namespace_keys(namespace: &str, kind: sys::nvs_type_t) -> Vec<String> {
+    let Ok(namespace) = CString::new(namespace) else {
+        return Vec::new();
+    };
+    let mut keys = Vec::new();
+    let mut iterator: sys::nvs_iterator_t = std::ptr::null_mut();
+    let mut result = unsafe {
+        sys::nvs_entry_find(
+            sys::NVS_DEFAULT_PART_NAME.as_ptr() as *const _,
+            namespace.as_ptr(),
+            kind,
+            &mut iterator,
+        )
+    };
+    while result == sys::ESP_OK {
+        let mut info = sys::nvs_entry_info_t::default();
+        if unsafe { sys::nvs_entry_info(iterator, &mut info) } == sys::ESP_OK {
+            let key = unsafe { CStr::from_ptr(info.key.as_ptr()) };
+            match key.to_str() {
+                Ok(key) => keys.push(key.to_string()),
+                Err(_) => log::warn!("跳过非UTF-8的键名: {:?}", key),
+            }
+        }
+        result = unsafe { sys::nvs_entry_next(&mut iterator) };
+    }
+    // 迭代结束时iterator已被释放并置空，提前出错时需要手动释放
+    unsafe { sys::nvs_release_iterator(iterator) };
+    if result != sys::ESP_ERR_NVS_NOT_FOUND {
+        log::warn!("遍历NVS失败: {:?}", EspError::from(result));
+    }
+    keys
+}
+
+/// 在一次提交中擦除命名空间中的所有键
+pub fn erase_namespace(namespace: &CStr) -> Result<(), EspError> {
+    let mut handle: sys::nvs_handle_t = 0;
+    esp!(unsafe { sys::nvs_open(namespace.as_ptr(), sys::nvs_open_mode_t_NVS_READWRITE, &mut handle) })?;
+    let result = esp!(unsafe { sys::nvs_erase_all(handle) }).and_then(|()| esp!(unsafe { sys::nvs_commit(handle) }));
+    unsafe { sys::nvs_close(handle) };
+    result
+}
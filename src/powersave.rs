@@ -0,0 +1,234 @@
+use std::ffi::c_void;
+use std::ops::RangeInclusive;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::sys::{self, EspError, ESP_ERR_NOT_SUPPORTED};
+
+/// 没有连接和红外活动超过该时长（秒）后进入省电模式
+pub const DEFAULT_IDLE_S: u32 = 60;
+pub const IDLE_RANGE_S: RangeInclusive<u32> = 10..=3600;
+
+/// 自动light sleep之间CPU的最高和最低频率
+const MAX_FREQ_MHZ: i32 = 240;
+const MIN_FREQ_MHZ: i32 = 40;
+
+/// 当前的电源状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    Active,
+    /// 空闲时自动进入light sleep，红外接收引脚变低时唤醒
+    Sleep,
+}
+
+impl PowerState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Sleep => "sleep",
+        }
+    }
+}
+
+/// 红外接收引脚的唤醒源
+///
+/// 接收头输出低电平时唤醒芯片，中断中持有锁禁止再次进入light sleep，
+/// 接收线程收完这一帧后调用`frame_done`释放，帧的剩余部分由RMT正常接收。
+pub struct IrWake {
+    pin: sys::gpio_num_t,
+    lock: sys::esp_pm_lock_handle_t,
+    armed: AtomicBool,
+    /// 中断已经获取了锁
+    held: AtomicBool,
+}
+
+// 电源管理锁的句柄可以在任意任务和中断中使用
+unsafe impl Send for IrWake {}
+unsafe impl Sync for IrWake {}
+
+impl IrWake {
+    pub fn new(pin: u8) -> Result<Arc<Self>, EspError> {
+        let mut lock = ptr::null_mut();
+        EspError::convert(unsafe {
+            sys::esp_pm_lock_create(sys::esp_pm_lock_type_t_ESP_PM_NO_LIGHT_SLEEP, 0, c"ir_wake".as_ptr(), &mut lock)
+        })?;
+        Ok(Arc::new(Self {
+            pin: pin as sys::gpio_num_t,
+            lock,
+            armed: AtomicBool::new(false),
+            held: AtomicBool::new(false),
+        }))
+    }
+
+    /// 接收线程在一帧结束或等待超时后调用，允许再次进入light sleep
+    pub fn frame_done(&self) {
+        if self.held.swap(false, Ordering::AcqRel) {
+            unsafe { sys::esp_pm_lock_release(self.lock) };
+        }
+        if self.armed.load(Ordering::Acquire) {
+            unsafe { sys::gpio_intr_enable(self.pin) };
+        }
+    }
+
+    /// 低电平唤醒并触发中断
+    fn arm(self: &Arc<Self>) -> Result<(), EspError> {
+        // 服务已经由其他驱动安装时返回INVALID_STATE
+        let installed = unsafe { sys::gpio_install_isr_service(0) };
+        if installed != sys::ESP_ERR_INVALID_STATE {
+            EspError::convert(installed)?;
+        }
+        EspError::convert(unsafe { sys::gpio_wakeup_enable(self.pin, sys::gpio_int_type_t_GPIO_INTR_LOW_LEVEL) })?;
+        EspError::convert(unsafe { sys::esp_sleep_enable_gpio_wakeup() })?;
+        // 唤醒源只在省电模式中使用，IrWake不会被释放
+        EspError::convert(unsafe {
+            sys::gpio_isr_handler_add(self.pin, Some(on_ir_low), Arc::as_ptr(self) as *mut c_void)
+        })?;
+        self.armed.store(true, Ordering::Release);
+        EspError::convert(unsafe { sys::gpio_intr_enable(self.pin) })
+    }
+
+    fn disarm(&self) {
+        self.armed.store(false, Ordering::Release);
+        unsafe {
+            sys::gpio_intr_disable(self.pin);
+            sys::gpio_isr_handler_remove(self.pin);
+            sys::gpio_wakeup_disable(self.pin);
+        }
+        if self.held.swap(false, Ordering::AcqRel) {
+            unsafe { sys::esp_pm_lock_release(self.lock) };
+        }
+    }
+}
+
+/// 电平中断会一直触发，获取锁后关闭中断，等`frame_done`重新打开
+unsafe extern "C" fn on_ir_low(arg: *mut c_void) {
+    let wake = &*(arg as *const IrWake);
+    sys::gpio_intr_disable(wake.pin);
+    if !wake.held.swap(true, Ordering::AcqRel) {
+        sys::esp_pm_lock_acquire(wake.lock);
+    }
+}
+
+/// 省电模式：没有蓝牙连接和红外活动一段时间后打开自动light sleep
+///
+/// 主循环在Tick中调用`poll`，有活动时调用`activity`；状态改变时返回新状态，
+/// 由主循环相应地调整广播间隔和Tick间隔。
+pub struct PowerSave {
+    enabled: bool,
+    idle_after: Duration,
+    /// 没有打开电源管理或红外接收不可用时为None，无法进入省电模式
+    wake: Option<Arc<IrWake>>,
+    state: PowerState,
+    last_activity: Instant,
+    /// 本次进入省电模式的时刻，以及之前累计的时长
+    sleep_started: Option<Instant>,
+    slept: Duration,
+}
+
+impl PowerSave {
+    pub fn new(wake: Option<Arc<IrWake>>, enabled: bool, idle_after_s: u32) -> Self {
+        Self {
+            enabled,
+            idle_after: Duration::from_secs(idle_after_s as u64),
+            wake,
+            state: PowerState::Active,
+            last_activity: Instant::now(),
+            sleep_started: None,
+            slept: Duration::ZERO,
+        }
+    }
+
+    pub fn state(&self) -> PowerState {
+        self.state
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// 启动以来处于省电模式的总时长
+    pub fn slept(&self, now: Instant) -> Duration {
+        self.slept + self.sleep_started.map_or(Duration::ZERO, |started| now.duration_since(started))
+    }
+
+    /// 关闭时立即退出省电模式
+    pub fn set_enabled(&mut self, enabled: bool, now: Instant) -> Option<PowerState> {
+        self.enabled = enabled;
+        self.last_activity = now;
+        if enabled {
+            None
+        } else {
+            self.exit(now)
+        }
+    }
+
+    pub fn set_idle_after(&mut self, idle_after_s: u32) {
+        self.idle_after = Duration::from_secs(idle_after_s as u64);
+    }
+
+    /// 收到红外信号、蓝牙消息或按键时调用，省电模式中时退出
+    pub fn activity(&mut self, now: Instant) -> Option<PowerState> {
+        self.last_activity = now;
+        self.exit(now)
+    }
+
+    /// 有客户端连接时退出省电模式，空闲足够久时进入
+    pub fn poll(&mut self, now: Instant, connected: bool) -> Option<PowerState> {
+        if connected {
+            self.last_activity = now;
+            return self.exit(now);
+        }
+        if !self.enabled || self.state == PowerState::Sleep || now.duration_since(self.last_activity) < self.idle_after {
+            return None;
+        }
+        match self.enter() {
+            Ok(()) => {
+                log::info!("进入省电模式");
+                self.state = PowerState::Sleep;
+                self.sleep_started = Some(now);
+                Some(PowerState::Sleep)
+            }
+            Err(e) => {
+                // sdkconfig没有打开电源管理时不再重试
+                log::error!("进入省电模式失败，已关闭省电模式: {:?}", e);
+                if let Some(wake) = &self.wake {
+                    wake.disarm();
+                }
+                self.enabled = false;
+                None
+            }
+        }
+    }
+
+    fn enter(&self) -> Result<(), EspError> {
+        let wake = self.wake.as_ref().ok_or_else(EspError::from_infallible::<ESP_ERR_NOT_SUPPORTED>)?;
+        wake.arm()?;
+        configure_pm(true)
+    }
+
+    fn exit(&mut self, now: Instant) -> Option<PowerState> {
+        let started = self.sleep_started.take()?;
+        self.slept += now.duration_since(started);
+        if let Err(e) = configure_pm(false) {
+            log::error!("关闭自动light sleep失败: {:?}", e);
+        }
+        if let Some(wake) = &self.wake {
+            wake.disarm();
+        }
+        self.state = PowerState::Active;
+        log::info!("退出省电模式，本次{}秒", now.duration_since(started).as_secs());
+        Some(PowerState::Active)
+    }
+}
+
+/// 打开时空闲降频并自动light sleep，关闭时保持最高频率
+fn configure_pm(light_sleep: bool) -> Result<(), EspError> {
+    let config = sys::esp_pm_config_t {
+        max_freq_mhz: MAX_FREQ_MHZ,
+        min_freq_mhz: if light_sleep { MIN_FREQ_MHZ } else { MAX_FREQ_MHZ },
+        light_sleep_enable: light_sleep,
+    };
+    EspError::convert(unsafe { sys::esp_pm_configure(&config as *const _ as *const c_void) })
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+
+use esp_idf_hal::gpio::OutputPin;
+use esp_idf_hal::peripheral::Peripheral;
+use esp_idf_hal::rmt::config::TransmitConfig;
+use esp_idf_hal::rmt::{TxRmtDriver, CHANNEL0, CHANNEL1, CHANNEL2, CHANNEL3};
+use esp_idf_svc::sys::EspError;
+
+/// ESP32-S3的发射通道数量，每个发射通道有一个自己的内存块，能保存48个RMT符号
+pub const TX_CHANNELS: usize = 4;
+
+/// LED和红外发射在分配表中的名称
+pub const OWNER_LED: &str = "led";
+pub const OWNER_IR: &str = "ir";
+
+/// 一个使用者分到的通道，占用从`channel`开始的`mem_blocks`个内存块
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Assignment {
+    pub owner: &'static str,
+    pub channel: usize,
+    pub mem_blocks: u8,
+}
+
+impl fmt::Display for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mem_blocks {
+            1 => write!(f, "{} 通道{}", self.owner, self.channel),
+            blocks => write!(f, "{} 通道{}-{}", self.owner, self.channel, self.channel + blocks as usize - 1),
+        }
+    }
+}
+
+/// 发射通道分配失败的原因
+#[derive(Debug)]
+pub enum RmtError {
+    /// 内存块数量为0或超过发射通道的总数
+    InvalidBlocks { owner: &'static str, mem_blocks: u8 },
+    /// 剩下的内存块中没有足够长的连续空间
+    NoRoom {
+        owner: &'static str,
+        mem_blocks: u8,
+        assigned: Vec<Assignment>,
+    },
+    /// 创建驱动失败
+    Driver(EspError),
+}
+
+impl fmt::Display for RmtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidBlocks { owner, mem_blocks } => {
+                write!(f, "{}请求{}个内存块，必须在1~{}之间", owner, mem_blocks, TX_CHANNELS)
+            }
+            Self::NoRoom {
+                owner,
+                mem_blocks,
+                assigned,
+            } => {
+                write!(f, "{}需要{}个连续的发射内存块，共{}个，已分配: ", owner, mem_blocks, TX_CHANNELS)?;
+                for (index, assignment) in assigned.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str("，")?;
+                    }
+                    write!(f, "{}", assignment)?;
+                }
+                Ok(())
+            }
+            Self::Driver(e) => write!(f, "创建RMT发射驱动失败: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for RmtError {}
+
+impl From<EspError> for RmtError {
+    fn from(e: EspError) -> Self {
+        Self::Driver(e)
+    }
+}
+
+/// 发射互斥
+///
+/// 旧版RMT驱动的所有通道共用一个中断，长信号靠中断在发送过程中续写内存块；
+/// 两个通道同时续写时中断可能来不及，WS2812和红外信号都会出错，所以同一时间只发送一路。
+#[derive(Clone, Default)]
+pub struct TxGate(Arc<Mutex<()>>);
+
+impl TxGate {
+    /// 等待另一路发送完成
+    pub fn lock(&self) -> MutexGuard<'_, ()> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// 另一路正在发送时返回None
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, ()>> {
+        match self.0.try_lock() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+}
+
+/// 管理发射通道和内存块，启动时按配置把通道分给LED和红外发射
+///
+/// 通道n的内存块数量大于1时占用之后通道的内存块，被占用的通道不能再使用。
+pub struct RmtAllocator {
+    channel0: Option<CHANNEL0>,
+    channel1: Option<CHANNEL1>,
+    channel2: Option<CHANNEL2>,
+    channel3: Option<CHANNEL3>,
+    assigned: Vec<Assignment>,
+    gate: TxGate,
+}
+
+impl RmtAllocator {
+    pub fn new(channel0: CHANNEL0, channel1: CHANNEL1, channel2: CHANNEL2, channel3: CHANNEL3) -> Self {
+        Self {
+            channel0: Some(channel0),
+            channel1: Some(channel1),
+            channel2: Some(channel2),
+            channel3: Some(channel3),
+            assigned: Vec::new(),
+            gate: TxGate::default(),
+        }
+    }
+
+    /// 按顺序分配`requests`中的内存块数量能否放下，不创建驱动
+    pub fn check(requests: &[(&'static str, u8)]) -> Result<Vec<Assignment>, RmtError> {
+        let mut assigned = Vec::new();
+        for &(owner, mem_blocks) in requests {
+            let assignment = place(&assigned, owner, mem_blocks)?;
+            assigned.push(assignment);
+        }
+        Ok(assigned)
+    }
+
+    /// 在第一个能放下`config.mem_block_num`个连续内存块的通道上创建发射驱动
+    pub fn tx_driver(
+        &mut self,
+        owner: &'static str,
+        pin: impl Peripheral<P = impl OutputPin> + 'static,
+        config: &TransmitConfig,
+    ) -> Result<TxRmtDriver<'static>, RmtError> {
+        let assignment = place(&self.assigned, owner, config.mem_block_num)?;
+        let driver = match assignment.channel {
+            0 => TxRmtDriver::new(self.channel0.take().unwrap(), pin, config)?,
+            1 => TxRmtDriver::new(self.channel1.take().unwrap(), pin, config)?,
+            2 => TxRmtDriver::new(self.channel2.take().unwrap(), pin, config)?,
+            _ => TxRmtDriver::new(self.channel3.take().unwrap(), pin, config)?,
+        };
+        log::info!("RMT发射通道分配: {}（{}个内存块）", assignment, assignment.mem_blocks);
+        self.assigned.push(assignment);
+        Ok(driver)
+    }
+
+    pub fn assigned(&self) -> &[Assignment] {
+        &self.assigned
+    }
+
+    /// 所有发射驱动共用的互斥
+    pub fn gate(&self) -> TxGate {
+        self.gate.clone()
+    }
+}
+
+/// 从通道0开始找到第一段足够长的连续空闲内存块
+fn place(assigned: &[Assignment], owner: &'static str, mem_blocks: u8) -> Result<Assignment, RmtError> {
+    let len = mem_blocks as usize;
+    if len == 0 || len > TX_CHANNELS {
+        return Err(RmtError::InvalidBlocks { owner, mem_blocks });
+    }
+    let used = |block: usize| {
+        assigned
+            .iter()
+            .any(|assignment| (assignment.channel..assignment.channel + assignment.mem_blocks as usize).contains(&block))
+    };
+    (0..=TX_CHANNELS - len)
+        .find(|&channel| (channel..channel + len).all(|block| !used(block)))
+        .map(|channel| Assignment {
+            owner,
+            channel,
+            mem_blocks,
+        })
+        .ok_or_else(|| RmtError::NoRoom {
+            owner,
+            mem_blocks,
+            assigned: assigned.to_vec(),
+        })
+}
@@ -0,0 +1,110 @@
+use std::fmt::Debug;
+
+/// 启动自检检查的子系统，编号即失败时LED红色闪烁的次数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    /// LED的RMT发射通道和计数时钟
+    LedTx = 1,
+    /// 红外发射的RMT通道
+    IrTx = 2,
+    /// 红外接收的RMT通道
+    IrRx = 3,
+    /// NVS分区能否读取
+    Nvs = 4,
+    /// 蓝牙GATT服务
+    Ble = 5,
+    /// 红外发射管的LEDC功率控制
+    TxPower = 6,
+    /// 载波测量的RMT接收通道
+    Carrier = 7,
+    /// 电池监测线程
+    Battery = 8,
+    /// BOOT按键的GPIO
+    Button = 9,
+    /// 发送Tick和轮询按键的定时器线程
+    Ticker = 10,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 10] = [
+        Self::LedTx,
+        Self::IrTx,
+        Self::IrRx,
+        Self::Nvs,
+        Self::Ble,
+        Self::TxPower,
+        Self::Carrier,
+        Self::Battery,
+        Self::Button,
+        Self::Ticker,
+    ];
+
+    pub fn number(&self) -> u32 {
+        *self as u32
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::LedTx => "led",
+            Self::IrTx => "ir_tx",
+            Self::IrRx => "ir_rx",
+            Self::Nvs => "nvs",
+            Self::Ble => "ble",
+            Self::TxPower => "tx_power",
+            Self::Carrier => "carrier",
+            Self::Battery => "battery",
+            Self::Button => "button",
+            Self::Ticker => "ticker",
+        }
+    }
+}
+
+/// 启动自检的结果，某个子系统失败时设备继续启动，只是缺少该功能
+#[derive(Debug, Default)]
+pub struct SelfCheck {
+    failed: Vec<Subsystem>,
+}
+
+impl SelfCheck {
+    /// 记录一项检查的结果，失败时记下错误并返回None
+    pub fn check<T, E: Debug>(&mut self, subsystem: Subsystem, result: Result<T, E>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                log::error!("启动自检失败: {}（{}）: {:?}", subsystem.name(), subsystem.number(), e);
+                self.fail(subsystem);
+                None
+            }
+        }
+    }
+
+    pub fn fail(&mut self, subsystem: Subsystem) {
+        if !self.failed.contains(&subsystem) {
+            self.failed.push(subsystem);
+        }
+    }
+
+    pub fn passed(&self, subsystem: Subsystem) -> bool {
+        !self.failed.contains(&subsystem)
+    }
+
+    /// 有子系统失败，设备在降级模式下运行
+    pub fn degraded(&self) -> bool {
+        !self.failed.is_empty()
+    }
+
+    /// 编号最小的失败子系统，LED按它的编号闪烁
+    pub fn first_failure(&self) -> Option<Subsystem> {
+        self.failed.iter().copied().min_by_key(Subsystem::number)
+    }
+
+    /// 一行的结构化报告，例如`SELFCHECK: degraded led=ok ir_tx=ok ir_rx=fail nvs=ok ble=ok tx_power=ok ...`
+    pub fn report(&self) -> String {
+        let mut report = format!("SELFCHECK: {}", if self.degraded() { "degraded" } else { "ok" });
+        for subsystem in Subsystem::ALL {
+            let result = if self.passed(subsystem) { "ok" } else { "fail" };
+            report.push_str(&format!(" {}={}", subsystem.name(), result));
+        }
+        report
+    }
+}
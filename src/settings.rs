@@ -0,0 +1,662 @@
+use esp_idf_svc::bt::BdAddr;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sys::EspError;
+use log::LevelFilter;
+
+use crate::battery::{DEFAULT_ADC_PIN, DEFAULT_DIVIDER_PERMILLE};
+use crate::button::{ButtonAction, ButtonEvent};
+use crate::config::{
+    DEFAULT_BLE_TX_POWER_DBM, DEFAULT_IDLE_THRESHOLD_US, DEFAULT_IR_RX_PIN, DEFAULT_IR_TX_PIN, DEFAULT_LED_CLOCK_DIVIDER,
+    DEFAULT_LED_PIN,
+};
+use crate::bluetooth::{
+    DEFAULT_DEVICE_NAME, DEFAULT_HARDWARE_REVISION, DEFAULT_INDICATION_TIMEOUT_MS, MAX_DEVICE_NAME_LEN,
+    MAX_HARDWARE_REVISION_LEN, MAX_WHITELIST,
+};
+use crate::ir::filter::DEFAULT_MIN_PULSE_US;
+use crate::powersave::DEFAULT_IDLE_S;
+use crate::namespace::Namespace;
+use crate::led::{
+    PixelFormat, PowerBudget, Timing, BRIGHTNESS_RANGE, CHANNEL_MA_RANGE, DEFAULT_BRIGHTNESS_PERCENT, DEFAULT_CHANNEL_MA,
+    MAX_STRIP_LEN,
+};
+use crate::ir::noise::{DEFAULT_MIN_HEADER_US, DEFAULT_MIN_PULSES};
+use crate::ir::power::{DEFAULT_TX_POWER_PERCENT, DEFAULT_WARM_UP_US};
+use crate::ir::quality;
+use crate::rmt::TX_CHANNELS;
+use crate::http::{DEFAULT_MAX_BODY, MAX_TOKEN_LEN};
+use crate::mqtt::{MAX_CREDENTIAL_LEN, MAX_DEVICE_LEN, MAX_URL_LEN};
+use crate::wifi::{MAX_SSID_LEN, PASSWORD_LEN_RANGE};
+use crate::write_policy::PIN_LEN;
+
+/// 设置所在的NVS命名空间
+const NAMESPACE: &str = "ir_settings";
+
+const KEY_MIN_PULSE_US: &str = "min_pulse_us";
+const KEY_NEC_STRICT: &str = "nec_strict";
+const KEY_MIN_PULSES: &str = "min_pulses";
+const KEY_MIN_HEADER_US: &str = "min_header_us";
+const KEY_MULTI_FRAME: &str = "multi_frame";
+const KEY_QUALITY_MIN: &str = "quality_min";
+const KEY_TX_POWER: &str = "tx_power";
+const KEY_WARM_UP_US: &str = "tx_warm_up_us";
+const KEY_LRU_EVICT: &str = "lru_evict";
+const KEY_IND_TIMEOUT_MS: &str = "ind_timeout_ms";
+const KEY_PASSKEY: &str = "passkey";
+const KEY_DEVICE_NAME: &str = "device_name";
+const KEY_USER_ID: &str = "user_id";
+const KEY_LOG_LEVEL: &str = "log_level";
+const KEY_BATTERY_OFF: &str = "battery_off";
+const KEY_BATTERY_PIN: &str = "battery_pin";
+const KEY_BATTERY_DIVIDER: &str = "battery_div";
+const KEY_HW_REVISION: &str = "hw_revision";
+const KEY_LOCK_PIN: &str = "lock_pin";
+const KEY_WHITELIST_ON: &str = "whitelist_on";
+const KEY_WHITELIST: &str = "whitelist";
+const KEY_LED_BRIGHTNESS: &str = "led_brightness";
+const KEY_LED_COUNT: &str = "led_count";
+const KEY_LED_FORMAT: &str = "led_format";
+const KEY_LED_AUTO_WHITE: &str = "led_auto_white";
+const KEY_LED_TIMING: &str = "led_timing";
+const KEY_LED_MAX_MA: &str = "led_max_ma";
+const KEY_LED_CHANNEL_MA: &str = "led_channel_ma";
+const KEY_RMT_LED_BLOCKS: &str = "rmt_led_blocks";
+const KEY_RMT_IR_BLOCKS: &str = "rmt_ir_blocks";
+const KEY_LED_PIN: &str = "led_pin";
+const KEY_IR_TX_PIN: &str = "ir_tx_pin";
+const KEY_IR_RX_PIN: &str = "ir_rx_pin";
+const KEY_LED_CLK_DIV: &str = "led_clk_div";
+const KEY_IDLE_US: &str = "idle_us";
+const KEY_BLE_TX_POWER: &str = "ble_tx_power";
+const KEY_BTN_PRESS: &str = "btn_press";
+const KEY_BTN_DOUBLE: &str = "btn_double";
+const KEY_BTN_LONG: &str = "btn_long";
+const KEY_BTN_HOLD: &str = "btn_hold";
+const KEY_BOOT_COUNT: &str = "boot_count";
+const KEY_POWER_SAVE: &str = "power_save";
+const KEY_SLEEP_IDLE_S: &str = "sleep_idle_s";
+const KEY_WIFI_SSID: &str = "wifi_ssid";
+const KEY_WIFI_PASS: &str = "wifi_pass";
+const KEY_MQTT_URL: &str = "mqtt_url";
+const KEY_MQTT_USER: &str = "mqtt_user";
+const KEY_MQTT_PASS: &str = "mqtt_pass";
+const KEY_MQTT_DEVICE: &str = "mqtt_device";
+const KEY_HTTP_TOKEN: &str = "http_token";
+const KEY_HTTP_MAX_BODY: &str = "http_max_body";
+
+/// 没有设置过时的配对码
+pub const DEFAULT_PASSKEY: u32 = 123_456;
+
+/// 保存在NVS中、重启后仍然有效的运行时设置
+pub struct Settings {
+    nvs: Namespace,
+}
+
+impl Settings {
+    pub fn new(partition: &EspDefaultNvsPartition) -> Result<Self, EspError> {
+        Ok(Self {
+            nvs: Namespace::open(partition, NAMESPACE)?,
+        })
+    }
+
+    /// NVS不可用时使用，未修改的设置都是默认值，修改只保留到重启
+    pub fn in_memory() -> Self {
+        Self {
+            nvs: Namespace::in_memory(NAMESPACE),
+        }
+    }
+
+    /// 启动自检：读取一个键，确认NVS分区可以访问
+    pub fn probe(&self) -> Result<(), EspError> {
+        self.nvs.contains(KEY_LOG_LEVEL).map(|_| ())
+    }
+
+    /// 软件毛刺滤波阈值（微秒），未保存过时返回默认值
+    pub fn min_pulse_us(&self) -> u32 {
+        self.get_u32(KEY_MIN_PULSE_US, DEFAULT_MIN_PULSE_US)
+    }
+
+    pub fn set_min_pulse_us(&self, value: u32) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_MIN_PULSE_US, value)
+    }
+
+    /// NEC严格模式，默认关闭
+    pub fn nec_strict(&self) -> bool {
+        self.get_bool(KEY_NEC_STRICT)
+    }
+
+    pub fn set_nec_strict(&self, strict: bool) -> Result<(), EspError> {
+        self.nvs.set_u8(KEY_NEC_STRICT, strict as u8)
+    }
+
+    /// 噪声过滤的最小脉冲数量
+    pub fn min_pulses(&self) -> u32 {
+        self.get_u32(KEY_MIN_PULSES, DEFAULT_MIN_PULSES)
+    }
+
+    pub fn set_min_pulses(&self, value: u32) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_MIN_PULSES, value)
+    }
+
+    /// 噪声过滤的最短引导mark（微秒）
+    pub fn min_header_us(&self) -> u32 {
+        self.get_u32(KEY_MIN_HEADER_US, DEFAULT_MIN_HEADER_US)
+    }
+
+    pub fn set_min_header_us(&self, value: u32) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_MIN_HEADER_US, value)
+    }
+
+    /// 多帧录制模式，默认关闭
+    pub fn multi_frame(&self) -> bool {
+        self.get_bool(KEY_MULTI_FRAME)
+    }
+
+    pub fn set_multi_frame(&self, multi_frame: bool) -> Result<(), EspError> {
+        self.nvs.set_u8(KEY_MULTI_FRAME, multi_frame as u8)
+    }
+
+    /// 录制质量阈值（0–100），为0时不检查
+    pub fn quality_threshold(&self) -> u8 {
+        self.get_u32(KEY_QUALITY_MIN, quality::DEFAULT_THRESHOLD as u32).min(100) as u8
+    }
+
+    pub fn set_quality_threshold(&self, threshold: u8) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_QUALITY_MIN, threshold as u32)
+    }
+
+    /// 发射功率（百分比）
+    pub fn tx_power(&self) -> u8 {
+        self.get_u32(KEY_TX_POWER, DEFAULT_TX_POWER_PERCENT as u32).min(100) as u8
+    }
+
+    pub fn set_tx_power(&self, percent: u8) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_TX_POWER, percent as u32)
+    }
+
+    /// LED亮度上限（百分比）
+    pub fn led_brightness(&self) -> u8 {
+        let percent = self.get_u32(KEY_LED_BRIGHTNESS, DEFAULT_BRIGHTNESS_PERCENT as u32);
+        percent.clamp(*BRIGHTNESS_RANGE.start() as u32, *BRIGHTNESS_RANGE.end() as u32) as u8
+    }
+
+    pub fn set_led_brightness(&self, percent: u8) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_LED_BRIGHTNESS, percent as u32)
+    }
+
+    /// 灯带的像素数量，默认只有板载的一个LED
+    pub fn led_count(&self) -> usize {
+        self.get_u32(KEY_LED_COUNT, 1).clamp(1, MAX_STRIP_LEN as u32) as usize
+    }
+
+    pub fn set_led_count(&self, count: u8) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_LED_COUNT, count as u32)
+    }
+
+    /// LED的像素格式，默认WS2812的GRB
+    pub fn led_format(&self) -> PixelFormat {
+        let mut buf = [0; 8];
+        match self.nvs.get_str(KEY_LED_FORMAT, &mut buf) {
+            Ok(format) => format.and_then(|format| format.parse().ok()).unwrap_or_default(),
+            Err(e) => {
+                log::warn!("读取设置{}失败: {:?}", KEY_LED_FORMAT, e);
+                PixelFormat::default()
+            }
+        }
+    }
+
+    pub fn set_led_format(&mut self, format: PixelFormat) -> Result<(), EspError> {
+        self.nvs.set_str(KEY_LED_FORMAT, format.as_str())
+    }
+
+    /// RGBW像素是否把RGB共同的部分换成白光，默认关闭
+    pub fn led_auto_white(&self) -> bool {
+        self.get_bool(KEY_LED_AUTO_WHITE)
+    }
+
+    pub fn set_led_auto_white(&self, enabled: bool) -> Result<(), EspError> {
+        self.nvs.set_u8(KEY_LED_AUTO_WHITE, enabled as u8)
+    }
+
+    /// LED的时序，预设名称或自定义的各段时长，默认WS2812B
+    pub fn led_timing(&self) -> Timing {
+        let mut buf = [0; 32];
+        match self.nvs.get_str(KEY_LED_TIMING, &mut buf) {
+            Ok(timing) => timing.and_then(|timing| timing.parse().ok()).unwrap_or_default(),
+            Err(e) => {
+                log::warn!("读取设置{}失败: {:?}", KEY_LED_TIMING, e);
+                Timing::default()
+            }
+        }
+    }
+
+    pub fn set_led_timing(&mut self, timing: Timing) -> Result<(), EspError> {
+        self.nvs.set_str(KEY_LED_TIMING, &timing.to_string())
+    }
+
+    /// 灯带的电流预算，默认不限制
+    pub fn led_power(&self) -> PowerBudget {
+        let channel_ma = self.get_u32(KEY_LED_CHANNEL_MA, DEFAULT_CHANNEL_MA as u32);
+        PowerBudget {
+            channel_ma: channel_ma.clamp(*CHANNEL_MA_RANGE.start() as u32, *CHANNEL_MA_RANGE.end() as u32) as u16,
+            max_current_ma: self.get_u32(KEY_LED_MAX_MA, 0),
+        }
+    }
+
+    pub fn set_led_power(&self, power: PowerBudget) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_LED_CHANNEL_MA, power.channel_ma as u32)?;
+        self.nvs.set_u32(KEY_LED_MAX_MA, power.max_current_ma)
+    }
+
+    /// LED发射通道的RMT内存块数量，重启后生效
+    pub fn rmt_led_blocks(&self) -> u8 {
+        self.get_u32(KEY_RMT_LED_BLOCKS, 1).clamp(1, TX_CHANNELS as u32) as u8
+    }
+
+    pub fn set_rmt_led_blocks(&self, blocks: u8) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_RMT_LED_BLOCKS, blocks as u32)
+    }
+
+    /// 红外发射通道的RMT内存块数量，重启后生效
+    pub fn rmt_ir_blocks(&self) -> u8 {
+        self.get_u32(KEY_RMT_IR_BLOCKS, 1).clamp(1, TX_CHANNELS as u32) as u8
+    }
+
+    pub fn set_rmt_ir_blocks(&self, blocks: u8) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_RMT_IR_BLOCKS, blocks as u32)
+    }
+
+    /// LED数据引脚，重启后生效，由`Config`检查是否可用
+    pub fn led_pin(&self) -> u8 {
+        self.get_u32(KEY_LED_PIN, DEFAULT_LED_PIN as u32).min(u8::MAX as u32) as u8
+    }
+
+    pub fn set_led_pin(&self, pin: u8) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_LED_PIN, pin as u32)
+    }
+
+    /// 红外发射管引脚，重启后生效
+    pub fn ir_tx_pin(&self) -> u8 {
+        self.get_u32(KEY_IR_TX_PIN, DEFAULT_IR_TX_PIN as u32).min(u8::MAX as u32) as u8
+    }
+
+    pub fn set_ir_tx_pin(&self, pin: u8) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_IR_TX_PIN, pin as u32)
+    }
+
+    /// 红外接收头引脚，重启后生效
+    pub fn ir_rx_pin(&self) -> u8 {
+        self.get_u32(KEY_IR_RX_PIN, DEFAULT_IR_RX_PIN as u32).min(u8::MAX as u32) as u8
+    }
+
+    pub fn set_ir_rx_pin(&self, pin: u8) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_IR_RX_PIN, pin as u32)
+    }
+
+    /// LED发射通道的时钟分频，重启后生效
+    pub fn led_clock_divider(&self) -> u8 {
+        self.get_u32(KEY_LED_CLK_DIV, DEFAULT_LED_CLOCK_DIVIDER as u32).min(u8::MAX as u32) as u8
+    }
+
+    pub fn set_led_clock_divider(&self, divider: u8) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_LED_CLK_DIV, divider as u32)
+    }
+
+    /// 红外接收的空闲阈值（微秒）
+    pub fn idle_threshold_us(&self) -> u32 {
+        self.get_u32(KEY_IDLE_US, DEFAULT_IDLE_THRESHOLD_US)
+    }
+
+    pub fn set_idle_threshold_us(&self, value: u32) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_IDLE_US, value)
+    }
+
+    /// 蓝牙发射功率（dBm）
+    pub fn ble_tx_power(&self) -> i8 {
+        match self.nvs.get_i32(KEY_BLE_TX_POWER) {
+            Ok(value) => value.map_or(DEFAULT_BLE_TX_POWER_DBM, |dbm| dbm.clamp(i8::MIN as i32, i8::MAX as i32) as i8),
+            Err(e) => {
+                log::warn!("读取设置{}失败: {:?}", KEY_BLE_TX_POWER, e);
+                DEFAULT_BLE_TX_POWER_DBM
+            }
+        }
+    }
+
+    pub fn set_ble_tx_power(&self, dbm: i8) -> Result<(), EspError> {
+        self.nvs.set_i32(KEY_BLE_TX_POWER, dbm as i32)
+    }
+
+    /// 按键手势对应的操作，没有修改过时使用默认的对应关系
+    pub fn button_action(&self, event: ButtonEvent) -> ButtonAction {
+        let key = button_key(event);
+        let mut buf = [0; 16];
+        match self.nvs.get_str(key, &mut buf) {
+            Ok(action) => action
+                .and_then(|action| action.parse().ok())
+                .unwrap_or(ButtonAction::default_for(event)),
+            Err(e) => {
+                log::warn!("读取设置{}失败: {:?}", key, e);
+                ButtonAction::default_for(event)
+            }
+        }
+    }
+
+    pub fn set_button_action(&mut self, event: ButtonEvent, action: ButtonAction) -> Result<(), EspError> {
+        self.nvs.set_str(button_key(event), action.name())
+    }
+
+    /// 打开发射管使能引脚后的预热时间（微秒）
+    pub fn warm_up_us(&self) -> u32 {
+        self.get_u32(KEY_WARM_UP_US, DEFAULT_WARM_UP_US)
+    }
+
+    pub fn set_warm_up_us(&self, value: u32) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_WARM_UP_US, value)
+    }
+
+    /// 空间不足时淘汰最久没有使用的录制，默认关闭
+    pub fn lru_evict(&self) -> bool {
+        self.get_bool(KEY_LRU_EVICT)
+    }
+
+    pub fn set_lru_evict(&self, evict: bool) -> Result<(), EspError> {
+        self.nvs.set_u8(KEY_LRU_EVICT, evict as u8)
+    }
+
+    /// 等待客户端确认指示的时间（毫秒）
+    pub fn indication_timeout_ms(&self) -> u32 {
+        self.get_u32(KEY_IND_TIMEOUT_MS, DEFAULT_INDICATION_TIMEOUT_MS)
+    }
+
+    pub fn set_indication_timeout_ms(&self, value: u32) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_IND_TIMEOUT_MS, value)
+    }
+
+    /// 蓝牙配对码
+    pub fn passkey(&self) -> u32 {
+        self.get_u32(KEY_PASSKEY, DEFAULT_PASSKEY)
+    }
+
+    pub fn set_passkey(&self, passkey: u32) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_PASSKEY, passkey)
+    }
+
+    /// 蓝牙设备名称
+    pub fn device_name(&self) -> String {
+        let mut buf = [0; MAX_DEVICE_NAME_LEN + 1];
+        match self.nvs.get_str(KEY_DEVICE_NAME, &mut buf) {
+            Ok(name) => name.unwrap_or(DEFAULT_DEVICE_NAME).to_string(),
+            Err(e) => {
+                log::warn!("读取设置{}失败: {:?}", KEY_DEVICE_NAME, e);
+                DEFAULT_DEVICE_NAME.to_string()
+            }
+        }
+    }
+
+    pub fn set_device_name(&mut self, name: &str) -> Result<(), EspError> {
+        self.nvs.set_str(KEY_DEVICE_NAME, name)
+    }
+
+    /// 广播厂商数据中的用户编号，未设置时不放厂商数据
+    pub fn user_id(&self) -> Option<u16> {
+        self.nvs.get_u16(KEY_USER_ID).unwrap_or_else(|e| {
+            log::warn!("读取设置{}失败: {:?}", KEY_USER_ID, e);
+            None
+        })
+    }
+
+    pub fn set_user_id(&mut self, user_id: Option<u16>) -> Result<(), EspError> {
+        match user_id {
+            Some(user_id) => self.nvs.set_u16(KEY_USER_ID, user_id),
+            None => self.nvs.remove(KEY_USER_ID).map(|_| ()),
+        }
+    }
+
+    /// 设备信息服务中的硬件版本，由组装设备的人写入
+    pub fn hardware_revision(&self) -> String {
+        let mut buf = [0; MAX_HARDWARE_REVISION_LEN + 1];
+        match self.nvs.get_str(KEY_HW_REVISION, &mut buf) {
+            Ok(revision) => revision.unwrap_or(DEFAULT_HARDWARE_REVISION).to_string(),
+            Err(e) => {
+                log::warn!("读取设置{}失败: {:?}", KEY_HW_REVISION, e);
+                DEFAULT_HARDWARE_REVISION.to_string()
+            }
+        }
+    }
+
+    pub fn set_hardware_revision(&mut self, revision: &str) -> Result<(), EspError> {
+        self.nvs.set_str(KEY_HW_REVISION, revision)
+    }
+
+    /// 锁定写入时解锁用的PIN，没有锁定时为None
+    pub fn lock_pin(&self) -> Option<String> {
+        let mut buf = [0; *PIN_LEN.end() + 1];
+        match self.nvs.get_str(KEY_LOCK_PIN, &mut buf) {
+            Ok(pin) => pin.map(str::to_string),
+            Err(e) => {
+                log::warn!("读取设置{}失败: {:?}", KEY_LOCK_PIN, e);
+                None
+            }
+        }
+    }
+
+    pub fn set_lock_pin(&mut self, pin: Option<&str>) -> Result<(), EspError> {
+        match pin {
+            Some(pin) => self.nvs.set_str(KEY_LOCK_PIN, pin),
+            None => self.nvs.remove(KEY_LOCK_PIN).map(|_| ()),
+        }
+    }
+
+    /// 只接受白名单中的客户端连接，默认关闭
+    pub fn whitelist_enabled(&self) -> bool {
+        self.get_bool(KEY_WHITELIST_ON)
+    }
+
+    pub fn set_whitelist_enabled(&self, enabled: bool) -> Result<(), EspError> {
+        self.nvs.set_u8(KEY_WHITELIST_ON, enabled as u8)
+    }
+
+    /// 白名单中的地址，每个6字节连续保存
+    pub fn whitelist(&self) -> Vec<BdAddr> {
+        let mut buf = [0; MAX_WHITELIST * 6];
+        match self.nvs.get_blob(KEY_WHITELIST, &mut buf) {
+            Ok(blob) => blob
+                .unwrap_or_default()
+                .chunks_exact(6)
+                .map(|addr| BdAddr::from_bytes(addr.try_into().unwrap()))
+                .collect(),
+            Err(e) => {
+                log::warn!("读取设置{}失败: {:?}", KEY_WHITELIST, e);
+                Vec::new()
+            }
+        }
+    }
+
+    pub fn set_whitelist(&mut self, peers: &[BdAddr]) -> Result<(), EspError> {
+        let blob: Vec<u8> = peers.iter().flat_map(|peer| peer.raw()).collect();
+        self.nvs.set_blob(KEY_WHITELIST, &blob)
+    }
+
+    /// 通过蓝牙发送的日志级别，默认info
+    pub fn log_level(&self) -> LevelFilter {
+        let mut buf = [0; 8];
+        match self.nvs.get_str(KEY_LOG_LEVEL, &mut buf) {
+            Ok(level) => level.and_then(|level| level.parse().ok()).unwrap_or(LevelFilter::Info),
+            Err(e) => {
+                log::warn!("读取设置{}失败: {:?}", KEY_LOG_LEVEL, e);
+                LevelFilter::Info
+            }
+        }
+    }
+
+    pub fn set_log_level(&mut self, level: LevelFilter) -> Result<(), EspError> {
+        self.nvs.set_str(KEY_LOG_LEVEL, level.as_str())
+    }
+
+    /// 电池服务，默认打开；没有分压电路的设备应关闭
+    pub fn battery_enabled(&self) -> bool {
+        !self.get_bool(KEY_BATTERY_OFF)
+    }
+
+    pub fn set_battery_enabled(&self, enabled: bool) -> Result<(), EspError> {
+        self.nvs.set_u8(KEY_BATTERY_OFF, !enabled as u8)
+    }
+
+    /// 电池分压输入的GPIO
+    pub fn battery_pin(&self) -> u8 {
+        self.get_u32(KEY_BATTERY_PIN, DEFAULT_ADC_PIN as u32).min(u8::MAX as u32) as u8
+    }
+
+    pub fn set_battery_pin(&self, pin: u8) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_BATTERY_PIN, pin as u32)
+    }
+
+    /// 电池分压比（千分比）
+    pub fn battery_divider_permille(&self) -> u32 {
+        self.get_u32(KEY_BATTERY_DIVIDER, DEFAULT_DIVIDER_PERMILLE)
+    }
+
+    pub fn set_battery_divider_permille(&self, value: u32) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_BATTERY_DIVIDER, value)
+    }
+
+    /// 启动次数，每次启动时加一
+    pub fn boot_count(&self) -> u32 {
+        self.get_u32(KEY_BOOT_COUNT, 0)
+    }
+
+    pub fn set_boot_count(&self, count: u32) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_BOOT_COUNT, count)
+    }
+
+    /// 空闲时进入省电模式，默认关闭
+    pub fn power_save(&self) -> bool {
+        self.get_bool(KEY_POWER_SAVE)
+    }
+
+    pub fn set_power_save(&self, enabled: bool) -> Result<(), EspError> {
+        self.nvs.set_u8(KEY_POWER_SAVE, enabled as u8)
+    }
+
+    /// 空闲多少秒后进入省电模式
+    pub fn sleep_idle_s(&self) -> u32 {
+        self.get_u32(KEY_SLEEP_IDLE_S, DEFAULT_IDLE_S)
+    }
+
+    pub fn set_sleep_idle_s(&self, seconds: u32) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_SLEEP_IDLE_S, seconds)
+    }
+
+    /// Wi-Fi的SSID，为空时不初始化Wi-Fi
+    pub fn wifi_ssid(&self) -> String {
+        self.get_string(KEY_WIFI_SSID, MAX_SSID_LEN)
+    }
+
+    pub fn set_wifi_ssid(&mut self, ssid: &str) -> Result<(), EspError> {
+        self.nvs.set_str(KEY_WIFI_SSID, ssid)
+    }
+
+    /// Wi-Fi密码，开放网络为空
+    pub fn wifi_password(&self) -> String {
+        self.get_string(KEY_WIFI_PASS, *PASSWORD_LEN_RANGE.end())
+    }
+
+    pub fn set_wifi_password(&mut self, password: &str) -> Result<(), EspError> {
+        self.nvs.set_str(KEY_WIFI_PASS, password)
+    }
+
+    /// MQTT代理地址，为空时不连接
+    pub fn mqtt_url(&self) -> String {
+        self.get_string(KEY_MQTT_URL, MAX_URL_LEN)
+    }
+
+    pub fn set_mqtt_url(&mut self, url: &str) -> Result<(), EspError> {
+        self.nvs.set_str(KEY_MQTT_URL, url)
+    }
+
+    pub fn mqtt_username(&self) -> String {
+        self.get_string(KEY_MQTT_USER, MAX_CREDENTIAL_LEN)
+    }
+
+    pub fn set_mqtt_username(&mut self, username: &str) -> Result<(), EspError> {
+        self.nvs.set_str(KEY_MQTT_USER, username)
+    }
+
+    pub fn mqtt_password(&self) -> String {
+        self.get_string(KEY_MQTT_PASS, MAX_CREDENTIAL_LEN)
+    }
+
+    pub fn set_mqtt_password(&mut self, password: &str) -> Result<(), EspError> {
+        self.nvs.set_str(KEY_MQTT_PASS, password)
+    }
+
+    /// MQTT主题中的设备名称，为空时由设备名称生成
+    pub fn mqtt_device(&self) -> String {
+        self.get_string(KEY_MQTT_DEVICE, MAX_DEVICE_LEN)
+    }
+
+    pub fn set_mqtt_device(&mut self, device: &str) -> Result<(), EspError> {
+        self.nvs.set_str(KEY_MQTT_DEVICE, device)
+    }
+
+    /// HTTP接口的访问令牌，为空时不启动HTTP服务
+    pub fn http_token(&self) -> String {
+        self.get_string(KEY_HTTP_TOKEN, MAX_TOKEN_LEN)
+    }
+
+    pub fn set_http_token(&mut self, token: &str) -> Result<(), EspError> {
+        self.nvs.set_str(KEY_HTTP_TOKEN, token)
+    }
+
+    /// HTTP请求体的上限（字节）
+    pub fn http_max_body(&self) -> u32 {
+        self.get_u32(KEY_HTTP_MAX_BODY, DEFAULT_MAX_BODY)
+    }
+
+    pub fn set_http_max_body(&self, bytes: u32) -> Result<(), EspError> {
+        self.nvs.set_u32(KEY_HTTP_MAX_BODY, bytes)
+    }
+
+    /// 读取失败或未保存过时返回false
+    fn get_bool(&self, key: &str) -> bool {
+        match self.nvs.get_u8(key) {
+            Ok(value) => value.is_some_and(|value| value != 0),
+            Err(e) => {
+                log::warn!("读取设置{}失败: {:?}", key, e);
+                false
+            }
+        }
+    }
+
+    /// 读取失败或未保存过时返回空字符串
+    fn get_string(&self, key: &str, max_len: usize) -> String {
+        let mut buf = vec![0; max_len + 1];
+        match self.nvs.get_str(key, &mut buf) {
+            Ok(text) => text.unwrap_or_default().to_string(),
+            Err(e) => {
+                log::warn!("读取设置{}失败: {:?}", key, e);
+                String::new()
+            }
+        }
+    }
+
+    /// 读取失败或未保存过时返回默认值
+    fn get_u32(&self, key: &str, default: u32) -> u32 {
+        match self.nvs.get_u32(key) {
+            Ok(value) => value.unwrap_or(default),
+            Err(e) => {
+                log::warn!("读取设置{}失败: {:?}", key, e);
+                default
+            }
+        }
+    }
+}
+
+fn button_key(event: ButtonEvent) -> &'static str {
+    match event {
+        ButtonEvent::Press => KEY_BTN_PRESS,
+        ButtonEvent::DoublePress => KEY_BTN_DOUBLE,
+        ButtonEvent::LongPress => KEY_BTN_LONG,
+        ButtonEvent::Hold => KEY_BTN_HOLD,
+    }
+}
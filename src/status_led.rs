@@ -0,0 +1,158 @@
+use std::time::{Duration, Instant};
+
+use crate::effect::Priority;
+use crate::error::Error;
+use crate::led::{RgbColor, Ws2812Led};
+
+/// 其他地方设置的LED颜色保持的时间，之后恢复状态指示
+pub const OVERRIDE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 设备的持续状态，由主循环每次迭代给出
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceState {
+    /// 没有客户端连接，正在广播
+    Advertising,
+    /// 已连接，空闲
+    Connected,
+    /// 等待录制的红外信号
+    Recording,
+    /// 正在重放录制
+    Transmitting,
+    /// 正在导入归档或Flipper文件
+    Transferring,
+    /// 省电模式，LED熄灭
+    Sleeping,
+}
+
+/// 一次性的提示，播放完后回到当前状态的样式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notice {
+    /// 录制成功
+    Captured,
+    /// 录制或发射失败
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Indication {
+    State(DeviceState),
+    Notice(Notice),
+}
+
+/// LED样式
+#[derive(Debug, Clone, Copy)]
+enum Pattern {
+    Solid(RgbColor),
+    /// 颜色和呼吸周期（毫秒），一直运行
+    Breathing(RgbColor, u32),
+    /// 颜色、亮和灭的时长（毫秒）和次数，次数为None时一直闪烁
+    Blink(RgbColor, u32, u32, Option<u32>),
+}
+
+/// 每种状态和提示对应的样式，调整样式只需要修改这里
+const PATTERNS: [(Indication, Pattern); 8] = [
+    (Indication::State(DeviceState::Advertising), Pattern::Breathing(RgbColor::blue(), 4000)),
+    (Indication::State(DeviceState::Connected), Pattern::Solid(RgbColor::new(0, 48, 0))),
+    (Indication::State(DeviceState::Recording), Pattern::Blink(RgbColor::blue(), 150, 150, None)),
+    (Indication::State(DeviceState::Transmitting), Pattern::Solid(RgbColor::green())),
+    (Indication::State(DeviceState::Transferring), Pattern::Breathing(RgbColor::new(255, 165, 0), 1000)),
+    (Indication::State(DeviceState::Sleeping), Pattern::Solid(RgbColor::black())),
+    (Indication::Notice(Notice::Captured), Pattern::Blink(RgbColor::new(128, 0, 128), 120, 120, Some(2))),
+    (Indication::Notice(Notice::Error), Pattern::Blink(RgbColor::red(), 120, 120, Some(3))),
+];
+
+/// 用LED样式显示设备状态
+///
+/// 其他地方（颜色命令、匹配后的颜色等）设置了LED时暂停状态指示，
+/// 超过`OVERRIDE_TIMEOUT`且没有效果在运行、或者状态改变时恢复。
+pub struct StatusLed {
+    state: DeviceState,
+    /// 等待播放的提示
+    notice: Option<Notice>,
+    playing_notice: bool,
+    /// 其他地方设置的颜色保持到这个时刻
+    override_until: Option<Instant>,
+    /// 状态指示最近一次设置LED后LED的修改次数
+    revision: u32,
+    /// 需要重新显示当前状态的样式
+    dirty: bool,
+}
+
+impl StatusLed {
+    pub fn new(led: &Ws2812Led) -> Self {
+        Self {
+            state: DeviceState::Advertising,
+            notice: None,
+            playing_notice: false,
+            override_until: None,
+            revision: led.revision(),
+            dirty: true,
+        }
+    }
+
+    pub fn set_state(&mut self, state: DeviceState) {
+        if state != self.state {
+            log::info!("LED状态: {:?}", state);
+            self.state = state;
+            self.dirty = true;
+        }
+    }
+
+    /// 播放一次提示，替换还没播放完的提示
+    pub fn notify(&mut self, notice: Notice) {
+        self.notice = Some(notice);
+    }
+
+    /// 主循环每次迭代调用，按需要切换LED样式
+    pub fn update(&mut self, led: &mut Ws2812Led, now: Instant) -> Result<(), Error> {
+        if led.revision() != self.revision {
+            // 其他地方设置了LED，暂停状态指示
+            self.revision = led.revision();
+            self.override_until = Some(now + OVERRIDE_TIMEOUT);
+            self.playing_notice = false;
+            self.dirty = false;
+        }
+
+        if let Some(notice) = self.notice.take() {
+            self.override_until = None;
+            self.playing_notice = true;
+            return self.show(led, Indication::Notice(notice));
+        }
+        if self.playing_notice {
+            // 提示和状态样式同一优先级，暂停的氛围灯不算
+            if led.effect_running_at(Priority::Status) {
+                return Ok(());
+            }
+            self.playing_notice = false;
+            self.dirty = true;
+        }
+        if let Some(until) = self.override_until {
+            // 其他地方启动的效果运行期间同样保持
+            if !self.dirty && (now < until || led.effect_running()) {
+                return Ok(());
+            }
+            self.override_until = None;
+            self.dirty = true;
+        }
+
+        if !self.dirty {
+            return Ok(());
+        }
+        self.dirty = false;
+        self.show(led, Indication::State(self.state))
+    }
+
+    fn show(&mut self, led: &mut Ws2812Led, indication: Indication) -> Result<(), Error> {
+        let pattern = PATTERNS
+            .iter()
+            .find(|(known, _)| *known == indication)
+            .map_or(Pattern::Solid(RgbColor::black()), |(_, pattern)| *pattern);
+        match pattern {
+            Pattern::Solid(color) => led.set_color(color)?,
+            Pattern::Breathing(color, period_ms) => led.breathing(color, None, period_ms),
+            Pattern::Blink(color, on_ms, off_ms, times) => led.blink(color, times, on_ms, off_ms),
+        }
+        self.revision = led.revision();
+        Ok(())
+    }
+}
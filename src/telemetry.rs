@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use esp_idf_svc::log::EspLogger;
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::bluetooth::BluetoothManager;
+use crate::ir::metadata::{format_utc, synced_unix_secs};
+
+/// 缓冲区最多保留的日志行数
+const MAX_LINES: usize = 32;
+
+/// 每行日志的最大字节数，超出的部分截断
+pub const MAX_LINE_LEN: usize = 120;
+
+/// 发送线程检查缓冲区的间隔
+const SEND_INTERVAL: Duration = Duration::from_millis(200);
+
+const SENDER_STACK_SIZE: usize = 4096;
+
+/// 发送日志本身会产生日志，这些模块的日志只输出到串口
+const EXCLUDED_TARGETS: [&str; 2] = [
+    concat!(env!("CARGO_CRATE_NAME"), "::bluetooth"),
+    concat!(env!("CARGO_CRATE_NAME"), "::telemetry"),
+];
+
+static LOGGER: TelemetryLogger = TelemetryLogger {
+    esp: EspLogger::new(),
+    lines: Mutex::new(VecDeque::new()),
+    level: AtomicUsize::new(LevelFilter::Info as usize),
+    dropped: AtomicU32::new(0),
+};
+
+/// 同时输出到串口和蓝牙日志缓冲区的日志器
+///
+/// 写入缓冲区时不等待锁，缓冲区满或正被读取时丢弃这一行并计数。
+struct TelemetryLogger {
+    esp: EspLogger,
+    lines: Mutex<VecDeque<String>>,
+    /// 蓝牙日志的级别，`LevelFilter`的数值
+    level: AtomicUsize,
+    dropped: AtomicU32,
+}
+
+impl TelemetryLogger {
+    fn ble_level(&self) -> LevelFilter {
+        match self.level.load(Ordering::Relaxed) {
+            0 => LevelFilter::Off,
+            1 => LevelFilter::Error,
+            2 => LevelFilter::Warn,
+            3 => LevelFilter::Info,
+            4 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+
+    fn push(&self, record: &Record) {
+        if record.level() > self.ble_level()
+            || EXCLUDED_TARGETS.iter().any(|target| record.target().starts_with(target))
+        {
+            return;
+        }
+
+        let Ok(mut lines) = self.lines.try_lock() else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        if lines.len() >= MAX_LINES {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let mut line = String::new();
+        // 同步过时间后加上UTC时间
+        if let Some(secs) = synced_unix_secs() {
+            let _ = write!(line, "{} ", format_utc(secs));
+        }
+        let _ = write!(line, "{} {}: {}", record.level(), record.target(), record.args());
+        truncate(&mut line, MAX_LINE_LEN);
+        lines.push_back(line);
+    }
+}
+
+impl Log for TelemetryLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.esp.enabled(metadata) || metadata.level() <= self.ble_level()
+    }
+
+    fn log(&self, record: &Record) {
+        self.esp.log(record);
+        self.push(record);
+    }
+
+    fn flush(&self) {
+        self.esp.flush();
+    }
+}
+
+/// 安装日志器，代替`EspLogger::initialize_default`
+pub fn init() {
+    log::set_logger(&LOGGER).unwrap();
+    update_max_level();
+}
+
+/// 修改蓝牙日志的级别，不影响串口输出
+pub fn set_level(level: LevelFilter) {
+    LOGGER.level.store(level as usize, Ordering::Relaxed);
+    update_max_level();
+}
+
+pub fn level() -> LevelFilter {
+    LOGGER.ble_level()
+}
+
+/// 取出下一行日志，之前有丢弃的行时先返回丢弃的数量
+pub fn pop_line() -> Option<String> {
+    let dropped = LOGGER.dropped.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        return Some(format!("[{} lines dropped]", dropped));
+    }
+    LOGGER.lines.lock().ok()?.pop_front()
+}
+
+/// 启动发送线程，有客户端订阅时把缓冲区中的日志逐行发出
+///
+/// 没有订阅时缓冲区保留最近的日志，满了之后丢弃新的行并计数。
+pub fn spawn_sender(bluetooth_manager: BluetoothManager) -> std::io::Result<()> {
+    thread::Builder::new()
+        .name("ble-telemetry".into())
+        .stack_size(SENDER_STACK_SIZE)
+        .spawn(move || loop {
+            thread::sleep(SEND_INTERVAL);
+            if !bluetooth_manager.telemetry_subscribed() {
+                continue;
+            }
+            while let Some(line) = pop_line() {
+                if let Err(e) = bluetooth_manager.send_telemetry(&line) {
+                    // 这里的日志不会再进入缓冲区
+                    log::warn!("发送日志失败: {:?}", e);
+                    break;
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// 串口和蓝牙两者中较详细的级别
+fn update_max_level() {
+    log::set_max_level(LOGGER.esp.get_max_level().max(LOGGER.ble_level()));
+}
+
+/// 在字符边界截断
+fn truncate(line: &mut String, max_len: usize) {
+    if line.len() > max_len {
+        let end = (0..=max_len).rev().find(|&end| line.is_char_boundary(end)).unwrap_or(0);
+        line.truncate(end);
+    }
+}
@@ -0,0 +1,347 @@
+use std::fmt;
+use std::time::Instant;
+
+use esp_idf_svc::bt::ble::gatt::server::ConnectionId;
+
+use crate::bluetooth::BluetoothManager;
+use crate::command::{Opcode, Request, Status};
+use crate::App;
+
+/// 参数的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// 原样传给处理函数；最后一个参数是字符串时包括其后所有的`:`
+    String,
+    /// 十进制或`0x`开头的十六进制，不超过u32
+    Int,
+    /// 偶数个十六进制数字，按字节解析
+    Hex,
+    /// 只能是参数名称本身，例如`record:tv/power:force`中的`force`
+    Flag,
+}
+
+/// 参数表中的一项，可省略的参数只能放在最后
+#[derive(Debug, Clone, Copy)]
+pub struct Param {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool,
+}
+
+pub const fn required(name: &'static str, kind: ArgKind) -> Param {
+    Param { name, kind, optional: false }
+}
+
+pub const fn optional(name: &'static str, kind: ArgKind) -> Param {
+    Param { name, kind, optional: true }
+}
+
+#[derive(Debug)]
+enum Value<'a> {
+    String(&'a str),
+    Int(u32),
+    Hex(Vec<u8>),
+    Flag,
+}
+
+/// 按参数表检查过的参数，按参数表中的位置读取，省略的参数为None
+#[derive(Debug)]
+pub struct Args<'a> {
+    values: Vec<Option<Value<'a>>>,
+}
+
+impl<'a> Args<'a> {
+    pub fn string(&self, index: usize) -> Option<&'a str> {
+        match self.values.get(index)? {
+            Some(Value::String(text)) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn int(&self, index: usize) -> Option<u32> {
+        match self.values.get(index)? {
+            Some(Value::Int(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn hex(&self, index: usize) -> Option<&[u8]> {
+        match self.values.get(index)? {
+            Some(Value::Hex(bytes)) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn flag(&self, index: usize) -> bool {
+        matches!(self.values.get(index), Some(Some(Value::Flag)))
+    }
+}
+
+/// 处理函数检查参数的取值范围等，不符合时返回参数名称，由注册表回复用法
+#[derive(Debug, Clone, Copy)]
+pub struct InvalidArgument(pub &'static str);
+
+/// 回复给客户端的命令错误，都以`ERROR: `开头，参数错误附带用法
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    /// 既不是命令也不是颜色
+    Unknown(String),
+    /// 只能通过蓝牙使用的命令
+    BleOnly(String),
+    MissingArgument { param: &'static str, usage: String },
+    InvalidArgument { param: &'static str, usage: String },
+    TooManyArguments { usage: String },
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown(command) => write!(f, "ERROR: unknown command '{}'", command),
+            Self::BleOnly(name) => write!(f, "ERROR: {} is only available over BLE", name),
+            Self::MissingArgument { param, usage } => write!(f, "ERROR: missing {}, usage {}", param, usage),
+            Self::InvalidArgument { param, usage } => write!(f, "ERROR: invalid {}, usage {}", param, usage),
+            Self::TooManyArguments { usage } => write!(f, "ERROR: too many arguments, usage {}", usage),
+        }
+    }
+}
+
+/// 处理函数可以使用的主循环状态，每条命令创建一次
+pub struct CommandContext<'a> {
+    pub app: &'a mut App,
+    /// 回复只发给发出命令的客户端或控制台
+    pub bluetooth_manager: &'a BluetoothManager,
+    /// 发出命令的蓝牙连接，来自串口控制台时为None
+    pub conn_id: Option<ConnectionId>,
+    pub now: Instant,
+}
+
+impl CommandContext<'_> {
+    pub fn reply(&self, message: &str) {
+        if let Err(e) = self.bluetooth_manager.send_data(message.as_bytes()) {
+            log::error!("发送回复失败: {:?}", e);
+        }
+    }
+}
+
+type Handler = Box<dyn Fn(&mut CommandContext<'_>, &Args<'_>) -> Result<(), InvalidArgument>>;
+
+/// 结构化命令的处理函数，返回响应帧的状态码和内容
+type FrameHandler = Box<dyn Fn(&mut CommandContext<'_>, Request) -> (Status, Vec<u8>)>;
+
+struct Command {
+    name: &'static str,
+    params: Vec<Param>,
+    usage: String,
+    /// 传输二进制数据或针对蓝牙连接的命令，串口控制台不可用
+    ble_only: bool,
+    handler: Handler,
+}
+
+/// 命令的注册表，蓝牙文本命令、串口控制台和结构化命令帧都从这里分发
+///
+/// 注册的命令先按参数表检查参数再调用处理函数；不是命令名称的整行交给`fallback`。
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: Vec<Command>,
+    fallback: Option<Command>,
+    frames: Vec<(Opcode, FrameHandler)>,
+}
+
+impl CommandRegistry {
+    /// 注册一条命令，用法由参数表生成，例如`record[:<name>[:force]]`
+    pub fn register(
+        &mut self,
+        name: &'static str,
+        params: &[Param],
+        handler: impl Fn(&mut CommandContext<'_>, &Args<'_>) -> Result<(), InvalidArgument> + 'static,
+    ) {
+        self.commands.push(Command::new(name, params, false, Box::new(handler)));
+    }
+
+    /// 注册一条只能通过蓝牙使用的命令，控制台发送时回复错误，`help`中也不列出
+    pub fn register_ble_only(
+        &mut self,
+        name: &'static str,
+        params: &[Param],
+        handler: impl Fn(&mut CommandContext<'_>, &Args<'_>) -> Result<(), InvalidArgument> + 'static,
+    ) {
+        self.commands.push(Command::new(name, params, true, Box::new(handler)));
+    }
+
+    /// 注册不是命令名称时的处理，整行作为唯一的参数，例如按颜色设置LED
+    pub fn register_fallback(
+        &mut self,
+        param: Param,
+        handler: impl Fn(&mut CommandContext<'_>, &Args<'_>) -> Result<(), InvalidArgument> + 'static,
+    ) {
+        let mut fallback = Command::new("", &[param], false, Box::new(handler));
+        fallback.usage = format!("<{}>", param.name);
+        self.fallback = Some(fallback);
+    }
+
+    /// 注册一个结构化命令的操作码，通常与对应的文本命令共用实现
+    pub fn register_frame(
+        &mut self,
+        opcode: Opcode,
+        handler: impl Fn(&mut CommandContext<'_>, Request) -> (Status, Vec<u8>) + 'static,
+    ) {
+        self.frames.push((opcode, Box::new(handler)));
+    }
+
+    /// 处理一条`<命令>[:<参数>]`，参数错误和控制台中只能通过蓝牙使用的命令都在这里回复错误
+    pub fn dispatch(&self, context: &mut CommandContext<'_>, line: &str) {
+        let (name, args) = line.split_once(':').unwrap_or((line, ""));
+        if name == "help" {
+            let text = self.help(context.conn_id.is_some());
+            if let Err(e) = context.bluetooth_manager.send_chunked(text.as_bytes()) {
+                log::error!("发送命令列表失败: {:?}", e);
+            }
+            return;
+        }
+        let (command, args) = match self.commands.iter().find(|command| command.name == name) {
+            Some(command) => (command, args),
+            None => match &self.fallback {
+                Some(fallback) => (fallback, line),
+                None => {
+                    log::info!("未知的命令: {}", line);
+                    context.reply(&CommandError::Unknown(line.to_string()).to_string());
+                    return;
+                }
+            },
+        };
+        if command.ble_only && context.conn_id.is_none() {
+            context.reply(&CommandError::BleOnly(name.to_string()).to_string());
+            return;
+        }
+
+        let result = parse_args(&command.params, args)
+            .and_then(|args| (command.handler)(context, &args).map_err(|e| ParseError::Invalid(e.0)));
+        if let Err(error) = result {
+            let usage = command.usage.clone();
+            let error = match error {
+                ParseError::Missing(param) => CommandError::MissingArgument { param, usage },
+                ParseError::Invalid(param) => CommandError::InvalidArgument { param, usage },
+                ParseError::TooMany => CommandError::TooManyArguments { usage },
+            };
+            log::info!("命令{}的参数无效: {}", name, args);
+            context.reply(&error.to_string());
+        }
+    }
+
+    /// 处理一个结构化命令帧，没有注册的操作码回复UnknownOpcode
+    pub fn dispatch_frame(&self, context: &mut CommandContext<'_>, request: Request) -> (Status, Vec<u8>) {
+        let opcode = request.opcode();
+        match self.frames.iter().find(|(registered, _)| *registered == opcode) {
+            Some((_, handler)) => handler(context, request),
+            None => (Status::UnknownOpcode, Vec::new()),
+        }
+    }
+
+    /// `help`的回复：每行一条命令的用法，以空行结束；`ble`为false时省略只能通过蓝牙使用的命令
+    pub fn help(&self, ble: bool) -> String {
+        let mut text = String::from("help\n");
+        let commands = self.commands.iter().filter(|command| ble || !command.ble_only);
+        for command in commands.chain(&self.fallback) {
+            text.push_str(&command.usage);
+            text.push('\n');
+        }
+        text.push('\n');
+        text
+    }
+}
+
+impl Command {
+    fn new(name: &'static str, params: &[Param], ble_only: bool, handler: Handler) -> Self {
+        let mut usage = name.to_string();
+        let mut open = 0;
+        for param in params {
+            if param.optional {
+                usage.push('[');
+                open += 1;
+            }
+            match param.kind {
+                ArgKind::Flag => usage.push_str(&format!(":{}", param.name)),
+                _ => usage.push_str(&format!(":<{}>", param.name)),
+            }
+        }
+        usage.push_str(&"]".repeat(open));
+        Self {
+            name,
+            params: params.to_vec(),
+            usage,
+            ble_only,
+            handler,
+        }
+    }
+}
+
+enum ParseError {
+    Missing(&'static str),
+    Invalid(&'static str),
+    TooMany,
+}
+
+impl From<&'static str> for ParseError {
+    fn from(param: &'static str) -> Self {
+        Self::Invalid(param)
+    }
+}
+
+/// 按`:`拆分参数；空的参数和省略的参数相同
+fn parse_args<'a>(params: &[Param], args: &'a str) -> Result<Args<'a>, ParseError> {
+    let mut rest = (!args.is_empty()).then_some(args);
+    let mut values = Vec::with_capacity(params.len());
+    for (index, param) in params.iter().enumerate() {
+        let text = match rest {
+            Some(text) if index + 1 == params.len() && param.kind == ArgKind::String => {
+                rest = None;
+                text
+            }
+            Some(text) => {
+                let (head, tail) = match text.split_once(':') {
+                    Some((head, tail)) => (head, Some(tail)),
+                    None => (text, None),
+                };
+                rest = tail;
+                head
+            }
+            None => "",
+        };
+        if text.is_empty() {
+            if !param.optional {
+                return Err(ParseError::Missing(param.name));
+            }
+            values.push(None);
+            continue;
+        }
+        let value = match param.kind {
+            ArgKind::String => Value::String(text),
+            ArgKind::Int => Value::Int(parse_int(text).ok_or(param.name)?),
+            ArgKind::Hex => Value::Hex(parse_hex(text).ok_or(param.name)?),
+            ArgKind::Flag if text == param.name => Value::Flag,
+            ArgKind::Flag => return Err(ParseError::Invalid(param.name)),
+        };
+        values.push(Some(value));
+    }
+    match rest {
+        Some(_) => Err(ParseError::TooMany),
+        None => Ok(Args { values }),
+    }
+}
+
+fn parse_int(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn parse_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 || !text.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&text[index..index + 2], 16).ok())
+        .collect()
+}
@@ -0,0 +1,43 @@
+use std::marker::PhantomData;
+use std::ptr;
+use std::time::Duration;
+
+use esp_idf_svc::sys::{self, EspError};
+
+/// 订阅的任务超过该时长没有喂狗时触发panic并重启，比最长的指示确认超时（30秒）更长
+pub const TIMEOUT: Duration = Duration::from_secs(40);
+
+/// 设置任务看门狗的超时时间，超时后panic重启而不只是打印日志；只监视订阅的任务
+pub fn configure(timeout: Duration) -> Result<(), EspError> {
+    let config = sys::esp_task_wdt_config_t {
+        timeout_ms: timeout.as_millis() as u32,
+        idle_core_mask: 0,
+        trigger_panic: true,
+    };
+    // sdkconfig关闭了启动时初始化看门狗时改为初始化
+    EspError::convert(unsafe { sys::esp_task_wdt_reconfigure(&config) })
+        .or_else(|_| EspError::convert(unsafe { sys::esp_task_wdt_init(&config) }))
+}
+
+/// 当前任务对看门狗的订阅，只能在订阅的任务中喂狗，释放时取消订阅
+pub struct Subscription {
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Subscription {
+    /// 订阅当前任务
+    pub fn current() -> Result<Self, EspError> {
+        EspError::convert(unsafe { sys::esp_task_wdt_add(ptr::null_mut()) })?;
+        Ok(Self { _not_send: PhantomData })
+    }
+
+    pub fn feed(&self) {
+        unsafe { sys::esp_task_wdt_reset() };
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        unsafe { sys::esp_task_wdt_delete(ptr::null_mut()) };
+    }
+}
@@ -0,0 +1,142 @@
+use std::net::Ipv4Addr;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::hal::modem::WifiModem;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sntp::{EspSntp, SyncStatus};
+use esp_idf_svc::sys::{EspError, ESP_ERR_INVALID_ARG, ESP_FAIL};
+use esp_idf_svc::wifi::{AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi};
+
+use crate::ir::metadata::{format_utc, synced_unix_secs};
+
+/// SSID最长32字节
+pub const MAX_SSID_LEN: usize = 32;
+/// WPA2密码8-64字节，空密码表示开放网络
+pub const PASSWORD_LEN_RANGE: RangeInclusive<usize> = 8..=64;
+
+/// 连接失败后重试的间隔，每次失败加倍
+const RETRY_MIN: Duration = Duration::from_secs(5);
+const RETRY_MAX: Duration = Duration::from_secs(120);
+
+/// 已连接时检查连接和时间同步状态的间隔
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+const WIFI_STACK_SIZE: usize = 6144;
+
+/// Wi-Fi连接状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WifiState {
+    Connecting,
+    Connected(Ipv4Addr),
+}
+
+/// 在后台线程中以STA模式连接Wi-Fi并保持连接，连上后通过SNTP同步时间
+///
+/// 只有保存了SSID时才创建，没有配置Wi-Fi的设备不初始化Wi-Fi驱动。
+pub struct WifiLink {
+    ssid: String,
+    state: Arc<Mutex<WifiState>>,
+}
+
+impl WifiLink {
+    pub fn start(
+        modem: WifiModem,
+        sys_loop: EspSystemEventLoop,
+        nvs: Option<EspDefaultNvsPartition>,
+        ssid: &str,
+        password: &str,
+    ) -> Result<Self, EspError> {
+        let configuration = Configuration::Client(ClientConfiguration {
+            ssid: ssid.try_into().map_err(|_| EspError::from_infallible::<ESP_ERR_INVALID_ARG>())?,
+            password: password.try_into().map_err(|_| EspError::from_infallible::<ESP_ERR_INVALID_ARG>())?,
+            auth_method: if password.is_empty() { AuthMethod::None } else { AuthMethod::WPA2Personal },
+            ..Default::default()
+        });
+        let mut wifi = BlockingWifi::wrap(EspWifi::new(modem, sys_loop.clone(), nvs)?, sys_loop)?;
+        wifi.set_configuration(&configuration)?;
+        wifi.start()?;
+
+        let state = Arc::new(Mutex::new(WifiState::Connecting));
+        let thread_state = state.clone();
+        let thread_ssid = ssid.to_string();
+        thread::Builder::new()
+            .name("wifi".into())
+            .stack_size(WIFI_STACK_SIZE)
+            .spawn(move || run(wifi, &thread_ssid, &thread_state))
+            .map_err(|_| EspError::from_infallible::<ESP_FAIL>())?;
+
+        Ok(Self {
+            ssid: ssid.to_string(),
+            state,
+        })
+    }
+
+    pub fn ssid(&self) -> &str {
+        &self.ssid
+    }
+
+    pub fn state(&self) -> WifiState {
+        *self.state.lock().unwrap()
+    }
+}
+
+/// 断开后按退避间隔重连，第一次连上后启动SNTP，之后由SNTP定期校时
+fn run(mut wifi: BlockingWifi<EspWifi<'static>>, ssid: &str, state: &Mutex<WifiState>) {
+    let mut retry = RETRY_MIN;
+    let mut sntp = None;
+    let mut synced = false;
+    loop {
+        if !wifi.is_connected().unwrap_or(false) {
+            *state.lock().unwrap() = WifiState::Connecting;
+            match connect(&mut wifi) {
+                Ok(ip) => {
+                    log::info!("Wi-Fi已连接: {}, IP: {}", ssid, ip);
+                    *state.lock().unwrap() = WifiState::Connected(ip);
+                    retry = RETRY_MIN;
+                }
+                Err(e) => {
+                    log::warn!("连接Wi-Fi {}失败: {:?}，{}秒后重试", ssid, e, retry.as_secs());
+                    let _ = wifi.disconnect();
+                    thread::sleep(retry);
+                    retry = (retry * 2).min(RETRY_MAX);
+                    continue;
+                }
+            }
+        }
+
+        if sntp.is_none() {
+            sntp = match EspSntp::new_default() {
+                Ok(sntp) => Some(sntp),
+                Err(e) => {
+                    log::error!("启动SNTP失败: {:?}", e);
+                    None
+                }
+            };
+        }
+        if !synced && sntp.as_ref().is_some_and(|sntp| sntp.get_sync_status() == SyncStatus::Completed) {
+            if let Some(secs) = synced_unix_secs() {
+                synced = true;
+                log::info!("时间已同步: {}", format_utc(secs));
+            }
+        }
+        thread::sleep(CHECK_INTERVAL);
+    }
+}
+
+fn connect(wifi: &mut BlockingWifi<EspWifi<'static>>) -> Result<Ipv4Addr, EspError> {
+    wifi.connect()?;
+    wifi.wait_netif_up()?;
+    Ok(wifi.wifi().sta_netif().get_ip_info()?.ip)
+}
+
+pub fn check_ssid(ssid: &str) -> bool {
+    ssid.len() <= MAX_SSID_LEN && !ssid.contains(char::is_control)
+}
+
+pub fn check_password(password: &str) -> bool {
+    password.is_empty() || PASSWORD_LEN_RANGE.contains(&password.len())
+}
@@ -0,0 +1,79 @@
+use std::sync::Mutex;
+
+use esp_idf_svc::bt::BdAddr;
+
+use crate::bluetooth::WriteTarget;
+
+/// 解锁命令的前缀，锁定时也允许写入
+pub const UNLOCK_PREFIX: &[u8] = b"unlock:";
+
+/// 设备PIN的位数范围
+pub const PIN_LEN: std::ops::RangeInclusive<usize> = 4..=8;
+
+/// 一次写入的来源和内容，供写入策略判断
+pub struct WriteRequest<'a> {
+    pub peer: BdAddr,
+    /// 客户端已经与设备绑定
+    pub bonded: bool,
+    pub target: WriteTarget,
+    /// 长写入时只是其中一个片段
+    pub is_prep: bool,
+    pub value: &'a [u8],
+}
+
+/// 处理写入之前检查是否允许，拒绝的写入回复Write Not Permitted
+pub trait WritePolicy: Send + Sync {
+    fn allows(&self, request: &WriteRequest) -> bool;
+
+    /// 客户端通过了解锁命令的PIN检查
+    fn authorize(&self, _peer: BdAddr) {}
+
+    /// 客户端断开，授权随连接一起失效
+    fn disconnected(&self, _peer: BdAddr) {}
+}
+
+/// 默认策略，允许所有写入
+pub struct AllowAll;
+
+impl WritePolicy for AllowAll {
+    fn allows(&self, _request: &WriteRequest) -> bool {
+        true
+    }
+}
+
+/// 锁定策略：只接受已绑定的客户端，或者在本次连接中用PIN解锁过的客户端
+///
+/// 订阅和完整写入的解锁命令总是允许，否则客户端无法解锁，也收不到解锁的结果。
+#[derive(Default)]
+pub struct Locked {
+    unlocked: Mutex<Vec<BdAddr>>,
+}
+
+impl WritePolicy for Locked {
+    fn allows(&self, request: &WriteRequest) -> bool {
+        match request.target {
+            WriteTarget::Recv => {
+                request.bonded
+                    || (!request.is_prep && request.value.starts_with(UNLOCK_PREFIX))
+                    || self.unlocked.lock().unwrap().contains(&request.peer)
+            }
+            _ => true,
+        }
+    }
+
+    fn authorize(&self, peer: BdAddr) {
+        let mut unlocked = self.unlocked.lock().unwrap();
+        if !unlocked.contains(&peer) {
+            unlocked.push(peer);
+        }
+    }
+
+    fn disconnected(&self, peer: BdAddr) {
+        self.unlocked.lock().unwrap().retain(|unlocked| *unlocked != peer);
+    }
+}
+
+/// PIN为4到8位数字
+pub fn check_pin(pin: &str) -> bool {
+    PIN_LEN.contains(&pin.len()) && pin.bytes().all(|byte| byte.is_ascii_digit())
+}